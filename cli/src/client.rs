@@ -0,0 +1,174 @@
+use mu_protocol::api::{PlayRequest, QueueRequest, Stats};
+use mu_protocol::lite::LiteResults;
+use reqwest::blocking::{self, Response};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, TcpStream, ToSocketAddrs};
+use std::path::PathBuf;
+use std::time::Duration;
+
+type Result<T> = std::result::Result<T, Box<dyn std::error::Error>>;
+
+/// Blocking client of the HTTP API of the daemon
+pub struct Client {
+    base: String,
+    http: blocking::Client,
+}
+
+/// `host` as written in a url, with brackets around IPv6 addresses. The daemon listening on
+/// every interface for an unspecified address, it's reached on the loopback one.
+fn url_host(host: &str) -> String {
+    let bare = host
+        .strip_prefix('[')
+        .and_then(|x| x.strip_suffix(']'))
+        .unwrap_or(host);
+    match bare.parse::<IpAddr>() {
+        Ok(IpAddr::V4(ip)) if ip.is_unspecified() => Ipv4Addr::LOCALHOST.to_string(),
+        Ok(IpAddr::V6(ip)) if ip.is_unspecified() => format!("[{}]", Ipv6Addr::LOCALHOST),
+        Ok(IpAddr::V6(ip)) => format!("[{ip}]"),
+        _ => bare.to_string(),
+    }
+}
+
+/// Whether something answers on `authority`, e.g. `[::1]:7700`
+fn reachable(authority: &str) -> bool {
+    authority
+        .to_socket_addrs()
+        .into_iter()
+        .flatten()
+        .any(|x| TcpStream::connect_timeout(&x, Duration::from_millis(500)).is_ok())
+}
+
+/// Url of the daemon from its configuration, without creating it if missing, with the Unix
+/// socket to reach it through when it has one. Otherwise the first of `network.host` and the
+/// addresses of `network.listen` answering is used.
+fn configured() -> (String, Option<PathBuf>) {
+    let path = dirs::config_dir()
+        .unwrap_or_default()
+        .join("lorchestre")
+        .join("config.toml");
+    let network = lorconf::Config::parse(&path)
+        .ok()
+        .and_then(|x| x.network)
+        .unwrap_or_default();
+    let defaults = lorconf::Network::default();
+    let base_path = network
+        .base_path
+        .as_deref()
+        .map(|x| x.trim().trim_matches('/'))
+        .filter(|x| !x.is_empty())
+        .map(|x| format!("/{x}"))
+        .unwrap_or_default();
+
+    // The socket is served without TLS, the host of the url is left unresolved
+    let unix_socket = network.unix_socket.filter(|x| cfg!(unix) && x.exists());
+    if let Some(socket) = unix_socket {
+        return (format!("http://localhost{base_path}"), Some(socket));
+    }
+
+    let tls = network
+        .tls
+        .is_some_and(|tls| tls.cert.is_some() && tls.key.is_some());
+    let mut authorities = vec![format!(
+        "{}:{}",
+        url_host(&network.host.or(defaults.host).unwrap_or_default()),
+        network.port.or(defaults.port).unwrap_or_default()
+    )];
+    for entry in network.listen.unwrap_or_default() {
+        if let Some((host, port)) = entry.rsplit_once(':') {
+            authorities.push(format!("{}:{port}", url_host(host)));
+        }
+    }
+    let authority = match authorities.iter().find(|x| reachable(x)) {
+        Some(authority) => authority,
+        None => &authorities[0],
+    };
+
+    (
+        format!(
+            "{}://{authority}{base_path}",
+            if tls { "https" } else { "http" }
+        ),
+        None,
+    )
+}
+
+/// Turns the error statuses into errors carrying the message of the daemon
+fn check(response: Response) -> Result<Response> {
+    let status = response.status();
+    if status.is_success() {
+        return Ok(response);
+    }
+    let message = response.text().unwrap_or_default();
+    if message.is_empty() {
+        Err(status.to_string().into())
+    } else {
+        Err(format!("{status}: {message}").into())
+    }
+}
+
+impl Client {
+    /// `address` is either `host:port` or a url, e.g. `https://host:port`
+    pub fn new(address: Option<String>) -> Result<Self> {
+        let (base, unix_socket) = match address {
+            Some(address) if address.contains("://") => (address, None),
+            Some(address) => (format!("http://{address}"), None),
+            None => configured(),
+        };
+        // Scans can take a while on large libraries
+        let mut http = blocking::Client::builder().timeout(None);
+        #[cfg(unix)]
+        if let Some(socket) = unix_socket {
+            http = http.unix_socket(socket);
+        }
+        #[cfg(not(unix))]
+        let _ = unix_socket;
+        Ok(Self {
+            base: base.trim_end_matches('/').to_string(),
+            http: http.build()?,
+        })
+    }
+
+    fn url(&self, path: &str) -> String {
+        format!("{}{path}", self.base)
+    }
+
+    pub fn search(&self, query: &str) -> Result<LiteResults> {
+        let response = self
+            .http
+            .get(self.url("/search"))
+            .query(&[("q", query), ("lite", "true")])
+            .send()?;
+        Ok(check(response)?.json()?)
+    }
+
+    pub fn play(&self, id: &str) -> Result<()> {
+        let request = PlayRequest { id: id.to_string() };
+        check(
+            self.http
+                .post(self.url("/player/play"))
+                .json(&request)
+                .send()?,
+        )?;
+        Ok(())
+    }
+
+    pub fn queue(&self, ids: Vec<String>, next: bool) -> Result<()> {
+        let request = QueueRequest { ids, next };
+        check(
+            self.http
+                .post(self.url("/player/queue"))
+                .json(&request)
+                .send()?,
+        )?;
+        Ok(())
+    }
+
+    /// Returns once the scan is over
+    pub fn scan(&self) -> Result<()> {
+        check(self.http.put(self.url("/updatemusic")).send()?)?;
+        Ok(())
+    }
+
+    pub fn stats(&self) -> Result<Stats> {
+        Ok(check(self.http.get(self.url("/stats")).send()?)?.json()?)
+    }
+}
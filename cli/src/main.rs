@@ -0,0 +1,137 @@
+mod client;
+mod output;
+
+use clap::{Parser, Subcommand};
+use client::Client;
+use mu_protocol::lite::LiteResults;
+
+#[derive(Debug, Parser)]
+#[clap(author, version, about)]
+struct MuArgs {
+    /// Address (`host:port`) or url of the daemon, read from the configuration by default
+    #[clap(long, global = true)]
+    daemon: Option<String>,
+    /// Print JSON instead of tables
+    #[clap(long, global = true)]
+    json: bool,
+    #[clap(subcommand)]
+    command: Command,
+}
+
+#[derive(Debug, Subcommand)]
+enum Command {
+    /// Search albums, playlists and tracks
+    Search { query: String },
+    /// Play a track in the running players
+    Play {
+        /// Id of the track
+        id: String,
+    },
+    /// Edit the queue of the running players
+    Queue {
+        #[clap(subcommand)]
+        action: QueueAction,
+    },
+    /// Rescan the library and wait for the end of the scan
+    Scan,
+    /// Show statistics about the library
+    Stats,
+}
+
+#[derive(Debug, Subcommand)]
+enum QueueAction {
+    /// Add tracks to the queue
+    Add {
+        /// Ids of the tracks
+        #[clap(required = true)]
+        ids: Vec<String>,
+        /// Play them next instead of after the rest of the queue
+        #[clap(long)]
+        next: bool,
+    },
+}
+
+fn print_results(results: &LiteResults) {
+    let mut rows = vec![];
+    for album in &results.albums {
+        rows.push(vec![
+            "album".to_string(),
+            album.id.clone(),
+            album.title.clone(),
+            album.artist.clone(),
+            output::duration(album.duration),
+        ]);
+    }
+    for playlist in &results.playlists {
+        rows.push(vec![
+            "playlist".to_string(),
+            playlist.id.clone(),
+            playlist.title.clone(),
+            String::new(),
+            output::duration(playlist.duration),
+        ]);
+    }
+    for track in &results.tracks {
+        rows.push(vec![
+            "track".to_string(),
+            track.id.clone(),
+            track.title.clone(),
+            track.artist.clone(),
+            output::duration(track.duration),
+        ]);
+    }
+
+    output::table(&["KIND", "ID", "TITLE", "ARTIST", "DURATION"], rows);
+}
+
+fn run(args: MuArgs) -> Result<(), Box<dyn std::error::Error>> {
+    let client = Client::new(args.daemon)?;
+    match args.command {
+        Command::Search { query } => {
+            let results = client.search(&query)?;
+            if args.json {
+                output::json(&results);
+            } else {
+                print_results(&results);
+            }
+        }
+        Command::Play { id } => client.play(&id)?,
+        Command::Queue {
+            action: QueueAction::Add { ids, next },
+        } => client.queue(ids, next)?,
+        Command::Scan => {
+            client.scan()?;
+            if !args.json {
+                println!("Scan finished");
+            }
+        }
+        Command::Stats => {
+            let stats = client.stats()?;
+            if args.json {
+                output::json(&stats);
+            } else {
+                output::table(
+                    &["", ""],
+                    vec![
+                        vec!["Tracks".to_string(), stats.tracks.to_string()],
+                        vec!["Albums".to_string(), stats.albums.to_string()],
+                        vec!["Artists".to_string(), stats.artists.to_string()],
+                        vec!["Playlists".to_string(), stats.playlists.to_string()],
+                        vec!["Duration".to_string(), output::duration(stats.duration)],
+                        vec!["Plays".to_string(), stats.plays.to_string()],
+                        vec!["Favorites".to_string(), stats.favorites.to_string()],
+                    ],
+                );
+            }
+        }
+    }
+
+    Ok(())
+}
+
+fn main() {
+    if let Err(e) = run(MuArgs::parse()) {
+        eprintln!("mu: {e}");
+        std::process::exit(1);
+    }
+}
@@ -0,0 +1,48 @@
+/// Prints `rows` in columns aligned on their widest cell. The header line is skipped when
+/// every header is empty.
+pub fn table(headers: &[&str], rows: Vec<Vec<String>>) {
+    let mut widths: Vec<usize> = headers.iter().map(|x| x.chars().count()).collect();
+    for row in &rows {
+        for (width, cell) in widths.iter_mut().zip(row) {
+            *width = (*width).max(cell.chars().count());
+        }
+    }
+
+    let line = |cells: Vec<&str>| {
+        let last = cells.len().saturating_sub(1);
+        let line: Vec<String> = cells
+            .iter()
+            .zip(&widths)
+            .enumerate()
+            .map(|(i, (cell, width))| {
+                if i == last {
+                    cell.to_string()
+                } else {
+                    format!("{cell:<width$}")
+                }
+            })
+            .collect();
+        println!("{}", line.join("  "));
+    };
+
+    if headers.iter().any(|x| !x.is_empty()) {
+        line(headers.to_vec());
+    }
+    for row in &rows {
+        line(row.iter().map(|x| x.as_str()).collect());
+    }
+}
+
+pub fn json<T: serde::Serialize>(value: &T) {
+    println!("{}", serde_json::to_string_pretty(value).unwrap());
+}
+
+/// `m:ss`, or `h:mm:ss` from an hour
+pub fn duration(seconds: u64) -> String {
+    let (h, m, s) = (seconds / 3600, seconds / 60 % 60, seconds % 60);
+    if h > 0 {
+        format!("{h}:{m:02}:{s:02}")
+    } else {
+        format!("{m}:{s:02}")
+    }
+}
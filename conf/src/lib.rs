@@ -20,6 +20,75 @@ macro_rules! update_conf {
 pub struct Network {
     pub port: Option<u32>,
     pub host: Option<String>,
+    pub max_streams_per_ip: Option<u32>,
+    /// Requests a remote client can make per minute, 0 disables the limit. Requests carrying
+    /// the token of an account or the admin token count against it, the others against
+    /// their address.
+    pub requests_per_minute: Option<u32>,
+    /// Largest request body accepted, in bytes
+    pub max_body_size: Option<u64>,
+    /// Token expected by the remote management endpoints, e.g. `POST /shutdown`
+    pub admin_token: Option<String>,
+    pub tls: Option<Tls>,
+    /// Addresses served on top of `host` and `port`, e.g. `[::]:7700` or `192.168.1.2:7700`
+    pub listen: Option<Vec<String>>,
+    /// Unix domain socket served to the local clients
+    pub unix_socket: Option<PathBuf>,
+    /// Path the daemon is reached under behind a reverse proxy, e.g. `/mu`
+    pub base_path: Option<String>,
+    /// Folder of a web client served at `/app`
+    pub web_ui: Option<PathBuf>,
+    pub cors: Option<Cors>,
+}
+
+/// Certificate and private key in PEM, HTTPS is served once both are set
+#[derive(Debug, Clone, Default, serde::Deserialize, serde::Serialize)]
+pub struct Tls {
+    pub cert: Option<PathBuf>,
+    pub key: Option<PathBuf>,
+}
+
+/// Origins of the web pages allowed to call the daemon, besides the web app at `/app` and the
+/// desktop app which always are
+#[derive(Debug, Clone, serde::Deserialize, serde::Serialize)]
+pub struct Cors {
+    /// Origins allowed to read, e.g. `https://music.example.org`, `*` for any
+    pub origins: Option<Vec<String>>,
+    /// Origins also allowed to change things, with `POST`, `PUT`, `PATCH` and `DELETE`
+    pub write_origins: Option<Vec<String>>,
+    pub methods: Option<Vec<String>>,
+    /// Headers the requests may carry besides the ones always allowed, `*` for any
+    pub headers: Option<Vec<String>>,
+    /// Seconds the browsers keep the answer to a preflight request
+    pub max_age: Option<u64>,
+}
+
+impl Default for Cors {
+    fn default() -> Self {
+        Self {
+            origins: Some(vec!["*".to_string()]),
+            write_origins: Some(vec![]),
+            methods: Some(
+                ["GET", "HEAD", "POST", "PUT", "PATCH", "DELETE", "PROPFIND"]
+                    .map(String::from)
+                    .to_vec(),
+            ),
+            headers: Some(
+                [
+                    "authorization",
+                    "content-type",
+                    "range",
+                    "if-none-match",
+                    "if-modified-since",
+                    "x-library",
+                    "depth",
+                ]
+                .map(String::from)
+                .to_vec(),
+            ),
+            max_age: Some(600),
+        }
+    }
 }
 
 impl Default for Network {
@@ -27,6 +96,16 @@ impl Default for Network {
         Self {
             port: Some(7700),
             host: Some("localhost".to_string()),
+            max_streams_per_ip: Some(16),
+            requests_per_minute: Some(1200),
+            max_body_size: Some(8 * 1024 * 1024),
+            admin_token: None,
+            tls: None,
+            listen: None,
+            unix_socket: None,
+            base_path: None,
+            web_ui: None,
+            cors: None,
         }
     }
 }
@@ -49,10 +128,211 @@ impl Default for Global {
     }
 }
 
+#[derive(Debug, Clone, serde::Deserialize, serde::Serialize)]
+pub struct Library {
+    pub allow_downloads: Option<bool>,
+    pub embed_covers: Option<bool>,
+    pub transcode_idle_timeout: Option<u64>,
+    pub artist_separators: Option<Vec<String>>,
+    pub artist_album_sort: Option<String>,
+    pub audit_interval: Option<u64>,
+    pub audit_sample_size: Option<usize>,
+    /// Tracks at least this long, in seconds, are classified as audiobooks, 0 disables it
+    pub audiobook_min_duration: Option<u64>,
+    /// Background jobs run at once
+    pub max_jobs: Option<usize>,
+    /// Where `POST /organize` moves the files, e.g.
+    /// `{albumartist}/{album} ({year})/{track:02} {title}.{ext}`, the organizer is off unset
+    pub organize_pattern: Option<String>,
+    /// Drop folder whose files are tagged, moved into the library and added to it, the import
+    /// is off unset
+    pub import_path: Option<PathBuf>,
+    /// Seconds between two looks at `import_path`
+    pub import_interval: Option<u64>,
+    /// Whether missing tags of the imported files are looked up on MusicBrainz
+    pub import_musicbrainz: Option<bool>,
+    /// Whether artist portraits and missing album covers are fetched from fanart.tv, the Cover
+    /// Art Archive and Deezer
+    pub external_artwork: Option<bool>,
+    /// Personal key of the fanart.tv API, which is skipped without one
+    pub fanart_api_key: Option<String>,
+    /// Seconds between two collections of the covers cache, 0 disables them
+    pub cover_gc_interval: Option<u64>,
+    /// Globs of the files and folders left out of the scan, on top of the `.muignore` files
+    pub exclude: Option<Vec<String>>,
+    /// Whether the scan walks symlinked folders
+    pub follow_symlinks: Option<bool>,
+    /// Whether the tempo and key of the tracks whose tags don't give them are found from their
+    /// audio after each scan, which takes ffmpeg
+    pub analyse_audio: Option<bool>,
+    /// Patterns the titles and track numbers of the tracks without title tag are read from
+    /// their file names with, e.g. `{track} - {title}`, the first one matching is used
+    pub title_patterns: Option<Vec<String>>,
+    /// Leaves the explicit tracks out of the browse, search and radio of the default user, the
+    /// accounts have their own setting
+    pub hide_explicit: Option<bool>,
+    /// Where the lyrics edited with `PUT /track/:id/lyrics` are written: `lrc` for the `.lrc`
+    /// file next to the track, `tag` for the lyrics tag of the file
+    pub lyrics_target: Option<String>,
+    /// Size the segments of the remote files streamed are cached up to, in MiB
+    pub remote_cache_size: Option<u64>,
+}
+
+impl Default for Library {
+    fn default() -> Self {
+        Self {
+            allow_downloads: Some(true),
+            embed_covers: Some(false),
+            transcode_idle_timeout: Some(60),
+            artist_separators: Some(
+                [";", " / ", " feat. ", " ft. "]
+                    .iter()
+                    .map(|x| x.to_string())
+                    .collect(),
+            ),
+            artist_album_sort: Some("year".to_string()),
+            audit_interval: Some(3600),
+            audit_sample_size: Some(100),
+            audiobook_min_duration: Some(1800),
+            max_jobs: Some(2),
+            organize_pattern: None,
+            import_path: None,
+            import_interval: Some(30),
+            import_musicbrainz: Some(false),
+            external_artwork: Some(false),
+            fanart_api_key: None,
+            cover_gc_interval: Some(86400),
+            exclude: Some(vec![]),
+            follow_symlinks: Some(true),
+            analyse_audio: Some(false),
+            title_patterns: Some(
+                [
+                    "{track} - {title}",
+                    "{track}. {title}",
+                    "{track} {title}",
+                    "{title}",
+                ]
+                .iter()
+                .map(|x| x.to_string())
+                .collect(),
+            ),
+            hide_explicit: Some(false),
+            lyrics_target: Some("lrc".to_string()),
+            remote_cache_size: Some(2048),
+        }
+    }
+}
+
+/// Playback settings applied by the players, unless an album or playlist sets its own
+#[derive(Debug, Clone, serde::Deserialize, serde::Serialize)]
+pub struct Player {
+    /// Seconds two tracks overlap, 0 disables it
+    pub crossfade: Option<f32>,
+    pub gapless: Option<bool>,
+    pub normalize: Option<bool>,
+    /// Loudness tracks are normalized to, in LUFS
+    pub loudness_target: Option<f32>,
+    /// Tracks of the queue a radio doesn't queue again, the last ones
+    pub radio_window: Option<usize>,
+    /// Whether the silences found at the ends of the tracks are skipped, by the players and
+    /// the HLS streams
+    pub trim_silence: Option<bool>,
+}
+
+impl Default for Player {
+    fn default() -> Self {
+        Self {
+            crossfade: Some(0.0),
+            gapless: Some(true),
+            normalize: Some(false),
+            loudness_target: Some(-14.0),
+            radio_window: Some(100),
+            trim_silence: Some(false),
+        }
+    }
+}
+
+/// What the scan extracts besides the tags. Turning it down spares the CPU of small devices.
+#[derive(Debug, Clone, serde::Deserialize, serde::Serialize)]
+pub struct Scan {
+    /// Whether the covers and the other pictures of the tracks are extracted by the scan, else on
+    /// their first request
+    pub extract_covers: Option<bool>,
+    /// Whether the palettes of the covers are computed in the background, else on their first
+    /// request
+    pub extract_palette: Option<bool>,
+    /// Time between two scans of the libraries looking for changes, e.g. `6h`, `90m` or `1d`.
+    /// The libraries are only scanned on start and on request when unset.
+    pub interval: Option<String>,
+    /// Times of day the periodic scans wait for the end of, e.g. `23:00-07:00`
+    pub quiet_hours: Option<String>,
+}
+
+impl Default for Scan {
+    fn default() -> Self {
+        Self {
+            extract_covers: Some(true),
+            extract_palette: Some(true),
+            interval: None,
+            quiet_hours: None,
+        }
+    }
+}
+
+/// A named library with its own folders, e.g. `Audiobooks`, scanned and cached apart
+#[derive(Debug, Clone, serde::Deserialize, serde::Serialize)]
+pub struct LibraryProfile {
+    pub name: String,
+    #[serde(default)]
+    pub paths: Vec<PathBuf>,
+    /// Left out of the requests without account token, only the accounts listing it in their
+    /// libraries and the admin token see it
+    #[serde(default)]
+    pub hidden: bool,
+}
+
+/// A storage the audio files of a library are read from besides its folders, e.g. an S3 bucket
+#[derive(Debug, Clone, serde::Deserialize, serde::Serialize)]
+pub struct Remote {
+    /// Name of the remote in the paths of its tracks, `remote://<name>/<key>`
+    pub name: String,
+    /// Library the files are scanned into, the first one when unset
+    pub library: Option<String>,
+    /// Kind of storage, only `s3` for now
+    pub kind: Option<String>,
+    /// URL of the service, e.g. `https://s3.eu-west-3.amazonaws.com` or `http://nas:9000`
+    pub endpoint: String,
+    pub bucket: String,
+    pub region: Option<String>,
+    /// Only the keys starting with it are scanned, e.g. `music/`
+    pub prefix: Option<String>,
+    /// Credentials of the requests, which are anonymous without them
+    pub access_key: Option<String>,
+    pub secret_key: Option<String>,
+}
+
+/// A URL the events of the daemon are posted to, e.g. an automation of Home Assistant
+#[derive(Debug, Clone, serde::Deserialize, serde::Serialize)]
+pub struct Webhook {
+    pub url: String,
+    /// Names of the events posted, e.g. `track:played`, or prefixes ending with `*`, e.g.
+    /// `scan:*`. Every event is posted when unset.
+    pub events: Option<Vec<String>>,
+    /// Key of the HMAC-SHA256 of the bodies, sent in `X-Mu-Signature`
+    pub secret: Option<String>,
+}
+
 #[derive(Debug, Clone, serde::Deserialize, serde::Serialize)]
 pub struct Config {
     pub global: Option<Global>,
     pub network: Option<Network>,
+    pub library: Option<Library>,
+    pub player: Option<Player>,
+    pub scan: Option<Scan>,
+    /// The audio directory of the user is the only library when unset
+    pub libraries: Option<Vec<LibraryProfile>>,
+    pub remotes: Option<Vec<Remote>>,
+    pub webhooks: Option<Vec<Webhook>>,
 }
 
 impl Default for Config {
@@ -60,6 +340,12 @@ impl Default for Config {
         Self {
             global: Some(Global::default()),
             network: Some(Network::default()),
+            library: Some(Library::default()),
+            player: Some(Player::default()),
+            scan: Some(Scan::default()),
+            libraries: None,
+            remotes: None,
+            webhooks: None,
         }
     }
 }
@@ -77,6 +363,12 @@ impl Config {
         let _ = f.write_all(toml::to_string(&new_config).unwrap().as_bytes());
     }
 
+    /// Reads the configuration at `path`, failing instead of falling back to the defaults
+    pub fn parse(path: &PathBuf) -> Result<Config, Box<dyn std::error::Error>> {
+        let buf = std::fs::read_to_string(path)?;
+        Ok(toml::from_str::<Config>(&buf)?)
+    }
+
     pub fn get(path: &PathBuf) -> Config {
         let mut buf = String::new();
         if path.exists() {
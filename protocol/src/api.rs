@@ -0,0 +1,1518 @@
+use crate::library::{Album, Color, LyricLine, Palette, Playlist, Quality, Track};
+#[cfg(feature = "openapi")]
+use crate::lite::LiteTrack;
+use std::path::PathBuf;
+use std::time::SystemTime;
+
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone, Copy, PartialEq, Default)]
+#[cfg_attr(feature = "ts", derive(ts_rs::TS), ts(export))]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+#[serde(rename_all = "lowercase")]
+pub enum SearchScope {
+    /// Names, artists, credits and lyrics of the tracks, albums and playlists
+    #[default]
+    All,
+    /// The tracks whose synced lyrics hold the phrase, with the lines holding it
+    Lyrics,
+}
+
+#[derive(serde::Serialize, serde::Deserialize, Debug)]
+#[cfg_attr(feature = "ts", derive(ts_rs::TS), ts(export))]
+#[cfg_attr(feature = "openapi", derive(utoipa::IntoParams), into_params(parameter_in = Query))]
+pub struct SearchQuery {
+    pub q: String,
+    /// `all` by default
+    pub scope: Option<SearchScope>,
+}
+
+#[derive(serde::Serialize, serde::Deserialize, Debug)]
+#[cfg_attr(feature = "ts", derive(ts_rs::TS), ts(export))]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct SearchResults {
+    pub albums: Vec<Album>,
+    pub playlists: Vec<Playlist>,
+    pub tracks: Vec<Track>,
+    /// Lines holding the phrase of a `scope=lyrics` search, empty otherwise
+    #[serde(default)]
+    pub lyrics: Vec<LyricsMatch>,
+}
+
+/// Lines of the lyrics of a track holding the phrase searched, to jump to the moment it is sung
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone)]
+#[cfg_attr(feature = "ts", derive(ts_rs::TS), ts(export))]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct LyricsMatch {
+    /// Id of the track
+    pub track: String,
+    /// Sorted by time, a phrase running over two lines gives the first one
+    pub lines: Vec<LyricLine>,
+}
+
+/// `?size=<width>x<height>&fallback=false` of `GET /cover/:handle`
+#[derive(serde::Serialize, serde::Deserialize, Debug, Default)]
+#[cfg_attr(feature = "ts", derive(ts_rs::TS), ts(export))]
+#[cfg_attr(feature = "openapi", derive(utoipa::IntoParams), into_params(parameter_in = Query))]
+pub struct CoverQuery {
+    /// Box the cover is resized to fit in, e.g. `300x300`
+    pub size: Option<String>,
+    /// Whether the default cover is sent for a missing one, a 404 is otherwise. `true` unless
+    /// set.
+    pub fallback: Option<bool>,
+}
+
+impl CoverQuery {
+    pub fn size(&self) -> Option<(u32, u32)> {
+        let (x, y) = self.size.as_ref()?.split_once('x')?;
+        Some((x.parse().ok()?, y.parse().ok()?))
+    }
+}
+
+#[derive(serde::Serialize, serde::Deserialize, Debug)]
+#[cfg_attr(feature = "ts", derive(ts_rs::TS), ts(export))]
+#[cfg_attr(feature = "openapi", derive(utoipa::IntoParams), into_params(parameter_in = Query))]
+pub struct MusicPath {
+    pub path: String,
+}
+
+/// Body of `PUT /track/:id/position`
+#[derive(serde::Serialize, serde::Deserialize, Debug)]
+#[cfg_attr(feature = "ts", derive(ts_rs::TS), ts(export))]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct NewPosition {
+    /// In milliseconds
+    #[cfg_attr(feature = "ts", ts(type = "number"))]
+    pub position: u64,
+}
+
+#[derive(serde::Serialize, serde::Deserialize, Debug)]
+#[cfg_attr(feature = "ts", derive(ts_rs::TS), ts(export))]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct NewBookmark {
+    pub name: String,
+    #[cfg_attr(feature = "ts", ts(type = "number"))]
+    pub position: u64,
+}
+
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "ts", derive(ts_rs::TS), ts(export))]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+#[serde(rename_all = "lowercase")]
+pub enum LaterKind {
+    Album,
+    Track,
+}
+
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone)]
+#[cfg_attr(feature = "ts", derive(ts_rs::TS), ts(export))]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct LaterEntry {
+    pub kind: LaterKind,
+    pub id: String,
+    #[cfg_attr(feature = "ts", ts(as = "crate::SystemTime"))]
+    #[cfg_attr(feature = "openapi", schema(value_type = SystemTime))]
+    pub added_at: SystemTime,
+    /// Tracks of an album entry already played since it was added
+    #[serde(default)]
+    pub played: Vec<String>,
+}
+
+#[derive(serde::Serialize, serde::Deserialize, Debug)]
+#[cfg_attr(feature = "ts", derive(ts_rs::TS), ts(export))]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct NewLaterEntry {
+    pub kind: LaterKind,
+    pub id: String,
+}
+
+#[derive(serde::Serialize, serde::Deserialize, Debug, Default)]
+#[cfg_attr(feature = "ts", derive(ts_rs::TS), ts(export))]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct Recent {
+    pub albums: Vec<Album>,
+    pub tracks: Vec<Track>,
+}
+
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "ts", derive(ts_rs::TS), ts(export))]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+#[serde(rename_all = "lowercase")]
+pub enum RecentKind {
+    Added,
+    Played,
+}
+
+#[derive(serde::Serialize, serde::Deserialize, Debug)]
+#[cfg_attr(feature = "ts", derive(ts_rs::TS), ts(export))]
+#[cfg_attr(feature = "openapi", derive(utoipa::IntoParams), into_params(parameter_in = Query))]
+pub struct RecentQuery {
+    pub kind: RecentKind,
+    pub limit: Option<usize>,
+}
+
+#[derive(serde::Serialize, serde::Deserialize, Debug)]
+#[cfg_attr(feature = "ts", derive(ts_rs::TS), ts(export))]
+#[cfg_attr(feature = "openapi", derive(utoipa::IntoParams), into_params(parameter_in = Query))]
+pub struct ChangesQuery {
+    /// Generation the client is at, from the last `GET /media/changes` or socket event
+    #[cfg_attr(feature = "ts", ts(type = "number"))]
+    pub since: u64,
+}
+
+#[derive(serde::Serialize, serde::Deserialize, Debug, Default)]
+#[cfg_attr(feature = "ts", derive(ts_rs::TS), ts(export))]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct ChangedIds {
+    pub tracks: Vec<String>,
+    pub albums: Vec<String>,
+    pub playlists: Vec<String>,
+}
+
+/// Body of `GET /media/changes`
+#[derive(serde::Serialize, serde::Deserialize, Debug, Default)]
+#[cfg_attr(feature = "ts", derive(ts_rs::TS), ts(export))]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct MediaChanges {
+    /// Generation the changes go up to, the `since` of the next request
+    #[cfg_attr(feature = "ts", ts(type = "number"))]
+    pub generation: u64,
+    /// The changes since `since` are no longer known, e.g. the daemon restarted: `GET /media`
+    /// again
+    pub reset: bool,
+    pub added: ChangedIds,
+    pub updated: ChangedIds,
+    pub removed: ChangedIds,
+    /// The added and updated tracks
+    pub tracks: Vec<Track>,
+    /// The added and updated albums
+    pub albums: Vec<Album>,
+    /// The added and updated playlists
+    pub playlists: Vec<Playlist>,
+}
+
+#[derive(serde::Serialize, serde::Deserialize, Debug)]
+#[cfg_attr(feature = "ts", derive(ts_rs::TS), ts(export))]
+#[cfg_attr(feature = "openapi", derive(utoipa::IntoParams), into_params(parameter_in = Query))]
+pub struct RandomTracksQuery {
+    pub limit: Option<usize>,
+    pub offset: Option<usize>,
+    pub genre: Option<String>,
+    pub min_year: Option<u32>,
+    /// Same seed, same order: lets clients page through one shuffle
+    #[cfg_attr(feature = "ts", ts(type = "number | null"))]
+    pub seed: Option<u64>,
+}
+
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "ts", derive(ts_rs::TS), ts(export))]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+#[serde(rename_all = "lowercase")]
+pub enum TrackSort {
+    /// Alphabetical
+    Title,
+    /// Slowest first, tracks without tempo last
+    Bpm,
+    /// Around the Camelot wheel, from `1A` (`G#m`) to `12B` (`E`), so that neighbouring keys
+    /// mix well. Tracks without key last.
+    Key,
+    /// Oldest first
+    Added,
+}
+
+#[derive(serde::Serialize, serde::Deserialize, Debug)]
+#[cfg_attr(feature = "ts", derive(ts_rs::TS), ts(export))]
+#[cfg_attr(feature = "openapi", derive(utoipa::IntoParams), into_params(parameter_in = Query))]
+pub struct TracksQuery {
+    /// By title by default
+    pub sort: Option<TrackSort>,
+    /// Reverses the order, tracks missing what they are sorted by still last
+    #[serde(default)]
+    pub desc: bool,
+    pub min_bpm: Option<f32>,
+    pub max_bpm: Option<f32>,
+    /// Keeps the tracks in this key, e.g. `Am`, `A minor` or `8A`
+    pub key: Option<String>,
+    pub genre: Option<String>,
+    /// 50 by default
+    pub limit: Option<usize>,
+    pub offset: Option<usize>,
+}
+
+/// A page of the tracks of `GET /tracks`
+#[derive(serde::Serialize, serde::Deserialize, Debug)]
+#[cfg_attr(feature = "ts", derive(ts_rs::TS), ts(export))]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+#[cfg_attr(
+    feature = "openapi",
+    aliases(TrackTrackList = TrackList<Track>, LiteTrackList = TrackList<LiteTrack>)
+)]
+pub struct TrackList<T = Track> {
+    /// Tracks matching the filters, across the pages
+    pub total: usize,
+    pub tracks: Vec<T>,
+}
+
+#[derive(serde::Serialize, serde::Deserialize, Debug, Default)]
+#[cfg_attr(feature = "ts", derive(ts_rs::TS), ts(export))]
+#[cfg_attr(feature = "openapi", derive(utoipa::IntoParams), into_params(parameter_in = Query))]
+pub struct SimilarQuery {
+    /// 25 by default
+    pub limit: Option<usize>,
+}
+
+/// A track like the one asked, see `GET /track/{id}/similar`
+#[derive(serde::Serialize, serde::Deserialize, Debug)]
+#[cfg_attr(feature = "ts", derive(ts_rs::TS), ts(export))]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+#[cfg_attr(
+    feature = "openapi",
+    aliases(
+        TrackSimilarTrack = SimilarTrack<Track>,
+        LiteSimilarTrack = SimilarTrack<LiteTrack>
+    )
+)]
+pub struct SimilarTrack<T = Track> {
+    /// How alike the tracks are, from the genre, artists, credits, year and duration they
+    /// share. Only comparable between the tracks of a response.
+    pub score: f32,
+    pub track: T,
+}
+
+/// Keeps the tracks, and the albums all of whose tracks, are of a quality
+#[derive(serde::Serialize, serde::Deserialize, Debug, Default, Clone, Copy)]
+#[cfg_attr(feature = "ts", derive(ts_rs::TS), ts(export))]
+#[cfg_attr(feature = "openapi", derive(utoipa::IntoParams), into_params(parameter_in = Query))]
+pub struct QualityQuery {
+    pub quality: Option<Quality>,
+    /// Leaves out the explicit tracks and albums, set by the daemon for the users hiding them
+    #[serde(skip)]
+    pub hide_explicit: bool,
+}
+
+impl QualityQuery {
+    pub fn keeps(&self, track: &Track) -> bool {
+        self.quality.is_none_or(|x| x.matches(track))
+            && !(self.hide_explicit && track.is_explicit())
+    }
+
+    pub fn keeps_album(&self, album: &Album) -> bool {
+        self.quality.is_none_or(|x| x.matches_album(album))
+            && !(self.hide_explicit && album.explicit)
+    }
+}
+
+#[derive(serde::Serialize, serde::Deserialize, Debug)]
+#[cfg_attr(feature = "ts", derive(ts_rs::TS), ts(export))]
+#[cfg_attr(feature = "openapi", derive(utoipa::IntoParams), into_params(parameter_in = Query))]
+pub struct RandomAlbumQuery {
+    #[cfg_attr(feature = "ts", ts(type = "number | null"))]
+    pub seed: Option<u64>,
+}
+
+#[derive(serde::Serialize, serde::Deserialize, Debug)]
+#[cfg_attr(feature = "ts", derive(ts_rs::TS), ts(export))]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+#[cfg_attr(
+    feature = "openapi",
+    aliases(
+        TrackRandomTracks = RandomTracks<Track>,
+        LiteRandomTracks = RandomTracks<LiteTrack>
+    )
+)]
+pub struct RandomTracks<T = Track> {
+    #[cfg_attr(feature = "ts", ts(type = "number"))]
+    pub seed: u64,
+    pub total: usize,
+    pub tracks: Vec<T>,
+}
+
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "ts", derive(ts_rs::TS), ts(export))]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+#[serde(rename_all = "lowercase")]
+pub enum AlbumSort {
+    /// Chronological, albums without year last
+    Year,
+    /// Alphabetical
+    Name,
+}
+
+#[derive(serde::Serialize, serde::Deserialize, Debug)]
+#[cfg_attr(feature = "ts", derive(ts_rs::TS), ts(export))]
+#[cfg_attr(feature = "openapi", derive(utoipa::IntoParams), into_params(parameter_in = Query))]
+pub struct ArtistAlbumsQuery {
+    /// Overrides `library.artist_album_sort`
+    pub sort: Option<AlbumSort>,
+}
+
+#[derive(serde::Serialize, serde::Deserialize, Debug)]
+#[cfg_attr(feature = "ts", derive(ts_rs::TS), ts(export))]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct Artist {
+    pub id: String,
+    pub name: String,
+    pub albums: usize,
+}
+
+#[derive(serde::Serialize, serde::Deserialize, Debug)]
+#[cfg_attr(feature = "ts", derive(ts_rs::TS), ts(export))]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct Composer {
+    pub id: String,
+    pub name: String,
+    pub tracks: usize,
+    /// Distinct works of the composer, movements of a same work counting once
+    pub works: usize,
+}
+
+/// Tracks of an album following each other in the same work, body of `GET /album/{id}/works`
+#[derive(serde::Serialize, serde::Deserialize, Debug)]
+#[cfg_attr(feature = "ts", derive(ts_rs::TS), ts(export))]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct AlbumWork {
+    /// `None` for tracks that aren't part of a work
+    pub work: Option<String>,
+    pub composers: Vec<String>,
+    /// Ids of the tracks, in movement order
+    pub tracks: Vec<String>,
+}
+
+/// An image of an album, listed by `GET /album/{id}/artwork`
+#[derive(serde::Serialize, serde::Deserialize, Debug)]
+#[cfg_attr(feature = "ts", derive(ts_rs::TS), ts(export))]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct AlbumArtwork {
+    /// What the image shows, as typed in the tags, e.g. `front`, `back`, `leaflet`, `media` or
+    /// `lead_artist`
+    pub kind: String,
+    pub url: String,
+    pub mime: String,
+    /// In bytes
+    #[cfg_attr(feature = "ts", ts(type = "number"))]
+    pub size: u64,
+    pub width: Option<u32>,
+    pub height: Option<u32>,
+}
+
+/// Folder of playlists mirroring the folders of the library, body of `GET /playlists/tree`
+#[derive(serde::Serialize, serde::Deserialize, Debug, Default)]
+#[cfg_attr(feature = "ts", derive(ts_rs::TS), ts(export))]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct PlaylistFolder {
+    /// Empty for the root of the library
+    pub name: String,
+    pub folders: Vec<PlaylistFolder>,
+    pub playlists: Vec<Playlist>,
+}
+
+#[derive(serde::Serialize, serde::Deserialize, Debug)]
+#[cfg_attr(feature = "ts", derive(ts_rs::TS), ts(export))]
+#[cfg_attr(feature = "openapi", derive(utoipa::IntoParams), into_params(parameter_in = Query))]
+pub struct SeekTableQuery {
+    /// Seconds between two entries of the table, at most the duration of the track
+    #[cfg_attr(feature = "ts", ts(type = "number | null"))]
+    pub interval: Option<u64>,
+}
+
+/// Byte offsets to request to start playing at `n * interval` seconds
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone)]
+#[cfg_attr(feature = "ts", derive(ts_rs::TS), ts(export))]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct SeekTable {
+    #[cfg_attr(feature = "ts", ts(type = "number"))]
+    pub interval: u64,
+    /// Whether the offsets come from the frames of the file, or are estimated from its size
+    pub exact: bool,
+    #[cfg_attr(feature = "ts", ts(type = "number[]"))]
+    pub offsets: Vec<u64>,
+}
+
+/// A picture embedded in the tags of a file
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone)]
+#[cfg_attr(feature = "ts", derive(ts_rs::TS), ts(export))]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct EmbeddedPicture {
+    /// Tag holding it, see `TrackInfo::tags`
+    pub tag: String,
+    /// e.g. `CoverFront`, `Artist`
+    pub kind: String,
+    pub mime: Option<String>,
+    pub description: Option<String>,
+    /// In bytes
+    pub size: usize,
+    pub width: Option<u32>,
+    pub height: Option<u32>,
+}
+
+/// Body of `GET /track/{id}/info`, read from the audio file on each request
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone)]
+#[cfg_attr(feature = "ts", derive(ts_rs::TS), ts(export))]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct TrackInfo {
+    pub id: String,
+    /// The audio file, the one split by the cue sheet for the parts of a file
+    pub file: String,
+    /// e.g. `FLAC`, `MP4`, `Ogg`
+    pub container: String,
+    /// e.g. `FLAC`, `AAC`, `ALAC`, `Opus`, `PCM`
+    pub codec: String,
+    /// In bytes
+    #[cfg_attr(feature = "ts", ts(type = "number"))]
+    pub size: u64,
+    /// Of the whole file, in milliseconds
+    #[cfg_attr(feature = "ts", ts(type = "number"))]
+    pub duration: u64,
+    /// In Hz
+    pub sample_rate: Option<u32>,
+    pub bit_depth: Option<u8>,
+    pub channels: Option<u8>,
+    /// In kbps
+    pub audio_bitrate: Option<u32>,
+    /// In kbps, counting the tags and the container
+    pub overall_bitrate: Option<u32>,
+    /// Tags in the file, e.g. `Id3v2`, `VorbisComments`, the one read by the scan first
+    pub tags: Vec<String>,
+    pub pictures: Vec<EmbeddedPicture>,
+    /// Other paths of the file, through symlinks or hard links, left out of the library
+    #[serde(default)]
+    pub aliases: Vec<String>,
+}
+
+/// A translation of the synced lyrics, read from `<track>.<language>.lrc`
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone)]
+#[cfg_attr(feature = "ts", derive(ts_rs::TS), ts(export))]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct LyricsTranslation {
+    /// From the name of the file, e.g. `fr`, `pt-BR`
+    pub language: String,
+    /// One per line of `TrackLyrics::synced`, `None` where the translation has no line at
+    /// its time
+    pub lines: Vec<Option<String>>,
+}
+
+/// Body of `PUT /track/{id}/lyrics`, the synced lines or else the plain text. Lyrics left
+/// empty are removed.
+#[derive(serde::Serialize, serde::Deserialize, Debug, Default)]
+#[cfg_attr(feature = "ts", derive(ts_rs::TS), ts(export))]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct NewLyrics {
+    #[serde(default)]
+    pub synced: Vec<LyricLine>,
+    #[serde(default)]
+    pub plain: Option<String>,
+}
+
+/// Body of `GET /lyrics/{id}`, read from the files of the track on each request
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone)]
+#[cfg_attr(feature = "ts", derive(ts_rs::TS), ts(export))]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct TrackLyrics {
+    pub id: String,
+    /// From the `.lrc` file next to the track, or the lyrics tag when it is timed
+    pub synced: Vec<LyricLine>,
+    /// The lyrics without timing, for tracks with no synced lyrics
+    pub plain: Option<String>,
+    pub translations: Vec<LyricsTranslation>,
+}
+
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone)]
+#[cfg_attr(feature = "ts", derive(ts_rs::TS), ts(export))]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct AuditReport {
+    #[cfg_attr(feature = "ts", ts(as = "crate::SystemTime"))]
+    #[cfg_attr(feature = "openapi", schema(value_type = SystemTime))]
+    pub finished_at: SystemTime,
+    /// Number of tracks sampled
+    pub checked: usize,
+    /// Tracks whose file is gone, removed from the cache
+    #[cfg_attr(feature = "openapi", schema(value_type = Vec<String>))]
+    pub removed: Vec<PathBuf>,
+    /// Tracks whose cover had disappeared from the covers dir, extracted again
+    #[cfg_attr(feature = "openapi", schema(value_type = Vec<String>))]
+    pub covers_restored: Vec<PathBuf>,
+}
+
+impl AuditReport {
+    pub fn repaired(&self) -> bool {
+        !self.removed.is_empty() || !self.covers_restored.is_empty()
+    }
+}
+
+/// Report of a collection of the covers cache
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone)]
+#[cfg_attr(feature = "ts", derive(ts_rs::TS), ts(export))]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct CacheGcReport {
+    #[cfg_attr(feature = "ts", ts(as = "crate::SystemTime"))]
+    #[cfg_attr(feature = "openapi", schema(value_type = SystemTime))]
+    pub finished_at: SystemTime,
+    /// Covers and portraits no album or artist of the library uses anymore, removed
+    pub removed: usize,
+    /// Names of the covers cut short, removed to be extracted again by the audit
+    pub truncated: Vec<String>,
+    /// Bytes freed
+    #[cfg_attr(feature = "ts", ts(type = "number"))]
+    pub reclaimed: u64,
+}
+
+/// Body of `GET /cache/stats`
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone)]
+#[cfg_attr(feature = "ts", derive(ts_rs::TS), ts(export))]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct CacheStats {
+    /// Album covers stored
+    pub covers: usize,
+    /// In bytes
+    #[cfg_attr(feature = "ts", ts(type = "number"))]
+    pub covers_size: u64,
+    /// Artist portraits stored, see `library.external_artwork`
+    pub portraits: usize,
+    /// In bytes
+    #[cfg_attr(feature = "ts", ts(type = "number"))]
+    pub portraits_size: u64,
+    /// `null` until a collection ran
+    pub last_gc: Option<CacheGcReport>,
+}
+
+/// Kind of a problem of the library reported by `GET /library/issues`
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "ts", derive(ts_rs::TS), ts(export))]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+#[serde(rename_all = "snake_case")]
+pub enum IssueCode {
+    /// The track has no title tag
+    MissingTitle,
+    /// The track has no album tag
+    MissingAlbum,
+    /// The album has no cover, embedded or next to its files
+    MissingCover,
+    /// The duration of the track couldn't be read
+    ZeroDuration,
+    /// The bitrate of the track is unknown, or too low for music
+    SuspiciousBitrate,
+    /// Albums share an id, e.g. through a MusicBrainz release id copied over
+    DuplicateAlbumId,
+    /// A file of the library is gone or can't be read, and left out of it
+    UnreadableFile,
+    /// An entry of a playlist points to no track of the library
+    BrokenPlaylistEntry,
+    /// The tracks of an album are dated of different years
+    YearConflict,
+}
+
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone)]
+#[cfg_attr(feature = "ts", derive(ts_rs::TS), ts(export))]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct LibraryIssue {
+    pub code: IssueCode,
+    /// Description of the problem, for people
+    pub message: String,
+    /// Id of the album concerned
+    pub album: Option<String>,
+    /// Ids of the tracks concerned
+    pub tracks: Vec<String>,
+    /// Id of the playlist concerned
+    pub playlist: Option<String>,
+    /// Path of the file concerned, or the entry of the playlist as written
+    pub path: Option<String>,
+}
+
+/// A library of the daemon, listed by `GET /libraries`
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone)]
+#[cfg_attr(feature = "ts", derive(ts_rs::TS), ts(export))]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct LibraryInfo {
+    pub name: String,
+    #[cfg_attr(feature = "openapi", schema(value_type = Vec<String>))]
+    pub paths: Vec<PathBuf>,
+    /// Requests naming no library are scoped to this one
+    pub default: bool,
+    pub tracks: usize,
+    pub albums: usize,
+}
+
+/// Files handled by the running scan
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone, Copy)]
+#[cfg_attr(feature = "ts", derive(ts_rs::TS), ts(export))]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct ScanProgress {
+    pub done: usize,
+    /// Files new to the cache, the unchanged ones are not read again
+    pub total: usize,
+}
+
+/// What a scan found against the cache of the library
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone, Copy, Default)]
+#[cfg_attr(feature = "ts", derive(ts_rs::TS), ts(export))]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct Reconciliation {
+    /// Files new to the cache, read for the first time
+    pub added: usize,
+    /// Files gone since the last scan, dropped with their tracks
+    pub removed: usize,
+    /// Files found at another path with the same size and content, kept without being read
+    /// again
+    pub moved: usize,
+    /// Files whose size or modification time changed at the same path, e.g. retagged, read
+    /// again and kept under the ids of their tracks
+    #[serde(default)]
+    pub updated: usize,
+    pub unchanged: usize,
+}
+
+/// A file the scan couldn't read, e.g. a corrupt or empty one, or one that isn't audio
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone)]
+#[cfg_attr(feature = "ts", derive(ts_rs::TS), ts(export))]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct ScanError {
+    pub path: String,
+    pub error: String,
+}
+
+/// Body of `GET /scan/last`
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone)]
+#[cfg_attr(feature = "ts", derive(ts_rs::TS), ts(export))]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct LastScan {
+    #[cfg_attr(feature = "ts", ts(as = "crate::SystemTime"))]
+    #[cfg_attr(feature = "openapi", schema(value_type = SystemTime))]
+    pub finished_at: SystemTime,
+    /// In milliseconds
+    #[cfg_attr(feature = "ts", ts(type = "number"))]
+    pub duration: u64,
+    /// Files that could not be read, left out of the library
+    pub errors: usize,
+    /// The first of these files, with the reason they couldn't be read
+    #[serde(default)]
+    pub failed: Vec<ScanError>,
+    /// Whether the scan went through, it may have been cancelled by a shutdown
+    pub completed: bool,
+    /// Missing when the scan was cancelled
+    pub reconciliation: Option<Reconciliation>,
+    /// Digest of the paths, sizes and modification times of the files of the library as the
+    /// scan found them. A client holding another one has a stale view of the library.
+    /// Missing when the scan was cancelled.
+    #[serde(default)]
+    pub fingerprint: Option<String>,
+}
+
+/// Query of `POST /scan`
+#[derive(serde::Serialize, serde::Deserialize, Debug, Default)]
+#[cfg_attr(feature = "ts", derive(ts_rs::TS), ts(export))]
+#[cfg_attr(feature = "openapi", derive(utoipa::IntoParams), into_params(parameter_in = Query))]
+pub struct ScanQuery {
+    /// Read every file again, not only the ones added or changed since the last scan
+    pub full: Option<bool>,
+}
+
+/// Body of `GET /scan/status`
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone)]
+#[cfg_attr(feature = "ts", derive(ts_rs::TS), ts(export))]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct ScanStatus {
+    pub running: bool,
+    /// Of the running scan
+    pub progress: Option<ScanProgress>,
+    pub last: Option<LastScan>,
+}
+
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "ts", derive(ts_rs::TS), ts(export))]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+#[serde(rename_all = "lowercase")]
+pub enum JobKind {
+    /// Scan of the library
+    Scan,
+    /// Folder covers embedded into the tracks of the library, see `library.embed_covers`
+    Embed,
+    /// Check of the cache of the library against the files
+    Audit,
+    /// Cut of a track split by a cue sheet
+    Transcode,
+    /// Tags of many tracks edited at once, see `POST /tracks/batch`
+    Tags,
+    /// Files moved by `POST /organize`
+    Organize,
+    /// Files taken from the drop folder `library.import_path`
+    Import,
+    /// Covers the library doesn't use, or cut short, removed from its cache
+    #[serde(rename = "cache_gc")]
+    CacheGc,
+    /// Tempo and key of the tracks found from their audio, see `library.analyse_audio`
+    Analysis,
+}
+
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "ts", derive(ts_rs::TS), ts(export))]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+#[serde(rename_all = "lowercase")]
+pub enum JobState {
+    Queued,
+    Running,
+    Done,
+    Failed,
+    Cancelled,
+}
+
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone, Copy)]
+#[cfg_attr(feature = "ts", derive(ts_rs::TS), ts(export))]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct JobProgress {
+    pub done: usize,
+    pub total: usize,
+}
+
+/// A background job, listed by `GET /jobs` and sent with the `job:updated` event
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone)]
+#[cfg_attr(feature = "ts", derive(ts_rs::TS), ts(export))]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct Job {
+    pub id: String,
+    pub kind: JobKind,
+    /// Name of the library the job works on
+    pub library: String,
+    /// Id of the track of a `transcode` job, `full` for a scan reading every file again
+    pub target: Option<String>,
+    pub state: JobState,
+    /// Of a running job, for the kinds which report it
+    pub progress: Option<JobProgress>,
+    /// Why the job failed
+    pub error: Option<String>,
+    #[cfg_attr(feature = "ts", ts(as = "crate::SystemTime"))]
+    #[cfg_attr(feature = "openapi", schema(value_type = SystemTime))]
+    pub created_at: SystemTime,
+    #[cfg_attr(feature = "ts", ts(as = "Option<crate::SystemTime>"))]
+    #[cfg_attr(feature = "openapi", schema(value_type = Option<SystemTime>))]
+    pub finished_at: Option<SystemTime>,
+}
+
+impl Job {
+    pub fn finished(&self) -> bool {
+        matches!(
+            self.state,
+            JobState::Done | JobState::Failed | JobState::Cancelled
+        )
+    }
+}
+
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "ts", derive(ts_rs::TS), ts(export))]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+#[serde(rename_all = "lowercase")]
+pub enum Role {
+    /// Manages the accounts and the daemon
+    Admin,
+    Listener,
+}
+
+/// An account, with its own favorites, history and queue
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone)]
+#[cfg_attr(feature = "ts", derive(ts_rs::TS), ts(export))]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct User {
+    pub name: String,
+    pub role: Role,
+    #[cfg_attr(feature = "ts", ts(as = "crate::SystemTime"))]
+    #[cfg_attr(feature = "openapi", schema(value_type = SystemTime))]
+    pub created_at: SystemTime,
+    /// Leaves the explicit tracks out of the browse, search and radio of the account
+    #[serde(default)]
+    pub hide_explicit: bool,
+    /// Names of the libraries the account sees, every one when empty. Requests without
+    /// `X-Library` are scoped to the first one the account sees.
+    #[serde(default)]
+    pub libraries: Vec<String>,
+}
+
+/// Body of `POST /admin/users`
+#[derive(serde::Serialize, serde::Deserialize, Debug)]
+#[cfg_attr(feature = "ts", derive(ts_rs::TS), ts(export))]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct NewUser {
+    pub name: String,
+    pub role: Role,
+    #[serde(default)]
+    pub hide_explicit: bool,
+    /// See [`User::libraries`]
+    #[serde(default)]
+    pub libraries: Vec<String>,
+}
+
+/// Body of `PATCH /admin/users/{name}`, the fields left out are kept
+#[derive(serde::Serialize, serde::Deserialize, Debug, Default)]
+#[cfg_attr(feature = "ts", derive(ts_rs::TS), ts(export))]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct UserChanges {
+    pub hide_explicit: Option<bool>,
+    /// See [`User::libraries`], empty to let the account see every library
+    pub libraries: Option<Vec<String>>,
+}
+
+/// Token of an account, only given when the account is created or its token renewed. Requests
+/// carry it as `Authorization: Bearer <token>`, socket.io connections as `auth.token`.
+#[derive(serde::Serialize, serde::Deserialize, Debug)]
+#[cfg_attr(feature = "ts", derive(ts_rs::TS), ts(export))]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct UserToken {
+    pub user: User,
+    pub token: String,
+}
+
+/// Body of `POST /import`
+#[derive(serde::Serialize, serde::Deserialize, Debug)]
+#[cfg_attr(feature = "ts", derive(ts_rs::TS), ts(export))]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct ImportReport {
+    /// Files of the cache and of the user data restored, as named in the archive
+    pub restored: Vec<String>,
+    /// Playlists written to the folders of the library
+    #[cfg_attr(feature = "openapi", schema(value_type = Vec<String>))]
+    pub playlists: Vec<PathBuf>,
+    /// Entries of the archive left out: unknown, or playlists already there
+    pub skipped: Vec<String>,
+    /// The scan matching the restored cache with the files of this machine
+    pub scan: Job,
+}
+
+#[derive(serde::Serialize, serde::Deserialize, Debug)]
+#[cfg_attr(feature = "ts", derive(ts_rs::TS), ts(export))]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct Stats {
+    pub tracks: usize,
+    pub albums: usize,
+    pub artists: usize,
+    pub playlists: usize,
+    /// Of all the tracks, in seconds
+    #[cfg_attr(feature = "ts", ts(type = "number"))]
+    pub duration: u64,
+    /// Tracks listened to the end
+    pub plays: usize,
+    pub favorites: usize,
+}
+
+/// Body of `POST /player/play` and `POST /sessions/{id}/queue`
+#[derive(serde::Serialize, serde::Deserialize, Debug)]
+#[cfg_attr(feature = "ts", derive(ts_rs::TS), ts(export))]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct PlayRequest {
+    pub id: String,
+}
+
+/// Body of `POST /player/queue`
+#[derive(serde::Serialize, serde::Deserialize, Debug)]
+#[cfg_attr(feature = "ts", derive(ts_rs::TS), ts(export))]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct QueueRequest {
+    pub ids: Vec<String>,
+    /// Put the tracks at the top of the queue instead of the bottom
+    #[serde(default)]
+    pub next: bool,
+}
+
+/// Body of `PUT /queue`, what a player is playing
+#[derive(serde::Serialize, serde::Deserialize, Debug)]
+#[cfg_attr(feature = "ts", derive(ts_rs::TS), ts(export))]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct NewQueue {
+    /// Ids of the tracks
+    pub tracks: Vec<String>,
+    /// Index of the current track in `tracks`
+    pub current: Option<usize>,
+    /// Elapsed time in the current track, in milliseconds
+    #[cfg_attr(feature = "ts", ts(type = "number"))]
+    pub position: u64,
+    /// Whether the current track is playing, its position then goes on
+    pub playing: bool,
+}
+
+/// Body of `GET /queue` and data of the `queue:updated` event
+#[derive(serde::Serialize, serde::Deserialize, Debug)]
+#[cfg_attr(feature = "ts", derive(ts_rs::TS), ts(export))]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct PlayQueue {
+    pub tracks: Vec<Track>,
+    /// Index of the current track in `tracks`
+    pub current: Option<usize>,
+    /// Elapsed time in the current track, counting the time since the queue was saved while
+    /// playing, in milliseconds
+    #[cfg_attr(feature = "ts", ts(type = "number"))]
+    pub position: u64,
+    pub playing: bool,
+    #[cfg_attr(feature = "ts", ts(as = "crate::SystemTime"))]
+    #[cfg_attr(feature = "openapi", schema(value_type = SystemTime))]
+    pub updated_at: SystemTime,
+    /// What the radio topping the queue up plays like, `None` without radio
+    #[serde(default)]
+    pub radio: Option<RadioSeed>,
+}
+
+/// What a radio plays like: a track, the tracks of an album, or a genre
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "ts", derive(ts_rs::TS), ts(export))]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+#[serde(tag = "kind", rename_all = "lowercase")]
+pub enum RadioSeed {
+    Track { id: String },
+    Album { id: String },
+    Genre { name: String },
+}
+
+/// Body of `POST /queue/radio`
+#[derive(serde::Serialize, serde::Deserialize, Debug)]
+#[cfg_attr(feature = "ts", derive(ts_rs::TS), ts(export))]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct RadioRequest {
+    pub seed: RadioSeed,
+    /// Empties the queue first, starting it with the seed track when it is one. The radio
+    /// follows the current queue otherwise.
+    #[serde(default)]
+    pub replace: bool,
+}
+
+/// Playback settings of an album or playlist, the unset ones falling back to the `[player]`
+/// configuration
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone, Default)]
+#[cfg_attr(feature = "ts", derive(ts_rs::TS), ts(export))]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct PlaybackHints {
+    /// Seconds two tracks overlap, 0 disables it
+    pub crossfade: Option<f32>,
+    pub gapless: Option<bool>,
+    pub normalize: Option<bool>,
+    /// In LUFS
+    pub loudness_target: Option<f32>,
+    /// Whether the silences at the ends of the tracks are skipped, see `Track::trim_start_ms`
+    pub trim_silence: Option<bool>,
+}
+
+/// Playback settings players apply to an album or playlist
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone)]
+#[cfg_attr(feature = "ts", derive(ts_rs::TS), ts(export))]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct PlaybackPreferences {
+    /// Seconds two tracks overlap
+    pub crossfade: f32,
+    pub gapless: bool,
+    pub normalize: bool,
+    /// In LUFS
+    pub loudness_target: f32,
+    pub trim_silence: bool,
+}
+
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "ts", derive(ts_rs::TS), ts(export))]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+#[serde(rename_all = "lowercase")]
+pub enum PlaybackTarget {
+    Album,
+    Playlist,
+}
+
+/// Data of the `playback:updated` event
+#[derive(serde::Serialize, serde::Deserialize, Debug)]
+#[cfg_attr(feature = "ts", derive(ts_rs::TS), ts(export))]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct PlaybackChange {
+    pub target: PlaybackTarget,
+    /// Id of the album or playlist
+    pub id: String,
+    pub preferences: PlaybackPreferences,
+}
+
+/// Body of `POST /sessions`
+#[derive(serde::Serialize, serde::Deserialize, Debug, Default)]
+#[cfg_attr(feature = "ts", derive(ts_rs::TS), ts(export))]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct NewSession {
+    /// Shown to the listeners, defaults to `Party`
+    pub name: Option<String>,
+}
+
+/// A track suggested to a session with the votes it got
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone)]
+#[cfg_attr(feature = "ts", derive(ts_rs::TS), ts(export))]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct SessionEntry {
+    pub track: Track,
+    pub votes: usize,
+}
+
+/// A shared listening session, data of the `session:updated` event sent to its room
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone)]
+#[cfg_attr(feature = "ts", derive(ts_rs::TS), ts(export))]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct Session {
+    pub id: String,
+    pub name: String,
+    /// What the host plays
+    pub current: Option<Track>,
+    /// Elapsed time in the current track, counting the time since the host reported it while
+    /// playing, in milliseconds
+    #[cfg_attr(feature = "ts", ts(type = "number"))]
+    pub position: u64,
+    pub playing: bool,
+    /// Suggested tracks, the most voted first
+    pub queue: Vec<SessionEntry>,
+    #[cfg_attr(feature = "ts", ts(as = "crate::SystemTime"))]
+    #[cfg_attr(feature = "openapi", schema(value_type = SystemTime))]
+    pub updated_at: SystemTime,
+}
+
+/// Reply to `POST /sessions`, the token is only given to the host, who sends it as
+/// `X-Session-Token` to control the session
+#[derive(serde::Serialize, serde::Deserialize, Debug)]
+#[cfg_attr(feature = "ts", derive(ts_rs::TS), ts(export))]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct HostedSession {
+    pub session: Session,
+    pub host_token: String,
+}
+
+/// Body of `PUT /sessions/{id}/playback`, what the host plays
+#[derive(serde::Serialize, serde::Deserialize, Debug)]
+#[cfg_attr(feature = "ts", derive(ts_rs::TS), ts(export))]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct SessionPlayback {
+    /// Id of the track, nothing playing when unset
+    pub track: Option<String>,
+    /// In milliseconds
+    #[serde(default)]
+    #[cfg_attr(feature = "ts", ts(type = "number"))]
+    pub position: u64,
+    #[serde(default)]
+    pub playing: bool,
+}
+
+/// Data of the `session:sync` event, sent to the room of a playing session every few seconds
+/// for the listeners to catch up with the host
+#[derive(serde::Serialize, serde::Deserialize, Debug)]
+#[cfg_attr(feature = "ts", derive(ts_rs::TS), ts(export))]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct SessionSync {
+    /// Id of the session
+    pub id: String,
+    /// Id of the current track
+    pub track: Option<String>,
+    /// In milliseconds
+    #[cfg_attr(feature = "ts", ts(type = "number"))]
+    pub position: u64,
+    pub playing: bool,
+}
+
+/// What a timer does when it fires
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "ts", derive(ts_rs::TS), ts(export))]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+#[serde(tag = "kind", rename_all = "lowercase")]
+pub enum TimerAction {
+    /// Stops the playback, e.g. to fall asleep
+    Stop,
+    /// Plays the playlist `id`
+    Playlist { id: String },
+    /// Plays the album `id`
+    Album { id: String },
+}
+
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "ts", derive(ts_rs::TS), ts(export))]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+#[serde(rename_all = "lowercase")]
+pub enum Weekday {
+    Monday,
+    Tuesday,
+    Wednesday,
+    Thursday,
+    Friday,
+    Saturday,
+    Sunday,
+}
+
+/// Body of `POST /timers`: the timer fires `after` seconds, or at the time of day `at` in the
+/// local time of the daemon
+#[derive(serde::Serialize, serde::Deserialize, Debug)]
+#[cfg_attr(feature = "ts", derive(ts_rs::TS), ts(export))]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct NewTimer {
+    pub action: TimerAction,
+    pub label: Option<String>,
+    #[cfg_attr(feature = "ts", ts(type = "number | null"))]
+    pub after: Option<u64>,
+    /// `HH:MM`
+    pub at: Option<String>,
+    /// Days `at` repeats on, it fires once when empty
+    #[serde(default)]
+    pub days: Vec<Weekday>,
+}
+
+/// A scheduled action, see `NewTimer`
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone)]
+#[cfg_attr(feature = "ts", derive(ts_rs::TS), ts(export))]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct Timer {
+    pub id: String,
+    pub label: Option<String>,
+    pub action: TimerAction,
+    pub at: Option<String>,
+    pub days: Vec<Weekday>,
+    /// Library of the album or playlist to play
+    pub library: String,
+    /// When it fires next
+    #[cfg_attr(feature = "ts", ts(as = "crate::SystemTime"))]
+    #[cfg_attr(feature = "openapi", schema(value_type = SystemTime))]
+    pub next: SystemTime,
+    #[cfg_attr(feature = "ts", ts(as = "crate::SystemTime"))]
+    #[cfg_attr(feature = "openapi", schema(value_type = SystemTime))]
+    pub created_at: SystemTime,
+}
+
+/// Data of the `timer:fired` event
+#[derive(serde::Serialize, serde::Deserialize, Debug)]
+#[cfg_attr(feature = "ts", derive(ts_rs::TS), ts(export))]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct FiredTimer {
+    pub timer: Timer,
+    /// Tracks of the album or playlist to play, none for `stop`
+    pub tracks: Vec<Track>,
+}
+
+/// Data of the `queue:add` event
+#[derive(serde::Serialize, serde::Deserialize, Debug)]
+#[cfg_attr(feature = "ts", derive(ts_rs::TS), ts(export))]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct QueuedTracks {
+    pub tracks: Vec<Track>,
+    pub next: bool,
+}
+
+/// Data of the `track:colored` event, sent for each cover analysed after a scan
+#[derive(serde::Serialize, serde::Deserialize, Debug)]
+#[cfg_attr(feature = "ts", derive(ts_rs::TS), ts(export))]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct ColoredTracks {
+    /// Ids of the tracks sharing the cover
+    pub ids: Vec<String>,
+    pub album_id: String,
+    pub palette: Palette,
+}
+
+/// Colors of the cover of an album, for a client to theme itself without the cover or the
+/// whole media, body of `GET /album/{id}/theme`
+#[derive(serde::Serialize, serde::Deserialize, Debug)]
+#[cfg_attr(feature = "ts", derive(ts_rs::TS), ts(export))]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct AlbumTheme {
+    pub album_id: String,
+    /// `palette.text` is readable over `palette.primary`
+    pub palette: Palette,
+    /// Whether `palette.primary` is light, dark text goes over it
+    pub is_light: bool,
+    /// Readable text over `palette.secondary`
+    pub on_secondary: Color,
+    /// Readable text over `palette.accent`
+    pub on_accent: Color,
+}
+
+impl AlbumTheme {
+    pub fn new(album_id: String, palette: Palette) -> Self {
+        Self {
+            album_id,
+            is_light: palette.primary.is_light_color(),
+            on_secondary: palette.secondary.contrasting(),
+            on_accent: palette.accent.contrasting(),
+            palette,
+        }
+    }
+}
+
+/// A change of the tags applied to every track of a `POST /tracks/batch`
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "ts", derive(ts_rs::TS), ts(export))]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+#[serde(tag = "op", rename_all = "snake_case")]
+pub enum BatchOperation {
+    SetGenre {
+        genre: String,
+    },
+    SetAlbumArtist {
+        album_artist: String,
+    },
+    /// Numbers the tracks in the order they are given, from `start` or 1
+    Renumber {
+        start: Option<u32>,
+    },
+    /// Sets the album of the tracks, and their album artist if given
+    MoveToAlbum {
+        album: String,
+        album_artist: Option<String>,
+    },
+    /// Writes the titles and track numbers read from the file names to the tags, see
+    /// `Track::title_inferred`
+    WriteInferred,
+}
+
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone)]
+#[cfg_attr(feature = "ts", derive(ts_rs::TS), ts(export))]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct BatchRequest {
+    /// Ids of the tracks
+    pub tracks: Vec<String>,
+    pub operation: BatchOperation,
+}
+
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone)]
+#[cfg_attr(feature = "ts", derive(ts_rs::TS), ts(export))]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct BatchFailure {
+    pub track: String,
+    pub error: String,
+}
+
+/// Outcome of a `POST /tracks/batch`. The files of the failed tracks are left untouched.
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone)]
+#[cfg_attr(feature = "ts", derive(ts_rs::TS), ts(export))]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct BatchResult {
+    pub job: Job,
+    /// Ids of the tracks edited
+    pub updated: Vec<String>,
+    pub failed: Vec<BatchFailure>,
+}
+
+#[derive(serde::Serialize, serde::Deserialize, Debug, Default)]
+#[cfg_attr(feature = "ts", derive(ts_rs::TS), ts(export))]
+#[cfg_attr(feature = "openapi", derive(utoipa::IntoParams), into_params(parameter_in = Query))]
+pub struct OrganizeQuery {
+    /// Moves the files, they are only listed otherwise
+    #[serde(default)]
+    pub apply: bool,
+}
+
+/// A file moved to the path given by `library.organize_pattern`
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone)]
+#[cfg_attr(feature = "ts", derive(ts_rs::TS), ts(export))]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct OrganizeMove {
+    /// Id of the track of the file
+    pub track: String,
+    pub from: String,
+    pub to: String,
+}
+
+/// A file left where it is
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone)]
+#[cfg_attr(feature = "ts", derive(ts_rs::TS), ts(export))]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct OrganizeSkip {
+    pub track: String,
+    pub path: String,
+    pub reason: String,
+}
+
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone)]
+#[cfg_attr(feature = "ts", derive(ts_rs::TS), ts(export))]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct OrganizeReport {
+    /// Whether the files were moved, `moves` only lists them otherwise
+    pub applied: bool,
+    pub moves: Vec<OrganizeMove>,
+    /// Files already in place aren't listed
+    pub skipped: Vec<OrganizeSkip>,
+    /// The job that moved the files
+    pub job: Option<Job>,
+}
+
+/// A request that changed something, as kept by the audit trail of `GET /admin/audit`
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone)]
+#[cfg_attr(feature = "ts", derive(ts_rs::TS), ts(export))]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct AuditEntry {
+    #[cfg_attr(feature = "ts", ts(as = "crate::SystemTime"))]
+    #[cfg_attr(feature = "openapi", schema(value_type = SystemTime))]
+    pub at: SystemTime,
+    /// Account whose token the request carried, `None` for the default user
+    pub user: Option<String>,
+    /// Remote address of the client, `127.0.0.1` for the Unix socket
+    pub address: String,
+    pub method: String,
+    /// Path of the route, without `network.base_path` and the library prefix
+    pub path: String,
+    /// Library the request was scoped to, `None` for the default one
+    pub library: Option<String>,
+    pub status: u16,
+    #[cfg_attr(feature = "ts", ts(type = "number"))]
+    pub duration_ms: u64,
+}
+
+#[derive(serde::Serialize, serde::Deserialize, Debug, Default)]
+#[cfg_attr(feature = "ts", derive(ts_rs::TS), ts(export))]
+#[cfg_attr(feature = "openapi", derive(utoipa::IntoParams), into_params(parameter_in = Query))]
+pub struct AuditQuery {
+    /// Only the entries from this time on, in seconds since the Unix epoch
+    #[cfg_attr(feature = "ts", ts(type = "number | null"))]
+    pub since: Option<u64>,
+    /// Only the requests of this account
+    pub user: Option<String>,
+    /// Only the requests with this path prefix, e.g. `/tracks` or `/playlist`
+    pub path: Option<String>,
+    /// The most recent entries kept, 100 by default
+    pub limit: Option<usize>,
+}
+
+#[derive(serde::Serialize, serde::Deserialize, Debug, Default)]
+#[cfg_attr(feature = "ts", derive(ts_rs::TS), ts(export))]
+#[cfg_attr(feature = "openapi", derive(utoipa::IntoParams), into_params(parameter_in = Query))]
+pub struct SyncQuery {
+    /// Ids of the playlists, separated by commas
+    #[serde(default)]
+    pub playlists: String,
+}
+
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "ts", derive(ts_rs::TS), ts(export))]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+#[serde(rename_all = "lowercase")]
+pub enum SyncKind {
+    Audio,
+    Cover,
+    /// The body of `GET /lyrics/{id}`
+    Lyrics,
+}
+
+/// A file needed to play the playlists of a sync manifest offline
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone)]
+#[cfg_attr(feature = "ts", derive(ts_rs::TS), ts(export))]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct SyncFile {
+    /// Name of the file in the archives of `POST /sync/download`, `audio/<track id>.<ext>`,
+    /// `covers/<album id>.<ext>` or `lyrics/<track id>.json`
+    pub path: String,
+    pub kind: SyncKind,
+    /// Id of the track, or of the album for a cover
+    pub id: String,
+    /// Where the file is served alone
+    pub url: String,
+    /// In bytes, unknown for the parts of files split by a cue sheet not cut yet and the files
+    /// of remote storages
+    #[cfg_attr(feature = "ts", ts(type = "number | null"))]
+    pub size: Option<u64>,
+    /// Changes with the content of the file
+    pub hash: String,
+}
+
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone)]
+#[cfg_attr(feature = "ts", derive(ts_rs::TS), ts(export))]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct SyncPlaylist {
+    pub id: String,
+    pub name: String,
+    pub description: Option<String>,
+    /// Ids of the tracks, in order
+    pub tracks: Vec<String>,
+}
+
+/// Body of `GET /sync/manifest`. A client keeping the playlists offline downloads the files
+/// whose hash differs from the one it has, and removes the files no longer listed.
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone)]
+#[cfg_attr(feature = "ts", derive(ts_rs::TS), ts(export))]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct SyncManifest {
+    pub playlists: Vec<SyncPlaylist>,
+    /// Each file once, even when shared by several playlists
+    pub files: Vec<SyncFile>,
+    /// Total of the sizes known, in bytes
+    #[cfg_attr(feature = "ts", ts(type = "number"))]
+    pub size: u64,
+    /// Hash of the whole manifest, unchanged while nothing in it is
+    pub hash: String,
+}
+
+/// Body of `POST /sync/download`
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone)]
+#[cfg_attr(feature = "ts", derive(ts_rs::TS), ts(export))]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct SyncDownload {
+    /// Paths of the files of a manifest
+    pub paths: Vec<String>,
+}
+
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "ts", derive(ts_rs::TS), ts(export))]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+#[serde(rename_all = "lowercase")]
+pub enum HistoryFormat {
+    /// An array of `ExportedPlay`
+    #[default]
+    Json,
+    /// The fields of `ExportedPlay` with a header line, the artists joined by `; `
+    Csv,
+    /// One listen per line as in the exports of ListenBrainz, `listened_at` and
+    /// `track_metadata`
+    ListenBrainz,
+}
+
+#[derive(serde::Serialize, serde::Deserialize, Debug, Default)]
+#[cfg_attr(feature = "ts", derive(ts_rs::TS), ts(export))]
+#[cfg_attr(feature = "openapi", derive(utoipa::IntoParams), into_params(parameter_in = Query))]
+pub struct HistoryQuery {
+    /// `json` by default
+    #[serde(default)]
+    pub format: HistoryFormat,
+}
+
+/// A play of `GET /history/export`, with what identifies the track elsewhere. The track of a
+/// play may be gone from the library, its fields are empty then.
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone)]
+#[cfg_attr(feature = "ts", derive(ts_rs::TS), ts(export))]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct ExportedPlay {
+    /// RFC 3339 time, e.g. `2026-10-15T15:00:00Z`
+    pub played_at: String,
+    /// Id of the track
+    pub track: String,
+    #[serde(default)]
+    pub artists: Vec<String>,
+    #[serde(default)]
+    pub title: String,
+    #[serde(default)]
+    pub album: String,
+    /// In seconds
+    #[cfg_attr(feature = "ts", ts(type = "number"))]
+    #[serde(default)]
+    pub duration: u64,
+}
+
+/// Outcome of a `POST /history/import`
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone, Default)]
+#[cfg_attr(feature = "ts", derive(ts_rs::TS), ts(export))]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct HistoryImportReport {
+    /// Plays added to the history
+    pub imported: usize,
+    /// Plays already in the history, of the same track at the same second
+    pub duplicates: usize,
+    /// Tracks played that aren't in the library, as `artist - title`, once each
+    pub unmatched: Vec<String>,
+    /// Lines or entries that couldn't be read
+    pub invalid: usize,
+}
+
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "ts", derive(ts_rs::TS), ts(export))]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+#[serde(rename_all = "lowercase")]
+pub enum IdKind {
+    Track,
+    Album,
+    Playlist,
+}
+
+/// Body of the `400 Bad Request` answered to a malformed id in the path of a request
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone)]
+#[cfg_attr(feature = "ts", derive(ts_rs::TS), ts(export))]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct InvalidId {
+    pub kind: IdKind,
+    /// The id as given
+    pub id: String,
+    /// What is wrong with it, e.g. `must be 32 characters long, not 7`
+    pub reason: String,
+}
@@ -0,0 +1,260 @@
+/// Socket.io namespaces a client can connect to, to only receive the events it needs.
+/// The root namespace `/` still receives every event.
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "ts", derive(ts_rs::TS), ts(export))]
+#[serde(rename_all = "lowercase")]
+pub enum Namespace {
+    /// Changes of the library and of the user data attached to it
+    Library,
+    /// What is being listened to
+    Player,
+    /// Progress of the library scans
+    Scan,
+    /// Progress of the background jobs
+    Jobs,
+}
+
+impl Namespace {
+    pub const ALL: [Namespace; 4] = [
+        Namespace::Library,
+        Namespace::Player,
+        Namespace::Scan,
+        Namespace::Jobs,
+    ];
+
+    pub fn path(&self) -> &'static str {
+        match self {
+            Namespace::Library => "/library",
+            Namespace::Player => "/player",
+            Namespace::Scan => "/scan",
+            Namespace::Jobs => "/jobs",
+        }
+    }
+}
+
+/// Every event emitted by the daemon, serialized as its socket.io event name. Events are sent
+/// with the generation of the media as second argument, to ask `GET /media/changes` for what
+/// changed since, and with their sequence as third, to `resume` from it once reconnected.
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "ts", derive(ts_rs::TS), ts(export))]
+#[cfg_attr(test, derive(strum::EnumIter))]
+pub enum Event {
+    /// The `Track`s added to the library, e.g. by a scan
+    #[serde(rename = "track:added")]
+    TrackAdded,
+    /// The `Track`s whose tags or files changed
+    #[serde(rename = "track:updated")]
+    TrackUpdated,
+    /// The ids of the tracks removed from the library
+    #[serde(rename = "track:removed")]
+    TrackRemoved,
+    /// The `Album`s added to the library
+    #[serde(rename = "album:added")]
+    AlbumAdded,
+    /// The `Album`s whose tracks, cover or summary changed
+    #[serde(rename = "album:updated")]
+    AlbumUpdated,
+    /// The ids of the albums removed from the library
+    #[serde(rename = "album:removed")]
+    AlbumRemoved,
+    /// The `Playlist`s added to the library
+    #[serde(rename = "playlist:added")]
+    PlaylistAdded,
+    /// The `Playlist`s whose entries changed
+    #[serde(rename = "playlist:updated")]
+    PlaylistUpdated,
+    /// The ids of the playlists removed from the library
+    #[serde(rename = "playlist:removed")]
+    PlaylistRemoved,
+    /// The listen later entries
+    #[serde(rename = "listenlater")]
+    ListenLater,
+    /// The ids of the favorite tracks
+    #[serde(rename = "favorites")]
+    Favorites,
+    /// The id of the track listened to the end
+    #[serde(rename = "track:played")]
+    TrackPlayed,
+    /// A client asked the players to play this `Track`
+    #[serde(rename = "player:play")]
+    PlayerPlay,
+    /// A client asked the players to queue these `QueuedTracks`
+    #[serde(rename = "queue:add")]
+    QueueAdd,
+    /// A player saved the `PlayQueue` it plays
+    #[serde(rename = "queue:updated")]
+    QueueUpdated,
+    /// The `PlaybackChange` of an album or playlist
+    #[serde(rename = "playback:updated")]
+    PlaybackUpdated,
+    /// The `Session` changed, only sent to its room
+    #[serde(rename = "session:updated")]
+    SessionUpdated,
+    /// The `SessionSync` of a playing session, only sent to its room
+    #[serde(rename = "session:sync")]
+    SessionSync,
+    /// The session with this id was ended by its host or left idle, only sent to its room
+    #[serde(rename = "session:ended")]
+    SessionEnded,
+    /// A `FiredTimer`
+    #[serde(rename = "timer:fired")]
+    TimerFired,
+    /// The `Timer` list changed
+    #[serde(rename = "timers")]
+    Timers,
+    /// A scan started, no data
+    #[serde(rename = "scan:started")]
+    ScanStarted,
+    /// A scan ended, `true` if it went through, `false` if it was cancelled
+    #[serde(rename = "scan:finished")]
+    ScanFinished,
+    /// A `Job` was queued, started, progressed or finished
+    #[serde(rename = "job:updated")]
+    JobUpdated,
+    /// The `ColoredTracks` whose palette was computed in the background
+    #[serde(rename = "track:colored")]
+    TrackColored,
+    /// Report of a cache audit: the tracks checked, removed and whose cover was restored
+    #[serde(rename = "cache:audited")]
+    CacheAudited,
+    /// The configuration, with its secrets redacted
+    #[serde(rename = "config")]
+    Config,
+    /// The daemon is stopping, no data
+    #[serde(rename = "server:shutdown")]
+    ServerShutdown,
+    /// Reply to a `search` message, only sent to the socket which asked
+    #[serde(rename = "searchresponse")]
+    SearchResponse,
+}
+
+impl Event {
+    /// The socket.io event name, the same as the serialized event
+    pub fn name(&self) -> &'static str {
+        match self {
+            Event::TrackAdded => "track:added",
+            Event::TrackUpdated => "track:updated",
+            Event::TrackRemoved => "track:removed",
+            Event::AlbumAdded => "album:added",
+            Event::AlbumUpdated => "album:updated",
+            Event::AlbumRemoved => "album:removed",
+            Event::PlaylistAdded => "playlist:added",
+            Event::PlaylistUpdated => "playlist:updated",
+            Event::PlaylistRemoved => "playlist:removed",
+            Event::ListenLater => "listenlater",
+            Event::Favorites => "favorites",
+            Event::TrackPlayed => "track:played",
+            Event::PlayerPlay => "player:play",
+            Event::QueueAdd => "queue:add",
+            Event::QueueUpdated => "queue:updated",
+            Event::PlaybackUpdated => "playback:updated",
+            Event::SessionUpdated => "session:updated",
+            Event::SessionSync => "session:sync",
+            Event::SessionEnded => "session:ended",
+            Event::TimerFired => "timer:fired",
+            Event::Timers => "timers",
+            Event::ScanStarted => "scan:started",
+            Event::ScanFinished => "scan:finished",
+            Event::JobUpdated => "job:updated",
+            Event::TrackColored => "track:colored",
+            Event::CacheAudited => "cache:audited",
+            Event::Config => "config",
+            Event::ServerShutdown => "server:shutdown",
+            Event::SearchResponse => "searchresponse",
+        }
+    }
+
+    /// Namespaces the event is emitted on, besides the root one
+    pub fn namespaces(&self) -> &'static [Namespace] {
+        match self {
+            Event::TrackAdded
+            | Event::TrackUpdated
+            | Event::TrackRemoved
+            | Event::AlbumAdded
+            | Event::AlbumUpdated
+            | Event::AlbumRemoved
+            | Event::PlaylistAdded
+            | Event::PlaylistUpdated
+            | Event::PlaylistRemoved
+            | Event::ListenLater
+            | Event::Favorites
+            | Event::TrackColored
+            | Event::CacheAudited => &[Namespace::Library],
+            Event::TrackPlayed
+            | Event::PlayerPlay
+            | Event::QueueAdd
+            | Event::QueueUpdated
+            | Event::PlaybackUpdated
+            | Event::SessionUpdated
+            | Event::SessionSync
+            | Event::SessionEnded
+            | Event::TimerFired
+            | Event::Timers => &[Namespace::Player],
+            Event::ScanStarted | Event::ScanFinished => &[Namespace::Scan],
+            Event::JobUpdated => &[Namespace::Jobs],
+            Event::ServerShutdown => &Namespace::ALL,
+            Event::Config | Event::SearchResponse => &[],
+        }
+    }
+
+    /// Whether the event is replayed to a socket resuming after it, see [`ResumeRequest`].
+    /// Requests to the players, progress and notices only make sense when they are sent.
+    pub fn replayed(&self) -> bool {
+        !matches!(
+            self,
+            Event::PlayerPlay
+                | Event::QueueAdd
+                | Event::SessionSync
+                | Event::TimerFired
+                | Event::JobUpdated
+                | Event::ServerShutdown
+                | Event::SearchResponse
+        )
+    }
+}
+
+/// Data of the `resume` message a client sends once reconnected, with an acknowledgement
+/// callback receiving a [`ResumeReply`]
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone)]
+#[cfg_attr(feature = "ts", derive(ts_rs::TS), ts(export))]
+pub struct ResumeRequest {
+    /// Sequence of the last event received, `None` to only get the current one, e.g. on the
+    /// first connection
+    #[cfg_attr(feature = "ts", ts(type = "number | null"))]
+    pub sequence: Option<u64>,
+}
+
+/// Acknowledgement of `resume`, sent once the missed events were replayed
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone)]
+#[cfg_attr(feature = "ts", derive(ts_rs::TS), ts(export))]
+pub struct ResumeReply {
+    /// Sequence to resume from next time
+    #[cfg_attr(feature = "ts", ts(type = "number"))]
+    pub sequence: u64,
+    /// Events replayed to the socket
+    pub replayed: usize,
+    /// The missed events are no longer known, e.g. the daemon restarted: everything must be
+    /// fetched again
+    pub resync: bool,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use strum::IntoEnumIterator;
+
+    #[test]
+    fn names_match_serde() {
+        for event in Event::iter() {
+            assert_eq!(
+                serde_json::to_value(event).unwrap(),
+                event.name(),
+                "{event:?}"
+            );
+            assert_eq!(
+                serde_json::from_value::<Event>(event.name().into()).unwrap(),
+                event
+            );
+        }
+    }
+}
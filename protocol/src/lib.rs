@@ -0,0 +1,23 @@
+//! Types exchanged between the daemon and its clients.
+//!
+//! With the `ts` feature, `cargo test --features ts` exports their TypeScript definitions to
+//! `bindings/`, or to `$TS_RS_EXPORT_DIR` when set. With the `openapi` feature, they describe
+//! themselves as OpenAPI schemas for the document served by the daemon at `/openapi.json`.
+
+pub mod api;
+pub mod events;
+pub mod library;
+pub mod lite;
+pub mod time;
+
+/// How serde serializes a `std::time::SystemTime` in JSON, binary formats write milliseconds
+/// since the epoch, see [`time`]
+#[cfg(any(feature = "ts", feature = "openapi"))]
+#[cfg_attr(feature = "ts", derive(ts_rs::TS), ts(export))]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+#[allow(dead_code)]
+pub struct SystemTime {
+    #[cfg_attr(feature = "ts", ts(type = "number"))]
+    secs_since_epoch: u64,
+    nanos_since_epoch: u32,
+}
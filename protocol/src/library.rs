@@ -0,0 +1,483 @@
+use std::path::PathBuf;
+use std::time::SystemTime;
+
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone)]
+#[cfg_attr(feature = "ts", derive(ts_rs::TS), ts(export))]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct LyricLine {
+    #[cfg_attr(feature = "ts", ts(type = "number"))]
+    pub start_time: i64,
+    pub text: String,
+    /// Timing of each word, from the `<mm:ss.xx>` tags of enhanced LRC
+    #[serde(default)]
+    pub words: Vec<LyricWord>,
+}
+
+/// A word of an enhanced LRC line, sung from `t` in milliseconds
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone)]
+#[cfg_attr(feature = "ts", derive(ts_rs::TS), ts(export))]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct LyricWord {
+    #[cfg_attr(feature = "ts", ts(type = "number"))]
+    pub t: i64,
+    pub text: String,
+}
+
+/// Someone involved in a recording, e.g. a producer or a performer
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "ts", derive(ts_rs::TS), ts(export))]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct Credit {
+    pub role: String,
+    pub name: String,
+    /// The instrument of a performer, when the tag gives it as `Name (instrument)`
+    pub instrument: Option<String>,
+}
+
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone, Copy)]
+#[cfg_attr(feature = "ts", derive(ts_rs::TS), ts(export))]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct Color {
+    pub r: u8,
+    pub g: u8,
+    pub b: u8,
+}
+
+impl Color {
+    pub const BLACK: Color = Color { r: 0, g: 0, b: 0 };
+    pub const WHITE: Color = Color {
+        r: 255,
+        g: 255,
+        b: 255,
+    };
+
+    pub fn is_light_color(&self) -> bool {
+        let luminance =
+            0.2126 * (self.r as f64) + 0.7152 * (self.g as f64) + 0.0722 * (self.b as f64);
+        let threshold = 180.0;
+
+        luminance > threshold
+    }
+
+    /// Black over light colors, white over dark ones
+    pub fn contrasting(&self) -> Color {
+        if self.is_light_color() {
+            Color::BLACK
+        } else {
+            Color::WHITE
+        }
+    }
+}
+
+/// Colors of a cover
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone, Copy)]
+#[cfg_attr(feature = "ts", derive(ts_rs::TS), ts(export))]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct Palette {
+    /// The dominant color, same as `Track::color`
+    pub primary: Color,
+    /// The next color of the palette
+    pub secondary: Color,
+    /// The most vivid color
+    pub accent: Color,
+    /// Readable text over `primary`
+    pub text: Color,
+}
+
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone)]
+#[cfg_attr(feature = "ts", derive(ts_rs::TS), ts(export))]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct Bookmark {
+    pub id: String,
+    pub name: String,
+    /// Position in the track, in milliseconds
+    #[cfg_attr(feature = "ts", ts(type = "number"))]
+    pub position: u64,
+    #[cfg_attr(feature = "ts", ts(as = "crate::SystemTime"))]
+    #[cfg_attr(feature = "openapi", schema(value_type = SystemTime))]
+    #[serde(with = "crate::time")]
+    pub created_at: SystemTime,
+}
+
+/// Where listening stopped in a track, to resume it later
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone, Copy)]
+#[cfg_attr(feature = "ts", derive(ts_rs::TS), ts(export))]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct SavedPosition {
+    /// In milliseconds
+    #[cfg_attr(feature = "ts", ts(type = "number"))]
+    pub position: u64,
+    #[cfg_attr(feature = "ts", ts(as = "crate::SystemTime"))]
+    #[cfg_attr(feature = "openapi", schema(value_type = SystemTime))]
+    #[serde(with = "crate::time")]
+    pub updated_at: SystemTime,
+}
+
+/// What a track is, guessed from its tags, folder and duration
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "ts", derive(ts_rs::TS), ts(export))]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+#[serde(rename_all = "lowercase")]
+pub enum MediaKind {
+    #[default]
+    Music,
+    Audiobook,
+}
+
+/// Samples to trim at both ends of the decoded audio for gapless playback
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "ts", derive(ts_rs::TS), ts(export))]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct Gapless {
+    /// Samples added by the encoder at the start, as written in the headers
+    pub encoder_delay: u32,
+    /// Samples added by the encoder at the end
+    pub padding: u32,
+    /// Exact length of the audio once trimmed, in samples
+    #[cfg_attr(feature = "ts", ts(type = "number | null"))]
+    pub sample_count: Option<u64>,
+    pub sample_rate: u32,
+}
+
+/// Quality of audio, to filter the browse and search endpoints with `?quality=`
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "ts", derive(ts_rs::TS), ts(export))]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+#[serde(rename_all = "lowercase")]
+pub enum Quality {
+    /// Lossless codecs, e.g. FLAC, ALAC or PCM
+    Lossless,
+    /// Lossless above CD quality, more than 16 bits or 48 kHz
+    Hires,
+    /// Lossy codecs, e.g. MP3, AAC or Opus
+    Lossy,
+}
+
+impl Quality {
+    pub fn matches(&self, track: &Track) -> bool {
+        match self {
+            Quality::Lossless => track.is_lossless,
+            Quality::Hires => track.is_hires(),
+            Quality::Lossy => !track.is_lossless,
+        }
+    }
+
+    /// Whether all the tracks of the album are of this quality
+    pub fn matches_album(&self, album: &Album) -> bool {
+        let summary = &album.quality;
+        let total = summary.lossless + summary.lossy;
+        total > 0
+            && match self {
+                Quality::Lossless => summary.lossless == total,
+                Quality::Hires => summary.hires == total,
+                Quality::Lossy => summary.lossy == total,
+            }
+    }
+}
+
+/// Qualities of the tracks of an album, for a badge like "Hi-Res 24/96"
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "ts", derive(ts_rs::TS), ts(export))]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct QualitySummary {
+    /// Number of lossless tracks, hi-res ones included
+    pub lossless: usize,
+    /// Number of hi-res tracks
+    pub hires: usize,
+    /// Number of lossy tracks
+    pub lossy: usize,
+    /// Highest sample rate of the tracks, in Hz
+    pub sample_rate: Option<u32>,
+    /// Highest bit depth of the lossless tracks
+    pub bit_depth: Option<u8>,
+}
+
+impl QualitySummary {
+    pub fn of<'a>(tracks: impl IntoIterator<Item = &'a Track>) -> Self {
+        let mut summary = QualitySummary::default();
+        for track in tracks {
+            summary.add(track);
+        }
+        summary
+    }
+
+    pub fn add(&mut self, track: &Track) {
+        if track.is_lossless {
+            self.lossless += 1;
+            self.bit_depth = self.bit_depth.max(track.bit_depth);
+        } else {
+            self.lossy += 1;
+        }
+        if track.is_hires() {
+            self.hires += 1;
+        }
+        self.sample_rate = self.sample_rate.max(track.sample_rate);
+    }
+}
+
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone)]
+#[cfg_attr(feature = "ts", derive(ts_rs::TS), ts(export))]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct Track {
+    pub title: String,
+    /// Whether `title` was read from the file name, the file having no title tag
+    #[serde(default)]
+    pub title_inferred: bool,
+    pub artists: Vec<String>,
+    pub track: u32,
+    /// Whether `track` was read from the file name, the file having no track number tag
+    #[serde(default)]
+    pub track_inferred: bool,
+    pub album: String,
+    /// Whether `album` is the name of the folder of the track, which has no album tag
+    #[serde(default)]
+    pub album_guessed: bool,
+    pub album_artists: Vec<String>,
+    pub album_id: String,
+    pub musicbrainz_album_id: Option<String>,
+    /// MusicBrainz recording id, the track keeps its id while tagged with it
+    #[serde(default)]
+    pub musicbrainz_recording_id: Option<String>,
+    pub cover_ext: String,
+    pub mime: String,
+    pub album_year: Option<u32>,
+    /// Date of the recording or release as tagged, e.g. `2011-03-14` or `2011`, from `TDRC`
+    /// or `DATE`
+    #[serde(default)]
+    pub release_date: Option<String>,
+    /// Date the music was first released, before a reissue or a remaster, from `TDOR`, `TORY`
+    /// or `ORIGINALDATE`
+    #[serde(default)]
+    pub original_date: Option<String>,
+    pub genre: Option<String>,
+    #[serde(default)]
+    pub composers: Vec<String>,
+    #[serde(default)]
+    pub conductor: Option<String>,
+    /// Work the track is a movement of, e.g. `Symphony No. 5 in C minor, Op. 67`
+    #[serde(default)]
+    pub work: Option<String>,
+    /// Name of the movement, e.g. `Allegro con brio`
+    #[serde(default)]
+    pub movement: Option<String>,
+    /// Number of the movement in the work
+    #[serde(default)]
+    pub movement_number: Option<u32>,
+    #[serde(default)]
+    pub credits: Vec<Credit>,
+    /// Parental advisory, from `ITUNESADVISORY`, `rtng` or `EXPLICIT`: `Some(false)` for the
+    /// clean versions, `None` when untagged
+    #[serde(default)]
+    pub explicit: Option<bool>,
+    pub lyrics: Vec<LyricLine>,
+    pub color: Option<Color>,
+    pub is_light: Option<bool>,
+    #[serde(default)]
+    pub palette: Option<Palette>,
+    pub file_path: String,
+    /// Url safe base64 encoding of `file_path`, still accepted where `id` is
+    pub path_base64: String,
+    /// Stable id of the track, derived from its path and content when first read and kept as
+    /// the file moves
+    #[serde(default)]
+    pub id: String,
+    /// In seconds
+    #[cfg_attr(feature = "ts", ts(type = "number"))]
+    pub duration: u64,
+    pub bitrate: u32,
+    /// Whether the codec is lossless, read again by the next scan for the tracks cached before
+    #[serde(default)]
+    pub is_lossless: bool,
+    /// In Hz
+    #[serde(default)]
+    pub sample_rate: Option<u32>,
+    /// Bits per sample, only known for lossless codecs
+    #[serde(default)]
+    pub bit_depth: Option<u8>,
+    #[serde(default)]
+    pub gapless: Option<Gapless>,
+    /// Tempo in beats per minute, from the `TBPM` or `BPM` tag, else found by the analysis of
+    /// the audio when `library.analyse_audio` is on
+    #[serde(default)]
+    pub bpm: Option<f32>,
+    /// Musical key, e.g. `Am` or `F#`, from the `TKEY` or `INITIALKEY` tag, else found by the
+    /// analysis of the audio
+    #[serde(default)]
+    pub key: Option<String>,
+    /// Silence at the start of the track to skip, in milliseconds, found by the analysis of the
+    /// audio. Silences shorter than a second are left, a silent track is skipped whole.
+    #[serde(default)]
+    #[cfg_attr(feature = "ts", ts(type = "number | null"))]
+    pub trim_start_ms: Option<u64>,
+    /// Silence at the end of the track to skip, in milliseconds, e.g. the padding before a
+    /// hidden track
+    #[serde(default)]
+    #[cfg_attr(feature = "ts", ts(type = "number | null"))]
+    pub trim_end_ms: Option<u64>,
+    #[cfg_attr(feature = "ts", ts(as = "crate::SystemTime"))]
+    #[cfg_attr(feature = "openapi", schema(value_type = SystemTime))]
+    #[serde(with = "crate::time")]
+    pub created_at: SystemTime,
+    #[serde(default)]
+    pub media_kind: MediaKind,
+    /// Audio file holding the track when it is a part of it, split by a cue sheet. `file_path`
+    /// is then `<source>#<track number>`.
+    #[serde(default)]
+    pub source: Option<String>,
+    /// Offset of the track in `source`, in milliseconds
+    #[serde(default)]
+    #[cfg_attr(feature = "ts", ts(type = "number | null"))]
+    pub start: Option<u64>,
+    /// End of the track in `source`, in milliseconds, `None` for the last one
+    #[serde(default)]
+    #[cfg_attr(feature = "ts", ts(type = "number | null"))]
+    pub end: Option<u64>,
+}
+
+impl Track {
+    /// Path of the file to read the audio from
+    pub fn audio_file(&self) -> &str {
+        self.source.as_deref().unwrap_or(&self.file_path)
+    }
+
+    /// Year the music was first released, falling back to the year of the release
+    pub fn year(&self) -> Option<u32> {
+        let year = |date: &String| date.get(..4).and_then(|x| x.parse().ok());
+        self.original_date
+            .as_ref()
+            .and_then(year)
+            .or(self.release_date.as_ref().and_then(year))
+            .or(self.album_year)
+    }
+
+    /// Tagged as explicit, the untagged tracks aren't
+    pub fn is_explicit(&self) -> bool {
+        self.explicit == Some(true)
+    }
+
+    /// Lossless above CD quality
+    pub fn is_hires(&self) -> bool {
+        self.is_lossless
+            && (self.bit_depth.is_some_and(|x| x > 16)
+                || self.sample_rate.is_some_and(|x| x > 48_000))
+    }
+}
+
+impl Default for Track {
+    fn default() -> Self {
+        Self {
+            title: "@UNKNOWN@".to_string(),
+            title_inferred: false,
+            artists: vec![],
+            track: 0,
+            track_inferred: false,
+            album: "@UNKNOWN@".to_string(),
+            album_guessed: false,
+            album_artists: vec![],
+            album_id: String::new(),
+            musicbrainz_album_id: None,
+            musicbrainz_recording_id: None,
+            album_year: None,
+            release_date: None,
+            original_date: None,
+            genre: None,
+            composers: vec![],
+            conductor: None,
+            work: None,
+            movement: None,
+            movement_number: None,
+            credits: vec![],
+            explicit: None,
+            lyrics: vec![],
+            cover_ext: ".png".to_string(),
+            mime: "audio/mp3".to_string(),
+            color: None,
+            is_light: None,
+            palette: None,
+            file_path: String::new(),
+            path_base64: String::new(),
+            id: String::new(),
+            bitrate: 0,
+            is_lossless: false,
+            sample_rate: None,
+            bit_depth: None,
+            gapless: None,
+            bpm: None,
+            key: None,
+            trim_start_ms: None,
+            trim_end_ms: None,
+            duration: 0,
+            created_at: SystemTime::UNIX_EPOCH,
+            media_kind: MediaKind::Music,
+            source: None,
+            start: None,
+            end: None,
+        }
+    }
+}
+
+#[derive(serde::Serialize, serde::Deserialize, Default, Debug, Clone)]
+#[cfg_attr(feature = "ts", derive(ts_rs::TS), ts(export))]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct Album {
+    pub name: String,
+    pub artists: Vec<String>,
+    #[cfg_attr(feature = "openapi", schema(value_type = Vec<String>))]
+    pub tracks: Vec<PathBuf>,
+    /// The year most of the tracks were first released, the earliest one on a tie
+    pub year: Option<u32>,
+    pub id: String,
+    /// Credits of all the tracks of the album
+    #[serde(default)]
+    pub credits: Vec<Credit>,
+    /// Palette of the first track having a cover
+    #[serde(default)]
+    pub palette: Option<Palette>,
+    /// Path of the cover on the daemon, e.g. `/cover/<id>.jpeg`, `None` without cover
+    #[serde(default)]
+    pub cover_url: Option<String>,
+    /// Audiobooks have their tracks in chapter order
+    #[serde(default)]
+    pub media_kind: MediaKind,
+    #[serde(default)]
+    pub quality: QualitySummary,
+    /// Whether the album groups the tracks of a folder, named after it, as they have no album
+    /// tag
+    #[serde(default)]
+    pub guessed: bool,
+    /// Whether one of the tracks is explicit
+    #[serde(default)]
+    pub explicit: bool,
+}
+
+impl Album {
+    pub fn remove_track(&mut self, path: PathBuf) {
+        self.tracks.retain(|x| *x != path);
+    }
+
+    pub fn add_credits(&mut self, credits: &[Credit]) {
+        for credit in credits {
+            if !self.credits.contains(credit) {
+                self.credits.push(credit.clone());
+            }
+        }
+    }
+}
+
+#[derive(serde::Serialize, serde::Deserialize, Default, Debug, Clone)]
+#[cfg_attr(feature = "ts", derive(ts_rs::TS), ts(export))]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct Playlist {
+    pub name: String,
+    #[cfg_attr(feature = "openapi", schema(value_type = Vec<String>))]
+    pub tracks: Vec<PathBuf>,
+    pub path: String,
+    pub id: String,
+    /// Set with `#PLAYLIST:` in M3U playlists and `<annotation>` in XSPF ones
+    #[serde(default)]
+    pub description: Option<String>,
+    /// `/playlist/<id>/cover` when an image named like the playlist sits next to it, e.g.
+    /// `Road trip.jpg`
+    #[serde(default)]
+    pub cover_url: Option<String>,
+}
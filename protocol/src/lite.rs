@@ -0,0 +1,75 @@
+//! Slim representations returned by the browse and search endpoints when called with
+//! `?lite=true`, for screen readers and low bandwidth clients.
+//!
+//! This schema is stable: fields are never renamed nor removed, new ones may be added.
+//! `cover_url` is relative to the daemon address, e.g. `/cover/<album id>.png`.
+
+use crate::api::LyricsMatch;
+
+#[derive(serde::Serialize, serde::Deserialize, Debug, Default)]
+#[cfg_attr(feature = "ts", derive(ts_rs::TS), ts(export))]
+#[cfg_attr(feature = "openapi", derive(utoipa::IntoParams), into_params(parameter_in = Query))]
+pub struct LiteQuery {
+    /// Answers with the lite form of the response
+    #[serde(default)]
+    pub lite: bool,
+}
+
+#[derive(serde::Serialize, serde::Deserialize, Debug)]
+#[cfg_attr(feature = "ts", derive(ts_rs::TS), ts(export))]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct LiteTrack {
+    pub id: String,
+    pub title: String,
+    pub artist: String,
+    /// In seconds
+    #[cfg_attr(feature = "ts", ts(type = "number"))]
+    pub duration: u64,
+    pub cover_url: String,
+}
+
+#[derive(serde::Serialize, serde::Deserialize, Debug)]
+#[cfg_attr(feature = "ts", derive(ts_rs::TS), ts(export))]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct LiteAlbum {
+    pub id: String,
+    pub title: String,
+    pub artist: String,
+    /// In seconds
+    #[cfg_attr(feature = "ts", ts(type = "number"))]
+    pub duration: u64,
+    pub cover_url: String,
+}
+
+/// An album with its tracks, in order
+#[derive(serde::Serialize, serde::Deserialize, Debug)]
+#[cfg_attr(feature = "ts", derive(ts_rs::TS), ts(export))]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct LiteAlbumTracks {
+    #[serde(flatten)]
+    pub album: LiteAlbum,
+    pub tracks: Vec<LiteTrack>,
+}
+
+#[derive(serde::Serialize, serde::Deserialize, Debug)]
+#[cfg_attr(feature = "ts", derive(ts_rs::TS), ts(export))]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct LitePlaylist {
+    pub id: String,
+    pub title: String,
+    /// In seconds
+    #[cfg_attr(feature = "ts", ts(type = "number"))]
+    pub duration: u64,
+}
+
+#[derive(serde::Serialize, serde::Deserialize, Debug)]
+#[cfg_attr(feature = "ts", derive(ts_rs::TS), ts(export))]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct LiteResults {
+    pub albums: Vec<LiteAlbum>,
+    pub playlists: Vec<LitePlaylist>,
+    pub tracks: Vec<LiteTrack>,
+    /// Lines holding the phrase of a `scope=lyrics` search, empty otherwise
+    #[serde(default)]
+    pub lyrics: Vec<LyricsMatch>,
+}
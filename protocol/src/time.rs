@@ -0,0 +1,25 @@
+//! `SystemTime` fields, as serde writes them in human readable formats like JSON, see
+//! [`crate::SystemTime`], and as milliseconds since the epoch in binary ones like MessagePack
+//! and CBOR. Used with `#[serde(with = "crate::time")]`.
+
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::time::{Duration, SystemTime};
+
+pub fn serialize<S: Serializer>(time: &SystemTime, serializer: S) -> Result<S::Ok, S::Error> {
+    if serializer.is_human_readable() {
+        return time.serialize(serializer);
+    }
+    let millis = time
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis() as u64;
+    millis.serialize(serializer)
+}
+
+pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<SystemTime, D::Error> {
+    if deserializer.is_human_readable() {
+        return SystemTime::deserialize(deserializer);
+    }
+    let millis = u64::deserialize(deserializer)?;
+    Ok(SystemTime::UNIX_EPOCH + Duration::from_millis(millis))
+}
@@ -1,4 +1,5 @@
 use clap::{Parser, Subcommand};
+use std::path::PathBuf;
 
 #[derive(Debug, Parser)]
 #[clap(author, version, about)]
@@ -10,4 +11,20 @@ pub struct LorArgs {
 pub enum LorSubcommand {
     /// Start the Lorchestre daemon
     Daemon,
+    /// Import play counts, favorites and playlists from another music server
+    Import {
+        #[clap(value_enum)]
+        source: crate::daemon::import::Source,
+        /// The database or export file to read
+        path: PathBuf,
+        /// Json object mapping path prefixes of the other server to local ones
+        #[clap(long)]
+        remap: Option<PathBuf>,
+        /// Only import the data of this user
+        #[clap(long)]
+        user: Option<String>,
+        /// Jellyfin playlists directory (`data/playlists`)
+        #[clap(long)]
+        playlists: Option<PathBuf>,
+    },
 }
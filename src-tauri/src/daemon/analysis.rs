@@ -0,0 +1,496 @@
+//! Analysis of the audio of the tracks, for the tempo and key their tags don't give and the
+//! silences at their ends. Each track is decoded by ffmpeg, mono at a low rate. The tempo is the
+//! beat period the onsets of the notes repeat at the most, the key the one whose
+//! Krumhansl-Kessler profile is the most like the notes heard, both from a part of the track.
+//! The silences are the blocks under -60 dBFS at its start and end. Results are kept by track id in `analysis.json`, so that tracks read
+//! again by a scan aren't decoded again.
+
+use crate::daemon::global::Media;
+use crate::daemon::jobs::JobContext;
+use crate::daemon::libraries::Library;
+use crate::daemon::utils as cache;
+use mu_protocol::library::{MediaKind, Track};
+use socketioxide::SocketIo;
+use std::collections::HashMap;
+use std::f32::consts::PI;
+use std::fmt;
+use std::io::{Read, Write};
+use std::path::PathBuf;
+use std::process::Stdio;
+use tokio::io::AsyncReadExt;
+use tokio::process::Command;
+use tracing::{info, warn};
+
+/// Sample rate the audio is decoded at, in Hz
+const RATE: u32 = 11025;
+/// Seconds skipped at the start of the tracks, quieter and looser than the rest, or a quarter
+/// of the shorter ones
+const SKIP: u64 = 30;
+/// Seconds analysed for the tempo and key
+const LENGTH: u64 = 90;
+/// Samples of a block the silences are found with, about 10 ms
+const BLOCK: usize = RATE as usize / 100;
+/// Mean square under which a block is silent, -60 dBFS
+const SILENCE: f32 = 1e-6;
+/// Shortest silence trimmed, in milliseconds, not to cut into the short pauses of gapless
+/// albums
+const MIN_SILENCE: u64 = 1000;
+/// Samples of a step of the energy envelope, about 12 ms
+const HOP: usize = 128;
+/// Samples the energy is measured over at each step, long enough to smooth out the beating of
+/// the notes held
+const WINDOW: usize = 4 * HOP;
+const MIN_BPM: f32 = 60.0;
+const MAX_BPM: f32 = 200.0;
+/// Tempo the guesses lean towards, between a tempo and its double or half
+const USUAL_BPM: f32 = 120.0;
+/// Samples of a frame of the chromagram, for a resolution of 1.3 Hz, finer than a semitone down
+/// to the lowest note
+const FRAME: usize = 8192;
+/// MIDI notes listened to, C2 to B6
+const NOTES: std::ops::Range<u8> = 36..96;
+
+const MAJOR: [f32; 12] = [
+    6.35, 2.23, 3.48, 2.33, 4.38, 4.09, 2.52, 5.19, 2.39, 3.66, 2.29, 2.88,
+];
+const MINOR: [f32; 12] = [
+    6.33, 2.68, 3.52, 5.38, 2.60, 3.53, 2.54, 4.75, 3.98, 2.69, 3.34, 3.17,
+];
+const PITCHES: [&str; 12] = [
+    "C", "C#", "D", "D#", "E", "F", "F#", "G", "G#", "A", "A#", "B",
+];
+
+pub fn enabled(config: &lorconf::Config) -> bool {
+    config
+        .library
+        .as_ref()
+        .and_then(|library| library.analyse_audio)
+        .unwrap_or(false)
+}
+
+/// A musical key, written with sharps and a trailing `m` when minor, e.g. `F#m`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Key {
+    /// Pitch class of the tonic, 0 for C
+    pitch: u8,
+    minor: bool,
+}
+
+impl Key {
+    /// Reads the usual ways keys are tagged: `Am`, `A minor`, `Bbmin`, `C#`, `E major`, and
+    /// the Camelot notation, `8A`
+    pub fn parse(text: &str) -> Option<Key> {
+        let text = text.trim();
+        if let Some(number) = text
+            .get(..text.len().saturating_sub(1))
+            .and_then(|x| x.parse::<u8>().ok())
+            .filter(|x| (1..=12).contains(x))
+        {
+            let major = 7 * ((number + 4) % 12) % 12;
+            return match text.chars().last()?.to_ascii_uppercase() {
+                'A' => Some(Key {
+                    pitch: (major + 9) % 12,
+                    minor: true,
+                }),
+                'B' => Some(Key {
+                    pitch: major,
+                    minor: false,
+                }),
+                _ => None,
+            };
+        }
+
+        let mut chars = text.chars();
+        let mut pitch: i8 = match chars.next()?.to_ascii_uppercase() {
+            'C' => 0,
+            'D' => 2,
+            'E' => 4,
+            'F' => 5,
+            'G' => 7,
+            'A' => 9,
+            'B' => 11,
+            _ => return None,
+        };
+        let mut rest = chars.as_str();
+        if let Some(x) = rest.strip_prefix(['#', '♯']) {
+            pitch += 1;
+            rest = x;
+        } else if let Some(x) = rest.strip_prefix(['b', '♭']) {
+            pitch -= 1;
+            rest = x;
+        }
+        let minor = match rest.trim().to_lowercase().as_str() {
+            "" | "maj" | "major" => false,
+            "m" | "min" | "minor" => true,
+            _ => return None,
+        };
+
+        Some(Key {
+            pitch: pitch.rem_euclid(12) as u8,
+            minor,
+        })
+    }
+
+    /// Position on the Camelot wheel: the hour, and whether it's on the inner, minor, circle
+    pub fn camelot(&self) -> (u8, bool) {
+        // Keys a fifth apart are an hour apart, C major is at 8B and A minor, sharing its
+        // notes, at 8A
+        let major = if self.minor {
+            (self.pitch + 3) % 12
+        } else {
+            self.pitch
+        };
+        match (7 * major + 8) % 12 {
+            0 => (12, self.minor),
+            hour => (hour, self.minor),
+        }
+    }
+}
+
+impl fmt::Display for Key {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mode = if self.minor { "m" } else { "" };
+        write!(f, "{}{mode}", PITCHES[self.pitch as usize])
+    }
+}
+
+/// Tempo of mono `samples` at [`RATE`], `None` for silence or audio without beat
+pub fn tempo(samples: &[f32]) -> Option<f32> {
+    let energies: Vec<f32> = samples
+        .windows(WINDOW)
+        .step_by(HOP)
+        .map(|x| {
+            (x.iter().map(|s| s * s).sum::<f32>() / WINDOW as f32)
+                .max(1e-9)
+                .ln()
+        })
+        .collect();
+    // Rises of the energy, where notes start
+    let mut onsets: Vec<f32> = energies
+        .windows(2)
+        .map(|x| (x[1] - x[0]).max(0.0))
+        .collect();
+    let mean = onsets.iter().sum::<f32>() / onsets.len().max(1) as f32;
+    if mean <= f32::EPSILON {
+        return None;
+    }
+    onsets.iter_mut().for_each(|x| *x -= mean);
+
+    let steps = RATE as f32 / HOP as f32;
+    // Lags of the slowest tempo and of its multiples
+    let max_lag = (4.0 * 60.0 * steps / MIN_BPM).ceil() as usize + 1;
+    if onsets.len() < 2 * max_lag {
+        return None;
+    }
+    let correlation: Vec<f32> = (0..=max_lag)
+        .map(|lag| {
+            let n = onsets.len() - lag;
+            onsets[..n]
+                .iter()
+                .zip(&onsets[lag..])
+                .map(|(a, b)| a * b)
+                .sum::<f32>()
+                / n as f32
+        })
+        .collect();
+    if correlation[0] <= f32::EPSILON {
+        return None;
+    }
+    let at = |lag: f32| {
+        let i = lag as usize;
+        let t = lag - i as f32;
+        correlation[i] * (1.0 - t) + correlation[i + 1] * t
+    };
+
+    // Candidates a tenth of a beat per minute apart, scored on the beats and bars after them
+    let mut best: Option<(f32, f32)> = None;
+    for tenths in (MIN_BPM * 10.0) as u32..=(MAX_BPM * 10.0) as u32 {
+        let bpm = tenths as f32 / 10.0;
+        let lag = 60.0 * steps / bpm;
+        let score: f32 = (1..=4).map(|m| at(m as f32 * lag)).sum::<f32>() / correlation[0];
+        let octaves = (bpm / USUAL_BPM).log2();
+        let score = score * (-0.5 * octaves * octaves / 0.64).exp();
+        if best.is_none_or(|(x, _)| score > x) {
+            best = Some((score, bpm));
+        }
+    }
+    best.filter(|(score, _)| *score > 0.0).map(|(_, bpm)| bpm)
+}
+
+/// Key of mono `samples` at [`RATE`], `None` for silence
+pub fn key(samples: &[f32]) -> Option<Key> {
+    let window: Vec<f32> = (0..FRAME)
+        .map(|i| 0.5 - 0.5 * (2.0 * PI * i as f32 / FRAME as f32).cos())
+        .collect();
+    let coefficients: Vec<(u8, f32)> = NOTES
+        .map(|note| {
+            let frequency = 440.0 * 2f32.powf((note as f32 - 69.0) / 12.0);
+            (note % 12, 2.0 * (2.0 * PI * frequency / RATE as f32).cos())
+        })
+        .collect();
+
+    // Loudness of each pitch class, with the Goertzel algorithm as only a few bins matter
+    let mut chroma = [0f32; 12];
+    let mut frame = vec![0f32; FRAME];
+    for chunk in samples.chunks_exact(FRAME) {
+        for (x, (sample, weight)) in frame.iter_mut().zip(chunk.iter().zip(&window)) {
+            *x = sample * weight;
+        }
+        for (pitch, coefficient) in &coefficients {
+            let (mut s1, mut s2) = (0f32, 0f32);
+            for x in &frame {
+                let s = x + coefficient * s1 - s2;
+                s2 = s1;
+                s1 = s;
+            }
+            let power = s1 * s1 + s2 * s2 - coefficient * s1 * s2;
+            chroma[*pitch as usize] += power.max(0.0).sqrt();
+        }
+    }
+    if chroma.iter().sum::<f32>() <= f32::EPSILON {
+        return None;
+    }
+
+    let mut best: Option<(f32, Key)> = None;
+    for pitch in 0..12 {
+        for (profile, minor) in [(&MAJOR, false), (&MINOR, true)] {
+            let rotated: Vec<f32> = (0..12).map(|i| profile[(i + 12 - pitch) % 12]).collect();
+            let score = pearson(&chroma, &rotated);
+            if best.is_none_or(|(x, _)| score > x) {
+                let pitch = pitch as u8;
+                best = Some((score, Key { pitch, minor }));
+            }
+        }
+    }
+    best.map(|(_, key)| key)
+}
+
+fn pearson(a: &[f32], b: &[f32]) -> f32 {
+    let n = a.len() as f32;
+    let (mean_a, mean_b) = (a.iter().sum::<f32>() / n, b.iter().sum::<f32>() / n);
+    let (mut covariance, mut var_a, mut var_b) = (0.0, 0.0, 0.0);
+    for (x, y) in a.iter().zip(b) {
+        covariance += (x - mean_a) * (y - mean_b);
+        var_a += (x - mean_a) * (x - mean_a);
+        var_b += (y - mean_b) * (y - mean_b);
+    }
+    covariance / (var_a * var_b).sqrt().max(f32::EPSILON)
+}
+
+/// Mean square of a block, about 10 ms, of the audio decoded
+struct Levels(Vec<f32>);
+
+impl Levels {
+    /// Silences at the start and at the end, in milliseconds. A silence shorter than
+    /// [`MIN_SILENCE`] is left at 0, audio silent throughout is silent from its start to its end.
+    fn silences(&self) -> Option<(u64, u64)> {
+        let ms = |blocks: usize| (blocks * BLOCK) as u64 * 1000 / RATE as u64;
+        if self.0.is_empty() {
+            return None;
+        }
+        let Some(first) = self.0.iter().position(|x| *x > SILENCE) else {
+            return Some((ms(self.0.len()), 0));
+        };
+        let last = self.0.iter().rposition(|x| *x > SILENCE).unwrap_or(first);
+        let gap = |blocks: usize| Some(ms(blocks)).filter(|x| *x >= MIN_SILENCE).unwrap_or(0);
+
+        Some((gap(first), gap(self.0.len() - last - 1)))
+    }
+}
+
+/// Decodes `track` mono at [`RATE`], returning the part analysed for the tempo and key and the
+/// levels of the whole track
+async fn decode(track: &Track) -> std::io::Result<(Vec<f32>, Levels)> {
+    let skip = SKIP.min(track.duration / 4);
+    let part = (skip * RATE as u64) as usize..((skip + LENGTH) * RATE as u64) as usize;
+    let start = track.start.unwrap_or(0);
+    let mut command = Command::new("ffmpeg");
+    command
+        .args(["-nostdin", "-loglevel", "error"])
+        .args(["-ss", &format!("{:.3}", start as f64 / 1000.0), "-i"])
+        .arg(track.audio_file());
+    if let Some(end) = track.end {
+        let length = end.saturating_sub(start) as f64 / 1000.0;
+        command.args(["-t", &format!("{length:.3}")]);
+    }
+    let mut child = command
+        .args(["-vn", "-map", "0:a:0", "-ac", "1", "-ar", &RATE.to_string()])
+        .args(["-f", "f32le", "-"])
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .kill_on_drop(true)
+        .spawn()?;
+
+    // Read as it comes, the whole track isn't kept
+    let mut stdout = child.stdout.take().unwrap();
+    let (mut samples, mut levels) = (vec![], vec![]);
+    let (mut index, mut block) = (0, 0f32);
+    let mut buf = vec![0u8; 64 * 1024];
+    let mut pending = 0;
+    loop {
+        let read = stdout.read(&mut buf[pending..]).await?;
+        if read == 0 {
+            break;
+        }
+        let available = pending + read;
+        let whole = available - available % 4;
+        for x in buf[..whole].chunks_exact(4) {
+            let sample = f32::from_le_bytes([x[0], x[1], x[2], x[3]]);
+            if part.contains(&index) {
+                samples.push(sample);
+            }
+            block += sample * sample;
+            index += 1;
+            if index % BLOCK == 0 {
+                levels.push(block / BLOCK as f32);
+                block = 0.0;
+            }
+        }
+        buf.copy_within(whole..available, 0);
+        pending = available - whole;
+    }
+    let status = child.wait().await?;
+    if !status.success() {
+        return Err(std::io::Error::other(format!(
+            "ffmpeg exited with {status}"
+        )));
+    }
+
+    Ok((samples, Levels(levels)))
+}
+
+#[derive(serde::Serialize, serde::Deserialize, Debug, Default, Clone)]
+struct Analysis {
+    bpm: Option<f32>,
+    key: Option<String>,
+    #[serde(default)]
+    trim_start_ms: Option<u64>,
+    #[serde(default)]
+    trim_end_ms: Option<u64>,
+}
+
+/// Analyses done, by track id. Tracks without beat or which can't be decoded are kept too, so
+/// they aren't decoded again after each scan.
+#[derive(Debug, Default)]
+struct AnalysisCache {
+    path: PathBuf,
+    analyses: HashMap<String, Analysis>,
+}
+
+impl AnalysisCache {
+    fn load(path: PathBuf) -> Self {
+        let mut analyses = HashMap::new();
+        if path.exists() {
+            let mut buf = String::new();
+            if let Ok(mut f) = std::fs::File::open(&path) {
+                let _ = f.read_to_string(&mut buf);
+            }
+            match serde_json::from_str(&buf) {
+                Ok(parsed) => analyses = parsed,
+                Err(e) => warn!("Unable to read analyses `{}`: {e}", path.display()),
+            }
+        }
+
+        Self { path, analyses }
+    }
+
+    fn save(&self) {
+        let data = serde_json::to_string(&self.analyses).unwrap();
+        match std::fs::File::create(&self.path) {
+            Ok(mut f) => {
+                let _ = f.write_all(data.as_bytes());
+            }
+            Err(e) => warn!("Unable to save analyses `{}`: {e}", self.path.display()),
+        }
+    }
+}
+
+/// Tracks of `media` not analysed yet or missing their tempo or key, audiobooks and podcasts
+/// aside
+fn pending(media: &Media) -> Vec<Track> {
+    media
+        .tracks
+        .values()
+        .filter(|x| {
+            x.media_kind == MediaKind::Music
+                && (x.bpm.is_none() || x.key.is_none() || x.trim_start_ms.is_none())
+        })
+        .cloned()
+        .collect()
+}
+
+/// Fills in the tempo, key and silences of the tracks of `library` missing them, as a job.
+/// Fails when ffmpeg can't be run.
+pub async fn run(library: &Library, io: &SocketIo, context: JobContext) -> Result<(), String> {
+    let mut analyses = AnalysisCache::load(library.cache_dir.join("analysis.json"));
+    let tracks = pending(&library.media.load());
+    let mut found = HashMap::new();
+    let mut failure = None;
+
+    for (i, track) in tracks.iter().enumerate() {
+        if context.cancel.is_cancelled() {
+            break;
+        }
+        context.progress(i, tracks.len());
+        let analysis = match analyses.analyses.get(&track.id) {
+            Some(analysis) => analysis.clone(),
+            None => {
+                let (samples, levels) = match decode(track).await {
+                    Ok(decoded) => decoded,
+                    Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                        failure = Some(format!("unable to run ffmpeg: {e}"));
+                        break;
+                    }
+                    Err(e) => {
+                        warn!("analysis: unable to decode `{}`: {e}", track.file_path);
+                        (vec![], Levels(vec![]))
+                    }
+                };
+                let silences = levels.silences();
+                let analysis = tokio::task::spawn_blocking(move || Analysis {
+                    bpm: tempo(&samples),
+                    key: key(&samples).map(|x| x.to_string()),
+                    trim_start_ms: silences.map(|(start, _)| start),
+                    trim_end_ms: silences.map(|(_, end)| end),
+                })
+                .await
+                .map_err(|e| e.to_string())?;
+                analyses.analyses.insert(track.id.clone(), analysis.clone());
+                analysis
+            }
+        };
+        found.insert(PathBuf::from(&track.file_path), analysis);
+    }
+    analyses.save();
+
+    let mut media = library.media.write().await;
+    let mut analysed = 0;
+    // The library may have been rescanned in the meantime
+    for (path, analysis) in found {
+        let Some(track) = media.tracks.get_mut(&path) else {
+            continue;
+        };
+        if track.bpm.is_none() && analysis.bpm.is_some()
+            || track.key.is_none() && analysis.key.is_some()
+            || track.trim_start_ms.is_none() && analysis.trim_start_ms.is_some()
+        {
+            track.bpm = track.bpm.or(analysis.bpm);
+            track.key = track.key.take().or(analysis.key);
+            track.trim_start_ms = analysis.trim_start_ms;
+            track.trim_end_ms = analysis.trim_end_ms;
+            analysed += 1;
+        }
+    }
+    let media = media.commit();
+    if analysed > 0 {
+        info!("analysis: {analysed} tracks of {} analysed", library.name);
+        library.publish(io, &media);
+        cache::save_cache(&library.cache_dir, &media);
+    }
+
+    match failure {
+        Some(failure) => Err(failure),
+        None => Ok(()),
+    }
+}
@@ -0,0 +1,91 @@
+use async_zip::tokio::write::ZipFileWriter;
+use async_zip::{Compression, ZipEntryBuilder};
+use axum::body::Body;
+use std::collections::HashSet;
+use std::path::PathBuf;
+use tokio::io::DuplexStream;
+use tokio_util::compat::TokioAsyncReadCompatExt;
+use tokio_util::io::ReaderStream;
+use tracing::warn;
+
+/// Size of the in-memory pipe between the zip writer and the response body
+const PIPE_CAPACITY: usize = 64 * 1024;
+
+/// Content of an entry of an archive
+pub enum Source {
+    File(PathBuf),
+    Data(Vec<u8>),
+}
+
+/// Streams a zip archive of `files`. Entries are stored as-is (audio is already compressed) and
+/// written one chunk at a time, so memory usage doesn't depend on the archive size.
+/// When `numbered` is set, entries are prefixed by their position to keep the order.
+pub fn zip_body(files: Vec<PathBuf>, numbered: bool) -> Body {
+    let mut names = HashSet::new();
+    let mut entries = vec![];
+    for (i, file) in files.into_iter().enumerate() {
+        let Some(file_name) = file.file_name() else {
+            continue;
+        };
+        let file_name = file_name.to_string_lossy();
+        let mut name = if numbered {
+            format!("{:02} - {file_name}", i + 1)
+        } else {
+            file_name.to_string()
+        };
+        let mut n = 1;
+        while !names.insert(name.clone()) {
+            name = format!("{n} - {file_name}");
+            n += 1;
+        }
+        entries.push((name, Source::File(file)));
+    }
+
+    entries_body(entries)
+}
+
+/// Streams a zip archive of `entries`, by name, like [`zip_body`]
+pub fn entries_body(entries: Vec<(String, Source)>) -> Body {
+    let (writer, reader) = tokio::io::duplex(PIPE_CAPACITY);
+    tokio::spawn(async move {
+        if let Err(e) = write_zip(writer, entries).await {
+            warn!("zip: archive generation aborted: {e}");
+        }
+    });
+
+    Body::from_stream(ReaderStream::new(reader))
+}
+
+async fn write_zip(
+    writer: DuplexStream,
+    entries: Vec<(String, Source)>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let mut zip = ZipFileWriter::with_tokio(writer);
+
+    for (name, source) in entries {
+        let builder = ZipEntryBuilder::new(name.into(), Compression::Stored);
+        match source {
+            Source::File(file) => {
+                let source = match tokio::fs::File::open(&file).await {
+                    Ok(source) => source,
+                    Err(e) => {
+                        warn!("zip: skipping `{}`: {e}", file.display());
+                        continue;
+                    }
+                };
+                let mut entry = zip.write_entry_stream(builder).await?;
+                futures::io::copy(&mut source.compat(), &mut entry).await?;
+                entry.close().await?;
+            }
+            Source::Data(data) => zip.write_entry_whole(builder, &data).await?,
+        }
+    }
+
+    zip.close().await?;
+    Ok(())
+}
+
+/// `Content-Disposition` value for an archive called `name`
+pub fn attachment(name: &str) -> String {
+    crate::daemon::utils::content_disposition("attachment", &format!("{name}.zip"))
+}
@@ -0,0 +1,79 @@
+use crate::daemon::global::{normalize, Media};
+use mu_protocol::api::{AlbumSort, Artist};
+use mu_protocol::library::Album;
+use std::cmp::Ordering;
+use std::collections::HashMap;
+
+/// `library.artist_album_sort`, chronological unless set to `name`
+pub fn album_sort(config: &lorconf::Config) -> AlbumSort {
+    match config
+        .library
+        .as_ref()
+        .and_then(|library| library.artist_album_sort.as_deref())
+    {
+        Some("name") => AlbumSort::Name,
+        _ => AlbumSort::Year,
+    }
+}
+
+pub fn artist_id(name: &str) -> String {
+    format!("{:x}", md5::compute(normalize(name)))
+}
+
+/// Every album artist of the library, alphabetically
+pub fn artists(media: &Media) -> Vec<Artist> {
+    let mut artists: HashMap<String, Artist> = HashMap::new();
+    for name in media.albums.iter().flat_map(|x| x.artists.iter()) {
+        artists
+            .entry(artist_id(name))
+            .or_insert_with_key(|id| Artist {
+                id: id.clone(),
+                name: name.clone(),
+                albums: 0,
+            })
+            .albums += 1;
+    }
+
+    let mut artists: Vec<Artist> = artists.into_values().collect();
+    artists.sort_by_cached_key(|x| normalize(&x.name));
+    artists
+}
+
+/// Name of the album artist with the id `id`, as first found in the library
+pub fn artist_name(media: &Media, id: &str) -> Option<String> {
+    media
+        .albums
+        .iter()
+        .flat_map(|x| x.artists.iter())
+        .find(|x| artist_id(x) == id)
+        .cloned()
+}
+
+fn compare(a: &Album, b: &Album, sort: AlbumSort) -> Ordering {
+    let by_name = || normalize(&a.name).cmp(&normalize(&b.name));
+    match sort {
+        AlbumSort::Year => match (a.year, b.year) {
+            (Some(x), Some(y)) => x.cmp(&y).then_with(by_name),
+            (Some(_), None) => Ordering::Less,
+            (None, Some(_)) => Ordering::Greater,
+            (None, None) => by_name(),
+        },
+        AlbumSort::Name => by_name().then(a.year.cmp(&b.year)),
+    }
+}
+
+/// Albums of the artist with the id `id`, `None` if there is no such artist
+pub fn artist_albums(media: &Media, id: &str, sort: AlbumSort) -> Option<Vec<Album>> {
+    let mut albums: Vec<Album> = media
+        .albums
+        .iter()
+        .filter(|album| album.artists.iter().any(|x| artist_id(x) == id))
+        .cloned()
+        .collect();
+    if albums.is_empty() {
+        return None;
+    }
+
+    albums.sort_by(|a, b| compare(a, b, sort));
+    Some(albums)
+}
@@ -0,0 +1,462 @@
+//! Artist portraits and the covers of albums without one, fetched from fanart.tv, the Cover
+//! Art Archive and Deezer when `library.external_artwork` is on. Portraits are stored in
+//! `covers/artists/`, album covers with the others. A miss is remembered for a while with a
+//! `.missing` file so the providers aren't asked again on each request.
+
+use crate::daemon::config::{SharedConfig, VERSION};
+use crate::daemon::covers;
+use crate::daemon::global::normalize;
+use crate::daemon::libraries::Library;
+use crate::daemon::utils;
+use socketioxide::SocketIo;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime};
+use tokio::sync::Mutex;
+use tracing::{info, warn};
+
+/// Folder of the portraits, in the covers folder of the library
+pub const ARTISTS_DIR: &str = "artists";
+
+/// Extension of the files remembering that no provider had an image
+pub const MISSING: &str = ".missing";
+
+/// How long a miss is remembered
+const RETRY_AFTER: Duration = Duration::from_secs(7 * 24 * 3600);
+
+/// Time between two requests to the providers, MusicBrainz allows one per second
+const REQUEST_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Longest wait for a provider
+const TIMEOUT: Duration = Duration::from_secs(15);
+
+/// Time before the first look for albums without cover, then between two
+const FIRST_PASS: Duration = Duration::from_secs(60);
+const PASS_INTERVAL: Duration = Duration::from_secs(3600);
+
+const MUSICBRAINZ: &str = "https://musicbrainz.org/ws/2/artist";
+const FANART: &str = "https://webservice.fanart.tv/v3/music";
+const COVER_ART_ARCHIVE: &str = "https://coverartarchive.org/release";
+const DEEZER: &str = "https://api.deezer.com/search";
+
+/// Lowest MusicBrainz score of an artist taken as the one searched for
+const MIN_SCORE: u32 = 90;
+
+const UNKNOWN: &str = "@UNKNOWN@";
+
+/// What the fetches need from the `[library]` section of the configuration
+#[derive(Debug, Clone)]
+pub struct Settings {
+    pub enabled: bool,
+    pub fanart_key: Option<String>,
+}
+
+impl Settings {
+    pub fn from_config(config: &lorconf::Config) -> Self {
+        let library = config.library.as_ref();
+        Self {
+            enabled: library
+                .and_then(|library| library.external_artwork)
+                .or(lorconf::Library::default().external_artwork)
+                .unwrap_or_default(),
+            fanart_key: library
+                .and_then(|library| library.fanart_api_key.clone())
+                .filter(|x| !x.is_empty()),
+        }
+    }
+}
+
+#[derive(serde::Deserialize)]
+struct MusicBrainzArtists {
+    artists: Vec<MusicBrainzArtist>,
+}
+
+#[derive(serde::Deserialize)]
+struct MusicBrainzArtist {
+    id: String,
+    score: u32,
+}
+
+#[derive(serde::Deserialize)]
+struct FanartArtist {
+    #[serde(default)]
+    artistthumb: Vec<FanartImage>,
+}
+
+#[derive(serde::Deserialize)]
+struct FanartImage {
+    url: String,
+}
+
+#[derive(serde::Deserialize)]
+struct DeezerResults<T> {
+    #[serde(default = "Vec::new")]
+    data: Vec<T>,
+}
+
+#[derive(serde::Deserialize)]
+struct DeezerArtist {
+    name: String,
+    picture_xl: Option<String>,
+}
+
+#[derive(serde::Deserialize)]
+struct DeezerAlbum {
+    title: String,
+    cover_xl: Option<String>,
+    artist: DeezerAlbumArtist,
+}
+
+#[derive(serde::Deserialize)]
+struct DeezerAlbumArtist {
+    name: String,
+}
+
+/// An image and the extension it is stored with
+type Image = (Vec<u8>, &'static str);
+
+/// The stored image named `stem` in `dir`
+fn stored(dir: &Path, stem: &str) -> Option<PathBuf> {
+    [".jpg", ".png", ".webp"]
+        .iter()
+        .map(|ext| dir.join(format!("{stem}{ext}")))
+        .find(|x| x.exists())
+}
+
+/// Whether no provider had the image named `stem` not long ago
+fn missed(dir: &Path, stem: &str) -> bool {
+    std::fs::metadata(dir.join(format!("{stem}{MISSING}")))
+        .and_then(|x| x.modified())
+        .is_ok_and(|x| SystemTime::now().duration_since(x).unwrap_or_default() < RETRY_AFTER)
+}
+
+/// Stores `image` as the one named `stem`, or remembers the miss without it
+fn keep(dir: &Path, stem: &str, image: Option<Image>) -> Option<PathBuf> {
+    let write = |path: &Path, data: &[u8]| {
+        let written = std::fs::create_dir_all(dir).and_then(|_| std::fs::write(path, data));
+        if let Err(e) = written {
+            warn!("artwork: unable to write `{}`: {e}", path.display());
+        }
+    };
+    match image {
+        Some((data, ext)) => {
+            let path = dir.join(format!("{stem}{ext}"));
+            write(&path, &data);
+            let _ = std::fs::remove_file(dir.join(format!("{stem}{MISSING}")));
+            path.exists().then_some(path)
+        }
+        None => {
+            write(&dir.join(format!("{stem}{MISSING}")), &[]);
+            None
+        }
+    }
+}
+
+/// Deezer points to an image without hash for the artists and albums it has no picture of
+fn is_placeholder(url: &str) -> bool {
+    url.split_once("://").is_some_and(|(_, x)| x.contains("//"))
+}
+
+/// Client of the providers, making one request at a time
+#[derive(Debug)]
+pub struct Artwork {
+    client: reqwest::Client,
+    /// When the last request was made, locked for the whole of a fetch
+    last: Mutex<Option<Instant>>,
+}
+
+impl Default for Artwork {
+    fn default() -> Self {
+        Self {
+            client: reqwest::Client::builder()
+                .user_agent(format!(
+                    "lorchestre/{VERSION} ( https://github.com/luxluth/mu )"
+                ))
+                .timeout(TIMEOUT)
+                .build()
+                .unwrap_or_default(),
+            last: Mutex::new(None),
+        }
+    }
+}
+
+impl Artwork {
+    /// Body of `url`, `None` when the provider doesn't know what is asked
+    async fn get(
+        &self,
+        last: &mut Option<Instant>,
+        url: &str,
+        query: &[(&str, &str)],
+    ) -> Result<Option<Vec<u8>>, String> {
+        if let Some(wait) = last.and_then(|x| REQUEST_INTERVAL.checked_sub(x.elapsed())) {
+            tokio::time::sleep(wait).await;
+        }
+        *last = Some(Instant::now());
+
+        let response = self
+            .client
+            .get(url)
+            .query(query)
+            .send()
+            .await
+            .map_err(|e| e.to_string())?;
+        if response.status() == reqwest::StatusCode::NOT_FOUND {
+            return Ok(None);
+        }
+        let body = response
+            .error_for_status()
+            .map_err(|e| e.to_string())?
+            .bytes()
+            .await
+            .map_err(|e| e.to_string())?;
+        Ok(Some(body.to_vec()))
+    }
+
+    async fn json<T: serde::de::DeserializeOwned>(
+        &self,
+        last: &mut Option<Instant>,
+        url: &str,
+        query: &[(&str, &str)],
+    ) -> Result<Option<T>, String> {
+        match self.get(last, url, query).await? {
+            Some(body) => serde_json::from_slice(&body).map_err(|e| e.to_string()),
+            None => Ok(None),
+        }
+    }
+
+    /// The image at `url`, unless it isn't one the covers can be stored as
+    async fn image(&self, last: &mut Option<Instant>, url: &str) -> Result<Option<Image>, String> {
+        let Some(data) = self.get(last, url, &[]).await? else {
+            return Ok(None);
+        };
+        let ext = match image::guess_format(&data) {
+            Ok(image::ImageFormat::Jpeg) => ".jpg",
+            Ok(image::ImageFormat::Png) => ".png",
+            Ok(image::ImageFormat::WebP) => ".webp",
+            _ => return Ok(None),
+        };
+        Ok(Some((data, ext)))
+    }
+
+    /// The fanart.tv thumbnail of the MusicBrainz artist named `name`
+    async fn fanart(
+        &self,
+        last: &mut Option<Instant>,
+        name: &str,
+        key: &str,
+    ) -> Result<Option<Image>, String> {
+        let query = format!(
+            "artist:\"{}\"",
+            name.replace('\\', "\\\\").replace('"', "\\\"")
+        );
+        let found: Option<MusicBrainzArtists> = self
+            .json(
+                last,
+                MUSICBRAINZ,
+                &[("query", &query), ("fmt", "json"), ("limit", "5")],
+            )
+            .await?;
+        let Some(artist) = found.and_then(|x| x.artists.into_iter().find(|x| x.score >= MIN_SCORE))
+        else {
+            return Ok(None);
+        };
+
+        let url = format!("{FANART}/{}", artist.id);
+        let images: Option<FanartArtist> = self.json(last, &url, &[("api_key", key)]).await?;
+        match images.and_then(|x| x.artistthumb.into_iter().next()) {
+            Some(thumb) => self.image(last, &thumb.url).await,
+            None => Ok(None),
+        }
+    }
+
+    /// The Deezer picture of the artist named `name`
+    async fn deezer_artist(
+        &self,
+        last: &mut Option<Instant>,
+        name: &str,
+    ) -> Result<Option<Image>, String> {
+        let url = format!("{DEEZER}/artist");
+        let found: Option<DeezerResults<DeezerArtist>> = self
+            .json(last, &url, &[("q", name), ("limit", "5")])
+            .await?;
+        let picture = found
+            .into_iter()
+            .flat_map(|x| x.data)
+            .filter(|x| normalize(&x.name) == normalize(name))
+            .find_map(|x| x.picture_xl.filter(|x| !is_placeholder(x)));
+        match picture {
+            Some(url) => self.image(last, &url).await,
+            None => Ok(None),
+        }
+    }
+
+    /// The Deezer cover of the album `name` by `artist`
+    async fn deezer_album(
+        &self,
+        last: &mut Option<Instant>,
+        name: &str,
+        artist: &str,
+    ) -> Result<Option<Image>, String> {
+        let url = format!("{DEEZER}/album");
+        let query = format!(
+            "artist:\"{}\" album:\"{}\"",
+            artist.replace('"', ""),
+            name.replace('"', "")
+        );
+        let found: Option<DeezerResults<DeezerAlbum>> = self
+            .json(last, &url, &[("q", &query), ("limit", "5")])
+            .await?;
+        let cover = found
+            .into_iter()
+            .flat_map(|x| x.data)
+            .filter(|x| {
+                normalize(&x.title) == normalize(name)
+                    && normalize(&x.artist.name) == normalize(artist)
+            })
+            .find_map(|x| x.cover_xl.filter(|x| !is_placeholder(x)));
+        match cover {
+            Some(url) => self.image(last, &url).await,
+            None => Ok(None),
+        }
+    }
+
+    /// The stored portrait of the artist `id` named `name`, fetched first when there is none
+    /// and the fetches are on
+    pub async fn artist_image(
+        &self,
+        covers_dir: &Path,
+        settings: &Settings,
+        id: &str,
+        name: &str,
+    ) -> Option<PathBuf> {
+        let dir = covers_dir.join(ARTISTS_DIR);
+        if let Some(path) = stored(&dir, id) {
+            return Some(path);
+        }
+        if !settings.enabled || name == UNKNOWN || missed(&dir, id) {
+            return None;
+        }
+
+        let mut last = self.last.lock().await;
+        // Fetched by another request in the meantime
+        if let Some(path) = stored(&dir, id) {
+            return Some(path);
+        }
+        if missed(&dir, id) {
+            return None;
+        }
+
+        let fanart = match &settings.fanart_key {
+            Some(key) => self.fanart(&mut last, name, key).await.unwrap_or_else(|e| {
+                warn!("artwork: unable to ask fanart.tv for {name}: {e}");
+                None
+            }),
+            None => None,
+        };
+        let image = match fanart {
+            Some(image) => Ok(Some(image)),
+            None => self.deezer_artist(&mut last, name).await,
+        };
+        match image {
+            Ok(image) => keep(&dir, id, image),
+            // Asked again on the next request
+            Err(e) => {
+                warn!("artwork: unable to fetch the portrait of {name}: {e}");
+                None
+            }
+        }
+    }
+
+    /// The cover of the album `name` by `artist`, from the Cover Art Archive when the album
+    /// has a MusicBrainz release id, from Deezer otherwise
+    async fn album_cover(
+        &self,
+        name: &str,
+        artist: &str,
+        release_id: Option<&str>,
+    ) -> Result<Option<Image>, String> {
+        let mut last = self.last.lock().await;
+        if let Some(id) = release_id {
+            let url = format!("{COVER_ART_ARCHIVE}/{id}/front-500");
+            if let Some(image) = self.image(&mut last, &url).await? {
+                return Ok(Some(image));
+            }
+        }
+        self.deezer_album(&mut last, name, artist).await
+    }
+}
+
+/// Looks for the albums of `library` without cover every hour while `library.external_artwork`
+/// is on, storing the covers found and selecting them
+pub async fn watch(
+    library: Arc<Library>,
+    config: SharedConfig,
+    io: SocketIo,
+    artwork: Arc<Artwork>,
+) {
+    let mut wait = FIRST_PASS;
+    loop {
+        tokio::time::sleep(wait).await;
+        wait = PASS_INTERVAL;
+        let settings = Settings::from_config(&*config.read().await);
+        // The scan selects the covers again
+        if !settings.enabled || library.scan.status().running {
+            continue;
+        }
+
+        let covers_dir = library.cache_dir.join("covers");
+        let albums: Vec<(String, String, String, Option<String>)> = {
+            let media = library.media.load();
+            media
+                .albums
+                .iter()
+                .filter(|x| x.cover_url.is_none() && x.name != UNKNOWN)
+                .filter(|x| !missed(&covers_dir, &x.id))
+                .filter_map(|album| {
+                    let artist = album.artists.first().filter(|x| x.as_str() != UNKNOWN)?;
+                    let release_id = album
+                        .tracks
+                        .first()
+                        .and_then(|x| media.tracks.get(x))
+                        .and_then(|x| x.musicbrainz_album_id.clone());
+                    Some((
+                        album.id.clone(),
+                        album.name.clone(),
+                        artist.clone(),
+                        release_id,
+                    ))
+                })
+                .collect()
+        };
+        if albums.is_empty() {
+            continue;
+        }
+
+        let lazy = covers::lazy(&*config.read().await);
+        let mut found = 0;
+        for (id, name, artist, release_id) in albums {
+            // Their tracks may have a cover the scan left out
+            if lazy && covers::extract(&library, &io, &id).await.is_some() {
+                continue;
+            }
+            match artwork
+                .album_cover(&name, &artist, release_id.as_deref())
+                .await
+            {
+                Ok(image) => found += keep(&covers_dir, &id, image).is_some() as usize,
+                Err(e) => warn!("artwork: unable to fetch the cover of {name}: {e}"),
+            }
+        }
+        info!("artwork: {found} album covers fetched for {}", library.name);
+        if found == 0 {
+            continue;
+        }
+
+        let mut media = library.media.write().await;
+        if media.select_covers(&covers_dir) {
+            utils::save_cache(&library.cache_dir, &media);
+            library.publish(&io, &media.commit());
+        }
+        library.colors.notify_one();
+    }
+}
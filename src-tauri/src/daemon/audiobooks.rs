@@ -0,0 +1,128 @@
+//! Audiobooks, told apart from music so that clients can resume them where listening stopped
+//! and list their chapters in order.
+
+use crate::daemon::global::Media;
+use mu_protocol::library::{MediaKind, Track};
+use std::path::Path;
+
+/// Genres of audiobooks, lowercase
+const GENRES: [&str; 7] = [
+    "audiobook",
+    "audiobooks",
+    "audio book",
+    "spoken word",
+    "spoken",
+    "hörbuch",
+    "livre audio",
+];
+
+/// Folders of audiobooks, lowercase
+const FOLDERS: [&str; 6] = [
+    "audiobook",
+    "audiobooks",
+    "audio books",
+    "hörbuch",
+    "hörbücher",
+    "livres audio",
+];
+
+fn is_audiobook(track: &Track, min_duration: u64) -> bool {
+    if track
+        .genre
+        .as_ref()
+        .is_some_and(|x| GENRES.contains(&x.trim().to_lowercase().as_str()))
+    {
+        return true;
+    }
+
+    let path = Path::new(track.audio_file());
+    if path
+        .extension()
+        .is_some_and(|x| x.eq_ignore_ascii_case("m4b"))
+    {
+        return true;
+    }
+    if path
+        .ancestors()
+        .skip(1)
+        .filter_map(|x| x.file_name())
+        .any(|x| FOLDERS.contains(&x.to_string_lossy().to_lowercase().as_str()))
+    {
+        return true;
+    }
+
+    min_duration > 0 && track.duration >= min_duration
+}
+
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord)]
+enum Chunk {
+    Number(u64),
+    Text(String),
+}
+
+/// Sorts `Chapter 2` before `Chapter 10`
+fn natural_key(value: &str) -> Vec<Chunk> {
+    let mut chunks = vec![];
+    let mut chars = value.chars().peekable();
+    while let Some(&c) = chars.peek() {
+        let digit = c.is_ascii_digit();
+        let mut chunk = String::new();
+        while let Some(&c) = chars.peek().filter(|x| x.is_ascii_digit() == digit) {
+            chunk.push(c);
+            chars.next();
+        }
+        chunks.push(match chunk.parse() {
+            Ok(n) if digit => Chunk::Number(n),
+            _ => Chunk::Text(chunk.to_lowercase()),
+        });
+    }
+    chunks
+}
+
+/// Chapters are numbered by their track number, or by their file names when untagged
+fn chapter_key(track: Option<&Track>) -> (u32, Vec<Chunk>) {
+    track.map_or((u32::MAX, vec![]), |x| (x.track, natural_key(&x.file_path)))
+}
+
+/// Sets the kind of the albums of `media` and their tracks: albums mostly made of audiobook
+/// tracks are audiobooks, with their tracks in chapter order. Returns whether anything changed.
+pub fn classify(media: &mut Media, min_duration: u64) -> bool {
+    let mut changed = false;
+    for album in &mut media.albums {
+        let audiobooks = album
+            .tracks
+            .iter()
+            .filter_map(|x| media.tracks.get(x))
+            .filter(|x| is_audiobook(x, min_duration))
+            .count();
+        let kind = if audiobooks * 2 > album.tracks.len() {
+            MediaKind::Audiobook
+        } else {
+            MediaKind::Music
+        };
+
+        if album.media_kind != kind {
+            album.media_kind = kind;
+            changed = true;
+        }
+        for path in &album.tracks {
+            if let Some(track) = media.tracks.get_mut(path) {
+                if track.media_kind != kind {
+                    track.media_kind = kind;
+                    changed = true;
+                }
+            }
+        }
+
+        if kind == MediaKind::Audiobook {
+            let mut tracks = album.tracks.clone();
+            tracks.sort_by_cached_key(|x| chapter_key(media.tracks.get(x)));
+            if tracks != album.tracks {
+                album.tracks = tracks;
+                changed = true;
+            }
+        }
+    }
+
+    changed
+}
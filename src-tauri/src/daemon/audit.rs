@@ -0,0 +1,191 @@
+//! Periodic check of the metadata cache against the filesystem, so that long-running daemons
+//! stay consistent without a manual rescan. Each run only looks at a random sample of tracks.
+
+use crate::daemon::config::SharedConfig;
+use crate::daemon::events;
+use crate::daemon::global::{read_track, Media, ScanOptions};
+use crate::daemon::jobs::Jobs;
+use crate::daemon::libraries::Library;
+use crate::daemon::remote;
+use crate::daemon::snapshot::Snapshot;
+use crate::daemon::utils;
+use mu_protocol::api::{AuditReport, JobKind};
+use mu_protocol::events::Event;
+use mu_protocol::library::Track;
+use rand::seq::IteratorRandom;
+use socketioxide::SocketIo;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+use tracing::{info, warn};
+
+/// How often a disabled audit looks at the configuration again
+const DISABLED_RECHECK: Duration = Duration::from_secs(60);
+
+/// `library.audit_interval` in seconds, `None` when the audit is disabled
+fn interval(config: &lorconf::Config) -> Option<Duration> {
+    let seconds = config
+        .library
+        .as_ref()
+        .and_then(|library| library.audit_interval)
+        .unwrap_or(3600);
+    (seconds > 0).then(|| Duration::from_secs(seconds))
+}
+
+fn sample_size(config: &lorconf::Config) -> usize {
+    config
+        .library
+        .as_ref()
+        .and_then(|library| library.audit_sample_size)
+        .unwrap_or(100)
+}
+
+fn cover_missing(track: &Track, covers_dir: &Path) -> bool {
+    // Tracks without embedded cover never get a color and use the default one
+    track.color.is_some()
+        && !covers_dir
+            .join(format!("{}{}", track.album_id, track.cover_ext))
+            .exists()
+}
+
+enum Repair {
+    Remove(PathBuf),
+    Reread(PathBuf, Box<Track>),
+}
+
+/// Checks `sample` against the filesystem, blocking
+fn check(sample: Vec<Track>, covers_dir: &PathBuf, options: &ScanOptions) -> Vec<Repair> {
+    let mut repairs = vec![];
+    for track in sample {
+        // Removing the file of tracks split by a cue sheet removes them all
+        let path = PathBuf::from(track.audio_file());
+        // The remote files are checked by listing their storage at every scan
+        if remote::is_remote(&path) {
+            continue;
+        }
+        if !path.exists() {
+            repairs.push(Repair::Remove(path));
+        } else if options.extract_covers
+            && track.source.is_none()
+            && cover_missing(&track, covers_dir)
+        {
+            match read_track(covers_dir, path.clone(), options) {
+                Ok(fresh) => repairs.push(Repair::Reread(path, Box::new(fresh))),
+                Err(e) => warn!("audit: unable to read `{}` again: {e}", path.display()),
+            }
+        }
+    }
+    repairs
+}
+
+/// Runs one audit over a sample of `media`, saving the cache if anything was repaired
+pub async fn run(
+    media: &Snapshot<Media>,
+    cache_dir: &Path,
+    config: &lorconf::Config,
+) -> Option<AuditReport> {
+    let sample: Vec<Track> = media
+        .load()
+        .tracks
+        .values()
+        .cloned()
+        .choose_multiple(&mut rand::thread_rng(), sample_size(config));
+    let checked = sample.len();
+
+    let covers_dir = cache_dir.join("covers");
+    let options = ScanOptions::from_config(config);
+    let repairs =
+        match tokio::task::spawn_blocking(move || check(sample, &covers_dir, &options)).await {
+            Ok(repairs) => repairs,
+            Err(e) => {
+                warn!("audit: {e}");
+                return None;
+            }
+        };
+
+    let mut report = AuditReport {
+        finished_at: SystemTime::now(),
+        checked,
+        removed: vec![],
+        covers_restored: vec![],
+    };
+    if repairs.is_empty() {
+        return Some(report);
+    }
+
+    let mut media = media.write().await;
+    for repair in repairs {
+        match repair {
+            Repair::Remove(path) => {
+                info!("audit: - {}", path.display());
+                media.remove_song(path.clone());
+                report.removed.push(path);
+            }
+            Repair::Reread(path, track) => {
+                info!("audit: cover restored for {}", path.display());
+                media.remove_song(path.clone());
+                media.add_song(*track);
+                report.covers_restored.push(path);
+            }
+        }
+    }
+    // Writing the whole library takes a while, the other writers don't wait for it
+    let media = media.commit();
+    let cache_dir = cache_dir.to_path_buf();
+    if let Err(e) = tokio::task::spawn_blocking(move || utils::save_cache(&cache_dir, &media)).await
+    {
+        warn!("audit: unable to save the cache: {e}");
+    }
+
+    Some(report)
+}
+
+/// Audits the cache of `library`, as a job. The report is kept in the library and sent with a
+/// `cache:audited` event, followed by the changes of the media if the cache was repaired. Its
+/// palette worker is woken up when covers were restored.
+pub async fn audit(library: &Library, config: &SharedConfig, io: &SocketIo) -> Result<(), String> {
+    let config = config.read().await.clone();
+    let Some(report) = run(&library.media, &library.cache_dir, &config).await else {
+        return Err("the audit was interrupted".to_string());
+    };
+    info!(
+        "audit: {} tracks checked in {}, {} removed, {} covers restored",
+        report.checked,
+        library.name,
+        report.removed.len(),
+        report.covers_restored.len()
+    );
+
+    events::emit(io, Event::CacheAudited, &report);
+    if report.repaired() {
+        library.publish(io, &library.media.load());
+    }
+    if !report.covers_restored.is_empty() {
+        library.colors.notify_one();
+    }
+    *library.audit.write().await = Some(report);
+
+    Ok(())
+}
+
+/// Queues an [`audit`] of `library` every `library.audit_interval` seconds
+pub async fn schedule(library: Arc<Library>, config: SharedConfig, io: SocketIo, jobs: Arc<Jobs>) {
+    loop {
+        let Some(every) = interval(&*config.read().await) else {
+            tokio::time::sleep(DISABLED_RECHECK).await;
+            continue;
+        };
+        tokio::time::sleep(every).await;
+
+        let (library, config, io) = (Arc::clone(&library), config.clone(), io.clone());
+        let name = library.name.clone();
+        let (_, done) = jobs.submit(
+            JobKind::Audit,
+            &name,
+            None,
+            Box::new(move |_| Box::pin(async move { audit(&library, &config, &io).await })),
+        );
+        // One audit at a time
+        let _ = done.await;
+    }
+}
@@ -0,0 +1,246 @@
+//! Portable archives of a library without its audio: its cache, its playlists and the user
+//! data of the daemon (favorites, history, bookmarks...). `GET /export` writes one, `POST
+//! /import` restores it, e.g. on another machine, where the next scan matches the cache with
+//! the files by their fingerprints.
+
+use async_zip::base::read::mem::ZipFileReader;
+use async_zip::tokio::write::ZipFileWriter;
+use async_zip::{Compression, ZipEntryBuilder};
+use axum::body::Body;
+use std::path::{Component, Path, PathBuf};
+use std::time::SystemTime;
+use tokio::io::DuplexStream;
+use tokio_util::io::ReaderStream;
+use tracing::{info, warn};
+
+/// Version of the layout of the archives, newer ones are refused
+const VERSION: u32 = 1;
+
+/// Size of the in-memory pipe between the zip writer and the response body
+const PIPE_CAPACITY: usize = 64 * 1024;
+
+const MANIFEST: &str = "manifest.json";
+
+/// Files of the cache of the library, kept in `cache/`. Covers are extracted again by the scan.
+const CACHE_FILES: [&str; 3] = [".cache.json", ".cache.list", ".cache.sums"];
+
+/// Files of the user data, kept in `data/`
+const DATA_FILES: [&str; 8] = [
+    "favorites.json",
+    "history.json",
+    "bookmarks.json",
+    "positions.json",
+    "listen_later.json",
+    "queue.json",
+    "playback.json",
+    "timers.json",
+];
+
+#[derive(serde::Serialize, serde::Deserialize, Debug)]
+struct Manifest {
+    version: u32,
+    /// Name of the exported library
+    library: String,
+    /// Its folders on the machine it was exported from
+    paths: Vec<PathBuf>,
+    created_at: SystemTime,
+}
+
+/// Where the files of an archive are read from and restored to
+pub struct Locations<'a> {
+    pub library: &'a str,
+    pub cache_dir: &'a Path,
+    pub app_dir: &'a Path,
+    /// Folders of the library
+    pub roots: &'a [PathBuf],
+}
+
+/// Path of `path` relative to the folder of `roots` it is in, its file name otherwise
+fn relative_to_roots(path: &Path, roots: &[PathBuf]) -> Option<PathBuf> {
+    match roots.iter().find_map(|x| path.strip_prefix(x).ok()) {
+        Some(relative) => Some(relative.to_path_buf()),
+        None => path.file_name().map(PathBuf::from),
+    }
+}
+
+/// Streams the archive of the library at `locations`, with its `playlists`
+pub fn export_body(locations: &Locations, playlists: Vec<PathBuf>) -> Body {
+    let manifest = Manifest {
+        version: VERSION,
+        library: locations.library.to_string(),
+        paths: locations.roots.to_vec(),
+        created_at: SystemTime::now(),
+    };
+    let mut files: Vec<(String, PathBuf)> = vec![];
+    for name in CACHE_FILES {
+        files.push((format!("cache/{name}"), locations.cache_dir.join(name)));
+    }
+    for name in DATA_FILES {
+        files.push((format!("data/{name}"), locations.app_dir.join(name)));
+    }
+    for path in playlists {
+        if let Some(relative) = relative_to_roots(&path, locations.roots) {
+            let name = relative.to_string_lossy().replace('\\', "/");
+            files.push((format!("playlists/{name}"), path));
+        }
+    }
+
+    let (writer, reader) = tokio::io::duplex(PIPE_CAPACITY);
+    tokio::spawn(async move {
+        if let Err(e) = write_archive(writer, manifest, files).await {
+            warn!("export: archive generation aborted: {e}");
+        }
+    });
+
+    Body::from_stream(ReaderStream::new(reader))
+}
+
+async fn write_archive(
+    writer: DuplexStream,
+    manifest: Manifest,
+    files: Vec<(String, PathBuf)>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let mut zip = ZipFileWriter::with_tokio(writer);
+    let data = serde_json::to_vec_pretty(&manifest)?;
+    let builder = ZipEntryBuilder::new(MANIFEST.to_string().into(), Compression::Stored);
+    zip.write_entry_whole(builder, &data).await?;
+
+    for (name, path) in files {
+        // Missing files, e.g. no favorite yet, are left out
+        let Ok(data) = tokio::fs::read(&path).await else {
+            continue;
+        };
+        let builder = ZipEntryBuilder::new(name.into(), Compression::Stored);
+        zip.write_entry_whole(builder, &data).await?;
+    }
+
+    zip.close().await?;
+    Ok(())
+}
+
+/// Entries of an archive, read whole before anything is restored
+pub struct Backup {
+    manifest: Manifest,
+    entries: Vec<(String, Vec<u8>)>,
+}
+
+/// Reads the archive `data`, failing with the reason it can't be restored
+pub async fn read(data: Vec<u8>) -> Result<Backup, String> {
+    let zip = ZipFileReader::new(data)
+        .await
+        .map_err(|e| format!("not a zip archive: {e}"))?;
+
+    let mut manifest = None;
+    let mut entries = vec![];
+    for i in 0..zip.file().entries().len() {
+        let mut reader = zip.reader_with_entry(i).await.map_err(|e| e.to_string())?;
+        let entry = reader.entry();
+        if entry.dir().unwrap_or(false) {
+            continue;
+        }
+        let name = entry
+            .filename()
+            .as_str()
+            .map_err(|e| e.to_string())?
+            .to_string();
+        let mut data = vec![];
+        reader
+            .read_to_end_checked(&mut data)
+            .await
+            .map_err(|e| format!("unable to read `{name}`: {e}"))?;
+
+        if name == MANIFEST {
+            manifest = Some(
+                serde_json::from_slice::<Manifest>(&data)
+                    .map_err(|e| format!("invalid manifest: {e}"))?,
+            );
+        } else {
+            entries.push((name, data));
+        }
+    }
+
+    let Some(manifest) = manifest else {
+        return Err("not an export of the library, the manifest is missing".to_string());
+    };
+    if manifest.version > VERSION {
+        return Err(format!(
+            "the archive was made by a newer version of the daemon (layout {})",
+            manifest.version
+        ));
+    }
+
+    Ok(Backup { manifest, entries })
+}
+
+/// `name` as a path below a folder, `None` if it could get out of it
+fn safe_relative(name: &str) -> Option<PathBuf> {
+    let path = Path::new(name);
+    let safe = !name.is_empty() && path.components().all(|x| matches!(x, Component::Normal(_)));
+    safe.then(|| path.to_path_buf())
+}
+
+/// What a restore wrote and left out
+#[derive(Debug, Default)]
+pub struct Restored {
+    pub files: Vec<String>,
+    pub playlists: Vec<PathBuf>,
+    pub skipped: Vec<String>,
+}
+
+/// Writes the cache and the user data of `backup` over the current ones, and its playlists to
+/// the first folder of the library unless they are already there
+pub fn restore(backup: Backup, locations: &Locations) -> Restored {
+    let mut restored = Restored::default();
+    let write = |path: &Path, data: &[u8]| -> bool {
+        if let Some(parent) = path.parent() {
+            let _ = std::fs::create_dir_all(parent);
+        }
+        match std::fs::write(path, data) {
+            Ok(()) => true,
+            Err(e) => {
+                warn!("import: unable to write `{}`: {e}", path.display());
+                false
+            }
+        }
+    };
+
+    for (name, data) in backup.entries {
+        let (dir, file) = name.split_once('/').unwrap_or_default();
+        let target = match dir {
+            "cache" if CACHE_FILES.contains(&file) => Some(locations.cache_dir.join(file)),
+            "data" if DATA_FILES.contains(&file) => Some(locations.app_dir.join(file)),
+            "playlists" => {
+                let path = safe_relative(file)
+                    .zip(locations.roots.first())
+                    .map(|(relative, root)| root.join(relative));
+                match path {
+                    Some(path) if !path.exists() => {
+                        if write(&path, &data) {
+                            restored.playlists.push(path);
+                        }
+                    }
+                    _ => restored.skipped.push(name),
+                }
+                continue;
+            }
+            _ => None,
+        };
+
+        match target {
+            Some(path) => {
+                if write(&path, &data) {
+                    restored.files.push(name);
+                }
+            }
+            None => restored.skipped.push(name),
+        }
+    }
+
+    info!(
+        "import: {} files and {} playlists restored from the library {}",
+        restored.files.len(),
+        restored.playlists.len(),
+        backup.manifest.library
+    );
+    restored
+}
@@ -0,0 +1,86 @@
+use crate::daemon::store;
+use mu_protocol::api::NewBookmark;
+use mu_protocol::library::Bookmark;
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::time::SystemTime;
+
+/// Named positions within tracks, keyed by track id
+#[derive(Debug, Default)]
+pub struct Bookmarks {
+    path: PathBuf,
+    entries: HashMap<String, Vec<Bookmark>>,
+}
+
+impl Bookmarks {
+    pub fn load(path: PathBuf) -> Self {
+        let entries = store::load(&path, "bookmarks");
+
+        Self { path, entries }
+    }
+
+    fn save(&self) {
+        store::save(&self.path, "bookmarks", &self.entries);
+    }
+
+    pub fn get(&self, track_id: &str) -> Vec<Bookmark> {
+        self.entries.get(track_id).cloned().unwrap_or_default()
+    }
+
+    pub fn add(&mut self, track_id: &str, bookmark: NewBookmark) -> Bookmark {
+        let bookmark = Bookmark {
+            id: uuid::Uuid::new_v4().to_string(),
+            name: bookmark.name,
+            position: bookmark.position,
+            created_at: SystemTime::now(),
+        };
+
+        let list = self.entries.entry(track_id.to_string()).or_default();
+        list.push(bookmark.clone());
+        list.sort_by_key(|x| x.position);
+        self.save();
+
+        bookmark
+    }
+
+    pub fn remove(&mut self, track_id: &str, bookmark_id: &str) -> bool {
+        let Some(list) = self.entries.get_mut(track_id) else {
+            return false;
+        };
+
+        let len = list.len();
+        list.retain(|x| x.id != bookmark_id);
+        let removed = list.len() != len;
+        if list.is_empty() {
+            self.entries.remove(track_id);
+        }
+        if removed {
+            self.save();
+        }
+
+        removed
+    }
+
+    /// Moves the bookmarks of the track ids found in `ids` to the ids they map to
+    pub fn remap_tracks(&mut self, ids: &HashMap<String, String>) -> bool {
+        let legacy: Vec<String> = self
+            .entries
+            .keys()
+            .filter(|x| ids.contains_key(*x))
+            .cloned()
+            .collect();
+        for old in &legacy {
+            if let Some(bookmarks) = self.entries.remove(old) {
+                self.entries
+                    .entry(ids[old].clone())
+                    .or_default()
+                    .extend(bookmarks);
+            }
+        }
+        if !legacy.is_empty() {
+            self.save();
+        }
+
+        !legacy.is_empty()
+    }
+}
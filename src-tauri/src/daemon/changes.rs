@@ -0,0 +1,230 @@
+//! Changes of the media of the libraries, for clients to catch up with
+//! `GET /media/changes?since=<generation>` instead of fetching the whole media again. Each
+//! change takes the next generation of the daemon, which starts at the time the daemon started
+//! in milliseconds: generations of a former run are older than any change kept.
+
+use crate::daemon::global::Media;
+use axum::http::HeaderName;
+use mu_protocol::api::MediaChanges;
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::hash::{Hash, Hasher};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::SystemTime;
+
+/// Changes kept by each library, older ones answer with a reset
+const KEPT: usize = 10_000;
+
+/// Header of `GET /media` with the generation of the media sent
+pub const GENERATION_HEADER: HeaderName = HeaderName::from_static("x-media-generation");
+
+static GENERATION: AtomicU64 = AtomicU64::new(0);
+
+/// Starts the generations at the current time, called once before the libraries are loaded
+pub fn start() {
+    let now = SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis() as u64;
+    GENERATION.store(now, Ordering::SeqCst);
+}
+
+/// The generation of the last change of any library
+pub fn generation() -> u64 {
+    GENERATION.load(Ordering::SeqCst)
+}
+
+fn next_generation() -> u64 {
+    GENERATION.fetch_add(1, Ordering::SeqCst) + 1
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+enum Item {
+    Track,
+    Album,
+    Playlist,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Kind {
+    Added,
+    Updated,
+    Removed,
+}
+
+#[derive(Debug)]
+struct Change {
+    generation: u64,
+    item: Item,
+    id: String,
+    kind: Kind,
+}
+
+/// Changes of the media of a library, found by comparing the digests of its tracks, albums and
+/// playlists with the ones of the previous [`ChangeLog::record`]
+#[derive(Debug)]
+pub struct ChangeLog {
+    changes: VecDeque<Change>,
+    digests: HashMap<(Item, String), u64>,
+    /// Changes up to this generation were dropped
+    floor: u64,
+}
+
+fn digest<T: serde::Serialize>(value: &T) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    serde_json::to_vec(value)
+        .unwrap_or_default()
+        .hash(&mut hasher);
+    hasher.finish()
+}
+
+fn digests(media: &Media) -> HashMap<(Item, String), u64> {
+    let tracks = media
+        .tracks
+        .values()
+        .map(|x| ((Item::Track, x.id.clone()), digest(x)));
+    let albums = media
+        .albums
+        .iter()
+        .map(|x| ((Item::Album, x.id.clone()), digest(x)));
+    let playlists = media
+        .playlists
+        .iter()
+        .map(|x| ((Item::Playlist, x.id.clone()), digest(x)));
+
+    tracks.chain(albums).chain(playlists).collect()
+}
+
+impl ChangeLog {
+    /// The log of `media` as loaded, nothing changed yet
+    pub fn new(media: &Media) -> Self {
+        Self {
+            changes: VecDeque::new(),
+            digests: digests(media),
+            floor: generation(),
+        }
+    }
+
+    fn push(&mut self, item: Item, id: String, kind: Kind) {
+        self.changes.push_back(Change {
+            generation: next_generation(),
+            item,
+            id,
+            kind,
+        });
+        while self.changes.len() > KEPT {
+            if let Some(change) = self.changes.pop_front() {
+                self.floor = change.generation;
+            }
+        }
+    }
+
+    /// Records what changed in `media` since the last time, returning these changes
+    pub fn record(&mut self, media: &Media) -> MediaChanges {
+        let current = digests(media);
+        let mut changed: Vec<(Item, String, Kind)> = vec![];
+        for (key, digest) in &current {
+            match self.digests.get(key) {
+                None => changed.push((key.0, key.1.clone(), Kind::Added)),
+                Some(old) if old != digest => changed.push((key.0, key.1.clone(), Kind::Updated)),
+                Some(_) => {}
+            }
+        }
+        for key in self.digests.keys() {
+            if !current.contains_key(key) {
+                changed.push((key.0, key.1.clone(), Kind::Removed));
+            }
+        }
+        for (item, id, kind) in &changed {
+            self.push(*item, id.clone(), *kind);
+        }
+        self.digests = current;
+
+        collect(
+            changed
+                .iter()
+                .map(|(item, id, kind)| (*item, id.as_str(), *kind)),
+            media,
+            generation(),
+        )
+    }
+
+    /// What changed after the generation `since`, with the current tracks, albums and
+    /// playlists of `media`
+    pub fn since(&self, since: u64, media: &Media) -> MediaChanges {
+        let generation = generation();
+        if since < self.floor || since > generation {
+            return MediaChanges {
+                generation,
+                reset: true,
+                ..Default::default()
+            };
+        }
+
+        // The last change of each item tells whether it is still there
+        let mut last: HashMap<(Item, &str), Kind> = HashMap::new();
+        // Items added then changed again since are still added
+        let mut added: HashSet<(Item, &str)> = HashSet::new();
+        for change in self.changes.iter().filter(|x| x.generation > since) {
+            let key = (change.item, change.id.as_str());
+            match change.kind {
+                Kind::Added => {
+                    added.insert(key);
+                }
+                Kind::Removed => {
+                    added.remove(&key);
+                }
+                Kind::Updated => {}
+            }
+            last.insert(key, change.kind);
+        }
+
+        let changes = last.into_iter().map(|((item, id), kind)| match kind {
+            Kind::Removed => (item, id, kind),
+            _ if added.contains(&(item, id)) => (item, id, Kind::Added),
+            _ => (item, id, Kind::Updated),
+        });
+        collect(changes, media, generation)
+    }
+}
+
+/// The changes of `changes` up to `generation`, with the current tracks, albums and playlists
+/// of `media`. Items gone from it are removed whatever their change.
+fn collect<'a>(
+    changes: impl Iterator<Item = (Item, &'a str, Kind)>,
+    media: &Media,
+    generation: u64,
+) -> MediaChanges {
+    let mut collected = MediaChanges {
+        generation,
+        ..Default::default()
+    };
+    for (item, id, kind) in changes {
+        let found = kind != Kind::Removed
+            && match item {
+                Item::Track => media
+                    .get_track(&id.into())
+                    .map(|x| collected.tracks.push(x)),
+                Item::Album => media
+                    .get_album(&id.into())
+                    .map(|x| collected.albums.push(x)),
+                Item::Playlist => media
+                    .get_playlist(&id.into())
+                    .map(|x| collected.playlists.push(x)),
+            }
+            .is_some();
+        let ids = match (found, kind) {
+            (false, _) | (_, Kind::Removed) => &mut collected.removed,
+            (_, Kind::Added) => &mut collected.added,
+            (_, Kind::Updated) => &mut collected.updated,
+        };
+        let ids = match item {
+            Item::Track => &mut ids.tracks,
+            Item::Album => &mut ids.albums,
+            Item::Playlist => &mut ids.playlists,
+        };
+        ids.push(id.to_string());
+    }
+
+    collected
+}
@@ -0,0 +1,121 @@
+//! Browsing by composer and work, for classical music: the composers of the library, their
+//! tracks work by work, and the tracks of an album grouped by the work they are movements of.
+
+use crate::daemon::artists::artist_id;
+use crate::daemon::global::{normalize, Media};
+use mu_protocol::api::{AlbumWork, Composer};
+use mu_protocol::library::{Album, Track};
+use std::collections::{HashMap, HashSet};
+
+/// Every composer of the library, alphabetically. Ids are made like the ones of the artists.
+pub fn composers(media: &Media) -> Vec<Composer> {
+    let mut composers: HashMap<String, (Composer, HashSet<String>)> = HashMap::new();
+    for track in media.tracks.values() {
+        for name in &track.composers {
+            let (composer, works) = composers.entry(artist_id(name)).or_insert_with_key(|id| {
+                let composer = Composer {
+                    id: id.clone(),
+                    name: name.clone(),
+                    tracks: 0,
+                    works: 0,
+                };
+                (composer, HashSet::new())
+            });
+            composer.tracks += 1;
+            // A track outside of any work is a work of its own
+            works.insert(normalize(track.work.as_ref().unwrap_or(&track.id)));
+        }
+    }
+
+    let mut composers: Vec<Composer> = composers
+        .into_values()
+        .map(|(composer, works)| Composer {
+            works: works.len(),
+            ..composer
+        })
+        .collect();
+    composers.sort_by_cached_key(|x| normalize(&x.name));
+    composers
+}
+
+/// Order of the tracks of a work: its movements, then the tracks without movement number by
+/// their number on the album
+fn movement_order(track: &Track) -> (Option<u32>, bool, u32) {
+    (
+        track.movement_number,
+        track.movement_number.is_none(),
+        track.track,
+    )
+}
+
+/// Tracks of the composer with the id `id`, by work then movement, `None` if there is no such
+/// composer
+pub fn composer_tracks<'a>(media: &'a Media, id: &str) -> Option<Vec<&'a Track>> {
+    let mut tracks: Vec<&Track> = media
+        .tracks
+        .values()
+        .filter(|x| x.composers.iter().any(|x| artist_id(x) == id))
+        .collect();
+    if tracks.is_empty() {
+        return None;
+    }
+
+    tracks.sort_by_cached_key(|x| {
+        let (number, unnumbered, track) = movement_order(x);
+        (
+            x.work.is_none(),
+            x.work.as_deref().map(normalize),
+            normalize(&x.album),
+            unnumbered,
+            number,
+            track,
+            x.file_path.clone(),
+        )
+    });
+    Some(tracks)
+}
+
+/// The tracks of `album` in their order on it, grouped by the work they follow each other in.
+/// Tracks outside of any work are grouped together likewise.
+pub fn album_works(media: &Media, album: &Album) -> Vec<AlbumWork> {
+    let mut tracks: Vec<&Track> = album
+        .tracks
+        .iter()
+        .filter_map(|x| media.tracks.get(x))
+        .collect();
+    tracks.sort_by(|a, b| a.track.cmp(&b.track).then(a.file_path.cmp(&b.file_path)));
+
+    let mut works: Vec<(AlbumWork, Vec<&Track>)> = vec![];
+    for track in tracks {
+        let work = track.work.as_deref().map(normalize);
+        match works.last_mut() {
+            Some((last, tracks)) if last.work.as_deref().map(normalize) == work => {
+                for composer in &track.composers {
+                    if !last.composers.contains(composer) {
+                        last.composers.push(composer.clone());
+                    }
+                }
+                tracks.push(track);
+            }
+            _ => works.push((
+                AlbumWork {
+                    work: track.work.clone(),
+                    composers: track.composers.clone(),
+                    tracks: vec![],
+                },
+                vec![track],
+            )),
+        }
+    }
+
+    works
+        .into_iter()
+        .map(|(mut work, mut tracks)| {
+            if work.work.is_some() {
+                tracks.sort_by_key(|x| movement_order(x));
+            }
+            work.tracks = tracks.into_iter().map(|x| x.id.clone()).collect();
+            work
+        })
+        .collect()
+}
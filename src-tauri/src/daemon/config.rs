@@ -1,4 +1,12 @@
-use std::{fs, path::PathBuf};
+use crate::daemon::cors;
+use crate::daemon::events;
+use crate::daemon::webhooks;
+use lorconf::Config;
+use mu_protocol::events::Event;
+use socketioxide::SocketIo;
+use std::{fs, path::PathBuf, sync::Arc, time::Duration};
+use tokio::sync::RwLock;
+use tracing::{info, warn};
 pub const VERSION: &str = env!("CARGO_PKG_VERSION");
 pub const APP_ID: &str = "lorchestre";
 
@@ -41,3 +49,164 @@ pub fn get_dirs() -> Dir {
 
     return dir;
 }
+
+pub type SharedConfig = Arc<RwLock<Config>>;
+
+/// Interval at which `config.toml` is checked for changes
+pub const WATCH_INTERVAL: Duration = Duration::from_secs(2);
+
+/// The daemon can't move to another address while running: keeps the current host, port and
+/// TLS files in `new`, warning if they were changed.
+pub fn keep_bind_address(running: &Config, new: &mut Config) {
+    let running_net = running.network.clone().unwrap_or_default();
+    let mut net = new.network.clone().unwrap_or_default();
+
+    if net.host != running_net.host
+        || net.port != running_net.port
+        || net.listen != running_net.listen
+        || net.unix_socket != running_net.unix_socket
+        || net.base_path != running_net.base_path
+    {
+        warn!(
+            "network.host, port, listen, unix_socket and base_path changes are applied on restart"
+        );
+        net.host = running_net.host;
+        net.port = running_net.port;
+        net.listen = running_net.listen;
+        net.unix_socket = running_net.unix_socket;
+        net.base_path = running_net.base_path;
+    }
+
+    let tls_files = |tls: &Option<lorconf::Tls>| {
+        tls.as_ref()
+            .map(|x| (x.cert.clone(), x.key.clone()))
+            .unwrap_or_default()
+    };
+    if tls_files(&net.tls) != tls_files(&running_net.tls) {
+        warn!("network.tls changes are applied on restart");
+        net.tls = running_net.tls;
+    }
+    new.network = Some(net);
+}
+
+/// Placeholder replacing secrets in the configuration sent to clients
+const REDACTED: &str = "********";
+
+/// A copy of `config` safe to hand to clients
+pub fn redacted(config: &Config) -> Config {
+    let mut config = config.clone();
+    if let Some(network) = config.network.as_mut() {
+        if network.admin_token.is_some() {
+            network.admin_token = Some(REDACTED.to_string());
+        }
+    }
+    if let Some(library) = config.library.as_mut() {
+        if library.fanart_api_key.is_some() {
+            library.fanart_api_key = Some(REDACTED.to_string());
+        }
+    }
+    for remote in config.remotes.iter_mut().flatten() {
+        if remote.secret_key.is_some() {
+            remote.secret_key = Some(REDACTED.to_string());
+        }
+    }
+    for webhook in config.webhooks.iter_mut().flatten() {
+        if webhook.secret.is_some() {
+            webhook.secret = Some(REDACTED.to_string());
+        }
+    }
+    config
+}
+
+/// Whether `new` sets another `network.admin_token` than `running`
+pub fn changes_admin_token(running: &Config, new: &Config) -> bool {
+    let token = |config: &Config| config.network.as_ref().and_then(|x| x.admin_token.clone());
+    token(running) != token(new)
+}
+
+/// Keeps the secrets of `running` that a client sent back redacted
+pub fn keep_secrets(running: &Config, new: &mut Config) {
+    if let Some(network) = new.network.as_mut() {
+        if network.admin_token.as_deref() == Some(REDACTED) {
+            network.admin_token = running.network.as_ref().and_then(|x| x.admin_token.clone());
+        }
+    }
+    if let Some(library) = new.library.as_mut() {
+        if library.fanart_api_key.as_deref() == Some(REDACTED) {
+            library.fanart_api_key = running
+                .library
+                .as_ref()
+                .and_then(|x| x.fanart_api_key.clone());
+        }
+    }
+    for remote in new.remotes.iter_mut().flatten() {
+        if remote.secret_key.as_deref() == Some(REDACTED) {
+            remote.secret_key = running
+                .remotes
+                .iter()
+                .flatten()
+                .find(|x| x.name == remote.name)
+                .and_then(|x| x.secret_key.clone());
+        }
+    }
+    for webhook in new.webhooks.iter_mut().flatten() {
+        if webhook.secret.as_deref() == Some(REDACTED) {
+            webhook.secret = running
+                .webhooks
+                .iter()
+                .flatten()
+                .find(|x| x.url == webhook.url)
+                .and_then(|x| x.secret.clone());
+        }
+    }
+}
+
+/// Applies a json merge patch (RFC 7396) on `target`
+pub fn merge_patch(target: &mut serde_json::Value, patch: serde_json::Value) {
+    match patch {
+        serde_json::Value::Object(patch) => {
+            if !target.is_object() {
+                *target = serde_json::Value::Object(Default::default());
+            }
+            let map = target.as_object_mut().unwrap();
+            for (key, value) in patch {
+                if value.is_null() {
+                    map.remove(&key);
+                } else {
+                    merge_patch(map.entry(key).or_insert(serde_json::Value::Null), value);
+                }
+            }
+        }
+        patch => *target = patch,
+    }
+}
+
+/// Reloads the configuration whenever `path` is modified
+pub async fn watch(path: PathBuf, config: SharedConfig, io: SocketIo) {
+    let modified = |path: &PathBuf| fs::metadata(path).and_then(|x| x.modified()).ok();
+    let mut last_modified = modified(&path);
+
+    loop {
+        tokio::time::sleep(WATCH_INTERVAL).await;
+
+        let current = modified(&path);
+        if current == last_modified {
+            continue;
+        }
+        last_modified = current;
+
+        // The parse error isn't `Send`, it can't be held across the lock
+        match Config::parse(&path).map_err(|e| e.to_string()) {
+            Ok(mut new_config) => {
+                let mut config = config.write().await;
+                keep_bind_address(&config, &mut new_config);
+                *config = new_config;
+                webhooks::configure(&config);
+                cors::configure(&config);
+                info!("configuration reloaded");
+                events::emit(&io, Event::Config, redacted(&config));
+            }
+            Err(e) => warn!("Ignoring invalid configuration `{}`: {e}", path.display()),
+        }
+    }
+}
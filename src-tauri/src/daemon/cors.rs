@@ -0,0 +1,169 @@
+//! Requests of the web pages of other origins, `network.cors`. They may read from
+//! `network.cors.origins`, any by default, but only `network.cors.write_origins`, none by
+//! default, may change things: the daemon trusts the local requests, a page could otherwise
+//! make the browser of the user stop it or edit the library. The web app served at `/app` and
+//! the desktop app are always allowed. The policy follows the reloads of the configuration.
+
+use crate::daemon::proxy;
+use crate::daemon::tls;
+use axum::extract::Request;
+use axum::http::header::{ACCESS_CONTROL_REQUEST_HEADERS, ACCESS_CONTROL_REQUEST_METHOD, ORIGIN};
+use axum::http::request::Parts;
+use axum::http::{HeaderMap, HeaderValue, Method, StatusCode};
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Response};
+use std::sync::RwLock;
+use std::time::Duration;
+use tower_http::cors::{AllowHeaders, AllowMethods, AllowOrigin, CorsLayer, ExposeHeaders, MaxAge};
+
+/// Origins of the desktop app, as the webviews of each platform name it
+const DESKTOP: [&str; 3] = [
+    "tauri://localhost",
+    "http://tauri.localhost",
+    "https://tauri.localhost",
+];
+
+/// Origin of the development server of the desktop app, allowed to the debug builds
+const DEVELOPMENT: &str = "http://localhost:1420";
+
+struct Policy {
+    origins: Vec<String>,
+    write_origins: Vec<String>,
+    methods: Vec<String>,
+    headers: Vec<String>,
+    max_age: u64,
+    /// Whether the daemon serves HTTPS, for its own origin
+    tls: bool,
+}
+
+static POLICY: RwLock<Option<Policy>> = RwLock::new(None);
+
+/// Takes the policy of `config`, on start and once reloaded
+pub fn configure(config: &lorconf::Config) {
+    let cors = config
+        .network
+        .as_ref()
+        .and_then(|network| network.cors.clone())
+        .unwrap_or_default();
+    let defaults = lorconf::Cors::default();
+    *POLICY.write().unwrap() = Some(Policy {
+        origins: cors.origins.or(defaults.origins).unwrap_or_default(),
+        write_origins: cors
+            .write_origins
+            .or(defaults.write_origins)
+            .unwrap_or_default(),
+        methods: cors
+            .methods
+            .or(defaults.methods)
+            .unwrap_or_default()
+            .iter()
+            .map(|x| x.to_uppercase())
+            .collect(),
+        headers: cors
+            .headers
+            .or(defaults.headers)
+            .unwrap_or_default()
+            .iter()
+            .map(|x| x.to_lowercase())
+            .collect(),
+        max_age: cors.max_age.or(defaults.max_age).unwrap_or_default(),
+        tls: tls::files(config).is_some(),
+    });
+}
+
+/// Whether `method` changes things
+fn is_write(method: &Method) -> bool {
+    !matches!(
+        method.as_str(),
+        "GET" | "HEAD" | "OPTIONS" | "PROPFIND" | "TRACE"
+    )
+}
+
+fn listed(origins: &[String], origin: &str) -> bool {
+    origins
+        .iter()
+        .any(|x| x == "*" || x.trim_end_matches('/').eq_ignore_ascii_case(origin))
+}
+
+/// Whether `origin` is the one of the daemon itself, of its web app
+fn is_own(policy: &Policy, origin: &str, headers: &HeaderMap) -> bool {
+    proxy::origin(headers, policy.tls).is_some_and(|x| {
+        x.strip_suffix(proxy::base_path())
+            .is_some_and(|x| x.eq_ignore_ascii_case(origin))
+    })
+}
+
+/// Whether `origin` may make a request of `method`
+fn allows(policy: &Policy, origin: &str, method: &Method, headers: &HeaderMap) -> bool {
+    if DESKTOP.contains(&origin)
+        || (cfg!(debug_assertions) && origin == DEVELOPMENT)
+        || is_own(policy, origin, headers)
+    {
+        return true;
+    }
+    match is_write(method) {
+        true => listed(&policy.write_origins, origin),
+        false => listed(&policy.origins, origin) || listed(&policy.write_origins, origin),
+    }
+}
+
+/// Whether the request of `parts`, or the one it is the preflight of, is allowed to `origin`
+fn allows_request(origin: &HeaderValue, parts: &Parts) -> bool {
+    let policy = POLICY.read().unwrap();
+    let (Some(policy), Ok(origin)) = (policy.as_ref(), origin.to_str()) else {
+        return false;
+    };
+    if parts.method != Method::OPTIONS {
+        return allows(policy, origin, &parts.method, &parts.headers);
+    }
+
+    let header = |name| parts.headers.get(name).and_then(|x| x.to_str().ok());
+    let Some(method) = header(ACCESS_CONTROL_REQUEST_METHOD).and_then(|x| x.parse::<Method>().ok())
+    else {
+        return allows(policy, origin, &parts.method, &parts.headers);
+    };
+    let method_allowed = policy.methods.iter().any(|x| x == method.as_str());
+    let headers_allowed = policy.headers.iter().any(|x| x == "*")
+        || header(ACCESS_CONTROL_REQUEST_HEADERS)
+            .unwrap_or_default()
+            .split(',')
+            .map(|x| x.trim().to_lowercase())
+            .filter(|x| !x.is_empty())
+            .all(|x| policy.headers.contains(&x));
+    method_allowed && headers_allowed && allows(policy, origin, &method, &parts.headers)
+}
+
+/// The CORS layer of the daemon. The methods and headers asked for in a preflight request are
+/// echoed once checked against the policy along with the origin.
+pub fn layer() -> CorsLayer {
+    CorsLayer::new()
+        .allow_origin(AllowOrigin::predicate(allows_request))
+        .allow_methods(AllowMethods::mirror_request())
+        .allow_headers(AllowHeaders::mirror_request())
+        .expose_headers(ExposeHeaders::any())
+        .max_age(MaxAge::dynamic(|_, _| {
+            let policy = POLICY.read().unwrap();
+            Duration::from_secs(policy.as_ref().map_or(0, |x| x.max_age))
+        }))
+}
+
+/// Refuses the requests changing things sent by the pages of the origins not allowed to, which
+/// browsers send without asking first when they look like the submission of a form
+pub async fn guard(request: Request, next: Next) -> Response {
+    let refused = match request.headers().get(ORIGIN).map(|x| x.to_str()) {
+        Some(Ok(origin)) if is_write(request.method()) => {
+            let policy = POLICY.read().unwrap();
+            policy
+                .as_ref()
+                .is_some_and(|x| !allows(x, origin, request.method(), request.headers()))
+        }
+        _ => false,
+    };
+    if refused {
+        let mut response = "this origin isn't allowed to change anything".into_response();
+        *response.status_mut() = StatusCode::FORBIDDEN;
+        return response;
+    }
+
+    next.run(request).await
+}
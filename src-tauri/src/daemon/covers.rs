@@ -0,0 +1,52 @@
+//! Album covers extracted on their first request, for the libraries scanned with
+//! `scan.extract_covers` off: decoding every picture during the scan takes most of the CPU of
+//! small devices, e.g. a Raspberry Pi.
+
+use crate::daemon::global;
+use crate::daemon::libraries::Library;
+use crate::daemon::utils;
+use socketioxide::SocketIo;
+use std::path::PathBuf;
+use tracing::warn;
+
+/// Whether the covers are left to their first request, `scan.extract_covers` being off
+pub fn lazy(config: &lorconf::Config) -> bool {
+    !config
+        .scan
+        .as_ref()
+        .and_then(|x| x.extract_covers)
+        .or(lorconf::Scan::default().extract_covers)
+        .unwrap_or_default()
+}
+
+/// Extracts the cover of the album `album_id` of `library` from the tags or the folder of its
+/// first track, and points the album to it. Returns the name of the cover in the covers
+/// folder, `None` for an album missing or without cover.
+pub async fn extract(library: &Library, io: &SocketIo, album_id: &str) -> Option<String> {
+    let file = {
+        let media = library.media.load();
+        let album = media.album(&album_id.into())?;
+        let track = media.tracks.get(album.tracks.first()?)?;
+        PathBuf::from(track.audio_file())
+    };
+
+    let covers_dir = library.cache_dir.join("covers");
+    let (dir, id) = (covers_dir.clone(), album_id.to_string());
+    let ext =
+        match tokio::task::spawn_blocking(move || global::extract_cover(&dir, &id, &file)).await {
+            Ok(ext) => ext?,
+            Err(e) => {
+                warn!("covers: unable to extract the cover of {album_id}: {e}");
+                return None;
+            }
+        };
+
+    let mut media = library.media.write().await;
+    if media.select_covers(&covers_dir) {
+        utils::save_cache(&library.cache_dir, &media);
+        library.publish(io, &media.commit());
+    }
+    library.colors.notify_one();
+
+    Some(format!("{album_id}{ext}"))
+}
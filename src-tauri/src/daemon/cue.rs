@@ -0,0 +1,397 @@
+//! Cue sheets, describing the tracks of an album ripped as a single audio file. Each track of
+//! the sheet becomes a virtual track of the library, played from a cut of the file.
+
+use crate::daemon::global;
+use base64::{engine::general_purpose::URL_SAFE, Engine as _};
+use mu_protocol::library::Track;
+use std::path::{Path, PathBuf};
+use std::process::Stdio;
+use tokio::process::Command;
+use tracing::info;
+
+/// `INDEX` positions are in `mm:ss:ff`, with 75 frames per second
+const FRAMES_PER_SECOND: u64 = 75;
+
+#[derive(Debug, Default)]
+pub struct CueTrack {
+    /// Audio file of the track, from the last `FILE` command
+    pub file: String,
+    pub number: u32,
+    pub title: Option<String>,
+    pub performer: Option<String>,
+    /// Position of `INDEX 01` in milliseconds, the pregap belongs to the previous track
+    pub start: u64,
+}
+
+#[derive(Debug, Default)]
+pub struct CueSheet {
+    pub title: Option<String>,
+    pub performer: Option<String>,
+    pub genre: Option<String>,
+    pub year: Option<u32>,
+    pub tracks: Vec<CueTrack>,
+}
+
+/// Argument of a command, without its quotes
+fn unquote(value: &str) -> String {
+    let value = value.trim();
+    value
+        .strip_prefix('"')
+        .and_then(|x| x.strip_suffix('"'))
+        .unwrap_or(value)
+        .to_string()
+}
+
+/// `mm:ss:ff` in milliseconds
+fn parse_position(value: &str) -> Option<u64> {
+    let mut parts = value.trim().split(':').map(|x| x.parse::<u64>().ok());
+    let (minutes, seconds, frames) = (parts.next()??, parts.next()??, parts.next()??);
+    Some((minutes * 60 + seconds) * 1000 + frames * 1000 / FRAMES_PER_SECOND)
+}
+
+pub fn parse(text: &str) -> CueSheet {
+    let mut sheet = CueSheet::default();
+    let mut file = String::new();
+
+    for line in text.trim_start_matches('\u{feff}').lines() {
+        let line = line.trim();
+        let (command, rest) = line.split_once(char::is_whitespace).unwrap_or((line, ""));
+        let track = sheet.tracks.last_mut();
+        match command.to_uppercase().as_str() {
+            "FILE" => {
+                // `FILE "name.flac" WAVE`
+                let rest = rest.trim();
+                file = match rest.rsplit_once(char::is_whitespace) {
+                    Some((name, _)) => unquote(name),
+                    None => unquote(rest),
+                };
+            }
+            "TRACK" => sheet.tracks.push(CueTrack {
+                file: file.clone(),
+                number: rest
+                    .split_whitespace()
+                    .next()
+                    .and_then(|x| x.parse().ok())
+                    .unwrap_or(sheet.tracks.len() as u32 + 1),
+                ..Default::default()
+            }),
+            "TITLE" => match track {
+                Some(track) => track.title = Some(unquote(rest)),
+                None => sheet.title = Some(unquote(rest)),
+            },
+            "PERFORMER" => match track {
+                Some(track) => track.performer = Some(unquote(rest)),
+                None => sheet.performer = Some(unquote(rest)),
+            },
+            "INDEX" => {
+                if let (Some(track), Some(("01", position))) = (track, rest.trim().split_once(' '))
+                {
+                    track.start = parse_position(position).unwrap_or(0);
+                }
+            }
+            "REM" => {
+                let (key, value) = rest.trim().split_once(' ').unwrap_or((rest, ""));
+                match key.to_uppercase().as_str() {
+                    "GENRE" => sheet.genre = Some(unquote(value)),
+                    "DATE" => sheet.year = value.trim().get(..4).and_then(|x| x.parse().ok()),
+                    _ => {}
+                }
+            }
+            _ => {}
+        }
+    }
+
+    sheet
+}
+
+fn read(path: &Path) -> Option<CueSheet> {
+    // Sheets are often written in the codepage of the ripper rather than UTF-8
+    let data = std::fs::read(path).ok()?;
+    Some(parse(&String::from_utf8_lossy(&data)))
+}
+
+/// Keeps the tracks of `sheet` played from `audio`
+fn tracks_of(mut sheet: CueSheet, audio: &Path, by_name_only: bool) -> Option<CueSheet> {
+    let name = audio.file_name()?.to_string_lossy().to_lowercase();
+    let stem = audio.file_stem()?.to_string_lossy().to_lowercase();
+    let files = sheet
+        .tracks
+        .iter()
+        .map(|x| x.file.to_lowercase())
+        .collect::<std::collections::HashSet<_>>();
+
+    sheet.tracks.retain(|track| {
+        let file = track.file.to_lowercase();
+        let file = Path::new(&file);
+        if file
+            .file_name()
+            .is_some_and(|x| x.to_string_lossy() == name)
+        {
+            return true;
+        }
+        // A sheet next to the file it describes, still referencing the file before it was
+        // converted, e.g. `album.wav` for `album.flac`
+        !by_name_only
+            && files.len() == 1
+            && file
+                .file_stem()
+                .is_some_and(|x| x.to_string_lossy() == stem)
+    });
+
+    // A single track is the file itself
+    (sheet.tracks.len() > 1).then_some(sheet)
+}
+
+/// The cue sheet splitting `audio`: the one embedded in its tags, a `.cue` file with the same
+/// name, or any `.cue` file of its directory referencing it
+pub fn find(audio: &Path, embedded: Option<&str>) -> Option<CueSheet> {
+    if let Some(text) = embedded {
+        if let Some(sheet) = tracks_of(parse(text), audio, false) {
+            return Some(sheet);
+        }
+    }
+
+    let same_name = audio.with_extension("cue");
+    if let Some(sheet) = read(&same_name).and_then(|x| tracks_of(x, audio, false)) {
+        return Some(sheet);
+    }
+
+    std::fs::read_dir(audio.parent()?)
+        .ok()?
+        .flatten()
+        .map(|x| x.path())
+        .filter(|x| *x != same_name)
+        .filter(|x| x.extension().is_some_and(|x| x.eq_ignore_ascii_case("cue")))
+        .find_map(|x| read(&x).and_then(|sheet| tracks_of(sheet, audio, true)))
+}
+
+/// The virtual tracks of `track`, as described by `sheet`. Their album id is left to the
+/// caller.
+pub fn split(track: &Track, sheet: &CueSheet) -> Vec<Track> {
+    let length = track.duration * 1000;
+
+    sheet
+        .tracks
+        .iter()
+        .enumerate()
+        .map(|(i, cue)| {
+            let end = sheet.tracks.get(i + 1).map(|x| x.start);
+            let file_path = format!("{}#{}", track.file_path, cue.number);
+            let performer = cue.performer.as_ref().or(sheet.performer.as_ref());
+            Track {
+                title: cue
+                    .title
+                    .clone()
+                    .unwrap_or_else(|| format!("Track {}", cue.number)),
+                title_inferred: false,
+                artists: performer.map_or(track.artists.clone(), |x| vec![x.clone()]),
+                track: cue.number,
+                track_inferred: false,
+                album: sheet.title.clone().unwrap_or(track.album.clone()),
+                album_guessed: sheet.title.is_none() && track.album_guessed,
+                album_artists: sheet
+                    .performer
+                    .as_ref()
+                    .map_or(track.album_artists.clone(), |x| vec![x.clone()]),
+                album_year: sheet.year.or(track.album_year),
+                genre: sheet.genre.clone().or(track.genre.clone()),
+                path_base64: URL_SAFE.encode(file_path.as_bytes()),
+                id: global::track_id(&file_path, Path::new(&track.file_path), None),
+                // Tagged on the whole file
+                musicbrainz_recording_id: None,
+                file_path,
+                duration: (end.unwrap_or(length).saturating_sub(cue.start)) / 1000,
+                source: Some(track.file_path.clone()),
+                start: Some(cue.start),
+                end,
+                // Bound to the whole file
+                gapless: None,
+                bpm: None,
+                key: None,
+                trim_start_ms: None,
+                trim_end_ms: None,
+                lyrics: vec![],
+                ..track.clone()
+            }
+        })
+        .collect()
+}
+
+fn modified(path: &Path) -> Option<std::time::SystemTime> {
+    std::fs::metadata(path).and_then(|x| x.modified()).ok()
+}
+
+/// Encoding of the cut of `track`: extension, ffmpeg format and codec, and mime type. MP3s are
+/// cut without being encoded again, other formats are encoded to FLAC.
+fn encoding(track: &Track) -> (&'static str, &'static str, &'static str, &'static str) {
+    match track.mime.as_str() {
+        "audio/mpeg" => ("mp3", "mp3", "copy", "audio/mpeg"),
+        _ => ("flac", "flac", "flac", "audio/flac"),
+    }
+}
+
+/// Where the cut of the virtual `track` is made in `<cache_dir>/cue`, with its mime type, and
+/// whether it is up to date with its source
+pub fn cut(cache_dir: &Path, track: &Track) -> std::io::Result<(PathBuf, &'static str, bool)> {
+    let (ext, _, _, mime) = encoding(track);
+    let path = cache_dir
+        .join("cue")
+        .join(format!("{:x}.{ext}", md5::compute(&track.file_path)));
+    let source_modified = modified(Path::new(track.audio_file()))
+        .ok_or_else(|| std::io::Error::from(std::io::ErrorKind::NotFound))?;
+    let fresh = modified(&path).is_some_and(|x| x >= source_modified);
+
+    Ok((path, mime, fresh))
+}
+
+/// Cuts the part of its source played by the virtual `track`, unless already done, see
+/// [`cut`]
+pub async fn extract(cache_dir: &Path, track: &Track) -> std::io::Result<(PathBuf, &'static str)> {
+    let source = track.audio_file();
+    let (_, format, codec, _) = encoding(track);
+    let (path, mime, fresh) = cut(cache_dir, track)?;
+    if fresh {
+        return Ok((path, mime));
+    }
+    let dir = cache_dir.join("cue");
+
+    info!("cue: extracting `{}`", track.file_path);
+    tokio::fs::create_dir_all(&dir).await?;
+    // Written aside and renamed, as a concurrent request may be cutting the same track
+    let tmp = dir.join(format!("{}.tmp", uuid::Uuid::new_v4()));
+    let start = track.start.unwrap_or(0);
+    let mut command = Command::new("ffmpeg");
+    command
+        .args(["-nostdin", "-loglevel", "error"])
+        .args(["-ss", &format!("{:.3}", start as f64 / 1000.0), "-i"])
+        .arg(source);
+    if let Some(end) = track.end {
+        let length = end.saturating_sub(start) as f64 / 1000.0;
+        command.args(["-t", &format!("{length:.3}")]);
+    }
+    let status = command
+        .args(["-vn", "-map", "0:a:0", "-c:a", codec, "-f", format])
+        .arg(&tmp)
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .status()
+        .await
+        // Not to be taken for a missing source
+        .map_err(|e| std::io::Error::other(format!("unable to run ffmpeg: {e}")))?;
+
+    if !status.success() {
+        let _ = tokio::fs::remove_file(&tmp).await;
+        return Err(std::io::Error::other(format!(
+            "ffmpeg exited with {status}"
+        )));
+    }
+    tokio::fs::rename(&tmp, &path).await?;
+
+    Ok((path, mime))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SHEET: &str = "\u{feff}REM GENRE \"Jazz\"
+REM DATE 1959-08-17
+PERFORMER \"The Band\"
+TITLE \"The Album\"
+FILE \"album.wav\" WAVE
+  TRACK 01 AUDIO
+    TITLE \"Opening\"
+    PERFORMER \"Guest\"
+    INDEX 00 00:00:00
+    INDEX 01 00:00:32
+  TRACK 02 AUDIO
+    INDEX 00 03:58:60
+    INDEX 01 04:01:15
+  TRACK 03 AUDIO
+    TITLE \"Closing\"
+    INDEX 01 09:30:74
+";
+
+    #[test]
+    fn index_frames() {
+        // 75 frames per second
+        assert_eq!(parse_position("00:00:00"), Some(0));
+        assert_eq!(parse_position("01:02:30"), Some(62_400));
+        assert_eq!(parse_position("00:00:74"), Some(986));
+        assert_eq!(parse_position("12:00"), None);
+        assert_eq!(parse_position("aa:00:00"), None);
+    }
+
+    #[test]
+    fn sheet() {
+        let sheet = parse(SHEET);
+        assert_eq!(sheet.title.as_deref(), Some("The Album"));
+        assert_eq!(sheet.performer.as_deref(), Some("The Band"));
+        assert_eq!(sheet.genre.as_deref(), Some("Jazz"));
+        assert_eq!(sheet.year, Some(1959));
+
+        let tracks: Vec<_> = sheet
+            .tracks
+            .iter()
+            .map(|x| (x.number, x.file.as_str(), x.start, x.title.as_deref()))
+            .collect();
+        // The pregaps of `INDEX 00` belong to the previous track
+        assert_eq!(
+            tracks,
+            vec![
+                (1, "album.wav", 426, Some("Opening")),
+                (2, "album.wav", 241_200, None),
+                (3, "album.wav", 570_986, Some("Closing")),
+            ]
+        );
+    }
+
+    #[test]
+    fn split_falls_back_on_the_sheet() {
+        let track = Track {
+            title: "album".to_string(),
+            artists: vec!["Tagged".to_string()],
+            album: "Tagged album".to_string(),
+            file_path: "/music/album.flac".to_string(),
+            duration: 600,
+            ..Default::default()
+        };
+        let sheet = tracks_of(parse(SHEET), Path::new(&track.file_path), false).unwrap();
+        let tracks = split(&track, &sheet);
+
+        let described: Vec<_> = tracks
+            .iter()
+            .map(|x| (x.title.as_str(), x.artists.clone(), x.start, x.end))
+            .collect();
+        assert_eq!(
+            described,
+            vec![
+                (
+                    "Opening",
+                    vec!["Guest".to_string()],
+                    Some(426),
+                    Some(241_200)
+                ),
+                (
+                    "Track 2",
+                    vec!["The Band".to_string()],
+                    Some(241_200),
+                    Some(570_986)
+                ),
+                ("Closing", vec!["The Band".to_string()], Some(570_986), None),
+            ]
+        );
+        assert!(tracks.iter().all(|x| x.album == "The Album"));
+        assert_eq!(tracks[1].file_path, "/music/album.flac#2");
+        assert_eq!(tracks[2].duration, 29);
+    }
+
+    #[test]
+    fn sheets_of_other_files() {
+        let audio = Path::new("/music/other.flac");
+        // A sheet of another file only describes it when next to it with the same name
+        assert!(tracks_of(parse(SHEET), audio, true).is_none());
+        assert!(tracks_of(parse(SHEET), Path::new("/music/album.flac"), true).is_none());
+        assert!(tracks_of(parse(SHEET), Path::new("/music/album.wav"), true).is_some());
+    }
+}
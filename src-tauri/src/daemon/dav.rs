@@ -0,0 +1,306 @@
+//! Read-only WebDAV view of a library under `/dav/`, for the file managers and car head units
+//! browsing and copying the music off the daemon. The files are laid out as
+//! `library.organize_pattern` would move them, by album artist and album with the default
+//! pattern, whatever their paths on the disk. Only the class 1 methods reading the tree are
+//! answered: `OPTIONS`, `PROPFIND`, `GET` and `HEAD`.
+
+use crate::daemon::cue;
+use crate::daemon::global::Media;
+use crate::daemon::organize::{self, Part};
+use crate::daemon::proxy;
+use axum::extract::Request;
+use axum::http::header::ALLOW;
+use axum::http::{HeaderValue, Method};
+use axum::middleware::Next;
+use axum::response::Response;
+use chrono::{DateTime, Utc};
+use mu_protocol::library::Track;
+use percent_encoding::{utf8_percent_encode, AsciiSet, NON_ALPHANUMERIC};
+use quick_xml::escape::escape;
+use std::collections::HashSet;
+use std::fmt::Write;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::SystemTime;
+use tokio::sync::Mutex;
+
+/// Methods of the tree, for `Allow`
+pub const METHODS: &str = "OPTIONS, PROPFIND, GET, HEAD";
+
+/// Characters escaped in the segments of the hrefs
+const SEGMENT: &AsciiSet = &NON_ALPHANUMERIC
+    .remove(b'-')
+    .remove(b'.')
+    .remove(b'_')
+    .remove(b'~');
+
+/// A file of the tree
+pub struct Entry {
+    /// Path in the tree, split in folders and file name
+    pub path: Vec<String>,
+    pub track: Track,
+    /// The file read for the track, the cut made of it for the tracks split by a cue sheet
+    file: PathBuf,
+    mime: String,
+}
+
+/// What a path of the tree points to
+pub enum Node<'a> {
+    /// A folder and what it holds: the names of its folders and its files
+    Collection(Vec<String>, Vec<&'a Entry>),
+    File(&'a Entry),
+}
+
+/// The files of `media` at the paths `pattern` renders for them. Tracks split by a cue sheet get
+/// the extension of their cut, and a path given twice is numbered, e.g. `Intro (2).flac`.
+pub fn entries(
+    media: &Media,
+    cache_dir: &Path,
+    pattern: &[Part],
+    hide_explicit: bool,
+) -> Vec<Entry> {
+    let mut tracks: Vec<&Track> = media
+        .tracks
+        .values()
+        .filter(|x| !(hide_explicit && x.is_explicit()))
+        .collect();
+    tracks.sort_by(|a, b| a.file_path.cmp(&b.file_path));
+
+    let mut taken = HashSet::new();
+    let mut entries = vec![];
+    for track in tracks {
+        let mut rendered = track.clone();
+        let (file, mime) = match track.source {
+            Some(_) => {
+                let Ok((cut, mime, _)) = cue::cut(cache_dir, track) else {
+                    continue;
+                };
+                let ext = cut.extension().unwrap_or_default().to_string_lossy();
+                rendered.file_path = format!("{}.{ext}", track.file_path);
+                (cut, mime.to_string())
+            }
+            None => (track.file_path.clone().into(), track.mime.clone()),
+        };
+
+        let path = organize::render(pattern, &rendered);
+        let mut segments: Vec<String> = path
+            .iter()
+            .map(|x| x.to_string_lossy().to_string())
+            .collect();
+        let Some(name) = segments.pop() else {
+            continue;
+        };
+        let (stem, ext) = name.rsplit_once('.').unwrap_or((&name, ""));
+        let mut name = name.clone();
+        let mut n = 1;
+        while !taken.insert([&segments[..], std::slice::from_ref(&name)].concat()) {
+            n += 1;
+            name = format!("{stem} ({n}).{ext}");
+        }
+        segments.push(name);
+
+        entries.push(Entry {
+            path: segments,
+            track: track.clone(),
+            file,
+            mime,
+        });
+    }
+    entries
+}
+
+/// The entries of a snapshot of the media, laid out with `pattern`
+struct Listed {
+    media: Arc<Media>,
+    pattern: Vec<Part>,
+    entries: Arc<Vec<Entry>>,
+}
+
+/// The entries of a library, listed again only once its media or the pattern changed rather
+/// than on every request. One listing is kept with the explicit tracks and one without.
+#[derive(Default)]
+pub struct Tree {
+    listed: Mutex<[Option<Listed>; 2]>,
+}
+
+impl std::fmt::Debug for Tree {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Tree").finish_non_exhaustive()
+    }
+}
+
+impl Tree {
+    /// [`entries`] of `media`, the snapshot a request reads
+    pub async fn entries(
+        &self,
+        media: Arc<Media>,
+        cache_dir: &Path,
+        pattern: Vec<Part>,
+        hide_explicit: bool,
+    ) -> Arc<Vec<Entry>> {
+        // Requests made while listing wait for it rather than listing too
+        let mut listed = self.listed.lock().await;
+        let slot = &mut listed[hide_explicit as usize];
+        if let Some(listed) = slot
+            .as_ref()
+            .filter(|x| Arc::ptr_eq(&x.media, &media) && x.pattern == pattern)
+        {
+            return Arc::clone(&listed.entries);
+        }
+
+        let entries = Arc::new(entries(&media, cache_dir, &pattern, hide_explicit));
+        *slot = Some(Listed {
+            media,
+            pattern,
+            entries: Arc::clone(&entries),
+        });
+        entries
+    }
+}
+
+/// What `path`, split in segments, points to in `entries`
+pub fn resolve<'a>(entries: &'a [Entry], path: &[String]) -> Option<Node<'a>> {
+    if let Some(entry) = entries.iter().find(|x| x.path == path) {
+        return Some(Node::File(entry));
+    }
+
+    let mut collections: Vec<String> = vec![];
+    let mut files = vec![];
+    for entry in entries {
+        let Some(rest) = entry.path.strip_prefix(path) else {
+            continue;
+        };
+        match rest {
+            [_] => files.push(entry),
+            [folder, ..] if !collections.contains(folder) => collections.push(folder.clone()),
+            _ => {}
+        }
+    }
+    if path.is_empty() || !collections.is_empty() || !files.is_empty() {
+        collections.sort();
+        Some(Node::Collection(collections, files))
+    } else {
+        None
+    }
+}
+
+/// `href` with the segments of `path` appended
+fn href(base: &str, path: &[String], collection: bool) -> String {
+    let mut href = base.trim_end_matches('/').to_string();
+    for segment in path {
+        href.push('/');
+        href.extend(utf8_percent_encode(segment, SEGMENT));
+    }
+    if collection {
+        href.push('/');
+    }
+    href
+}
+
+/// The href of the root of the tree, below `/libraries/<library>` for a library other than the
+/// default one
+pub fn base(library: Option<&str>) -> String {
+    match library {
+        Some(name) => proxy::url(&format!(
+            "/libraries/{}/dav",
+            utf8_percent_encode(name, SEGMENT)
+        )),
+        None => proxy::url("/dav"),
+    }
+}
+
+fn http_date(time: SystemTime) -> String {
+    DateTime::<Utc>::from(time)
+        .format("%a, %d %b %Y %H:%M:%S GMT")
+        .to_string()
+}
+
+fn collection(out: &mut String, base: &str, path: &[String]) {
+    let name = path.last().map_or("", |x| x.as_str());
+    let _ = write!(
+        out,
+        "<D:response><D:href>{}</D:href><D:propstat><D:prop>\
+        <D:displayname>{}</D:displayname><D:resourcetype><D:collection/></D:resourcetype>\
+        </D:prop><D:status>HTTP/1.1 200 OK</D:status></D:propstat></D:response>",
+        escape(&href(base, path, true)),
+        escape(name),
+    );
+}
+
+fn file(out: &mut String, base: &str, entry: &Entry) {
+    let name = entry.path.last().map_or("", |x| x.as_str());
+    let _ = write!(
+        out,
+        "<D:response><D:href>{}</D:href><D:propstat><D:prop>\
+        <D:displayname>{}</D:displayname><D:resourcetype/>\
+        <D:getcontenttype>{}</D:getcontenttype>",
+        escape(&href(base, &entry.path, false)),
+        escape(name),
+        escape(&entry.mime),
+    );
+    // The cuts not made yet have no size until they are fetched
+    if let Ok(metadata) = std::fs::metadata(&entry.file) {
+        let _ = write!(
+            out,
+            "<D:getcontentlength>{}</D:getcontentlength>",
+            metadata.len()
+        );
+        if let Ok(modified) = metadata.modified() {
+            let _ = write!(
+                out,
+                "<D:getlastmodified>{}</D:getlastmodified>",
+                http_date(modified)
+            );
+        }
+    }
+    out.push_str("</D:prop><D:status>HTTP/1.1 200 OK</D:status></D:propstat></D:response>");
+}
+
+/// The `207 Multi-Status` body answering a `PROPFIND` of `node` at `path`, listing what it holds
+/// unless `depth` is 0. `base` is the href of the root of the tree.
+pub fn multistatus(base: &str, path: &[String], node: &Node, depth: u8) -> String {
+    let mut out =
+        String::from(r#"<?xml version="1.0" encoding="utf-8"?><D:multistatus xmlns:D="DAV:">"#);
+    match node {
+        Node::File(entry) => file(&mut out, base, entry),
+        Node::Collection(collections, files) => {
+            collection(&mut out, base, path);
+            if depth > 0 {
+                for name in collections {
+                    collection(&mut out, base, &[path, std::slice::from_ref(name)].concat());
+                }
+                for entry in files {
+                    file(&mut out, base, entry);
+                }
+            }
+        }
+    }
+    out.push_str("</D:multistatus>");
+    out
+}
+
+/// Adds the WebDAV headers to the answers to `OPTIONS` in the tree, made by the CORS layer as
+/// it takes every `OPTIONS` for a preflight request. The clients look for `DAV` before
+/// browsing.
+pub async fn options(request: Request, next: Next) -> Response {
+    let path = request.uri().path();
+    let dav = request.method() == Method::OPTIONS && (path == "/dav" || path.starts_with("/dav/"));
+    let mut response = next.run(request).await;
+    if dav && response.status().is_success() {
+        let headers = response.headers_mut();
+        headers.insert("DAV", HeaderValue::from_static("1"));
+        headers.insert(ALLOW, HeaderValue::from_static(METHODS));
+    }
+    response
+}
+
+/// The pattern of the tree, `library.organize_pattern` or the default one when unset or invalid
+pub fn pattern(config: &lorconf::Config) -> Vec<Part> {
+    config
+        .library
+        .as_ref()
+        .and_then(|library| library.organize_pattern.clone())
+        .or(lorconf::Library::default().organize_pattern)
+        .and_then(|x| organize::parse(&x).ok())
+        .unwrap_or_else(|| organize::parse(organize::DEFAULT_PATTERN).unwrap_or_default())
+}
@@ -0,0 +1,123 @@
+use crate::daemon::global::{utils, Media};
+use crate::daemon::jobs::JobContext;
+use crate::daemon::snapshot::Snapshot;
+use lofty::config::WriteOptions;
+use lofty::file::FileType;
+use lofty::picture::{MimeType, Picture, PictureType};
+use lofty::prelude::*;
+use lofty::probe::Probe;
+use lofty::tag::Tag;
+use std::path::{Path, PathBuf};
+use tracing::{info, warn};
+
+/// Largest picture a format can carry.
+/// FLAC and Ogg picture blocks store their length on 24 bits, ID3v2 frames on 28 bits
+/// (synchsafe) and APE items are kept small as most readers load them whole.
+fn max_picture_size(file_type: FileType) -> usize {
+    match file_type {
+        FileType::Flac | FileType::Opus | FileType::Vorbis | FileType::Speex => (1 << 24) - 1,
+        FileType::Mpeg | FileType::Aiff | FileType::Wav | FileType::Aac => (1 << 28) - 1,
+        FileType::Ape | FileType::Mpc | FileType::WavPack => 8 * 1024 * 1024,
+        _ => 16 * 1024 * 1024,
+    }
+}
+
+fn picture_mime(path: &Path) -> MimeType {
+    match path
+        .extension()
+        .and_then(|x| x.to_str())
+        .map(|x| x.to_lowercase())
+        .as_deref()
+    {
+        Some("png") => MimeType::Png,
+        Some("gif") => MimeType::Gif,
+        Some("bmp") => MimeType::Bmp,
+        Some("tif") | Some("tiff") => MimeType::Tiff,
+        _ => MimeType::Jpeg,
+    }
+}
+
+/// Embeds `cover` as the front cover of `track` unless it already has one.
+/// Returns whether the file was modified.
+fn embed_cover(track: &Path, cover: &Path) -> Result<bool, Box<dyn std::error::Error>> {
+    let mut tagged_file = Probe::open(track)?.read()?;
+    let file_type = tagged_file.file_type();
+
+    if tagged_file.primary_tag().is_none() {
+        tagged_file.insert_tag(Tag::new(tagged_file.primary_tag_type()));
+    }
+    let tag = tagged_file.primary_tag_mut().unwrap();
+
+    if tag.get_picture_type(PictureType::CoverFront).is_some() {
+        return Ok(false);
+    }
+
+    let data = std::fs::read(cover)?;
+    if data.len() > max_picture_size(file_type) {
+        warn!(
+            "embed: `{}` is too large to be embedded in `{}`",
+            cover.display(),
+            track.display()
+        );
+        return Ok(false);
+    }
+
+    tag.push_picture(Picture::new_unchecked(
+        PictureType::CoverFront,
+        Some(picture_mime(cover)),
+        None,
+        data,
+    ));
+    tag.save_to_path(track, WriteOptions::default())?;
+
+    Ok(true)
+}
+
+/// Embeds the folder image of each track's directory into the tracks missing a front cover,
+/// until the job of `context` is cancelled
+pub fn embed_folder_covers(tracks: &[PathBuf], context: &JobContext) -> usize {
+    let mut embedded = 0;
+
+    for (i, track) in tracks.iter().enumerate() {
+        if context.cancel.is_cancelled() {
+            break;
+        }
+        context.progress(i, tracks.len());
+        let Some(cover) = track.parent().and_then(utils::find_folder_cover) else {
+            continue;
+        };
+
+        match embed_cover(track, &cover) {
+            Ok(true) => {
+                info!("embed: cover added to `{}`", track.display());
+                embedded += 1;
+            }
+            Ok(false) => {}
+            Err(e) => warn!("embed: unable to update `{}`: {e}", track.display()),
+        }
+    }
+
+    embedded
+}
+
+pub fn enabled(config: &lorconf::Config) -> bool {
+    config
+        .library
+        .as_ref()
+        .and_then(|library| library.embed_covers)
+        .unwrap_or(false)
+}
+
+/// Runs [`embed_folder_covers`] over `media`, as a job
+pub async fn run(media: &Snapshot<Media>, context: JobContext) -> Result<(), String> {
+    let tracks = {
+        let media = media.load();
+        media.audio_files(&media.tracks.keys().cloned().collect::<Vec<_>>())
+    };
+    let embedded = tokio::task::spawn_blocking(move || embed_folder_covers(&tracks, &context))
+        .await
+        .map_err(|e| e.to_string())?;
+    info!("embed: {embedded} covers embedded");
+
+    Ok(())
+}
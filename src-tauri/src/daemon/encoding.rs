@@ -0,0 +1,83 @@
+//! Encodings of the large responses, chosen with the `Accept` header: MessagePack and CBOR
+//! are quicker to decode than JSON for big libraries. Their `SystemTime`s are milliseconds
+//! since the epoch, see `mu_protocol::time`.
+
+use axum::{
+    http::{
+        header::{ACCEPT, CONTENT_TYPE, VARY},
+        HeaderMap, HeaderValue, StatusCode,
+    },
+    response::{IntoResponse, Response},
+    Json,
+};
+use tracing::warn;
+
+pub const MSGPACK: &str = "application/msgpack";
+pub const CBOR: &str = "application/cbor";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Encoding {
+    Json,
+    MessagePack,
+    Cbor,
+}
+
+impl Encoding {
+    /// The first binary encoding named by the `Accept` header, JSON otherwise
+    pub fn negotiate(headers: &HeaderMap) -> Self {
+        let accepted = headers
+            .get_all(ACCEPT)
+            .iter()
+            .filter_map(|x| x.to_str().ok())
+            .flat_map(|x| x.split(','))
+            .map(|x| x.split(';').next().unwrap_or_default().trim());
+        for mime in accepted {
+            match mime {
+                MSGPACK | "application/x-msgpack" | "application/vnd.msgpack" => {
+                    return Self::MessagePack
+                }
+                CBOR => return Self::Cbor,
+                _ => {}
+            }
+        }
+
+        Self::Json
+    }
+
+    /// `value` encoded as negotiated, with `Vary: Accept` for caches
+    pub fn respond<T: serde::Serialize>(self, value: &T) -> Response {
+        let encoded = match self {
+            Self::Json => Ok(None),
+            Self::MessagePack => rmp_serde::to_vec_named(value)
+                .map(|x| Some((x, MSGPACK)))
+                .map_err(|e| e.to_string()),
+            Self::Cbor => {
+                let mut data = vec![];
+                ciborium::into_writer(value, &mut data)
+                    .map(|_| Some((data, CBOR)))
+                    .map_err(|e| e.to_string())
+            }
+        };
+
+        let mut response = match encoded {
+            Ok(None) => Json(value).into_response(),
+            Ok(Some((data, mime))) => {
+                let mut response = data.into_response();
+                response
+                    .headers_mut()
+                    .insert(CONTENT_TYPE, HeaderValue::from_static(mime));
+                response
+            }
+            Err(e) => {
+                warn!("Unable to encode a response as {self:?}: {e}");
+                let mut response = format!("unable to encode the response: {e}").into_response();
+                *response.status_mut() = StatusCode::INTERNAL_SERVER_ERROR;
+                response
+            }
+        };
+        response
+            .headers_mut()
+            .insert(VARY, HeaderValue::from_static("accept"));
+        response
+    }
+}
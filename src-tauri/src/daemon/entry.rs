@@ -1,58 +1,723 @@
+use crate::daemon::analysis;
+use crate::daemon::archive;
+use crate::daemon::artists;
+use crate::daemon::artwork::{self, Artwork};
+use crate::daemon::audit;
+use crate::daemon::backup::{self, Locations};
+use crate::daemon::bookmarks::Bookmarks;
+use crate::daemon::changes;
+use crate::daemon::composers;
 use crate::daemon::config;
-use crate::daemon::config::Dir;
-use crate::daemon::global::Media;
+use crate::daemon::config::{Dir, SharedConfig};
+use crate::daemon::cors;
+use crate::daemon::covers;
+use crate::daemon::cue;
+use crate::daemon::dav;
+use crate::daemon::embed;
+use crate::daemon::encoding::Encoding;
+use crate::daemon::events;
+use crate::daemon::favorites::Favorites;
+use crate::daemon::fileinfo;
+use crate::daemon::gc;
+use crate::daemon::global::{Media, ScanOptions};
+use crate::daemon::history::{self, History};
+use crate::daemon::hls::{self, HlsSessions};
+use crate::daemon::ids::{AlbumId, PlaylistId, TrackId};
+use crate::daemon::ingest;
+use crate::daemon::issues;
+use crate::daemon::jobs::{JobContext, Jobs, Run};
+use crate::daemon::libraries::{self, Libraries, Library, Viewer};
+use crate::daemon::limits::{self, Client, RateLimiter, StreamLimiter};
+use crate::daemon::listen;
+use crate::daemon::listen_later::ListenLater;
+use crate::daemon::listens;
+use crate::daemon::lite;
+use crate::daemon::lrc;
+use crate::daemon::migrations;
+use crate::daemon::ndjson;
+use crate::daemon::openapi::{self, LibraryHeader};
+use crate::daemon::organize;
+use crate::daemon::palette;
+use crate::daemon::pictures;
+use crate::daemon::playback::{self, Playback};
+use crate::daemon::playlist;
+use crate::daemon::positions::Positions;
+use crate::daemon::proxy;
+use crate::daemon::queue::Queue;
+use crate::daemon::radio;
+use crate::daemon::random;
+use crate::daemon::reconcile::Fingerprints;
+use crate::daemon::remote;
+use crate::daemon::rescan;
+use crate::daemon::scan::ScanState;
+use crate::daemon::seek;
+use crate::daemon::sessions::{self, Sessions};
+use crate::daemon::shutdown;
+use crate::daemon::similar;
+use crate::daemon::stats;
+use crate::daemon::sync;
+use crate::daemon::systemd;
+use crate::daemon::tags;
+use crate::daemon::timers::{self, Timers};
+use crate::daemon::tls;
+use crate::daemon::trail::{self, AuditTrail};
+use crate::daemon::users::{self, Users};
 use crate::daemon::utils;
+use crate::daemon::webapp;
+use crate::daemon::webhooks;
 use axum::{
+    async_trait,
     body::Body,
-    extract::{Path, Query, State},
-    http::{header::CACHE_CONTROL, HeaderValue, StatusCode},
-    response::{IntoResponse, Response},
-    routing::{get, put},
+    extract::{ConnectInfo, DefaultBodyLimit, FromRequestParts, Path, Query, Request, State},
+    http::{
+        header::{
+            ALLOW, CACHE_CONTROL, CONTENT_DISPOSITION, CONTENT_LENGTH, CONTENT_TYPE, IF_NONE_MATCH,
+            RETRY_AFTER,
+        },
+        request::Parts,
+        HeaderMap, HeaderName, HeaderValue, Method, StatusCode,
+    },
+    middleware::{self, Next},
+    response::{IntoResponse, Redirect, Response},
+    routing::{any, delete, get, patch, post, put},
     Json, Router,
 };
-use axum_extra::{extract::OptionalQuery, headers::Range, TypedHeader};
+use axum_extra::{
+    headers::{
+        AcceptRanges, ContentLength, ETag, HeaderMapExt, IfModifiedSince, IfNoneMatch,
+        LastModified, Range,
+    },
+    TypedHeader,
+};
 use axum_range::{KnownSize, Ranged};
-use base64::{engine::general_purpose::URL_SAFE, Engine as _};
-use image::io::Reader as ImageReader;
+use axum_server::tls_rustls::RustlsConfig;
+use futures::future::BoxFuture;
+use http_body_util::Limited;
+use mu_protocol::api::{
+    AlbumTheme, Artist, ArtistAlbumsQuery, AuditEntry, AuditQuery, AuditReport, BatchFailure,
+    BatchRequest, BatchResult, CacheStats, ChangesQuery, Composer, CoverQuery, HistoryQuery,
+    HostedSession, ImportReport, Job, JobKind, JobState, LaterKind, LibraryInfo, MusicPath,
+    NewBookmark, NewLaterEntry, NewLyrics, NewPosition, NewQueue, NewSession, NewTimer, NewUser,
+    OrganizeQuery, OrganizeReport, PlayQueue, PlayRequest, PlaybackChange, PlaybackHints,
+    PlaybackPreferences, PlaybackTarget, PlaylistFolder, QualityQuery, QueueRequest, QueuedTracks,
+    RadioRequest, RadioSeed, RandomAlbumQuery, RandomTracks, RandomTracksQuery, RecentKind,
+    RecentQuery, Role, ScanQuery, ScanStatus, SearchQuery, SearchScope, SeekTableQuery, Session,
+    SessionPlayback, SimilarQuery, SimilarTrack, Stats, SyncDownload, SyncQuery, Timer,
+    TimerAction, TrackList, TracksQuery, User, UserChanges,
+};
+use mu_protocol::events::Event;
+use mu_protocol::library::Track;
+use mu_protocol::lite::{LiteAlbum, LiteQuery, LiteResults, LiteTrack};
 use socketioxide::{
-    extract::{Data, SocketRef},
+    extract::{Data, SocketRef, TryData},
+    layer::SocketIoLayer,
     SocketIo,
 };
-use std::io::{BufWriter, Cursor, Read};
+use std::future::IntoFuture;
+use std::io::Cursor;
+use std::net::SocketAddr;
 use std::sync::Arc;
 use tokio::fs::File;
 use tokio::sync::RwLock;
+use tokio_util::sync::CancellationToken;
 use tower::ServiceBuilder;
-use tower_http::cors::CorsLayer;
-use tracing::{info, warn};
+use tower_http::compression::CompressionLayer;
+use tracing::{debug, field, info, info_span, warn, Instrument};
+use utoipa::OpenApi;
 
 #[derive(Debug, Clone)]
 struct AppData {
-    media: Arc<RwLock<Media>>,
+    libraries: Arc<Libraries>,
+    /// Library the request is scoped to, the default one outside of [`Scoped`]
+    library: Arc<Library>,
     dirs: Dir,
     io: SocketIo,
+    hls: Arc<HlsSessions>,
+    artwork: Arc<Artwork>,
+    config: SharedConfig,
+    bookmarks: Arc<RwLock<Bookmarks>>,
+    positions: Arc<RwLock<Positions>>,
+    streams: Arc<StreamLimiter>,
+    requests: Arc<RateLimiter>,
+    listen_later: Arc<RwLock<ListenLater>>,
+    history: Arc<RwLock<History>>,
+    favorites: Arc<RwLock<Favorites>>,
+    queue: Arc<RwLock<Queue>>,
+    playback: Arc<RwLock<Playback>>,
+    timers: Arc<RwLock<Timers>>,
+    trail: Arc<RwLock<AuditTrail>>,
+    jobs: Arc<Jobs>,
+    sessions: Arc<Sessions>,
+    users: Arc<Users>,
+    /// Account the request is made by, `None` for the default user, see [`Scoped`]
+    user: Option<String>,
+    /// Whether the user of the request leaves the explicit tracks out, see [`Scoped`]
+    hide_explicit: bool,
+    shutdown: CancellationToken,
+}
+
+/// Who the request of `headers` is made by, `user` being the account of its token
+async fn viewer<'a>(state: &AppData, user: Option<&'a User>, headers: &HeaderMap) -> Viewer<'a> {
+    match (user, users::bearer(headers)) {
+        (Some(user), _) => Viewer::Account(user),
+        (None, Some(token)) if shutdown::is_admin_token(&*state.config.read().await, token) => {
+            Viewer::Admin
+        }
+        _ => Viewer::Default,
+    }
+}
+
+/// The state with the library named by the `X-Library` header, see [`libraries`], and the
+/// stores of the account whose token the request carries, see [`users`]
+struct Scoped(AppData);
+
+#[async_trait]
+impl FromRequestParts<AppData> for Scoped {
+    type Rejection = Response;
+
+    async fn from_request_parts(parts: &mut Parts, state: &AppData) -> Result<Self, Response> {
+        let name = match parts.headers.get(libraries::LIBRARY) {
+            Some(value) => match value.to_str() {
+                Ok(name) => Some(name),
+                Err(_) => {
+                    let mut response = "invalid library name".into_response();
+                    *response.status_mut() = StatusCode::BAD_REQUEST;
+                    return Err(response);
+                }
+            },
+            None => None,
+        };
+
+        let user = users::bearer(&parts.headers).and_then(|x| state.users.authenticate(x));
+        // The libraries a request doesn't see answer like the ones that don't exist
+        let viewer = viewer(state, user.as_ref(), &parts.headers).await;
+        let Some(library) = state.libraries.seen(name, &viewer) else {
+            let mut response =
+                format!("no library named {}", name.unwrap_or_default()).into_response();
+            *response.status_mut() = StatusCode::NOT_FOUND;
+            return Err(response);
+        };
+
+        let mut scoped = AppData {
+            library: Arc::clone(library),
+            hide_explicit: users::hide_explicit(&*state.config.read().await),
+            ..state.clone()
+        };
+        if let Some(token) = users::bearer(&parts.headers) {
+            match user {
+                Some(user) => {
+                    let data = state.users.data(&user.name);
+                    scoped.favorites = data.favorites;
+                    scoped.history = data.history;
+                    scoped.queue = data.queue;
+                    scoped.bookmarks = data.bookmarks;
+                    scoped.positions = data.positions;
+                    scoped.listen_later = data.listen_later;
+                    scoped.hide_explicit = user.hide_explicit;
+                    scoped.user = Some(user.name);
+                }
+                // The admin token stays on the default user
+                None if shutdown::is_admin_token(&*state.config.read().await, token) => {}
+                None => {
+                    let mut response = "unknown token".into_response();
+                    *response.status_mut() = StatusCode::UNAUTHORIZED;
+                    return Err(response);
+                }
+            }
+        }
+
+        Ok(Scoped(scoped))
+    }
 }
 
-#[derive(Debug, serde::Serialize, serde::Deserialize)]
-struct SearchQuery {
-    q: String,
+/// Emits an event about the data of the account of the request to its sockets only, to every
+/// socket for the default user
+fn emit_user<T: serde::Serialize>(state: &AppData, event: Event, data: T) {
+    match &state.user {
+        Some(user) => events::emit_to(&state.io, users::room(user), event, data),
+        None => events::emit(&state.io, event, data),
+    }
 }
 
-async fn on_connect(socket: SocketRef) {
+#[derive(OpenApi)]
+#[openapi(
+    info(title = "lorchestre"),
+    paths(
+        healthz,
+        readyz,
+        start_scan,
+        scan_status,
+        scan_last,
+        jobs_list,
+        job,
+        cancel_job,
+        media,
+        media_changes,
+        media_stream,
+        album,
+        album_theme,
+        album_artwork,
+        album_artwork_image,
+        search,
+        track_bookmarks,
+        add_bookmark,
+        remove_bookmark,
+        track_position,
+        save_position,
+        remove_position,
+        track_played,
+        track_info,
+        similar_tracks,
+        track_lyrics,
+        save_lyrics,
+        player_play,
+        player_queue,
+        play_queue,
+        save_queue,
+        start_radio,
+        stop_radio,
+        player_preferences,
+        album_preferences,
+        save_album_preferences,
+        remove_album_preferences,
+        playlist_preferences,
+        save_playlist_preferences,
+        remove_playlist_preferences,
+        library_stats,
+        seek_table,
+        browse_recent,
+        artists_list,
+        artist_albums,
+        artist_image,
+        composers_list,
+        composer_tracks,
+        album_works,
+        tracks_list,
+        random_tracks,
+        random_album,
+        listen_later_list,
+        listen_later_add,
+        listen_later_remove,
+        favorites_list,
+        favorites_add,
+        favorites_remove,
+        get_config,
+        patch_config,
+        updatemusic,
+        last_audit,
+        cache_stats,
+        cache_gc,
+        library_issues,
+        batch_tags,
+        organize_library,
+        libraries_list,
+        shutdown_daemon,
+        export_library,
+        import_library,
+        users_list,
+        create_user,
+        change_user,
+        delete_user,
+        renew_user_token,
+        audit_trail,
+        me,
+        history_export,
+        history_import,
+        sessions_list,
+        create_session,
+        session,
+        end_session,
+        vote_session_track,
+        unvote_session_track,
+        session_playback,
+        next_session_track,
+        timers_list,
+        create_timer,
+        remove_timer,
+        audio_by_path,
+        audio,
+        hls_playlist,
+        hls_segment,
+        album_download,
+        playlist_download,
+        sync_manifest,
+        sync_download,
+        playlists_tree,
+        playlist_cover,
+        cover,
+    ),
+    components(schemas(
+        Media,
+        mu_protocol::SystemTime,
+        mu_protocol::library::Track,
+        mu_protocol::library::Album,
+        mu_protocol::library::Playlist,
+        mu_protocol::library::LyricLine,
+        mu_protocol::library::LyricWord,
+        mu_protocol::library::Credit,
+        mu_protocol::library::Color,
+        mu_protocol::library::Palette,
+        mu_protocol::api::AlbumTheme,
+        mu_protocol::library::Bookmark,
+        mu_protocol::library::SavedPosition,
+        mu_protocol::library::MediaKind,
+        mu_protocol::library::Gapless,
+        mu_protocol::library::Quality,
+        mu_protocol::library::QualitySummary,
+        mu_protocol::api::SearchResults,
+        mu_protocol::api::SearchScope,
+        mu_protocol::api::LyricsMatch,
+        mu_protocol::api::ChangedIds,
+        mu_protocol::api::MediaChanges,
+        mu_protocol::api::NewPosition,
+        mu_protocol::api::NewBookmark,
+        mu_protocol::api::LaterKind,
+        mu_protocol::api::LaterEntry,
+        mu_protocol::api::NewLaterEntry,
+        mu_protocol::api::Recent,
+        mu_protocol::api::RecentKind,
+        mu_protocol::api::TrackRandomTracks,
+        mu_protocol::api::LiteRandomTracks,
+        mu_protocol::api::TrackSort,
+        mu_protocol::api::TrackTrackList,
+        mu_protocol::api::LiteTrackList,
+        mu_protocol::api::TrackSimilarTrack,
+        mu_protocol::api::LiteSimilarTrack,
+        mu_protocol::api::AlbumSort,
+        mu_protocol::api::Artist,
+        mu_protocol::api::Composer,
+        mu_protocol::api::AlbumWork,
+        mu_protocol::api::AlbumArtwork,
+        mu_protocol::api::PlaylistFolder,
+        mu_protocol::api::SeekTable,
+        mu_protocol::api::EmbeddedPicture,
+        mu_protocol::api::TrackInfo,
+        mu_protocol::api::TrackLyrics,
+        mu_protocol::api::NewLyrics,
+        mu_protocol::api::LyricsTranslation,
+        mu_protocol::api::AuditReport,
+        mu_protocol::api::CacheGcReport,
+        mu_protocol::api::CacheStats,
+        mu_protocol::api::IssueCode,
+        mu_protocol::api::LibraryIssue,
+        mu_protocol::api::BatchOperation,
+        mu_protocol::api::BatchRequest,
+        mu_protocol::api::BatchFailure,
+        mu_protocol::api::BatchResult,
+        mu_protocol::api::OrganizeMove,
+        mu_protocol::api::OrganizeSkip,
+        mu_protocol::api::OrganizeReport,
+        mu_protocol::api::LibraryInfo,
+        mu_protocol::api::Stats,
+        mu_protocol::api::ScanStatus,
+        mu_protocol::api::ScanProgress,
+        mu_protocol::api::LastScan,
+        mu_protocol::api::ScanError,
+        mu_protocol::api::Reconciliation,
+        mu_protocol::api::Job,
+        mu_protocol::api::JobKind,
+        mu_protocol::api::JobState,
+        mu_protocol::api::JobProgress,
+        mu_protocol::api::ImportReport,
+        mu_protocol::api::Role,
+        mu_protocol::api::User,
+        mu_protocol::api::NewUser,
+        mu_protocol::api::UserChanges,
+        mu_protocol::api::AuditEntry,
+        mu_protocol::api::HistoryFormat,
+        mu_protocol::api::ExportedPlay,
+        mu_protocol::api::HistoryImportReport,
+        mu_protocol::api::IdKind,
+        mu_protocol::api::InvalidId,
+        mu_protocol::api::SyncKind,
+        mu_protocol::api::SyncFile,
+        mu_protocol::api::SyncPlaylist,
+        mu_protocol::api::SyncManifest,
+        mu_protocol::api::SyncDownload,
+        mu_protocol::api::UserToken,
+        mu_protocol::api::PlayRequest,
+        mu_protocol::api::QueueRequest,
+        mu_protocol::api::NewQueue,
+        mu_protocol::api::PlayQueue,
+        mu_protocol::api::RadioSeed,
+        mu_protocol::api::RadioRequest,
+        mu_protocol::api::PlaybackHints,
+        mu_protocol::api::PlaybackPreferences,
+        mu_protocol::api::PlaybackTarget,
+        mu_protocol::api::PlaybackChange,
+        mu_protocol::api::NewSession,
+        mu_protocol::api::Session,
+        mu_protocol::api::SessionEntry,
+        mu_protocol::api::HostedSession,
+        mu_protocol::api::SessionPlayback,
+        mu_protocol::api::SessionSync,
+        mu_protocol::api::TimerAction,
+        mu_protocol::api::Weekday,
+        mu_protocol::api::NewTimer,
+        mu_protocol::api::Timer,
+        mu_protocol::api::FiredTimer,
+        mu_protocol::lite::LiteTrack,
+        mu_protocol::lite::LiteAlbum,
+        mu_protocol::lite::LiteAlbumTracks,
+        mu_protocol::lite::LitePlaylist,
+        mu_protocol::lite::LiteResults,
+    )),
+    modifiers(&LibraryHeader)
+)]
+struct ApiDoc;
+
+async fn on_connect(
+    socket: SocketRef,
+    TryData(auth): TryData<serde_json::Value>,
+    users: socketioxide::extract::State<Arc<Users>>,
+    libraries: socketioxide::extract::State<Arc<Libraries>>,
+) {
     info!("socket connected: {}", socket.id);
+    let user = users.join_room(&socket, &auth.unwrap_or_default());
+    libraries.hide(&socket, &Viewer::socket(user.as_ref()));
+    sessions::listen(&socket);
+    events::listen(&socket);
 
     socket.on(
         "search",
-        |sock: SocketRef,
-         Data::<String>(q),
-         media: socketioxide::extract::State<Arc<RwLock<Media>>>| async move {
-            let m = media.read().await;
+        move |sock: SocketRef,
+              Data::<String>(q),
+              libraries: socketioxide::extract::State<Arc<Libraries>>| async move {
+            // The first library the socket sees, nothing if it sees none
+            let Some(library) = libraries.seen(None, &Viewer::socket(user.as_ref())) else {
+                return;
+            };
+            let m = library.media.load();
             let res = m.search(&q);
-            let _ = sock.emit("searchresponse", res);
+            let _ = sock.emit(Event::SearchResponse.name(), res);
         },
     )
 }
 
+/// The state of the daemon read from `dirs`, with the layer serving its socket.io clients and
+/// the jobs left unfinished by the previous run
+fn load_state(
+    dirs: &Dir,
+    config: lorconf::Config,
+    shutdown: &CancellationToken,
+) -> (AppData, SocketIoLayer, Vec<Job>) {
+    let bookmarks = Bookmarks::load(dirs.app.join("bookmarks.json"));
+    let positions = Positions::load(dirs.app.join("positions.json"));
+    let listen_later = ListenLater::load(dirs.app.join("listen_later.json"));
+    let history = History::load(dirs.app.join("history.json"));
+    let favorites = Favorites::load(dirs.app.join("favorites.json"));
+    let queue = Queue::load(dirs.app.join("queue.json"));
+    let profiles = libraries::profiles(&config, &dirs.cache);
+    let cache_dirs: Vec<_> = profiles.iter().map(|x| x.cache_dir.clone()).collect();
+    migrations::run(&dirs.cache, &cache_dirs);
+    // Served from the cache until scanned, empty without one, see `scan_library`
+    let mut loaded = vec![];
+    for (i, profile) in profiles.into_iter().enumerate() {
+        let mut m = utils::read_cache(&profile.cache_dir).unwrap_or_default();
+        // Caches written before the tracks had ids
+        m.assign_track_ids();
+        loaded.push(Library::new(profile, m, ScanState::default(), i == 0));
+    }
+    let libraries = Arc::new(Libraries::new(loaded));
+
+    let users = Arc::new(Users::load(&dirs.app));
+    let sessions = Arc::new(Sessions::default());
+    let (layer, io) = SocketIo::builder()
+        .with_state(Arc::clone(&libraries))
+        .with_state(Arc::clone(&users))
+        .with_state(Arc::clone(&sessions))
+        .build_layer();
+    io.ns("/", on_connect);
+    events::register_namespaces(&io);
+
+    let max_jobs = config
+        .library
+        .as_ref()
+        .and_then(|library| library.max_jobs)
+        .or(lorconf::Library::default().max_jobs)
+        .unwrap_or_default();
+    let (jobs, unfinished) = Jobs::load(
+        dirs.app.join("jobs.json"),
+        max_jobs,
+        io.clone(),
+        shutdown.clone(),
+    );
+
+    let state = AppData {
+        library: Arc::clone(libraries.default()),
+        libraries,
+        dirs: dirs.clone(),
+        io,
+        hls: Arc::new(HlsSessions::default()),
+        artwork: Arc::new(Artwork::default()),
+        config: Arc::new(RwLock::new(config)),
+        bookmarks: Arc::new(RwLock::new(bookmarks)),
+        positions: Arc::new(RwLock::new(positions)),
+        streams: Arc::new(StreamLimiter::default()),
+        requests: Arc::new(RateLimiter::default()),
+        listen_later: Arc::new(RwLock::new(listen_later)),
+        history: Arc::new(RwLock::new(history)),
+        favorites: Arc::new(RwLock::new(favorites)),
+        queue: Arc::new(RwLock::new(queue)),
+        playback: Arc::new(RwLock::new(Playback::load(dirs.app.join("playback.json")))),
+        timers: Arc::new(RwLock::new(Timers::load(dirs.app.join("timers.json")))),
+        trail: Arc::new(RwLock::new(AuditTrail::load(dirs.app.join("audit.jsonl")))),
+        jobs: Arc::new(jobs),
+        sessions,
+        users,
+        user: None,
+        hide_explicit: false,
+        shutdown: shutdown.clone(),
+    };
+
+    (state, layer, unfinished)
+}
+
+/// The routes of the daemon, their paths are rewritten by [`proxy::strip_base`] and
+/// [`libraries::scope_prefix`] first
+fn routes(state: AppData, layer: SocketIoLayer) -> Router {
+    let streams = Router::new()
+        .route("/audio", get(audio_by_path))
+        .route("/audio/:id", get(audio))
+        .route("/audio/:id/hls/playlist.m3u8", get(hls_playlist))
+        .route("/audio/:id/hls/:segment", get(hls_segment))
+        .route("/album/:id/download", get(album_download))
+        .route("/playlist/:id/download", get(playlist_download))
+        .route("/sync/download", post(sync_download))
+        .route("/dav", any(dav_tree))
+        .route("/dav/", any(dav_tree))
+        .route("/dav/*path", any(dav_tree))
+        .route_layer(middleware::from_fn_with_state(state.clone(), limit_streams))
+        .route("/cover/:handle", get(cover))
+        .route("/artist/:id/image", get(artist_image));
+
+    // Audio, covers and archives are already compressed and must keep their byte ranges
+    Router::new()
+        // Kept for the clients pinging it
+        .route("/", get(healthz))
+        .route("/healthz", get(healthz))
+        .route("/readyz", get(readyz))
+        .route("/scan", post(start_scan))
+        .route("/scan/status", get(scan_status))
+        .route("/scan/last", get(scan_last))
+        .route("/jobs", get(jobs_list))
+        .route("/jobs/:id", get(job).delete(cancel_job))
+        .route("/media", get(media))
+        .route("/media/changes", get(media_changes))
+        .route("/media/stream", get(media_stream))
+        .route("/album/:id", get(album))
+        .route("/album/:id/theme", get(album_theme))
+        .route("/album/:id/artwork", get(album_artwork))
+        .route("/album/:id/artwork/:name", get(album_artwork_image))
+        .route("/album/:id/works", get(album_works))
+        .route("/playlists/tree", get(playlists_tree))
+        .route("/playlist/:id/cover", get(playlist_cover))
+        .route("/sync/manifest", get(sync_manifest))
+        .route("/search", get(search))
+        .route(
+            "/track/:id/bookmarks",
+            get(track_bookmarks).post(add_bookmark),
+        )
+        .route("/track/:id/bookmarks/:bookmark", delete(remove_bookmark))
+        .route(
+            "/track/:id/position",
+            get(track_position)
+                .put(save_position)
+                .delete(remove_position),
+        )
+        .route("/track/:id/played", post(track_played))
+        .route("/track/:id/info", get(track_info))
+        .route("/track/:id/similar", get(similar_tracks))
+        .route("/track/:id/lyrics", put(save_lyrics))
+        .route("/lyrics/:id", get(track_lyrics))
+        .route("/player/play", post(player_play))
+        .route("/player/queue", post(player_queue))
+        .route("/queue", get(play_queue).put(save_queue))
+        .route("/queue/radio", post(start_radio).delete(stop_radio))
+        .route("/player/preferences", get(player_preferences))
+        .route("/sessions", get(sessions_list).post(create_session))
+        .route("/sessions/:id", get(session).delete(end_session))
+        .route("/sessions/:id/queue", post(vote_session_track))
+        .route("/sessions/:id/queue/:track", delete(unvote_session_track))
+        .route("/sessions/:id/playback", put(session_playback))
+        .route("/sessions/:id/next", post(next_session_track))
+        .route("/timers", get(timers_list).post(create_timer))
+        .route("/timers/:id", delete(remove_timer))
+        .route(
+            "/album/:id/preferences",
+            get(album_preferences)
+                .put(save_album_preferences)
+                .delete(remove_album_preferences),
+        )
+        .route(
+            "/playlist/:id/preferences",
+            get(playlist_preferences)
+                .put(save_playlist_preferences)
+                .delete(remove_playlist_preferences),
+        )
+        .route("/stats", get(library_stats))
+        .route("/audio/:id/seektable", get(seek_table))
+        .route("/browse/recent", get(browse_recent))
+        .route("/artists", get(artists_list))
+        .route("/artist/:id/albums", get(artist_albums))
+        .route("/composers", get(composers_list))
+        .route("/composer/:id/tracks", get(composer_tracks))
+        .route("/tracks", get(tracks_list))
+        .route("/random/tracks", get(random_tracks))
+        .route("/random/album", get(random_album))
+        .route(
+            "/listen-later",
+            get(listen_later_list).post(listen_later_add),
+        )
+        .route("/listen-later/:kind/:id", delete(listen_later_remove))
+        .route("/favorites", get(favorites_list))
+        .route(
+            "/favorites/:id",
+            put(favorites_add).delete(favorites_remove),
+        )
+        .route("/config", get(get_config).patch(patch_config))
+        .route("/updatemusic", put(updatemusic))
+        .route("/audit", get(last_audit))
+        .route("/cache/stats", get(cache_stats))
+        .route("/cache/gc", post(cache_gc))
+        .route("/library/issues", get(library_issues))
+        .route("/tracks/batch", post(batch_tags))
+        .route("/organize", post(organize_library))
+        .route("/libraries", get(libraries_list))
+        .route("/shutdown", post(shutdown_daemon))
+        .route("/admin/users", get(users_list).post(create_user))
+        .route("/admin/users/:name", patch(change_user).delete(delete_user))
+        .route("/admin/users/:name/token", post(renew_user_token))
+        .route("/admin/audit", get(audit_trail))
+        .route("/me", get(me))
+        .route("/history/export", get(history_export))
+        .route("/history/import", post(history_import))
+        .route("/export", get(export_library))
+        .route("/import", post(import_library))
+        .route("/openapi.json", get(openapi_json))
+        .route("/docs", get(openapi::swagger_ui))
+        .route("/app", get(web_app_redirect))
+        .route("/app/", get(web_app_index))
+        .route("/app/*path", get(web_app))
+        .layer(CompressionLayer::new())
+        .merge(streams)
+        // The limit follows `network.max_body_size` in `limit_requests`
+        .layer(DefaultBodyLimit::disable())
+        .layer(middleware::from_fn_with_state(
+            state.clone(),
+            limit_requests,
+        ))
+        .layer(middleware::from_fn_with_state(state.clone(), log_requests))
+        .with_state(state)
+        .layer(
+            ServiceBuilder::new()
+                .layer(middleware::from_fn(dav::options))
+                .layer(cors::layer())
+                .layer(middleware::from_fn(cors::guard))
+                .layer(layer),
+        )
+}
+
 pub async fn start() -> Result<(), Box<dyn std::error::Error>> {
     let mut host = "localhost".to_string();
     let mut port: u32 = 7700;
@@ -60,7 +725,7 @@ pub async fn start() -> Result<(), Box<dyn std::error::Error>> {
     let dirs = config::get_dirs();
     let config_path = dirs.config.join("config.toml");
     let config = lorconf::Config::get(&config_path);
-    if let Some(network) = config.network {
+    if let Some(network) = config.network.clone() {
         if let Some(p) = network.port {
             port = p;
         }
@@ -69,163 +734,4165 @@ pub async fn start() -> Result<(), Box<dyn std::error::Error>> {
             host = h;
         }
     }
+    let port = u16::try_from(port).map_err(|_| format!("invalid network.port {port}"))?;
+    let addresses = listen::resolve(&host, port).await;
+    let extra = listen::extra(&config).await;
+    let unix_socket = config.network.as_ref().and_then(|x| x.unix_socket.clone());
+    let tls_files = tls::files(&config);
+    proxy::init(&config);
+    remote::init(&config);
+    webhooks::init(&config);
+    cors::configure(&config);
 
-    let req_client = reqwest::Client::new();
-    let response = req_client.get(format!("http://{host}:{port}")).send().await;
-    if let Ok(_) = response {
+    // Under socket activation the port is held by systemd until we accept on it
+    let activated = systemd::activated_listener();
+    if activated.is_none() && listen::taken(&addresses).await {
         tracing::error!("Daemon already running");
         return Ok(());
+    }
+
+    let shutdown = CancellationToken::new();
+    changes::start();
+    events::start();
+    tokio::spawn(shutdown::watch_signals(shutdown.clone()));
+
+    let (state, layer, unfinished) = load_state(&dirs, config, &shutdown);
+
+    let mut resumed = vec![];
+    // The scans of the libraries on start, the daemon is ready once they are over
+    let mut scans = vec![];
+    for job in unfinished {
+        let Some(library) = state.libraries.get(Some(&job.library)) else {
+            warn!(
+                "Dropping the job {}, the library {} is gone",
+                job.id, job.library
+            );
+            continue;
+        };
+        let scoped = AppData {
+            library: Arc::clone(library),
+            ..state.clone()
+        };
+        info!("Resuming the job {} ({:?})", job.id, job.kind);
+        if job.kind == JobKind::Scan {
+            resumed.push(job.library.clone());
+        }
+        let scan = job.kind == JobKind::Scan;
+        let run = job_run(&scoped, job.kind, job.target.clone());
+        let (_, done) = state.jobs.resume(job, run);
+        if scan {
+            scans.push(done);
+        }
+    }
+    // The covers are embedded and the audio analysed once scanned
+    for library in state.libraries.all() {
+        if resumed.contains(&library.name) {
+            continue;
+        }
+        let scoped = AppData {
+            library: Arc::clone(library),
+            ..state.clone()
+        };
+        scans.push(submit_job(&scoped, JobKind::Scan, None).1);
+    }
+
+    tokio::spawn(config::watch(
+        config_path,
+        Arc::clone(&state.config),
+        state.io.clone(),
+    ));
+    tokio::spawn(hls::reap(Arc::clone(&state.hls), Arc::clone(&state.config)));
+    tokio::spawn(sessions::sync(
+        Arc::clone(&state.sessions),
+        state.io.clone(),
+    ));
+    tokio::spawn(timers::run(
+        Arc::clone(&state.timers),
+        Arc::clone(&state.libraries),
+        state.io.clone(),
+    ));
+    for library in state.libraries.all() {
+        tokio::spawn(audit::schedule(
+            Arc::clone(library),
+            Arc::clone(&state.config),
+            state.io.clone(),
+            Arc::clone(&state.jobs),
+        ));
+        tokio::spawn(gc::schedule(
+            Arc::clone(library),
+            Arc::clone(&state.config),
+            state.io.clone(),
+            Arc::clone(&state.jobs),
+        ));
+        let scoped = AppData {
+            library: Arc::clone(library),
+            ..state.clone()
+        };
+        tokio::spawn(rescan::schedule(
+            Arc::clone(library),
+            Arc::clone(&state.config),
+            move || submit_job(&scoped, JobKind::Scan, None).1,
+        ));
+        tokio::spawn(palette::worker(
+            Arc::clone(library),
+            Arc::clone(&state.config),
+            state.io.clone(),
+        ));
+        tokio::spawn(artwork::watch(
+            Arc::clone(library),
+            Arc::clone(&state.config),
+            state.io.clone(),
+            Arc::clone(&state.artwork),
+        ));
+    }
+    tokio::spawn(ingest::watch(
+        Arc::clone(state.libraries.default()),
+        Arc::clone(&state.config),
+        state.io.clone(),
+        Arc::clone(&state.jobs),
+    ));
+
+    let stopping = {
+        let shutdown = shutdown.clone();
+        let io = state.io.clone();
+        let hls = Arc::clone(&state.hls);
+        async move {
+            shutdown.cancelled().await;
+            info!("lorchestre daemon shutting down");
+            events::emit(&io, Event::ServerShutdown, ());
+            systemd::notify("STOPPING=1");
+            events::disconnect_all(&io);
+            hls.stop().await;
+        }
+    };
+
+    let app = routes(state, layer);
+
+    let mut listeners = match activated {
+        Some(listener) => vec![tokio::net::TcpListener::from_std(listener)?],
+        None => listen::bind(&addresses).await,
+    };
+    if listeners.is_empty() {
+        return Err(format!("unable to listen on {host}:{port}").into());
+    }
+    listeners.extend(listen::bind(&extra).await);
+
+    // Every server stops accepting once the clients were told
+    let stopped = CancellationToken::new();
+    tokio::spawn({
+        let stopped = stopped.clone();
+        async move {
+            stopping.await;
+            stopped.cancel();
+        }
+    });
+    // Rewrite the path, so they must run before routing
+    let router = Router::new().fallback_service(
+        ServiceBuilder::new()
+            .layer(middleware::from_fn(proxy::strip_base))
+            .layer(middleware::from_fn(libraries::scope_prefix))
+            .service(app),
+    );
+    let app = router
+        .clone()
+        .into_make_service_with_connect_info::<SocketAddr>();
+    let rustls = match tls_files {
+        Some((cert, key)) => {
+            let rustls = RustlsConfig::from_pem_file(&cert, &key).await?;
+            tokio::spawn(tls::watch(rustls.clone(), cert, key));
+            Some(rustls)
+        }
+        None => None,
+    };
+
+    let mut servers: Vec<BoxFuture<'static, std::io::Result<()>>> = vec![];
+    for listener in listeners {
+        let address = listener.local_addr()?;
+        let stopped = stopped.clone();
+        servers.push(match &rustls {
+            Some(rustls) => {
+                let handle = axum_server::Handle::new();
+                tokio::spawn({
+                    let handle = handle.clone();
+                    async move {
+                        stopped.cancelled().await;
+                        handle.graceful_shutdown(None);
+                    }
+                });
+                info!("lorchestre daemon started on https://{address}");
+                Box::pin(
+                    axum_server::from_tcp_rustls(listener.into_std()?, rustls.clone())
+                        .handle(handle)
+                        .serve(app.clone()),
+                )
+            }
+            None => {
+                info!("lorchestre daemon started on http://{address}");
+                Box::pin(
+                    axum::serve(listener, app.clone())
+                        .with_graceful_shutdown(stopped.cancelled_owned())
+                        .into_future(),
+                )
+            }
+        });
+    }
+    if let Some(path) = unix_socket {
+        let stopped = stopped.clone();
+        servers.push(Box::pin(async move {
+            // Local clients still have the TCP addresses
+            if let Err(e) = listen::serve_unix(&path, router, stopped).await {
+                warn!("Unable to listen on `{}`: {e}", path.display());
+            }
+            Ok(())
+        }));
+    }
+    // Units ordered after the daemon wait for the first scans, requests are served meanwhile
+    systemd::notify("STATUS=Scanning the libraries");
+    tokio::spawn(async move {
+        futures::future::join_all(scans).await;
+        systemd::notify("READY=1\nSTATUS=Serving the library");
+    });
+
+    tokio::select! {
+        result = futures::future::try_join_all(servers) => {
+            result?;
+        }
+        _ = async {
+            shutdown.cancelled().await;
+            tokio::time::sleep(shutdown::GRACE_PERIOD).await;
+        } => warn!("Closing the connections still open after the grace period"),
+    }
+    info!("lorchestre daemon stopped");
+
+    Ok(())
+}
+
+/// Whether `handle` names a file right in the covers folder, and nothing out of it
+fn is_cover_handle(handle: &str) -> bool {
+    !handle.starts_with('.')
+        && !handle.contains('\\')
+        && std::path::Path::new(handle).file_name() == Some(std::ffi::OsStr::new(handle))
+}
+
+/// `data` resized to fit `width` by `height`, as PNG
+fn resize_cover(data: &[u8], width: u32, height: u32) -> Option<Vec<u8>> {
+    let image = image::load_from_memory(data).ok()?.resize(
+        width,
+        height,
+        image::imageops::FilterType::Gaussian,
+    );
+    let mut buffer = Cursor::new(Vec::new());
+    image.write_to(&mut buffer, image::ImageFormat::Png).ok()?;
+    Some(buffer.into_inner())
+}
+
+#[utoipa::path(
+    get, path = "/cover/{handle}", tag = "library",
+    params(("handle" = String, Path, description = "File name of the cover"), CoverQuery),
+    responses(
+        (status = 200, description = "The cover, as PNG when resized", content_type = "image/*"),
+        (status = 304, description = "The cover didn't change since the `ETag` or date given"),
+        (status = 400, description = "The handle isn't the name of a file"),
+        (status = 404, description = "No such cover, with `?fallback=false`"),
+    )
+)]
+async fn cover(
+    Scoped(state): Scoped,
+    Path(handle): Path<String>,
+    Query(query): Query<CoverQuery>,
+    headers: HeaderMap,
+) -> Response {
+    if !is_cover_handle(&handle) {
+        let mut response = "invalid cover handle".into_response();
+        *response.status_mut() = StatusCode::BAD_REQUEST;
+        return response;
+    }
+
+    let covers_dir = state.library.cache_dir.join("covers");
+    let mut path = covers_dir.join(&handle);
+    // Named after the extension clients assume, `.png`, until the cover is extracted
+    if !path.exists() && covers::lazy(&*state.config.read().await) {
+        if let Some((album_id, _)) = handle.split_once('.') {
+            if let Some(name) = covers::extract(&state.library, &state.io, album_id).await {
+                path = covers_dir.join(name);
+            }
+        }
+    }
+    let file =
+        std::fs::read(&path).and_then(|data| Ok((data, std::fs::metadata(&path)?.modified()?)));
+    let Ok((data, modified)) = file else {
+        if !query.fallback.unwrap_or(true) {
+            let mut response = format!("no cover named {handle}").into_response();
+            *response.status_mut() = StatusCode::NOT_FOUND;
+            return response;
+        }
+        // Not cached for long, the cover may be extracted by the next scan
+        let buf = include_bytes!("./assets/default-cover.png");
+        let mut resp = Response::new(Body::from(buf.as_slice()));
+        let headers = resp.headers_mut();
+        headers.insert(CONTENT_TYPE, HeaderValue::from_static("image/png"));
+        headers.insert(CACHE_CONTROL, HeaderValue::from_static("no-cache"));
+        return resp;
+    };
+
+    // The tag of a resized cover differs from the one of the whole
+    let size = query.size();
+    let hash = md5::compute(&data);
+    let etag = match size {
+        Some((w, h)) => format!("\"{hash:x}-{w}x{h}\""),
+        None => format!("\"{hash:x}\""),
+    };
+    let Ok(etag) = etag.parse::<ETag>() else {
+        return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+    };
+    // `If-None-Match` decodes from no header at all, as an empty list
+    let not_modified = if headers.contains_key(IF_NONE_MATCH) {
+        headers
+            .typed_get::<IfNoneMatch>()
+            .is_some_and(|x| !x.precondition_passes(&etag))
     } else {
-        drop(req_client);
-        drop(response);
+        headers
+            .typed_get::<IfModifiedSince>()
+            .is_some_and(|x| !x.is_modified(modified))
+    };
+    let validators = (TypedHeader(etag), TypedHeader(LastModified::from(modified)));
+    if not_modified {
+        return (StatusCode::NOT_MODIFIED, validators).into_response();
     }
 
-    let m = utils::cache_resolve(&dirs.cache).await;
-    let media_data = Arc::new(RwLock::new(m));
+    let mime = mime_guess::from_path(&path).first_or_octet_stream();
+    let (body, mime) = match size.and_then(|(w, h)| resize_cover(&data, w, h)) {
+        Some(resized) => (resized, "image/png".to_string()),
+        None => (data, mime.to_string()),
+    };
+    (validators, [(CONTENT_TYPE, mime)], body).into_response()
+}
 
-    let (layer, io) = SocketIo::builder()
-        .with_state(Arc::clone(&media_data))
-        .build_layer();
-    io.ns("/", on_connect);
+#[utoipa::path(
+    put, path = "/updatemusic", tag = "library",
+    responses(
+        (status = 200, description = "The scan job, once finished", body = Job),
+        (status = 401, description = "Not an admin"),
+        (status = 503, description = "The daemon stopped before the end of the scan"),
+    )
+)]
+async fn updatemusic(
+    Scoped(state): Scoped,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    headers: HeaderMap,
+) -> Response {
+    if !is_admin(&state, addr, &headers).await {
+        return StatusCode::UNAUTHORIZED.into_response();
+    }
 
-    let app = Router::new()
-        .route("/", get(ping))
-        .route("/media", get(media))
-        .route("/audio", get(audio))
-        .route("/album/:id", get(album))
-        .route("/cover/:handle", get(cover))
-        .route("/updatemusic", put(updatemusic))
-        .with_state(AppData {
-            media: media_data,
-            dirs: dirs.clone(),
-            io,
-        })
-        .layer(
-            ServiceBuilder::new()
-                .layer(CorsLayer::permissive())
-                .layer(layer),
-        );
+    // The scan queued or running already is waited for
+    let (_, done) = submit_job(&state, JobKind::Scan, None);
+    // Answered at the end of the scan, as clients expect
+    match done.await {
+        Ok(job) => Json(job).into_response(),
+        Err(_) => StatusCode::SERVICE_UNAVAILABLE.into_response(),
+    }
+}
+
+/// Target of a scan job reading every file again
+const FULL_SCAN: &str = "full";
+
+/// Queues a scan of the library, answered right away. Only one scan of a library is queued or
+/// running at a time.
+#[utoipa::path(
+    post, path = "/scan", tag = "library",
+    params(ScanQuery),
+    responses(
+        (status = 202, description = "The scan job, queued", body = Job),
+        (status = 401, description = "Not an admin"),
+        (status = 409, description = "The scan job already queued or running", body = Job),
+    )
+)]
+async fn start_scan(
+    Scoped(state): Scoped,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    headers: HeaderMap,
+    Query(query): Query<ScanQuery>,
+) -> Response {
+    if !is_admin(&state, addr, &headers).await {
+        return StatusCode::UNAUTHORIZED.into_response();
+    }
+
+    let target = query
+        .full
+        .unwrap_or_default()
+        .then(|| FULL_SCAN.to_string());
+    let run = job_run(&state, JobKind::Scan, target.clone());
+    let (job, _, submitted) =
+        state
+            .jobs
+            .submit_single(JobKind::Scan, &state.library.name, target, run);
+    match submitted {
+        true => (StatusCode::ACCEPTED, Json(job)).into_response(),
+        false => (StatusCode::CONFLICT, Json(job)).into_response(),
+    }
+}
+
+/// How often a running scan reports its progress
+const SCAN_PROGRESS_INTERVAL: std::time::Duration = std::time::Duration::from_secs(1);
 
-    let listener = tokio::net::TcpListener::bind(format!("{host}:{port}")).await?;
-    info!("lorchestre daemon started on http://{host}:{port}");
-    axum::serve(listener, app).await?;
+/// Scans the library of `state`, as a job, reading every file again when `full`. The tracks read
+/// are streamed into the media of the library as the scan goes, the clients seeing them come in
+/// through the `track:added` events.
+async fn scan_library(state: AppData, context: JobContext, full: bool) -> Result<(), String> {
+    events::emit(&state.io, Event::ScanStarted, ());
+    let mut options = ScanOptions::from_config(&*state.config.read().await);
+    options.full = full;
+    let library = &state.library;
+    let before = library.media.load();
+    let scan = library.scan.begin();
+    // The progress is reported from another task
+    let reporter = tokio::spawn({
+        let (progress, context) = (Arc::clone(&scan.progress), context.clone());
+        async move {
+            let mut ticks = tokio::time::interval(SCAN_PROGRESS_INTERVAL);
+            loop {
+                ticks.tick().await;
+                let progress = progress.snapshot();
+                context.progress(progress.done, progress.total);
+            }
+        }
+    });
+    scan.progress.stream();
+    let streamer = tokio::spawn({
+        let (progress, state) = (Arc::clone(&scan.progress), state.clone());
+        async move {
+            let mut streamed = false;
+            while let Some(m) = progress.partial().await {
+                let mut media = state.library.media.write().await;
+                media.swap_with(m);
+                state.library.publish(&state.io, &media.commit());
+                streamed = true;
+            }
+            streamed
+        }
+    });
+    // Off the worker, the requests and the tasks above are served while the files are read
+    let resolved = tokio::task::block_in_place(|| {
+        tokio::runtime::Handle::current().block_on(utils::cache_resolve(
+            &library.cache_dir,
+            &library.paths,
+            &options,
+            &context.cancel,
+            &scan.progress,
+        ))
+    });
+    reporter.abort();
+    // Done with the media read so far before swapping the one read in full
+    scan.progress.end_stream();
+    let streamed = streamer.await.unwrap_or_default();
+    let Some(m) = resolved else {
+        // The library is left as it was, like the cache
+        if streamed {
+            let mut media = state.library.media.write().await;
+            media.swap_with(Media::clone(&before));
+            state.library.publish(&state.io, &media.commit());
+        }
+        events::emit(&state.io, Event::ScanFinished, false);
+        return Err("the scan was cancelled".to_string());
+    };
+    scan.complete();
+    remap_legacy_ids(&state, &m).await;
+    // Requests wait for the swap only, the changes are found while they read the new media
+    let mut media = state.library.media.write().await;
+    media.swap_with(m);
+    state.library.publish(&state.io, &media.commit());
+    state.library.colors.notify_one();
+    if embed::enabled(&*state.config.read().await) {
+        submit_job(&state, JobKind::Embed, None);
+    }
+    if analysis::enabled(&*state.config.read().await) {
+        submit_job(&state, JobKind::Analysis, None);
+    }
+    events::emit(&state.io, Event::ScanFinished, true);
 
     Ok(())
 }
 
-#[derive(serde::Deserialize, Debug)]
-struct ImageSize {
-    size: String,
+/// Points the entries saved with the former ids of the tracks, the encodings of their paths,
+/// and with album ids of an older scheme, to the ids of `media`
+async fn remap_legacy_ids(state: &AppData, media: &Media) {
+    let track_ids = media.legacy_track_ids();
+    state.bookmarks.write().await.remap_tracks(&track_ids);
+    state.positions.write().await.remap_tracks(&track_ids);
+    state.listen_later.write().await.remap_tracks(&track_ids);
+    state.history.write().await.remap_tracks(&track_ids);
+    state.favorites.write().await.remap_tracks(&track_ids);
+    state.queue.write().await.remap_tracks(&track_ids);
+    state
+        .listen_later
+        .write()
+        .await
+        .remap_albums(&media.legacy_album_ids());
 }
 
-#[derive(serde::Deserialize, Debug)]
-struct MusicPath {
-    path: String,
+/// What a job of `kind` runs on the library of `state`
+fn job_run(state: &AppData, kind: JobKind, target: Option<String>) -> Run {
+    let state = state.clone();
+    match kind {
+        JobKind::Scan => Box::new(move |context| {
+            let full = target.as_deref() == Some(FULL_SCAN);
+            Box::pin(scan_library(state, context, full))
+        }),
+        JobKind::Embed => Box::new(move |context| {
+            Box::pin(async move { embed::run(&state.library.media, context).await })
+        }),
+        JobKind::Audit => Box::new(move |_| {
+            Box::pin(async move { audit::audit(&state.library, &state.config, &state.io).await })
+        }),
+        JobKind::Transcode => Box::new(move |_| {
+            Box::pin(async move {
+                let id = target.unwrap_or_default();
+                let Some(track) = state.library.media.load().get_track(&id.as_str().into()) else {
+                    return Err(format!("no song found with the id of {id}"));
+                };
+                cue::extract(&state.library.cache_dir, &track)
+                    .await
+                    .map(|_| ())
+                    .map_err(|e| e.to_string())
+            })
+        }),
+        // The operation isn't kept, see `batch_tags`
+        JobKind::Tags => Box::new(move |_| {
+            Box::pin(async move {
+                Err("interrupted by the daemon stopping, send the batch again".to_string())
+            })
+        }),
+        // The moves aren't kept, the next scan finds the files moved already
+        JobKind::Organize => Box::new(move |_| {
+            Box::pin(async move {
+                Err("interrupted by the daemon stopping, organize the library again".to_string())
+            })
+        }),
+        JobKind::Import => Box::new(move |context| {
+            Box::pin(
+                async move { ingest::run(&state.library, &state.config, &state.io, context).await },
+            )
+        }),
+        JobKind::CacheGc => {
+            Box::new(move |_| Box::pin(async move { gc::run(&state.library, &state.io).await }))
+        }
+        JobKind::Analysis => Box::new(move |context| {
+            Box::pin(async move { analysis::run(&state.library, &state.io, context).await })
+        }),
+    }
 }
 
-impl ImageSize {
-    pub fn parse(self) -> Option<(u32, u32)> {
-        self.size
-            .split_once('x')
-            .map(|(x, y)| (x.parse().unwrap(), y.parse().unwrap()))
+fn submit_job(
+    state: &AppData,
+    kind: JobKind,
+    target: Option<String>,
+) -> (Job, tokio::sync::oneshot::Receiver<Job>) {
+    let run = job_run(state, kind, target.clone());
+    if kind == JobKind::Scan {
+        // One scan of a library at a time, the one queued or running already is handed back
+        let (job, done, _) = state
+            .jobs
+            .submit_single(kind, &state.library.name, target, run);
+        return (job, done);
     }
+    state.jobs.submit(kind, &state.library.name, target, run)
 }
 
-async fn cover(
+#[utoipa::path(
+    get, path = "/libraries", tag = "library",
+    responses((status = 200, body = Vec<LibraryInfo>))
+)]
+async fn libraries_list(
     State(state): State<AppData>,
-    Path(handle): Path<String>,
-    OptionalQuery(size): OptionalQuery<ImageSize>,
+    headers: HeaderMap,
+) -> Json<Vec<LibraryInfo>> {
+    let user = users::bearer(&headers).and_then(|x| state.users.authenticate(x));
+    let viewer = viewer(&state, user.as_ref(), &headers).await;
+    let mut libraries = vec![];
+    for library in state.libraries.all() {
+        if viewer.sees(library) {
+            libraries.push(library.info().await);
+        }
+    }
+    Json(libraries)
+}
+
+/// Report of the last cache audit, `null` until one ran
+#[utoipa::path(
+    get, path = "/audit", tag = "library",
+    responses((status = 200, body = Option<AuditReport>))
+)]
+async fn last_audit(Scoped(state): Scoped) -> Json<Option<AuditReport>> {
+    Json(state.library.audit.read().await.clone())
+}
+
+/// Covers and portraits stored for the library, with the report of the last collection
+#[utoipa::path(
+    get, path = "/cache/stats", tag = "library",
+    responses((status = 200, body = CacheStats))
+)]
+async fn cache_stats(Scoped(state): Scoped) -> Json<CacheStats> {
+    Json(gc::stats(&state.library).await)
+}
+
+/// Removes the covers and portraits the library doesn't use anymore, and the truncated ones.
+/// Answered once done, the report is then in `GET /cache/stats`.
+#[utoipa::path(
+    post, path = "/cache/gc", tag = "library",
+    responses(
+        (status = 200, description = "The collection job, once finished", body = Job),
+        (status = 401, description = "Not an admin"),
+        (status = 503, description = "The daemon stopped before the end of the collection"),
+    )
+)]
+async fn cache_gc(
+    Scoped(state): Scoped,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    headers: HeaderMap,
 ) -> Response {
-    let path = state.dirs.cache.join("covers").join(handle);
+    if !is_admin(&state, addr, &headers).await {
+        return StatusCode::UNAUTHORIZED.into_response();
+    }
+
+    let (_, done) = submit_job(&state, JobKind::CacheGc, None);
+    match done.await {
+        Ok(job) => Json(job).into_response(),
+        Err(_) => StatusCode::SERVICE_UNAVAILABLE.into_response(),
+    }
+}
+
+/// Problems of the library to clean up: missing tags and covers, unreadable files, broken
+/// playlist entries... Each has a code for clients to group them by.
+#[utoipa::path(
+    get, path = "/library/issues", tag = "library",
+    responses(
+        (status = 200, body = Vec<LibraryIssue>),
+        (status = 500, description = "The check failed"),
+    )
+)]
+async fn library_issues(Scoped(state): Scoped) -> Response {
+    // Files being scanned aren't in the library yet
+    let roots = (!state.library.scan.status().running).then(|| state.library.paths.clone());
+    let options = ScanOptions::from_config(&*state.config.read().await);
+    let media = Arc::clone(&state.library.media);
+    let issues = tokio::task::spawn_blocking(move || {
+        issues::issues(&media.load(), roots.as_deref(), &options.walk)
+    })
+    .await;
 
-    if let Some(image_size) = size {
-        if let Some((w, h)) = image_size.parse() {
-            if let Ok(mut image) = ImageReader::open(&path).unwrap().decode() {
-                image = image.resize(w, h, image::imageops::FilterType::Gaussian);
+    match issues {
+        Ok(issues) => Json(issues).into_response(),
+        Err(e) => {
+            let mut response = format!("unable to check the library: {e}").into_response();
+            *response.status_mut() = StatusCode::INTERNAL_SERVER_ERROR;
+            response
+        }
+    }
+}
 
-                let mut buffer = BufWriter::new(Cursor::new(Vec::new()));
-                let _ = image.write_to(&mut buffer, image::ImageFormat::Png);
+/// Applies one change of the tags to many tracks, as a job. Each file is edited in a copy
+/// replacing it once written, a failure leaves it untouched. Answered once the job finished.
+#[utoipa::path(
+    post, path = "/tracks/batch", tag = "tracks",
+    request_body = BatchRequest,
+    responses(
+        (status = 200, body = BatchResult),
+        (status = 400, description = "No track given"),
+        (status = 401, description = "Not an admin"),
+        (status = 503, description = "The daemon stopped before the end of the batch"),
+    )
+)]
+async fn batch_tags(
+    Scoped(state): Scoped,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    headers: HeaderMap,
+    Json(request): Json<BatchRequest>,
+) -> Response {
+    if !is_admin(&state, addr, &headers).await {
+        return StatusCode::UNAUTHORIZED.into_response();
+    }
 
-                let bytes = buffer.into_inner().unwrap().into_inner();
+    if request.tracks.is_empty() {
+        let mut response = "no track given".into_response();
+        *response.status_mut() = StatusCode::BAD_REQUEST;
+        return response;
+    }
 
-                let body = Body::from(bytes);
-                let resp = Response::new(body);
-                return resp;
+    let mut tracks = vec![];
+    let mut failed = vec![];
+    {
+        let media = state.library.media.load();
+        for id in request.tracks {
+            match media.get_track(&id.as_str().into()) {
+                // Their tags are the ones of the whole file
+                Some(track) if track.source.is_some() => failed.push(BatchFailure {
+                    track: track.id,
+                    error: "the track is split from a file by a cue sheet".to_string(),
+                }),
+                Some(track) => tracks.push(track),
+                None => failed.push(BatchFailure {
+                    track: id,
+                    error: "no such track".to_string(),
+                }),
             }
         }
     }
 
-    if let Ok(mut file) = std::fs::File::open(&path) {
-        let mut buf = vec![];
-        let _ = file.read_to_end(&mut buf);
+    let outcome = Arc::new(std::sync::Mutex::new((vec![], vec![])));
+    let run: Run = {
+        let (state, outcome) = (state.clone(), Arc::clone(&outcome));
+        let operation = request.operation;
+        Box::new(move |context| {
+            Box::pin(async move {
+                let library = &state.library;
+                let result = tags::run(
+                    library,
+                    &state.config,
+                    &state.io,
+                    tracks,
+                    operation,
+                    context,
+                )
+                .await?;
+                *outcome.lock().unwrap() = result;
+                Ok(())
+            })
+        })
+    };
+    let (_, done) = state
+        .jobs
+        .submit(JobKind::Tags, &state.library.name, None, run);
+    let Ok(job) = done.await else {
+        return StatusCode::SERVICE_UNAVAILABLE.into_response();
+    };
 
-        let body = Body::from(buf);
-        let resp = Response::new(body);
-        return resp;
-    } else {
-        warn!("Fail to retrieve the cover file `{}`", path.display());
-        let buf = include_bytes!("./assets/default-cover.png");
-        let body = Body::from(buf.as_slice());
-        let mut resp = Response::new(body);
+    let (updated, mut edit_failed) = std::mem::take(&mut *outcome.lock().unwrap());
+    failed.append(&mut edit_failed);
+    Json(BatchResult {
+        job,
+        updated,
+        failed,
+    })
+    .into_response()
+}
 
-        resp.headers_mut().insert(
-            CACHE_CONTROL,
-            HeaderValue::from_static("public, max-age=2419200, immutable"),
-        );
+/// Moves the files of the library to the paths given by `library.organize_pattern`. Only lists
+/// the moves unless `apply` is set, then makes them as a job answered once it finished.
+#[utoipa::path(
+    post, path = "/organize", tag = "library",
+    params(OrganizeQuery),
+    responses(
+        (status = 200, body = OrganizeReport),
+        (status = 400, description = "The pattern is invalid"),
+        (status = 401, description = "Not an admin"),
+        (status = 409, description = "No pattern is set"),
+        (status = 503, description = "The daemon stopped before the files were moved"),
+    )
+)]
+async fn organize_library(
+    Scoped(state): Scoped,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    headers: HeaderMap,
+    Query(query): Query<OrganizeQuery>,
+) -> Response {
+    if !is_admin(&state, addr, &headers).await {
+        return StatusCode::UNAUTHORIZED.into_response();
+    }
 
-        resp
+    let pattern = state
+        .config
+        .read()
+        .await
+        .library
+        .as_ref()
+        .and_then(|library| library.organize_pattern.clone())
+        .or(lorconf::Library::default().organize_pattern);
+    let Some(pattern) = pattern else {
+        let mut response = "set `library.organize_pattern` to organize the library".into_response();
+        *response.status_mut() = StatusCode::CONFLICT;
+        return response;
+    };
+    let pattern = match organize::parse(&pattern) {
+        Ok(pattern) => pattern,
+        Err(e) => {
+            let mut response = format!("invalid `library.organize_pattern`: {e}").into_response();
+            *response.status_mut() = StatusCode::BAD_REQUEST;
+            return response;
+        }
+    };
+
+    let plan = organize::plan(&state.library.media.load(), &state.library.paths, &pattern);
+    if !query.apply || plan.moves.is_empty() {
+        return Json(OrganizeReport {
+            applied: false,
+            moves: plan.moves,
+            skipped: plan.skipped,
+            job: None,
+        })
+        .into_response();
     }
+
+    let outcome = Arc::new(std::sync::Mutex::new((vec![], vec![])));
+    let run: Run = {
+        let (state, outcome) = (state.clone(), Arc::clone(&outcome));
+        let moves = plan.moves;
+        Box::new(move |context| {
+            Box::pin(async move {
+                let result = organize::run(&state.library, &state.io, moves, context).await?;
+                *outcome.lock().unwrap() = result;
+                Ok(())
+            })
+        })
+    };
+    let (_, done) = state
+        .jobs
+        .submit(JobKind::Organize, &state.library.name, None, run);
+    let Ok(job) = done.await else {
+        return StatusCode::SERVICE_UNAVAILABLE.into_response();
+    };
+
+    let (moves, mut failed) = std::mem::take(&mut *outcome.lock().unwrap());
+    let mut skipped = plan.skipped;
+    skipped.append(&mut failed);
+    Json(OrganizeReport {
+        applied: true,
+        moves,
+        skipped,
+        job: Some(job),
+    })
+    .into_response()
 }
 
-async fn updatemusic(State(state): State<AppData>) {
-    let m = utils::cache_resolve(&state.dirs.cache).await;
-    let mut binding = state.media.write().await;
-    binding.swap_with(m.clone());
-    let _ = state.io.emit("newmedia", m);
+/// The id of the track `id` names, which may be the one it had before, see `Track::id`
+async fn track_id(state: &AppData, id: TrackId) -> TrackId {
+    match state.library.media.load().get_track(&id) {
+        Some(track) => track.id.into(),
+        None => id,
+    }
 }
 
-async fn album(State(state): State<AppData>, Path(id): Path<String>) -> Response {
-    if let Some(album) = state.media.read().await.get_album(&id) {
-        Json(album).into_response()
+#[utoipa::path(
+    get, path = "/track/{id}/bookmarks", tag = "tracks",
+    params(("id" = String, Path, description = "Id of the track")),
+    responses((status = 200, body = Vec<Bookmark>), (status = 400, description = "Malformed id", body = InvalidId), (status = 404, description = "No such track"))
+)]
+async fn track_bookmarks(Scoped(state): Scoped, id: TrackId) -> Response {
+    if let Some(track) = state.library.media.load().get_track(&id) {
+        Json(state.bookmarks.read().await.get(&track.id)).into_response()
     } else {
-        let mut response = format!("no album found with the id of {id}").into_response();
+        let mut response = format!("no song found with the id of {id}").into_response();
         *response.status_mut() = StatusCode::NOT_FOUND;
         response
     }
 }
 
-async fn audio(
-    range: Option<TypedHeader<Range>>,
-    State(state): State<AppData>,
-    Query(music_path): Query<MusicPath>,
+#[utoipa::path(
+    post, path = "/track/{id}/bookmarks", tag = "tracks",
+    params(("id" = String, Path, description = "Id of the track")),
+    request_body = NewBookmark,
+    responses((status = 201, body = Bookmark), (status = 400, description = "Malformed id", body = InvalidId), (status = 404, description = "No such track"))
+)]
+async fn add_bookmark(
+    Scoped(state): Scoped,
+    id: TrackId,
+    Json(bookmark): Json<NewBookmark>,
 ) -> Response {
-    let path = String::from_utf8_lossy(&URL_SAFE.decode(music_path.path).unwrap()).to_string();
-    if let Some(track) = state.media.read().await.get_song(&path) {
-        let file = File::open(&track.file_path).await.unwrap();
-        let body = KnownSize::file(file).await.unwrap();
-        let r = range.clone().map(|TypedHeader(range)| range);
-        let response = Ranged::new(r, body).try_respond();
-        if let Ok(response) = response {
-            return response.into_response();
-        } else {
-            let mut response =
-                format!("An error occured while satisfying the request for path `{path}`")
-                    .into_response();
-            *response.status_mut() = StatusCode::NOT_FOUND;
-            response
+    let Some(track) = state.library.media.load().get_track(&id) else {
+        let mut response = format!("no song found with the id of {id}").into_response();
+        *response.status_mut() = StatusCode::NOT_FOUND;
+        return response;
+    };
+
+    let bookmark = state.bookmarks.write().await.add(&track.id, bookmark);
+
+    let mut response = Json(bookmark).into_response();
+    *response.status_mut() = StatusCode::CREATED;
+    response
+}
+
+#[utoipa::path(
+    delete, path = "/track/{id}/bookmarks/{bookmark}", tag = "tracks",
+    params(("id" = String, Path, description = "Id of the track"), ("bookmark" = String, Path, description = "Id of the bookmark")),
+    responses((status = 204), (status = 400, description = "Malformed id", body = InvalidId), (status = 404, description = "No such bookmark"))
+)]
+async fn remove_bookmark(
+    Scoped(state): Scoped,
+    id: TrackId,
+    Path((_, bookmark)): Path<(String, String)>,
+) -> StatusCode {
+    let id = track_id(&state, id).await;
+    if state.bookmarks.write().await.remove(&id, &bookmark) {
+        StatusCode::NO_CONTENT
+    } else {
+        StatusCode::NOT_FOUND
+    }
+}
+
+/// Where listening stopped in the track, `null` if it wasn't saved
+#[utoipa::path(
+    get, path = "/track/{id}/position", tag = "tracks",
+    params(("id" = String, Path, description = "Id of the track")),
+    responses((status = 200, body = Option<SavedPosition>), (status = 400, description = "Malformed id", body = InvalidId), (status = 404, description = "No such track"))
+)]
+async fn track_position(Scoped(state): Scoped, id: TrackId) -> Response {
+    if let Some(track) = state.library.media.load().get_track(&id) {
+        Json(state.positions.read().await.get(&track.id)).into_response()
+    } else {
+        let mut response = format!("no song found with the id of {id}").into_response();
+        *response.status_mut() = StatusCode::NOT_FOUND;
+        response
+    }
+}
+
+#[utoipa::path(
+    put, path = "/track/{id}/position", tag = "tracks",
+    params(("id" = String, Path, description = "Id of the track")),
+    request_body = NewPosition,
+    responses((status = 200, body = SavedPosition), (status = 400, description = "Malformed id", body = InvalidId), (status = 404, description = "No such track"))
+)]
+async fn save_position(
+    Scoped(state): Scoped,
+    id: TrackId,
+    Json(position): Json<NewPosition>,
+) -> Response {
+    let Some(track) = state.library.media.load().get_track(&id) else {
+        let mut response = format!("no song found with the id of {id}").into_response();
+        *response.status_mut() = StatusCode::NOT_FOUND;
+        return response;
+    };
+
+    let saved = state
+        .positions
+        .write()
+        .await
+        .set(&track.id, position.position);
+
+    Json(saved).into_response()
+}
+
+#[utoipa::path(
+    delete, path = "/track/{id}/position", tag = "tracks",
+    params(("id" = String, Path, description = "Id of the track")),
+    responses((status = 204), (status = 400, description = "Malformed id", body = InvalidId), (status = 404, description = "No saved position"))
+)]
+async fn remove_position(Scoped(state): Scoped, id: TrackId) -> StatusCode {
+    let id = track_id(&state, id).await;
+    if state.positions.write().await.remove(&id) {
+        StatusCode::NO_CONTENT
+    } else {
+        StatusCode::NOT_FOUND
+    }
+}
+
+#[utoipa::path(
+    get, path = "/listen-later", tag = "listen later",
+    responses((status = 200, body = Vec<LaterEntry>))
+)]
+async fn listen_later_list(Scoped(state): Scoped) -> Response {
+    Json(state.listen_later.read().await.entries()).into_response()
+}
+
+#[utoipa::path(
+    post, path = "/listen-later", tag = "listen later",
+    request_body = NewLaterEntry,
+    responses(
+        (status = 201, description = "Added"),
+        (status = 200, description = "Already there"),
+        (status = 404, description = "No such album or track"),
+    )
+)]
+async fn listen_later_add(Scoped(state): Scoped, Json(mut entry): Json<NewLaterEntry>) -> Response {
+    let exists = {
+        let media = state.library.media.load();
+        match entry.kind {
+            LaterKind::Album => media.get_album(&entry.id.as_str().into()).is_some(),
+            LaterKind::Track => match media.get_track(&entry.id.as_str().into()) {
+                Some(track) => {
+                    entry.id = track.id;
+                    true
+                }
+                None => false,
+            },
+        }
+    };
+    if !exists {
+        let mut response = format!("nothing found with the id of {}", entry.id).into_response();
+        *response.status_mut() = StatusCode::NOT_FOUND;
+        return response;
+    }
+
+    let mut listen_later = state.listen_later.write().await;
+    if listen_later.add(entry) {
+        emit_user(&state, Event::ListenLater, listen_later.entries());
+        StatusCode::CREATED.into_response()
+    } else {
+        StatusCode::OK.into_response()
+    }
+}
+
+#[utoipa::path(
+    delete, path = "/listen-later/{kind}/{id}", tag = "listen later",
+    params(("kind" = LaterKind, Path, description = "Kind of the entry"), ("id" = String, Path, description = "Id of the album or track")),
+    responses((status = 204), (status = 404, description = "Not in the list"))
+)]
+async fn listen_later_remove(
+    Scoped(state): Scoped,
+    Path((kind, id)): Path<(LaterKind, String)>,
+) -> StatusCode {
+    let id = match kind {
+        LaterKind::Album => id,
+        LaterKind::Track => track_id(&state, id.into()).await.into(),
+    };
+    let mut listen_later = state.listen_later.write().await;
+    if listen_later.remove(kind, &id) {
+        emit_user(&state, Event::ListenLater, listen_later.entries());
+        StatusCode::NO_CONTENT
+    } else {
+        StatusCode::NOT_FOUND
+    }
+}
+
+#[utoipa::path(
+    get, path = "/browse/recent", tag = "library",
+    params(RecentQuery, LiteQuery, QualityQuery),
+    responses((status = 200, description = "`LiteResults` when lite", body = Recent))
+)]
+async fn browse_recent(
+    Scoped(state): Scoped,
+    Query(query): Query<RecentQuery>,
+    Query(lite): Query<LiteQuery>,
+    Query(quality): Query<QualityQuery>,
+) -> Response {
+    let quality = QualityQuery {
+        hide_explicit: state.hide_explicit,
+        ..quality
+    };
+    let limit = query.limit.unwrap_or(20);
+    let media = state.library.media.load();
+    let recent = match query.kind {
+        RecentKind::Added => history::recently_added(&media, limit, &quality),
+        RecentKind::Played => state
+            .history
+            .read()
+            .await
+            .recently_played(&media, limit, &quality),
+    };
+
+    if lite.lite {
+        Json(LiteResults {
+            albums: recent
+                .albums
+                .iter()
+                .map(|x| lite::album(&media, x))
+                .collect(),
+            playlists: vec![],
+            tracks: recent.tracks.iter().map(lite::track).collect(),
+            lyrics: vec![],
+        })
+        .into_response()
+    } else {
+        Json(recent).into_response()
+    }
+}
+
+#[utoipa::path(
+    get, path = "/favorites", tag = "favorites",
+    responses((status = 200, description = "Ids of the favorite tracks", body = Vec<String>))
+)]
+async fn favorites_list(Scoped(state): Scoped) -> Json<Vec<String>> {
+    Json(state.favorites.read().await.tracks())
+}
+
+#[utoipa::path(
+    put, path = "/favorites/{id}", tag = "favorites",
+    params(("id" = String, Path, description = "Id of the track")),
+    responses(
+        (status = 201, description = "Added"),
+        (status = 200, description = "Already a favorite"),
+        (status = 400, description = "Malformed id", body = InvalidId),
+        (status = 404, description = "No such track"),
+    )
+)]
+async fn favorites_add(Scoped(state): Scoped, id: TrackId) -> StatusCode {
+    let Some(track) = state.library.media.load().get_track(&id) else {
+        return StatusCode::NOT_FOUND;
+    };
+
+    let mut favorites = state.favorites.write().await;
+    if favorites.add(&track.id) {
+        emit_user(&state, Event::Favorites, favorites.tracks());
+        StatusCode::CREATED
+    } else {
+        StatusCode::OK
+    }
+}
+
+#[utoipa::path(
+    delete, path = "/favorites/{id}", tag = "favorites",
+    params(("id" = String, Path, description = "Id of the track")),
+    responses((status = 204), (status = 400, description = "Malformed id", body = InvalidId), (status = 404, description = "Not a favorite"))
+)]
+async fn favorites_remove(Scoped(state): Scoped, id: TrackId) -> StatusCode {
+    let id = track_id(&state, id).await;
+    let mut favorites = state.favorites.write().await;
+    if favorites.remove(&id) {
+        emit_user(&state, Event::Favorites, favorites.tracks());
+        StatusCode::NO_CONTENT
+    } else {
+        StatusCode::NOT_FOUND
+    }
+}
+
+#[utoipa::path(
+    get, path = "/artists", tag = "library",
+    responses((status = 200, body = Vec<Artist>))
+)]
+async fn artists_list(Scoped(state): Scoped) -> Json<Vec<Artist>> {
+    Json(artists::artists(&state.library.media.load()))
+}
+
+#[utoipa::path(
+    get, path = "/artist/{id}/albums", tag = "library",
+    params(("id" = String, Path, description = "Id of the artist"), ArtistAlbumsQuery, LiteQuery, QualityQuery),
+    responses(
+        (status = 200, description = "`LiteAlbum`s when lite", body = Vec<Album>),
+        (status = 404, description = "No such artist"),
+    )
+)]
+async fn artist_albums(
+    Scoped(state): Scoped,
+    Path(id): Path<String>,
+    Query(query): Query<ArtistAlbumsQuery>,
+    Query(lite): Query<LiteQuery>,
+    Query(quality): Query<QualityQuery>,
+) -> Response {
+    let quality = QualityQuery {
+        hide_explicit: state.hide_explicit,
+        ..quality
+    };
+    let sort = match query.sort {
+        Some(sort) => sort,
+        None => artists::album_sort(&*state.config.read().await),
+    };
+
+    let media = state.library.media.load();
+    if let Some(mut albums) = artists::artist_albums(&media, &id, sort) {
+        albums.retain(|x| quality.keeps_album(x));
+        if lite.lite {
+            let albums: Vec<LiteAlbum> = albums.iter().map(|x| lite::album(&media, x)).collect();
+            return Json(albums).into_response();
         }
+        Json(albums).into_response()
     } else {
-        warn!("{path} not founded");
-        let mut response = format!("no song found with the id of {path}").into_response();
+        let mut response = format!("no artist found with the id of {id}").into_response();
         *response.status_mut() = StatusCode::NOT_FOUND;
         response
     }
 }
 
-async fn ping() -> String {
-    format!("OK lorchestrectl v{}", config::VERSION)
+#[utoipa::path(
+    get, path = "/composers", tag = "library",
+    responses((status = 200, body = Vec<Composer>))
+)]
+async fn composers_list(Scoped(state): Scoped) -> Json<Vec<Composer>> {
+    Json(composers::composers(&state.library.media.load()))
+}
+
+/// The tracks of a composer, work by work and in movement order
+#[utoipa::path(
+    get, path = "/composer/{id}/tracks", tag = "library",
+    params(("id" = String, Path, description = "Id of the composer"), LiteQuery, QualityQuery),
+    responses(
+        (status = 200, description = "`LiteTrack`s when lite", body = Vec<Track>),
+        (status = 404, description = "No such composer"),
+    )
+)]
+async fn composer_tracks(
+    Scoped(state): Scoped,
+    Path(id): Path<String>,
+    Query(lite): Query<LiteQuery>,
+    Query(quality): Query<QualityQuery>,
+) -> Response {
+    let quality = QualityQuery {
+        hide_explicit: state.hide_explicit,
+        ..quality
+    };
+    let media = state.library.media.load();
+    let Some(mut tracks) = composers::composer_tracks(&media, &id) else {
+        let mut response = format!("no composer found with the id of {id}").into_response();
+        *response.status_mut() = StatusCode::NOT_FOUND;
+        return response;
+    };
+    tracks.retain(|x| quality.keeps(x));
+
+    if lite.lite {
+        let tracks: Vec<LiteTrack> = tracks.into_iter().map(lite::track).collect();
+        Json(tracks).into_response()
+    } else {
+        let tracks: Vec<Track> = tracks.into_iter().cloned().collect();
+        Json(tracks).into_response()
+    }
 }
 
-async fn media(State(state): State<AppData>) -> Json<Media> {
-    Json(state.media.read().await.clone())
+/// The tracks of the library, filtered by tempo, key or genre and sorted, a page at a time
+#[utoipa::path(
+    get, path = "/tracks", tag = "library",
+    params(TracksQuery, LiteQuery, QualityQuery),
+    responses((status = 200, description = "`LiteTrackList` when lite", body = TrackTrackList))
+)]
+async fn tracks_list(
+    Scoped(state): Scoped,
+    Query(query): Query<TracksQuery>,
+    Query(lite): Query<LiteQuery>,
+    Query(quality): Query<QualityQuery>,
+) -> Response {
+    let quality = QualityQuery {
+        hide_explicit: state.hide_explicit,
+        ..quality
+    };
+    let list = state.library.media.load().list_tracks(&query, &quality);
+    if lite.lite {
+        Json(TrackList {
+            total: list.total,
+            tracks: list.tracks.iter().map(lite::track).collect(),
+        })
+        .into_response()
+    } else {
+        Json(list).into_response()
+    }
+}
+
+#[utoipa::path(
+    get, path = "/random/tracks", tag = "library",
+    params(RandomTracksQuery, LiteQuery, QualityQuery),
+    responses((status = 200, description = "`LiteRandomTracks` when lite", body = TrackRandomTracks))
+)]
+async fn random_tracks(
+    Scoped(state): Scoped,
+    Query(query): Query<RandomTracksQuery>,
+    Query(lite): Query<LiteQuery>,
+    Query(quality): Query<QualityQuery>,
+) -> Response {
+    let quality = QualityQuery {
+        hide_explicit: state.hide_explicit,
+        ..quality
+    };
+    let random = random::random_tracks(&state.library.media.load(), query, &quality);
+    if lite.lite {
+        Json(RandomTracks {
+            seed: random.seed,
+            total: random.total,
+            tracks: random.tracks.iter().map(lite::track).collect(),
+        })
+        .into_response()
+    } else {
+        Json(random).into_response()
+    }
+}
+
+#[utoipa::path(
+    get, path = "/random/album", tag = "library",
+    params(RandomAlbumQuery, LiteQuery, QualityQuery),
+    responses(
+        (status = 200, description = "`LiteAlbumTracks` when lite", body = Album),
+        (status = 404, description = "The library has no album"),
+    )
+)]
+async fn random_album(
+    Scoped(state): Scoped,
+    Query(query): Query<RandomAlbumQuery>,
+    Query(lite): Query<LiteQuery>,
+    Query(quality): Query<QualityQuery>,
+) -> Response {
+    let quality = QualityQuery {
+        hide_explicit: state.hide_explicit,
+        ..quality
+    };
+    let media = state.library.media.load();
+    if let Some(album) = random::random_album(&media, query, &quality) {
+        if lite.lite {
+            return Json(lite::album_tracks(&media, &album)).into_response();
+        }
+        Json(album).into_response()
+    } else {
+        let mut response = "the library has no album".into_response();
+        *response.status_mut() = StatusCode::NOT_FOUND;
+        response
+    }
+}
+
+/// Codec, format, tags and embedded pictures of the audio file of a track
+#[utoipa::path(
+    get, path = "/track/{id}/info", tag = "tracks",
+    params(("id" = String, Path, description = "Id of the track")),
+    responses(
+        (status = 200, body = TrackInfo),
+        (status = 400, description = "Malformed id", body = InvalidId),
+        (status = 404, description = "No such track"),
+        (status = 410, description = "The file was removed"),
+    )
+)]
+async fn track_info(Scoped(state): Scoped, id: TrackId) -> Response {
+    let media = state.library.media.load();
+    let Some(track) = media.get_track(&id) else {
+        let mut response = format!("no song found with the id of {id}").into_response();
+        *response.status_mut() = StatusCode::NOT_FOUND;
+        return response;
+    };
+    let aliases = media.aliases_of(track.audio_file());
+    drop(media);
+    if !std::path::Path::new(track.audio_file()).exists() {
+        let mut response = "the audio file no longer exists".into_response();
+        *response.status_mut() = StatusCode::GONE;
+        return response;
+    }
+
+    match tokio::task::spawn_blocking(move || fileinfo::read(&track, aliases)).await {
+        Ok(Ok(info)) => Json(info).into_response(),
+        Ok(Err(e)) => {
+            warn!("Unable to read the file of {id}: {e}");
+            let mut response = "unable to read the audio file".into_response();
+            *response.status_mut() = StatusCode::INTERNAL_SERVER_ERROR;
+            response
+        }
+        Err(e) => {
+            warn!("Unable to read the file of {id}: {e}");
+            let mut response = "unable to read the audio file".into_response();
+            *response.status_mut() = StatusCode::INTERNAL_SERVER_ERROR;
+            response
+        }
+    }
+}
+
+/// Synced lyrics of a track with their translations, or its lyrics without timing
+#[utoipa::path(
+    get, path = "/lyrics/{id}", tag = "tracks",
+    params(("id" = String, Path, description = "Id of the track")),
+    responses(
+        (status = 200, body = TrackLyrics),
+        (status = 400, description = "Malformed id", body = InvalidId),
+        (status = 404, description = "No such track"),
+    )
+)]
+async fn track_lyrics(Scoped(state): Scoped, id: TrackId) -> Response {
+    let Some(track) = state.library.media.load().get_track(&id) else {
+        let mut response = format!("no song found with the id of {id}").into_response();
+        *response.status_mut() = StatusCode::NOT_FOUND;
+        return response;
+    };
+
+    match tokio::task::spawn_blocking(move || lrc::lyrics(&track)).await {
+        Ok(lyrics) => Json(lyrics).into_response(),
+        Err(e) => {
+            warn!("Unable to read the lyrics of {id}: {e}");
+            let mut response = "unable to read the lyrics".into_response();
+            *response.status_mut() = StatusCode::INTERNAL_SERVER_ERROR;
+            response
+        }
+    }
+}
+
+/// Replaces the lyrics of a track, in its `.lrc` file or its lyrics tag as set by
+/// `library.lyrics_target`, sent to the clients as `track:updated`
+#[utoipa::path(
+    put, path = "/track/{id}/lyrics", tag = "tracks",
+    params(("id" = String, Path, description = "Id of the track")),
+    request_body = NewLyrics,
+    responses(
+        (status = 200, body = TrackLyrics),
+        (status = 400, description = "Malformed id", body = InvalidId),
+        (status = 401, description = "Not an admin"),
+        (status = 403, description = "The account isn't an admin"),
+        (status = 404, description = "No such track"),
+        (status = 409, description = "The track is a part of a file split by a cue sheet"),
+        (status = 500, description = "The lyrics couldn't be written"),
+    )
+)]
+async fn save_lyrics(
+    Scoped(state): Scoped,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    headers: HeaderMap,
+    id: TrackId,
+    Json(lyrics): Json<NewLyrics>,
+) -> Response {
+    if !is_admin(&state, addr, &headers).await {
+        // The files are shared by every account
+        let account = users::bearer(&headers).and_then(|x| state.users.authenticate(x));
+        let mut response = "only the admins may change the lyrics".into_response();
+        *response.status_mut() = match account {
+            Some(_) => StatusCode::FORBIDDEN,
+            None => StatusCode::UNAUTHORIZED,
+        };
+        return response;
+    }
+    let Some(track) = state.library.media.load().get_track(&id) else {
+        let mut response = format!("no song found with the id of {id}").into_response();
+        *response.status_mut() = StatusCode::NOT_FOUND;
+        return response;
+    };
+    if track.source.is_some() {
+        let mut response = "parts of a file split by a cue sheet have no lyrics".into_response();
+        *response.status_mut() = StatusCode::CONFLICT;
+        return response;
+    }
+
+    let embedded = lrc::embedded(&*state.config.read().await);
+    let written = tokio::task::spawn_blocking(move || {
+        let lines = lrc::write(&track, &lyrics, embedded).map_err(|e| e.to_string())?;
+        Ok::<_, String>((lines, lrc::lyrics(&track), track))
+    })
+    .await
+    .map_err(|e| e.to_string())
+    .and_then(|x| x);
+    let (lines, saved, track) = match written {
+        Ok(written) => written,
+        Err(e) => {
+            warn!("Unable to write the lyrics of {id}: {e}");
+            let mut response = format!("unable to write the lyrics: {e}").into_response();
+            *response.status_mut() = StatusCode::INTERNAL_SERVER_ERROR;
+            return response;
+        }
+    };
+    Fingerprints::load(&state.library.cache_dir)
+        .refresh(&[std::path::PathBuf::from(&track.file_path)]);
+
+    let mut media = state.library.media.write().await;
+    media.set_lyrics(&track.id.as_str().into(), lines);
+    utils::save_cache(&state.library.cache_dir, &media);
+    let media = media.commit();
+    let changes = state.library.publish(&state.io, &media);
+    // Plain lyrics aren't part of the track, the clients are told all the same
+    if state.library.default && !changes.updated.tracks.contains(&track.id) {
+        if let Some(track) = media.get_track(&track.id.as_str().into()) {
+            events::emit(&state.io, Event::TrackUpdated, [track]);
+        }
+    }
+
+    Json(saved).into_response()
+}
+
+/// Tracks like a track, the most alike first, to play something like it
+#[utoipa::path(
+    get, path = "/track/{id}/similar", tag = "tracks",
+    params(("id" = String, Path, description = "Id of the track"), SimilarQuery, LiteQuery, QualityQuery),
+    responses(
+        (status = 200, description = "`LiteSimilarTrack`s when lite", body = Vec<TrackSimilarTrack>),
+        (status = 400, description = "Malformed id", body = InvalidId),
+        (status = 404, description = "No such track"),
+    )
+)]
+async fn similar_tracks(
+    Scoped(state): Scoped,
+    id: TrackId,
+    Query(query): Query<SimilarQuery>,
+    Query(lite): Query<LiteQuery>,
+    Query(quality): Query<QualityQuery>,
+) -> Response {
+    let quality = QualityQuery {
+        hide_explicit: state.hide_explicit,
+        ..quality
+    };
+    let media = state.library.media.load();
+    let Some(track) = media.get_track(&id) else {
+        let mut response = format!("no song found with the id of {id}").into_response();
+        *response.status_mut() = StatusCode::NOT_FOUND;
+        return response;
+    };
+
+    let profile = similar::Profile::of([&track]);
+    let tracks = similar::similar(&media, &profile, &quality)
+        .into_iter()
+        .take(query.limit.unwrap_or(25));
+    if lite.lite {
+        let tracks: Vec<SimilarTrack<LiteTrack>> = tracks
+            .map(|(score, x)| SimilarTrack {
+                score,
+                track: lite::track(x),
+            })
+            .collect();
+        Json(tracks).into_response()
+    } else {
+        let tracks: Vec<SimilarTrack> = tracks
+            .map(|(score, x)| SimilarTrack {
+                score,
+                track: x.clone(),
+            })
+            .collect();
+        Json(tracks).into_response()
+    }
+}
+
+/// Called by clients once a track has been listened to the end
+#[utoipa::path(
+    post, path = "/track/{id}/played", tag = "tracks",
+    params(("id" = String, Path, description = "Id of the track")),
+    responses((status = 204), (status = 400, description = "Malformed id", body = InvalidId), (status = 404, description = "No such track"))
+)]
+async fn track_played(Scoped(state): Scoped, id: TrackId) -> StatusCode {
+    let media = state.library.media.load();
+    let Some(track) = media.get_track(&id) else {
+        return StatusCode::NOT_FOUND;
+    };
+    state.history.write().await.record(&track.id);
+    emit_user(&state, Event::TrackPlayed, &track.id);
+
+    let mut listen_later = state.listen_later.write().await;
+    if listen_later.played(&track, &media) {
+        emit_user(&state, Event::ListenLater, listen_later.entries());
+    }
+
+    StatusCode::NO_CONTENT
+}
+
+/// Relays a play request to the players listening on the `/player` namespace
+#[utoipa::path(
+    post, path = "/player/play", tag = "player",
+    request_body = PlayRequest,
+    responses((status = 202), (status = 404, description = "No such track"))
+)]
+async fn player_play(Scoped(state): Scoped, Json(request): Json<PlayRequest>) -> Response {
+    let Some(track) = state
+        .library
+        .media
+        .load()
+        .get_track(&request.id.as_str().into())
+    else {
+        let mut response = format!("no song found with the id of {}", request.id).into_response();
+        *response.status_mut() = StatusCode::NOT_FOUND;
+        return response;
+    };
+    emit_user(&state, Event::PlayerPlay, track);
+
+    StatusCode::ACCEPTED.into_response()
+}
+
+/// Relays tracks to queue to the players listening on the `/player` namespace
+#[utoipa::path(
+    post, path = "/player/queue", tag = "player",
+    request_body = QueueRequest,
+    responses((status = 202), (status = 404, description = "No such track"))
+)]
+async fn player_queue(Scoped(state): Scoped, Json(request): Json<QueueRequest>) -> Response {
+    let media = state.library.media.load();
+    let mut tracks = vec![];
+    for id in request.ids {
+        let Some(track) = media.get_track(&id.as_str().into()) else {
+            let mut response = format!("no song found with the id of {id}").into_response();
+            *response.status_mut() = StatusCode::NOT_FOUND;
+            return response;
+        };
+        tracks.push(track);
+    }
+    state
+        .queue
+        .write()
+        .await
+        .add(tracks.iter().map(|x| x.id.clone()).collect(), request.next);
+    emit_user(
+        &state,
+        Event::QueueAdd,
+        QueuedTracks {
+            tracks,
+            next: request.next,
+        },
+    );
+
+    StatusCode::ACCEPTED.into_response()
+}
+
+/// The shared play queue, with the position reached in the current track
+#[utoipa::path(
+    get, path = "/queue", tag = "player",
+    responses((status = 200, body = PlayQueue))
+)]
+async fn play_queue(Scoped(state): Scoped) -> Json<PlayQueue> {
+    let media = state.library.media.load();
+    Json(state.queue.read().await.get(&media))
+}
+
+/// Saves what a player is playing, sent to the others as `queue:updated`
+#[utoipa::path(
+    put, path = "/queue", tag = "player",
+    request_body = NewQueue,
+    responses((status = 200, body = PlayQueue), (status = 404, description = "No such track"))
+)]
+async fn save_queue(Scoped(state): Scoped, Json(mut request): Json<NewQueue>) -> Response {
+    let media = state.library.media.load();
+    for id in request.tracks.iter_mut() {
+        let Some(track) = media.get_track(&id.as_str().into()) else {
+            let mut response = format!("no song found with the id of {id}").into_response();
+            *response.status_mut() = StatusCode::NOT_FOUND;
+            return response;
+        };
+        *id = track.id;
+    }
+
+    let window = radio::window(&*state.config.read().await);
+    let quality = QualityQuery {
+        hide_explicit: state.hide_explicit,
+        ..Default::default()
+    };
+    let mut queue = state.queue.write().await;
+    queue.set(request);
+    queue.top_up(&media, window, &quality);
+    let saved = queue.get(&media);
+    emit_user(&state, Event::QueueUpdated, &saved);
+
+    Json(saved).into_response()
+}
+
+/// Starts a radio keeping the queue topped up with tracks like a track, an album or a genre,
+/// sent to the players as `queue:updated`
+#[utoipa::path(
+    post, path = "/queue/radio", tag = "player",
+    request_body = RadioRequest,
+    responses(
+        (status = 200, body = PlayQueue),
+        (status = 404, description = "No such track or album, or no track of the genre"),
+    )
+)]
+async fn start_radio(Scoped(state): Scoped, Json(request): Json<RadioRequest>) -> Response {
+    let media = state.library.media.load();
+    // Tracks are given by their current id
+    let seed = match request.seed {
+        RadioSeed::Track { id } => match media.get_track(&id.as_str().into()) {
+            Some(track) => RadioSeed::Track { id: track.id },
+            None => RadioSeed::Track { id },
+        },
+        seed => seed,
+    };
+    if radio::profile(&media, &seed).is_none() {
+        let mut response = "nothing to play like this seed".into_response();
+        *response.status_mut() = StatusCode::NOT_FOUND;
+        return response;
+    }
+
+    let first = request.replace.then(|| match &seed {
+        RadioSeed::Track { id } => vec![id.clone()],
+        _ => vec![],
+    });
+    let window = radio::window(&*state.config.read().await);
+    let quality = QualityQuery {
+        hide_explicit: state.hide_explicit,
+        ..Default::default()
+    };
+    let mut queue = state.queue.write().await;
+    queue.start_radio(seed, first);
+    queue.top_up(&media, window, &quality);
+    let saved = queue.get(&media);
+    emit_user(&state, Event::QueueUpdated, &saved);
+
+    Json(saved).into_response()
+}
+
+/// Stops the radio, the tracks it queued stay
+#[utoipa::path(
+    delete, path = "/queue/radio", tag = "player",
+    responses((status = 200, body = PlayQueue), (status = 404, description = "No radio"))
+)]
+async fn stop_radio(Scoped(state): Scoped) -> Response {
+    let media = state.library.media.load();
+    let mut queue = state.queue.write().await;
+    if !queue.stop_radio() {
+        let mut response = "no radio is playing".into_response();
+        *response.status_mut() = StatusCode::NOT_FOUND;
+        return response;
+    }
+    let saved = queue.get(&media);
+    emit_user(&state, Event::QueueUpdated, &saved);
+
+    Json(saved).into_response()
+}
+
+/// The playback settings from the `[player]` configuration
+#[utoipa::path(
+    get, path = "/player/preferences", tag = "player",
+    responses((status = 200, body = PlaybackPreferences))
+)]
+async fn player_preferences(State(state): State<AppData>) -> Json<PlaybackPreferences> {
+    Json(playback::preferences(&*state.config.read().await, None))
+}
+
+/// Answer of the session routes: the session, sent to its room as `session:updated`, or 404
+fn session_response(state: &AppData, id: &str, session: Option<Session>) -> Response {
+    match session {
+        Some(session) => {
+            events::emit_to(
+                &state.io,
+                sessions::room(id),
+                Event::SessionUpdated,
+                &session,
+            );
+            Json(session).into_response()
+        }
+        None => {
+            let mut response = format!("no session with the id of {id}").into_response();
+            *response.status_mut() = StatusCode::NOT_FOUND;
+            response
+        }
+    }
+}
+
+/// The answer to a request not carrying the token of the host of the session `id`, see
+/// [`sessions::HOST_HEADER`]
+fn host_denial(state: &AppData, id: &str, headers: &HeaderMap) -> Option<Response> {
+    match state.sessions.is_host(id, headers) {
+        Some(true) => None,
+        Some(false) => {
+            let mut response = "only the host controls the session".into_response();
+            *response.status_mut() = StatusCode::UNAUTHORIZED;
+            Some(response)
+        }
+        None => Some(session_response(state, id, None)),
+    }
+}
+
+/// The shared listening sessions
+#[utoipa::path(
+    get, path = "/sessions", tag = "sessions",
+    responses((status = 200, body = Vec<Session>))
+)]
+async fn sessions_list(State(state): State<AppData>) -> Json<Vec<Session>> {
+    Json(state.sessions.list())
+}
+
+/// Starts a shared listening session hosted by the client, which gets the token to control it
+#[utoipa::path(
+    post, path = "/sessions", tag = "sessions",
+    request_body = NewSession,
+    responses((status = 201, body = HostedSession))
+)]
+async fn create_session(
+    State(state): State<AppData>,
+    request: Option<Json<NewSession>>,
+) -> (StatusCode, Json<HostedSession>) {
+    let Json(request) = request.unwrap_or_default();
+    let hosted = state.sessions.create(request.name);
+    (StatusCode::CREATED, Json(hosted))
+}
+
+#[utoipa::path(
+    get, path = "/sessions/{id}", tag = "sessions",
+    params(("id" = String, Path, description = "Id of the session")),
+    responses((status = 200, body = Session), (status = 404, description = "No such session"))
+)]
+async fn session(State(state): State<AppData>, Path(id): Path<String>) -> Response {
+    match state.sessions.get(&id) {
+        Some(session) => Json(session).into_response(),
+        None => session_response(&state, &id, None),
+    }
+}
+
+/// Ends a session, sending `session:ended` to its room
+#[utoipa::path(
+    delete, path = "/sessions/{id}", tag = "sessions",
+    params(
+        ("id" = String, Path, description = "Id of the session"),
+        ("X-Session-Token" = String, Header, description = "Token of the host"),
+    ),
+    responses(
+        (status = 204),
+        (status = 401, description = "Not the host"),
+        (status = 404, description = "No such session"),
+    )
+)]
+async fn end_session(
+    State(state): State<AppData>,
+    Path(id): Path<String>,
+    headers: HeaderMap,
+) -> Response {
+    if let Some(response) = host_denial(&state, &id, &headers) {
+        return response;
+    }
+    state.sessions.end(&id);
+    events::emit_to(&state.io, sessions::room(&id), Event::SessionEnded, &id);
+
+    StatusCode::NO_CONTENT.into_response()
+}
+
+/// Votes for a track to be played next in a session, suggesting it if it isn't queued yet.
+/// Each device has one vote per track.
+#[utoipa::path(
+    post, path = "/sessions/{id}/queue", tag = "sessions",
+    params(("id" = String, Path, description = "Id of the session")),
+    request_body = PlayRequest,
+    responses((status = 200, body = Session), (status = 404, description = "No such session or track"))
+)]
+async fn vote_session_track(
+    Scoped(state): Scoped,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    Path(id): Path<String>,
+    Json(request): Json<PlayRequest>,
+) -> Response {
+    let Some(track) = state
+        .library
+        .media
+        .load()
+        .get_track(&request.id.as_str().into())
+    else {
+        let mut response = format!("no song found with the id of {}", request.id).into_response();
+        *response.status_mut() = StatusCode::NOT_FOUND;
+        return response;
+    };
+    let session = state.sessions.vote(&id, track, addr.ip());
+    session_response(&state, &id, session)
+}
+
+/// Withdraws the vote of the device for a track, which leaves the queue without votes
+#[utoipa::path(
+    delete, path = "/sessions/{id}/queue/{track}", tag = "sessions",
+    params(
+        ("id" = String, Path, description = "Id of the session"),
+        ("track" = String, Path, description = "Id of the track"),
+    ),
+    responses((status = 200, body = Session), (status = 404, description = "No such session"))
+)]
+async fn unvote_session_track(
+    State(state): State<AppData>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    Path((id, track)): Path<(String, String)>,
+) -> Response {
+    let session = state.sessions.unvote(&id, &track, addr.ip());
+    session_response(&state, &id, session)
+}
+
+/// Reports what the host plays, for the listeners to follow
+#[utoipa::path(
+    put, path = "/sessions/{id}/playback", tag = "sessions",
+    params(
+        ("id" = String, Path, description = "Id of the session"),
+        ("X-Session-Token" = String, Header, description = "Token of the host"),
+    ),
+    request_body = SessionPlayback,
+    responses(
+        (status = 200, body = Session),
+        (status = 401, description = "Not the host"),
+        (status = 404, description = "No such session or track"),
+    )
+)]
+async fn session_playback(
+    Scoped(state): Scoped,
+    Path(id): Path<String>,
+    headers: HeaderMap,
+    Json(request): Json<SessionPlayback>,
+) -> Response {
+    if let Some(response) = host_denial(&state, &id, &headers) {
+        return response;
+    }
+    let track = match request.track {
+        Some(track_id) => match state
+            .library
+            .media
+            .load()
+            .get_track(&track_id.as_str().into())
+        {
+            Some(track) => Some(track),
+            None => {
+                let mut response =
+                    format!("no song found with the id of {track_id}").into_response();
+                *response.status_mut() = StatusCode::NOT_FOUND;
+                return response;
+            }
+        },
+        None => None,
+    };
+
+    let session = state
+        .sessions
+        .play(&id, track, request.position, request.playing);
+    session_response(&state, &id, session)
+}
+
+/// Plays the most voted track of the session from its start
+#[utoipa::path(
+    post, path = "/sessions/{id}/next", tag = "sessions",
+    params(
+        ("id" = String, Path, description = "Id of the session"),
+        ("X-Session-Token" = String, Header, description = "Token of the host"),
+    ),
+    responses(
+        (status = 200, body = Session),
+        (status = 401, description = "Not the host"),
+        (status = 404, description = "No such session"),
+    )
+)]
+async fn next_session_track(
+    State(state): State<AppData>,
+    Path(id): Path<String>,
+    headers: HeaderMap,
+) -> Response {
+    if let Some(response) = host_denial(&state, &id, &headers) {
+        return response;
+    }
+    let session = state.sessions.next(&id);
+    session_response(&state, &id, session)
+}
+
+/// The sleep timers and alarms, the next to fire first
+#[utoipa::path(
+    get, path = "/timers", tag = "timers",
+    responses((status = 200, body = Vec<Timer>))
+)]
+async fn timers_list(State(state): State<AppData>) -> Json<Vec<Timer>> {
+    Json(state.timers.read().await.list())
+}
+
+/// Schedules stopping the playback, or playing an album or playlist of the library, sent to the
+/// players as `timer:fired`
+#[utoipa::path(
+    post, path = "/timers", tag = "timers",
+    request_body = NewTimer,
+    responses(
+        (status = 201, body = Timer),
+        (status = 400, description = "Invalid schedule"),
+        (status = 404, description = "No such album or playlist"),
+    )
+)]
+async fn create_timer(Scoped(state): Scoped, Json(request): Json<NewTimer>) -> Response {
+    let target = match &request.action {
+        TimerAction::Stop => None,
+        TimerAction::Album { id } => Some((PlaybackTarget::Album, id.clone())),
+        TimerAction::Playlist { id } => Some((PlaybackTarget::Playlist, id.clone())),
+    };
+    if let Some((target, id)) = target {
+        if !playback_target_exists(&state, target, &id).await {
+            return no_playback_target(target, &id);
+        }
+    }
+
+    let mut timers = state.timers.write().await;
+    match timers.add(request, &state.library.name) {
+        Ok(timer) => {
+            events::emit(&state.io, Event::Timers, timers.list());
+            (StatusCode::CREATED, Json(timer)).into_response()
+        }
+        Err(e) => {
+            let mut response = e.into_response();
+            *response.status_mut() = StatusCode::BAD_REQUEST;
+            response
+        }
+    }
+}
+
+#[utoipa::path(
+    delete, path = "/timers/{id}", tag = "timers",
+    params(("id" = String, Path, description = "Id of the timer")),
+    responses((status = 204), (status = 404, description = "No such timer"))
+)]
+async fn remove_timer(State(state): State<AppData>, Path(id): Path<String>) -> StatusCode {
+    let mut timers = state.timers.write().await;
+    if !timers.remove(&id) {
+        return StatusCode::NOT_FOUND;
+    }
+    events::emit(&state.io, Event::Timers, timers.list());
+
+    StatusCode::NO_CONTENT
+}
+
+/// Whether the album or playlist `id` exists in the library of `state`
+async fn playback_target_exists(state: &AppData, target: PlaybackTarget, id: &str) -> bool {
+    let media = state.library.media.load();
+    match target {
+        PlaybackTarget::Album => media.get_album(&id.into()).is_some(),
+        PlaybackTarget::Playlist => media.get_playlist(&id.into()).is_some(),
+    }
+}
+
+fn no_playback_target(target: PlaybackTarget, id: &str) -> Response {
+    let mut response = match target {
+        PlaybackTarget::Album => format!("no album found with the id of {id}"),
+        PlaybackTarget::Playlist => format!("no playlist found with the id of {id}"),
+    }
+    .into_response();
+    *response.status_mut() = StatusCode::NOT_FOUND;
+    response
+}
+
+async fn get_preferences(state: AppData, target: PlaybackTarget, id: String) -> Response {
+    if !playback_target_exists(&state, target, &id).await {
+        return no_playback_target(target, &id);
+    }
+    let config = state.config.read().await;
+    Json(state.playback.read().await.get(&config, target, &id)).into_response()
+}
+
+async fn save_preferences(
+    state: AppData,
+    target: PlaybackTarget,
+    id: String,
+    hints: PlaybackHints,
+) -> Response {
+    if !playback_target_exists(&state, target, &id).await {
+        return no_playback_target(target, &id);
+    }
+    if hints
+        .crossfade
+        .is_some_and(|x| !(0.0..=playback::MAX_CROSSFADE).contains(&x))
+    {
+        let mut response = format!(
+            "crossfade must be between 0 and {} seconds",
+            playback::MAX_CROSSFADE
+        )
+        .into_response();
+        *response.status_mut() = StatusCode::BAD_REQUEST;
+        return response;
+    }
+
+    let config = state.config.read().await;
+    let mut playback = state.playback.write().await;
+    playback.set(target, &id, hints);
+    let preferences = playback.get(&config, target, &id);
+    events::emit(
+        &state.io,
+        Event::PlaybackUpdated,
+        PlaybackChange {
+            target,
+            id,
+            preferences: preferences.clone(),
+        },
+    );
+
+    Json(preferences).into_response()
+}
+
+async fn remove_preferences(state: AppData, target: PlaybackTarget, id: String) -> StatusCode {
+    let config = state.config.read().await;
+    let mut playback = state.playback.write().await;
+    if !playback.remove(target, &id) {
+        return StatusCode::NOT_FOUND;
+    }
+    events::emit(
+        &state.io,
+        Event::PlaybackUpdated,
+        PlaybackChange {
+            target,
+            preferences: playback.get(&config, target, &id),
+            id,
+        },
+    );
+
+    StatusCode::NO_CONTENT
+}
+
+/// The playback settings of the album, completed with the `[player]` configuration
+#[utoipa::path(
+    get, path = "/album/{id}/preferences", tag = "player",
+    params(("id" = String, Path, description = "Id of the album")),
+    responses((status = 200, body = PlaybackPreferences), (status = 400, description = "Malformed id", body = InvalidId), (status = 404, description = "No such album"))
+)]
+async fn album_preferences(Scoped(state): Scoped, id: AlbumId) -> Response {
+    get_preferences(state, PlaybackTarget::Album, id.into()).await
+}
+
+/// Overrides the playback settings of the album, sent to the players as `playback:updated`
+#[utoipa::path(
+    put, path = "/album/{id}/preferences", tag = "player",
+    params(("id" = String, Path, description = "Id of the album")),
+    request_body = PlaybackHints,
+    responses(
+        (status = 200, body = PlaybackPreferences),
+        (status = 400, description = "Crossfade out of range, or malformed id", body = InvalidId),
+        (status = 404, description = "No such album"),
+    )
+)]
+async fn save_album_preferences(
+    Scoped(state): Scoped,
+    id: AlbumId,
+    Json(hints): Json<PlaybackHints>,
+) -> Response {
+    save_preferences(state, PlaybackTarget::Album, id.into(), hints).await
+}
+
+/// Brings the album back to the `[player]` configuration
+#[utoipa::path(
+    delete, path = "/album/{id}/preferences", tag = "player",
+    params(("id" = String, Path, description = "Id of the album")),
+    responses((status = 204), (status = 400, description = "Malformed id", body = InvalidId), (status = 404, description = "No settings for the album"))
+)]
+async fn remove_album_preferences(Scoped(state): Scoped, id: AlbumId) -> StatusCode {
+    remove_preferences(state, PlaybackTarget::Album, id.into()).await
+}
+
+/// The playback settings of the playlist, completed with the `[player]` configuration
+#[utoipa::path(
+    get, path = "/playlist/{id}/preferences", tag = "player",
+    params(("id" = String, Path, description = "Id of the playlist")),
+    responses((status = 200, body = PlaybackPreferences), (status = 400, description = "Malformed id", body = InvalidId), (status = 404, description = "No such playlist"))
+)]
+async fn playlist_preferences(Scoped(state): Scoped, id: PlaylistId) -> Response {
+    get_preferences(state, PlaybackTarget::Playlist, id.into()).await
+}
+
+/// Overrides the playback settings of the playlist, sent to the players as `playback:updated`
+#[utoipa::path(
+    put, path = "/playlist/{id}/preferences", tag = "player",
+    params(("id" = String, Path, description = "Id of the playlist")),
+    request_body = PlaybackHints,
+    responses(
+        (status = 200, body = PlaybackPreferences),
+        (status = 400, description = "Crossfade out of range, or malformed id", body = InvalidId),
+        (status = 404, description = "No such playlist"),
+    )
+)]
+async fn save_playlist_preferences(
+    Scoped(state): Scoped,
+    id: PlaylistId,
+    Json(hints): Json<PlaybackHints>,
+) -> Response {
+    save_preferences(state, PlaybackTarget::Playlist, id.into(), hints).await
+}
+
+/// Brings the playlist back to the `[player]` configuration
+#[utoipa::path(
+    delete, path = "/playlist/{id}/preferences", tag = "player",
+    params(("id" = String, Path, description = "Id of the playlist")),
+    responses((status = 204), (status = 400, description = "Malformed id", body = InvalidId), (status = 404, description = "No settings for the playlist"))
+)]
+async fn remove_playlist_preferences(Scoped(state): Scoped, id: PlaylistId) -> StatusCode {
+    remove_preferences(state, PlaybackTarget::Playlist, id.into()).await
+}
+
+#[utoipa::path(
+    get, path = "/stats", tag = "library",
+    responses((status = 200, body = Stats))
+)]
+async fn library_stats(Scoped(state): Scoped) -> Json<Stats> {
+    Json(stats::stats(
+        &state.library.media.load(),
+        state.history.read().await.plays(),
+        state.favorites.read().await.tracks().len(),
+    ))
+}
+
+#[utoipa::path(
+    get, path = "/album/{id}", tag = "library",
+    params(("id" = String, Path, description = "Id of the album"), LiteQuery),
+    responses(
+        (status = 200, description = "`LiteAlbumTracks` when lite", body = Album),
+        (status = 400, description = "Malformed id", body = InvalidId),
+        (status = 404, description = "No such album"),
+    )
+)]
+async fn album(Scoped(state): Scoped, id: AlbumId, Query(lite): Query<LiteQuery>) -> Response {
+    let media = state.library.media.load();
+    if let Some(album) = media.get_album(&id) {
+        if lite.lite {
+            return Json(lite::album_tracks(&media, &album)).into_response();
+        }
+        Json(album).into_response()
+    } else {
+        let mut response = format!("no album found with the id of {id}").into_response();
+        *response.status_mut() = StatusCode::NOT_FOUND;
+        response
+    }
+}
+
+/// The tracks of an album grouped by the work they are movements of, for classical albums
+#[utoipa::path(
+    get, path = "/album/{id}/works", tag = "library",
+    params(("id" = String, Path, description = "Id of the album")),
+    responses(
+        (status = 200, body = Vec<AlbumWork>),
+        (status = 400, description = "Malformed id", body = InvalidId),
+        (status = 404, description = "No such album"),
+    )
+)]
+async fn album_works(Scoped(state): Scoped, id: AlbumId) -> Response {
+    let media = state.library.media.load();
+    let Some(album) = media.album(&id) else {
+        let mut response = format!("no album found with the id of {id}").into_response();
+        *response.status_mut() = StatusCode::NOT_FOUND;
+        return response;
+    };
+    Json(composers::album_works(&media, album)).into_response()
+}
+
+/// The images of an album: its cover, and the back cover, booklet pages or photos of the
+/// artists embedded in its tracks, see [`pictures`]
+#[utoipa::path(
+    get, path = "/album/{id}/artwork", tag = "library",
+    params(("id" = String, Path, description = "Id of the album")),
+    responses(
+        (status = 200, body = Vec<AlbumArtwork>),
+        (status = 400, description = "Malformed id", body = InvalidId),
+        (status = 404, description = "No such album"),
+    )
+)]
+async fn album_artwork(Scoped(state): Scoped, id: AlbumId) -> Response {
+    if covers::lazy(&*state.config.read().await) {
+        let media = state.library.media.load();
+        let uncovered = media.album(&id).is_some_and(|x| x.cover_url.is_none());
+        drop(media);
+        if uncovered {
+            covers::extract(&state.library, &state.io, &id).await;
+        }
+    }
+
+    let (album, file) = {
+        let media = state.library.media.load();
+        let Some(album) = media.album(&id) else {
+            let mut response = format!("no album found with the id of {id}").into_response();
+            *response.status_mut() = StatusCode::NOT_FOUND;
+            return response;
+        };
+        let file = album
+            .tracks
+            .first()
+            .and_then(|x| media.tracks.get(x))
+            .map(|x| std::path::PathBuf::from(x.audio_file()));
+        (album.clone(), file)
+    };
+
+    let covers_dir = state.library.cache_dir.join("covers");
+    let images = tokio::task::spawn_blocking(move || {
+        // Left to the first listing by the scans without covers, and for the tracks cached
+        // before the pictures were kept
+        if let Some(file) = file.filter(|_| !covers_dir.join(&album.id).exists()) {
+            pictures::extract(&covers_dir, &album.id, &file);
+        }
+        pictures::list(&covers_dir, &album)
+    })
+    .await
+    .unwrap_or_default();
+    Json(images).into_response()
+}
+
+/// An image listed by `GET /album/{id}/artwork`
+#[utoipa::path(
+    get, path = "/album/{id}/artwork/{name}", tag = "library",
+    params(
+        ("id" = String, Path, description = "Id of the album"),
+        ("name" = String, Path, description = "File name of the image"),
+    ),
+    responses(
+        (status = 200, description = "The image", content_type = "image/*"),
+        (status = 400, description = "The name isn't the one of a file, or malformed id", body = InvalidId),
+        (status = 404, description = "No such image"),
+    )
+)]
+async fn album_artwork_image(
+    Scoped(state): Scoped,
+    id: AlbumId,
+    Path((_, name)): Path<(String, String)>,
+) -> Response {
+    if !is_cover_handle(&id) || !is_cover_handle(&name) {
+        let mut response = "invalid image name".into_response();
+        *response.status_mut() = StatusCode::BAD_REQUEST;
+        return response;
+    }
+
+    let path = state
+        .library
+        .cache_dir
+        .join("covers")
+        .join(id.as_str())
+        .join(&name);
+    match tokio::fs::read(&path).await {
+        Ok(data) => {
+            let mime = mime_guess::from_path(&path).first_or_octet_stream();
+            ([(CONTENT_TYPE, mime.to_string())], data).into_response()
+        }
+        Err(_) => {
+            let mut response = format!("no image named {name}").into_response();
+            *response.status_mut() = StatusCode::NOT_FOUND;
+            response
+        }
+    }
+}
+
+/// The colors of the cover of an album, computed on the spot when they weren't yet
+#[utoipa::path(
+    get, path = "/album/{id}/theme", tag = "library",
+    params(("id" = String, Path, description = "Id of the album")),
+    responses(
+        (status = 200, body = AlbumTheme),
+        (status = 400, description = "Malformed id", body = InvalidId),
+        (status = 404, description = "No such album or the album has no cover"),
+    )
+)]
+async fn album_theme(Scoped(state): Scoped, id: AlbumId) -> Response {
+    match palette::album_palette(&state.library, &id, &state.io).await {
+        Some(palette) => Json(AlbumTheme::new(id.into(), palette)).into_response(),
+        None => {
+            let mut response =
+                format!("no album with a cover found with the id of {id}").into_response();
+            *response.status_mut() = StatusCode::NOT_FOUND;
+            response
+        }
+    }
+}
+
+/// The playlists in folders mirroring the ones of the library they sit in
+#[utoipa::path(
+    get, path = "/playlists/tree", tag = "library",
+    responses((status = 200, body = PlaylistFolder))
+)]
+async fn playlists_tree(Scoped(state): Scoped) -> Json<PlaylistFolder> {
+    let media = state.library.media.load();
+    Json(playlist::tree(&media.playlists, &state.library.paths))
+}
+
+/// The image named like the playlist next to it, see `Playlist::cover_url`
+#[utoipa::path(
+    get, path = "/playlist/{id}/cover", tag = "library",
+    params(("id" = String, Path, description = "Id of the playlist")),
+    responses(
+        (status = 200, description = "The cover", content_type = "image/*"),
+        (status = 400, description = "Malformed id", body = InvalidId),
+        (status = 404, description = "No such playlist or no cover"),
+    )
+)]
+async fn playlist_cover(Scoped(state): Scoped, id: PlaylistId) -> Response {
+    let cover = state
+        .library
+        .media
+        .load()
+        .get_playlist(&id)
+        .and_then(|x| playlist::cover(&x));
+    let Some((path, data)) = cover.and_then(|x| std::fs::read(&x).ok().map(|data| (x, data)))
+    else {
+        let mut response = format!("no cover found for the playlist {id}").into_response();
+        *response.status_mut() = StatusCode::NOT_FOUND;
+        return response;
+    };
+
+    let mut response = Response::new(Body::from(data));
+    let mime = mime_guess::from_path(&path).first_or_octet_stream();
+    if let Ok(mime) = HeaderValue::from_str(mime.as_ref()) {
+        response.headers_mut().insert(CONTENT_TYPE, mime);
+    }
+    response
+}
+
+/// Portrait of an artist, fetched on the first request when `library.external_artwork` is on
+#[utoipa::path(
+    get, path = "/artist/{id}/image", tag = "library",
+    params(("id" = String, Path, description = "Id of the artist")),
+    responses(
+        (status = 200, description = "The portrait", content_type = "image/*"),
+        (status = 404, description = "No such artist or no portrait"),
+    )
+)]
+async fn artist_image(Scoped(state): Scoped, Path(id): Path<String>) -> Response {
+    let name = artists::artist_name(&state.library.media.load(), &id);
+    let Some(name) = name else {
+        let mut response = format!("no artist found with the id of {id}").into_response();
+        *response.status_mut() = StatusCode::NOT_FOUND;
+        return response;
+    };
+
+    let settings = artwork::Settings::from_config(&*state.config.read().await);
+    let covers_dir = state.library.cache_dir.join("covers");
+    let image = state
+        .artwork
+        .artist_image(&covers_dir, &settings, &id, &name)
+        .await;
+    let Some((path, data)) = image.and_then(|x| std::fs::read(&x).ok().map(|data| (x, data)))
+    else {
+        let mut response = format!("no image found for the artist {id}").into_response();
+        *response.status_mut() = StatusCode::NOT_FOUND;
+        return response;
+    };
+
+    let mut response = Response::new(Body::from(data));
+    let mime = mime_guess::from_path(&path).first_or_octet_stream();
+    if let Ok(mime) = HeaderValue::from_str(mime.as_ref()) {
+        response.headers_mut().insert(CONTENT_TYPE, mime);
+    }
+    response
+}
+
+/// Who the limits apply to for the request of `headers`, see [`Client`]
+async fn limited_client(state: &AppData, addr: SocketAddr, headers: &HeaderMap) -> Client {
+    let token = users::bearer(headers);
+    if let Some(user) = token.and_then(|x| state.users.authenticate(x)) {
+        return Client::Account(user.name);
+    }
+    if let Some(token) = token {
+        if shutdown::is_admin_token(&*state.config.read().await, token) {
+            return Client::Admin;
+        }
+    }
+    Client::Ip(proxy::client(headers, addr.ip()))
+}
+
+/// Caps the number of audio streams and downloads a single client can keep open
+async fn limit_streams(
+    State(state): State<AppData>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let max = state
+        .config
+        .read()
+        .await
+        .network
+        .as_ref()
+        .and_then(|network| network.max_streams_per_ip)
+        .unwrap_or(lorconf::Network::default().max_streams_per_ip.unwrap());
+
+    let client = limited_client(&state, addr, request.headers()).await;
+    match state.streams.acquire(client.clone(), max) {
+        Ok(guard) => {
+            let (parts, body) = next.run(request).await.into_parts();
+            Response::from_parts(parts, limits::guarded(body, guard))
+        }
+        Err(active) => {
+            warn!("{client} has too many open streams ({active})");
+            let mut response = format!(
+                "too many concurrent streams: {active} already open by {client}, the limit is {max}"
+            )
+            .into_response();
+            *response.status_mut() = StatusCode::TOO_MANY_REQUESTS;
+            response
+                .headers_mut()
+                .insert(RETRY_AFTER, HeaderValue::from_static("5"));
+            response
+        }
+    }
+}
+
+/// Rate limits the remote clients and caps the size of request bodies. Requests from the
+/// machine itself aren't limited, unless a proxy on it passes them on.
+async fn limit_requests(
+    State(state): State<AppData>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let (per_minute, max_body_size) = {
+        let config = state.config.read().await;
+        let network = config.network.as_ref();
+        let defaults = lorconf::Network::default();
+        (
+            network
+                .and_then(|network| network.requests_per_minute)
+                .unwrap_or(defaults.requests_per_minute.unwrap()),
+            network
+                .and_then(|network| network.max_body_size)
+                .unwrap_or(defaults.max_body_size.unwrap()),
+        )
+    };
+
+    let local = addr.ip().is_loopback() && !proxy::forwarded(request.headers());
+    if per_minute > 0 && !local {
+        let client = limited_client(&state, addr, request.headers()).await;
+        if let Err(wait) = state.requests.check(&client, per_minute) {
+            warn!("{client} exceeds {per_minute} requests per minute");
+            let mut response =
+                format!("too many requests, the limit is {per_minute} per minute").into_response();
+            *response.status_mut() = StatusCode::TOO_MANY_REQUESTS;
+            response.headers_mut().insert(
+                RETRY_AFTER,
+                HeaderValue::from(wait.as_secs_f64().ceil() as u64),
+            );
+            return response;
+        }
+    }
+
+    let length = request
+        .headers()
+        .get(CONTENT_LENGTH)
+        .and_then(|x| x.to_str().ok())
+        .and_then(|x| x.parse::<u64>().ok());
+    if length.is_some_and(|length| length > max_body_size) {
+        let mut response =
+            format!("request body too large, the limit is {max_body_size} bytes").into_response();
+        *response.status_mut() = StatusCode::PAYLOAD_TOO_LARGE;
+        return response;
+    }
+    // Bodies without length are cut once they reach the limit
+    let limit = usize::try_from(max_body_size).unwrap_or(usize::MAX);
+    let request = request.map(|body| Body::new(Limited::new(body, limit)));
+
+    next.run(request).await
+}
+
+/// Runs the request in a `request` span, logs how it ended and keeps the ones changing
+/// something in the audit trail, see [`trail`]. The duration goes up to the response head,
+/// not to the end of a streamed body.
+async fn log_requests(
+    State(state): State<AppData>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let started = std::time::Instant::now();
+    let method = request.method().clone();
+    let path = request.uri().path().to_string();
+    let user = users::bearer(request.headers())
+        .and_then(|x| state.users.authenticate(x))
+        .map(|x| x.name);
+    let library = request
+        .headers()
+        .get(libraries::LIBRARY)
+        .and_then(|x| x.to_str().ok())
+        .map(String::from);
+    let client = user.clone().unwrap_or_else(|| addr.ip().to_string());
+    let span = info_span!(
+        "request",
+        %method,
+        path,
+        client,
+        status = field::Empty,
+        duration_ms = field::Empty,
+    );
+
+    let response = next.run(request).instrument(span.clone()).await;
+    let status = response.status();
+    let duration_ms = started.elapsed().as_millis() as u64;
+    span.record("status", status.as_u16());
+    span.record("duration_ms", duration_ms);
+    let audited = trail::audited(&method, &path);
+    span.in_scope(|| {
+        if status.is_server_error() {
+            warn!("request failed");
+        } else if audited {
+            info!("request done");
+        } else {
+            debug!("request done");
+        }
+    });
+
+    if audited {
+        state.trail.write().await.record(AuditEntry {
+            at: std::time::SystemTime::now(),
+            user,
+            address: addr.ip().to_string(),
+            method: method.to_string(),
+            path,
+            library,
+            status: status.as_u16(),
+            duration_ms,
+        });
+    }
+    response
+}
+
+fn downloads_allowed(config: &lorconf::Config) -> bool {
+    config
+        .library
+        .as_ref()
+        .and_then(|library| library.allow_downloads)
+        .unwrap_or(true)
+}
+
+fn zip_response(name: &str, files: Vec<std::path::PathBuf>, numbered: bool) -> Response {
+    let mut resp = Response::new(archive::zip_body(files, numbered));
+    resp.headers_mut()
+        .insert(CONTENT_TYPE, HeaderValue::from_static("application/zip"));
+    if let Ok(disposition) = HeaderValue::from_str(&archive::attachment(name)) {
+        resp.headers_mut().insert(CONTENT_DISPOSITION, disposition);
+    }
+    resp
+}
+
+#[utoipa::path(
+    get, path = "/album/{id}/download", tag = "streams",
+    params(("id" = String, Path, description = "Id of the album")),
+    responses(
+        (status = 200, description = "Zip archive of the album", content_type = "application/zip"),
+        (status = 403, description = "Downloads are disabled"),
+        (status = 400, description = "Malformed id", body = InvalidId),
+        (status = 404, description = "No such album"),
+    )
+)]
+async fn album_download(Scoped(state): Scoped, id: AlbumId) -> Response {
+    if !downloads_allowed(&*state.config.read().await) {
+        let mut response = "downloads are disabled".into_response();
+        *response.status_mut() = StatusCode::FORBIDDEN;
+        return response;
+    }
+
+    let media = state.library.media.load();
+    if let Some(album) = media.get_album(&id) {
+        zip_response(
+            &format!("{} - {}", album.artists.join(", "), album.name),
+            media.audio_files(&album.tracks),
+            false,
+        )
+    } else {
+        let mut response = format!("no album found with the id of {id}").into_response();
+        *response.status_mut() = StatusCode::NOT_FOUND;
+        response
+    }
+}
+
+#[utoipa::path(
+    get, path = "/playlist/{id}/download", tag = "streams",
+    params(("id" = String, Path, description = "Id of the playlist")),
+    responses(
+        (status = 200, description = "Zip archive of the playlist", content_type = "application/zip"),
+        (status = 403, description = "Downloads are disabled"),
+        (status = 400, description = "Malformed id", body = InvalidId),
+        (status = 404, description = "No such playlist"),
+    )
+)]
+async fn playlist_download(Scoped(state): Scoped, id: PlaylistId) -> Response {
+    if !downloads_allowed(&*state.config.read().await) {
+        let mut response = "downloads are disabled".into_response();
+        *response.status_mut() = StatusCode::FORBIDDEN;
+        return response;
+    }
+
+    let media = state.library.media.load();
+    if let Some(playlist) = media.get_playlist(&id) {
+        zip_response(&playlist.name, media.audio_files(&playlist.tracks), true)
+    } else {
+        let mut response = format!("no playlist found with the id of {id}").into_response();
+        *response.status_mut() = StatusCode::NOT_FOUND;
+        response
+    }
+}
+
+#[utoipa::path(
+    get, path = "/sync/manifest", tag = "library",
+    params(SyncQuery),
+    responses(
+        (status = 200, body = SyncManifest),
+        (status = 400, description = "Malformed id", body = InvalidId),
+        (status = 404, description = "No such playlist"),
+    )
+)]
+async fn sync_manifest(Scoped(state): Scoped, Query(query): Query<SyncQuery>) -> Response {
+    let mut playlists = vec![];
+    {
+        let media = state.library.media.load();
+        for id in query.playlists.split(',').filter(|x| !x.is_empty()) {
+            let id = match PlaylistId::parse(id) {
+                Ok(id) => id,
+                Err(e) => return (StatusCode::BAD_REQUEST, Json(e)).into_response(),
+            };
+            let Some(playlist) = media.get_playlist(&id) else {
+                let mut response = format!("no playlist found with the id of {id}").into_response();
+                *response.status_mut() = StatusCode::NOT_FOUND;
+                return response;
+            };
+            let tracks = playlist
+                .tracks
+                .iter()
+                .filter_map(|x| media.tracks.get(x))
+                .filter(|x| !(state.hide_explicit && x.is_explicit()))
+                .cloned()
+                .collect();
+            playlists.push((playlist, tracks));
+        }
+    }
+
+    let cache_dir = state.library.cache_dir.clone();
+    match tokio::task::spawn_blocking(move || sync::manifest(&cache_dir, playlists)).await {
+        Ok(manifest) => Json(manifest).into_response(),
+        Err(e) => {
+            warn!("Unable to make the sync manifest: {e}");
+            StatusCode::INTERNAL_SERVER_ERROR.into_response()
+        }
+    }
+}
+
+/// Zip archive of files of a sync manifest, named by their path in it. The files of remote
+/// storages are left out, they are fetched alone from their url.
+#[utoipa::path(
+    post, path = "/sync/download", tag = "streams",
+    request_body = SyncDownload,
+    responses(
+        (status = 200, description = "Zip archive of the files", content_type = "application/zip"),
+        (status = 400, description = "A path isn't one of a manifest"),
+        (status = 403, description = "Downloads are disabled"),
+        (status = 404, description = "No such track"),
+    )
+)]
+async fn sync_download(Scoped(state): Scoped, Json(request): Json<SyncDownload>) -> Response {
+    if !downloads_allowed(&*state.config.read().await) {
+        let mut response = "downloads are disabled".into_response();
+        *response.status_mut() = StatusCode::FORBIDDEN;
+        return response;
+    }
+
+    let mut entries = vec![];
+    for path in request.paths {
+        let (id, audio) = match path.split_once('/') {
+            Some(("covers", handle)) if is_cover_handle(handle) => {
+                let file = state.library.cache_dir.join("covers").join(handle);
+                entries.push((path, archive::Source::File(file)));
+                continue;
+            }
+            Some(("audio", name)) => (name.split('.').next().unwrap_or_default(), true),
+            Some(("lyrics", name)) => (name.strip_suffix(".json").unwrap_or(name), false),
+            _ => {
+                let mut response = format!("`{path}` isn't a file of a manifest").into_response();
+                *response.status_mut() = StatusCode::BAD_REQUEST;
+                return response;
+            }
+        };
+        let track = state.library.media.load().get_track(&id.into());
+        let Some(track) = track.filter(|x| !(state.hide_explicit && x.is_explicit())) else {
+            let mut response = format!("no song found with the id of {id}").into_response();
+            *response.status_mut() = StatusCode::NOT_FOUND;
+            return response;
+        };
+
+        if !audio {
+            let lyrics = tokio::task::spawn_blocking(move || sync::lyrics_data(&track)).await;
+            if let Ok(Some(data)) = lyrics {
+                entries.push((path, archive::Source::Data(data)));
+            }
+        } else if track.source.is_some() {
+            match cue_cut(&state, &track).await {
+                Ok((file, _)) => entries.push((path, archive::Source::File(file))),
+                Err(e) => warn!("zip: skipping `{path}`: {e}"),
+            }
+        } else if !remote::is_remote(std::path::Path::new(&track.file_path)) {
+            entries.push((path, archive::Source::File(track.file_path.into())));
+        }
+    }
+
+    let mut resp = Response::new(archive::entries_body(entries));
+    resp.headers_mut()
+        .insert(CONTENT_TYPE, HeaderValue::from_static("application/zip"));
+    if let Ok(disposition) = HeaderValue::from_str(&archive::attachment("sync")) {
+        resp.headers_mut().insert(CONTENT_DISPOSITION, disposition);
+    }
+    resp
+}
+
+/// Gapless playback information of the served track, see [`mu_protocol::library::Gapless`]
+const ENCODER_DELAY: HeaderName = HeaderName::from_static("x-encoder-delay");
+const ENCODER_PADDING: HeaderName = HeaderName::from_static("x-encoder-padding");
+const SAMPLE_COUNT: HeaderName = HeaderName::from_static("x-sample-count");
+
+/// The cut of the virtual `track`, made by a transcode job unless already up to date
+async fn cue_cut(
+    state: &AppData,
+    track: &Track,
+) -> std::io::Result<(std::path::PathBuf, &'static str)> {
+    let (path, mime, fresh) = cue::cut(&state.library.cache_dir, track)?;
+    if fresh {
+        return Ok((path, mime));
+    }
+
+    let (_, done) = submit_job(state, JobKind::Transcode, Some(track.id.clone()));
+    match done.await {
+        Ok(job) if job.state == JobState::Done => Ok((path, mime)),
+        Ok(job) => Err(std::io::Error::other(
+            job.error.unwrap_or("the cut was cancelled".to_string()),
+        )),
+        Err(_) => Err(std::io::Error::other("the daemon is stopping")),
+    }
+}
+
+/// Streams the file of `track`, honoring `Range` requests and answering `HEAD` without a body.
+/// Tracks split by a cue sheet are served from a cut of their file, see [`cue_cut`].
+async fn serve_track(
+    track: Track,
+    range: Option<Range>,
+    method: Method,
+    state: &AppData,
+) -> Response {
+    let (path, mime, file_name) = if track.source.is_some() {
+        match cue_cut(state, &track).await {
+            Ok((path, mime)) => {
+                let ext = path.extension().unwrap_or_default().to_string_lossy();
+                let name = format!("{:02} - {}.{ext}", track.track, track.title);
+                (path, mime.to_string(), name)
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                warn!("`{}` is no longer available", track.audio_file());
+                let mut response =
+                    format!("the file `{}` is gone", track.audio_file()).into_response();
+                *response.status_mut() = StatusCode::GONE;
+                return response;
+            }
+            Err(e) => {
+                warn!("Fail to extract `{}`: {e}", track.file_path);
+                let mut response =
+                    format!("unable to extract `{}`", track.file_path).into_response();
+                *response.status_mut() = StatusCode::INTERNAL_SERVER_ERROR;
+                return response;
+            }
+        }
+    } else {
+        let path = std::path::PathBuf::from(&track.file_path);
+        let name = path
+            .file_name()
+            .map(|x| x.to_string_lossy().to_string())
+            .unwrap_or_default();
+        (path, track.mime.clone(), name)
+    };
+
+    let mut response = if remote::is_remote(&path) {
+        let cache_size = state
+            .config
+            .read()
+            .await
+            .library
+            .as_ref()
+            .and_then(|library| library.remote_cache_size)
+            .or(lorconf::Library::default().remote_cache_size)
+            .unwrap_or_default();
+        let head = method == Method::HEAD;
+        match remote::respond(
+            &state.library.cache_dir,
+            &path,
+            range,
+            head,
+            cache_size << 20,
+        )
+        .await
+        {
+            Ok(response) => response,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                warn!("`{}` is no longer available", path.display());
+                let mut response = format!("the file `{}` is gone", path.display()).into_response();
+                *response.status_mut() = StatusCode::GONE;
+                return response;
+            }
+            Err(e) => {
+                warn!("Fail to reach `{}`: {e}", path.display());
+                let mut response = format!("unable to reach `{}`", path.display()).into_response();
+                *response.status_mut() = StatusCode::BAD_GATEWAY;
+                return response;
+            }
+        }
+    } else {
+        let file = match File::open(&path).await {
+            Ok(file) => file,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                warn!("`{}` is no longer available", path.display());
+                let mut response = format!("the file `{}` is gone", path.display()).into_response();
+                *response.status_mut() = StatusCode::GONE;
+                return response;
+            }
+            Err(e) => {
+                warn!("Fail to open `{}`: {e}", path.display());
+                let mut response = format!("unable to open `{}`", path.display()).into_response();
+                *response.status_mut() = StatusCode::INTERNAL_SERVER_ERROR;
+                return response;
+            }
+        };
+
+        let size = match file.metadata().await {
+            Ok(meta) => meta.len(),
+            Err(e) => {
+                warn!("Fail to read the metadata of `{}`: {e}", path.display());
+                let mut response = format!("unable to open `{}`", path.display()).into_response();
+                *response.status_mut() = StatusCode::INTERNAL_SERVER_ERROR;
+                return response;
+            }
+        };
+
+        if method == Method::HEAD {
+            (
+                TypedHeader(ContentLength(size)),
+                TypedHeader(AcceptRanges::bytes()),
+            )
+                .into_response()
+        } else {
+            let range = match utils::byte_range(range.as_ref(), size) {
+                Ok(bounds) => bounds.and_then(|(start, end)| Range::bytes(start..=end).ok()),
+                Err(not_satisfiable) => return not_satisfiable.into_response(),
+            };
+            match Ranged::new(range, KnownSize::sized(file, size)).try_respond() {
+                Ok(response) => response.into_response(),
+                Err(not_satisfiable) => return not_satisfiable.into_response(),
+            }
+        }
+    };
+
+    if let Ok(mime) = HeaderValue::from_str(&mime) {
+        response.headers_mut().insert(CONTENT_TYPE, mime);
+    }
+    if let Some(gapless) = track.gapless {
+        let headers = response.headers_mut();
+        headers.insert(ENCODER_DELAY, HeaderValue::from(gapless.encoder_delay));
+        headers.insert(ENCODER_PADDING, HeaderValue::from(gapless.padding));
+        if let Some(samples) = gapless.sample_count {
+            headers.insert(SAMPLE_COUNT, HeaderValue::from(samples));
+        }
+    }
+    if let Ok(disposition) =
+        HeaderValue::from_str(&utils::content_disposition("inline", &file_name))
+    {
+        response
+            .headers_mut()
+            .insert(CONTENT_DISPOSITION, disposition);
+    }
+
+    response
+}
+
+#[utoipa::path(
+    get, path = "/audio/{id}", tag = "streams",
+    params(("id" = String, Path, description = "Id of the track")),
+    responses(
+        (status = 200, description = "The audio file", content_type = "audio/*"),
+        (status = 206, description = "The requested range", content_type = "audio/*"),
+        (status = 400, description = "Malformed id", body = InvalidId),
+        (status = 404, description = "No such track"),
+        (status = 410, description = "The file was removed"),
+    )
+)]
+async fn audio(
+    method: Method,
+    range: Option<TypedHeader<Range>>,
+    Scoped(state): Scoped,
+    id: TrackId,
+) -> Response {
+    if let Some(track) = state.library.media.load().get_track(&id) {
+        serve_track(track, range.map(|TypedHeader(range)| range), method, &state).await
+    } else {
+        warn!("{id} not founded");
+        let mut response = format!("no song found with the id of {id}").into_response();
+        *response.status_mut() = StatusCode::NOT_FOUND;
+        response
+    }
+}
+
+/// Same as [`audio`], with the id passed as the `path` query parameter
+#[utoipa::path(
+    get, path = "/audio", tag = "streams",
+    params(MusicPath),
+    responses(
+        (status = 200, description = "The audio file", content_type = "audio/*"),
+        (status = 206, description = "The requested range", content_type = "audio/*"),
+        (status = 404, description = "No such track"),
+        (status = 410, description = "The file was removed"),
+    )
+)]
+async fn audio_by_path(
+    method: Method,
+    range: Option<TypedHeader<Range>>,
+    state: Scoped,
+    Query(music_path): Query<MusicPath>,
+) -> Response {
+    match TrackId::parse(&music_path.path) {
+        Ok(id) => audio(method, range, state, id).await,
+        Err(e) => (StatusCode::BAD_REQUEST, Json(e)).into_response(),
+    }
+}
+
+/// Read-only WebDAV tree of the library, see [`dav`]. Left out of the API documentation, as its
+/// methods are not described by OpenAPI. `OPTIONS` is answered by the CORS layer, with the
+/// headers of [`dav::options`].
+async fn dav_tree(
+    method: Method,
+    headers: HeaderMap,
+    range: Option<TypedHeader<Range>>,
+    Scoped(state): Scoped,
+    path: Option<Path<String>>,
+) -> Response {
+    let allow = |mut response: Response| {
+        response
+            .headers_mut()
+            .insert(ALLOW, HeaderValue::from_static(dav::METHODS));
+        response
+    };
+    let propfind = method.as_str() == "PROPFIND";
+    if !propfind && method != Method::GET && method != Method::HEAD {
+        let mut response = allow("the WebDAV tree is read-only".into_response());
+        *response.status_mut() = StatusCode::METHOD_NOT_ALLOWED;
+        return response;
+    }
+
+    let segments: Vec<String> = path
+        .map(|Path(path)| {
+            path.split('/')
+                .filter(|x| !x.is_empty())
+                .map(str::to_string)
+                .collect()
+        })
+        .unwrap_or_default();
+    let pattern = dav::pattern(&*state.config.read().await);
+    let entries = state
+        .library
+        .dav
+        .entries(
+            state.library.media.load(),
+            &state.library.cache_dir,
+            pattern,
+            state.hide_explicit,
+        )
+        .await;
+    let Some(node) = dav::resolve(&entries, &segments) else {
+        let mut response = format!("nothing at `/{}`", segments.join("/")).into_response();
+        *response.status_mut() = StatusCode::NOT_FOUND;
+        return response;
+    };
+
+    if propfind {
+        // `infinity` is answered as 1, the whole library being too large to list at once
+        let depth = match headers.get("Depth").and_then(|x| x.to_str().ok()) {
+            Some("0") => 0,
+            _ => 1,
+        };
+        let library = headers
+            .get(libraries::LIBRARY)
+            .and_then(|x| x.to_str().ok());
+        let mut response =
+            dav::multistatus(&dav::base(library), &segments, &node, depth).into_response();
+        *response.status_mut() = StatusCode::MULTI_STATUS;
+        response.headers_mut().insert(
+            CONTENT_TYPE,
+            HeaderValue::from_static("application/xml; charset=utf-8"),
+        );
+        return response;
+    }
+
+    match node {
+        dav::Node::File(entry) => {
+            let track = entry.track.clone();
+            serve_track(track, range.map(|TypedHeader(range)| range), method, &state).await
+        }
+        dav::Node::Collection(..) => {
+            let mut response = allow("use PROPFIND to list a folder".into_response());
+            *response.status_mut() = StatusCode::METHOD_NOT_ALLOWED;
+            response
+        }
+    }
+}
+
+#[utoipa::path(
+    get, path = "/audio/{id}/seektable", tag = "streams",
+    params(("id" = String, Path, description = "Id of the track"), SeekTableQuery),
+    responses(
+        (status = 200, body = SeekTable),
+        (status = 400, description = "Malformed id", body = InvalidId),
+        (status = 404, description = "No such track"),
+        (status = 410, description = "The file was removed"),
+    )
+)]
+async fn seek_table(
+    Scoped(state): Scoped,
+    id: TrackId,
+    Query(query): Query<SeekTableQuery>,
+) -> Response {
+    let Some(mut track) = state.library.media.load().get_track(&id) else {
+        let mut response = format!("no song found with the id of {id}").into_response();
+        *response.status_mut() = StatusCode::NOT_FOUND;
+        return response;
+    };
+
+    // Tracks split by a cue sheet are seeked in the cut served by `/audio`
+    if track.source.is_some() {
+        match cue_cut(&state, &track).await {
+            Ok((path, mime)) => {
+                track.file_path = path.to_string_lossy().to_string();
+                track.mime = mime.to_string();
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                let mut response = "the audio file no longer exists".into_response();
+                *response.status_mut() = StatusCode::GONE;
+                return response;
+            }
+            Err(e) => {
+                warn!("Unable to extract {id}: {e}");
+                let mut response = "unable to build the seek table".into_response();
+                *response.status_mut() = StatusCode::INTERNAL_SERVER_ERROR;
+                return response;
+            }
+        }
+    }
+
+    // Longer intervals would only give the start of the file, and overflow the offsets
+    let interval = query
+        .interval
+        .unwrap_or(seek::DEFAULT_INTERVAL)
+        .clamp(1, track.duration.max(1));
+    let cache_dir = state.library.cache_dir.clone();
+    let table =
+        tokio::task::spawn_blocking(move || seek::seek_table(&cache_dir, &track, interval)).await;
+
+    match table {
+        Ok(Ok(table)) => Json(table).into_response(),
+        Ok(Err(e)) if e.kind() == std::io::ErrorKind::NotFound => {
+            let mut response = "the audio file no longer exists".into_response();
+            *response.status_mut() = StatusCode::GONE;
+            response
+        }
+        Ok(Err(e)) => {
+            warn!("Unable to build the seek table of {id}: {e}");
+            let mut response = "unable to build the seek table".into_response();
+            *response.status_mut() = StatusCode::INTERNAL_SERVER_ERROR;
+            response
+        }
+        Err(e) => {
+            warn!("Unable to build the seek table of {id}: {e}");
+            let mut response = "unable to build the seek table".into_response();
+            *response.status_mut() = StatusCode::INTERNAL_SERVER_ERROR;
+            response
+        }
+    }
+}
+
+#[utoipa::path(
+    get, path = "/audio/{id}/hls/playlist.m3u8", tag = "streams",
+    params(("id" = String, Path, description = "Id of the track")),
+    responses(
+        (status = 200, content_type = "application/vnd.apple.mpegurl"),
+        (status = 400, description = "Malformed id", body = InvalidId),
+        (status = 404, description = "No such track"),
+    )
+)]
+async fn hls_playlist(Scoped(state): Scoped, id: TrackId) -> Response {
+    if let Some(track) = hls_track(&state, &id).await {
+        let mut resp = Response::new(Body::from(hls::playlist(&track)));
+        resp.headers_mut().insert(
+            CONTENT_TYPE,
+            HeaderValue::from_static("application/vnd.apple.mpegurl"),
+        );
+        resp
+    } else {
+        let mut response = format!("no song found with the id of {id}").into_response();
+        *response.status_mut() = StatusCode::NOT_FOUND;
+        response
+    }
+}
+
+/// The track `id` as streamed, without its silences when `player.trim_silence` is on
+async fn hls_track(state: &AppData, id: &str) -> Option<Track> {
+    let track = state.library.media.load().get_track(&id.into())?;
+    if playback::preferences(&*state.config.read().await, None).trim_silence {
+        return Some(hls::trimmed(&track));
+    }
+    Some(track)
+}
+
+#[utoipa::path(
+    get, path = "/audio/{id}/hls/{segment}", tag = "streams",
+    params(("id" = String, Path, description = "Id of the track"), ("segment" = String, Path, description = "`segment<n>.ts`")),
+    responses(
+        (status = 200, content_type = "video/mp2t"),
+        (status = 400, description = "Malformed id", body = InvalidId),
+        (status = 404, description = "No such segment"),
+        (status = 503, description = "The encoder could not start"),
+    )
+)]
+async fn hls_segment(
+    Scoped(state): Scoped,
+    id: TrackId,
+    Path((_, segment)): Path<(String, String)>,
+) -> Response {
+    let track = hls_track(&state, &id).await;
+    let (Some(track), Some(n)) = (track, hls::parse_segment_name(&segment)) else {
+        let mut response = format!("no segment `{segment}` for the id of {id}").into_response();
+        *response.status_mut() = StatusCode::NOT_FOUND;
+        return response;
+    };
+
+    match state.hls.segment(&state.library.cache_dir, &track, n).await {
+        Ok(Some(path)) => match tokio::fs::read(&path).await {
+            Ok(bytes) => {
+                let mut resp = Response::new(Body::from(bytes));
+                resp.headers_mut()
+                    .insert(CONTENT_TYPE, HeaderValue::from_static("video/mp2t"));
+                resp
+            }
+            Err(e) => {
+                warn!("Fail to read the segment file `{}`: {e}", path.display());
+                let mut response = "unable to read the segment".into_response();
+                *response.status_mut() = StatusCode::INTERNAL_SERVER_ERROR;
+                response
+            }
+        },
+        Ok(None) => {
+            let mut response = format!("no segment `{segment}` for the id of {id}").into_response();
+            *response.status_mut() = StatusCode::NOT_FOUND;
+            response
+        }
+        Err(e) => {
+            warn!("hls: unable to start the encoder: {e}");
+            let mut response = "unable to start the hls encoder".into_response();
+            *response.status_mut() = StatusCode::SERVICE_UNAVAILABLE;
+            response
+        }
+    }
+}
+
+#[utoipa::path(
+    get, path = "/config", tag = "daemon",
+    responses(
+        (status = 200, description = "The configuration, without its secrets", body = Object),
+        (status = 401, description = "Not an admin"),
+    )
+)]
+async fn get_config(
+    State(state): State<AppData>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    headers: HeaderMap,
+) -> Response {
+    if !is_admin(&state, addr, &headers).await {
+        return StatusCode::UNAUTHORIZED.into_response();
+    }
+
+    Json(config::redacted(&*state.config.read().await)).into_response()
+}
+
+#[utoipa::path(
+    patch, path = "/config", tag = "daemon",
+    request_body(content = Object, description = "JSON merge patch of the configuration"),
+    responses(
+        (status = 200, description = "The new configuration", body = Object),
+        (status = 400, description = "Invalid configuration"),
+        (status = 401, description = "Not an admin"),
+        (status = 403, description = "Changes network.admin_token without holding it"),
+    )
+)]
+async fn patch_config(
+    State(state): State<AppData>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    headers: HeaderMap,
+    Json(patch): Json<serde_json::Value>,
+) -> Response {
+    if !is_admin(&state, addr, &headers).await {
+        return StatusCode::UNAUTHORIZED.into_response();
+    }
+
+    let mut config = state.config.write().await;
+    let mut value = serde_json::to_value(&*config).unwrap();
+    config::merge_patch(&mut value, patch);
+
+    let mut new_config = match serde_json::from_value::<lorconf::Config>(value) {
+        Ok(new_config) => new_config,
+        Err(e) => {
+            let mut response = format!("invalid configuration: {e}").into_response();
+            *response.status_mut() = StatusCode::BAD_REQUEST;
+            return response;
+        }
+    };
+    config::keep_bind_address(&config, &mut new_config);
+    config::keep_secrets(&config, &mut new_config);
+    // Admin accounts can't take over the token of the daemon
+    if config::changes_admin_token(&config, &new_config)
+        && !shutdown::is_authorized(&config, addr.ip(), &headers)
+    {
+        let mut response = "only the holder of network.admin_token may change it".into_response();
+        *response.status_mut() = StatusCode::FORBIDDEN;
+        return response;
+    }
+
+    lorconf::Config::dump(&state.dirs.config.join("config.toml"), new_config.clone());
+    *config = new_config;
+    webhooks::configure(&config);
+    cors::configure(&config);
+    events::emit(&state.io, Event::Config, config::redacted(&config));
+
+    Json(config::redacted(&config)).into_response()
+}
+
+#[utoipa::path(
+    post, path = "/shutdown", tag = "daemon",
+    responses((status = 202), (status = 401, description = "Not allowed to stop the daemon"))
+)]
+async fn shutdown_daemon(
+    State(state): State<AppData>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    headers: HeaderMap,
+) -> StatusCode {
+    if !is_admin(&state, addr, &headers).await {
+        return StatusCode::UNAUTHORIZED;
+    }
+
+    info!("shutdown requested by {}", addr.ip());
+    state.shutdown.cancel();
+    StatusCode::ACCEPTED
+}
+
+/// Whether the request may manage the daemon: it carries the token of an admin account, or no
+/// account token and is allowed by [`shutdown::is_authorized`]
+async fn is_admin(state: &AppData, addr: SocketAddr, headers: &HeaderMap) -> bool {
+    match users::bearer(headers).and_then(|x| state.users.authenticate(x)) {
+        Some(user) => user.role == Role::Admin,
+        None => shutdown::is_authorized(&*state.config.read().await, addr.ip(), headers),
+    }
+}
+
+/// The accounts of the daemon
+#[utoipa::path(
+    get, path = "/admin/users", tag = "users",
+    responses((status = 200, body = Vec<User>), (status = 401, description = "Not an admin"))
+)]
+async fn users_list(
+    State(state): State<AppData>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    headers: HeaderMap,
+) -> Response {
+    if !is_admin(&state, addr, &headers).await {
+        return StatusCode::UNAUTHORIZED.into_response();
+    }
+
+    Json(state.users.list()).into_response()
+}
+
+/// The requests that changed something, newest first, see [`trail`]
+#[utoipa::path(
+    get, path = "/admin/audit", tag = "users",
+    params(AuditQuery),
+    responses(
+        (status = 200, body = Vec<AuditEntry>),
+        (status = 401, description = "Not an admin"),
+    )
+)]
+async fn audit_trail(
+    State(state): State<AppData>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    headers: HeaderMap,
+    Query(query): Query<AuditQuery>,
+) -> Response {
+    if !is_admin(&state, addr, &headers).await {
+        return StatusCode::UNAUTHORIZED.into_response();
+    }
+
+    Json(state.trail.read().await.query(&query)).into_response()
+}
+
+/// Creates an account, its token is only given in the response
+#[utoipa::path(
+    post, path = "/admin/users", tag = "users",
+    request_body = NewUser,
+    responses(
+        (status = 201, body = UserToken),
+        (status = 400, description = "Invalid name or unknown library"),
+        (status = 401, description = "Not an admin"),
+        (status = 409, description = "The name is taken"),
+    )
+)]
+async fn create_user(
+    State(state): State<AppData>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    headers: HeaderMap,
+    Json(new): Json<NewUser>,
+) -> Response {
+    if !is_admin(&state, addr, &headers).await {
+        return StatusCode::UNAUTHORIZED.into_response();
+    }
+    if let Some(name) = unknown_library(&state, &new.libraries) {
+        let mut response = format!("no library named {name}").into_response();
+        *response.status_mut() = StatusCode::BAD_REQUEST;
+        return response;
+    }
+    if state.users.list().iter().any(|x| x.name == new.name.trim()) {
+        let mut response = format!("a user named {} already exists", new.name).into_response();
+        *response.status_mut() = StatusCode::CONFLICT;
+        return response;
+    }
+
+    match state.users.create(new) {
+        Ok(created) => (StatusCode::CREATED, Json(created)).into_response(),
+        Err(e) => {
+            let mut response = e.into_response();
+            *response.status_mut() = StatusCode::BAD_REQUEST;
+            response
+        }
+    }
+}
+
+/// The first of `names` that isn't the name of a library
+fn unknown_library<'a>(state: &AppData, names: &'a [String]) -> Option<&'a String> {
+    names
+        .iter()
+        .find(|name| state.libraries.get(Some(name.as_str())).is_none())
+}
+
+/// Changes the settings of an account
+#[utoipa::path(
+    patch, path = "/admin/users/{name}", tag = "users",
+    params(("name" = String, Path, description = "Name of the user")),
+    request_body = UserChanges,
+    responses(
+        (status = 200, body = User),
+        (status = 400, description = "Unknown library"),
+        (status = 401, description = "Not an admin"),
+        (status = 404, description = "No such user"),
+    )
+)]
+async fn change_user(
+    State(state): State<AppData>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    headers: HeaderMap,
+    Path(name): Path<String>,
+    Json(changes): Json<UserChanges>,
+) -> Response {
+    if !is_admin(&state, addr, &headers).await {
+        return StatusCode::UNAUTHORIZED.into_response();
+    }
+    if let Some(library) = changes
+        .libraries
+        .as_ref()
+        .and_then(|x| unknown_library(&state, x))
+    {
+        let mut response = format!("no library named {library}").into_response();
+        *response.status_mut() = StatusCode::BAD_REQUEST;
+        return response;
+    }
+
+    match state.users.change(&name, changes) {
+        Some(user) => Json(user).into_response(),
+        None => {
+            let mut response = format!("no user named {name}").into_response();
+            *response.status_mut() = StatusCode::NOT_FOUND;
+            response
+        }
+    }
+}
+
+/// Removes an account along with its favorites, history and queue
+#[utoipa::path(
+    delete, path = "/admin/users/{name}", tag = "users",
+    params(("name" = String, Path, description = "Name of the user")),
+    responses(
+        (status = 204),
+        (status = 401, description = "Not an admin"),
+        (status = 404, description = "No such user"),
+    )
+)]
+async fn delete_user(
+    State(state): State<AppData>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    headers: HeaderMap,
+    Path(name): Path<String>,
+) -> StatusCode {
+    if !is_admin(&state, addr, &headers).await {
+        return StatusCode::UNAUTHORIZED;
+    }
+
+    if state.users.remove(&name) {
+        StatusCode::NO_CONTENT
+    } else {
+        StatusCode::NOT_FOUND
+    }
+}
+
+/// Gives an account a new token, the former one no longer works
+#[utoipa::path(
+    post, path = "/admin/users/{name}/token", tag = "users",
+    params(("name" = String, Path, description = "Name of the user")),
+    responses(
+        (status = 200, body = UserToken),
+        (status = 401, description = "Not an admin"),
+        (status = 404, description = "No such user"),
+    )
+)]
+async fn renew_user_token(
+    State(state): State<AppData>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    headers: HeaderMap,
+    Path(name): Path<String>,
+) -> Response {
+    if !is_admin(&state, addr, &headers).await {
+        return StatusCode::UNAUTHORIZED.into_response();
+    }
+
+    match state.users.renew(&name) {
+        Some(renewed) => Json(renewed).into_response(),
+        None => {
+            let mut response = format!("no user named {name}").into_response();
+            *response.status_mut() = StatusCode::NOT_FOUND;
+            response
+        }
+    }
+}
+
+/// The account of the token of the request, `null` for the default user
+#[utoipa::path(
+    get, path = "/me", tag = "users",
+    responses((status = 200, body = Option<User>), (status = 401, description = "Unknown token"))
+)]
+async fn me(Scoped(state): Scoped) -> Json<Option<User>> {
+    let user = state
+        .user
+        .and_then(|name| state.users.list().into_iter().find(|x| x.name == name));
+    Json(user)
+}
+
+/// Play history of the account, oldest first, to move it to another player or service
+#[utoipa::path(
+    get, path = "/history/export", tag = "users",
+    params(HistoryQuery),
+    responses((status = 200, description = "The plays in the format asked for", body = Vec<ExportedPlay>))
+)]
+async fn history_export(Scoped(state): Scoped, Query(query): Query<HistoryQuery>) -> Response {
+    let plays = state.history.read().await.list().to_vec();
+    let body = listens::export_body(plays, Arc::clone(&state.library.media), query.format);
+
+    let mut response = Response::new(body);
+    let headers = response.headers_mut();
+    headers.insert(
+        CONTENT_TYPE,
+        HeaderValue::from_static(listens::content_type(query.format)),
+    );
+    let disposition = utils::content_disposition("attachment", listens::file_name(query.format));
+    if let Ok(disposition) = HeaderValue::from_str(&disposition) {
+        headers.insert(CONTENT_DISPOSITION, disposition);
+    }
+    response
+}
+
+/// Adds the plays of an export of `GET /history/export`, or of ListenBrainz, to the history of
+/// the account. The plays already in it are left out.
+#[utoipa::path(
+    post, path = "/history/import", tag = "users",
+    params(HistoryQuery),
+    request_body(content = String, description = "An export in the format given"),
+    responses(
+        (status = 200, body = HistoryImportReport),
+        (status = 400, description = "The export can't be read"),
+    )
+)]
+async fn history_import(
+    Scoped(state): Scoped,
+    Query(query): Query<HistoryQuery>,
+    body: String,
+) -> Response {
+    let media = state.library.media.load();
+    let mut history = state.history.write().await;
+    match listens::import(&body, query.format, &media, history.list()) {
+        Ok((plays, report)) => {
+            if !plays.is_empty() {
+                history.import(plays);
+            }
+            Json(report).into_response()
+        }
+        Err(e) => {
+            let mut response = format!("unable to read the export: {e}").into_response();
+            *response.status_mut() = StatusCode::BAD_REQUEST;
+            response
+        }
+    }
+}
+
+/// Archive of the cache, the playlists and the user data of the library, without its audio
+#[utoipa::path(
+    get, path = "/export", tag = "daemon",
+    responses(
+        (status = 200, description = "Zip archive for `POST /import`", content_type = "application/zip"),
+        (status = 401, description = "Not an admin"),
+    )
+)]
+async fn export_library(
+    Scoped(state): Scoped,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    headers: HeaderMap,
+) -> Response {
+    if !is_admin(&state, addr, &headers).await {
+        return StatusCode::UNAUTHORIZED.into_response();
+    }
+    let playlists = state
+        .library
+        .media
+        .load()
+        .playlists
+        .iter()
+        .map(|x| std::path::PathBuf::from(&x.path))
+        .collect();
+    let locations = Locations {
+        library: &state.library.name,
+        cache_dir: &state.library.cache_dir,
+        app_dir: &state.dirs.app,
+        roots: &state.library.paths,
+    };
+
+    let mut response = Response::new(backup::export_body(&locations, playlists));
+    response
+        .headers_mut()
+        .insert(CONTENT_TYPE, HeaderValue::from_static("application/zip"));
+    if let Ok(disposition) = HeaderValue::from_str(&archive::attachment(&format!(
+        "lorchestre - {}",
+        state.library.name
+    ))) {
+        response
+            .headers_mut()
+            .insert(CONTENT_DISPOSITION, disposition);
+    }
+    response
+}
+
+/// Restores an archive of `GET /export` over the cache and the data of the default user, then
+/// scans the library to match the cache with its files. Only allowed to the clients allowed to
+/// stop the daemon.
+#[utoipa::path(
+    post, path = "/import", tag = "daemon",
+    request_body(content = Vec<u8>, description = "Zip archive of `GET /export`", content_type = "application/zip"),
+    responses(
+        (status = 200, body = ImportReport),
+        (status = 400, description = "Not an archive of `GET /export`"),
+        (status = 401, description = "Not allowed to import"),
+        (status = 409, description = "The library is being scanned"),
+    )
+)]
+async fn import_library(
+    State(shared): State<AppData>,
+    Scoped(scoped): Scoped,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    headers: HeaderMap,
+    body: axum::body::Bytes,
+) -> Response {
+    if !is_admin(&shared, addr, &headers).await {
+        return StatusCode::UNAUTHORIZED.into_response();
+    }
+    // The archive holds the data of the default user, even when an admin account imports it
+    let state = AppData {
+        library: scoped.library,
+        ..shared
+    };
+    if state.library.scan.status().running {
+        let mut response = "the library is being scanned, retry once it is done".into_response();
+        *response.status_mut() = StatusCode::CONFLICT;
+        return response;
+    }
+    let archive = match backup::read(body.to_vec()).await {
+        Ok(archive) => archive,
+        Err(e) => {
+            let mut response = format!("invalid archive: {e}").into_response();
+            *response.status_mut() = StatusCode::BAD_REQUEST;
+            return response;
+        }
+    };
+
+    // Held while restoring so that none of them saves over the restored files
+    let mut bookmarks = state.bookmarks.write().await;
+    let mut positions = state.positions.write().await;
+    let mut listen_later = state.listen_later.write().await;
+    let mut history = state.history.write().await;
+    let mut favorites = state.favorites.write().await;
+    let mut queue = state.queue.write().await;
+    let mut playback = state.playback.write().await;
+    let mut timers = state.timers.write().await;
+    let locations = Locations {
+        library: &state.library.name,
+        cache_dir: &state.library.cache_dir,
+        app_dir: &state.dirs.app,
+        roots: &state.library.paths,
+    };
+    let restored = backup::restore(archive, &locations);
+
+    let app = &state.dirs.app;
+    *bookmarks = Bookmarks::load(app.join("bookmarks.json"));
+    *positions = Positions::load(app.join("positions.json"));
+    *listen_later = ListenLater::load(app.join("listen_later.json"));
+    *history = History::load(app.join("history.json"));
+    *favorites = Favorites::load(app.join("favorites.json"));
+    *queue = Queue::load(app.join("queue.json"));
+    *playback = Playback::load(app.join("playback.json"));
+    timers.reload();
+    events::emit(&state.io, Event::Favorites, favorites.tracks());
+    events::emit(&state.io, Event::ListenLater, listen_later.entries());
+    events::emit(&state.io, Event::Timers, timers.list());
+    drop((
+        bookmarks,
+        positions,
+        listen_later,
+        history,
+        favorites,
+        queue,
+        playback,
+        timers,
+    ));
+
+    let (scan, _) = submit_job(&state, JobKind::Scan, None);
+    Json(ImportReport {
+        restored: restored.files,
+        playlists: restored.playlists,
+        skipped: restored.skipped,
+        scan,
+    })
+    .into_response()
+}
+
+async fn openapi_json(
+    State(state): State<AppData>,
+    headers: HeaderMap,
+) -> Json<utoipa::openapi::OpenApi> {
+    let mut doc = ApiDoc::openapi();
+    doc.info.version = config::VERSION.to_string();
+    // Where "try it out" sends the requests, the proxy when behind one
+    let tls = tls::files(&*state.config.read().await).is_some();
+    if let Some(origin) = proxy::origin(&headers, tls) {
+        doc.servers = Some(vec![utoipa::openapi::Server::new(origin)]);
+    }
+    Json(doc)
+}
+
+/// The web client resolves its files against `/app/`
+async fn web_app_redirect() -> Redirect {
+    Redirect::permanent(&proxy::url("/app/"))
+}
+
+async fn web_app_index(State(state): State<AppData>) -> Response {
+    web_app(State(state), Path(String::new())).await
+}
+
+/// A file of the web client of `network.web_ui`, see `webapp`
+async fn web_app(State(state): State<AppData>, Path(path): Path<String>) -> Response {
+    let Some(root) = webapp::root(&*state.config.read().await) else {
+        let mut response = "no web client is served".into_response();
+        *response.status_mut() = StatusCode::NOT_FOUND;
+        return response;
+    };
+    webapp::serve(&root, &path).await
+}
+
+/// Answers as long as the daemon runs
+#[utoipa::path(
+    get, path = "/healthz", tag = "daemon",
+    responses((status = 200, body = String))
+)]
+async fn healthz() -> String {
+    format!("OK lorchestrectl v{}", config::VERSION)
+}
+
+/// Answers once every library went through a scan and its cache can be read
+#[utoipa::path(
+    get, path = "/readyz", tag = "daemon",
+    responses(
+        (status = 200, body = String),
+        (status = 503, description = "Why the daemon isn't ready", body = String),
+    )
+)]
+async fn readyz(State(state): State<AppData>) -> Response {
+    for library in state.libraries.all() {
+        let reason = if !library.scan.scanned() {
+            format!("the library {} is being scanned", library.name)
+        } else if let Err(e) = File::open(library.cache_dir.join(".cache.json")).await {
+            format!(
+                "the cache of the library {} is unreachable: {e}",
+                library.name
+            )
+        } else {
+            continue;
+        };
+
+        let mut response = reason.into_response();
+        *response.status_mut() = StatusCode::SERVICE_UNAVAILABLE;
+        return response;
+    }
+
+    "ready".into_response()
+}
+
+#[utoipa::path(
+    get, path = "/jobs", tag = "jobs",
+    responses((status = 200, description = "Unfinished jobs first, then the last finished ones", body = Vec<Job>))
+)]
+async fn jobs_list(State(state): State<AppData>) -> Json<Vec<Job>> {
+    Json(state.jobs.list())
+}
+
+#[utoipa::path(
+    get, path = "/jobs/{id}", tag = "jobs",
+    params(("id" = String, Path, description = "Id of the job")),
+    responses((status = 200, body = Job), (status = 404, description = "No such job"))
+)]
+async fn job(State(state): State<AppData>, Path(id): Path<String>) -> Response {
+    if let Some(job) = state.jobs.get(&id) {
+        Json(job).into_response()
+    } else {
+        let mut response = format!("no job found with the id of {id}").into_response();
+        *response.status_mut() = StatusCode::NOT_FOUND;
+        response
+    }
+}
+
+/// Cancels a queued or running job, it's marked as cancelled once stopped
+#[utoipa::path(
+    delete, path = "/jobs/{id}", tag = "jobs",
+    params(("id" = String, Path, description = "Id of the job")),
+    responses(
+        (status = 202),
+        (status = 401, description = "Not an admin"),
+        (status = 404, description = "No such job"),
+        (status = 409, description = "The job is already finished"),
+    )
+)]
+async fn cancel_job(
+    State(state): State<AppData>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    headers: HeaderMap,
+    Path(id): Path<String>,
+) -> StatusCode {
+    if !is_admin(&state, addr, &headers).await {
+        return StatusCode::UNAUTHORIZED;
+    }
+    match state.jobs.cancel(&id) {
+        Some(true) => StatusCode::ACCEPTED,
+        Some(false) => StatusCode::CONFLICT,
+        None => StatusCode::NOT_FOUND,
+    }
+}
+
+#[utoipa::path(
+    get, path = "/scan/status", tag = "library",
+    responses((status = 200, body = ScanStatus))
+)]
+async fn scan_status(Scoped(state): Scoped) -> Json<ScanStatus> {
+    Json(state.library.scan.status())
+}
+
+/// What the last scan found against the cache: files added, removed and moved
+#[utoipa::path(
+    get, path = "/scan/last", tag = "library",
+    responses((status = 200, body = LastScan), (status = 404, description = "No scan went through yet"))
+)]
+async fn scan_last(Scoped(state): Scoped) -> Response {
+    match state.library.scan.last() {
+        Some(last) => Json(last).into_response(),
+        None => {
+            let mut response = "the library wasn't scanned yet".into_response();
+            *response.status_mut() = StatusCode::NOT_FOUND;
+            response
+        }
+    }
+}
+
+/// The whole media, in MessagePack or CBOR when asked by the `Accept` header
+#[utoipa::path(
+    get, path = "/media", tag = "library",
+    params(LiteQuery),
+    responses((
+        status = 200, description = "`LiteResults` when lite",
+        content(("application/json" = Media), ("application/msgpack" = Media), ("application/cbor" = Media))
+    ))
+)]
+async fn media(
+    Scoped(state): Scoped,
+    Query(lite): Query<LiteQuery>,
+    headers: HeaderMap,
+) -> Response {
+    let encoding = Encoding::negotiate(&headers);
+    let media = state.library.media.load();
+    // Only copied for the users hiding the explicit tracks
+    let filtered = state.hide_explicit.then(|| {
+        media.filtered(&QualityQuery {
+            hide_explicit: true,
+            ..Default::default()
+        })
+    });
+    let media = filtered.as_ref().unwrap_or(&media);
+    let mut response = if lite.lite {
+        encoding.respond(&lite::media(media))
+    } else {
+        encoding.respond(media)
+    };
+    response
+        .headers_mut()
+        .insert(changes::GENERATION_HEADER, changes::generation().into());
+    response
+}
+
+/// Every track of the library as newline-delimited JSON, written as it is read
+#[utoipa::path(
+    get, path = "/media/stream", tag = "library",
+    params(LiteQuery),
+    responses((
+        status = 200, description = "One `Track` per line, `LiteTrack` when lite",
+        content_type = "application/x-ndjson", body = String
+    ))
+)]
+async fn media_stream(Scoped(state): Scoped, Query(lite): Query<LiteQuery>) -> Response {
+    let quality = QualityQuery {
+        hide_explicit: state.hide_explicit,
+        ..Default::default()
+    };
+    let body = ndjson::tracks_body(Arc::clone(&state.library.media), lite.lite, quality).await;
+    let mut response = Response::new(body);
+    response
+        .headers_mut()
+        .insert(CONTENT_TYPE, HeaderValue::from_static(ndjson::NDJSON));
+    response
+}
+
+/// What changed in the media after a generation, given by the `X-Media-Generation` header of
+/// `GET /media` or by the socket events
+#[utoipa::path(
+    get, path = "/media/changes", tag = "library",
+    params(ChangesQuery),
+    responses((status = 200, body = MediaChanges))
+)]
+async fn media_changes(Scoped(state): Scoped, Query(query): Query<ChangesQuery>) -> Response {
+    let media = state.library.media.load();
+    let changes = state
+        .library
+        .changes
+        .lock()
+        .unwrap()
+        .since(query.since, &media);
+    Json(changes).into_response()
+}
+
+#[utoipa::path(
+    get, path = "/search", tag = "library",
+    params(SearchQuery, LiteQuery, QualityQuery),
+    responses((status = 200, description = "`LiteResults` when lite", body = SearchResults))
+)]
+async fn search(
+    Scoped(state): Scoped,
+    Query(query): Query<SearchQuery>,
+    Query(lite): Query<LiteQuery>,
+    Query(quality): Query<QualityQuery>,
+) -> Response {
+    let quality = QualityQuery {
+        hide_explicit: state.hide_explicit,
+        ..quality
+    };
+    let media = state.library.media.load();
+    let mut results = match query.scope.unwrap_or_default() {
+        SearchScope::All => media.search(&query.q),
+        SearchScope::Lyrics => media.search_lyrics(&query.q),
+    };
+    results.tracks.retain(|x| quality.keeps(x));
+    results.albums.retain(|x| quality.keeps_album(x));
+    let kept: std::collections::HashSet<&String> = results.tracks.iter().map(|x| &x.id).collect();
+    results.lyrics.retain(|x| kept.contains(&x.track));
+    if lite.lite {
+        Json(lite::results(&media, &results)).into_response()
+    } else {
+        Json(results).into_response()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::daemon::global;
+    use axum::http::header::{
+        ACCEPT_ENCODING, ACCEPT_RANGES, AUTHORIZATION, CONTENT_ENCODING, CONTENT_RANGE, RANGE,
+    };
+    use axum::Extension;
+    use tower::ServiceExt;
+
+    /// Size of the audio file of the library of [`app`]
+    const TRACK_SIZE: usize = 64 * 1024;
+
+    /// The routes over a temporary folder, holding a library of a single wav file. Returns the
+    /// folder and the id of the track.
+    async fn app() -> (Router, std::path::PathBuf, String) {
+        let (state, layer, root, id) = setup(lorconf::Config::default()).await;
+        (serve(state, layer), root, id)
+    }
+
+    /// The routes of `state`, requested from the machine itself as on the Unix socket
+    fn serve(state: AppData, layer: SocketIoLayer) -> Router {
+        routes(state, layer).layer(Extension(ConnectInfo(SocketAddr::from((
+            [127, 0, 0, 1],
+            0,
+        )))))
+    }
+
+    /// The state of `config` in a temporary folder, its first library holding a single wav
+    /// file. Returns the folder and the id of the track along with the state.
+    async fn setup(
+        config: lorconf::Config,
+    ) -> (AppData, SocketIoLayer, std::path::PathBuf, String) {
+        let root = std::env::temp_dir().join(format!("lorchestre-{}", uuid::Uuid::new_v4()));
+        let dirs = Dir {
+            config: root.join("config"),
+            app: root.join("app"),
+            cache: root.join("cache"),
+        };
+        for dir in [&dirs.config, &dirs.app, &dirs.cache] {
+            std::fs::create_dir_all(dir).unwrap();
+        }
+        let file = root.join("track.wav");
+        std::fs::write(&file, vec![0u8; TRACK_SIZE]).unwrap();
+
+        let (state, layer, _) = load_state(&dirs, config, &CancellationToken::new());
+        let path = file.to_string_lossy().to_string();
+        let track = Track {
+            id: global::track_id(&path, &file, None),
+            title: "Track".to_string(),
+            file_path: path,
+            mime: "audio/wav".to_string(),
+            ..Default::default()
+        };
+        let id = track.id.clone();
+        state.library.media.write().await.add_song(track);
+
+        (state, layer, root, id)
+    }
+
+    /// Answers `request` with `app`, giving the status and the body
+    async fn send(app: &Router, request: Request) -> (StatusCode, String) {
+        let response = app.clone().oneshot(request).await.unwrap();
+        let status = response.status();
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        (status, String::from_utf8_lossy(&body).to_string())
+    }
+
+    #[tokio::test]
+    async fn ranges_stay_uncompressed() {
+        let (app, root, id) = app().await;
+        let request = Request::get(format!("/audio/{id}"))
+            .header(ACCEPT_ENCODING, "gzip")
+            .header(RANGE, "bytes=100-1099")
+            .body(Body::empty())
+            .unwrap();
+        let response = app.oneshot(request).await.unwrap();
+
+        assert_eq!(response.status(), StatusCode::PARTIAL_CONTENT);
+        assert!(response.headers().get(CONTENT_ENCODING).is_none());
+        assert_eq!(
+            response.headers()[CONTENT_RANGE],
+            format!("bytes 100-1099/{TRACK_SIZE}")
+        );
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        assert_eq!(body.len(), 1000);
+
+        std::fs::remove_dir_all(root).unwrap();
+    }
+
+    /// Compressing would drop `Accept-Ranges`, the players could no longer seek
+    #[tokio::test]
+    async fn audio_stays_uncompressed() {
+        let (app, root, id) = app().await;
+        let request = Request::get(format!("/audio/{id}"))
+            .header(ACCEPT_ENCODING, "gzip")
+            .body(Body::empty())
+            .unwrap();
+        let response = app.oneshot(request).await.unwrap();
+
+        assert_eq!(response.status(), StatusCode::OK);
+        assert!(response.headers().get(CONTENT_ENCODING).is_none());
+        assert_eq!(response.headers()[ACCEPT_RANGES], "bytes");
+        assert_eq!(response.headers()[CONTENT_LENGTH], TRACK_SIZE.to_string());
+
+        std::fs::remove_dir_all(root).unwrap();
+    }
+
+    #[tokio::test]
+    async fn json_is_compressed() {
+        let (app, root, _) = app().await;
+        let request = Request::get("/media")
+            .header(ACCEPT_ENCODING, "gzip")
+            .body(Body::empty())
+            .unwrap();
+        let response = app.oneshot(request).await.unwrap();
+
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers()[CONTENT_ENCODING], "gzip");
+
+        std::fs::remove_dir_all(root).unwrap();
+    }
+
+    #[tokio::test]
+    async fn hidden_libraries_need_a_token() {
+        let library = |name: &str, hidden| lorconf::LibraryProfile {
+            name: name.to_string(),
+            paths: vec![],
+            hidden,
+        };
+        let config = lorconf::Config {
+            libraries: Some(vec![library("Open", false), library("Hidden", true)]),
+            ..Default::default()
+        };
+        let (state, layer, root, _) = setup(config).await;
+        let token = state
+            .users
+            .create(NewUser {
+                name: "kid".to_string(),
+                role: Role::Listener,
+                hide_explicit: false,
+                libraries: vec!["Hidden".to_string()],
+            })
+            .unwrap()
+            .token;
+        let app = serve(state, layer);
+        let media = |library: &str, token: Option<&str>| {
+            let mut request = Request::get("/media").header(libraries::LIBRARY, library);
+            if let Some(token) = token {
+                request = request.header(AUTHORIZATION, format!("Bearer {token}"));
+            }
+            request.body(Body::empty()).unwrap()
+        };
+
+        // Dropping the token of a limited account doesn't reach the libraries it can't see
+        assert_eq!(
+            send(&app, media("Hidden", None)).await.0,
+            StatusCode::NOT_FOUND
+        );
+        assert_eq!(send(&app, media("Open", None)).await.0, StatusCode::OK);
+        assert_eq!(
+            send(&app, media("Hidden", Some(&token))).await.0,
+            StatusCode::OK
+        );
+        assert_eq!(
+            send(&app, media("Open", Some(&token))).await.0,
+            StatusCode::NOT_FOUND
+        );
+        let list = Request::get("/libraries").body(Body::empty()).unwrap();
+        let (status, body) = send(&app, list).await;
+        assert_eq!(status, StatusCode::OK);
+        assert!(body.contains("\"Open\"") && !body.contains("\"Hidden\""));
+
+        std::fs::remove_dir_all(root).unwrap();
+    }
+
+    #[tokio::test]
+    async fn listeners_cant_change_the_lyrics() {
+        let (state, layer, root, id) = setup(lorconf::Config::default()).await;
+        let token = state
+            .users
+            .create(NewUser {
+                name: "guest".to_string(),
+                role: Role::Listener,
+                hide_explicit: false,
+                libraries: vec![],
+            })
+            .unwrap()
+            .token;
+        let app = serve(state, layer);
+        let request = Request::put(format!("/track/{id}/lyrics"))
+            .header(AUTHORIZATION, format!("Bearer {token}"))
+            .header(CONTENT_TYPE, "application/json")
+            .body(Body::from(r#"{"plain":"la la la"}"#))
+            .unwrap();
+
+        assert_eq!(send(&app, request).await.0, StatusCode::FORBIDDEN);
+        assert!(!root.join("track.lrc").exists());
+
+        std::fs::remove_dir_all(root).unwrap();
+    }
+
+    /// People behind the same proxy or network have their own limits
+    #[tokio::test]
+    async fn accounts_are_limited_apart() {
+        let config = lorconf::Config {
+            network: Some(lorconf::Network {
+                requests_per_minute: Some(2),
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+        let (state, layer, root, _) = setup(config).await;
+        let mut tokens = vec![];
+        for name in ["one", "two"] {
+            let created = state.users.create(NewUser {
+                name: name.to_string(),
+                role: Role::Listener,
+                hide_explicit: false,
+                libraries: vec![],
+            });
+            tokens.push(created.unwrap().token);
+        }
+        let app = serve(state, layer);
+        let status = |token: &str| {
+            let request = Request::get("/libraries")
+                .header("x-forwarded-for", "203.0.113.7")
+                .header(AUTHORIZATION, format!("Bearer {token}"))
+                .body(Body::empty())
+                .unwrap();
+            let app = app.clone();
+            async move { send(&app, request).await.0 }
+        };
+
+        assert_eq!(status(&tokens[0]).await, StatusCode::OK);
+        assert_eq!(status(&tokens[0]).await, StatusCode::OK);
+        assert_eq!(status(&tokens[0]).await, StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(status(&tokens[1]).await, StatusCode::OK);
+
+        std::fs::remove_dir_all(root).unwrap();
+    }
 }
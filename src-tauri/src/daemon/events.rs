@@ -0,0 +1,294 @@
+//! Socket.io events. Each event takes the next sequence of the daemon, which starts at the time
+//! the daemon started in milliseconds like the generations of the media, see [`changes`]. The
+//! last ones are kept for the clients to `resume` from the sequence they got last once
+//! reconnected, e.g. after a sleep, rather than fetching everything again.
+
+use crate::daemon::changes;
+use crate::daemon::libraries::{Libraries, Viewer};
+use crate::daemon::sessions;
+use crate::daemon::users::Users;
+use crate::daemon::webhooks;
+use mu_protocol::api::MediaChanges;
+use mu_protocol::events::{Event, Namespace, ResumeReply, ResumeRequest};
+use socketioxide::{
+    extract::{AckSender, Data, SocketRef, State, TryData},
+    SocketIo,
+};
+use std::collections::{HashSet, VecDeque};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::SystemTime;
+use tracing::{info, warn};
+
+/// Events kept for the sockets resuming, older ones answer with a resync
+const KEPT: usize = 1_000;
+
+static SEQUENCE: AtomicU64 = AtomicU64::new(0);
+static REPLAY: Mutex<Replay> = Mutex::new(Replay {
+    events: VecDeque::new(),
+    floor: 0,
+});
+
+/// An event as emitted, to be emitted again to a socket which missed it
+#[derive(Debug)]
+struct Logged {
+    sequence: u64,
+    event: Event,
+    /// Room the event was emitted to, every socket got it otherwise
+    room: Option<String>,
+    /// Room whose sockets were left out
+    except: Option<String>,
+    data: serde_json::Value,
+    generation: u64,
+}
+
+#[derive(Debug)]
+struct Replay {
+    events: VecDeque<Logged>,
+    /// Events up to this sequence were dropped
+    floor: u64,
+}
+
+/// Starts the sequences at the current time, called once before anything is emitted
+pub fn start() {
+    let now = SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis() as u64;
+    SEQUENCE.store(now, Ordering::SeqCst);
+    REPLAY.lock().unwrap().floor = now;
+}
+
+/// The sequence of the last event emitted
+pub fn sequence() -> u64 {
+    SEQUENCE.load(Ordering::SeqCst)
+}
+
+/// Keeps `event` for the sockets resuming, returns its sequence. Events which aren't replayed
+/// don't take one and carry the sequence of the last event.
+fn log<T: serde::Serialize>(
+    event: Event,
+    room: Option<&str>,
+    except: Option<&str>,
+    data: &T,
+) -> u64 {
+    if !event.replayed() {
+        return sequence();
+    }
+    let data = match serde_json::to_value(data) {
+        Ok(data) => data,
+        Err(e) => {
+            warn!("Unable to keep the event {}: {e}", event.name());
+            return sequence();
+        }
+    };
+
+    let mut replay = REPLAY.lock().unwrap();
+    let sequence = SEQUENCE.fetch_add(1, Ordering::SeqCst) + 1;
+    replay.events.push_back(Logged {
+        sequence,
+        event,
+        room: room.map(String::from),
+        except: except.map(String::from),
+        data,
+        generation: changes::generation(),
+    });
+    while replay.events.len() > KEPT {
+        if let Some(logged) = replay.events.pop_front() {
+            replay.floor = logged.sequence;
+        }
+    }
+    sequence
+}
+
+/// Replays to `socket` the events it missed after `request.sequence`: the ones of its
+/// namespace and of the rooms it is in
+fn resume(socket: &SocketRef, request: ResumeRequest) -> ResumeReply {
+    let replay = REPLAY.lock().unwrap();
+    let current = sequence();
+    let Some(since) = request.sequence else {
+        return ResumeReply {
+            sequence: current,
+            replayed: 0,
+            resync: false,
+        };
+    };
+    // A sequence of a former run is older than the floor
+    if since < replay.floor || since > current {
+        return ResumeReply {
+            sequence: current,
+            replayed: 0,
+            resync: true,
+        };
+    }
+
+    let rooms: HashSet<String> = socket
+        .rooms()
+        .unwrap_or_default()
+        .into_iter()
+        .map(|x| x.to_string())
+        .collect();
+    let root = socket.ns() == "/";
+    let mut replayed = 0;
+    for logged in replay.events.iter().filter(|x| x.sequence > since) {
+        let namespace = root
+            || logged
+                .event
+                .namespaces()
+                .iter()
+                .any(|x| x.path() == socket.ns());
+        let room = logged.room.as_ref().is_none_or(|x| rooms.contains(x))
+            && logged.except.as_ref().is_none_or(|x| !rooms.contains(x));
+        if namespace && room {
+            let arguments = (&logged.data, logged.generation, logged.sequence);
+            let _ = socket.emit(logged.event.name(), arguments);
+            replayed += 1;
+        }
+    }
+    ResumeReply {
+        sequence: current,
+        replayed,
+        resync: false,
+    }
+}
+
+/// Handles the `resume` message of a reconnected socket, see [`ResumeRequest`]
+pub fn listen(socket: &SocketRef) {
+    socket.on(
+        "resume",
+        |socket: SocketRef, Data::<ResumeRequest>(request), ack: AckSender| {
+            let reply = resume(&socket, request);
+            if reply.resync {
+                info!("socket {} must resync, its events are gone", socket.id);
+            }
+            let _ = ack.send(reply);
+        },
+    );
+}
+
+/// Declares the scoped namespaces, clients only listen on them: nothing to handle but the
+/// token of their account and the sessions they join
+pub fn register_namespaces(io: &SocketIo) {
+    for namespace in Namespace::ALL {
+        io.ns(
+            namespace.path(),
+            move |socket: SocketRef,
+                  TryData(auth): TryData<serde_json::Value>,
+                  users: State<Arc<Users>>,
+                  libraries: State<Arc<Libraries>>| {
+                info!("socket connected to {}: {}", namespace.path(), socket.id);
+                let user = users.join_room(&socket, &auth.unwrap_or_default());
+                libraries.hide(&socket, &Viewer::socket(user.as_ref()));
+                sessions::listen(&socket);
+                listen(&socket);
+            },
+        );
+    }
+}
+
+/// Disconnects the sockets of every namespace
+pub fn disconnect_all(io: &SocketIo) {
+    let _ = io.disconnect();
+    for namespace in Namespace::ALL {
+        if let Some(operators) = io.of(namespace.path()) {
+            let _ = operators.disconnect();
+        }
+    }
+}
+
+/// Emits `event` to the sockets in `room`, e.g. the ones of an account, see
+/// [`Users::join_room`], on the root namespace and on the namespaces the event belongs to, and
+/// to the webhooks
+pub fn emit_to<T: serde::Serialize>(io: &SocketIo, room: String, event: Event, data: T) {
+    let sequence = log(event, Some(&room), None, &data);
+    webhooks::post(event, Some(&room), &data, changes::generation(), sequence);
+    let arguments = (&data, changes::generation(), sequence);
+    let _ = io.to(room.clone()).emit(event.name(), arguments);
+    for namespace in event.namespaces() {
+        if let Some(operators) = io.of(namespace.path()) {
+            let _ = operators.to(room.clone()).emit(event.name(), arguments);
+        }
+    }
+}
+
+/// Emits `event` on the root namespace and on the namespaces it belongs to, with the
+/// generation of the media as second argument and the sequence of the event as third, and to
+/// the webhooks
+pub fn emit<T: serde::Serialize>(io: &SocketIo, event: Event, data: T) {
+    let sequence = log(event, None, None, &data);
+    webhooks::post(event, None, &data, changes::generation(), sequence);
+    // A tuple is sent as arguments, `data` stays whole even if it is a list
+    let arguments = (&data, changes::generation(), sequence);
+    let _ = io.emit(event.name(), arguments);
+    for namespace in event.namespaces() {
+        if let Some(operators) = io.of(namespace.path()) {
+            let _ = operators.emit(event.name(), arguments);
+        }
+    }
+}
+
+/// Emits `event` like [`emit`], leaving out the sockets in `room`
+fn emit_except<T: serde::Serialize>(io: &SocketIo, room: &str, event: Event, data: T) {
+    let sequence = log(event, None, Some(room), &data);
+    webhooks::post(event, None, &data, changes::generation(), sequence);
+    let arguments = (&data, changes::generation(), sequence);
+    let _ = io.except(room.to_string()).emit(event.name(), arguments);
+    for namespace in event.namespaces() {
+        if let Some(operators) = io.of(namespace.path()) {
+            let _ = operators
+                .except(room.to_string())
+                .emit(event.name(), arguments);
+        }
+    }
+}
+
+/// The items of `items` whose `id` is one of `ids`
+fn select<'a, T>(items: &'a [T], ids: &[String], id: impl Fn(&T) -> &String) -> Vec<&'a T> {
+    let ids: HashSet<&String> = ids.iter().collect();
+    items.iter().filter(|x| ids.contains(id(x))).collect()
+}
+
+/// Emits `changes` of the media as `track:*`, `album:*` and `playlist:*` events, the added and
+/// updated items whole and the removed ones by id. Events without item aren't sent, and tracks
+/// are added before the albums listing them and removed after. The sockets in `hidden`, not
+/// seeing the library, are left out.
+pub fn emit_changes(io: &SocketIo, hidden: &str, changes: &MediaChanges) {
+    let (added, updated, removed) = (&changes.added, &changes.updated, &changes.removed);
+    let tracks = |ids| select(&changes.tracks, ids, |x| &x.id);
+    let albums = |ids| select(&changes.albums, ids, |x| &x.id);
+    let playlists = |ids| select(&changes.playlists, ids, |x| &x.id);
+
+    for (event, tracks) in [
+        (Event::TrackAdded, tracks(&added.tracks)),
+        (Event::TrackUpdated, tracks(&updated.tracks)),
+    ] {
+        if !tracks.is_empty() {
+            emit_except(io, hidden, event, tracks);
+        }
+    }
+    for (event, albums) in [
+        (Event::AlbumAdded, albums(&added.albums)),
+        (Event::AlbumUpdated, albums(&updated.albums)),
+    ] {
+        if !albums.is_empty() {
+            emit_except(io, hidden, event, albums);
+        }
+    }
+    for (event, playlists) in [
+        (Event::PlaylistAdded, playlists(&added.playlists)),
+        (Event::PlaylistUpdated, playlists(&updated.playlists)),
+    ] {
+        if !playlists.is_empty() {
+            emit_except(io, hidden, event, playlists);
+        }
+    }
+    for (event, ids) in [
+        (Event::AlbumRemoved, &removed.albums),
+        (Event::TrackRemoved, &removed.tracks),
+        (Event::PlaylistRemoved, &removed.playlists),
+    ] {
+        if !ids.is_empty() {
+            emit_except(io, hidden, event, ids);
+        }
+    }
+}
@@ -0,0 +1,84 @@
+use crate::daemon::store;
+use std::collections::HashMap;
+use std::path::PathBuf;
+
+/// Ids of the tracks marked as favorite
+#[derive(Debug, Default)]
+pub struct Favorites {
+    path: PathBuf,
+    tracks: Vec<String>,
+}
+
+impl Favorites {
+    pub fn load(path: PathBuf) -> Self {
+        let tracks = store::load(&path, "favorites");
+
+        Self { path, tracks }
+    }
+
+    fn save(&self) {
+        store::save(&self.path, "favorites", &self.tracks);
+    }
+
+    pub fn tracks(&self) -> Vec<String> {
+        self.tracks.clone()
+    }
+
+    pub fn add(&mut self, track_id: &str) -> bool {
+        if self.tracks.iter().any(|x| x == track_id) {
+            return false;
+        }
+        self.tracks.push(track_id.to_string());
+        self.save();
+
+        true
+    }
+
+    /// Adds the tracks not in the list yet, returns how many were added
+    pub fn extend(&mut self, track_ids: Vec<String>) -> usize {
+        let count = self.tracks.len();
+        for id in track_ids {
+            if !self.tracks.contains(&id) {
+                self.tracks.push(id);
+            }
+        }
+        self.save();
+
+        self.tracks.len() - count
+    }
+
+    /// Replaces the track ids found in `ids`, returns whether the list changed
+    pub fn remap_tracks(&mut self, ids: &HashMap<String, String>) -> bool {
+        let mut changed = false;
+        let mut tracks: Vec<String> = vec![];
+        for track in &self.tracks {
+            let id = match ids.get(track) {
+                Some(id) => {
+                    changed = true;
+                    id
+                }
+                None => track,
+            };
+            if !tracks.contains(id) {
+                tracks.push(id.clone());
+            }
+        }
+        if changed {
+            self.tracks = tracks;
+            self.save();
+        }
+
+        changed
+    }
+
+    pub fn remove(&mut self, track_id: &str) -> bool {
+        let len = self.tracks.len();
+        self.tracks.retain(|x| x != track_id);
+        let removed = self.tracks.len() != len;
+        if removed {
+            self.save();
+        }
+
+        removed
+    }
+}
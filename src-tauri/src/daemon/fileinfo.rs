@@ -0,0 +1,138 @@
+//! Technical details of an audio file for `GET /track/{id}/info`: its codec, format, tags and
+//! embedded pictures, which the scan reads but doesn't keep.
+
+use image::io::Reader as ImageReader;
+use lofty::config::ParseOptions;
+use lofty::file::{AudioFile, FileType};
+use lofty::iff::wav::{WavFile, WavFormat};
+use lofty::mp4::{Mp4Codec, Mp4File};
+use lofty::prelude::*;
+use lofty::probe::Probe;
+use mu_protocol::api::{EmbeddedPicture, TrackInfo};
+use mu_protocol::library::Track;
+use std::io::Cursor;
+use std::path::Path;
+
+fn container(file_type: FileType) -> &'static str {
+    match file_type {
+        FileType::Aac => "ADTS",
+        FileType::Aiff => "AIFF",
+        FileType::Ape => "APE",
+        FileType::Flac => "FLAC",
+        FileType::Mpeg => "MPEG",
+        FileType::Mp4 => "MP4",
+        FileType::Mpc => "Musepack",
+        FileType::Opus | FileType::Vorbis | FileType::Speex => "Ogg",
+        FileType::Wav => "WAV",
+        FileType::WavPack => "WavPack",
+        _ => "unknown",
+    }
+}
+
+/// The codec of the audio of `path`, which MP4 and WAV files need to be read again for
+fn codec(path: &Path, file_type: FileType) -> String {
+    let options = ParseOptions::new();
+    let codec = match file_type {
+        FileType::Aac => "AAC",
+        FileType::Aiff => "PCM",
+        FileType::Ape => "Monkey's Audio",
+        FileType::Flac => "FLAC",
+        FileType::Mpeg => "MP3",
+        FileType::Mp4 => {
+            let codec = std::fs::File::open(path)
+                .ok()
+                .and_then(|mut f| Mp4File::read_from(&mut f, options).ok())
+                .map(|x| *x.properties().codec());
+            match codec {
+                Some(Mp4Codec::AAC) => "AAC",
+                Some(Mp4Codec::ALAC) => "ALAC",
+                Some(Mp4Codec::MP3) => "MP3",
+                Some(Mp4Codec::FLAC) => "FLAC",
+                _ => "unknown",
+            }
+        }
+        FileType::Mpc => "Musepack",
+        FileType::Opus => "Opus",
+        FileType::Vorbis => "Vorbis",
+        FileType::Speex => "Speex",
+        FileType::Wav => {
+            let format = std::fs::File::open(path)
+                .ok()
+                .and_then(|mut f| WavFile::read_from(&mut f, options).ok())
+                .map(|x| *x.properties().format());
+            match format {
+                Some(WavFormat::IEEE_FLOAT) => "IEEE float",
+                Some(WavFormat::Other(tag)) => return format!("format {tag:#06x}"),
+                _ => "PCM",
+            }
+        }
+        FileType::WavPack => "WavPack",
+        _ => "unknown",
+    };
+
+    codec.to_string()
+}
+
+/// Whether the audio of `path` is encoded without loss, which MP4 and WAV files need to be read
+/// again for
+pub fn is_lossless(path: &Path, file_type: FileType) -> bool {
+    match file_type {
+        FileType::Aiff | FileType::Ape | FileType::Flac | FileType::WavPack => true,
+        FileType::Mp4 | FileType::Wav => {
+            matches!(
+                &*codec(path, file_type),
+                "ALAC" | "FLAC" | "PCM" | "IEEE float"
+            )
+        }
+        _ => false,
+    }
+}
+
+/// Reads the details of the audio file of `track`, also reached through the paths `aliases`
+pub fn read(track: &Track, aliases: Vec<String>) -> lofty::error::Result<TrackInfo> {
+    let path = Path::new(track.audio_file());
+    let tagged_file = Probe::open(path)?.read()?;
+    let properties = tagged_file.properties();
+    let file_type = tagged_file.file_type();
+
+    // The primary tag first, as the scan prefers it
+    let primary = tagged_file.primary_tag_type();
+    let mut tags: Vec<_> = tagged_file.tags().iter().collect();
+    tags.sort_by_key(|x| x.tag_type() != primary);
+
+    let mut pictures = vec![];
+    for tag in &tags {
+        for picture in tag.pictures() {
+            let dimensions = ImageReader::new(Cursor::new(picture.data()))
+                .with_guessed_format()
+                .ok()
+                .and_then(|x| x.into_dimensions().ok());
+            pictures.push(EmbeddedPicture {
+                tag: format!("{:?}", tag.tag_type()),
+                kind: format!("{:?}", picture.pic_type()),
+                mime: picture.mime_type().map(|x| x.as_str().to_string()),
+                description: picture.description().map(|x| x.to_string()),
+                size: picture.data().len(),
+                width: dimensions.map(|(width, _)| width),
+                height: dimensions.map(|(_, height)| height),
+            });
+        }
+    }
+
+    Ok(TrackInfo {
+        id: track.id.clone(),
+        file: path.to_string_lossy().to_string(),
+        container: container(file_type).to_string(),
+        codec: codec(path, file_type),
+        size: std::fs::metadata(path)?.len(),
+        duration: properties.duration().as_millis() as u64,
+        sample_rate: properties.sample_rate(),
+        bit_depth: properties.bit_depth(),
+        channels: properties.channels(),
+        audio_bitrate: properties.audio_bitrate(),
+        overall_bitrate: properties.overall_bitrate(),
+        tags: tags.iter().map(|x| format!("{:?}", x.tag_type())).collect(),
+        pictures,
+        aliases,
+    })
+}
@@ -0,0 +1,161 @@
+//! Titles and track numbers of the tracks without title tag, read from their file names with
+//! the patterns of `library.title_patterns`, e.g. `{track} - {title}` for `03 - Intro.flac`.
+//! The first pattern the name matches is used. Underscores of the titles are read as spaces.
+
+/// Piece of a pattern: text as written, the track number or the title
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Part {
+    Text(String),
+    Track,
+    Title,
+}
+
+/// Parses `pattern`, failing with the reason it is invalid
+pub fn parse(pattern: &str) -> Result<Vec<Part>, String> {
+    let mut parts = vec![];
+    let mut rest = pattern;
+    while let Some(start) = rest.find('{') {
+        if start > 0 {
+            parts.push(Part::Text(rest[..start].to_string()));
+        }
+        let Some(end) = rest[start..].find('}') else {
+            return Err(format!("unclosed `{{` in `{pattern}`"));
+        };
+        parts.push(match &rest[start + 1..start + end] {
+            "track" => Part::Track,
+            "title" => Part::Title,
+            field => {
+                return Err(format!(
+                    "unknown field `{{{field}}}`, expected track or title"
+                ))
+            }
+        });
+        rest = &rest[start + end + 1..];
+    }
+    if !rest.is_empty() {
+        parts.push(Part::Text(rest.to_string()));
+    }
+
+    if !parts.contains(&Part::Title) {
+        return Err(format!("`{pattern}` has no {{title}}"));
+    }
+    Ok(parts)
+}
+
+/// The track number and the title of `name` if it matches `parts`
+fn matches<'a>(parts: &[Part], name: &'a str) -> Option<(Option<u32>, Option<&'a str>)> {
+    let Some((part, parts)) = parts.split_first() else {
+        return name.is_empty().then_some((None, None));
+    };
+    match part {
+        Part::Text(text) => matches(parts, name.strip_prefix(text.as_str())?),
+        Part::Track => {
+            let digits = name.len() - name.trim_start_matches(|x: char| x.is_ascii_digit()).len();
+            // The longest number first, `{track}{title}` reads `12Intro` as track 12
+            (1..=digits.min(3)).rev().find_map(|len| {
+                let (_, title) = matches(parts, &name[len..])?;
+                Some((name[..len].parse().ok(), title))
+            })
+        }
+        Part::Title => (1..=name.len())
+            .rev()
+            .filter(|x| name.is_char_boundary(*x))
+            .find_map(|end| {
+                let (track, _) = matches(parts, &name[end..])?;
+                Some((track, Some(&name[..end])))
+            }),
+    }
+}
+
+/// The track number and the title read from `name`, a file name without its extension, with
+/// the first of `patterns` it matches
+pub fn guess(patterns: &[Vec<Part>], name: &str) -> Option<(Option<u32>, String)> {
+    patterns.iter().find_map(|parts| {
+        let (track, title) = matches(parts, name)?;
+        let title = title?.replace('_', " ").trim().to_string();
+        (!title.is_empty()).then_some((track.filter(|x| *x > 0), title))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn patterns(patterns: &[&str]) -> Vec<Vec<Part>> {
+        patterns.iter().map(|x| parse(x).unwrap()).collect()
+    }
+
+    fn defaults() -> Vec<Vec<Part>> {
+        let defaults = lorconf::Library::default().title_patterns.unwrap();
+        defaults.iter().map(|x| parse(x).unwrap()).collect()
+    }
+
+    fn guessed(track: Option<u32>, title: &str) -> Option<(Option<u32>, String)> {
+        Some((track, title.to_string()))
+    }
+
+    #[test]
+    fn invalid_patterns() {
+        assert!(parse("{track} - {title").is_err());
+        assert!(parse("{artist} - {title}").is_err());
+        assert!(parse("{track}").is_err());
+        assert_eq!(
+            parse("{track}. {title}").unwrap(),
+            vec![Part::Track, Part::Text(". ".to_string()), Part::Title]
+        );
+    }
+
+    #[test]
+    fn default_patterns() {
+        let defaults = defaults();
+        assert_eq!(guess(&defaults, "03 - Intro"), guessed(Some(3), "Intro"));
+        assert_eq!(
+            guess(&defaults, "12. The End"),
+            guessed(Some(12), "The End")
+        );
+        assert_eq!(
+            guess(&defaults, "7 Nation Army"),
+            guessed(Some(7), "Nation Army")
+        );
+        assert_eq!(
+            guess(&defaults, "Just_a_title"),
+            guessed(None, "Just a title")
+        );
+        // Track 0 is no track number
+        assert_eq!(guess(&defaults, "00 - Hidden"), guessed(None, "Hidden"));
+    }
+
+    #[test]
+    fn first_matching_pattern() {
+        let patterns = patterns(&["{track} - {title} (live)", "{title}"]);
+        assert_eq!(
+            guess(&patterns, "04 - Song (live)"),
+            guessed(Some(4), "Song")
+        );
+        assert_eq!(
+            guess(&patterns, "04 - Song (demo)"),
+            guessed(None, "04 - Song (demo)")
+        );
+    }
+
+    #[test]
+    fn names_matching_no_pattern() {
+        let patterns = patterns(&["{track} - {title}", "Disc {track}: {title}"]);
+        assert_eq!(guess(&patterns, "Intro"), None);
+        assert_eq!(guess(&patterns, "AB - Intro"), None);
+        assert_eq!(guess(&patterns, "Disc one: Intro"), None);
+        // An empty title, or one of underscores only, is no title
+        assert_eq!(guess(&patterns, "03 - "), None);
+        assert_eq!(guess(&patterns, "03 - __"), None);
+        assert_eq!(guess(&[], "03 - Intro"), None);
+    }
+
+    #[test]
+    fn numbers_without_separator() {
+        let patterns = patterns(&["{track}{title}"]);
+        // The longest number first, up to three digits
+        assert_eq!(guess(&patterns, "12Intro"), guessed(Some(12), "Intro"));
+        assert_eq!(guess(&patterns, "1234Intro"), guessed(Some(123), "4Intro"));
+        assert_eq!(guess(&patterns, "Été"), None);
+    }
+}
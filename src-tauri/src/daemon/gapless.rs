@@ -0,0 +1,127 @@
+//! Gapless playback information: the samples added by the encoder at both ends of the audio,
+//! read from the LAME header of MP3s or from the `iTunSMPB` tag written by iTunes.
+
+use crate::daemon::seek::{mpeg_frame, skip_id3v2};
+use lofty::config::ParseOptions;
+use lofty::file::{AudioFile, FileType};
+use lofty::id3::v2::Frame;
+use lofty::mp4::{AtomData, AtomIdent, Mp4File};
+use lofty::mpeg::MpegFile;
+use mu_protocol::library::Gapless;
+use std::borrow::Cow;
+use std::fs::File;
+use std::io::{Read, Seek, SeekFrom};
+use std::path::Path;
+
+/// Bytes read after the id3v2 tag to find the first frame, holding the LAME header
+const HEADER_WINDOW: u64 = 4096;
+
+/// `iTunSMPB` is ` 00000000 <delay> <padding> <sample count> ...` in hexadecimal
+fn parse_itunsmpb(value: &str, sample_rate: u32) -> Option<Gapless> {
+    let mut fields = value
+        .split_whitespace()
+        .skip(1)
+        .map(|x| u64::from_str_radix(x, 16).ok());
+    let encoder_delay = fields.next()?? as u32;
+    let padding = fields.next()?? as u32;
+    let sample_count = fields.next()?.filter(|x| *x > 0);
+
+    Some(Gapless {
+        encoder_delay,
+        padding,
+        sample_count,
+        sample_rate,
+    })
+}
+
+/// The Xing/Info frame written at the start of the file by LAME, or by ffmpeg as `Lavc`
+fn lame_header(path: &Path) -> Option<Gapless> {
+    let mut file = File::open(path).ok()?;
+    let start = skip_id3v2(&mut file).ok()?;
+    file.seek(SeekFrom::Start(start)).ok()?;
+    let mut buf = vec![];
+    file.take(HEADER_WINDOW).read_to_end(&mut buf).ok()?;
+
+    let (offset, (_, frame_samples, sample_rate)) = buf
+        .windows(4)
+        .enumerate()
+        .find_map(|(i, x)| Some((i, mpeg_frame(x.try_into().ok()?)?)))?;
+    let header = &buf[offset..];
+    let mpeg1 = (header[1] >> 3) & 0x3 == 3;
+    let mono = header[3] >> 6 == 3;
+    let side_info = match (mpeg1, mono) {
+        (true, true) => 17,
+        (true, false) => 32,
+        (false, true) => 9,
+        (false, false) => 17,
+    };
+
+    let xing = header.get(4 + side_info..)?;
+    if !xing.starts_with(b"Xing") && !xing.starts_with(b"Info") {
+        return None;
+    }
+    let field = |at: usize| Some(u32::from_be_bytes(xing.get(at..at + 4)?.try_into().ok()?));
+    let flags = field(4)?;
+    let frames = if flags & 0x1 != 0 { field(8) } else { None };
+    // Frame count, byte count, table of contents and quality, when present
+    let lame = 8 + [(0x1, 4), (0x2, 4), (0x4, 100), (0x8, 4)]
+        .iter()
+        .filter(|(flag, _)| flags & flag != 0)
+        .map(|(_, size)| size)
+        .sum::<usize>();
+    let lame = xing.get(lame..lame + 24)?;
+    if !lame.starts_with(b"LAME") && !lame.starts_with(b"Lavc") && !lame.starts_with(b"Lavf") {
+        return None;
+    }
+
+    let encoder_delay = ((lame[21] as u32) << 4) | (lame[22] as u32 >> 4);
+    let padding = (((lame[22] & 0x0f) as u32) << 8) | lame[23] as u32;
+    Some(Gapless {
+        encoder_delay,
+        padding,
+        sample_count: frames.map(|frames| {
+            (frames as u64 * frame_samples).saturating_sub((encoder_delay + padding) as u64)
+        }),
+        sample_rate: sample_rate as u32,
+    })
+}
+
+fn itunsmpb(path: &Path, file_type: FileType, sample_rate: u32) -> Option<Gapless> {
+    let mut f = File::open(path).ok()?;
+    let options = ParseOptions::new().read_properties(false);
+    match file_type {
+        FileType::Mpeg => {
+            let file = MpegFile::read_from(&mut f, options).ok()?;
+            let value = file.id3v2()?.into_iter().find_map(|frame| match frame {
+                Frame::Comment(comment) if comment.description == "iTunSMPB" => {
+                    Some(comment.content.clone())
+                }
+                _ => None,
+            })?;
+            parse_itunsmpb(&value, sample_rate)
+        }
+        FileType::Mp4 => {
+            let file = Mp4File::read_from(&mut f, options).ok()?;
+            let atom = file.ilst()?.get(&AtomIdent::Freeform {
+                mean: Cow::Borrowed("com.apple.iTunes"),
+                name: Cow::Borrowed("iTunSMPB"),
+            })?;
+            let value = match atom.data().next()? {
+                AtomData::UTF8(value) => value.clone(),
+                _ => return None,
+            };
+            parse_itunsmpb(&value, sample_rate)
+        }
+        _ => None,
+    }
+}
+
+/// Gapless information of the file at `path`, `None` for formats without encoder delay or
+/// files not tagged with it
+pub fn read(path: &Path, file_type: FileType, sample_rate: u32) -> Option<Gapless> {
+    match file_type {
+        FileType::Mpeg => lame_header(path).or_else(|| itunsmpb(path, file_type, sample_rate)),
+        FileType::Mp4 => itunsmpb(path, file_type, sample_rate),
+        _ => None,
+    }
+}
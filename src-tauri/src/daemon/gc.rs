@@ -0,0 +1,247 @@
+//! Collection of the covers cache: the covers and pictures of albums retagged or removed and
+//! the portraits of artists gone from the library are deleted, as are the covers cut short by a
+//! crash while they were written. The audit extracts those again.
+
+use crate::daemon::artists;
+use crate::daemon::artwork::{ARTISTS_DIR, MISSING};
+use crate::daemon::config::SharedConfig;
+use crate::daemon::global::Media;
+use crate::daemon::jobs::Jobs;
+use crate::daemon::libraries::Library;
+use crate::daemon::utils;
+use mu_protocol::api::{CacheGcReport, CacheStats, JobKind};
+use socketioxide::SocketIo;
+use std::collections::HashSet;
+use std::path::Path;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+use tracing::{info, warn};
+
+/// How often a disabled collection looks at the configuration again
+const DISABLED_RECHECK: Duration = Duration::from_secs(60);
+
+/// `library.cover_gc_interval` in seconds, `None` when the collection is disabled
+fn interval(config: &lorconf::Config) -> Option<Duration> {
+    let seconds = config
+        .library
+        .as_ref()
+        .and_then(|library| library.cover_gc_interval)
+        .or(lorconf::Library::default().cover_gc_interval)
+        .unwrap_or_default();
+    (seconds > 0).then(|| Duration::from_secs(seconds))
+}
+
+/// Whether the image `data` stops before its end marker. Formats without one are decoded.
+fn is_truncated(data: &[u8]) -> bool {
+    match image::guess_format(data) {
+        // Some encoders pad the file after the end of image
+        Ok(image::ImageFormat::Jpeg) => {
+            let end = data.iter().rposition(|x| *x != 0).map_or(0, |x| x + 1);
+            !data[..end].ends_with(&[0xFF, 0xD9])
+        }
+        Ok(image::ImageFormat::Png) => !data
+            .len()
+            .checked_sub(12)
+            .is_some_and(|x| data[x..].starts_with(&[0, 0, 0, 0, b'I', b'E', b'N', b'D'])),
+        // The RIFF header holds the size of the rest of the file
+        Ok(image::ImageFormat::WebP) => data
+            .get(4..8)
+            .map(|x| u32::from_le_bytes([x[0], x[1], x[2], x[3]]) as usize + 8)
+            .is_none_or(|size| data.len() < size),
+        _ => image::load_from_memory(data).is_err(),
+    }
+}
+
+/// Names of the files of the covers folder used by `media`: the covers of its albums and
+/// the misses of `artwork`
+fn used_covers(media: &Media) -> HashSet<String> {
+    let mut used = HashSet::new();
+    for track in media.tracks.values() {
+        used.insert(format!("{}{}", track.album_id, track.cover_ext));
+    }
+    for album in &media.albums {
+        if let Some(name) = album.cover_url.as_ref().and_then(|x| x.rsplit('/').next()) {
+            used.insert(name.to_string());
+        }
+        used.insert(format!("{}{MISSING}", album.id));
+    }
+    used
+}
+
+/// Files of `dir` with their size
+fn files(dir: &Path) -> Vec<(std::path::PathBuf, u64)> {
+    let Ok(entries) = std::fs::read_dir(dir) else {
+        return vec![];
+    };
+    entries
+        .flatten()
+        .filter_map(|x| Some((x.path(), x.metadata().ok().filter(|x| x.is_file())?.len())))
+        .collect()
+}
+
+/// Removes what `used` and `artists` don't name from `covers_dir`, the pictures of the albums
+/// not in `albums`, and the used covers that are truncated, blocking
+fn collect(
+    covers_dir: &Path,
+    used: &HashSet<String>,
+    albums: &HashSet<String>,
+    artists: &HashSet<String>,
+) -> CacheGcReport {
+    let mut report = CacheGcReport {
+        finished_at: SystemTime::now(),
+        removed: 0,
+        truncated: vec![],
+        reclaimed: 0,
+    };
+    let mut remove = |path: &Path, size: u64| match std::fs::remove_file(path) {
+        Ok(()) => {
+            report.reclaimed += size;
+            true
+        }
+        Err(e) => {
+            warn!("gc: unable to remove `{}`: {e}", path.display());
+            false
+        }
+    };
+
+    let mut removed = 0;
+    let mut truncated = vec![];
+    for (path, size) in files(covers_dir) {
+        let name = path.file_name().unwrap_or_default().to_string_lossy();
+        if !used.contains(name.as_ref()) {
+            removed += remove(&path, size) as usize;
+        } else if !name.ends_with(MISSING)
+            && std::fs::read(&path).is_ok_and(|x| is_truncated(&x))
+            && remove(&path, size)
+        {
+            truncated.push(name.to_string());
+        }
+    }
+
+    // Pictures of the albums are in a folder named after their id, see `pictures`
+    let folders = std::fs::read_dir(covers_dir)
+        .into_iter()
+        .flatten()
+        .flatten()
+        .map(|x| x.path())
+        .filter(|x| x.is_dir());
+    for folder in folders {
+        let id = folder.file_name().unwrap_or_default().to_string_lossy();
+        if id == ARTISTS_DIR || albums.contains(id.as_ref()) {
+            continue;
+        }
+        for (path, size) in files(&folder) {
+            removed += remove(&path, size) as usize;
+        }
+        if let Err(e) = std::fs::remove_dir(&folder) {
+            warn!("gc: unable to remove `{}`: {e}", folder.display());
+        }
+    }
+
+    // Portraits and misses are named after the id of the artist
+    for (path, size) in files(&covers_dir.join(ARTISTS_DIR)) {
+        let id = path.file_stem().unwrap_or_default().to_string_lossy();
+        if !artists.contains(id.as_ref()) {
+            removed += remove(&path, size) as usize;
+        } else if path.extension().is_some_and(|x| x != &MISSING[1..])
+            && std::fs::read(&path).is_ok_and(|x| is_truncated(&x))
+            && remove(&path, size)
+        {
+            let name = path.file_name().unwrap_or_default().to_string_lossy();
+            truncated.push(format!("{ARTISTS_DIR}/{name}"));
+        }
+    }
+
+    report.removed = removed;
+    report.truncated = truncated;
+    report
+}
+
+/// Collects the covers cache of `library`, as a job. The report is kept in the library, and
+/// the albums whose cover was truncated lose it until the audit extracts it again.
+pub async fn run(library: &Library, io: &SocketIo) -> Result<(), String> {
+    // Covers are extracted before their tracks join the media
+    if library.scan.status().running {
+        return Err("the library is being scanned".to_string());
+    }
+
+    let covers_dir = library.cache_dir.join("covers");
+    let (used, albums, artists) = {
+        let media = library.media.load();
+        let albums: HashSet<String> = media.albums.iter().map(|x| x.id.clone()).collect();
+        let artists: HashSet<String> = artists::artists(&media).into_iter().map(|x| x.id).collect();
+        (used_covers(&media), albums, artists)
+    };
+    let dir = covers_dir.clone();
+    let report = tokio::task::spawn_blocking(move || collect(&dir, &used, &albums, &artists))
+        .await
+        .map_err(|e| e.to_string())?;
+    info!(
+        "gc: {} covers removed from {}, {} truncated, {} bytes reclaimed",
+        report.removed,
+        library.name,
+        report.truncated.len(),
+        report.reclaimed
+    );
+
+    if !report.truncated.is_empty() {
+        let mut media = library.media.write().await;
+        if media.select_covers(&covers_dir) {
+            utils::save_cache(&library.cache_dir, &media);
+            library.publish(io, &media.commit());
+        }
+    }
+    *library.gc.write().await = Some(report);
+
+    Ok(())
+}
+
+/// Count and size of the covers and portraits of `library`
+pub async fn stats(library: &Library) -> CacheStats {
+    let covers_dir = library.cache_dir.join("covers");
+    let images = |dir: &Path| {
+        let sizes: Vec<u64> = files(dir)
+            .into_iter()
+            .filter(|(path, _)| path.extension().is_some_and(|x| x != &MISSING[1..]))
+            .map(|(_, size)| size)
+            .collect();
+        (sizes.len(), sizes.iter().sum())
+    };
+    let ((covers, covers_size), (portraits, portraits_size)) =
+        tokio::task::spawn_blocking(move || {
+            (images(&covers_dir), images(&covers_dir.join(ARTISTS_DIR)))
+        })
+        .await
+        .unwrap_or_default();
+
+    CacheStats {
+        covers,
+        covers_size,
+        portraits,
+        portraits_size,
+        last_gc: library.gc.read().await.clone(),
+    }
+}
+
+/// Queues a collection of the covers cache of `library` every `library.cover_gc_interval`
+/// seconds
+pub async fn schedule(library: Arc<Library>, config: SharedConfig, io: SocketIo, jobs: Arc<Jobs>) {
+    loop {
+        let Some(every) = interval(&*config.read().await) else {
+            tokio::time::sleep(DISABLED_RECHECK).await;
+            continue;
+        };
+        tokio::time::sleep(every).await;
+
+        let (library, io) = (Arc::clone(&library), io.clone());
+        let name = library.name.clone();
+        let (_, done) = jobs.submit(
+            JobKind::CacheGc,
+            &name,
+            None,
+            Box::new(move |_| Box::pin(async move { run(&library, &io).await })),
+        );
+        // One collection at a time
+        let _ = done.await;
+    }
+}
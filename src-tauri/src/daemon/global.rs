@@ -1,22 +1,111 @@
-use crate::daemon::m3u8;
+use crate::daemon::analysis::Key;
+use crate::daemon::cue;
+use crate::daemon::fileinfo;
+use crate::daemon::filename;
+use crate::daemon::gapless;
+use crate::daemon::ids::{AlbumId, PlaylistId, TrackId};
+use crate::daemon::lrc;
+use crate::daemon::pictures;
+use crate::daemon::playlist::{self, PlaylistFormat};
+use crate::daemon::proxy;
+use crate::daemon::remote;
+use crate::daemon::seek;
+use crate::daemon::walk::{Aliases, WalkOptions};
 use base64::{engine::general_purpose::URL_SAFE, Engine as _};
-use color_thief::ColorFormat;
-use lofty::picture::{MimeType, PictureType};
+use lofty::config::ParseOptions;
+use lofty::file::{AudioFile, FileType};
+use lofty::id3::v2::{Frame, FrameId, Id3v2Tag};
+use lofty::iff::aiff::AiffFile;
+use lofty::iff::wav::WavFile;
+use lofty::mpeg::MpegFile;
+use lofty::picture::PictureType;
 use lofty::prelude::*;
 use lofty::probe::Probe;
-use lrc::Lyrics;
-use m3u8::Playlist;
 use mime_guess::{self, mime};
+use mu_protocol::api::{
+    LyricsMatch, QualityQuery, SearchResults, TrackList, TrackSort, TracksQuery,
+};
+use mu_protocol::library::{Album, Credit, LyricLine, MediaKind, Playlist, QualitySummary, Track};
+use std::borrow::Cow;
+use std::cmp::{Ordering, Reverse};
 use std::collections::HashMap;
 use std::fs;
-use std::io::{Read, Write};
-use std::path::PathBuf;
-use std::time::SystemTime;
+use std::io::{Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+use tracing::warn;
+
+/// Tag items holding credits and the role they are exposed under
+const CREDIT_KEYS: [(ItemKey, &str); 9] = [
+    (ItemKey::Producer, "producer"),
+    (ItemKey::Engineer, "engineer"),
+    (ItemKey::MixEngineer, "mix_engineer"),
+    (ItemKey::MixDj, "dj_mixer"),
+    (ItemKey::Arranger, "arranger"),
+    (ItemKey::Conductor, "conductor"),
+    (ItemKey::Remixer, "remixer"),
+    (ItemKey::Performer, "performer"),
+    (ItemKey::MusicianCredits, "performer"),
+];
+
+/// The id3v2 tag of `inode`, read apart for the frames lofty drops when it converts the tag to
+/// a generic one
+fn id3v2_tag(inode: &PathBuf, file_type: FileType) -> Option<Id3v2Tag> {
+    let mut f = fs::File::open(inode).ok()?;
+    let options = ParseOptions::new().read_properties(false);
+    match file_type {
+        FileType::Mpeg => MpegFile::read_from(&mut f, options)
+            .ok()
+            .and_then(|x| x.id3v2().cloned()),
+        FileType::Wav => WavFile::read_from(&mut f, options)
+            .ok()
+            .and_then(|x| x.id3v2().cloned()),
+        FileType::Aiff => AiffFile::read_from(&mut f, options)
+            .ok()
+            .and_then(|x| x.id3v2().cloned()),
+        _ => None,
+    }
+}
+
+/// `TMCL` (musician credits) frames of `tag`
+fn musician_credits(tag: Option<&Id3v2Tag>) -> Vec<(String, String)> {
+    match tag.and_then(|x| x.get(&FrameId::Valid(Cow::Borrowed("TMCL")))) {
+        Some(Frame::KeyValue(frame)) => frame.key_value_pairs.clone(),
+        _ => vec![],
+    }
+}
+
+fn get_credits(tag: &lofty::tag::Tag, musicians: Vec<(String, String)>) -> Vec<Credit> {
+    let mut credits = vec![];
+    for (instrument, name) in musicians {
+        credits.push(Credit {
+            role: "performer".to_string(),
+            name,
+            instrument: Some(instrument),
+        });
+    }
+    for (key, role) in CREDIT_KEYS.iter() {
+        for value in tag.get_strings(key) {
+            for name in value.split(';').map(|x| x.trim()).filter(|x| !x.is_empty()) {
+                let (name, instrument) = match name
+                    .strip_suffix(')')
+                    .and_then(|x| x.rsplit_once(" ("))
+                {
+                    Some((name, instrument)) => (name.trim(), Some(instrument.trim().to_string())),
+                    None => (name, None),
+                };
+                let credit = Credit {
+                    role: role.to_string(),
+                    name: name.to_string(),
+                    instrument,
+                };
+                if !credits.contains(&credit) {
+                    credits.push(credit);
+                }
+            }
+        }
+    }
 
-#[derive(serde::Serialize, serde::Deserialize, Debug, Clone)]
-pub struct LyricLine {
-    pub start_time: i64,
-    pub text: String,
+    credits
 }
 
 #[derive(serde::Serialize, Debug)]
@@ -25,244 +114,587 @@ pub struct Cover {
     ext: String,
 }
 
-#[derive(serde::Serialize, serde::Deserialize, Debug, Clone, Copy)]
-pub struct Color {
-    r: u8,
-    g: u8,
-    b: u8,
-}
+/// Extensions the cover of an album may have been stored with
+const COVER_EXTENSIONS: [&str; 7] = [".png", ".jpeg", ".jpg", ".tiff", ".bmp", ".gif", ".webp"];
 
-impl Color {
-    pub fn is_light_color(&self) -> bool {
-        let luminance =
-            0.2126 * (self.r as f64) + 0.7152 * (self.g as f64) + 0.0722 * (self.b as f64);
-        let threshold = 180.0;
-
-        luminance > threshold
-    }
+/// Width times height of an image, read from its header only
+fn resolution(reader: image::io::Reader<impl std::io::BufRead + std::io::Seek>) -> u64 {
+    reader
+        .with_guessed_format()
+        .ok()
+        .and_then(|x| x.into_dimensions().ok())
+        .map_or(0, |(w, h)| w as u64 * h as u64)
 }
 
-#[derive(serde::Serialize, serde::Deserialize, Default, Debug, Clone)]
-pub struct Album {
-    pub name: String,
-    pub artist: String,
-    pub tracks: Vec<PathBuf>,
-    pub year: Option<u32>,
-    pub id: String,
-}
-
-impl Album {
-    pub fn remove_track(&mut self, path: PathBuf) {
-        self.tracks.retain(|x| *x != path);
-    }
-}
-
-#[derive(serde::Serialize, serde::Deserialize, Debug, Clone)]
-pub struct Track {
-    pub title: String,
-    pub artists: Vec<String>,
-    pub track: u32,
-    pub album: String,
-    pub album_artist: Option<String>,
-    pub album_id: String,
-    pub cover_ext: String,
-    pub mime: String,
-    pub album_year: Option<u32>,
-    pub lyrics: Vec<LyricLine>,
-    pub color: Option<Color>,
-    pub is_light: Option<bool>,
-    pub file_path: String,
-    pub path_base64: String,
-    pub duration: u64,
-    pub bitrate: u32,
-    pub created_at: SystemTime,
-}
-
-impl Track {
-    pub fn from_file(covers_dir: &PathBuf, inode: PathBuf) -> Self {
-        let tagged_file = Probe::open(&inode).unwrap().read().unwrap();
-        let properties = tagged_file.properties();
-        let bitrate = properties.audio_bitrate().unwrap_or(0);
-        let duration = properties.duration();
-        let mime = tagged_file.file_type();
-
-        let default_tag = lofty::tag::Tag::new(lofty::tag::TagType::Id3v2);
-
-        let tag = match tagged_file.primary_tag() {
-            Some(primary_tag) => primary_tag,
-            // If the "primary" tag doesn't exist, we just grab the
-            // first tag we can find. Realistically, a tag reader would likely
-            // iterate through the tags to find a suitable one.
-            None => tagged_file.first_tag().unwrap_or(&default_tag),
-        };
+/// The stored cover of `album_id` with the largest resolution, with its extension
+fn stored_cover(covers_dir: &Path, album_id: &str, extra_ext: &str) -> Option<(String, u64)> {
+    COVER_EXTENSIONS
+        .iter()
+        .copied()
+        .chain(std::iter::once(extra_ext))
+        .filter(|ext| covers_dir.join(format!("{album_id}{ext}")).exists())
+        .map(|ext| {
+            let path = covers_dir.join(format!("{album_id}{ext}"));
+            let size = image::io::Reader::open(path).map_or(0, resolution);
+            (ext.to_string(), size)
+        })
+        .max_by_key(|(_, size)| *size)
+}
 
-        let path = inode.to_str().unwrap().to_string();
-        let mut audio: Track = Track {
-            path_base64: URL_SAFE.encode(path.as_bytes()),
-            file_path: path,
-            ..Default::default()
-        };
+impl Cover {
+    /// A folder image, e.g. `cover.jpg`
+    fn from_file(path: &Path) -> Option<Self> {
+        let ext = path.extension()?.to_str()?.to_lowercase();
+        Some(Self {
+            data: fs::read(path).ok()?,
+            ext: format!(".{ext}"),
+        })
+    }
 
-        audio.mime = match mime {
-            lofty::file::FileType::Aac => "audio/aac",
-            lofty::file::FileType::Aiff => "audio/aiff",
-            lofty::file::FileType::Ape => "audio/ape",
-            lofty::file::FileType::Flac => "audio/flac",
-            lofty::file::FileType::Mpeg => "audio/mpeg",
-            lofty::file::FileType::Mp4 => "audio/mp4",
-            lofty::file::FileType::Mpc => "audio/mpc",
-            lofty::file::FileType::Opus => "audio/webm",
-            lofty::file::FileType::Vorbis => "audio/webm",
-            lofty::file::FileType::Speex => "audio/speex",
-            lofty::file::FileType::Wav => "audio/wav",
-            lofty::file::FileType::WavPack => "audio/wav",
-            _ => "application/octet-stream",
-        }
-        .to_string();
-
-        if let Ok(meta) = inode.metadata() {
-            if let Ok(created_at) = meta.created() {
-                audio.created_at = created_at;
+    /// Stores the cover as the one of `album_id`, unless the album already has a larger one.
+    /// Returns the extension of the cover kept.
+    fn store(self, covers_dir: &Path, album_id: &str) -> String {
+        let size = resolution(image::io::Reader::new(std::io::Cursor::new(&self.data)));
+        if let Some((ext, current)) = stored_cover(covers_dir, album_id, &self.ext) {
+            if current >= size {
+                return ext;
             }
-        };
-
-        if let Some(year) = tag.year() {
-            audio.album_year = Some(year);
+            let _ = fs::remove_file(covers_dir.join(format!("{album_id}{ext}")));
         }
 
-        if let Some(title) = tag.title() {
-            audio.title = title.to_string();
+        let path = covers_dir.join(format!("{album_id}{}", self.ext));
+        if let Err(e) = fs::write(&path, &self.data) {
+            warn!("Unable to store the cover `{}`: {e}", path.display());
         }
-        if let Some(artists) = tag.get_string(&ItemKey::TrackArtist) {
-            audio.artists = artists
-                .split(';')
-                .filter(|x| !x.is_empty())
-                .map(|x| x.trim().to_string())
-                .collect();
-        };
 
-        if let Some(album) = tag.album() {
-            audio.album = album.to_string();
+        self.ext
+    }
+}
+
+/// Settings of the library scan, from the `[library]` and `[scan]` sections of the configuration
+#[derive(Debug, Clone)]
+pub struct ScanOptions {
+    pub artist_separators: Vec<String>,
+    /// Tracks at least this long are audiobooks, in seconds, 0 to not classify by duration
+    pub audiobook_min_duration: u64,
+    pub walk: WalkOptions,
+    /// `scan.extract_covers`
+    pub extract_covers: bool,
+    /// `library.title_patterns`, parsed, without the invalid ones
+    pub title_patterns: Vec<Vec<filename::Part>>,
+    /// Whether the files left as they were since the last scan are read again too, see
+    /// `POST /scan?full=true`
+    pub full: bool,
+}
+
+impl ScanOptions {
+    pub fn from_config(config: &lorconf::Config) -> Self {
+        let library = config.library.clone().unwrap_or_default();
+        Self {
+            artist_separators: library
+                .artist_separators
+                .or(lorconf::Library::default().artist_separators)
+                .unwrap_or_default(),
+            audiobook_min_duration: library
+                .audiobook_min_duration
+                .or(lorconf::Library::default().audiobook_min_duration)
+                .unwrap_or_default(),
+            walk: WalkOptions {
+                exclude: library
+                    .exclude
+                    .or(lorconf::Library::default().exclude)
+                    .unwrap_or_default(),
+                follow_symlinks: library
+                    .follow_symlinks
+                    .or(lorconf::Library::default().follow_symlinks)
+                    .unwrap_or_default(),
+            },
+            title_patterns: library
+                .title_patterns
+                .or(lorconf::Library::default().title_patterns)
+                .unwrap_or_default()
+                .iter()
+                .filter_map(|x| match filename::parse(x) {
+                    Ok(parts) => Some(parts),
+                    Err(e) => {
+                        warn!("library.title_patterns: {e}");
+                        None
+                    }
+                })
+                .collect(),
+            extract_covers: config
+                .scan
+                .as_ref()
+                .and_then(|x| x.extract_covers)
+                .or(lorconf::Scan::default().extract_covers)
+                .unwrap_or_default(),
+            full: false,
         }
+    }
+}
+
+impl Default for ScanOptions {
+    fn default() -> Self {
+        Self::from_config(&lorconf::Config::default())
+    }
+}
 
-        if let Some(album_artist) = tag.get_string(&ItemKey::OriginalArtist) {
-            audio.album_artist = Some(album_artist.to_string());
+/// Splits an artist tag on `separators` (ASCII case insensitive) and on the null character
+/// separating the values of id3v2.4 frames
+fn split_artists(value: &str, separators: &[String]) -> Vec<String> {
+    let lower = value.to_ascii_lowercase();
+    let separators: Vec<String> = separators
+        .iter()
+        .filter(|x| !x.is_empty())
+        .map(|x| x.to_ascii_lowercase())
+        .chain(std::iter::once("\0".to_string()))
+        .collect();
+
+    let mut artists = vec![];
+    let mut start = 0;
+    while start <= value.len() {
+        let next = separators
+            .iter()
+            .filter_map(|sep| {
+                lower[start..]
+                    .find(sep.as_str())
+                    .map(|i| (start + i, sep.len()))
+            })
+            .min();
+        let end = next.map_or(value.len(), |(i, _)| i);
+        let artist = value[start..end].trim();
+        if !artist.is_empty() && !artists.iter().any(|x| x == artist) {
+            artists.push(artist.to_string());
+        }
+        match next {
+            Some((i, len)) => start = i + len,
+            None => break,
         }
+    }
+
+    artists
+}
+
+/// Bumped whenever the way album ids are computed changes, forcing a rescan of older caches
+pub const ALBUM_ID_SCHEME: u32 = 2;
+
+/// Case folded with whitespaces collapsed, so tags differing only by their case or spacing
+/// end up in the same album
+pub fn normalize(value: &str) -> String {
+    value
+        .split_whitespace()
+        .collect::<Vec<_>>()
+        .join(" ")
+        .to_lowercase()
+}
 
-        if let Some(no) = tag.track() {
-            audio.track = no;
+/// The year most of `tracks` were first released, the earliest one on a tie
+pub fn album_year<'a>(tracks: impl IntoIterator<Item = &'a Track>) -> Option<u32> {
+    let mut years: HashMap<u32, usize> = HashMap::new();
+    for year in tracks.into_iter().filter_map(|x| x.year()) {
+        *years.entry(year).or_default() += 1;
+    }
+    years
+        .into_iter()
+        .max_by_key(|(year, count)| (*count, Reverse(*year)))
+        .map(|(year, _)| year)
+}
+
+/// Sets the quality, the year and the advisory of `album` from its tracks. Returns whether they
+/// changed.
+fn summarize(album: &mut Album, tracks: &TrackCollection) -> bool {
+    let album_tracks: Vec<&Track> = album.tracks.iter().filter_map(|x| tracks.get(x)).collect();
+    let quality = QualitySummary::of(album_tracks.iter().copied());
+    let explicit = album_tracks.iter().any(|x| x.is_explicit());
+    let year = album_year(album_tracks);
+    let changed = album.quality != quality || album.year != year || album.explicit != explicit;
+    album.quality = quality;
+    album.year = year;
+    album.explicit = explicit;
+    changed
+}
+
+/// Parental advisory of a tag value: the `rtng` numbers of iTunes, 1 or 4 for explicit and 0 or
+/// 2 for clean, or the words some taggers write
+fn parse_advisory(value: &str) -> Option<bool> {
+    match value.trim().to_lowercase().as_str() {
+        "1" | "4" | "explicit" | "true" | "yes" => Some(true),
+        "0" | "2" | "clean" | "false" | "no" => Some(false),
+        _ => None,
+    }
+}
+
+/// `value` as `YYYY`, `YYYY-MM` or `YYYY-MM-DD`, dropping the time of timestamps like
+/// `2011-03-14T00:00:00`
+fn parse_date(value: &str) -> Option<String> {
+    let date = value.trim().split(['T', ' ']).next()?;
+    let mut parts = date.split(['-', '.', '/']);
+    let year = parts.next().filter(|x| x.len() == 4)?.parse::<u32>().ok()?;
+    let mut parsed = format!("{year:04}");
+    let month = parts.next().and_then(|x| x.parse::<u32>().ok());
+    if let Some(month) = month.filter(|x| (1..=12).contains(x)) {
+        parsed.push_str(&format!("-{month:02}"));
+        let day = parts.next().and_then(|x| x.parse::<u32>().ok());
+        if let Some(day) = day.filter(|x| (1..=31).contains(x)) {
+            parsed.push_str(&format!("-{day:02}"));
         }
+    }
 
-        let mut bytes = audio.album.as_bytes().to_vec();
-        bytes.extend(
-            audio
-                .artists
-                .first()
-                .unwrap_or(&"@UNKNOWN@".to_string())
-                .as_bytes(),
-        );
+    Some(parsed)
+}
 
-        let digest = md5::compute(bytes);
-
-        audio.album_id = format!("{digest:x}");
-
-        let cover = tag.get_picture_type(PictureType::CoverFront);
-        if let Some(cover) = cover {
-            let mime = cover.mime_type().unwrap();
-            let cover = Cover {
-                data: cover.data().to_vec(),
-                ext: match mime {
-                    MimeType::Png => ".png".to_string(),
-                    MimeType::Jpeg => ".jpeg".to_string(),
-                    MimeType::Tiff => ".tiff".to_string(),
-                    MimeType::Bmp => ".bmp".to_string(),
-                    MimeType::Gif => ".gif".to_string(),
-                    MimeType::Unknown(o) => format!(".{o}"),
-                    _ => ".png".to_string(),
-                },
-            };
+/// Albums are identified by their MusicBrainz release id when tagged with one, by their
+/// normalized album artist and name otherwise. Tracks without album tag are grouped by folder.
+fn album_digest(track: &Track) -> md5::Digest {
+    if let Some(id) = track
+        .musicbrainz_album_id
+        .as_ref()
+        .filter(|x| !x.is_empty())
+    {
+        return md5::compute(format!("musicbrainz:{}", id.to_lowercase()));
+    }
+    if track.album_guessed {
+        let folder = Path::new(track.audio_file())
+            .parent()
+            .unwrap_or(Path::new(""));
+        return md5::compute(format!("folder:{}", folder.display()));
+    }
 
-            let pathstr = covers_dir.join(format!("{digest:x}{}", cover.ext));
-            let cover_path = std::path::Path::new(&pathstr);
+    let artist = track
+        .album_artists
+        .first()
+        .or(track.artists.first())
+        .map(|x| normalize(x))
+        .unwrap_or("@UNKNOWN@".to_string());
+    md5::compute(format!("{artist}\0{}", normalize(&track.album)))
+}
 
-            if !cover_path.exists() {
-                check_dir(covers_dir);
-                let mut f = fs::File::create(cover_path).unwrap();
-                f.write_all(&cover.data).unwrap();
-            }
+/// Bytes of audio hashed into the id of a track
+const ID_HASHED: u64 = 64 * 1024;
 
-            let img = image::open(cover_path).unwrap();
-            let pixels = utils::get_image_buffer(img);
+/// Stable id of the track at `file_path`, from the path and its MusicBrainz `recording` id
+/// when tagged with one, else the start of the audio of `file`, past its tags: editing the
+/// tags keeps the id. A file replaced by another at the same path makes a new track.
+pub fn track_id(file_path: &str, file: &Path, recording: Option<&str>) -> String {
+    let content = match recording.map(str::trim).filter(|x| !x.is_empty()) {
+        Some(id) => format!("musicbrainz:{}", id.to_lowercase()),
+        None => audio_hash(file).unwrap_or_default(),
+    };
+    format!("{:x}", md5::compute(format!("{file_path}\0{content}")))
+}
 
-            let color = color_thief::get_palette(&pixels, ColorFormat::Rgb, 1, 2).unwrap();
+/// Hash of the first [`ID_HASHED`] bytes of the audio of `file`, see [`seek::audio_start`]
+fn audio_hash(file: &Path) -> std::io::Result<String> {
+    let mut f = std::fs::File::open(file)?;
+    f.seek(SeekFrom::Start(seek::audio_start(file)?))?;
+    let mut head = vec![];
+    f.take(ID_HASHED).read_to_end(&mut head)?;
+    Ok(format!("{:x}", md5::compute(&head)))
+}
 
-            let color = Color {
-                r: color[0].r,
-                g: color[0].g,
-                b: color[0].b,
-            };
+/// Album id computed before [`ALBUM_ID_SCHEME`], from the album name and first track artist
+fn legacy_album_id(track: &Track) -> String {
+    let mut bytes = track.album.as_bytes().to_vec();
+    bytes.extend(
+        track
+            .artists
+            .first()
+            .map_or("@UNKNOWN@", |x| x.as_str())
+            .as_bytes(),
+    );
+    format!("{:x}", md5::compute(bytes))
+}
 
-            audio.is_light = Some(color.is_light_color());
-            audio.color = Some(color);
-            audio.cover_ext = cover.ext;
-        }
-
-        audio.duration = duration.as_secs();
-        audio.bitrate = bitrate;
-
-        let lrc_path = inode.with_extension("lrc");
-        if lrc_path.exists() {
-            let mut f = fs::File::open(&lrc_path).unwrap();
-            let mut buf = Vec::new();
-            f.read_to_end(&mut buf).unwrap();
-            let buf = String::from_utf8(buf);
-            match buf {
-                Ok(buf) => {
-                    let buf = utils::remove_lyrics_tags(buf);
-                    let lyrics = Lyrics::from_str(buf).unwrap();
-                    let lines: Vec<LyricLine> = lyrics
-                        .get_timed_lines()
-                        .iter()
-                        .map(|(time, content)| LyricLine {
-                            start_time: time.get_timestamp(),
-                            text: content.to_string(),
-                        })
-                        .collect();
+/// Reads the tags of `inode`, extracting its cover to `covers_dir`. Fails when it isn't an
+/// audio file lofty can read, e.g. a corrupt or empty one.
+pub fn read_track(
+    covers_dir: &PathBuf,
+    inode: PathBuf,
+    options: &ScanOptions,
+) -> lofty::error::Result<Track> {
+    Ok(read_file(covers_dir, inode, options)?.0)
+}
 
-                    audio.lyrics = lines;
-                }
-                Err(e) => {
-                    eprintln!("{e}");
-                }
+/// The tracks of `inode`: the file itself, or the tracks of its cue sheet
+pub fn read_tracks(
+    covers_dir: &PathBuf,
+    inode: PathBuf,
+    options: &ScanOptions,
+) -> lofty::error::Result<Vec<Track>> {
+    let (track, cuesheet) = read_file(covers_dir, inode, options)?;
+    let Some(sheet) = cue::find(Path::new(&track.file_path), cuesheet.as_deref()) else {
+        return Ok(vec![track]);
+    };
+
+    let mut tracks = cue::split(&track, &sheet);
+    for virtual_track in &mut tracks {
+        virtual_track.artists = virtual_track
+            .artists
+            .iter()
+            .flat_map(|x| split_artists(x, &options.artist_separators))
+            .collect();
+        virtual_track.album_artists = virtual_track
+            .album_artists
+            .iter()
+            .flat_map(|x| split_artists(x, &options.artist_separators))
+            .collect();
+        virtual_track.album_id = format!("{:x}", album_digest(virtual_track));
+        if virtual_track.album_id != track.album_id {
+            let cover = covers_dir.join(format!("{}{}", track.album_id, track.cover_ext));
+            if let Some(cover) = Cover::from_file(&cover) {
+                virtual_track.cover_ext = cover.store(covers_dir, &virtual_track.album_id);
             }
         }
+    }
+
+    Ok(tracks)
+}
 
-        audio
+/// The front cover in `tag`, else the image of the folder of `inode`, e.g. `cover.jpg`
+fn find_cover(tag: &lofty::tag::Tag, inode: &Path) -> Option<Cover> {
+    match tag.get_picture_type(PictureType::CoverFront) {
+        Some(picture) => Some(Cover {
+            data: picture.data().to_vec(),
+            ext: pictures::extension(picture.mime_type()),
+        }),
+        None => inode
+            .parent()
+            .and_then(utils::find_folder_cover)
+            .and_then(|path| Cover::from_file(&path)),
     }
 }
 
-impl Default for Track {
-    fn default() -> Self {
-        Self {
-            title: "@UNKNOWN@".to_string(),
-            artists: vec![],
-            track: 0,
-            album: "@UNKNOWN@".to_string(),
-            album_artist: None,
-            album_id: String::new(),
-            album_year: None,
-            lyrics: vec![],
-            cover_ext: ".png".to_string(),
-            mime: "audio/mp3".to_string(),
-            color: None,
-            is_light: None,
-            file_path: String::new(),
-            path_base64: String::new(),
-            bitrate: 0,
-            duration: 0,
-            created_at: SystemTime::UNIX_EPOCH,
+/// Extracts the cover of the album `album_id` from `inode`, one of its tracks, for the
+/// libraries scanned without `scan.extract_covers`. Returns the extension of the cover kept,
+/// `None` when the track has none.
+pub fn extract_cover(covers_dir: &PathBuf, album_id: &str, inode: &Path) -> Option<String> {
+    let tagged_file = Probe::open(inode).ok()?.read().ok()?;
+    let default_tag = lofty::tag::Tag::new(lofty::tag::TagType::Id3v2);
+    let tag = tagged_file
+        .primary_tag()
+        .or(tagged_file.first_tag())
+        .unwrap_or(&default_tag);
+    let cover = find_cover(tag, inode)?;
+    check_dir(covers_dir);
+    Some(cover.store(covers_dir, album_id))
+}
+
+/// [`read_track`], with the cue sheet embedded in the tags if any
+fn read_file(
+    covers_dir: &PathBuf,
+    inode: PathBuf,
+    options: &ScanOptions,
+) -> lofty::error::Result<(Track, Option<String>)> {
+    let tagged_file = match remote::open(&inode) {
+        Some(reader) => Probe::new(reader?).guess_file_type()?.read()?,
+        None => Probe::open(&inode)?.read()?,
+    };
+    let properties = tagged_file.properties();
+    let bitrate = properties.audio_bitrate().unwrap_or(0);
+    let sample_rate = properties.sample_rate().unwrap_or(0);
+    let duration = properties.duration();
+    let mime = tagged_file.file_type();
+
+    let default_tag = lofty::tag::Tag::new(lofty::tag::TagType::Id3v2);
+
+    let tag = match tagged_file.primary_tag() {
+        Some(primary_tag) => primary_tag,
+        // If the "primary" tag doesn't exist, we just grab the
+        // first tag we can find. Realistically, a tag reader would likely
+        // iterate through the tags to find a suitable one.
+        None => tagged_file.first_tag().unwrap_or(&default_tag),
+    };
+
+    let path = inode.to_string_lossy().to_string();
+    let mut audio: Track = Track {
+        path_base64: URL_SAFE.encode(path.as_bytes()),
+        file_path: path,
+        ..Default::default()
+    };
+
+    audio.mime = match mime {
+        lofty::file::FileType::Aac => "audio/aac",
+        lofty::file::FileType::Aiff => "audio/aiff",
+        lofty::file::FileType::Ape => "audio/ape",
+        lofty::file::FileType::Flac => "audio/flac",
+        lofty::file::FileType::Mpeg => "audio/mpeg",
+        lofty::file::FileType::Mp4 => "audio/mp4",
+        lofty::file::FileType::Mpc => "audio/mpc",
+        lofty::file::FileType::Opus => "audio/webm",
+        lofty::file::FileType::Vorbis => "audio/webm",
+        lofty::file::FileType::Speex => "audio/speex",
+        lofty::file::FileType::Wav => "audio/wav",
+        lofty::file::FileType::WavPack => "audio/wav",
+        _ => "application/octet-stream",
+    }
+    .to_string();
+
+    if let Ok(meta) = inode.metadata() {
+        if let Ok(created_at) = meta.created() {
+            audio.created_at = created_at;
+        }
+    };
+
+    if let Some(year) = tag.year() {
+        audio.album_year = Some(year);
+    }
+    audio.release_date = tag
+        .get_string(&ItemKey::RecordingDate)
+        .or(tag.get_string(&ItemKey::ReleaseDate))
+        .or(tag.get_string(&ItemKey::Year))
+        .and_then(parse_date);
+    audio.original_date = tag
+        .get_string(&ItemKey::OriginalReleaseDate)
+        .and_then(parse_date);
+
+    if let Some(title) = tag.title() {
+        audio.title = title.to_string();
+    }
+    audio.artists = tag
+        .get_strings(&ItemKey::TrackArtist)
+        .flat_map(|x| split_artists(x, &options.artist_separators))
+        .collect();
+
+    match tag.album().filter(|x| !x.trim().is_empty()) {
+        Some(album) => audio.album = album.to_string(),
+        // Grouped with the other tracks of its folder, see `album_digest`
+        None => {
+            if let Some(folder) = inode.parent().and_then(|x| x.file_name()) {
+                audio.album = folder.to_string_lossy().to_string();
+                audio.album_guessed = true;
+            }
         }
     }
+
+    if let Some(genre) = tag.genre() {
+        audio.genre = Some(genre.to_string());
+    }
+
+    audio.bpm = tag
+        .get_string(&ItemKey::Bpm)
+        .or(tag.get_string(&ItemKey::IntegerBpm))
+        .and_then(|x| x.trim().parse::<f32>().ok())
+        .filter(|x| *x > 0.0);
+    audio.key = tag
+        .get_string(&ItemKey::InitialKey)
+        .and_then(Key::parse)
+        .map(|x| x.to_string());
+
+    let id3v2 = id3v2_tag(&inode, mime);
+    audio.composers = tag
+        .get_strings(&ItemKey::Composer)
+        .flat_map(|x| split_artists(x, &options.artist_separators))
+        .collect();
+    let text = |key: ItemKey| {
+        tag.get_string(&key)
+            .map(|x| x.trim().to_string())
+            .filter(|x| !x.is_empty())
+    };
+    audio.conductor = text(ItemKey::Conductor);
+    // `TXXX:WORK` is dropped by lofty too, its description being 4 characters long
+    audio.work = text(ItemKey::Work).or_else(|| {
+        id3v2
+            .as_ref()
+            .and_then(|x| x.get_user_text("WORK"))
+            .map(|x| x.trim().to_string())
+            .filter(|x| !x.is_empty())
+    });
+    audio.movement = text(ItemKey::Movement);
+    // e.g. `2` or `2/4`
+    audio.movement_number = text(ItemKey::MovementNumber)
+        .and_then(|x| x.split('/').next().and_then(|x| x.trim().parse().ok()));
+
+    audio.credits = get_credits(tag, musician_credits(id3v2.as_ref()));
+
+    // `ITUNESADVISORY` is only mapped for ID3v2 and MP4
+    audio.explicit = [
+        ItemKey::ParentalAdvisory,
+        ItemKey::Unknown("ITUNESADVISORY".to_string()),
+        ItemKey::Unknown("EXPLICIT".to_string()),
+    ]
+    .iter()
+    .find_map(|key| tag.get_string(key).and_then(parse_advisory));
+
+    audio.album_artists = tag
+        .get_strings(&ItemKey::AlbumArtist)
+        .flat_map(|x| split_artists(x, &options.artist_separators))
+        .collect();
+
+    if let Some(no) = tag.track() {
+        audio.track = no;
+    }
+
+    if tag.title().is_none() {
+        let name = inode.file_stem().unwrap_or_default().to_string_lossy();
+        if let Some((track, title)) = filename::guess(&options.title_patterns, &name) {
+            audio.title = title;
+            audio.title_inferred = true;
+            if let Some(no) = track.filter(|_| tag.track().is_none()) {
+                audio.track = no;
+                audio.track_inferred = true;
+            }
+        }
+    }
+
+    if let Some(id) = tag.get_string(&ItemKey::MusicBrainzReleaseId) {
+        audio.musicbrainz_album_id = Some(id.trim().to_string());
+    }
+    if let Some(id) = tag.get_string(&ItemKey::MusicBrainzRecordingId) {
+        audio.musicbrainz_recording_id = Some(id.trim().to_string());
+    }
+    audio.id = track_id(
+        &audio.file_path,
+        &inode,
+        audio.musicbrainz_recording_id.as_deref(),
+    );
+
+    let cuesheet = tag
+        .get_string(&ItemKey::Unknown("CUESHEET".to_string()))
+        .map(|x| x.to_string());
+
+    let digest = album_digest(&audio);
+    audio.album_id = format!("{digest:x}");
+
+    // Else extracted on the first request of the cover, see `extract_cover`
+    if options.extract_covers {
+        if let Some(cover) = find_cover(tag, &inode) {
+            check_dir(covers_dir);
+            // The colors are filled in afterwards by `palette::worker`
+            audio.cover_ext = cover.store(covers_dir, &audio.album_id);
+        }
+        pictures::store(covers_dir, &audio.album_id, tag.pictures());
+    }
+
+    audio.duration = duration.as_secs();
+    audio.bitrate = bitrate;
+    audio.is_lossless = fileinfo::is_lossless(&inode, mime);
+    audio.sample_rate = properties.sample_rate();
+    audio.bit_depth = properties.bit_depth();
+    audio.gapless = gapless::read(&inode, mime, sample_rate);
+
+    let lrc_path = inode.with_extension("lrc");
+    if lrc_path.exists() {
+        // The track is kept without lyrics when they can't be read
+        match lrc::read(&lrc_path) {
+            Ok(lyrics) => audio.lyrics = lyrics,
+            Err(e) => warn!("Unable to read the lyrics `{}`: {e}", lrc_path.display()),
+        }
+    } else if let Some(text) = tag.get_string(&ItemKey::Lyrics) {
+        // Some taggers store LRC in the lyrics tag, plain lyrics give no line
+        audio.lyrics = lrc::parse(text);
+    }
+
+    Ok((audio, cuesheet))
+}
+
+/// `text` lowercase with its words separated by single spaces, without punctuation
+fn lyrics_words(text: &str) -> String {
+    text.to_lowercase()
+        .split(|x: char| !x.is_alphanumeric() && x != '\'' && x != '’')
+        .map(|x| x.replace(['\'', '’'], ""))
+        .filter(|x| !x.is_empty())
+        .collect::<Vec<String>>()
+        .join(" ")
 }
 
 #[derive(serde::Serialize, serde::Deserialize, Default, Debug, Clone)]
@@ -272,11 +704,34 @@ pub struct Songs {
 
 pub type TrackCollection = HashMap<PathBuf, Track>;
 
-#[derive(serde::Serialize, serde::Deserialize, Default, Debug, Clone)]
+#[derive(serde::Serialize, serde::Deserialize, Default, Debug, Clone, utoipa::ToSchema)]
 pub struct Media {
+    /// Tracks keyed by their file path
+    #[schema(value_type = HashMap<String, Track>)]
     pub tracks: TrackCollection,
     pub albums: Vec<Album>,
     pub playlists: Vec<Playlist>,
+    #[serde(default)]
+    pub album_id_scheme: u32,
+    /// Other paths of the audio files reached through symlinks or hard links, keyed by the
+    /// path of their tracks
+    #[serde(default)]
+    #[schema(value_type = HashMap<String, Vec<String>>)]
+    pub aliases: Aliases,
+    /// Paths of the tracks keyed by their id. The indexes are rebuilt by [`Media::reindex`] once
+    /// read from the cache, then kept up to date by the methods changing the media.
+    #[serde(skip)]
+    ids: HashMap<String, PathBuf>,
+    /// Positions of the albums in `albums` keyed by their id
+    #[serde(skip)]
+    album_ids: HashMap<String, usize>,
+    /// Positions of the playlists in `playlists` keyed by their id
+    #[serde(skip)]
+    playlist_ids: HashMap<String, usize>,
+    /// Paths of the tracks keyed by their audio file, one per part of a file split by a cue
+    /// sheet
+    #[serde(skip)]
+    parts: HashMap<String, Vec<PathBuf>>,
 }
 
 impl Media {
@@ -302,9 +757,13 @@ impl Media {
                         .any(|artist| artist.to_lowercase().contains(&query_lower))
                     || track.album.to_lowercase().contains(&query_lower)
                     || track
-                        .album_artist
-                        .as_ref()
-                        .map_or(false, |artist| artist.to_lowercase().contains(&query_lower))
+                        .album_artists
+                        .iter()
+                        .any(|artist| artist.to_lowercase().contains(&query_lower))
+                    || track
+                        .credits
+                        .iter()
+                        .any(|credit| credit.name.to_lowercase().contains(&query_lower))
                     || track
                         .lyrics
                         .iter()
@@ -318,7 +777,14 @@ impl Media {
             .iter()
             .filter(|album| {
                 album.name.to_lowercase().contains(&query_lower)
-                    || album.artist.to_lowercase().contains(&query_lower)
+                    || album
+                        .artists
+                        .iter()
+                        .any(|artist| artist.to_lowercase().contains(&query_lower))
+                    || album
+                        .credits
+                        .iter()
+                        .any(|credit| credit.name.to_lowercase().contains(&query_lower))
             })
             .cloned()
             .collect();
@@ -334,6 +800,114 @@ impl Media {
             albums,
             playlists,
             tracks,
+            lyrics: vec![],
+        }
+    }
+
+    /// The tracks whose synced lyrics hold `phrase`, whatever the case, punctuation and spacing,
+    /// with the lines holding it. A phrase running over two lines is found too.
+    pub fn search_lyrics(&self, phrase: &str) -> SearchResults {
+        let phrase = lyrics_words(phrase);
+        let mut results = SearchResults {
+            albums: vec![],
+            playlists: vec![],
+            tracks: vec![],
+            lyrics: vec![],
+        };
+        if phrase.is_empty() {
+            return results;
+        }
+
+        let mut tracks: Vec<&Track> = self.tracks.values().collect();
+        tracks.sort_by(|a, b| a.file_path.cmp(&b.file_path));
+        for track in tracks {
+            let words: Vec<String> = track.lyrics.iter().map(|x| lyrics_words(&x.text)).collect();
+            let lines: Vec<LyricLine> = track
+                .lyrics
+                .iter()
+                .enumerate()
+                .filter(|(i, _)| {
+                    let line = &words[*i];
+                    line.contains(&phrase)
+                        || words.get(i + 1).is_some_and(|next| {
+                            let joined = format!("{line} {next}");
+                            // Found across the two lines, not in the next one alone
+                            joined.contains(&phrase) && !next.contains(&phrase)
+                        })
+                })
+                .map(|(_, x)| x.clone())
+                .collect();
+            if !lines.is_empty() {
+                results.lyrics.push(LyricsMatch {
+                    track: track.id.clone(),
+                    lines,
+                });
+                results.tracks.push(track.clone());
+            }
+        }
+        results
+    }
+
+    /// A page of the tracks matching `query` and `quality`, in its order
+    pub fn list_tracks(&self, query: &TracksQuery, quality: &QualityQuery) -> TrackList {
+        let genre = query.genre.as_ref().map(|x| x.to_lowercase());
+        let key = query.key.as_deref().and_then(Key::parse);
+        let key_of = |track: &Track| track.key.as_deref().and_then(Key::parse);
+        let mut tracks: Vec<&Track> = self
+            .tracks
+            .values()
+            .filter(|track| quality.keeps(track))
+            .filter(|track| {
+                genre.as_ref().is_none_or(|genre| {
+                    track
+                        .genre
+                        .as_ref()
+                        .is_some_and(|x| x.to_lowercase() == *genre)
+                })
+            })
+            .filter(|track| {
+                (query.min_bpm.is_none() && query.max_bpm.is_none())
+                    || track.bpm.is_some_and(|bpm| {
+                        query.min_bpm.is_none_or(|x| bpm >= x)
+                            && query.max_bpm.is_none_or(|x| bpm <= x)
+                    })
+            })
+            // An unknown key matches nothing
+            .filter(|track| query.key.is_none() || key.is_some() && key_of(track) == key)
+            .collect();
+
+        // By title between equals, the sorts being stable. Tracks missing the value sorted by
+        // come last either way.
+        tracks.sort_by_cached_key(|x| x.title.to_lowercase());
+        let order = |x: Ordering| if query.desc { x.reverse() } else { x };
+        match query.sort.unwrap_or(TrackSort::Title) {
+            TrackSort::Title => {
+                tracks.sort_by(|a, b| order(a.title.to_lowercase().cmp(&b.title.to_lowercase())))
+            }
+            TrackSort::Bpm => tracks.sort_by(|a, b| match (a.bpm, b.bpm) {
+                (Some(a), Some(b)) => order(a.total_cmp(&b)),
+                (a, b) => b.is_some().cmp(&a.is_some()),
+            }),
+            TrackSort::Key => tracks.sort_by(|a, b| {
+                match (
+                    key_of(a).map(|x| x.camelot()),
+                    key_of(b).map(|x| x.camelot()),
+                ) {
+                    (Some(a), Some(b)) => order(a.cmp(&b)),
+                    (a, b) => b.is_some().cmp(&a.is_some()),
+                }
+            }),
+            TrackSort::Added => tracks.sort_by(|a, b| order(a.created_at.cmp(&b.created_at))),
+        }
+
+        TrackList {
+            total: tracks.len(),
+            tracks: tracks
+                .into_iter()
+                .skip(query.offset.unwrap_or(0))
+                .take(query.limit.unwrap_or(50))
+                .cloned()
+                .collect(),
         }
     }
 
@@ -341,91 +915,446 @@ impl Media {
         self.albums = media.albums;
         self.tracks = media.tracks;
         self.playlists = media.playlists;
+        self.album_id_scheme = media.album_id_scheme;
+        self.ids = media.ids;
+        self.album_ids = media.album_ids;
+        self.playlist_ids = media.playlist_ids;
+        self.parts = media.parts;
     }
 
-    pub fn add_song(&mut self, song: Track) {
-        let mut inserted = false;
-        for album in &mut self.albums {
-            if song.album_id == album.id {
-                album.tracks.push(PathBuf::from(&song.file_path));
-                self.tracks
-                    .insert(PathBuf::from(song.file_path.clone()), song.clone());
-                inserted = true;
-                break;
+    /// Builds the indexes of the media again, once read from the cache or put together
+    pub fn reindex(&mut self) {
+        self.ids.clear();
+        self.parts.clear();
+        for (path, track) in &self.tracks {
+            if !track.id.is_empty() {
+                self.ids.insert(track.id.clone(), path.clone());
             }
+            self.parts
+                .entry(track.audio_file().to_string())
+                .or_default()
+                .push(path.clone());
+        }
+        self.index_albums();
+        self.index_playlists();
+    }
+
+    fn index_albums(&mut self) {
+        self.album_ids = self
+            .albums
+            .iter()
+            .enumerate()
+            .map(|(i, x)| (x.id.clone(), i))
+            .collect();
+    }
+
+    fn index_playlists(&mut self) {
+        self.playlist_ids = self
+            .playlists
+            .iter()
+            .enumerate()
+            .map(|(i, x)| (x.id.clone(), i))
+            .collect();
+    }
+
+    pub fn add_song(&mut self, song: Track) {
+        let path = PathBuf::from(&song.file_path);
+        self.ids.insert(song.id.clone(), path.clone());
+        let parts = self.parts.entry(song.audio_file().to_string()).or_default();
+        if !parts.contains(&path) {
+            parts.push(path.clone());
         }
-        if !inserted {
-            self.tracks
-                .insert(PathBuf::from(song.file_path.clone()), song.clone());
-            let albums = Songs { audios: vec![song] }.get_albums();
-            self.albums.extend(albums);
+        self.tracks.insert(path.clone(), song.clone());
+        match self.album_ids.get(&song.album_id) {
+            Some(&i) => {
+                let album = &mut self.albums[i];
+                album.tracks.push(path);
+                album.add_credits(&song.credits);
+                if album.palette.is_none() {
+                    album.palette = song.palette;
+                }
+                summarize(album, &self.tracks);
+            }
+            None => {
+                self.album_ids
+                    .insert(song.album_id.clone(), self.albums.len());
+                self.albums
+                    .extend(Songs { audios: vec![song] }.get_albums());
+            }
         }
     }
 
-    pub fn add_media(&mut self, path: PathBuf, covers_dir: &PathBuf) {
-        let ext = path.extension().unwrap().to_str().unwrap();
-        if ext == "m3u8" {
-            self.add_playlist(m3u8::M3U8::parse(path));
+    /// Adds the playlist or the tracks of the file `path`, failing if it can't be read
+    pub fn add_media(
+        &mut self,
+        path: PathBuf,
+        covers_dir: &PathBuf,
+        options: &ScanOptions,
+    ) -> lofty::error::Result<()> {
+        if PlaylistFormat::from_path(&path).is_some() {
+            self.add_playlist(playlist::parse(path));
         } else {
-            self.add_song(Track::from_file(covers_dir, path));
+            for track in read_tracks(covers_dir, path, options)? {
+                self.add_song(track);
+            }
         }
+
+        Ok(())
     }
 
     pub fn remove_media(&mut self, path: PathBuf) {
-        let ext = path.extension().unwrap().to_str().unwrap();
-        if ext == "m3u8" {
+        if PlaylistFormat::from_path(&path).is_some() {
             self.remove_playlist(path);
         } else {
             self.remove_song(path);
         }
     }
 
-    #[inline]
     pub fn add_playlist(&mut self, playlist: Playlist) {
+        self.playlist_ids
+            .insert(playlist.id.clone(), self.playlists.len());
         self.playlists.push(playlist);
     }
 
-    #[inline]
     pub fn remove_playlist(&mut self, path: PathBuf) {
+        let count = self.playlists.len();
         self.playlists
             .retain(|x| x.path != format!("{}", path.display()));
+        if self.playlists.len() != count {
+            self.index_playlists();
+        }
     }
 
+    /// Removes the track at `path`, or the tracks split from it by a cue sheet
     pub fn remove_song(&mut self, path: PathBuf) {
-        for album in &mut self.albums {
-            album.remove_track(path.clone());
+        let mut paths = self
+            .parts
+            .remove(&*path.to_string_lossy())
+            .unwrap_or_default();
+        if !paths.contains(&path) {
+            paths.push(path);
+        }
+
+        let mut album_ids = vec![];
+        for path in paths {
+            let Some(track) = self.tracks.remove(&path) else {
+                continue;
+            };
+            self.ids.remove(&track.id);
+            // A part removed alone, its file stays
+            if let Some(parts) = self.parts.get_mut(track.audio_file()) {
+                parts.retain(|x| *x != path);
+            }
+            if let Some(&i) = self.album_ids.get(&track.album_id) {
+                self.albums[i].remove_track(path);
+            }
+            album_ids.push(track.album_id);
         }
 
-        self.tracks.remove_entry(&path);
+        let count = self.albums.len();
         self.albums.retain(|x| !x.tracks.is_empty());
+        if self.albums.len() != count {
+            self.index_albums();
+        }
+        for id in album_ids {
+            if let Some(&i) = self.album_ids.get(&id) {
+                summarize(&mut self.albums[i], &self.tracks);
+            }
+        }
     }
 
-    pub fn get_album(&self, id: &String) -> Option<Album> {
-        for album in self.albums.iter() {
-            if album.id == id.clone() {
-                return Some(Album {
-                    name: album.name.clone(),
-                    artist: album.artist.clone(),
-                    tracks: album.tracks.clone(),
-                    year: album.year,
-                    id: album.id.clone(),
-                });
+    /// Points the track of the file moved from `from` to `to`, or the tracks split from it by a
+    /// cue sheet, to its new path without reading it again
+    pub fn move_song(&mut self, from: &Path, to: &Path) {
+        let (from, to) = (from.to_string_lossy(), to.to_string_lossy());
+        let moved: Vec<Track> = self
+            .parts
+            .remove(&*from)
+            .unwrap_or_default()
+            .iter()
+            .filter_map(|x| self.tracks.get(x))
+            .cloned()
+            .collect();
+
+        for mut track in moved {
+            let old = PathBuf::from(&track.file_path);
+            track.file_path = match track.source {
+                Some(_) => track.file_path.replacen(&*from, &to, 1),
+                None => to.to_string(),
+            };
+            if track.source.is_some() {
+                track.source = Some(to.to_string());
             }
+            track.path_base64 = URL_SAFE.encode(track.file_path.as_bytes());
+
+            let new = PathBuf::from(&track.file_path);
+            if let Some(&i) = self.album_ids.get(&track.album_id) {
+                for path in self.albums[i].tracks.iter_mut().filter(|x| **x == old) {
+                    *path = new.clone();
+                }
+            }
+            self.tracks.remove(&old);
+            self.ids.insert(track.id.clone(), new.clone());
+            self.parts
+                .entry(to.to_string())
+                .or_default()
+                .push(new.clone());
+            self.tracks.insert(new, track);
         }
+    }
 
-        None
+    /// Tracks of the audio file `file`, one per part when it is split by a cue sheet
+    pub fn tracks_of(&self, file: &Path) -> Vec<Track> {
+        self.parts
+            .get(&*file.to_string_lossy())
+            .into_iter()
+            .flatten()
+            .filter_map(|x| self.tracks.get(x))
+            .cloned()
+            .collect()
     }
 
-    pub fn get_song(&self, path: &String) -> Option<Track> {
-        self.tracks.get(&PathBuf::from(path)).cloned()
+    /// Gives the tracks read again from the files of `previous` the ids they had, as with tags
+    /// edited through the daemon, and what the analysis of their audio found.
+    pub fn restore_tracks(&mut self, previous: &[Track]) {
+        for track in previous {
+            let path = PathBuf::from(&track.file_path);
+            if let Some(read) = self.tracks.get_mut(&path) {
+                if read.id != track.id {
+                    self.ids.remove(&read.id);
+                    self.ids.insert(track.id.clone(), path);
+                }
+                read.id.clone_from(&track.id);
+                read.bpm = read.bpm.or(track.bpm);
+                read.key = read.key.take().or_else(|| track.key.clone());
+                read.trim_start_ms = track.trim_start_ms;
+                read.trim_end_ms = track.trim_end_ms;
+            }
+        }
     }
-}
 
-#[derive(serde::Serialize, Debug)]
-pub struct SearchResults {
-    pub albums: Vec<Album>,
-    pub playlists: Vec<Playlist>,
-    pub tracks: Vec<Track>,
+    /// Other paths of the audio file `file`
+    pub fn aliases_of(&self, file: &str) -> Vec<String> {
+        self.aliases
+            .get(Path::new(file))
+            .map(|x| x.iter().map(|x| x.display().to_string()).collect())
+            .unwrap_or_default()
+    }
+
+    /// Files of the tracks and playlists that no longer exist
+    pub fn missing_files(&self) -> Vec<PathBuf> {
+        let mut files: Vec<PathBuf> = self
+            .tracks
+            .values()
+            .map(|x| PathBuf::from(x.audio_file()))
+            .chain(self.playlists.iter().map(|x| PathBuf::from(&x.path)))
+            // The remote files gone are dropped from their listing instead
+            .filter(|x| !remote::is_remote(x) && !x.exists())
+            .collect();
+        files.sort();
+        files.dedup();
+        files
+    }
+
+    /// Audio files of `tracks`, once per file for tracks split from the same file
+    pub fn audio_files(&self, tracks: &[PathBuf]) -> Vec<PathBuf> {
+        let mut files: Vec<PathBuf> = vec![];
+        for path in tracks {
+            let file = self
+                .tracks
+                .get(path)
+                .map_or(path.clone(), |x| PathBuf::from(x.audio_file()));
+            if !files.contains(&file) {
+                files.push(file);
+            }
+        }
+        files
+    }
+
+    pub fn album(&self, id: &AlbumId) -> Option<&Album> {
+        self.albums.get(*self.album_ids.get(id.as_str())?)
+    }
+
+    pub fn album_mut(&mut self, id: &AlbumId) -> Option<&mut Album> {
+        self.albums.get_mut(*self.album_ids.get(id.as_str())?)
+    }
+
+    pub fn get_album(&self, id: &AlbumId) -> Option<Album> {
+        self.album(id).cloned()
+    }
+
+    pub fn playlist(&self, id: &PlaylistId) -> Option<&Playlist> {
+        self.playlists.get(*self.playlist_ids.get(id.as_str())?)
+    }
+
+    pub fn get_playlist(&self, id: &PlaylistId) -> Option<Playlist> {
+        self.playlist(id).cloned()
+    }
+
+    /// Looks a track up by its id, or by the url safe base64 encoding of its path that served
+    /// as its id before
+    pub fn track(&self, id: &TrackId) -> Option<&Track> {
+        self.tracks.get(&self.track_path(id)?)
+    }
+
+    pub fn get_track(&self, id: &TrackId) -> Option<Track> {
+        self.track(id).cloned()
+    }
+
+    fn track_path(&self, id: &str) -> Option<PathBuf> {
+        if let Some(path) = self.ids.get(id) {
+            return Some(path.clone());
+        }
+        let path = URL_SAFE.decode(id).ok()?;
+        Some(PathBuf::from(String::from_utf8_lossy(&path).to_string()))
+    }
+
+    /// Gives an id to the tracks of a cache written before they had one, and indexes them.
+    /// Returns whether any track got one.
+    pub fn assign_track_ids(&mut self) -> bool {
+        let mut changed = false;
+        for (path, track) in self.tracks.iter_mut() {
+            if track.id.is_empty() {
+                track.id = track_id(
+                    &track.file_path,
+                    Path::new(track.audio_file()),
+                    track.musicbrainz_recording_id.as_deref(),
+                );
+                self.ids.insert(track.id.clone(), path.clone());
+                changed = true;
+            }
+        }
+        changed
+    }
+
+    /// Maps the former ids of the tracks, the encoding of their paths, to their ids
+    pub fn legacy_track_ids(&self) -> HashMap<String, String> {
+        self.tracks
+            .values()
+            .map(|track| (track.path_base64.clone(), track.id.clone()))
+            .collect()
+    }
+
+    /// The tracks and albums `quality` keeps, the playlists with their tracks kept only
+    pub fn filtered(&self, quality: &QualityQuery) -> Media {
+        let tracks: TrackCollection = self
+            .tracks
+            .iter()
+            .filter(|(_, x)| quality.keeps(x))
+            .map(|(path, x)| (path.clone(), x.clone()))
+            .collect();
+        let playlists = self
+            .playlists
+            .iter()
+            .map(|x| Playlist {
+                tracks: x
+                    .tracks
+                    .iter()
+                    .filter(|x| tracks.contains_key(*x))
+                    .cloned()
+                    .collect(),
+                ..x.clone()
+            })
+            .collect();
+
+        let mut media = Media {
+            albums: self
+                .albums
+                .iter()
+                .filter(|x| quality.keeps_album(x))
+                .cloned()
+                .collect(),
+            playlists,
+            album_id_scheme: self.album_id_scheme,
+            aliases: self
+                .aliases
+                .iter()
+                .filter(|(path, _)| tracks.contains_key(*path))
+                .map(|(path, x)| (path.clone(), x.clone()))
+                .collect(),
+            tracks,
+            ..Default::default()
+        };
+        media.reindex();
+        media
+    }
+
+    /// Maps the album ids of an older [`ALBUM_ID_SCHEME`] to the current ones
+    pub fn legacy_album_ids(&self) -> HashMap<String, String> {
+        self.tracks
+            .values()
+            .map(|track| (legacy_album_id(track), track.album_id.clone()))
+            .filter(|(legacy, id)| legacy != id)
+            .collect()
+    }
+
+    /// Points the playlists to the images named like them, added or removed since they were
+    /// read. Returns whether anything changed.
+    pub fn select_playlist_covers(&mut self) -> bool {
+        let mut changed = false;
+        for playlist in &mut self.playlists {
+            let cover_url = playlist::cover(playlist)
+                .map(|_| proxy::url(&format!("/playlist/{}/cover", playlist.id)));
+            if playlist.cover_url != cover_url {
+                playlist.cover_url = cover_url;
+                changed = true;
+            }
+        }
+        changed
+    }
+
+    /// Sums the tracks of the albums up again, for the caches written before the albums had a
+    /// quality or a year by consensus. Returns whether anything changed.
+    pub fn summarize_albums(&mut self) -> bool {
+        let mut changed = false;
+        for album in &mut self.albums {
+            changed |= summarize(album, &self.tracks);
+        }
+        changed
+    }
+
+    /// Points the albums and their tracks to the cover kept for each album, the largest one
+    /// found during the scans. Returns whether anything changed.
+    pub fn select_covers(&mut self, covers_dir: &Path) -> bool {
+        let mut changed = false;
+        for album in &mut self.albums {
+            let ext = album
+                .tracks
+                .first()
+                .and_then(|x| self.tracks.get(x))
+                .map_or(".png".to_string(), |x| x.cover_ext.clone());
+            let Some((ext, _)) = stored_cover(covers_dir, &album.id, &ext) else {
+                changed |= album.cover_url.take().is_some();
+                continue;
+            };
+
+            let url = proxy::url(&format!("/cover/{}{ext}", album.id));
+            if album.cover_url.as_ref() != Some(&url) {
+                album.cover_url = Some(url);
+                changed = true;
+            }
+            for path in &album.tracks {
+                let Some(track) = self.tracks.get_mut(path) else {
+                    continue;
+                };
+                if track.cover_ext != ext {
+                    track.cover_ext.clone_from(&ext);
+                    // Computed again from the new cover
+                    track.palette = None;
+                    album.palette = None;
+                    changed = true;
+                }
+            }
+        }
+
+        changed
+    }
+
+    pub fn set_lyrics(&mut self, id: &TrackId, lyrics: Vec<LyricLine>) {
+        if let Some(track) = self.track_path(id).and_then(|x| self.tracks.get_mut(&x)) {
+            track.lyrics = lyrics;
+        }
+    }
 }
 
 impl Songs {
@@ -440,17 +1369,39 @@ impl Songs {
         }
 
         for (k, v) in album_map {
+            let mut credits = vec![];
+            for credit in v.iter().flat_map(|x| x.credits.iter()) {
+                if !credits.contains(credit) {
+                    credits.push(credit.clone());
+                }
+            }
+            let palette = v.iter().find_map(|x| x.palette);
+            let quality = QualitySummary::of(&v);
+            let year = album_year(&v);
+            let guessed = v[0].album_guessed;
+            let explicit = v.iter().any(|x| x.is_explicit());
             albums.push(Album {
                 name: v[0].album.clone(),
-                artist: v[0].album_artist.clone().unwrap_or(String::from(
-                    v[0].artists
-                        .clone()
+                artists: if v[0].album_artists.is_empty() {
+                    vec![v[0]
+                        .artists
                         .first()
-                        .unwrap_or(&"@UNKNOWN@".to_string()),
-                )),
-                year: v[0].album_year,
+                        .cloned()
+                        .unwrap_or("@UNKNOWN@".to_string())]
+                } else {
+                    v[0].album_artists.clone()
+                },
+                year,
                 tracks: v.into_iter().map(|x| PathBuf::from(x.file_path)).collect(),
                 id: k,
+                credits,
+                palette,
+                cover_url: None,
+                // Set for the whole library by `audiobooks::classify`
+                media_kind: MediaKind::Music,
+                quality,
+                guessed,
+                explicit,
             });
         }
 
@@ -476,58 +1427,62 @@ pub fn check_dir(dir: &PathBuf) {
 pub mod utils {
     use std::{
         io::{Read, Write},
-        path::PathBuf,
+        path::{Path, PathBuf},
         str::FromStr,
     };
 
-    use glob::glob;
+    use crate::daemon::remote;
+    use crate::daemon::walk::{self, Aliases, WalkOptions};
+
+    const FOLDER_COVER_NAMES: [&str; 4] = ["cover", "folder", "front", "album"];
+    const FOLDER_COVER_EXTENSIONS: [&str; 3] = ["jpg", "jpeg", "png"];
+
+    /// Finds an image like `cover.jpg` or `folder.png` in `dir`
+    pub fn find_folder_cover(dir: &Path) -> Option<PathBuf> {
+        let entries = std::fs::read_dir(dir).ok()?;
+        entries.flatten().map(|x| x.path()).find(|path| {
+            let stem = path.file_stem().and_then(|x| x.to_str());
+            let ext = path.extension().and_then(|x| x.to_str());
+            match (stem, ext) {
+                (Some(stem), Some(ext)) => {
+                    FOLDER_COVER_NAMES.contains(&stem.to_lowercase().as_str())
+                        && FOLDER_COVER_EXTENSIONS.contains(&ext.to_lowercase().as_str())
+                }
+                _ => false,
+            }
+        })
+    }
 
+    /// Pixels of `img` as RGB8, whatever its color type (alpha, grayscale, 16 bits, float...)
     pub fn get_image_buffer(img: image::DynamicImage) -> Vec<u8> {
         match img {
-            image::DynamicImage::ImageRgb8(buffer) => buffer.to_vec(),
-            _ => unreachable!(),
+            image::DynamicImage::ImageRgb8(buffer) => buffer.into_raw(),
+            img => img.into_rgb8().into_raw(),
         }
     }
 
-    pub fn remove_lyrics_tags(buf: String) -> String {
-        let strings: Vec<String> = buf
-            .lines()
-            .filter(|x| !x.is_empty())
-            .filter(|x| {
-                let mut chars = x.chars();
-                let openparen = chars.next().unwrap();
-                let nextdata = chars.next().unwrap();
-                openparen == '[' && nextdata.is_ascii_digit()
+    /// Audio files and playlists found under `dirs`, walked per `options`, once per file with
+    /// the other paths of the ones reached through links. The roots of remotes are left out, see
+    /// [`remote::files`].
+    pub fn get_audio_files(dirs: &[PathBuf], options: &WalkOptions) -> (Vec<PathBuf>, Aliases) {
+        let dirs: Vec<PathBuf> = dirs
+            .iter()
+            .filter(|x| !remote::is_remote(x))
+            .cloned()
+            .collect();
+        let files = walk::files(&dirs, options)
+            .into_iter()
+            .filter(|inode| {
+                let guess = mime_guess::from_path(inode).first_or("text/plain".parse().unwrap());
+                guess.type_() == super::mime::AUDIO
+                    || super::PlaylistFormat::from_path(inode).is_some()
             })
-            .map(|x| x.to_string())
             .collect();
-
-        strings.join("\n")
-    }
-
-    pub fn get_audio_files() -> Vec<PathBuf> {
-        let mut files = vec![];
-        if let Some(audio_dir) = dirs::audio_dir() {
-            if let Ok(paths) = glob(&format!("{}/**/*", audio_dir.display())) {
-                for inode in paths.flatten() {
-                    if inode.is_file() {
-                        let guess =
-                            mime_guess::from_path(&inode).first_or("text/plain".parse().unwrap());
-                        if guess.type_() == super::mime::AUDIO {
-                            files.push(inode);
-                        }
-                    }
-                }
-            }
-        }
-        files
+        walk::dedupe(files)
     }
 
-    pub fn cache_audio_files(cache_path: &std::path::Path) {
-        let files: Vec<String> = get_audio_files()
-            .iter()
-            .map(|x| format!("{}", x.display()))
-            .collect();
+    pub fn cache_audio_files(cache_path: &std::path::Path, files: &[PathBuf]) {
+        let files: Vec<String> = files.iter().map(|x| format!("{}", x.display())).collect();
         let data = files.join("\n");
         let mut f = std::fs::File::create(cache_path).unwrap();
         let _ = f.write_all(data.as_bytes());
@@ -542,4 +1497,279 @@ pub mod utils {
 
         buf.lines().map(|x| PathBuf::from_str(x).unwrap()).collect()
     }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+        use crate::daemon::palette;
+        use image::{DynamicImage, Rgb, RgbImage};
+
+        const WIDTH: u32 = 16;
+        const HEIGHT: u32 = 8;
+
+        /// A gradient, for the palette to find several colors
+        fn fixture() -> DynamicImage {
+            DynamicImage::ImageRgb8(RgbImage::from_fn(WIDTH, HEIGHT, |x, y| {
+                Rgb([(x * 16) as u8, (y * 32) as u8, 128])
+            }))
+        }
+
+        #[test]
+        fn color_types_become_rgb8() {
+            let img = fixture();
+            let rgb = img.to_rgb8().into_raw();
+            let images = [
+                ("rgba8", DynamicImage::ImageRgba8(img.to_rgba8()), true),
+                ("luma8", DynamicImage::ImageLuma8(img.to_luma8()), false),
+                (
+                    "lumaa8",
+                    DynamicImage::ImageLumaA8(img.to_luma_alpha8()),
+                    false,
+                ),
+                ("rgb16", DynamicImage::ImageRgb16(img.to_rgb16()), true),
+                ("rgb32f", DynamicImage::ImageRgb32F(img.to_rgb32f()), true),
+            ];
+
+            for (name, img, colored) in images {
+                let buffer = get_image_buffer(img);
+                assert_eq!(buffer.len(), (WIDTH * HEIGHT * 3) as usize, "{name}");
+                if colored {
+                    assert_eq!(buffer, rgb, "{name}");
+                } else {
+                    assert!(
+                        buffer.chunks(3).all(|x| x[0] == x[1] && x[1] == x[2]),
+                        "{name}"
+                    );
+                }
+                assert!(palette::extract(&buffer).is_some(), "{name}");
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::{Duration, Instant};
+
+    const TRACKS: usize = 100_000;
+    const TRACKS_PER_ALBUM: usize = 10;
+    /// Every that many tracks and albums is looked up
+    const STEP: usize = 100;
+
+    /// A library of `TRACKS` tracks in albums of `TRACKS_PER_ALBUM`
+    fn synthetic() -> Media {
+        let mut media = Media::default();
+        for i in 0..TRACKS {
+            let album = i / TRACKS_PER_ALBUM;
+            media.add_song(Track {
+                id: format!("track-{i}"),
+                title: format!("Track {i}"),
+                album: format!("Album {album}"),
+                album_id: format!("album-{album}"),
+                file_path: format!("/music/{album}/{i}.flac"),
+                ..Default::default()
+            });
+        }
+        media
+    }
+
+    /// How long `lookup` takes for each of `ids`, checking it finds them all
+    fn time<T>(ids: &[String], lookup: impl Fn(&str) -> Option<T>) -> Duration {
+        let started = Instant::now();
+        for id in ids {
+            assert!(lookup(id).is_some(), "{id} not found");
+        }
+        started.elapsed()
+    }
+
+    /// Compares the lookups through the indexes of the media with the linear searches they
+    /// replaced. Run with `cargo test --release index_lookups -- --ignored --nocapture`.
+    #[test]
+    #[ignore = "benchmark"]
+    fn index_lookups() {
+        let media = synthetic();
+        let tracks: Vec<String> = (0..TRACKS)
+            .step_by(STEP)
+            .map(|i| format!("track-{i}"))
+            .collect();
+        let albums: Vec<String> = (0..TRACKS / TRACKS_PER_ALBUM)
+            .step_by(STEP)
+            .map(|i| format!("album-{i}"))
+            .collect();
+
+        let timings = [
+            (
+                "get_track",
+                tracks.len(),
+                time(&tracks, |id| {
+                    media.tracks.values().find(|x| x.id == id).cloned()
+                }),
+                time(&tracks, |id| media.get_track(&id.into())),
+            ),
+            (
+                "get_album",
+                albums.len(),
+                time(&albums, |id| {
+                    media.albums.iter().find(|x| x.id == id).cloned()
+                }),
+                time(&albums, |id| media.get_album(&id.into())),
+            ),
+        ];
+
+        println!("{TRACKS} tracks, {TRACKS_PER_ALBUM} per album");
+        println!("{:<16}{:>12}{:>12}", "", "scan", "index");
+        for (name, count, scan, index) in timings {
+            println!(
+                "{:<16}{scan:>12.2?}{index:>12.2?}",
+                format!("{count} {name}")
+            );
+            assert!(
+                index < scan,
+                "{name}: {index:?} with the index, {scan:?} without"
+            );
+        }
+    }
+
+    fn split(value: &str) -> Vec<String> {
+        let separators = lorconf::Library::default().artist_separators.unwrap();
+        split_artists(value, &separators)
+    }
+
+    #[test]
+    fn artist_separators() {
+        assert_eq!(split("Alice feat. Bob"), ["Alice", "Bob"]);
+        assert_eq!(
+            split("Alice FEAT. Bob Ft. Carol"),
+            ["Alice", "Bob", "Carol"]
+        );
+        assert_eq!(split("Alice;Bob ; Carol"), ["Alice", "Bob", "Carol"]);
+        assert_eq!(split("Alice / Bob"), ["Alice", "Bob"]);
+        // Multiple values of an id3v2.4 frame
+        assert_eq!(split("Alice\0Bob"), ["Alice", "Bob"]);
+        assert_eq!(split("Élodie feat. Åsa"), ["Élodie", "Åsa"]);
+    }
+
+    #[test]
+    fn artist_names_holding_a_separator() {
+        // The default separators are spaced for these to stay whole
+        assert_eq!(split("AC/DC"), ["AC/DC"]);
+        assert_eq!(split("Simon & Garfunkel"), ["Simon & Garfunkel"]);
+        assert_eq!(split("Defeat. Band"), ["Defeat. Band"]);
+        assert_eq!(split("AC/DC / Alice"), ["AC/DC", "Alice"]);
+
+        let separators = vec![" & ".to_string(), "/".to_string(), String::new()];
+        assert_eq!(
+            split_artists("Simon & Garfunkel", &separators),
+            ["Simon", "Garfunkel"]
+        );
+        assert_eq!(split_artists("AC/DC", &separators), ["AC", "DC"]);
+    }
+
+    #[test]
+    fn artists_split_once() {
+        assert_eq!(split("Alice; Alice feat. Bob"), ["Alice", "Bob"]);
+        assert_eq!(split(" ; Alice;;"), ["Alice"]);
+        assert!(split("").is_empty());
+    }
+
+    #[test]
+    fn full_and_partial_dates() {
+        assert_eq!(parse_date("1977"), Some("1977".to_string()));
+        assert_eq!(parse_date("1977-05"), Some("1977-05".to_string()));
+        assert_eq!(parse_date("1977-5-25"), Some("1977-05-25".to_string()));
+        assert_eq!(parse_date(" 1977.05.25 "), Some("1977-05-25".to_string()));
+        assert_eq!(parse_date("1977/05"), Some("1977-05".to_string()));
+        assert_eq!(
+            parse_date("2011-03-14T00:00:00"),
+            Some("2011-03-14".to_string())
+        );
+        assert_eq!(
+            parse_date("2011-03-14 12:00"),
+            Some("2011-03-14".to_string())
+        );
+    }
+
+    #[test]
+    fn invalid_dates() {
+        assert_eq!(parse_date(""), None);
+        assert_eq!(parse_date("77"), None);
+        assert_eq!(parse_date("19770"), None);
+        assert_eq!(parse_date("May 1977"), None);
+        assert_eq!(parse_date("abcd-05"), None);
+        // What can't be read after the year is dropped
+        assert_eq!(parse_date("1977-13-01"), Some("1977".to_string()));
+        assert_eq!(parse_date("1977-05-32"), Some("1977-05".to_string()));
+        assert_eq!(parse_date("1977-xx"), Some("1977".to_string()));
+    }
+
+    #[test]
+    fn album_years() {
+        let track = |original: Option<&str>, release: Option<&str>, year: Option<u32>| Track {
+            original_date: original.map(|x| x.to_string()),
+            release_date: release.map(|x| x.to_string()),
+            album_year: year,
+            ..Default::default()
+        };
+        // The first release wins over the reissue
+        assert_eq!(
+            album_year(&[track(Some("1969-09"), Some("2019-09-27"), Some(2019))]),
+            Some(1969)
+        );
+        assert_eq!(album_year(&[track(None, Some("1971"), None)]), Some(1971));
+        assert_eq!(album_year(&[track(None, None, Some(1985))]), Some(1985));
+        assert_eq!(album_year(&[track(None, None, None)]), None);
+        assert_eq!(album_year(&[]), None);
+
+        // Most of the tracks, then the earliest
+        let tracks = [
+            track(Some("1990"), None, None),
+            track(Some("1992"), None, None),
+            track(Some("1992-02"), None, None),
+            track(None, None, None),
+        ];
+        assert_eq!(album_year(&tracks), Some(1992));
+        assert_eq!(album_year(&tracks[..2]), Some(1990));
+    }
+
+    /// A wave file of `samples`, its tags in a `LIST` chunk of `tags` bytes before the samples
+    fn wav(tags: usize, samples: &[u8]) -> Vec<u8> {
+        let mut chunks = vec![];
+        chunks.extend(b"fmt ");
+        chunks.extend(16u32.to_le_bytes());
+        chunks.extend([1, 0, 1, 0, 0x44, 0xac, 0, 0, 0x44, 0xac, 0, 0, 1, 0, 8, 0]);
+        chunks.extend(b"LIST");
+        chunks.extend((tags as u32).to_le_bytes());
+        chunks.extend(vec![b'x'; tags]);
+        chunks.extend(b"data");
+        chunks.extend((samples.len() as u32).to_le_bytes());
+        chunks.extend(samples);
+
+        let mut file = b"RIFF".to_vec();
+        file.extend((chunks.len() as u32 + 4).to_le_bytes());
+        file.extend(b"WAVE");
+        file.extend(chunks);
+        file
+    }
+
+    #[test]
+    fn track_ids_survive_retagging() {
+        let root = std::env::temp_dir().join(format!("lorchestre-{}", uuid::Uuid::new_v4()));
+        fs::create_dir_all(&root).unwrap();
+        let file = root.join("track.wav");
+        let path = file.to_string_lossy().to_string();
+        let id = |tags, samples: &[u8], recording| {
+            fs::write(&file, wav(tags, samples)).unwrap();
+            track_id(&path, &file, recording)
+        };
+
+        let tagged = id(32, &[1; 1024], None);
+        assert_eq!(id(4096, &[1; 1024], None), tagged);
+        // Same place, other audio
+        assert_ne!(id(32, &[2; 1024], None), tagged);
+        let recording = Some("8f3471b5-7e6a-48da-86a9-c1c07a0f47ae");
+        assert_eq!(id(32, &[1; 1024], recording), id(32, &[2; 1024], recording));
+
+        fs::remove_dir_all(root).unwrap();
+    }
 }
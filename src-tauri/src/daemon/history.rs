@@ -0,0 +1,156 @@
+use crate::daemon::global::Media;
+use crate::daemon::store;
+use mu_protocol::api::{QualityQuery, Recent};
+use mu_protocol::library::{Album, Track};
+use std::cmp::Reverse;
+use std::collections::{HashMap, HashSet};
+use std::path::PathBuf;
+use std::time::SystemTime;
+
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone)]
+pub struct Play {
+    pub track: String,
+    pub played_at: SystemTime,
+}
+
+/// Every track listened to the end, oldest first
+#[derive(Debug, Default)]
+pub struct History {
+    path: PathBuf,
+    plays: Vec<Play>,
+}
+
+impl History {
+    pub fn load(path: PathBuf) -> Self {
+        let plays = store::load(&path, "the play history");
+
+        Self { path, plays }
+    }
+
+    fn save(&self) {
+        store::save(&self.path, "the play history", &self.plays);
+    }
+
+    pub fn record(&mut self, track_id: &str) {
+        self.plays.push(Play {
+            track: track_id.to_string(),
+            played_at: SystemTime::now(),
+        });
+        self.save();
+    }
+
+    pub fn plays(&self) -> usize {
+        self.plays.len()
+    }
+
+    pub fn list(&self) -> &[Play] {
+        &self.plays
+    }
+
+    /// Merges plays coming from another player, keeping the history sorted. The plays of a track
+    /// at a time already in the history are left out, so importing twice adds nothing the second
+    /// time. Returns how many were added.
+    pub fn import(&mut self, plays: Vec<Play>) -> usize {
+        let mut known: HashMap<(String, SystemTime), usize> = HashMap::new();
+        for play in &self.plays {
+            *known
+                .entry((play.track.clone(), play.played_at))
+                .or_default() += 1;
+        }
+
+        let mut added = 0;
+        for play in plays {
+            match known.get_mut(&(play.track.clone(), play.played_at)) {
+                Some(count) if *count > 0 => *count -= 1,
+                _ => {
+                    self.plays.push(play);
+                    added += 1;
+                }
+            }
+        }
+        self.plays.sort_by_key(|x| x.played_at);
+        self.save();
+
+        added
+    }
+
+    /// Replaces the track ids found in `ids`, returns whether the history changed
+    pub fn remap_tracks(&mut self, ids: &HashMap<String, String>) -> bool {
+        let mut changed = false;
+        for play in &mut self.plays {
+            if let Some(id) = ids.get(&play.track) {
+                play.track = id.clone();
+                changed = true;
+            }
+        }
+        if changed {
+            self.save();
+        }
+
+        changed
+    }
+
+    /// Distinct tracks and albums, most recently played first
+    pub fn recently_played(&self, media: &Media, limit: usize, quality: &QualityQuery) -> Recent {
+        let mut seen_tracks = HashSet::new();
+        let mut seen_albums = HashSet::new();
+        let mut recent = Recent::default();
+
+        for play in self.plays.iter().rev() {
+            if !seen_tracks.insert(play.track.as_str()) {
+                continue;
+            }
+            let Some(track) = media.get_track(&play.track.as_str().into()) else {
+                continue;
+            };
+
+            if recent.albums.len() < limit && seen_albums.insert(track.album_id.clone()) {
+                if let Some(album) = media.get_album(&track.album_id.as_str().into()) {
+                    if quality.keeps_album(&album) {
+                        recent.albums.push(album);
+                    }
+                }
+            }
+            if recent.tracks.len() < limit && quality.keeps(&track) {
+                recent.tracks.push(track);
+            }
+            if recent.tracks.len() >= limit && recent.albums.len() >= limit {
+                break;
+            }
+        }
+
+        recent
+    }
+}
+
+/// Tracks and albums sorted by the time their files were added, newest first
+pub fn recently_added(media: &Media, limit: usize, quality: &QualityQuery) -> Recent {
+    let mut tracks: Vec<&Track> = media.tracks.values().filter(|x| quality.keeps(x)).collect();
+    tracks.sort_by_key(|x| Reverse(x.created_at));
+
+    let mut albums: Vec<(SystemTime, &Album)> = media
+        .albums
+        .iter()
+        .filter(|x| quality.keeps_album(x))
+        .map(|album| {
+            let added = album
+                .tracks
+                .iter()
+                .filter_map(|x| media.tracks.get(x))
+                .map(|x| x.created_at)
+                .max()
+                .unwrap_or(SystemTime::UNIX_EPOCH);
+            (added, album)
+        })
+        .collect();
+    albums.sort_by_key(|x| Reverse(x.0));
+
+    Recent {
+        albums: albums
+            .into_iter()
+            .take(limit)
+            .map(|(_, x)| x.clone())
+            .collect(),
+        tracks: tracks.into_iter().take(limit).cloned().collect(),
+    }
+}
@@ -0,0 +1,279 @@
+use crate::daemon::config::SharedConfig;
+use mu_protocol::library::Track;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::process::Stdio;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::process::{Child, Command};
+use tokio::sync::Mutex;
+use tracing::{info, warn};
+
+/// Target length of a single HLS segment, in seconds
+pub const SEGMENT_DURATION: u64 = 6;
+/// How far ahead of the encoder a client can ask before we restart it at the requested segment
+const MAX_SEGMENT_LOOKAHEAD: u64 = 3;
+const SEGMENT_WAIT_TIMEOUT: Duration = Duration::from_secs(30);
+/// Interval at which sessions are checked for inactivity
+const REAP_INTERVAL: Duration = Duration::from_secs(10);
+
+#[derive(Debug)]
+struct HlsSession {
+    dir: PathBuf,
+    encoder: Option<Child>,
+    start_segment: u64,
+    /// Last time a segment of this session was requested
+    last_access: Instant,
+}
+
+#[derive(Debug, Default)]
+pub struct HlsSessions {
+    sessions: Mutex<HashMap<String, HlsSession>>,
+}
+
+/// `track` without the silences at its ends, which the stream then skips as it does the rest of
+/// the file of a track split by a cue sheet
+pub fn trimmed(track: &Track) -> Track {
+    let (Some(start), Some(end)) = (track.trim_start_ms, track.trim_end_ms) else {
+        return track.clone();
+    };
+    let length = track
+        .end
+        .map_or(track.duration * 1000, |x| x - track.start.unwrap_or(0));
+    // A silent track is streamed as is
+    if start + end >= length {
+        return track.clone();
+    }
+
+    let offset = track.start.unwrap_or(0);
+    Track {
+        start: Some(offset + start),
+        end: Some(offset + length - end),
+        duration: (length - start - end) / 1000,
+        ..track.clone()
+    }
+}
+
+pub fn segments_count(track: &Track) -> u64 {
+    track.duration.div_ceil(SEGMENT_DURATION).max(1)
+}
+
+pub fn playlist(track: &Track) -> String {
+    let mut lines = vec![
+        "#EXTM3U".to_string(),
+        "#EXT-X-VERSION:3".to_string(),
+        format!("#EXT-X-TARGETDURATION:{SEGMENT_DURATION}"),
+        "#EXT-X-MEDIA-SEQUENCE:0".to_string(),
+        "#EXT-X-PLAYLIST-TYPE:VOD".to_string(),
+    ];
+
+    let count = segments_count(track);
+    for n in 0..count {
+        let length = if n == count - 1 && track.duration > 0 {
+            track.duration - n * SEGMENT_DURATION
+        } else {
+            SEGMENT_DURATION
+        };
+        lines.push(format!("#EXTINF:{length}.000,"));
+        lines.push(format!("segment{n}.ts"));
+    }
+    lines.push("#EXT-X-ENDLIST".to_string());
+
+    lines.join("\n")
+}
+
+/// Parses `segment<N>.ts` into `N`
+pub fn parse_segment_name(name: &str) -> Option<u64> {
+    name.strip_prefix("segment")?
+        .strip_suffix(".ts")?
+        .parse()
+        .ok()
+}
+
+/// A track streamed with and without its silences has a session for each
+fn session_key(track: &Track) -> String {
+    match (track.start, track.end) {
+        (None, None) => format!("{:x}", md5::compute(&track.file_path)),
+        (start, end) => {
+            let part = format!("{}:{start:?}:{end:?}", track.file_path);
+            format!("{:x}", md5::compute(part))
+        }
+    }
+}
+
+fn segment_path(dir: &Path, n: u64) -> PathBuf {
+    dir.join(format!("segment{n}.ts"))
+}
+
+/// Tracks already encoded in a codec HLS can carry are only remuxed
+fn codec_args(track: &Track) -> Vec<&'static str> {
+    match track.mime.as_str() {
+        "audio/mpeg" | "audio/aac" => vec!["-c:a", "copy"],
+        _ => vec!["-c:a", "aac", "-b:a", "192k"],
+    }
+}
+
+fn spawn_encoder(track: &Track, dir: &Path, start_segment: u64) -> std::io::Result<Child> {
+    let start = (start_segment * SEGMENT_DURATION).to_string();
+    info!(
+        "hls: encoding `{}` from segment {start_segment}",
+        track.file_path
+    );
+
+    // Tracks split by a cue sheet are encoded from their part of the file only
+    let offset = track.start.unwrap_or(0) + start_segment * SEGMENT_DURATION * 1000;
+    let mut command = Command::new("ffmpeg");
+    command
+        .args(["-nostdin", "-loglevel", "error"])
+        .args(["-ss", &format!("{:.3}", offset as f64 / 1000.0), "-i"])
+        .arg(track.audio_file());
+    if let Some(end) = track.end {
+        let length = end.saturating_sub(offset) as f64 / 1000.0;
+        command.args(["-t", &format!("{length:.3}")]);
+    }
+
+    command
+        .args(["-vn", "-map", "0:a:0"])
+        .args(codec_args(track))
+        .args(["-output_ts_offset", &start])
+        .args(["-f", "hls", "-hls_time", &SEGMENT_DURATION.to_string()])
+        .args(["-hls_playlist_type", "vod", "-hls_flags", "temp_file"])
+        .args(["-start_number", &start_segment.to_string()])
+        .arg("-hls_segment_filename")
+        .arg(dir.join("segment%d.ts"))
+        .arg(dir.join("encoder.m3u8"))
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .kill_on_drop(true)
+        .spawn()
+}
+
+/// Segments being written by ffmpeg have a `.tmp` extension until they are complete
+fn remove_partial_segments(dir: &Path) {
+    let Ok(entries) = std::fs::read_dir(dir) else {
+        return;
+    };
+    for path in entries.flatten().map(|x| x.path()) {
+        if path.extension().is_some_and(|x| x == "tmp") {
+            if let Err(e) = std::fs::remove_file(&path) {
+                warn!("hls: unable to remove `{}`: {e}", path.display());
+            }
+        }
+    }
+}
+
+/// Periodically reaps the sessions idle for longer than `library.transcode_idle_timeout`
+pub async fn reap(sessions: Arc<HlsSessions>, config: SharedConfig) {
+    loop {
+        tokio::time::sleep(REAP_INTERVAL).await;
+        let idle_timeout = config
+            .read()
+            .await
+            .library
+            .as_ref()
+            .and_then(|library| library.transcode_idle_timeout)
+            .unwrap_or(60);
+        sessions.reap_idle(Duration::from_secs(idle_timeout)).await;
+    }
+}
+
+impl HlsSessions {
+    /// Drops the sessions without segment requests for `idle_timeout`: their encoder is killed
+    /// and the segment it was writing removed.
+    pub async fn reap_idle(&self, idle_timeout: Duration) {
+        let mut sessions = self.sessions.lock().await;
+        let idle: Vec<String> = sessions
+            .iter()
+            .filter(|(_, x)| x.last_access.elapsed() >= idle_timeout)
+            .map(|(key, _)| key.clone())
+            .collect();
+
+        for key in idle {
+            let Some(mut session) = sessions.remove(&key) else {
+                continue;
+            };
+            if let Some(mut encoder) = session.encoder.take() {
+                if encoder.try_wait().ok().flatten().is_none() {
+                    info!(
+                        "hls: stopping the idle encoder of `{}`",
+                        session.dir.display()
+                    );
+                }
+                let _ = encoder.kill().await;
+            }
+            remove_partial_segments(&session.dir);
+        }
+    }
+
+    /// Kills every running encoder
+    pub async fn stop(&self) {
+        let mut sessions = self.sessions.lock().await;
+        for (_, mut session) in sessions.drain() {
+            if let Some(mut encoder) = session.encoder.take() {
+                let _ = encoder.kill().await;
+            }
+        }
+    }
+
+    /// Returns the path of the requested segment, starting or restarting the encoder if needed
+    /// and waiting for the segment to be fully written.
+    pub async fn segment(
+        &self,
+        cache_dir: &Path,
+        track: &Track,
+        n: u64,
+    ) -> std::io::Result<Option<PathBuf>> {
+        if n >= segments_count(track) {
+            return Ok(None);
+        }
+
+        let dir = cache_dir.join("hls").join(session_key(track));
+        let path = segment_path(&dir, n);
+        if path.exists() {
+            if let Some(session) = self.sessions.lock().await.get_mut(&session_key(track)) {
+                session.last_access = Instant::now();
+            }
+            return Ok(Some(path));
+        }
+
+        {
+            let mut sessions = self.sessions.lock().await;
+            let session = sessions
+                .entry(session_key(track))
+                .or_insert_with(|| HlsSession {
+                    dir: dir.clone(),
+                    encoder: None,
+                    start_segment: n,
+                    last_access: Instant::now(),
+                });
+            session.last_access = Instant::now();
+
+            let produced = (session.start_segment..)
+                .take_while(|s| segment_path(&session.dir, *s).exists())
+                .last()
+                .unwrap_or(session.start_segment);
+
+            let encoder_done = match session.encoder.as_mut() {
+                Some(child) => child.try_wait()?.is_some(),
+                None => true,
+            };
+
+            if encoder_done || n < session.start_segment || n > produced + MAX_SEGMENT_LOOKAHEAD {
+                std::fs::DirBuilder::new().recursive(true).create(&dir)?;
+                session.encoder = Some(spawn_encoder(track, &dir, n)?);
+                session.start_segment = n;
+            }
+        }
+
+        let started = std::time::Instant::now();
+        while started.elapsed() < SEGMENT_WAIT_TIMEOUT {
+            if path.exists() {
+                return Ok(Some(path));
+            }
+            tokio::time::sleep(Duration::from_millis(100)).await;
+        }
+
+        warn!("hls: timed out waiting for `{}`", path.display());
+        Ok(None)
+    }
+}
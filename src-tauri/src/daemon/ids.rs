@@ -0,0 +1,136 @@
+//! Ids of the tracks, albums and playlists. They are the hexadecimal md5 digests the scan gives
+//! them, a track may also still be named by the url safe base64 encoding of its path, its id
+//! before. Taken from the `id` of the path of a request, a malformed one is answered with a
+//! `400 Bad Request` and an [`InvalidId`] instead of a lookup bound to fail.
+
+use axum::async_trait;
+use axum::extract::{FromRequestParts, RawPathParams};
+use axum::http::request::Parts;
+use axum::http::StatusCode;
+use axum::response::{IntoResponse, Response};
+use axum::Json;
+use base64::{engine::general_purpose::URL_SAFE, Engine as _};
+use mu_protocol::api::{IdKind, InvalidId};
+use std::fmt;
+use std::ops::Deref;
+
+/// Length of the ids, the digits of an md5 digest
+const LENGTH: usize = 32;
+
+/// Why `id` isn't a digest given by the scan
+fn check_digest(id: &str) -> Result<(), String> {
+    if id.len() != LENGTH {
+        return Err(format!(
+            "must be {LENGTH} characters long, not {}",
+            id.len()
+        ));
+    }
+    if !id.bytes().all(|x| matches!(x, b'0'..=b'9' | b'a'..=b'f')) {
+        return Err("must only have lowercase hexadecimal digits".to_string());
+    }
+    Ok(())
+}
+
+/// Why `id` is neither a digest nor the encoding of a path
+fn check_track(id: &str) -> Result<(), String> {
+    let Err(e) = check_digest(id) else {
+        return Ok(());
+    };
+    match URL_SAFE.decode(id) {
+        Ok(path) if !path.is_empty() && std::str::from_utf8(&path).is_ok() => Ok(()),
+        _ => Err(format!("{e}, or be the url safe base64 of a path")),
+    }
+}
+
+macro_rules! id {
+    ($(#[$doc:meta])* $name:ident, $kind:expr, $check:ident) => {
+        $(#[$doc])*
+        #[derive(Debug, Clone, PartialEq, Eq, Hash)]
+        pub struct $name(String);
+
+        impl $name {
+            /// Checks `id`, given by a client
+            pub fn parse(id: &str) -> Result<Self, InvalidId> {
+                match $check(id) {
+                    Ok(()) => Ok(Self(id.to_string())),
+                    Err(reason) => Err(InvalidId {
+                        kind: $kind,
+                        id: id.to_string(),
+                        reason,
+                    }),
+                }
+            }
+
+            pub fn as_str(&self) -> &str {
+                &self.0
+            }
+        }
+
+        /// An id the daemon gave, e.g. kept in a track, taken as is
+        impl From<&str> for $name {
+            fn from(id: &str) -> Self {
+                Self(id.to_string())
+            }
+        }
+
+        impl From<String> for $name {
+            fn from(id: String) -> Self {
+                Self(id)
+            }
+        }
+
+        impl From<$name> for String {
+            fn from(id: $name) -> Self {
+                id.0
+            }
+        }
+
+        impl Deref for $name {
+            type Target = str;
+
+            fn deref(&self) -> &str {
+                &self.0
+            }
+        }
+
+        impl fmt::Display for $name {
+            fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                f.write_str(&self.0)
+            }
+        }
+
+        #[async_trait]
+        impl<S: Send + Sync> FromRequestParts<S> for $name {
+            type Rejection = Response;
+
+            async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Response> {
+                let params = RawPathParams::from_request_parts(parts, state)
+                    .await
+                    .map_err(IntoResponse::into_response)?;
+                let Some((_, id)) = params.iter().find(|(name, _)| *name == "id") else {
+                    return Err(StatusCode::INTERNAL_SERVER_ERROR.into_response());
+                };
+                Self::parse(id).map_err(|e| (StatusCode::BAD_REQUEST, Json(e)).into_response())
+            }
+        }
+    };
+}
+
+id!(
+    /// Id of a track, `Track::id`
+    TrackId,
+    IdKind::Track,
+    check_track
+);
+id!(
+    /// Id of an album, `Album::id`
+    AlbumId,
+    IdKind::Album,
+    check_digest
+);
+id!(
+    /// Id of a playlist, `Playlist::id`
+    PlaylistId,
+    IdKind::Playlist,
+    check_digest
+);
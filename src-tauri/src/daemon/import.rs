@@ -0,0 +1,342 @@
+use crate::daemon::config;
+use crate::daemon::favorites::Favorites;
+use crate::daemon::global::{Media, ScanOptions};
+use crate::daemon::history::{History, Play};
+use crate::daemon::libraries;
+use crate::daemon::listen;
+use crate::daemon::playlist::PlaylistFormat;
+use crate::daemon::scan::Progress;
+use crate::daemon::utils;
+use std::cmp::Reverse;
+use std::collections::HashMap;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+use tokio_util::sync::CancellationToken;
+use tracing::{info, warn};
+
+#[derive(clap::ValueEnum, Debug, Clone, Copy)]
+pub enum Source {
+    /// A navidrome.db sqlite database
+    Navidrome,
+    /// The json returned by `/Users/{id}/Items?Recursive=true&IncludeItemTypes=Audio&Fields=Path`
+    Jellyfin,
+}
+
+#[derive(Debug, Default)]
+struct Imported {
+    /// Track path, play count and last play
+    plays: Vec<(String, u32, Option<SystemTime>)>,
+    favorites: Vec<String>,
+    playlists: Vec<(String, Vec<String>)>,
+}
+
+/// Parses `YYYY-MM-DD[ T]HH:MM:SS[.fff][Z|±HH:MM]` as used by both servers
+fn parse_datetime(value: &str) -> Option<SystemTime> {
+    let num = |s: &str| s.parse::<i64>().ok();
+    let (y, m, d) = (
+        num(value.get(0..4)?)?,
+        num(value.get(5..7)?)?,
+        num(value.get(8..10)?)?,
+    );
+    let (hh, mm, ss) = (
+        num(value.get(11..13)?)?,
+        num(value.get(14..16)?)?,
+        num(value.get(17..19)?)?,
+    );
+
+    // Days since the epoch of a proleptic gregorian date
+    let y = if m <= 2 { y - 1 } else { y };
+    let era = y.div_euclid(400);
+    let yoe = y - era * 400;
+    let doy = (153 * (m + if m > 2 { -3 } else { 9 }) + 2) / 5 + d - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    let days = era * 146097 + doe - 719468;
+
+    let mut secs = days * 86400 + hh * 3600 + mm * 60 + ss;
+    let rest = value[19..].trim_start_matches(|c: char| c == '.' || c.is_ascii_digit());
+    if let Some(sign) = rest.chars().next().filter(|c| *c == '+' || *c == '-') {
+        let offset = num(rest.get(1..3)?)? * 3600 + num(rest.get(4..6)?)? * 60;
+        secs -= if sign == '+' { offset } else { -offset };
+    }
+
+    u64::try_from(secs)
+        .ok()
+        .map(|secs| SystemTime::UNIX_EPOCH + Duration::from_secs(secs))
+}
+
+fn navidrome(db: &Path, user: Option<&str>) -> rusqlite::Result<Imported> {
+    let conn =
+        rusqlite::Connection::open_with_flags(db, rusqlite::OpenFlags::SQLITE_OPEN_READ_ONLY)?;
+    let mut imported = Imported::default();
+
+    let user_id: Option<String> = match user {
+        Some(user) => Some(conn.query_row(
+            "SELECT id FROM user WHERE user_name = ?1",
+            [user],
+            |row| row.get(0),
+        )?),
+        None => None,
+    };
+
+    let mut stmt = conn.prepare(
+        "SELECT mf.path, a.play_count, a.play_date, a.starred FROM annotation a \
+         JOIN media_file mf ON mf.id = a.item_id \
+         WHERE a.item_type = 'media_file' AND (?1 IS NULL OR a.user_id = ?1)",
+    )?;
+    let rows = stmt.query_map([&user_id], |row| {
+        Ok((
+            row.get::<_, String>(0)?,
+            row.get::<_, Option<u32>>(1)?.unwrap_or(0),
+            row.get::<_, Option<String>>(2)?,
+            row.get::<_, Option<bool>>(3)?.unwrap_or(false),
+        ))
+    })?;
+    for row in rows {
+        let (path, play_count, play_date, starred) = row?;
+        if play_count > 0 {
+            let last = play_date.as_deref().and_then(parse_datetime);
+            imported.plays.push((path.clone(), play_count, last));
+        }
+        if starred {
+            imported.favorites.push(path);
+        }
+    }
+
+    let mut stmt = conn.prepare(
+        "SELECT p.name, mf.path FROM playlist p \
+         JOIN playlist_tracks pt ON pt.playlist_id = p.id \
+         JOIN media_file mf ON mf.id = pt.media_file_id \
+         WHERE ?1 IS NULL OR p.owner_id = ?1 \
+         ORDER BY p.name, CAST(pt.id AS INTEGER)",
+    )?;
+    let rows = stmt.query_map([&user_id], |row| {
+        Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?))
+    })?;
+    for row in rows {
+        let (name, path) = row?;
+        match imported.playlists.last_mut() {
+            Some((last, tracks)) if *last == name => tracks.push(path),
+            _ => imported.playlists.push((name, vec![path])),
+        }
+    }
+
+    Ok(imported)
+}
+
+#[derive(serde::Deserialize, Debug)]
+#[serde(rename_all = "PascalCase")]
+struct JellyfinItems {
+    items: Vec<JellyfinItem>,
+}
+
+#[derive(serde::Deserialize, Debug)]
+#[serde(rename_all = "PascalCase")]
+struct JellyfinItem {
+    path: Option<String>,
+    user_data: Option<JellyfinUserData>,
+}
+
+#[derive(serde::Deserialize, Debug)]
+#[serde(rename_all = "PascalCase")]
+struct JellyfinUserData {
+    #[serde(default)]
+    play_count: u32,
+    #[serde(default)]
+    is_favorite: bool,
+    last_played_date: Option<String>,
+}
+
+/// Reads the `<Path>` entries of a jellyfin `playlist.xml`
+fn jellyfin_playlist(xml: &str) -> Vec<String> {
+    xml.split("<Path>")
+        .skip(1)
+        .filter_map(|x| x.split_once("</Path>"))
+        .map(|(path, _)| {
+            path.replace("&amp;", "&")
+                .replace("&lt;", "<")
+                .replace("&gt;", ">")
+                .replace("&quot;", "\"")
+                .replace("&apos;", "'")
+        })
+        .collect()
+}
+
+fn jellyfin(
+    export: &Path,
+    playlists_dir: Option<&Path>,
+) -> Result<Imported, Box<dyn std::error::Error>> {
+    let items: JellyfinItems = serde_json::from_str(&std::fs::read_to_string(export)?)?;
+    let mut imported = Imported::default();
+
+    for item in items.items {
+        let (Some(path), Some(data)) = (item.path, item.user_data) else {
+            continue;
+        };
+        if data.play_count > 0 {
+            let last = data.last_played_date.as_deref().and_then(parse_datetime);
+            imported.plays.push((path.clone(), data.play_count, last));
+        }
+        if data.is_favorite {
+            imported.favorites.push(path);
+        }
+    }
+
+    if let Some(dir) = playlists_dir {
+        for entry in std::fs::read_dir(dir)?.flatten() {
+            let xml = entry.path().join("playlist.xml");
+            if let Ok(xml) = std::fs::read_to_string(&xml) {
+                let name = entry.file_name().to_string_lossy().to_string();
+                imported.playlists.push((name, jellyfin_playlist(&xml)));
+            }
+        }
+    }
+
+    Ok(imported)
+}
+
+/// Rewrites the start of `path` according to the first matching prefix of `remap`
+fn remap_path(path: &str, remap: &[(String, String)]) -> PathBuf {
+    let path = path.replace('\\', "/");
+    for (from, to) in remap {
+        if let Some(rest) = path.strip_prefix(from.as_str()) {
+            return PathBuf::from(format!("{to}{rest}"));
+        }
+    }
+    PathBuf::from(path)
+}
+
+/// Imports play counts, favorites and playlists from another server into the local library.
+/// `remap` is a json object mapping path prefixes of the other server to local ones.
+pub async fn run(
+    source: Source,
+    path: PathBuf,
+    remap: Option<PathBuf>,
+    user: Option<String>,
+    playlists: Option<PathBuf>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let remap: Vec<(String, String)> = match remap {
+        Some(remap) => {
+            let table: HashMap<String, String> =
+                serde_json::from_str(&std::fs::read_to_string(remap)?)?;
+            let mut table: Vec<(String, String)> = table.into_iter().collect();
+            // Longest prefixes first so nested folders win over their parents
+            table.sort_by_key(|x| Reverse(x.0.len()));
+            table
+        }
+        None => vec![],
+    };
+
+    let dirs = config::get_dirs();
+    let config = lorconf::Config::get(&dirs.config.join("config.toml"));
+    // The daemon would write its own history and favorites over the imported ones
+    let defaults = lorconf::Network::default();
+    let network = config.network.clone().unwrap_or_default();
+    let host = network.host.or(defaults.host).unwrap_or_default();
+    if let Some(port) = network
+        .port
+        .or(defaults.port)
+        .and_then(|x| u16::try_from(x).ok())
+    {
+        let mut addresses = listen::resolve(&host, port).await;
+        addresses.extend(listen::extra(&config).await);
+        if listen::taken(&addresses).await {
+            return Err("the daemon is running, stop it before importing".into());
+        }
+    }
+
+    let imported = match source {
+        Source::Navidrome => navidrome(&path, user.as_deref())?,
+        Source::Jellyfin => jellyfin(&path, playlists.as_deref())?,
+    };
+
+    // Histories are imported for the default library
+    let library = libraries::profiles(&config, &dirs.cache).remove(0);
+    let media: Media = utils::cache_resolve(
+        &library.cache_dir,
+        &library.paths,
+        &ScanOptions::from_config(&config),
+        &CancellationToken::new(),
+        &Progress::default(),
+    )
+    .await
+    .unwrap_or_default();
+    let resolve = |path: &str| -> Option<String> {
+        let local = remap_path(path, &remap);
+        match media.tracks.get(&local) {
+            Some(track) => Some(track.id.clone()),
+            None => {
+                warn!("import: no local track for `{path}`");
+                None
+            }
+        }
+    };
+
+    let mut plays = vec![];
+    for (path, count, last) in &imported.plays {
+        if let Some(id) = resolve(path) {
+            for _ in 0..*count {
+                plays.push(Play {
+                    track: id.clone(),
+                    played_at: last.unwrap_or(SystemTime::UNIX_EPOCH),
+                });
+            }
+        }
+    }
+    let plays_count = History::load(dirs.app.join("history.json")).import(plays);
+
+    let favorites: Vec<String> = imported
+        .favorites
+        .iter()
+        .filter_map(|x| resolve(x))
+        .collect();
+    let favorites_count = Favorites::load(dirs.app.join("favorites.json")).extend(favorites);
+
+    let mut playlists_count = 0;
+    if let Some(audio_dir) = dirs::audio_dir() {
+        let playlists_dir = audio_dir.join("Playlists");
+        std::fs::DirBuilder::new()
+            .recursive(true)
+            .create(&playlists_dir)?;
+
+        for (name, tracks) in &imported.playlists {
+            let tracks: Vec<PathBuf> = tracks
+                .iter()
+                .map(|x| remap_path(x, &remap))
+                .filter(|x| media.tracks.contains_key(x))
+                .collect();
+            let file_name: String = name
+                .chars()
+                .map(|c| if "/\\:*?\"<>|".contains(c) { '_' } else { c })
+                .collect();
+
+            let format = PlaylistFormat::M3u8;
+            let ext = format.extension();
+            let content = format.render(name, None, &tracks);
+            let mut playlist_path = playlists_dir.join(format!("{file_name}.{ext}"));
+            let mut n = 1;
+            let mut imported_before = false;
+            while playlist_path.exists() {
+                if std::fs::read_to_string(&playlist_path).is_ok_and(|x| x == content) {
+                    imported_before = true;
+                    break;
+                }
+                playlist_path = playlists_dir.join(format!("{file_name} ({n}).{ext}"));
+                n += 1;
+            }
+            if imported_before {
+                continue;
+            }
+
+            let mut f = std::fs::File::create(&playlist_path)?;
+            f.write_all(content.as_bytes())?;
+            playlists_count += 1;
+        }
+    }
+
+    info!(
+        "import: {plays_count} plays, {favorites_count} favorites and {playlists_count} playlists imported"
+    );
+
+    Ok(())
+}
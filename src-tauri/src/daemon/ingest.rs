@@ -0,0 +1,621 @@
+//! Drop folder of the default library, `library.import_path`: the audio files copied there get
+//! their missing tags filled from their file and folder names, or looked up on MusicBrainz with
+//! `library.import_musicbrainz`, then are moved to its first folder per the organize pattern
+//! and added to it, as a job. Files that can't be imported go to `.rejected/` in the folder.
+
+use crate::daemon::config::{SharedConfig, VERSION};
+use crate::daemon::global::utils::{
+    cache_audio_files, find_folder_cover, get_audio_files, read_cache_audio_files,
+};
+use crate::daemon::global::{read_track, ScanOptions};
+use crate::daemon::jobs::{JobContext, Jobs};
+use crate::daemon::libraries::Library;
+use crate::daemon::organize::{self, Part};
+use crate::daemon::playlist::PlaylistFormat;
+use crate::daemon::reconcile::Fingerprints;
+use crate::daemon::tags;
+use crate::daemon::utils;
+use crate::daemon::walk::WalkOptions;
+use lofty::prelude::*;
+use lofty::tag::Tag;
+use mu_protocol::api::JobKind;
+use mu_protocol::library::Track;
+use socketioxide::SocketIo;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime};
+use tracing::{info, warn};
+
+/// Files modified more recently may still be being copied
+const SETTLE: Duration = Duration::from_secs(10);
+
+/// Folder of the drop folder the files that can't be imported are moved to
+const REJECTED: &str = ".rejected";
+
+const MUSICBRAINZ: &str = "https://musicbrainz.org/ws/2/recording";
+
+/// Matches scored lower by MusicBrainz are ignored
+const MIN_SCORE: u32 = 90;
+
+/// MusicBrainz allows one request per second
+const LOOKUP_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Value of the tags left unset by the scan
+const UNKNOWN: &str = "@UNKNOWN@";
+
+/// `library.import_path`, `None` when the import is off
+fn import_path(config: &lorconf::Config) -> Option<PathBuf> {
+    config
+        .library
+        .as_ref()
+        .and_then(|library| library.import_path.clone())
+        .or(lorconf::Library::default().import_path)
+}
+
+fn interval(config: &lorconf::Config) -> Duration {
+    let seconds = config
+        .library
+        .as_ref()
+        .and_then(|library| library.import_interval)
+        .or(lorconf::Library::default().import_interval)
+        .unwrap_or_default();
+    Duration::from_secs(seconds.max(1))
+}
+
+fn musicbrainz(config: &lorconf::Config) -> bool {
+    config
+        .library
+        .as_ref()
+        .and_then(|library| library.import_musicbrainz)
+        .or(lorconf::Library::default().import_musicbrainz)
+        .unwrap_or_default()
+}
+
+/// Audio files of `dir` done being copied, outside of its hidden folders and of the ones its
+/// `.muignore` files leave out. `library.exclude` is about the library, not the drop folder.
+fn pending(dir: &Path) -> Vec<PathBuf> {
+    let now = SystemTime::now();
+    let options = WalkOptions {
+        exclude: vec![],
+        follow_symlinks: true,
+    };
+    let mut files: Vec<PathBuf> = get_audio_files(&[dir.to_path_buf()], &options)
+        .0
+        .into_iter()
+        .filter(|x| PlaylistFormat::from_path(x).is_none())
+        .filter(|x| {
+            std::fs::metadata(x)
+                .and_then(|x| x.modified())
+                .is_ok_and(|modified| now.duration_since(modified).unwrap_or_default() >= SETTLE)
+        })
+        .collect();
+    files.sort();
+    files
+}
+
+fn known(value: &str) -> Option<&str> {
+    Some(value.trim()).filter(|x| !x.is_empty() && *x != UNKNOWN)
+}
+
+/// Tags an imported file lacks
+#[derive(Debug, Default, Clone, Copy)]
+struct Lacks {
+    title: bool,
+    artist: bool,
+    album: bool,
+    album_artist: bool,
+    track: bool,
+    date: bool,
+}
+
+impl Lacks {
+    fn of(track: &Track) -> Self {
+        let none = |values: &[String]| values.iter().all(|x| known(x).is_none());
+        Self {
+            title: track.title_inferred || known(&track.title).is_none(),
+            artist: none(&track.artists),
+            album: track.album_guessed || known(&track.album).is_none(),
+            album_artist: none(&track.album_artists),
+            track: track.track_inferred || track.track == 0,
+            date: track.release_date.is_none() && track.original_date.is_none(),
+        }
+    }
+}
+
+/// Tags written to an imported file
+#[derive(Debug, Default)]
+struct Fixes {
+    title: Option<String>,
+    artist: Option<String>,
+    album: Option<String>,
+    album_artist: Option<String>,
+    track: Option<u32>,
+    date: Option<String>,
+    recording_id: Option<String>,
+    release_id: Option<String>,
+    artist_id: Option<String>,
+}
+
+impl Fixes {
+    /// The fixes of `self` for the tags in `lacks`, the MusicBrainz ids being always taken
+    fn only(self, lacks: Lacks) -> Self {
+        Self {
+            title: self.title.filter(|_| lacks.title),
+            artist: self.artist.filter(|_| lacks.artist),
+            album: self.album.filter(|_| lacks.album),
+            album_artist: self.album_artist.filter(|_| lacks.album_artist),
+            track: self.track.filter(|_| lacks.track),
+            date: self.date.filter(|_| lacks.date),
+            ..self
+        }
+    }
+
+    /// The fixes of `self`, completed with the ones of `other`
+    fn or(self, other: Self) -> Self {
+        Self {
+            title: self.title.or(other.title),
+            artist: self.artist.or(other.artist),
+            album: self.album.or(other.album),
+            album_artist: self.album_artist.or(other.album_artist),
+            track: self.track.or(other.track),
+            date: self.date.or(other.date),
+            recording_id: self.recording_id.or(other.recording_id),
+            release_id: self.release_id.or(other.release_id),
+            artist_id: self.artist_id.or(other.artist_id),
+        }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.title.is_none()
+            && self.artist.is_none()
+            && self.album.is_none()
+            && self.album_artist.is_none()
+            && self.track.is_none()
+            && self.date.is_none()
+            && self.recording_id.is_none()
+            && self.release_id.is_none()
+            && self.artist_id.is_none()
+    }
+
+    fn apply(&self, tag: &mut Tag) {
+        if let Some(title) = &self.title {
+            tag.set_title(title.clone());
+        }
+        if let Some(artist) = &self.artist {
+            tag.set_artist(artist.clone());
+        }
+        if let Some(album) = &self.album {
+            tag.set_album(album.clone());
+        }
+        if let Some(track) = self.track {
+            tag.set_track(track);
+        }
+        let texts = [
+            (ItemKey::AlbumArtist, &self.album_artist),
+            (ItemKey::RecordingDate, &self.date),
+            (ItemKey::MusicBrainzRecordingId, &self.recording_id),
+            (ItemKey::MusicBrainzReleaseId, &self.release_id),
+            (ItemKey::MusicBrainzArtistId, &self.artist_id),
+        ];
+        for (key, value) in texts {
+            if let Some(value) = value {
+                tag.insert_text(key, value.clone());
+            }
+        }
+    }
+}
+
+/// `Artist - Album` or `Album`
+fn split_folder(name: &str) -> (Option<String>, String) {
+    match name.split_once(" - ") {
+        Some((artist, album)) if !artist.trim().is_empty() && !album.trim().is_empty() => {
+            (Some(artist.trim().to_string()), album.trim().to_string())
+        }
+        _ => (None, name.trim().to_string()),
+    }
+}
+
+/// `01 - Title`, `01. Title`, `Artist - Title` or `Title`
+fn split_stem(stem: &str) -> (Option<u32>, Option<String>, String) {
+    let digits: String = stem.chars().take_while(|x| x.is_ascii_digit()).collect();
+    let (number, rest) = match digits.len() {
+        1..=3 => (
+            digits.parse().ok().filter(|x| *x > 0),
+            stem[digits.len()..].trim_start_matches([' ', '-', '.', '_']),
+        ),
+        _ => (None, stem),
+    };
+    let rest = if rest.trim().is_empty() { stem } else { rest };
+    match rest.split_once(" - ") {
+        Some((artist, title)) if number.is_none() && !title.trim().is_empty() => (
+            None,
+            Some(artist.trim().to_string()),
+            title.trim().to_string(),
+        ),
+        _ => (number, None, rest.trim().to_string()),
+    }
+}
+
+/// The tags of `lacks` as told by the names of `path` and its folders below `root`, e.g.
+/// `Artist/Album/01 - Title.flac`
+fn guess(path: &Path, root: &Path, lacks: Lacks) -> Fixes {
+    let stem = path
+        .file_stem()
+        .map(|x| x.to_string_lossy().to_string())
+        .unwrap_or_default();
+    let (number, stem_artist, title) = split_stem(&stem);
+
+    // Folders between the drop folder and the file, the nearest first
+    let folders: Vec<String> = path
+        .parent()
+        .and_then(|x| x.strip_prefix(root).ok())
+        .map(|x| {
+            x.components()
+                .rev()
+                .map(|x| x.as_os_str().to_string_lossy().to_string())
+                .collect()
+        })
+        .unwrap_or_default();
+    let (folder_artist, album) = match folders.first() {
+        Some(name) => {
+            let (artist, album) = split_folder(name);
+            (artist.or(folders.get(1).cloned()), Some(album))
+        }
+        None => (None, None),
+    };
+    let artist = stem_artist.or(folder_artist.clone());
+
+    Fixes {
+        title: Some(title).filter(|x| !x.is_empty()),
+        artist,
+        album,
+        album_artist: folder_artist,
+        track: number,
+        ..Default::default()
+    }
+    .only(lacks)
+}
+
+#[derive(serde::Deserialize, Debug)]
+struct Recordings {
+    #[serde(default)]
+    recordings: Vec<Recording>,
+}
+
+#[derive(serde::Deserialize, Debug)]
+struct Recording {
+    id: String,
+    #[serde(default)]
+    score: u32,
+    title: String,
+    #[serde(rename = "artist-credit", default)]
+    artist_credit: Vec<Credit>,
+    #[serde(default)]
+    releases: Vec<Release>,
+}
+
+#[derive(serde::Deserialize, Debug)]
+struct Credit {
+    name: String,
+    #[serde(default)]
+    joinphrase: String,
+    artist: CreditedArtist,
+}
+
+#[derive(serde::Deserialize, Debug)]
+struct CreditedArtist {
+    id: String,
+}
+
+#[derive(serde::Deserialize, Debug)]
+struct Release {
+    id: String,
+    title: String,
+    date: Option<String>,
+    #[serde(rename = "artist-credit", default)]
+    artist_credit: Vec<Credit>,
+    #[serde(default)]
+    media: Vec<Medium>,
+}
+
+#[derive(serde::Deserialize, Debug)]
+struct Medium {
+    #[serde(default)]
+    track: Vec<ReleaseTrack>,
+}
+
+#[derive(serde::Deserialize, Debug)]
+struct ReleaseTrack {
+    number: String,
+}
+
+fn credited(credits: &[Credit]) -> Option<String> {
+    let names: String = credits
+        .iter()
+        .map(|x| format!("{}{}", x.name, x.joinphrase))
+        .collect();
+    Some(names.trim().to_string()).filter(|x| !x.is_empty())
+}
+
+/// `value` as a phrase of a Lucene query
+fn phrase(value: &str) -> String {
+    format!("\"{}\"", value.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+/// The tags MusicBrainz knows of the recording `title` by `artist`, on the release `album` when
+/// there is one of this name
+async fn lookup(
+    client: &reqwest::Client,
+    title: &str,
+    artist: &str,
+    album: Option<&str>,
+) -> Result<Option<Fixes>, String> {
+    let query = format!("recording:{} AND artist:{}", phrase(title), phrase(artist));
+    let text = client
+        .get(MUSICBRAINZ)
+        .query(&[("query", query.as_str()), ("fmt", "json"), ("limit", "5")])
+        .send()
+        .await
+        .and_then(|x| x.error_for_status())
+        .map_err(|e| e.to_string())?
+        .text()
+        .await
+        .map_err(|e| e.to_string())?;
+    let found: Recordings = serde_json::from_str(&text).map_err(|e| e.to_string())?;
+
+    let Some(recording) = found.recordings.into_iter().find(|x| x.score >= MIN_SCORE) else {
+        return Ok(None);
+    };
+    let release = recording
+        .releases
+        .iter()
+        .find(|x| album.is_some_and(|album| x.title.eq_ignore_ascii_case(album)))
+        .or(recording.releases.first());
+
+    Ok(Some(Fixes {
+        title: Some(recording.title.clone()),
+        artist: credited(&recording.artist_credit),
+        album: release.map(|x| x.title.clone()),
+        album_artist: release.and_then(|x| credited(&x.artist_credit)),
+        track: release
+            .and_then(|x| x.media.first())
+            .and_then(|x| x.track.first())
+            .and_then(|x| x.number.parse().ok()),
+        date: release
+            .and_then(|x| x.date.clone())
+            .filter(|x| !x.is_empty()),
+        recording_id: Some(recording.id.clone()),
+        release_id: release.map(|x| x.id.clone()),
+        artist_id: recording.artist_credit.first().map(|x| x.artist.id.clone()),
+    }))
+}
+
+/// What an import pass needs
+struct Settings {
+    dir: PathBuf,
+    /// Folder of the library the files are moved to
+    root: PathBuf,
+    pattern: Vec<Part>,
+    options: ScanOptions,
+    covers_dir: PathBuf,
+    /// Client of the MusicBrainz lookups, `None` unless they are on
+    client: Option<reqwest::Client>,
+}
+
+/// Tags, moves and reads the file `path` of the drop folder, returning its track once in the
+/// library
+async fn import(
+    path: &Path,
+    settings: &Settings,
+    last_lookup: &mut Option<Instant>,
+) -> Result<Track, String> {
+    let (covers_dir, options) = (settings.covers_dir.clone(), settings.options.clone());
+    let file = path.to_path_buf();
+    let track = tokio::task::spawn_blocking(move || read_track(&covers_dir, file, &options))
+        .await
+        .map_err(|e| e.to_string())?
+        .map_err(|e| e.to_string())?;
+
+    let lacks = Lacks::of(&track);
+    let mut fixes = guess(path, &settings.dir, lacks);
+    if let Some(client) = &settings.client {
+        let title = known(&track.title)
+            .map(str::to_string)
+            .or(fixes.title.clone());
+        let artist = track
+            .artists
+            .iter()
+            .find_map(|x| known(x))
+            .map(str::to_string)
+            .or(fixes.artist.clone());
+        let album = known(&track.album)
+            .map(str::to_string)
+            .or(fixes.album.clone());
+        if let (Some(title), Some(artist)) = (title, artist) {
+            if let Some(last) = last_lookup {
+                tokio::time::sleep(LOOKUP_INTERVAL.saturating_sub(last.elapsed())).await;
+            }
+            *last_lookup = Some(Instant::now());
+            match lookup(client, &title, &artist, album.as_deref()).await {
+                Ok(Some(found)) => fixes = found.only(lacks).or(fixes),
+                Ok(None) => info!("import: {title} by {artist} isn't on MusicBrainz"),
+                Err(e) => warn!("import: MusicBrainz lookup of {title} by {artist} failed: {e}"),
+            }
+        }
+    }
+
+    let (covers_dir, options) = (settings.covers_dir.clone(), settings.options.clone());
+    let (file, root, pattern) = (
+        path.to_path_buf(),
+        settings.root.clone(),
+        settings.pattern.clone(),
+    );
+    tokio::task::spawn_blocking(move || {
+        let mut track = track;
+        if !fixes.is_empty() {
+            tags::write(&file, |tag| fixes.apply(tag)).map_err(|e| e.to_string())?;
+            track = read_track(&covers_dir, file.clone(), &options).map_err(|e| e.to_string())?;
+        }
+
+        let to = root.join(organize::render(&pattern, &track));
+        if to.exists() {
+            return Err(format!("{} already exists", to.display()));
+        }
+        organize::move_file(&file, &to).map_err(|e| e.to_string())?;
+
+        let lyrics = file.with_extension("lrc");
+        if lyrics.exists() {
+            if let Err(e) = organize::move_file(&lyrics, &to.with_extension("lrc")) {
+                warn!("import: unable to move `{}`: {e}", lyrics.display());
+            }
+        }
+        // Left for the other files of the folder
+        let folder = to.parent().unwrap_or(&root);
+        if let Some(cover) = file.parent().and_then(find_folder_cover) {
+            if find_folder_cover(folder).is_none() {
+                let _ = std::fs::copy(&cover, folder.join(cover.file_name().unwrap_or_default()));
+            }
+        }
+
+        read_track(&covers_dir, to, &options).map_err(|e| e.to_string())
+    })
+    .await
+    .map_err(|e| e.to_string())?
+}
+
+/// Moves `path` to the rejected files of the drop folder `dir`
+fn reject(path: &Path, dir: &Path) {
+    let relative = path.strip_prefix(dir).unwrap_or(path);
+    let to = dir.join(REJECTED).join(relative);
+    if let Err(e) = organize::move_file(path, &to) {
+        warn!("import: unable to move `{}` aside: {e}", path.display());
+    }
+}
+
+/// Imports the files of the drop folder into `library`, as a job
+pub async fn run(
+    library: &Library,
+    config: &SharedConfig,
+    io: &SocketIo,
+    context: JobContext,
+) -> Result<(), String> {
+    let settings = {
+        let config = config.read().await;
+        let Some(dir) = import_path(&config) else {
+            return Ok(());
+        };
+        let pattern = config
+            .library
+            .as_ref()
+            .and_then(|library| library.organize_pattern.clone())
+            .unwrap_or(organize::DEFAULT_PATTERN.to_string());
+        let client = reqwest::Client::builder()
+            .user_agent(format!(
+                "lorchestre/{VERSION} ( https://github.com/luxluth/mu )"
+            ))
+            .build()
+            .map_err(|e| e.to_string())?;
+        Settings {
+            dir,
+            root: library
+                .paths
+                .first()
+                .cloned()
+                .ok_or("the library has no folder")?,
+            pattern: organize::parse(&pattern)
+                .map_err(|e| format!("invalid `library.organize_pattern`: {e}"))?,
+            options: ScanOptions::from_config(&config),
+            covers_dir: library.cache_dir.join("covers"),
+            client: musicbrainz(&config).then_some(client),
+        }
+    };
+
+    let dir = settings.dir.clone();
+    let files = tokio::task::spawn_blocking(move || pending(&dir))
+        .await
+        .map_err(|e| e.to_string())?;
+    let mut imported = vec![];
+    let mut rejected = 0;
+    let mut last_lookup = None;
+    for (i, path) in files.iter().enumerate() {
+        if context.cancel.is_cancelled() {
+            break;
+        }
+        context.progress(i, files.len());
+        match import(path, &settings, &mut last_lookup).await {
+            Ok(track) => imported.push(track),
+            Err(e) => {
+                warn!("import: unable to import `{}`: {e}", path.display());
+                reject(path, &settings.dir);
+                rejected += 1;
+            }
+        }
+        if let Some(parent) = path.parent() {
+            organize::remove_empty_dirs(parent, std::slice::from_ref(&settings.dir));
+        }
+    }
+    info!(
+        "import: {} files imported into {}, {rejected} rejected",
+        imported.len(),
+        library.name
+    );
+    if imported.is_empty() {
+        return Ok(());
+    }
+
+    let paths: Vec<PathBuf> = imported
+        .iter()
+        .map(|x| PathBuf::from(&x.file_path))
+        .collect();
+    let mut media = library.media.write().await;
+    for track in imported {
+        media.add_song(track);
+    }
+    media.select_covers(&settings.covers_dir);
+    utils::save_cache(&library.cache_dir, &media);
+    library.publish(io, &media.commit());
+    library.colors.notify_one();
+
+    // The files the next scan compares the folders with
+    let list = library.cache_dir.join(".cache.list");
+    let mut files = read_cache_audio_files(&list);
+    files.extend(paths.iter().cloned());
+    cache_audio_files(&list, &files);
+    Fingerprints::load(&library.cache_dir).refresh(&paths);
+
+    Ok(())
+}
+
+/// Looks at the drop folder every `library.import_interval`, importing its files into `library`
+/// as a job when there are some
+pub async fn watch(library: Arc<Library>, config: SharedConfig, io: SocketIo, jobs: Arc<Jobs>) {
+    loop {
+        tokio::time::sleep(interval(&*config.read().await)).await;
+        let Some(dir) = import_path(&*config.read().await) else {
+            continue;
+        };
+        // The scan would read the files moved in too
+        if library.scan.status().running {
+            continue;
+        }
+        let has_files = tokio::task::spawn_blocking(move || !pending(&dir).is_empty())
+            .await
+            .unwrap_or_default();
+        if !has_files {
+            continue;
+        }
+
+        let (library, config, io) = (Arc::clone(&library), config.clone(), io.clone());
+        let name = library.name.clone();
+        let (_, done) = jobs.submit(
+            JobKind::Import,
+            &name,
+            None,
+            Box::new(move |context| {
+                Box::pin(async move { run(&library, &config, &io, context).await })
+            }),
+        );
+        // One import at a time
+        let _ = done.await;
+    }
+}
@@ -0,0 +1,235 @@
+//! Problems of the library reported by `GET /library/issues`, for clients to offer fixing the
+//! tags and files
+
+use crate::daemon::global::utils::get_audio_files;
+use crate::daemon::global::Media;
+use crate::daemon::playlist::{self, PlaylistFormat};
+use crate::daemon::walk::WalkOptions;
+use mu_protocol::api::{IssueCode, LibraryIssue};
+use mu_protocol::library::{MediaKind, Track};
+use std::collections::{HashMap, HashSet};
+use std::path::PathBuf;
+
+/// Lossy music below this bitrate, in kbps, is likely a bad encode
+const MIN_LOSSY_BITRATE: u32 = 96;
+
+/// Value of the tags left unset by the scan
+const UNKNOWN: &str = "@UNKNOWN@";
+
+fn issue(code: IssueCode, message: String) -> LibraryIssue {
+    LibraryIssue {
+        code,
+        message,
+        album: None,
+        tracks: vec![],
+        playlist: None,
+        path: None,
+    }
+}
+
+/// Issues of the tags of a track
+fn track_issues(track: &Track) -> Vec<LibraryIssue> {
+    let mut codes = vec![];
+    if track.title_inferred || track.title.trim().is_empty() || track.title == UNKNOWN {
+        codes.push((IssueCode::MissingTitle, "has no title".to_string()));
+    }
+    if track.album_guessed || track.album.trim().is_empty() || track.album == UNKNOWN {
+        codes.push((IssueCode::MissingAlbum, "has no album".to_string()));
+    }
+    if track.duration == 0 {
+        codes.push((IssueCode::ZeroDuration, "has no duration".to_string()));
+    }
+    if track.bitrate == 0 {
+        codes.push((IssueCode::SuspiciousBitrate, "has no bitrate".to_string()));
+    } else if !track.is_lossless
+        && track.media_kind == MediaKind::Music
+        && track.bitrate < MIN_LOSSY_BITRATE
+    {
+        codes.push((
+            IssueCode::SuspiciousBitrate,
+            format!("is encoded at {} kbps", track.bitrate),
+        ));
+    }
+
+    codes
+        .into_iter()
+        .map(|(code, message)| LibraryIssue {
+            album: Some(track.album_id.clone()),
+            tracks: vec![track.id.clone()],
+            ..issue(code, format!("{} {message}", track.file_path))
+        })
+        .collect()
+}
+
+/// Albums without cover, and ids listed twice or shared by tracks of different albums
+fn album_issues(media: &Media) -> Vec<LibraryIssue> {
+    let mut issues = vec![];
+    let mut seen = HashSet::new();
+    for album in &media.albums {
+        let tracks: Vec<&Track> = album
+            .tracks
+            .iter()
+            .filter_map(|x| media.tracks.get(x))
+            .collect();
+        let ids = || tracks.iter().map(|x| x.id.clone()).collect();
+
+        if album.cover_url.is_none() {
+            issues.push(LibraryIssue {
+                album: Some(album.id.clone()),
+                tracks: ids(),
+                ..issue(
+                    IssueCode::MissingCover,
+                    format!("{} has no cover", album.name),
+                )
+            });
+        }
+
+        let mut names: Vec<&str> = tracks.iter().map(|x| x.album.as_str()).collect();
+        names.sort();
+        names.dedup();
+        if !seen.insert(&album.id) {
+            issues.push(LibraryIssue {
+                album: Some(album.id.clone()),
+                tracks: ids(),
+                ..issue(
+                    IssueCode::DuplicateAlbumId,
+                    format!("{} is listed more than once", album.name),
+                )
+            });
+        } else if names.len() > 1 {
+            issues.push(LibraryIssue {
+                album: Some(album.id.clone()),
+                tracks: ids(),
+                ..issue(
+                    IssueCode::DuplicateAlbumId,
+                    format!("the albums {} share the same id", names.join(", ")),
+                )
+            });
+        }
+    }
+
+    issues
+}
+
+/// Albums whose tracks are dated of different years, listing the tracks disagreeing with the
+/// year of the album
+fn year_conflicts(media: &Media) -> Vec<LibraryIssue> {
+    let mut issues = vec![];
+    for album in &media.albums {
+        let tracks: Vec<&Track> = album
+            .tracks
+            .iter()
+            .filter_map(|x| media.tracks.get(x))
+            .collect();
+        let mut years: Vec<u32> = tracks.iter().filter_map(|x| x.year()).collect();
+        years.sort();
+        years.dedup();
+        if years.len() < 2 {
+            continue;
+        }
+
+        let years: Vec<String> = years.iter().map(|x| x.to_string()).collect();
+        issues.push(LibraryIssue {
+            album: Some(album.id.clone()),
+            tracks: tracks
+                .iter()
+                .filter(|x| x.year().is_some_and(|year| Some(year) != album.year))
+                .map(|x| x.id.clone())
+                .collect(),
+            ..issue(
+                IssueCode::YearConflict,
+                format!(
+                    "the tracks of {} are dated {}",
+                    album.name,
+                    years.join(", ")
+                ),
+            )
+        });
+    }
+
+    issues
+}
+
+/// Files of the tracks that are gone or can't be opened, and the audio files of `roots` the
+/// scans couldn't read
+fn unreadable_files(
+    media: &Media,
+    roots: Option<&[PathBuf]>,
+    options: &WalkOptions,
+) -> Vec<LibraryIssue> {
+    let mut issues = vec![];
+    let mut files: HashMap<PathBuf, Vec<String>> = HashMap::new();
+    for track in media.tracks.values() {
+        files
+            .entry(PathBuf::from(track.audio_file()))
+            .or_default()
+            .push(track.id.clone());
+    }
+
+    let mut tracked: Vec<(&PathBuf, &Vec<String>)> = files.iter().collect();
+    tracked.sort();
+    for (path, ids) in tracked {
+        if let Err(e) = std::fs::File::open(path) {
+            issues.push(LibraryIssue {
+                tracks: ids.clone(),
+                path: Some(path.display().to_string()),
+                ..issue(
+                    IssueCode::UnreadableFile,
+                    format!("{} can't be read: {e}", path.display()),
+                )
+            });
+        }
+    }
+
+    let mut skipped: Vec<PathBuf> = get_audio_files(roots.unwrap_or_default(), options)
+        .0
+        .into_iter()
+        .filter(|x| PlaylistFormat::from_path(x).is_none() && !files.contains_key(x))
+        .collect();
+    skipped.sort();
+    for path in skipped {
+        issues.push(LibraryIssue {
+            path: Some(path.display().to_string()),
+            ..issue(
+                IssueCode::UnreadableFile,
+                format!("{} couldn't be read by the scan", path.display()),
+            )
+        });
+    }
+
+    issues
+}
+
+fn broken_playlist_entries(media: &Media) -> Vec<LibraryIssue> {
+    let mut issues = vec![];
+    for playlist in &media.playlists {
+        for entry in playlist::broken_entries(playlist, &media.tracks) {
+            let message = format!("{} points to {entry}, not in the library", playlist.name);
+            issues.push(LibraryIssue {
+                playlist: Some(playlist.id.clone()),
+                path: Some(entry),
+                ..issue(IssueCode::BrokenPlaylistEntry, message)
+            });
+        }
+    }
+
+    issues
+}
+
+/// The issues of `media`. The audio files of the folders `roots`, walked per `options`,
+/// missing from it are reported as unreadable, which is left out while a scan runs.
+pub fn issues(
+    media: &Media,
+    roots: Option<&[PathBuf]>,
+    options: &WalkOptions,
+) -> Vec<LibraryIssue> {
+    let mut tracks: Vec<&Track> = media.tracks.values().collect();
+    tracks.sort_by(|a, b| a.file_path.cmp(&b.file_path));
+
+    let mut issues: Vec<LibraryIssue> = tracks.into_iter().flat_map(track_issues).collect();
+    issues.extend(album_issues(media));
+    issues.extend(year_conflicts(media));
+    issues.extend(unreadable_files(media, roots, options));
+    issues.extend(broken_playlist_entries(media));
+    issues
+}
@@ -0,0 +1,294 @@
+//! Background jobs: scans, cover embedding, audits and cue sheet cuts share one queue and run a
+//! few at a time, see `library.max_jobs`. Jobs are followed with `job:updated` events and can
+//! be cancelled. The ones left unfinished when the daemon stops are queued again on start.
+
+use crate::daemon::events;
+use crate::daemon::store;
+use futures::future::BoxFuture;
+use mu_protocol::api::{Job, JobKind, JobProgress, JobState};
+use mu_protocol::events::Event;
+use socketioxide::SocketIo;
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime};
+use tokio::sync::{oneshot, Semaphore};
+use tokio_util::sync::CancellationToken;
+use tracing::info;
+
+/// Finished jobs kept for `GET /jobs`
+const HISTORY: usize = 50;
+
+/// Least time between two progress events of a job
+const PROGRESS_INTERVAL: Duration = Duration::from_secs(1);
+
+/// What a job does, failing with the reason shown in its `error`
+pub type Task = BoxFuture<'static, Result<(), String>>;
+pub type Run = Box<dyn FnOnce(JobContext) -> Task + Send>;
+
+/// Handed to the task of a job
+#[derive(Clone)]
+pub struct JobContext {
+    jobs: Arc<Jobs>,
+    id: String,
+    /// Cancelled by `DELETE /jobs/:id` or when the daemon stops
+    pub cancel: CancellationToken,
+    reported: Arc<Mutex<Option<Instant>>>,
+}
+
+impl JobContext {
+    pub fn progress(&self, done: usize, total: usize) {
+        let progress = Some(JobProgress { done, total });
+        let Some(job) = self.jobs.modify(&self.id, |job| job.progress = progress) else {
+            return;
+        };
+
+        let mut reported = self.reported.lock().unwrap();
+        if done < total && reported.is_some_and(|x| x.elapsed() < PROGRESS_INTERVAL) {
+            return;
+        }
+        *reported = Some(Instant::now());
+        events::emit(&self.jobs.io, Event::JobUpdated, &job);
+    }
+}
+
+#[derive(Debug)]
+pub struct Jobs {
+    path: PathBuf,
+    entries: Mutex<Vec<Job>>,
+    /// Of the unfinished jobs
+    tokens: Mutex<HashMap<String, CancellationToken>>,
+    /// Told when the job they wait for finishes, see [`Jobs::wait`]
+    waiters: Mutex<HashMap<String, Vec<oneshot::Sender<Job>>>>,
+    /// Held while a job is looked for then submitted, see [`Jobs::submit_single`]
+    submitting: Mutex<()>,
+    permits: Semaphore,
+    io: SocketIo,
+    shutdown: CancellationToken,
+}
+
+impl Jobs {
+    /// Returns the jobs, along with the unfinished ones saved at `path` to [`Jobs::resume`]
+    pub fn load(
+        path: PathBuf,
+        max_jobs: usize,
+        io: SocketIo,
+        shutdown: CancellationToken,
+    ) -> (Self, Vec<Job>) {
+        let entries: Vec<Job> = store::load(&path, "jobs");
+        let (entries, unfinished) = entries.into_iter().partition(|x| x.finished());
+
+        let jobs = Self {
+            path,
+            entries: Mutex::new(entries),
+            tokens: Mutex::new(HashMap::new()),
+            waiters: Mutex::new(HashMap::new()),
+            submitting: Mutex::new(()),
+            permits: Semaphore::new(max_jobs.max(1)),
+            io,
+            shutdown,
+        };
+        (jobs, unfinished)
+    }
+
+    fn save(&self) {
+        // Held while writing, so that the saves don't interleave
+        let entries = self.entries.lock().unwrap();
+        store::save(&self.path, "jobs", &*entries);
+    }
+
+    /// Applies `change` to the job `id`, returning it
+    fn modify(&self, id: &str, change: impl FnOnce(&mut Job)) -> Option<Job> {
+        let mut entries = self.entries.lock().unwrap();
+        let job = entries.iter_mut().find(|x| x.id == id)?;
+        change(job);
+        Some(job.clone())
+    }
+
+    /// Saves and sends the change of state of the job `id`
+    fn update(&self, id: &str, change: impl FnOnce(&mut Job)) -> Option<Job> {
+        let job = self.modify(id, change)?;
+        self.save();
+        events::emit(&self.io, Event::JobUpdated, &job);
+        Some(job)
+    }
+
+    /// Queues a job running `run`. The receiver gets the job once finished, it's dropped if
+    /// the daemon stops first.
+    pub fn submit(
+        self: &Arc<Self>,
+        kind: JobKind,
+        library: &str,
+        target: Option<String>,
+        run: Run,
+    ) -> (Job, oneshot::Receiver<Job>) {
+        let job = Job {
+            id: uuid::Uuid::new_v4().to_string(),
+            kind,
+            library: library.to_string(),
+            target,
+            state: JobState::Queued,
+            progress: None,
+            error: None,
+            created_at: SystemTime::now(),
+            finished_at: None,
+        };
+        self.enqueue(job, run)
+    }
+
+    /// Like [`Jobs::submit`], unless a job of `kind` is already queued or running for `library`:
+    /// it is returned instead, with a receiver of it once finished. Returns whether the job was
+    /// submitted.
+    pub fn submit_single(
+        self: &Arc<Self>,
+        kind: JobKind,
+        library: &str,
+        target: Option<String>,
+        run: Run,
+    ) -> (Job, oneshot::Receiver<Job>, bool) {
+        let _submitting = self.submitting.lock().unwrap();
+        let active = self
+            .entries
+            .lock()
+            .unwrap()
+            .iter()
+            .find(|x| x.kind == kind && x.library == library && !x.finished())
+            .cloned();
+        match active {
+            Some(job) => {
+                let done = self.wait(&job.id);
+                (job, done, false)
+            }
+            None => {
+                let (job, done) = self.submit(kind, library, target, run);
+                (job, done, true)
+            }
+        }
+    }
+
+    /// A receiver of the job `id` once finished, right away if it is. It's dropped if the
+    /// daemon stops first or there is no such job.
+    pub fn wait(&self, id: &str) -> oneshot::Receiver<Job> {
+        let (sender, receiver) = oneshot::channel();
+        let mut waiters = self.waiters.lock().unwrap();
+        match self.get(id) {
+            Some(job) if job.finished() => {
+                let _ = sender.send(job);
+            }
+            Some(_) => waiters.entry(id.to_string()).or_default().push(sender),
+            None => {}
+        }
+        receiver
+    }
+
+    /// Queues again a job interrupted by the daemon stopping
+    pub fn resume(self: &Arc<Self>, mut job: Job, run: Run) -> (Job, oneshot::Receiver<Job>) {
+        job.state = JobState::Queued;
+        job.progress = None;
+        self.enqueue(job, run)
+    }
+
+    fn enqueue(self: &Arc<Self>, job: Job, run: Run) -> (Job, oneshot::Receiver<Job>) {
+        let id = job.id.clone();
+        let cancel = self.shutdown.child_token();
+        self.tokens
+            .lock()
+            .unwrap()
+            .insert(id.clone(), cancel.clone());
+        self.entries.lock().unwrap().push(job.clone());
+        self.save();
+        events::emit(&self.io, Event::JobUpdated, &job);
+
+        let (sender, receiver) = oneshot::channel();
+        let jobs = Arc::clone(self);
+        tokio::spawn(async move {
+            let result = tokio::select! {
+                permit = jobs.permits.acquire() => match permit {
+                    Ok(_permit) => {
+                        jobs.update(&id, |job| job.state = JobState::Running);
+                        let context = JobContext {
+                            jobs: Arc::clone(&jobs),
+                            id: id.clone(),
+                            cancel: cancel.clone(),
+                            reported: Arc::default(),
+                        };
+                        run(context).await
+                    }
+                    Err(e) => Err(e.to_string()),
+                },
+                _ = cancel.cancelled() => Ok(()),
+            };
+            jobs.tokens.lock().unwrap().remove(&id);
+            // Left unfinished, to be resumed on the next start
+            if jobs.shutdown.is_cancelled() {
+                jobs.waiters.lock().unwrap().remove(&id);
+                return;
+            }
+
+            let finished = jobs.update(&id, |job| {
+                job.state = match result {
+                    _ if cancel.is_cancelled() => JobState::Cancelled,
+                    Ok(()) => JobState::Done,
+                    Err(e) => {
+                        job.error = Some(e);
+                        JobState::Failed
+                    }
+                };
+                job.progress = None;
+                job.finished_at = Some(SystemTime::now());
+            });
+            jobs.forget_finished();
+            // Once finished, the later waiters get the job right away
+            let waiters = jobs.waiters.lock().unwrap().remove(&id);
+            if let Some(job) = finished {
+                info!("job {} ({:?}) {:?}", job.id, job.kind, job.state);
+                for waiter in waiters.into_iter().flatten() {
+                    let _ = waiter.send(job.clone());
+                }
+                let _ = sender.send(job);
+            }
+        });
+
+        (job, receiver)
+    }
+
+    /// Keeps the last [`HISTORY`] finished jobs
+    fn forget_finished(&self) {
+        let mut entries = self.entries.lock().unwrap();
+        let finished = entries.iter().filter(|x| x.finished()).count();
+        let mut excess = finished.saturating_sub(HISTORY);
+        entries.retain(|x| {
+            if excess > 0 && x.finished() {
+                excess -= 1;
+                return false;
+            }
+            true
+        });
+    }
+
+    /// Unfinished jobs first, then the finished ones, most recent first
+    pub fn list(&self) -> Vec<Job> {
+        let mut jobs = self.entries.lock().unwrap().clone();
+        jobs.sort_by_key(|x| (x.finished(), std::cmp::Reverse(x.created_at)));
+        jobs
+    }
+
+    pub fn get(&self, id: &str) -> Option<Job> {
+        self.entries
+            .lock()
+            .unwrap()
+            .iter()
+            .find(|x| x.id == id)
+            .cloned()
+    }
+
+    /// Cancels the job `id`, returning `false` if it is already finished and `None` if there is
+    /// no such job
+    pub fn cancel(&self, id: &str) -> Option<bool> {
+        if let Some(token) = self.tokens.lock().unwrap().get(id) {
+            token.cancel();
+            return Some(true);
+        }
+        self.get(id).map(|_| false)
+    }
+}
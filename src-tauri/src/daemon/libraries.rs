@@ -0,0 +1,259 @@
+//! Libraries served by the daemon, each with its own folders, cache and media. Requests are
+//! scoped to one by the `X-Library` header or a `/libraries/<name>` path prefix, to the first
+//! one otherwise. Accounts only see the libraries of `User::libraries`, and the requests
+//! without account token the libraries which aren't `hidden`: the others answer as if they
+//! didn't exist, and their events aren't sent to the sockets of the account.
+
+use crate::daemon::changes::ChangeLog;
+use crate::daemon::dav;
+use crate::daemon::events;
+use crate::daemon::global::Media;
+use crate::daemon::remote;
+use crate::daemon::scan::ScanState;
+use crate::daemon::snapshot::Snapshot;
+use crate::daemon::users;
+use axum::{
+    extract::Request,
+    http::{HeaderName, HeaderValue, Uri},
+    middleware::Next,
+    response::Response,
+};
+use mu_protocol::api::{AuditReport, CacheGcReport, LibraryInfo, MediaChanges, User};
+use percent_encoding::percent_decode_str;
+use socketioxide::{extract::SocketRef, SocketIo};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use tokio::sync::{Notify, RwLock};
+use tracing::warn;
+
+/// Header naming the library a request is scoped to
+pub const LIBRARY: HeaderName = HeaderName::from_static("x-library");
+
+/// Prefix scoping the rest of the path to a library, e.g. `/libraries/Audiobooks/media`
+const PREFIX: &str = "/libraries/";
+
+/// Name of the library made of the audio directory, when none is configured
+const DEFAULT_NAME: &str = "Music";
+
+/// A library as configured
+#[derive(Debug, Clone)]
+pub struct Profile {
+    pub name: String,
+    pub paths: Vec<PathBuf>,
+    pub cache_dir: PathBuf,
+    pub hidden: bool,
+}
+
+/// The libraries of `libraries` in the configuration, in their own directory of `cache_dir`.
+/// Without any, the audio directory of the user is the only library and keeps its cache at the
+/// root of `cache_dir`.
+pub fn profiles(config: &lorconf::Config, cache_dir: &Path) -> Vec<Profile> {
+    let mut profiles: Vec<Profile> = vec![];
+    for library in config.libraries.iter().flatten() {
+        // Names are directories of the cache and path segments
+        let name = library.name.trim();
+        if name.is_empty() || name.starts_with('.') || name.contains(['/', '\\']) {
+            warn!("Ignoring the library with the invalid name `{name}`");
+            continue;
+        }
+        if profiles.iter().any(|x| x.name == name) {
+            warn!("Ignoring the library `{name}`, defined twice");
+            continue;
+        }
+
+        profiles.push(Profile {
+            name: name.to_string(),
+            paths: library.paths.clone(),
+            cache_dir: cache_dir.join("libraries").join(name),
+            hidden: library.hidden,
+        });
+    }
+
+    if profiles.is_empty() {
+        profiles.push(Profile {
+            name: DEFAULT_NAME.to_string(),
+            paths: dirs::audio_dir().into_iter().collect(),
+            cache_dir: cache_dir.to_path_buf(),
+            hidden: false,
+        });
+    }
+    // After the folders, the first one being where the imports go
+    for (i, profile) in profiles.iter_mut().enumerate() {
+        let roots = remote::roots(config, &profile.name, i == 0);
+        profile.paths.extend(roots);
+    }
+
+    profiles
+}
+
+#[derive(Debug)]
+pub struct Library {
+    pub name: String,
+    pub paths: Vec<PathBuf>,
+    pub cache_dir: PathBuf,
+    /// Whether requests naming no library are scoped to this one
+    pub default: bool,
+    /// Seen by the accounts listing it and the admin token only, see [`Viewer`]
+    pub hidden: bool,
+    pub media: Arc<Snapshot<Media>>,
+    /// Report of the last audit of the cache
+    pub audit: Arc<RwLock<Option<AuditReport>>>,
+    /// Report of the last collection of the covers cache
+    pub gc: Arc<RwLock<Option<CacheGcReport>>>,
+    /// Wakes `palette::worker` up once new covers were extracted
+    pub colors: Arc<Notify>,
+    pub scan: ScanState,
+    /// Changes of `media`, recorded after each scan, audit or coloring
+    pub changes: Mutex<ChangeLog>,
+    /// The WebDAV tree of the last snapshot of `media` browsed
+    pub dav: dav::Tree,
+}
+
+impl Library {
+    pub fn new(profile: Profile, media: Media, scan: ScanState, default: bool) -> Self {
+        Self {
+            name: profile.name,
+            paths: profile.paths,
+            cache_dir: profile.cache_dir,
+            default,
+            hidden: profile.hidden,
+            changes: Mutex::new(ChangeLog::new(&media)),
+            media: Arc::new(Snapshot::new(media)),
+            audit: Arc::new(RwLock::new(None)),
+            gc: Arc::new(RwLock::new(None)),
+            colors: Arc::new(Notify::new()),
+            scan,
+            dav: dav::Tree::default(),
+        }
+    }
+
+    /// Records what changed in `media`, the media of the library, and sends the changes to the
+    /// clients, which follow the default library only. Returns the changes.
+    pub fn publish(&self, io: &SocketIo, media: &Media) -> MediaChanges {
+        let changes = self.changes.lock().unwrap().record(media);
+        if self.default {
+            events::emit_changes(io, &hidden_room(&self.name), &changes);
+        }
+        changes
+    }
+
+    pub async fn info(&self) -> LibraryInfo {
+        let media = self.media.load();
+        LibraryInfo {
+            name: self.name.clone(),
+            paths: self.paths.clone(),
+            default: self.default,
+            tracks: media.tracks.len(),
+            albums: media.albums.len(),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct Libraries(Vec<Arc<Library>>);
+
+impl Libraries {
+    /// `libraries` must not be empty, the first one is the default
+    pub fn new(libraries: Vec<Library>) -> Self {
+        Self(libraries.into_iter().map(Arc::new).collect())
+    }
+
+    pub fn all(&self) -> &[Arc<Library>] {
+        &self.0
+    }
+
+    pub fn default(&self) -> &Arc<Library> {
+        &self.0[0]
+    }
+
+    /// The library named `name`, the default one for `None`
+    pub fn get(&self, name: Option<&str>) -> Option<&Arc<Library>> {
+        match name {
+            Some(name) => self.0.iter().find(|x| x.name == name),
+            None => Some(self.default()),
+        }
+    }
+
+    /// The library named `name` if `viewer` sees it, the first one it sees for `None`
+    pub fn seen(&self, name: Option<&str>, viewer: &Viewer) -> Option<&Arc<Library>> {
+        let sees = |x: &&Arc<Library>| viewer.sees(x);
+        match name {
+            Some(name) => self.0.iter().filter(sees).find(|x| x.name == name),
+            None => self.0.iter().find(sees),
+        }
+    }
+
+    /// Puts `socket` in the hidden rooms of the libraries `viewer` doesn't see, see
+    /// [`hidden_room`]
+    pub fn hide(&self, socket: &SocketRef, viewer: &Viewer) {
+        for library in self.0.iter().filter(|x| !viewer.sees(x)) {
+            if let Err(e) = socket.join(hidden_room(&library.name)) {
+                warn!(
+                    "Unable to hide the library {} from {}: {e}",
+                    library.name, socket.id
+                );
+            }
+        }
+    }
+}
+
+/// Who a request or a socket is made by, as far as the libraries it sees go
+pub enum Viewer<'a> {
+    /// The holder of `network.admin_token`, seeing every library
+    Admin,
+    /// An account, seeing the libraries of `User::libraries`
+    Account(&'a User),
+    /// The default user, seeing the libraries which aren't hidden
+    Default,
+}
+
+impl<'a> Viewer<'a> {
+    /// Who a socket is made by, `user` being the account of its `auth.token`. Sockets
+    /// connecting with the admin token see what the default user sees.
+    pub fn socket(user: Option<&'a User>) -> Self {
+        match user {
+            Some(user) => Self::Account(user),
+            None => Self::Default,
+        }
+    }
+
+    pub fn sees(&self, library: &Library) -> bool {
+        match self {
+            Self::Admin => true,
+            Self::Account(user) => users::sees(user, &library.name),
+            Self::Default => !library.hidden,
+        }
+    }
+}
+
+/// Socket.io room of the sockets not seeing the library `name`, left out of its events
+pub fn hidden_room(name: &str) -> String {
+    format!("hidden:{name}")
+}
+
+/// Turns a `/libraries/<name>/...` path into `/...` with an `X-Library: <name>` header, so that
+/// the routes stay the same for every library. Applied around the router, before routing.
+pub async fn scope_prefix(mut request: Request, next: Next) -> Response {
+    let Some(rest) = request.uri().path().strip_prefix(PREFIX) else {
+        return next.run(request).await;
+    };
+    let (name, path) = match rest.find('/') {
+        Some(i) => (&rest[..i], &rest[i..]),
+        None => (rest, "/"),
+    };
+    let name = percent_decode_str(name).decode_utf8_lossy().to_string();
+    let path_and_query = match request.uri().query() {
+        Some(query) => format!("{path}?{query}"),
+        None => path.to_string(),
+    };
+
+    if let (Ok(uri), Ok(value)) = (
+        Uri::builder().path_and_query(path_and_query).build(),
+        HeaderValue::from_str(&name),
+    ) {
+        *request.uri_mut() = uri;
+        request.headers_mut().insert(LIBRARY, value);
+    }
+
+    next.run(request).await
+}
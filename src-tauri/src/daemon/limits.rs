@@ -0,0 +1,123 @@
+use axum::body::Body;
+use futures::StreamExt;
+use std::collections::HashMap;
+use std::fmt;
+use std::net::IpAddr;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// Clients tracked before the idle ones are forgotten
+const RATE_LIMITED_CLIENTS: usize = 1024;
+
+/// Who a limit applies to: the account or the admin token of the request, so that people
+/// sharing an address don't share their limits, else the address of the client
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum Client {
+    Account(String),
+    Admin,
+    Ip(IpAddr),
+}
+
+impl fmt::Display for Client {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Client::Account(name) => write!(f, "account {name}"),
+            Client::Admin => write!(f, "the admin token"),
+            Client::Ip(ip) => ip.fmt(f),
+        }
+    }
+}
+
+/// Counts the streams currently open by each client
+#[derive(Debug, Default)]
+pub struct StreamLimiter {
+    active: Mutex<HashMap<Client, u32>>,
+}
+
+/// Holds one stream slot of a client, released on drop
+pub struct StreamGuard {
+    limiter: Arc<StreamLimiter>,
+    client: Client,
+}
+
+impl StreamLimiter {
+    /// Takes a stream slot for `client`, or returns the number of open streams if `max` is
+    /// reached
+    pub fn acquire(self: &Arc<Self>, client: Client, max: u32) -> Result<StreamGuard, u32> {
+        let mut active = self.active.lock().unwrap();
+        let count = active.entry(client.clone()).or_default();
+        if *count >= max {
+            return Err(*count);
+        }
+        *count += 1;
+
+        Ok(StreamGuard {
+            limiter: Arc::clone(self),
+            client,
+        })
+    }
+}
+
+impl Drop for StreamGuard {
+    fn drop(&mut self) {
+        let mut active = self.limiter.active.lock().unwrap();
+        if let Some(count) = active.get_mut(&self.client) {
+            *count -= 1;
+            if *count == 0 {
+                active.remove(&self.client);
+            }
+        }
+    }
+}
+
+/// Ties `guard` to `body`: the slot is released once the body is fully sent or dropped
+pub fn guarded(body: Body, guard: StreamGuard) -> Body {
+    Body::from_stream(body.into_data_stream().map(move |chunk| {
+        let _ = &guard;
+        chunk
+    }))
+}
+
+#[derive(Debug)]
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+/// Token bucket of each client: a full minute of requests can be sent at once, then they are
+/// allowed back at the configured pace
+#[derive(Debug, Default)]
+pub struct RateLimiter {
+    buckets: Mutex<HashMap<Client, Bucket>>,
+}
+
+impl RateLimiter {
+    /// Takes a request from the bucket of `client`, or returns how long to wait for the next
+    /// one
+    pub fn check(&self, client: &Client, per_minute: u32) -> Result<(), Duration> {
+        let now = Instant::now();
+        let capacity = per_minute as f64;
+        let per_second = capacity / 60.0;
+        let refill = |bucket: &Bucket| {
+            (bucket.tokens + now.duration_since(bucket.updated).as_secs_f64() * per_second)
+                .min(capacity)
+        };
+
+        let mut buckets = self.buckets.lock().unwrap();
+        if buckets.len() >= RATE_LIMITED_CLIENTS && !buckets.contains_key(client) {
+            buckets.retain(|_, bucket| refill(bucket) < capacity);
+        }
+        let bucket = buckets.entry(client.clone()).or_insert(Bucket {
+            tokens: capacity,
+            updated: now,
+        });
+        bucket.tokens = refill(bucket);
+        bucket.updated = now;
+        if bucket.tokens < 1.0 {
+            return Err(Duration::from_secs_f64((1.0 - bucket.tokens) / per_second));
+        }
+        bucket.tokens -= 1.0;
+
+        Ok(())
+    }
+}
@@ -0,0 +1,143 @@
+//! Addresses the daemon listens on: every address `network.host` resolves to on
+//! `network.port`, e.g. both `127.0.0.1` and `::1` for `localhost`, the addresses of
+//! `network.listen`, and the Unix socket of `network.unix_socket` for local clients.
+
+use axum::Router;
+use std::net::SocketAddr;
+use std::path::Path;
+use tokio::net::TcpListener;
+use tokio_util::sync::CancellationToken;
+use tracing::{info, warn};
+
+/// Addresses of `host`, with or without the brackets of an IPv6 literal, on `port`
+pub async fn resolve(host: &str, port: u16) -> Vec<SocketAddr> {
+    let host = host
+        .strip_prefix('[')
+        .and_then(|x| x.strip_suffix(']'))
+        .unwrap_or(host);
+    let mut addresses: Vec<SocketAddr> = match tokio::net::lookup_host((host, port)).await {
+        Ok(addresses) => addresses.collect(),
+        Err(e) => {
+            warn!("listen: unable to resolve `{host}`: {e}");
+            vec![]
+        }
+    };
+    addresses.dedup();
+    addresses
+}
+
+/// Whether a daemon already listens on one of `addresses`. Connecting is enough, an HTTP
+/// request would fail against HTTPS.
+pub async fn taken(addresses: &[SocketAddr]) -> bool {
+    for address in addresses {
+        if tokio::net::TcpStream::connect(address).await.is_ok() {
+            return true;
+        }
+    }
+    false
+}
+
+/// Addresses of `network.listen`, written `ip:port`, `[ipv6]:port` or `name:port`
+pub async fn extra(config: &lorconf::Config) -> Vec<SocketAddr> {
+    let listen = config
+        .network
+        .as_ref()
+        .and_then(|network| network.listen.clone())
+        .unwrap_or_default();
+    let mut addresses = vec![];
+    for entry in listen {
+        match tokio::net::lookup_host(entry.as_str()).await {
+            Ok(resolved) => addresses.extend(resolved),
+            Err(e) => warn!("listen: ignoring `{entry}` of network.listen: {e}"),
+        }
+    }
+    addresses
+}
+
+/// Listeners bound to `addresses`, skipping the ones that can't be bound, e.g. `::1` on a
+/// machine without IPv6 or `0.0.0.0` after `[::]` on a dual-stack one
+pub async fn bind(addresses: &[SocketAddr]) -> Vec<TcpListener> {
+    let mut listeners = vec![];
+    for address in addresses {
+        match TcpListener::bind(address).await {
+            Ok(listener) => listeners.push(listener),
+            Err(e) => warn!("listen: unable to bind {address}: {e}"),
+        }
+    }
+    listeners
+}
+
+/// Serves `app` on the Unix socket `path` until `stopped` is cancelled, then removes it. The
+/// clients count as local, as if they came from `127.0.0.1`.
+#[cfg(unix)]
+pub async fn serve_unix(
+    path: &Path,
+    app: Router,
+    stopped: CancellationToken,
+) -> std::io::Result<()> {
+    use axum::extract::ConnectInfo;
+    use axum::Extension;
+    use hyper_util::rt::{TokioExecutor, TokioIo};
+    use hyper_util::server::conn::auto::Builder;
+    use std::net::Ipv4Addr;
+    use std::os::unix::fs::PermissionsExt;
+    use tower::ServiceExt;
+
+    // Left behind by a daemon that didn't stop cleanly, unless another one is serving it
+    if path.exists() {
+        if tokio::net::UnixStream::connect(path).await.is_ok() {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::AddrInUse,
+                "the socket is in use",
+            ));
+        }
+        std::fs::remove_file(path)?;
+    }
+    let listener = tokio::net::UnixListener::bind(path)?;
+    // Only the user running the daemon connects
+    std::fs::set_permissions(path, std::fs::Permissions::from_mode(0o600))?;
+    info!("lorchestre daemon started on unix:{}", path.display());
+
+    let app = app.layer(Extension(ConnectInfo(SocketAddr::from((
+        Ipv4Addr::LOCALHOST,
+        0,
+    )))));
+    loop {
+        let stream = tokio::select! {
+            accepted = listener.accept() => match accepted {
+                Ok((stream, _)) => stream,
+                Err(e) => {
+                    warn!("listen: unable to accept on `{}`: {e}", path.display());
+                    continue;
+                }
+            },
+            _ = stopped.cancelled() => break,
+        };
+
+        let (app, stopped) = (app.clone(), stopped.clone());
+        tokio::spawn(async move {
+            let service = hyper::service::service_fn(move |request| app.clone().oneshot(request));
+            let builder = Builder::new(TokioExecutor::new());
+            let connection = builder.serve_connection_with_upgrades(TokioIo::new(stream), service);
+            tokio::pin!(connection);
+            tokio::select! {
+                _ = connection.as_mut() => {}
+                _ = stopped.cancelled() => {
+                    connection.as_mut().graceful_shutdown();
+                    let _ = connection.await;
+                }
+            }
+        });
+    }
+
+    let _ = std::fs::remove_file(path);
+    Ok(())
+}
+
+#[cfg(not(unix))]
+pub async fn serve_unix(_: &Path, _: Router, _: CancellationToken) -> std::io::Result<()> {
+    Err(std::io::Error::new(
+        std::io::ErrorKind::Unsupported,
+        "Unix sockets aren't supported on this platform",
+    ))
+}
@@ -0,0 +1,147 @@
+use crate::daemon::global::Media;
+use crate::daemon::store;
+use mu_protocol::api::{LaterEntry, LaterKind, NewLaterEntry};
+use mu_protocol::library::Track;
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::time::SystemTime;
+
+/// Albums and tracks put aside to be listened later
+#[derive(Debug, Default)]
+pub struct ListenLater {
+    path: PathBuf,
+    entries: Vec<LaterEntry>,
+}
+
+impl ListenLater {
+    pub fn load(path: PathBuf) -> Self {
+        let entries = store::load(&path, "the listen later list");
+
+        Self { path, entries }
+    }
+
+    fn save(&self) {
+        store::save(&self.path, "the listen later list", &self.entries);
+    }
+
+    pub fn entries(&self) -> Vec<LaterEntry> {
+        self.entries.clone()
+    }
+
+    /// Adds an entry, returns `false` if it was already in the list
+    pub fn add(&mut self, entry: NewLaterEntry) -> bool {
+        if self
+            .entries
+            .iter()
+            .any(|x| x.kind == entry.kind && x.id == entry.id)
+        {
+            return false;
+        }
+
+        self.entries.push(LaterEntry {
+            kind: entry.kind,
+            id: entry.id,
+            added_at: SystemTime::now(),
+            played: vec![],
+        });
+        self.save();
+
+        true
+    }
+
+    /// Replaces the album ids found in `ids`, returns whether the list changed
+    pub fn remap_albums(&mut self, ids: &HashMap<String, String>) -> bool {
+        let mut changed = false;
+        for entry in self
+            .entries
+            .iter_mut()
+            .filter(|x| x.kind == LaterKind::Album)
+        {
+            if let Some(id) = ids.get(&entry.id) {
+                entry.id = id.clone();
+                changed = true;
+            }
+        }
+        if changed {
+            self.save();
+        }
+
+        changed
+    }
+
+    /// Replaces the track ids found in `ids`, returns whether the list changed
+    pub fn remap_tracks(&mut self, ids: &HashMap<String, String>) -> bool {
+        let mut changed = false;
+        for entry in &mut self.entries {
+            if entry.kind == LaterKind::Track {
+                if let Some(id) = ids.get(&entry.id) {
+                    entry.id = id.clone();
+                    changed = true;
+                }
+            }
+            for played in &mut entry.played {
+                if let Some(id) = ids.get(played) {
+                    *played = id.clone();
+                    changed = true;
+                }
+            }
+        }
+        if changed {
+            self.save();
+        }
+
+        changed
+    }
+
+    pub fn remove(&mut self, kind: LaterKind, id: &str) -> bool {
+        let len = self.entries.len();
+        self.entries.retain(|x| !(x.kind == kind && x.id == id));
+        let removed = self.entries.len() != len;
+        if removed {
+            self.save();
+        }
+
+        removed
+    }
+
+    /// Records that `track` was fully played: its entry is dropped, and so is the entry of its
+    /// album once every track of the album has been played. Returns whether the list changed.
+    pub fn played(&mut self, track: &Track, media: &Media) -> bool {
+        let len = self.entries.len();
+        self.entries
+            .retain(|x| !(x.kind == LaterKind::Track && x.id == track.id));
+        let mut changed = self.entries.len() != len;
+
+        if let Some(album) = media.get_album(&track.album_id.as_str().into()) {
+            let album_tracks: Vec<&String> = album
+                .tracks
+                .iter()
+                .filter_map(|x| media.tracks.get(x))
+                .map(|x| &x.id)
+                .collect();
+
+            for entry in self
+                .entries
+                .iter_mut()
+                .filter(|x| x.kind == LaterKind::Album && x.id == album.id)
+            {
+                if !entry.played.contains(&track.id) {
+                    entry.played.push(track.id.clone());
+                    changed = true;
+                }
+            }
+
+            self.entries.retain(|x| {
+                !(x.kind == LaterKind::Album
+                    && x.id == album.id
+                    && album_tracks.iter().all(|t| x.played.contains(*t)))
+            });
+        }
+
+        if changed {
+            self.save();
+        }
+
+        changed
+    }
+}
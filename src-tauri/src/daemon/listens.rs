@@ -0,0 +1,563 @@
+//! Export and import of the play history in the formats other players and services read, for
+//! `GET /history/export` and `POST /history/import`. The export is serialized a batch at a time
+//! as the body is read. Imported plays are matched with the tracks of the library by id, then
+//! by artist and title, and the plays already in the history are left out so that importing a
+//! file twice changes nothing.
+
+use crate::daemon::global::Media;
+use crate::daemon::history::Play;
+use crate::daemon::ndjson::NDJSON;
+use crate::daemon::snapshot::Snapshot;
+use axum::body::{Body, Bytes};
+use chrono::{DateTime, SecondsFormat, Utc};
+use futures::StreamExt;
+use mu_protocol::api::{ExportedPlay, HistoryFormat, HistoryImportReport};
+use mu_protocol::library::Track;
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+use tracing::warn;
+
+/// Plays serialized each time the media is locked
+const BATCH: usize = 256;
+
+/// Columns of the CSV export, the names of the fields of `ExportedPlay`
+const CSV_HEADER: &str = "played_at,track,artists,title,album,duration";
+
+/// Separator of the artists in a CSV field
+const CSV_ARTISTS: &str = "; ";
+
+/// Name of the daemon in the listens exported for ListenBrainz
+const PLAYER: &str = "lorchestre";
+
+#[derive(serde::Serialize)]
+struct Listen<'a> {
+    listened_at: u64,
+    track_metadata: ListenMetadata<'a>,
+}
+
+#[derive(serde::Serialize)]
+struct ListenMetadata<'a> {
+    artist_name: String,
+    track_name: &'a str,
+    release_name: &'a str,
+    additional_info: ListenInfo<'a>,
+}
+
+#[derive(serde::Serialize)]
+struct ListenInfo<'a> {
+    duration_ms: u64,
+    tracknumber: u32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    release_mbid: Option<&'a str>,
+    media_player: &'static str,
+    submission_client: &'static str,
+}
+
+pub fn content_type(format: HistoryFormat) -> &'static str {
+    match format {
+        HistoryFormat::Json => "application/json",
+        HistoryFormat::Csv => "text/csv; charset=utf-8",
+        HistoryFormat::ListenBrainz => NDJSON,
+    }
+}
+
+/// Name of the file of an export, for `Content-Disposition`
+pub fn file_name(format: HistoryFormat) -> &'static str {
+    match format {
+        HistoryFormat::Json => "history.json",
+        HistoryFormat::Csv => "history.csv",
+        HistoryFormat::ListenBrainz => "listens.jsonl",
+    }
+}
+
+fn seconds(time: SystemTime) -> u64 {
+    time.duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+fn exported(play: &Play, track: Option<&Track>) -> ExportedPlay {
+    ExportedPlay {
+        played_at: DateTime::<Utc>::from(play.played_at).to_rfc3339_opts(SecondsFormat::Secs, true),
+        track: play.track.clone(),
+        artists: track.map(|x| x.artists.clone()).unwrap_or_default(),
+        title: track.map(|x| x.title.clone()).unwrap_or_default(),
+        album: track.map(|x| x.album.clone()).unwrap_or_default(),
+        duration: track.map_or(0, |x| x.duration),
+    }
+}
+
+/// `value` quoted when it holds a separator, a quote or a line break
+fn csv_field(value: &str) -> String {
+    if value.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
+/// Writes `play` to `out` in `format`, the `index`th of the export
+fn write(
+    out: &mut Vec<u8>,
+    format: HistoryFormat,
+    index: usize,
+    play: &Play,
+    track: Option<&Track>,
+) -> serde_json::Result<()> {
+    match format {
+        HistoryFormat::Json => {
+            if index > 0 {
+                out.push(b',');
+            }
+            serde_json::to_writer(out, &exported(play, track))
+        }
+        HistoryFormat::Csv => {
+            let play = exported(play, track);
+            let fields = [
+                play.played_at,
+                play.track,
+                play.artists.join(CSV_ARTISTS),
+                play.title,
+                play.album,
+                play.duration.to_string(),
+            ];
+            let fields: Vec<String> = fields.iter().map(|x| csv_field(x)).collect();
+            out.extend(fields.join(",").as_bytes());
+            out.extend(b"\r\n");
+            Ok(())
+        }
+        HistoryFormat::ListenBrainz => {
+            // ListenBrainz has no use for a listen without artist and title
+            let Some(track) = track else {
+                return Ok(());
+            };
+            let listen = Listen {
+                listened_at: seconds(play.played_at),
+                track_metadata: ListenMetadata {
+                    artist_name: track.artists.join(", "),
+                    track_name: &track.title,
+                    release_name: &track.album,
+                    additional_info: ListenInfo {
+                        duration_ms: track.duration * 1000,
+                        tracknumber: track.track,
+                        release_mbid: track.musicbrainz_album_id.as_deref(),
+                        media_player: PLAYER,
+                        submission_client: PLAYER,
+                    },
+                },
+            };
+            serde_json::to_writer(&mut *out, &listen)?;
+            out.push(b'\n');
+            Ok(())
+        }
+    }
+}
+
+/// Streams `plays` in `format`, with the tracks of `media` as they are when each batch is
+/// written
+pub fn export_body(plays: Vec<Play>, media: Arc<Snapshot<Media>>, format: HistoryFormat) -> Body {
+    let (head, tail) = match format {
+        HistoryFormat::Json => (Bytes::from_static(b"["), Bytes::from_static(b"]")),
+        HistoryFormat::Csv => (Bytes::from(format!("{CSV_HEADER}\r\n")), Bytes::new()),
+        HistoryFormat::ListenBrainz => (Bytes::new(), Bytes::new()),
+    };
+    let plays = Arc::new(plays);
+
+    let batches = futures::stream::unfold(0, move |start| {
+        let (media, plays) = (Arc::clone(&media), Arc::clone(&plays));
+        async move {
+            if start >= plays.len() {
+                return None;
+            }
+            let end = (start + BATCH).min(plays.len());
+            let media = media.load();
+            let mut chunk = vec![];
+            for (i, play) in plays[start..end].iter().enumerate() {
+                let track = media.get_track(&play.track.as_str().into());
+                if let Err(e) = write(&mut chunk, format, start + i, play, track.as_ref()) {
+                    warn!("history: unable to export a play of {}: {e}", play.track);
+                }
+            }
+            Some((Ok::<_, std::io::Error>(Bytes::from(chunk)), end))
+        }
+    });
+    let stream = futures::stream::once(async move { Ok(head) })
+        .chain(batches)
+        .chain(futures::stream::once(async move { Ok(tail) }));
+
+    Body::from_stream(stream)
+}
+
+/// A play read from an import, before its track is found
+struct Incoming {
+    played_at: SystemTime,
+    track: Option<String>,
+    artists: Vec<String>,
+    title: String,
+    album: String,
+}
+
+fn parse_time(value: &str) -> Option<SystemTime> {
+    if let Ok(secs) = value.trim().parse::<u64>() {
+        return Some(SystemTime::UNIX_EPOCH + Duration::from_secs(secs));
+    }
+    let time = DateTime::parse_from_rfc3339(value.trim()).ok()?;
+    Some(time.with_timezone(&Utc).into())
+}
+
+fn from_exported(play: ExportedPlay) -> Option<Incoming> {
+    Some(Incoming {
+        played_at: parse_time(&play.played_at)?,
+        track: Some(play.track).filter(|x| !x.is_empty()),
+        artists: play.artists,
+        title: play.title,
+        album: play.album,
+    })
+}
+
+/// The records of `text`, as RFC 4180 reads them
+fn csv_records(text: &str) -> Vec<Vec<String>> {
+    let mut records = vec![];
+    let mut record = vec![];
+    let mut field = String::new();
+    let mut quoted = false;
+    let mut chars = text.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' if quoted && chars.peek() == Some(&'"') => {
+                chars.next();
+                field.push('"');
+            }
+            '"' => quoted = !quoted,
+            ',' if !quoted => record.push(std::mem::take(&mut field)),
+            '\r' if !quoted => {}
+            '\n' if !quoted => {
+                record.push(std::mem::take(&mut field));
+                records.push(std::mem::take(&mut record));
+            }
+            c => field.push(c),
+        }
+    }
+    if !field.is_empty() || !record.is_empty() {
+        record.push(field);
+        records.push(record);
+    }
+    records
+}
+
+/// The fields of the listen `value` of ListenBrainz
+fn from_listen(value: &serde_json::Value) -> Option<Incoming> {
+    let metadata = value.get("track_metadata")?;
+    let text = |key: &str| {
+        metadata
+            .get(key)
+            .and_then(|x| x.as_str())
+            .unwrap_or_default()
+            .to_string()
+    };
+    Some(Incoming {
+        played_at: SystemTime::UNIX_EPOCH
+            + Duration::from_secs(value.get("listened_at")?.as_u64()?),
+        track: None,
+        artists: vec![text("artist_name")],
+        title: text("track_name"),
+        album: text("release_name"),
+    })
+}
+
+/// The plays of `data` in `format`, and the number of entries that couldn't be read
+fn read(data: &str, format: HistoryFormat) -> Result<(Vec<Incoming>, usize), String> {
+    let mut plays = vec![];
+    let mut invalid = 0;
+    match format {
+        HistoryFormat::Json => {
+            let entries: Vec<serde_json::Value> =
+                serde_json::from_str(data).map_err(|e| format!("invalid json: {e}"))?;
+            for entry in entries {
+                match serde_json::from_value(entry).ok().and_then(from_exported) {
+                    Some(play) => plays.push(play),
+                    None => invalid += 1,
+                }
+            }
+        }
+        HistoryFormat::Csv => {
+            let mut records = csv_records(data).into_iter();
+            let header = records.next().ok_or("empty csv")?;
+            let column = |name: &str| header.iter().position(|x| x.trim() == name);
+            let (Some(played_at), Some(title)) = (column("played_at"), column("title")) else {
+                return Err("the csv has no `played_at` and `title` columns".to_string());
+            };
+            let (track, artists, album) = (column("track"), column("artists"), column("album"));
+            for record in records.filter(|x| x.iter().any(|x| !x.is_empty())) {
+                let field =
+                    |i: Option<usize>| i.and_then(|i| record.get(i)).cloned().unwrap_or_default();
+                let Some(time) = parse_time(&field(Some(played_at))) else {
+                    invalid += 1;
+                    continue;
+                };
+                plays.push(Incoming {
+                    played_at: time,
+                    track: Some(field(track)).filter(|x| !x.is_empty()),
+                    artists: field(artists)
+                        .split(CSV_ARTISTS.trim())
+                        .map(|x| x.trim().to_string())
+                        .filter(|x| !x.is_empty())
+                        .collect(),
+                    title: field(Some(title)),
+                    album: field(album),
+                });
+            }
+        }
+        // The exports of ListenBrainz were an array before being split in lines
+        HistoryFormat::ListenBrainz if data.trim_start().starts_with('[') => {
+            let listens: Vec<serde_json::Value> =
+                serde_json::from_str(data).map_err(|e| format!("invalid json: {e}"))?;
+            for listen in &listens {
+                match from_listen(listen) {
+                    Some(play) => plays.push(play),
+                    None => invalid += 1,
+                }
+            }
+        }
+        HistoryFormat::ListenBrainz => {
+            for line in data.lines().filter(|x| !x.trim().is_empty()) {
+                match serde_json::from_str(line)
+                    .ok()
+                    .as_ref()
+                    .and_then(from_listen)
+                {
+                    Some(play) => plays.push(play),
+                    None => invalid += 1,
+                }
+            }
+        }
+    }
+    Ok((plays, invalid))
+}
+
+fn normalize(value: &str) -> String {
+    value.trim().to_lowercase()
+}
+
+/// The tracks of `media` by artist and title. A track with several artists is found by each
+/// of them and by all of them joined, as the other players write them.
+fn index(media: &Media) -> HashMap<(String, String), Vec<&Track>> {
+    let mut index: HashMap<(String, String), Vec<&Track>> = HashMap::new();
+    for track in media.tracks.values() {
+        let title = normalize(&track.title);
+        let mut artists: HashSet<String> = track.artists.iter().map(|x| normalize(x)).collect();
+        for separator in [", ", " & ", "; ", " feat. "] {
+            artists.insert(normalize(&track.artists.join(separator)));
+        }
+        for artist in artists {
+            index
+                .entry((artist, title.clone()))
+                .or_default()
+                .push(track);
+        }
+    }
+    index
+}
+
+/// The plays of `data` in `format` to add to `history`, matched with the tracks of `media`, with
+/// the report of the import
+pub fn import(
+    data: &str,
+    format: HistoryFormat,
+    media: &Media,
+    history: &[Play],
+) -> Result<(Vec<Play>, HistoryImportReport), String> {
+    let (incoming, invalid) = read(data, format)?;
+    let index = index(media);
+    let mut seen: HashSet<(String, u64)> = history
+        .iter()
+        .map(|x| (x.track.clone(), seconds(x.played_at)))
+        .collect();
+    let mut unmatched = HashSet::new();
+    let mut report = HistoryImportReport {
+        invalid,
+        ..Default::default()
+    };
+    let mut plays = vec![];
+
+    for play in incoming {
+        let by_id = play
+            .track
+            .as_deref()
+            .and_then(|x| media.get_track(&x.into()))
+            .map(|x| x.id);
+        let title = normalize(&play.title);
+        let by_name = || {
+            let candidates: Vec<&Track> = play
+                .artists
+                .iter()
+                .chain([&play.artists.join(", ")])
+                .filter_map(|x| index.get(&(normalize(x), title.clone())))
+                .flatten()
+                .copied()
+                .collect();
+            candidates
+                .iter()
+                .find(|x| normalize(&x.album) == normalize(&play.album))
+                .or(candidates.first())
+                .map(|x| x.id.clone())
+        };
+        let Some(track) = by_id.or_else(by_name) else {
+            let name = format!("{} - {}", play.artists.join(", "), play.title);
+            if unmatched.insert(name.clone()) {
+                report.unmatched.push(name);
+            }
+            continue;
+        };
+
+        if !seen.insert((track.clone(), seconds(play.played_at))) {
+            report.duplicates += 1;
+            continue;
+        }
+        plays.push(Play {
+            track,
+            played_at: play.played_at,
+        });
+    }
+    report.imported = plays.len();
+
+    Ok((plays, report))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A library of two tracks, `a` and `b`
+    fn media() -> Media {
+        let mut media = Media::default();
+        for (id, artists, title, album) in [
+            ("a", vec!["Alice"], "Song, with a comma", "First"),
+            ("b", vec!["Alice", "Bob"], "Duet", "Second"),
+        ] {
+            media.add_song(Track {
+                id: id.to_string(),
+                artists: artists.into_iter().map(|x| x.to_string()).collect(),
+                title: title.to_string(),
+                album: album.to_string(),
+                file_path: format!("/music/{id}.flac"),
+                ..Default::default()
+            });
+        }
+        media
+    }
+
+    fn at(secs: u64) -> SystemTime {
+        SystemTime::UNIX_EPOCH + Duration::from_secs(secs)
+    }
+
+    /// Tracks and times of `plays`
+    fn played(plays: &[Play]) -> Vec<(&str, u64)> {
+        plays
+            .iter()
+            .map(|x| (x.track.as_str(), seconds(x.played_at)))
+            .collect()
+    }
+
+    #[test]
+    fn csv_round_trip() {
+        let media = media();
+        let plays = vec![
+            Play {
+                track: "a".to_string(),
+                played_at: at(1_700_000_000),
+            },
+            Play {
+                track: "b".to_string(),
+                played_at: at(1_700_000_300),
+            },
+        ];
+        let mut out = format!("{CSV_HEADER}\r\n").into_bytes();
+        for (i, play) in plays.iter().enumerate() {
+            let track = media.get_track(&play.track.as_str().into());
+            write(&mut out, HistoryFormat::Csv, i, play, track.as_ref()).unwrap();
+        }
+        let csv = String::from_utf8(out).unwrap();
+        assert!(csv.contains("\"Song, with a comma\""));
+
+        let (imported, report) = import(&csv, HistoryFormat::Csv, &media, &[]).unwrap();
+        assert_eq!(played(&imported), played(&plays));
+        assert_eq!(report.imported, 2);
+        // Importing it again adds nothing
+        let (again, report) = import(&csv, HistoryFormat::Csv, &media, &plays).unwrap();
+        assert!(again.is_empty());
+        assert_eq!(report.duplicates, 2);
+    }
+
+    #[test]
+    fn malformed_csv_rows() {
+        let csv = "title,artists,played_at\n\
+                   \"Song, with a comma\",Alice,1700000000\n\
+                   Duet,Alice; Bob,yesterday\n\
+                   ,,\n\
+                   Duet,\"Alice, Bob\",2023-11-14T22:13:20Z\n\
+                   Unknown,Nobody,1700000100\n\
+                   Unknown,Nobody,1700000200\n";
+        let (plays, report) = import(csv, HistoryFormat::Csv, &media(), &[]).unwrap();
+
+        assert_eq!(
+            played(&plays),
+            vec![("a", 1_700_000_000), ("b", 1_700_000_000)]
+        );
+        assert_eq!(report.invalid, 1);
+        assert_eq!(report.unmatched, vec!["Nobody - Unknown"]);
+
+        assert!(import(
+            "artist,title\nAlice,Duet\n",
+            HistoryFormat::Csv,
+            &media(),
+            &[]
+        )
+        .is_err());
+        assert!(import("", HistoryFormat::Csv, &media(), &[]).is_err());
+    }
+
+    #[test]
+    fn duplicates() {
+        let json = r#"[
+            {"played_at": "2023-11-14T22:13:20Z", "track": "a", "duration": 0},
+            {"played_at": "1700000000", "track": "a", "duration": 0},
+            {"played_at": "2023-11-14T22:13:20+01:00", "track": "a", "duration": 0},
+            {"played_at": "2023-11-14T22:13:20Z", "track": "", "artists": ["Alice"], "title": "duet", "duration": 0}
+        ]"#;
+        let history = [Play {
+            track: "b".to_string(),
+            played_at: at(1_700_000_000),
+        }];
+        let (plays, report) = import(json, HistoryFormat::Json, &media(), &history).unwrap();
+
+        // The same track at the same second, in the file or already in the history
+        assert_eq!(
+            played(&plays),
+            vec![("a", 1_700_000_000), ("a", 1_699_996_400)]
+        );
+        assert_eq!(report.duplicates, 2);
+    }
+
+    #[test]
+    fn malformed_listens() {
+        let listens = [
+            r#"{"listened_at": 1700000000, "track_metadata": {"artist_name": "Alice, Bob", "track_name": "Duet"}}"#,
+            "not json",
+            r#"{"listened_at": "soon", "track_metadata": {"artist_name": "Alice", "track_name": "Duet"}}"#,
+            r#"{"listened_at": 1700000000}"#,
+            "",
+            r#"{"listened_at": 1700000060, "track_metadata": {"artist_name": "alice", "track_name": "SONG, WITH A COMMA"}}"#,
+        ]
+        .join("\n");
+        let (plays, report) = import(&listens, HistoryFormat::ListenBrainz, &media(), &[]).unwrap();
+
+        assert_eq!(
+            played(&plays),
+            vec![("b", 1_700_000_000), ("a", 1_700_000_060)]
+        );
+        assert_eq!(report.invalid, 3);
+        assert!(import("[1,", HistoryFormat::ListenBrainz, &media(), &[]).is_err());
+    }
+}
@@ -0,0 +1,83 @@
+//! Conversions to the slim representations of `mu_protocol::lite`, returned by the browse and
+//! search endpoints when called with `?lite=true`.
+
+use crate::daemon::global::Media;
+use crate::daemon::proxy;
+use mu_protocol::api::SearchResults;
+use mu_protocol::library::{Album, Playlist, Track};
+use mu_protocol::lite::{LiteAlbum, LiteAlbumTracks, LitePlaylist, LiteResults, LiteTrack};
+use std::path::PathBuf;
+
+fn cover_url(track: &Track) -> String {
+    proxy::url(&format!("/cover/{}{}", track.album_id, track.cover_ext))
+}
+
+pub fn track(track: &Track) -> LiteTrack {
+    LiteTrack {
+        id: track.id.clone(),
+        title: track.title.clone(),
+        artist: track.artists.join(", "),
+        duration: track.duration,
+        cover_url: cover_url(track),
+    }
+}
+
+fn tracks_of<'a>(media: &'a Media, paths: &'a [PathBuf]) -> impl Iterator<Item = &'a Track> {
+    paths.iter().filter_map(|x| media.tracks.get(x))
+}
+
+pub fn album(media: &Media, album: &Album) -> LiteAlbum {
+    let first = tracks_of(media, &album.tracks).next();
+    LiteAlbum {
+        id: album.id.clone(),
+        title: album.name.clone(),
+        artist: album.artists.join(", "),
+        duration: tracks_of(media, &album.tracks).map(|x| x.duration).sum(),
+        cover_url: album.cover_url.clone().unwrap_or_else(|| {
+            first.map_or(proxy::url(&format!("/cover/{}.png", album.id)), cover_url)
+        }),
+    }
+}
+
+pub fn album_tracks(media: &Media, album: &Album) -> LiteAlbumTracks {
+    let mut tracks: Vec<&Track> = tracks_of(media, &album.tracks).collect();
+    tracks.sort_by_key(|x| x.track);
+
+    LiteAlbumTracks {
+        album: self::album(media, album),
+        tracks: tracks.into_iter().map(self::track).collect(),
+    }
+}
+
+pub fn playlist(media: &Media, playlist: &Playlist) -> LitePlaylist {
+    LitePlaylist {
+        id: playlist.id.clone(),
+        title: playlist.name.clone(),
+        duration: tracks_of(media, &playlist.tracks).map(|x| x.duration).sum(),
+    }
+}
+
+pub fn results(media: &Media, results: &SearchResults) -> LiteResults {
+    LiteResults {
+        albums: results.albums.iter().map(|x| album(media, x)).collect(),
+        playlists: results
+            .playlists
+            .iter()
+            .map(|x| playlist(media, x))
+            .collect(),
+        tracks: results.tracks.iter().map(track).collect(),
+        lyrics: results.lyrics.clone(),
+    }
+}
+
+pub fn media(media: &Media) -> LiteResults {
+    let mut tracks: Vec<&Track> = media.tracks.values().collect();
+    tracks.sort_by(|a, b| a.file_path.cmp(&b.file_path));
+
+    LiteResults {
+        albums: media.albums.iter().map(|x| album(media, x)).collect(),
+        playlists: media.playlists.iter().map(|x| playlist(media, x)).collect(),
+        tracks: tracks.into_iter().map(track).collect(),
+        lyrics: vec![],
+    }
+}
@@ -0,0 +1,454 @@
+//! LRC lyrics, the `.lrc` files next to the tracks. Lines start with one or more
+//! `[mm:ss.xx]` timestamps, and enhanced LRC times each word with `<mm:ss.xx>` tags.
+//! The files come from many tools: UTF-8 or UTF-16, with or without a BOM, any line ending.
+//! Lyrics edited with `PUT /track/:id/lyrics` are written back as UTF-8.
+
+use crate::daemon::tags;
+use lofty::prelude::*;
+use lofty::probe::Probe;
+use mu_protocol::api::{LyricsTranslation, NewLyrics, TrackLyrics};
+use mu_protocol::library::{LyricLine, LyricWord, Track};
+use std::path::Path;
+use tracing::warn;
+
+/// Distance in milliseconds under which a translated line goes with a line of the lyrics
+const TRANSLATION_TOLERANCE: i64 = 100;
+
+/// `bytes` as text, decoding UTF-16 when its BOM is there and UTF-8 otherwise
+pub fn decode(bytes: &[u8]) -> String {
+    let utf16 = |bytes: &[u8], from: fn([u8; 2]) -> u16| {
+        let units: Vec<u16> = bytes.chunks_exact(2).map(|x| from([x[0], x[1]])).collect();
+        String::from_utf16_lossy(&units)
+    };
+    match bytes {
+        [0xEF, 0xBB, 0xBF, rest @ ..] => String::from_utf8_lossy(rest).into_owned(),
+        [0xFF, 0xFE, rest @ ..] => utf16(rest, u16::from_le_bytes),
+        [0xFE, 0xFF, rest @ ..] => utf16(rest, u16::from_be_bytes),
+        _ => String::from_utf8_lossy(bytes).into_owned(),
+    }
+}
+
+/// `mm:ss`, `mm:ss.xx`, `mm:ss.xxx` or `mm:ss:xx` in milliseconds
+fn parse_time(value: &str) -> Option<i64> {
+    let value = value.trim();
+    let (clock, fraction) = match value.split_once('.') {
+        Some((clock, fraction)) => (clock, Some(fraction)),
+        None => (value, None),
+    };
+    let digits = |x: &str| !x.is_empty() && x.bytes().all(|c| c.is_ascii_digit());
+
+    let parts: Vec<&str> = clock.split(':').collect();
+    let (minutes, seconds, fraction) = match (parts.as_slice(), fraction) {
+        ([minutes, seconds], fraction) => (*minutes, *seconds, fraction),
+        ([minutes, seconds, hundredths], None) => (*minutes, *seconds, Some(*hundredths)),
+        _ => return None,
+    };
+    if !digits(minutes) || !digits(seconds) || fraction.is_some_and(|x| !digits(x)) {
+        return None;
+    }
+
+    // `.5` is half a second, `.05` and `.050` are fifty milliseconds
+    let millis = fraction.map_or(0, |x| {
+        let x = &x[..x.len().min(3)];
+        x.parse::<i64>().unwrap_or(0) * 10_i64.pow(3 - x.len() as u32)
+    });
+    Some(minutes.parse::<i64>().ok()? * 60_000 + seconds.parse::<i64>().ok()? * 1000 + millis)
+}
+
+/// The words of an enhanced LRC line and its text without their tags. Text before the
+/// first `<mm:ss.xx>` tag is kept in the line but has no timing.
+fn parse_words(content: &str) -> (String, Vec<LyricWord>) {
+    let mut text = String::new();
+    let mut words: Vec<LyricWord> = vec![];
+    let mut rest = content;
+
+    while let Some(start) = rest.find('<') {
+        let Some(end) = rest[start..].find('>').map(|x| start + x) else {
+            break;
+        };
+        let Some(t) = parse_time(&rest[start + 1..end]) else {
+            // Not a timestamp, e.g. `<3`
+            text.push_str(&rest[..=end]);
+            if let Some(word) = words.last_mut() {
+                word.text.push_str(&rest[..=end]);
+            }
+            rest = &rest[end + 1..];
+            continue;
+        };
+        text.push_str(&rest[..start]);
+        if let Some(word) = words.last_mut() {
+            word.text.push_str(&rest[..start]);
+        }
+        words.push(LyricWord {
+            t,
+            text: String::new(),
+        });
+        rest = &rest[end + 1..];
+    }
+    text.push_str(rest);
+    if let Some(word) = words.last_mut() {
+        word.text.push_str(rest);
+    }
+
+    // A closing tag only marks when the last word ends
+    words.retain(|x| !x.text.trim().is_empty());
+    // The words keep the spaces around them, the line doesn't need them twice
+    let text = text.split_whitespace().collect::<Vec<_>>().join(" ");
+    (text, words)
+}
+
+/// The timed lines of `text`, sorted by time, with the `[offset:±ms]` tag applied. Lines
+/// with many timestamps, like a chorus, are repeated at each of them.
+pub fn parse(text: &str) -> Vec<LyricLine> {
+    let mut offset = 0;
+    let mut lines = vec![];
+
+    for line in text.trim_start_matches('\u{feff}').split(['\n', '\r']) {
+        let mut rest = line.trim();
+        let mut times = vec![];
+        while let Some((tag, after)) = rest.strip_prefix('[').and_then(|x| x.split_once(']')) {
+            if let Some(time) = parse_time(tag) {
+                times.push(time);
+            } else if let Some((key, value)) = tag.split_once(':') {
+                if key.trim().eq_ignore_ascii_case("offset") {
+                    offset = value
+                        .trim()
+                        .trim_start_matches('+')
+                        .parse()
+                        .unwrap_or(offset);
+                }
+            }
+            rest = after.trim_start();
+        }
+        let Some(&first) = times.first() else {
+            continue;
+        };
+
+        let (text, words) = parse_words(rest);
+        for start_time in times {
+            // Words are timed for the first timestamp of the line
+            let words = words
+                .iter()
+                .map(|x| LyricWord {
+                    t: x.t + start_time - first,
+                    text: x.text.clone(),
+                })
+                .collect();
+            lines.push(LyricLine {
+                start_time,
+                text: text.clone(),
+                words,
+            });
+        }
+    }
+
+    // A positive offset shows the lyrics sooner
+    for line in lines.iter_mut() {
+        line.start_time = (line.start_time - offset).max(0);
+        for word in line.words.iter_mut() {
+            word.t = (word.t - offset).max(0);
+        }
+    }
+    lines.sort_by_key(|x| x.start_time);
+    lines
+}
+
+/// The timed lines of the `.lrc` file at `path`
+pub fn read(path: &Path) -> std::io::Result<Vec<LyricLine>> {
+    Ok(parse(&decode(&std::fs::read(path)?)))
+}
+
+/// `ms` as `mm:ss.xx`
+fn format_time(ms: i64) -> String {
+    let ms = ms.max(0);
+    format!(
+        "{:02}:{:02}.{:02}",
+        ms / 60_000,
+        ms / 1000 % 60,
+        ms % 1000 / 10
+    )
+}
+
+/// `lines` as LRC, the words timed with `<mm:ss.xx>` tags when they are
+pub fn format(lines: &[LyricLine]) -> String {
+    let mut text = String::new();
+    for line in lines {
+        text.push_str(&format!("[{}]", format_time(line.start_time)));
+        if line.words.is_empty() {
+            text.push_str(&line.text);
+        } else {
+            for word in &line.words {
+                text.push_str(&format!("<{}>{}", format_time(word.t), word.text));
+            }
+        }
+        text.push('\n');
+    }
+    text
+}
+
+/// `library.lyrics_target` is `tag`
+pub fn embedded(config: &lorconf::Config) -> bool {
+    config
+        .library
+        .as_ref()
+        .and_then(|library| library.lyrics_target.clone())
+        .or(lorconf::Library::default().lyrics_target)
+        .is_some_and(|x| x == "tag")
+}
+
+/// Writes `lyrics` for `track`, to its lyrics tag when `embedded` and it has no `.lrc` file,
+/// which is kept in use, else to the `.lrc` file. Empty lyrics remove them. Returns the lines
+/// of the track, empty for plain lyrics.
+pub fn write(
+    track: &Track,
+    lyrics: &NewLyrics,
+    embedded: bool,
+) -> Result<Vec<LyricLine>, Box<dyn std::error::Error>> {
+    let text = if lyrics.synced.is_empty() {
+        lyrics
+            .plain
+            .as_ref()
+            .map(|x| x.trim().to_string())
+            .filter(|x| !x.is_empty())
+    } else {
+        Some(format(&lyrics.synced))
+    };
+
+    let file = Path::new(&track.file_path);
+    let lrc_path = file.with_extension("lrc");
+    if embedded && !lrc_path.exists() {
+        tags::write(file, |tag| match &text {
+            Some(text) => {
+                tag.insert_text(ItemKey::Lyrics, text.clone());
+            }
+            None => tag.remove_key(&ItemKey::Lyrics),
+        })?;
+    } else {
+        match &text {
+            Some(text) => std::fs::write(&lrc_path, text)?,
+            None if lrc_path.exists() => std::fs::remove_file(&lrc_path)?,
+            None => {}
+        }
+    }
+
+    Ok(text.as_deref().map(parse).unwrap_or_default())
+}
+
+/// The lines of `text` without timestamps and tags, for lyrics that aren't synced
+pub fn plain(text: &str) -> Option<String> {
+    let lines: Vec<&str> = text
+        .trim_start_matches('\u{feff}')
+        .lines()
+        .map(|x| x.trim())
+        .filter(|x| !(x.starts_with('[') && x.ends_with(']')))
+        .collect();
+    let text = lines.join("\n").trim().to_string();
+    (!text.is_empty()).then_some(text)
+}
+
+/// `fr` for `song.fr.lrc` when `stem` is `song`, or `pt-BR` for `song.pt-BR.lrc`
+fn language<'a>(name: &'a str, stem: &str) -> Option<&'a str> {
+    let language = name
+        .strip_prefix(stem)?
+        .strip_prefix('.')?
+        .strip_suffix(".lrc")?;
+    let (code, region) = language.split_once(['-', '_']).unwrap_or((language, ""));
+    let valid = (2..=3).contains(&code.len())
+        && code.chars().all(|x| x.is_ascii_alphabetic())
+        && (region.is_empty() || region.chars().all(|x| x.is_ascii_alphanumeric()));
+    valid.then_some(language)
+}
+
+/// The translations next to `file`, lined up with `synced`. Files named like another
+/// track, e.g. `song.fr.lrc` with `song.fr.flac`, are its lyrics rather than a translation.
+fn translations(file: &Path, synced: &[LyricLine]) -> Vec<LyricsTranslation> {
+    let (Some(dir), Some(stem)) = (file.parent(), file.file_stem().and_then(|x| x.to_str())) else {
+        return vec![];
+    };
+    let Ok(entries) = std::fs::read_dir(dir) else {
+        return vec![];
+    };
+    let names: Vec<String> = entries
+        .flatten()
+        .filter_map(|x| x.file_name().into_string().ok())
+        .collect();
+
+    let mut translations = vec![];
+    for name in &names {
+        let Some(language) = language(name, stem) else {
+            continue;
+        };
+        let track = format!("{stem}.{language}.");
+        if names
+            .iter()
+            .any(|x| x.starts_with(&track) && !x.ends_with(".lrc"))
+        {
+            continue;
+        }
+        let lines = match read(&dir.join(name)) {
+            Ok(lines) => lines,
+            Err(e) => {
+                warn!("Unable to read the lyrics `{name}`: {e}");
+                continue;
+            }
+        };
+        translations.push(LyricsTranslation {
+            language: language.to_string(),
+            lines: synced
+                .iter()
+                .map(|line| {
+                    lines
+                        .iter()
+                        .filter(|x| (x.start_time - line.start_time).abs() <= TRANSLATION_TOLERANCE)
+                        .min_by_key(|x| (x.start_time - line.start_time).abs())
+                        .map(|x| x.text.clone())
+                })
+                .collect(),
+        });
+    }
+    translations.sort_by(|a, b| a.language.cmp(&b.language));
+    translations
+}
+
+/// The lyrics of `track`: the `.lrc` file next to it, its translations, and the lyrics tag
+/// of the file. Parts of a file split by a cue sheet have none.
+pub fn lyrics(track: &Track) -> TrackLyrics {
+    let mut lyrics = TrackLyrics {
+        id: track.id.clone(),
+        synced: vec![],
+        plain: None,
+        translations: vec![],
+    };
+    if track.source.is_some() {
+        return lyrics;
+    }
+
+    let file = Path::new(&track.file_path);
+    let lrc_path = file.with_extension("lrc");
+    if lrc_path.exists() {
+        match std::fs::read(&lrc_path) {
+            Ok(bytes) => {
+                let text = decode(&bytes);
+                lyrics.synced = parse(&text);
+                if lyrics.synced.is_empty() {
+                    lyrics.plain = plain(&text);
+                }
+            }
+            Err(e) => warn!("Unable to read the lyrics `{}`: {e}", lrc_path.display()),
+        }
+    }
+
+    if lyrics.synced.is_empty() && lyrics.plain.is_none() {
+        // Some taggers store LRC in the lyrics tag
+        let tag = Probe::open(file).and_then(|x| x.read()).ok().and_then(|x| {
+            let tag = x.primary_tag().or(x.first_tag())?;
+            tag.get_string(&ItemKey::Lyrics).map(|x| x.to_string())
+        });
+        if let Some(text) = tag {
+            lyrics.synced = parse(&text);
+            if lyrics.synced.is_empty() {
+                lyrics.plain = plain(&text);
+            }
+        }
+    }
+
+    if !lyrics.synced.is_empty() {
+        lyrics.translations = translations(file, &lyrics.synced);
+    }
+    lyrics
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Start and text of each of `lines`
+    fn timed(lines: &[LyricLine]) -> Vec<(i64, &str)> {
+        lines
+            .iter()
+            .map(|x| (x.start_time, x.text.as_str()))
+            .collect()
+    }
+
+    #[test]
+    fn time_formats() {
+        assert_eq!(parse_time("01:02"), Some(62_000));
+        assert_eq!(parse_time("01:02.5"), Some(62_500));
+        assert_eq!(parse_time("01:02.05"), Some(62_050));
+        assert_eq!(parse_time("01:02.050"), Some(62_050));
+        assert_eq!(parse_time("01:02:34"), Some(62_340));
+        assert_eq!(parse_time(" 10:00.00 "), Some(600_000));
+    }
+
+    #[test]
+    fn time_not_a_timestamp() {
+        assert_eq!(parse_time("ar:Someone"), None);
+        assert_eq!(parse_time("01"), None);
+        assert_eq!(parse_time("01:xx"), None);
+        assert_eq!(parse_time("-1:00"), None);
+        assert_eq!(parse_time(""), None);
+    }
+
+    #[test]
+    fn lines_sorted_by_time() {
+        let lines = parse("[ti:Song]\n[00:05.00]Second\r\n[00:01.50]First\n\nNo time\n");
+        assert_eq!(timed(&lines), vec![(1500, "First"), (5000, "Second")]);
+    }
+
+    #[test]
+    fn many_timestamps_on_a_line() {
+        let lines = parse("[00:10.00][00:30.00] Chorus\n[00:20.00]Verse");
+        assert_eq!(
+            timed(&lines),
+            vec![(10_000, "Chorus"), (20_000, "Verse"), (30_000, "Chorus")]
+        );
+    }
+
+    #[test]
+    fn offsets() {
+        // A positive offset shows the lyrics sooner, without going before the start
+        let lines = parse("[offset:+500]\n[00:00.20]Early\n[00:02.00]Late");
+        assert_eq!(timed(&lines), vec![(0, "Early"), (1500, "Late")]);
+
+        let lines = parse("[offset:-250]\n[00:01.00]Line");
+        assert_eq!(timed(&lines), vec![(1250, "Line")]);
+
+        // Unreadable offsets are ignored
+        let lines = parse("[offset:soon]\n[00:01.00]Line");
+        assert_eq!(timed(&lines), vec![(1000, "Line")]);
+    }
+
+    #[test]
+    fn words_follow_each_timestamp() {
+        let lines = parse("[00:01.00][00:11.00]<00:01.00>Hello <00:01.50>world<00:02.00>");
+        assert_eq!(lines.len(), 2);
+        assert_eq!(lines[0].text, "Hello world");
+        let words = |line: &LyricLine| -> Vec<(i64, String)> {
+            line.words.iter().map(|x| (x.t, x.text.clone())).collect()
+        };
+        assert_eq!(
+            words(&lines[0]),
+            vec![(1000, "Hello ".to_string()), (1500, "world".to_string())]
+        );
+        assert_eq!(
+            words(&lines[1]),
+            vec![
+                (11_000, "Hello ".to_string()),
+                (11_500, "world".to_string())
+            ]
+        );
+    }
+
+    #[test]
+    fn format_round_trip() {
+        let text = "[00:01.50]First\n[01:02.03]<01:02.03>Timed <01:03.00>words\n";
+        assert_eq!(format(&parse(text)), text);
+    }
+
+    #[test]
+    fn utf16_with_bom() {
+        let mut bytes = vec![0xFF, 0xFE];
+        bytes.extend("[00:01.00]Été".encode_utf16().flat_map(u16::to_le_bytes));
+        assert_eq!(timed(&parse(&decode(&bytes))), vec![(1000, "Été")]);
+    }
+}
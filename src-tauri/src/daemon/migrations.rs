@@ -0,0 +1,111 @@
+//! Versions of the layout of the cache folder, e.g. the names of the covers or the format of
+//! `.cache.json`. The version the cache was written with is kept in its `CACHE_VERSION` file,
+//! and each change of the layout comes with a migration upgrading the caches in place on start.
+//! Caches written by a newer daemon, or whose migration failed, are cleared for the scan to
+//! rebuild them rather than being read wrong.
+
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use tracing::{info, warn};
+
+/// Version of the layout written by this daemon
+pub const CACHE_VERSION: u32 = 1;
+
+const VERSION_FILE: &str = "CACHE_VERSION";
+
+/// Files and folders of the cache of a library, all rebuilt by the scans and the players
+const LIBRARY_CACHE: [&str; 9] = [
+    ".cache.json",
+    ".cache.list",
+    ".cache.sums",
+    "covers",
+    "palettes.json",
+    "analysis.json",
+    "cue",
+    "hls",
+    "seek",
+];
+
+struct Migration {
+    /// Version the cache is at once migrated
+    to: u32,
+    description: &'static str,
+    /// Upgrades the cache folders of the libraries
+    run: fn(&[PathBuf]) -> io::Result<()>,
+}
+
+/// Migrations in order, the one to [`CACHE_VERSION`] last
+const MIGRATIONS: [Migration; 1] = [Migration {
+    to: 1,
+    description: "versioning of the cache",
+    run: |_| Ok(()),
+}];
+
+/// Version of the cache in `root`, 0 for a cache written before the versions, `None` when the
+/// file can't be read
+fn version(root: &Path) -> Option<u32> {
+    match fs::read_to_string(root.join(VERSION_FILE)) {
+        Ok(version) => version.trim().parse().ok(),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Some(0),
+        Err(_) => None,
+    }
+}
+
+/// Removes what the scans rebuild from `dirs`
+fn clear(dirs: &[PathBuf]) {
+    for dir in dirs {
+        for name in LIBRARY_CACHE {
+            let path = dir.join(name);
+            let removed = if path.is_dir() {
+                fs::remove_dir_all(&path)
+            } else {
+                fs::remove_file(&path)
+            };
+            match removed {
+                Err(e) if e.kind() != io::ErrorKind::NotFound => {
+                    warn!("migrations: unable to remove `{}`: {e}", path.display())
+                }
+                _ => {}
+            }
+        }
+    }
+}
+
+/// Brings the cache folder `root` and the ones of the libraries, `libraries`, to
+/// [`CACHE_VERSION`], blocking
+pub fn run(root: &Path, libraries: &[PathBuf]) {
+    let mut dirs = vec![root.to_path_buf()];
+    dirs.extend(libraries.iter().filter(|x| x.as_path() != root).cloned());
+
+    match version(root) {
+        Some(CACHE_VERSION) => return,
+        Some(from) if from < CACHE_VERSION => {
+            for migration in MIGRATIONS.iter().filter(|x| x.to > from) {
+                info!(
+                    "migrations: cache {} -> {}, {}",
+                    migration.to - 1,
+                    migration.to,
+                    migration.description
+                );
+                if let Err(e) = (migration.run)(&dirs) {
+                    warn!("migrations: {e}, the cache is cleared");
+                    clear(&dirs);
+                    break;
+                }
+            }
+        }
+        Some(from) => {
+            warn!("migrations: cache written by a newer daemon (version {from}), cleared");
+            clear(&dirs);
+        }
+        None => {
+            warn!("migrations: unknown cache version, the cache is cleared");
+            clear(&dirs);
+        }
+    }
+
+    if let Err(e) = fs::write(root.join(VERSION_FILE), format!("{CACHE_VERSION}\n")) {
+        warn!("migrations: unable to write the cache version: {e}");
+    }
+}
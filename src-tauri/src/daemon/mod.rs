@@ -1,5 +1,74 @@
+pub mod analysis;
+pub mod archive;
+pub mod artists;
+pub mod artwork;
+pub mod audiobooks;
+pub mod audit;
+pub mod backup;
+pub mod bookmarks;
+pub mod changes;
+pub mod composers;
 pub mod config;
+pub mod cors;
+pub mod covers;
+pub mod cue;
+pub mod dav;
+pub mod embed;
+pub mod encoding;
 pub mod entry;
+pub mod events;
+pub mod favorites;
+pub mod fileinfo;
+pub mod filename;
+pub mod gapless;
+pub mod gc;
 pub mod global;
-pub mod m3u8;
+pub mod history;
+pub mod hls;
+pub mod ids;
+pub mod import;
+pub mod ingest;
+pub mod issues;
+pub mod jobs;
+pub mod libraries;
+pub mod limits;
+pub mod listen;
+pub mod listen_later;
+pub mod listens;
+pub mod lite;
+pub mod lrc;
+pub mod migrations;
+pub mod ndjson;
+pub mod openapi;
+pub mod organize;
+pub mod palette;
+pub mod pictures;
+pub mod playback;
+pub mod playlist;
+pub mod positions;
+pub mod proxy;
+pub mod queue;
+pub mod radio;
+pub mod random;
+pub mod reconcile;
+pub mod remote;
+pub mod rescan;
+pub mod scan;
+pub mod seek;
+pub mod sessions;
+pub mod shutdown;
+pub mod similar;
+pub mod snapshot;
+pub mod stats;
+pub mod store;
+pub mod sync;
+pub mod systemd;
+pub mod tags;
+pub mod timers;
+pub mod tls;
+pub mod trail;
+pub mod users;
 pub mod utils;
+pub mod walk;
+pub mod webapp;
+pub mod webhooks;
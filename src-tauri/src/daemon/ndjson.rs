@@ -0,0 +1,58 @@
+//! Newline-delimited JSON export of the tracks of a library, one per line, for clients and
+//! backup tools going through huge libraries. Tracks are serialized a batch at a time as the
+//! body is read, the whole export is never held in memory.
+
+use crate::daemon::global::Media;
+use crate::daemon::lite;
+use crate::daemon::snapshot::Snapshot;
+use axum::body::{Body, Bytes};
+use mu_protocol::api::QualityQuery;
+use std::path::PathBuf;
+use std::sync::Arc;
+use tracing::warn;
+
+/// Tracks serialized each time the media is locked
+const BATCH: usize = 256;
+
+pub const NDJSON: &str = "application/x-ndjson";
+
+/// Streams the tracks of `media` kept by `quality` sorted by path, as `LiteTrack`s when `lite`.
+/// Tracks removed by a scan in the meantime are left out.
+pub async fn tracks_body(media: Arc<Snapshot<Media>>, lite: bool, quality: QualityQuery) -> Body {
+    let mut paths: Vec<PathBuf> = media
+        .load()
+        .tracks
+        .iter()
+        .filter(|(_, x)| quality.keeps(x))
+        .map(|(path, _)| path.clone())
+        .collect();
+    paths.sort();
+    let paths = Arc::new(paths);
+
+    let batches = futures::stream::unfold(0, move |start| {
+        let (media, paths) = (Arc::clone(&media), Arc::clone(&paths));
+        async move {
+            if start >= paths.len() {
+                return None;
+            }
+            let end = (start + BATCH).min(paths.len());
+            let media = media.load();
+            let mut chunk = vec![];
+            for track in paths[start..end].iter().filter_map(|x| media.tracks.get(x)) {
+                let written = if lite {
+                    serde_json::to_writer(&mut chunk, &lite::track(track))
+                } else {
+                    serde_json::to_writer(&mut chunk, track)
+                };
+                if let Err(e) = written {
+                    warn!("ndjson: unable to write `{}`: {e}", track.file_path);
+                    continue;
+                }
+                chunk.push(b'\n');
+            }
+            Some((Ok::<_, std::io::Error>(Bytes::from(chunk)), end))
+        }
+    });
+
+    Body::from_stream(batches)
+}
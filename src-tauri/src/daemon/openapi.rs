@@ -0,0 +1,86 @@
+//! OpenAPI document of the HTTP API, served at `/openapi.json` for client developers to
+//! generate SDKs from, and browsable at `/docs`. The routes are described next to their
+//! handlers in `entry`.
+
+use axum::response::Html;
+use utoipa::openapi::path::{ParameterBuilder, ParameterIn};
+use utoipa::openapi::{ObjectBuilder, OpenApi, Required, SchemaType};
+use utoipa::Modify;
+
+/// Routes answering the same whatever the library
+const UNSCOPED: [&str; 20] = [
+    "/healthz",
+    "/readyz",
+    "/config",
+    "/player/preferences",
+    "/libraries",
+    "/jobs",
+    "/jobs/{id}",
+    "/shutdown",
+    "/admin/users",
+    "/admin/users/{name}",
+    "/admin/users/{name}/token",
+    "/admin/audit",
+    "/me",
+    "/sessions",
+    "/sessions/{id}",
+    "/sessions/{id}/queue/{track}",
+    "/sessions/{id}/next",
+    "/timers/{id}",
+    "/openapi.json",
+    "/docs",
+];
+
+/// Documents the `X-Library` header on the routes scoped to a library, see `libraries`
+pub struct LibraryHeader;
+
+impl Modify for LibraryHeader {
+    fn modify(&self, openapi: &mut OpenApi) {
+        for (path, item) in openapi.paths.paths.iter_mut() {
+            if UNSCOPED.contains(&path.as_str()) {
+                continue;
+            }
+            for operation in item.operations.values_mut() {
+                let parameter = ParameterBuilder::new()
+                    .name("X-Library")
+                    .parameter_in(ParameterIn::Header)
+                    .required(Required::False)
+                    .description(Some(
+                        "Library the request is scoped to, the default one when missing. \
+                         The path may also be prefixed with `/libraries/{name}`.",
+                    ))
+                    .schema(Some(ObjectBuilder::new().schema_type(SchemaType::String)))
+                    .build();
+                operation
+                    .parameters
+                    .get_or_insert_with(Vec::new)
+                    .push(parameter);
+            }
+        }
+    }
+}
+
+/// Swagger UI, loaded from a CDN, browsing the document next to it
+const SWAGGER_UI: &str = r##"<!DOCTYPE html>
+<html lang="en">
+  <head>
+    <meta charset="utf-8" />
+    <meta name="viewport" content="width=device-width, initial-scale=1" />
+    <title>lorchestre API</title>
+    <link rel="stylesheet" href="https://unpkg.com/swagger-ui-dist@5/swagger-ui.css" />
+  </head>
+  <body>
+    <div id="swagger-ui"></div>
+    <script src="https://unpkg.com/swagger-ui-dist@5/swagger-ui-bundle.js" crossorigin></script>
+    <script>
+      window.onload = () => {
+        window.ui = SwaggerUIBundle({ url: "openapi.json", dom_id: "#swagger-ui" });
+      };
+    </script>
+  </body>
+</html>
+"##;
+
+pub async fn swagger_ui() -> Html<&'static str> {
+    Html(SWAGGER_UI)
+}
@@ -0,0 +1,474 @@
+//! Moves the files of a library to the paths given by `library.organize_pattern`, e.g.
+//! `{albumartist}/{album} ({year})/{track:02} {title}.{ext}`, below the folder of the library
+//! they are in. `POST /organize` lists the moves, and makes them as a job with `?apply=true`,
+//! pointing the cache and the playlists to the new paths.
+
+use crate::daemon::global::utils::{cache_audio_files, read_cache_audio_files};
+use crate::daemon::global::Media;
+use crate::daemon::jobs::JobContext;
+use crate::daemon::libraries::Library;
+use crate::daemon::playlist::PlaylistFormat;
+use crate::daemon::reconcile::Fingerprints;
+use crate::daemon::utils;
+use mu_protocol::api::{OrganizeMove, OrganizeSkip};
+use mu_protocol::library::Track;
+use socketioxide::SocketIo;
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use tracing::{info, warn};
+
+/// Value of the tags left unset by the scan
+const UNKNOWN: &str = "@UNKNOWN@";
+
+/// Pattern of the imported files while `library.organize_pattern` is unset
+pub const DEFAULT_PATTERN: &str = "{albumartist}/{album} ({year})/{track:02} {title}.{ext}";
+
+/// Fields of the pattern
+const FIELDS: [&str; 8] = [
+    "albumartist",
+    "artist",
+    "album",
+    "year",
+    "track",
+    "title",
+    "genre",
+    "ext",
+];
+
+/// Piece of a pattern: text as written, or a field with the width its number is padded to
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Part {
+    Text(String),
+    Field(String, usize),
+}
+
+/// Parses `pattern`, failing with the reason it is invalid. A pattern without `{ext}` gets the
+/// extension of the files appended.
+pub fn parse(pattern: &str) -> Result<Vec<Part>, String> {
+    let mut parts = vec![];
+    let mut rest = pattern.trim();
+    while let Some(start) = rest.find('{') {
+        if start > 0 {
+            parts.push(Part::Text(rest[..start].to_string()));
+        }
+        let Some(end) = rest[start..].find('}') else {
+            return Err(format!("unclosed `{{` in `{pattern}`"));
+        };
+        let field = &rest[start + 1..start + end];
+        let (name, width) = match field.split_once(':') {
+            Some((name, width)) => match width.parse() {
+                Ok(width) if name == "track" => (name, width),
+                _ => {
+                    return Err(format!(
+                        "invalid field `{{{field}}}`, only `{{track:02}}` is padded"
+                    ))
+                }
+            },
+            None => (field, 0),
+        };
+        if !FIELDS.contains(&name) {
+            return Err(format!(
+                "unknown field `{{{name}}}`, expected one of {}",
+                FIELDS.join(", ")
+            ));
+        }
+        parts.push(Part::Field(name.to_string(), width));
+        rest = &rest[start + end + 1..];
+    }
+    if !rest.is_empty() {
+        parts.push(Part::Text(rest.to_string()));
+    }
+
+    if parts.iter().all(|x| !matches!(x, Part::Field(..))) {
+        return Err(format!("`{pattern}` has no field"));
+    }
+    if !parts.contains(&Part::Field("ext".to_string(), 0)) {
+        parts.push(Part::Text(".".to_string()));
+        parts.push(Part::Field("ext".to_string(), 0));
+    }
+
+    Ok(parts)
+}
+
+/// `value` as part of a file name: separators and characters Windows refuses are replaced, and
+/// a leading dot too for the file not to be hidden
+fn sanitize(value: &str) -> String {
+    let value: String = value
+        .trim()
+        .chars()
+        .map(|x| match x {
+            '/' | '\\' | ':' | '*' | '?' | '"' | '<' | '>' | '|' => '_',
+            x if x.is_control() => '_',
+            x => x,
+        })
+        .collect();
+    match value.strip_prefix('.') {
+        Some(rest) => format!("_{rest}"),
+        None => value,
+    }
+}
+
+fn known(value: &str) -> Option<&str> {
+    Some(value.trim()).filter(|x| !x.is_empty() && *x != UNKNOWN)
+}
+
+/// The value of the field `name` for `track` of the file `path`
+fn value(name: &str, width: usize, track: &Track, path: &Path) -> String {
+    let first = |values: &[String]| values.iter().find_map(|x| known(x)).map(str::to_string);
+    match name {
+        "albumartist" => first(&track.album_artists)
+            .or(first(&track.artists))
+            .unwrap_or("Unknown Artist".to_string()),
+        "artist" => first(&track.artists).unwrap_or("Unknown Artist".to_string()),
+        "album" => known(&track.album).unwrap_or("Unknown Album").to_string(),
+        "year" => track.year().map(|x| x.to_string()).unwrap_or_default(),
+        "track" if track.track > 0 => format!("{:0width$}", track.track),
+        "title" => match known(&track.title) {
+            Some(title) => title.to_string(),
+            None => path
+                .file_stem()
+                .map(|x| x.to_string_lossy().to_string())
+                .unwrap_or_default(),
+        },
+        "genre" => track
+            .genre
+            .as_deref()
+            .and_then(known)
+            .unwrap_or("Unknown Genre")
+            .to_string(),
+        "ext" => path
+            .extension()
+            .map(|x| x.to_string_lossy().to_lowercase())
+            .unwrap_or_default(),
+        _ => String::new(),
+    }
+}
+
+/// A component of the rendered path, without the brackets and spaces left by empty fields,
+/// e.g. `Album ()` for an album without year
+fn tidy(component: &str) -> String {
+    let mut component = component.to_string();
+    for empty in ["()", "[]", "{}"] {
+        component = component.replace(empty, "");
+    }
+    let component = component.split_whitespace().collect::<Vec<_>>().join(" ");
+    // Stripped by Windows
+    let component = component.trim_end_matches(['.', ' ']);
+    let component = component.trim_start_matches(['-', '_', ' ']).trim();
+    match component {
+        "" => "_".to_string(),
+        x => x.to_string(),
+    }
+}
+
+/// The path of the file of `track` given by `pattern`, relative to its library folder
+pub fn render(pattern: &[Part], track: &Track) -> PathBuf {
+    let path = Path::new(&track.file_path);
+    let mut text = String::new();
+    for part in pattern {
+        match part {
+            Part::Text(x) => text.push_str(x),
+            Part::Field(name, width) => text.push_str(&sanitize(&value(name, *width, track, path))),
+        }
+    }
+
+    text.split(['/', '\\'])
+        .filter(|x| !x.trim().is_empty())
+        .map(tidy)
+        .collect()
+}
+
+/// What organizing a library would do
+#[derive(Debug, Default)]
+pub struct Plan {
+    pub moves: Vec<OrganizeMove>,
+    pub skipped: Vec<OrganizeSkip>,
+}
+
+/// The moves of the files of `media` to the paths of `pattern` below the folder of `roots` they
+/// are in. Files split by a cue sheet are left where they are, as are files whose path is taken.
+pub fn plan(media: &Media, roots: &[PathBuf], pattern: &[Part]) -> Plan {
+    let mut plan = Plan::default();
+    let mut tracks: Vec<&Track> = media.tracks.values().collect();
+    tracks.sort_by(|a, b| a.file_path.cmp(&b.file_path));
+
+    let mut cue_files = HashSet::new();
+    let mut targets: HashMap<PathBuf, String> = HashMap::new();
+    for track in tracks {
+        let skip = |reason: String| OrganizeSkip {
+            track: track.id.clone(),
+            path: track.audio_file().to_string(),
+            reason,
+        };
+        if track.source.is_some() {
+            if cue_files.insert(track.audio_file()) {
+                plan.skipped
+                    .push(skip("the file is split by a cue sheet".to_string()));
+            }
+            continue;
+        }
+
+        let from = PathBuf::from(&track.file_path);
+        let Some(root) = roots.iter().find(|x| from.starts_with(x)).or(roots.first()) else {
+            continue;
+        };
+        let to = root.join(render(pattern, track));
+        if to == from {
+            continue;
+        }
+        if let Some(other) = targets.get(&to) {
+            plan.skipped
+                .push(skip(format!("{} goes to {} too", other, to.display())));
+            continue;
+        }
+        if to.exists() {
+            plan.skipped
+                .push(skip(format!("{} already exists", to.display())));
+            continue;
+        }
+
+        targets.insert(to.clone(), track.file_path.clone());
+        plan.moves.push(OrganizeMove {
+            track: track.id.clone(),
+            from: track.file_path.clone(),
+            to: to.display().to_string(),
+        });
+    }
+
+    plan
+}
+
+/// Moves `from` to `to`, copying it when they are on different file systems
+pub fn move_file(from: &Path, to: &Path) -> std::io::Result<()> {
+    if let Some(parent) = to.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    if std::fs::rename(from, to).is_ok() {
+        return Ok(());
+    }
+
+    if let Err(e) = std::fs::copy(from, to) {
+        let _ = std::fs::remove_file(to);
+        return Err(e);
+    }
+    std::fs::remove_file(from)
+}
+
+/// Removes `dir` and its parents below the folder of `roots` they are in while they are empty
+pub fn remove_empty_dirs(dir: &Path, roots: &[PathBuf]) {
+    let mut dir = Some(dir);
+    while let Some(current) = dir {
+        if roots.iter().any(|x| x == current) || !roots.iter().any(|x| current.starts_with(x)) {
+            break;
+        }
+        // Fails unless empty
+        if std::fs::remove_dir(current).is_err() {
+            break;
+        }
+        dir = current.parent();
+    }
+}
+
+/// Makes the `moves` in order until the job of `context` is cancelled, taking the lyrics next
+/// to the files along. Returns the moves made and the failures.
+fn apply(
+    moves: Vec<OrganizeMove>,
+    roots: &[PathBuf],
+    context: &JobContext,
+) -> (Vec<OrganizeMove>, Vec<OrganizeSkip>) {
+    let mut moved = vec![];
+    let mut failed = vec![];
+    let total = moves.len();
+    for (i, planned) in moves.into_iter().enumerate() {
+        let (from, to) = (PathBuf::from(&planned.from), PathBuf::from(&planned.to));
+        let reason = if context.cancel.is_cancelled() {
+            Some("the organization was cancelled".to_string())
+        } else if to.exists() {
+            Some(format!("{} already exists", to.display()))
+        } else {
+            context.progress(i, total);
+            move_file(&from, &to).err().map(|e| e.to_string())
+        };
+        if let Some(reason) = reason {
+            warn!("organize: unable to move `{}`: {reason}", planned.from);
+            failed.push(OrganizeSkip {
+                track: planned.track,
+                path: planned.from,
+                reason,
+            });
+            continue;
+        }
+
+        let lyrics = from.with_extension("lrc");
+        if lyrics.exists() {
+            if let Err(e) = move_file(&lyrics, &to.with_extension("lrc")) {
+                warn!("organize: unable to move `{}`: {e}", lyrics.display());
+            }
+        }
+        if let Some(parent) = from.parent() {
+            remove_empty_dirs(parent, roots);
+        }
+        moved.push(planned);
+    }
+
+    (moved, failed)
+}
+
+/// Points the playlists of `media` with files of `moves` to their new paths, writing them again
+fn update_playlists(media: &mut Media, moves: &HashMap<PathBuf, PathBuf>) {
+    for playlist in &mut media.playlists {
+        if !playlist.tracks.iter().any(|x| moves.contains_key(x)) {
+            continue;
+        }
+        let tracks: Vec<PathBuf> = playlist
+            .tracks
+            .iter()
+            .map(|x| moves.get(x).unwrap_or(x).clone())
+            .collect();
+        let path = PathBuf::from(&playlist.path);
+        let format = PlaylistFormat::from_path(&path).unwrap_or(PlaylistFormat::M3u8);
+        let text = format.render(&playlist.name, playlist.description.as_deref(), &tracks);
+        match std::fs::write(&path, text) {
+            Ok(()) => playlist.tracks = tracks,
+            Err(e) => warn!("organize: unable to update `{}`: {e}", path.display()),
+        }
+    }
+}
+
+/// Makes the `moves` of files of `library` and points its media to them, as a job. Returns the
+/// moves made and the failures.
+pub async fn run(
+    library: &Library,
+    io: &SocketIo,
+    moves: Vec<OrganizeMove>,
+    context: JobContext,
+) -> Result<(Vec<OrganizeMove>, Vec<OrganizeSkip>), String> {
+    let roots = library.paths.clone();
+    let (moved, failed) = tokio::task::spawn_blocking(move || apply(moves, &roots, &context))
+        .await
+        .map_err(|e| e.to_string())?;
+    info!(
+        "organize: {} files moved in {}, {} failed",
+        moved.len(),
+        library.name,
+        failed.len()
+    );
+    if moved.is_empty() {
+        return Ok((moved, failed));
+    }
+
+    let paths: HashMap<PathBuf, PathBuf> = moved
+        .iter()
+        .map(|x| (PathBuf::from(&x.from), PathBuf::from(&x.to)))
+        .collect();
+    let mut media = library.media.write().await;
+    for (from, to) in &paths {
+        media.move_song(from, to);
+    }
+    update_playlists(&mut media, &paths);
+    utils::save_cache(&library.cache_dir, &media);
+    library.publish(io, &media.commit());
+
+    // The files the next scan compares the folders with
+    let list = library.cache_dir.join(".cache.list");
+    let files: Vec<PathBuf> = read_cache_audio_files(&list)
+        .into_iter()
+        .map(|x| paths.get(&x).cloned().unwrap_or(x))
+        .collect();
+    cache_audio_files(&list, &files);
+    Fingerprints::load(&library.cache_dir).rename(&paths);
+
+    Ok((moved, failed))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tagged() -> Track {
+        Track {
+            title: "Intro".to_string(),
+            artists: vec!["Guest".to_string()],
+            album_artists: vec!["The Band".to_string()],
+            album: "First".to_string(),
+            album_year: Some(1999),
+            track: 3,
+            genre: Some("Rock".to_string()),
+            file_path: "/music/incoming/file.FLAC".to_string(),
+            ..Default::default()
+        }
+    }
+
+    fn rendered(track: &Track) -> String {
+        let pattern = parse(DEFAULT_PATTERN).unwrap();
+        render(&pattern, track).display().to_string()
+    }
+
+    #[test]
+    fn patterns() {
+        assert!(parse("{album}/{title").is_err());
+        assert!(parse("{composer}/{title}").is_err());
+        assert!(parse("{title:02}").is_err());
+        assert!(parse("no fields").is_err());
+        // The extension of the file is kept without `{ext}`
+        let pattern = parse("{artist} - {title}").unwrap();
+        assert_eq!(
+            render(&pattern, &tagged()),
+            PathBuf::from("Guest - Intro.flac")
+        );
+    }
+
+    #[test]
+    fn all_fields() {
+        assert_eq!(rendered(&tagged()), "The Band/First (1999)/03 Intro.flac");
+        let pattern = parse("{genre}/{artist}/{track:3}-{title}.{ext}").unwrap();
+        assert_eq!(
+            render(&pattern, &tagged()),
+            PathBuf::from("Rock/Guest/003-Intro.flac")
+        );
+    }
+
+    #[test]
+    fn missing_fields() {
+        let bare = Track {
+            title: UNKNOWN.to_string(),
+            album: " ".to_string(),
+            file_path: "/music/incoming/Some file.mp3".to_string(),
+            ..Default::default()
+        };
+        // The title falls back on the file name, the brackets and spaces of empty fields go
+        assert_eq!(
+            rendered(&bare),
+            "Unknown Artist/Unknown Album/Some file.mp3"
+        );
+
+        let pattern = parse("{genre}/{albumartist}/{title}").unwrap();
+        let untagged = Track {
+            artists: vec![UNKNOWN.to_string(), "Solo".to_string()],
+            ..bare
+        };
+        assert_eq!(
+            render(&pattern, &untagged),
+            PathBuf::from("Unknown Genre/Solo/Some file.mp3")
+        );
+    }
+
+    #[test]
+    fn separators_in_values() {
+        let track = Track {
+            title: "What? / Why: \"Now\"".to_string(),
+            album_artists: vec!["AC/DC".to_string()],
+            album: "..".to_string(),
+            album_year: None,
+            ..tagged()
+        };
+        // Values can't add folders nor climb out of the library
+        assert_eq!(rendered(&track), "AC_DC/_/03 What_ _ Why_ _Now_.flac");
+
+        let hidden = Track {
+            album: ".hidden".to_string(),
+            ..tagged()
+        };
+        assert_eq!(rendered(&hidden), "The Band/hidden (1999)/03 Intro.flac");
+    }
+}
@@ -0,0 +1,281 @@
+use crate::daemon::config::SharedConfig;
+use crate::daemon::events;
+use crate::daemon::global::{utils, Media};
+use crate::daemon::libraries::Library;
+use crate::daemon::snapshot::Snapshot;
+use crate::daemon::utils as cache;
+use color_thief::ColorFormat;
+use mu_protocol::api::ColoredTracks;
+use mu_protocol::events::Event;
+use mu_protocol::library::{Color, Palette};
+use socketioxide::SocketIo;
+use std::collections::HashMap;
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use tracing::{info, warn};
+
+/// Whether the palettes are computed in the background, `scan.extract_palette`
+fn background(config: &lorconf::Config) -> bool {
+    config
+        .scan
+        .as_ref()
+        .and_then(|x| x.extract_palette)
+        .or(lorconf::Scan::default().extract_palette)
+        .unwrap_or_default()
+}
+
+/// Colors asked to color-thief, the most present ones first
+const CANDIDATES: u8 = 6;
+
+/// Saturation weighted by brightness, so that near black colors aren't picked as accent
+fn vividness(color: &Color) -> f64 {
+    let max = color.r.max(color.g).max(color.b) as f64;
+    let min = color.r.min(color.g).min(color.b) as f64;
+    if max == 0.0 {
+        return 0.0;
+    }
+
+    (max - min) / max * (max / 255.0)
+}
+
+/// Palette of RGB8 `pixels`
+pub fn extract(pixels: &[u8]) -> Option<Palette> {
+    let colors: Vec<Color> = color_thief::get_palette(pixels, ColorFormat::Rgb, 1, CANDIDATES)
+        .ok()?
+        .into_iter()
+        .map(|x| Color {
+            r: x.r,
+            g: x.g,
+            b: x.b,
+        })
+        .collect();
+    let primary = *colors.first()?;
+    let secondary = colors.get(1).copied().unwrap_or(primary);
+    let accent = colors
+        .iter()
+        .skip(1)
+        .copied()
+        .max_by(|a, b| vividness(a).total_cmp(&vividness(b)))
+        .unwrap_or(primary);
+
+    Some(Palette {
+        primary,
+        secondary,
+        accent,
+        text: primary.contrasting(),
+    })
+}
+
+/// Palette of an encoded cover image
+pub fn from_cover(data: &[u8]) -> Option<Palette> {
+    let img = image::load_from_memory(data).ok()?;
+    extract(&utils::get_image_buffer(img))
+}
+
+/// Palettes already computed, by md5 of the cover. Covers which can't be decoded are kept as
+/// `None` so they aren't decoded again after each scan.
+#[derive(Debug, Default)]
+pub struct PaletteCache {
+    path: PathBuf,
+    palettes: HashMap<String, Option<Palette>>,
+    changed: bool,
+}
+
+impl PaletteCache {
+    pub fn load(path: PathBuf) -> Self {
+        let mut palettes = HashMap::new();
+        if path.exists() {
+            let mut buf = String::new();
+            if let Ok(mut f) = std::fs::File::open(&path) {
+                let _ = f.read_to_string(&mut buf);
+            }
+            match serde_json::from_str(&buf) {
+                Ok(parsed) => palettes = parsed,
+                Err(e) => warn!("Unable to read palettes `{}`: {e}", path.display()),
+            }
+        }
+
+        Self {
+            path,
+            palettes,
+            changed: false,
+        }
+    }
+
+    fn save(&mut self) {
+        if !self.changed {
+            return;
+        }
+        self.changed = false;
+        let data = serde_json::to_string(&self.palettes).unwrap();
+        match std::fs::File::create(&self.path) {
+            Ok(mut f) => {
+                let _ = f.write_all(data.as_bytes());
+            }
+            Err(e) => warn!("Unable to save palettes `{}`: {e}", self.path.display()),
+        }
+    }
+
+    /// Palette of the cover at `path`, decoding it only if its content wasn't seen before
+    async fn get(&mut self, path: PathBuf) -> Option<Palette> {
+        let data = tokio::fs::read(&path).await.ok()?;
+        let hash = format!("{:x}", md5::compute(&data));
+        if let Some(palette) = self.palettes.get(&hash) {
+            return *palette;
+        }
+
+        let palette = match tokio::task::spawn_blocking(move || from_cover(&data)).await {
+            Ok(palette) => palette,
+            Err(e) => {
+                warn!("palette: unable to decode `{}`: {e}", path.display());
+                None
+            }
+        };
+        self.palettes.insert(hash, palette);
+        self.changed = true;
+
+        palette
+    }
+}
+
+/// Computes the palettes of the covers extracted since the last pass, returns whether any
+/// track got one
+async fn color_pending(
+    media: &Snapshot<Media>,
+    covers_dir: &Path,
+    palettes: &mut PaletteCache,
+    io: &SocketIo,
+) -> bool {
+    // Tracks of an album share their cover
+    let mut pending: HashMap<(String, String), Vec<PathBuf>> = HashMap::new();
+    for (path, track) in &media.load().tracks {
+        if track.palette.is_none() {
+            pending
+                .entry((track.album_id.clone(), track.cover_ext.clone()))
+                .or_default()
+                .push(path.clone());
+        }
+    }
+
+    let mut painted = vec![];
+    for ((album_id, cover_ext), tracks) in pending {
+        let path = covers_dir.join(format!("{album_id}{cover_ext}"));
+        // Tracks without cover
+        if !path.exists() {
+            continue;
+        }
+        if let Some(palette) = palettes.get(path).await {
+            painted.push((album_id, tracks, palette));
+        }
+    }
+
+    paint(media, painted, io).await
+}
+
+/// Gives each palette to the tracks of its album and to the album if it has none, all in
+/// one write as each write copies the media. Returns whether any track got one.
+async fn paint(
+    media: &Snapshot<Media>,
+    painted: Vec<(String, Vec<PathBuf>, Palette)>,
+    io: &SocketIo,
+) -> bool {
+    if painted.is_empty() {
+        return false;
+    }
+
+    let mut media = media.write().await;
+    let mut colored = vec![];
+    for (album_id, tracks, palette) in painted {
+        let mut ids = vec![];
+        // The library may have been rescanned in the meantime
+        for path in &tracks {
+            let Some(track) = media.tracks.get_mut(path) else {
+                continue;
+            };
+            track.is_light = Some(palette.primary.is_light_color());
+            track.color = Some(palette.primary);
+            track.palette = Some(palette);
+            ids.push(track.id.clone());
+        }
+        if let Some(album) = media.album_mut(&album_id.as_str().into()) {
+            album.palette.get_or_insert(palette);
+        }
+        if !ids.is_empty() {
+            colored.push(ColoredTracks {
+                ids,
+                album_id,
+                palette,
+            });
+        }
+    }
+    drop(media);
+
+    let any = !colored.is_empty();
+    for tracks in colored {
+        events::emit(io, Event::TrackColored, tracks);
+    }
+    any
+}
+
+/// Palette of the album `id` of `library`, computed from its cover right away when the worker
+/// didn't get to it yet. `None` for an album missing or without cover.
+pub async fn album_palette(library: &Library, id: &str, io: &SocketIo) -> Option<Palette> {
+    let (tracks, cover_ext) = {
+        let media = library.media.load();
+        let album = media.album(&id.into())?;
+        if let Some(palette) = album.palette {
+            return Some(palette);
+        }
+        let cover_ext = album
+            .tracks
+            .iter()
+            .filter_map(|x| media.tracks.get(x))
+            .find(|x| !x.cover_ext.is_empty())?
+            .cover_ext
+            .clone();
+        (album.tracks.clone(), cover_ext)
+    };
+
+    let path = library
+        .cache_dir
+        .join("covers")
+        .join(format!("{id}{cover_ext}"));
+    let data = tokio::fs::read(&path).await.ok()?;
+    let palette = match tokio::task::spawn_blocking(move || from_cover(&data)).await {
+        Ok(palette) => palette?,
+        Err(e) => {
+            warn!("palette: unable to decode `{}`: {e}", path.display());
+            return None;
+        }
+    };
+    if paint(&library.media, vec![(id.to_string(), tracks, palette)], io).await {
+        let media = library.media.load();
+        library.changes.lock().unwrap().record(&media);
+        cache::save_cache(&library.cache_dir, &media);
+    }
+
+    Some(palette)
+}
+
+/// Fills in the palettes of `library` in the background, each time its `colors` is notified,
+/// e.g. after a scan, as decoding every cover would hold the scan up. Idle while
+/// `scan.extract_palette` is off.
+pub async fn worker(library: Arc<Library>, config: SharedConfig, io: SocketIo) {
+    let covers_dir = library.cache_dir.join("covers");
+    let mut palettes = PaletteCache::load(library.cache_dir.join("palettes.json"));
+
+    loop {
+        // Else computed by `album_palette` when asked for
+        if background(&*config.read().await)
+            && color_pending(&library.media, &covers_dir, &mut palettes, &io).await
+        {
+            info!("palette: covers of {} analysed", library.name);
+            let media = library.media.load();
+            library.changes.lock().unwrap().record(&media);
+            cache::save_cache(&library.cache_dir, &media);
+        }
+        palettes.save();
+        library.colors.notified().await;
+    }
+}
@@ -0,0 +1,169 @@
+//! The pictures the tracks embed besides their front cover, e.g. the back cover, the pages of
+//! a booklet or a photo of the artist, kept in `covers/<album id>/` as `<type>.<ext>` for
+//! `GET /album/:id/artwork`. A type embedded several times is numbered, e.g. `leaflet-2.jpeg`,
+//! and the pictures of the first track carrying a type are kept.
+
+use crate::daemon::proxy;
+use lofty::file::TaggedFileExt;
+use lofty::picture::{MimeType, Picture, PictureType};
+use lofty::probe::Probe;
+use mu_protocol::api::AlbumArtwork;
+use mu_protocol::library::Album;
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::path::Path;
+use tracing::warn;
+
+/// Name of `kind` in the file names and the listing, e.g. `back` or `lead_artist`
+fn kind_name(kind: PictureType) -> String {
+    match kind {
+        PictureType::Other => "other",
+        PictureType::Icon => "icon",
+        PictureType::OtherIcon => "other_icon",
+        PictureType::CoverFront => "front",
+        PictureType::CoverBack => "back",
+        PictureType::Leaflet => "leaflet",
+        PictureType::Media => "media",
+        PictureType::LeadArtist => "lead_artist",
+        PictureType::Artist => "artist",
+        PictureType::Conductor => "conductor",
+        PictureType::Band => "band",
+        PictureType::Composer => "composer",
+        PictureType::Lyricist => "lyricist",
+        PictureType::RecordingLocation => "recording_location",
+        PictureType::DuringRecording => "during_recording",
+        PictureType::DuringPerformance => "during_performance",
+        PictureType::ScreenCapture => "screen_capture",
+        PictureType::BrightFish => "bright_fish",
+        PictureType::Illustration => "illustration",
+        PictureType::BandLogo => "band_logo",
+        PictureType::PublisherLogo => "publisher_logo",
+        // Undefined by the ID3v2 numbering
+        kind => return format!("type_{}", kind.as_u8()),
+    }
+    .to_string()
+}
+
+/// Extension of a picture of the type `mime`, e.g. `.jpeg`
+pub fn extension(mime: Option<&MimeType>) -> String {
+    match mime {
+        Some(MimeType::Png) => ".png".to_string(),
+        Some(MimeType::Jpeg) => ".jpeg".to_string(),
+        Some(MimeType::Tiff) => ".tiff".to_string(),
+        Some(MimeType::Bmp) => ".bmp".to_string(),
+        Some(MimeType::Gif) => ".gif".to_string(),
+        Some(MimeType::Unknown(o)) => format!(".{o}"),
+        _ => ".png".to_string(),
+    }
+}
+
+/// Stores `pictures`, the front covers aside, in the folder of `album_id` in `covers_dir`.
+/// Types the folder already has are left.
+pub fn store(covers_dir: &Path, album_id: &str, pictures: &[Picture]) {
+    let pictures: Vec<&Picture> = pictures
+        .iter()
+        .filter(|x| x.pic_type() != PictureType::CoverFront)
+        .collect();
+    if pictures.is_empty() {
+        return;
+    }
+
+    let dir = covers_dir.join(album_id);
+    let kinds: HashSet<String> = fs::read_dir(&dir)
+        .into_iter()
+        .flatten()
+        .flatten()
+        .filter_map(|x| Some(Path::new(&x.file_name()).file_stem()?.to_str()?.to_string()))
+        .map(|x| {
+            x.split_once('-')
+                .map_or(x.clone(), |(kind, _)| kind.to_string())
+        })
+        .collect();
+    if let Err(e) = fs::create_dir_all(&dir) {
+        warn!("pictures: unable to create `{}`: {e}", dir.display());
+        return;
+    }
+
+    let mut counts: HashMap<String, usize> = HashMap::new();
+    for picture in pictures {
+        let kind = kind_name(picture.pic_type());
+        let count = counts.entry(kind.clone()).or_default();
+        *count += 1;
+        if kinds.contains(&kind) {
+            continue;
+        }
+        let name = match *count {
+            1 => kind,
+            n => format!("{kind}-{n}"),
+        };
+        let path = dir.join(format!("{name}{}", extension(picture.mime_type())));
+        if let Err(e) = fs::write(&path, picture.data()) {
+            warn!("pictures: unable to store `{}`: {e}", path.display());
+        }
+    }
+}
+
+/// Stores the pictures of `inode`, a track of the album `album_id`, for the libraries scanned
+/// without `scan.extract_covers`. The folder is made even when the track has none, for the
+/// pictures not to be looked for again.
+pub fn extract(covers_dir: &Path, album_id: &str, inode: &Path) {
+    let dir = covers_dir.join(album_id);
+    if let Err(e) = fs::create_dir_all(&dir) {
+        warn!("pictures: unable to create `{}`: {e}", dir.display());
+        return;
+    }
+    let Some(tagged_file) = Probe::open(inode).ok().and_then(|x| x.read().ok()) else {
+        return;
+    };
+    if let Some(tag) = tagged_file.primary_tag().or(tagged_file.first_tag()) {
+        store(covers_dir, album_id, tag.pictures());
+    }
+}
+
+fn artwork(path: &Path, kind: String, url: String) -> Option<AlbumArtwork> {
+    let size = fs::metadata(path).ok()?.len();
+    let dimensions = image::image_dimensions(path).ok();
+    Some(AlbumArtwork {
+        kind,
+        url,
+        mime: mime_guess::from_path(path)
+            .first_or_octet_stream()
+            .to_string(),
+        size,
+        width: dimensions.map(|(w, _)| w),
+        height: dimensions.map(|(_, h)| h),
+    })
+}
+
+/// The images of `album`: its cover first, then the pictures stored for it by type
+pub fn list(covers_dir: &Path, album: &Album) -> Vec<AlbumArtwork> {
+    let mut images = vec![];
+    if let Some(url) = &album.cover_url {
+        let name = url.rsplit('/').next().unwrap_or_default();
+        images.extend(artwork(
+            &covers_dir.join(name),
+            "front".to_string(),
+            url.clone(),
+        ));
+    }
+
+    let mut paths: Vec<_> = fs::read_dir(covers_dir.join(&album.id))
+        .into_iter()
+        .flatten()
+        .flatten()
+        .map(|x| x.path())
+        .collect();
+    paths.sort();
+    for path in paths {
+        let (Some(stem), Some(name)) = (
+            path.file_stem().and_then(|x| x.to_str()),
+            path.file_name().and_then(|x| x.to_str()),
+        ) else {
+            continue;
+        };
+        let kind = stem.split_once('-').map_or(stem, |(kind, _)| kind);
+        let url = proxy::url(&format!("/album/{}/artwork/{name}", album.id));
+        images.extend(artwork(&path, kind.to_string(), url));
+    }
+    images
+}
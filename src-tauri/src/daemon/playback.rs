@@ -0,0 +1,106 @@
+//! Playback settings shared by the players: crossfade, gapless playback, loudness normalization
+//! and silence trimming. The defaults come from the `[player]` configuration, albums and
+//! playlists may override some of them.
+
+use crate::daemon::store;
+use lorconf::Config;
+use mu_protocol::api::{PlaybackHints, PlaybackPreferences, PlaybackTarget};
+use std::collections::HashMap;
+use std::path::PathBuf;
+
+/// Longest crossfade accepted, in seconds
+pub const MAX_CROSSFADE: f32 = 30.0;
+
+#[derive(serde::Serialize, serde::Deserialize, Debug, Default)]
+struct Overrides {
+    albums: HashMap<String, PlaybackHints>,
+    playlists: HashMap<String, PlaybackHints>,
+}
+
+/// Settings of the albums and playlists, keyed by id
+#[derive(Debug, Default)]
+pub struct Playback {
+    path: PathBuf,
+    overrides: Overrides,
+}
+
+impl Playback {
+    pub fn load(path: PathBuf) -> Self {
+        let overrides = store::load(&path, "playback settings");
+
+        Self { path, overrides }
+    }
+
+    fn save(&self) {
+        store::save(&self.path, "playback settings", &self.overrides);
+    }
+
+    fn entries(&self, target: PlaybackTarget) -> &HashMap<String, PlaybackHints> {
+        match target {
+            PlaybackTarget::Album => &self.overrides.albums,
+            PlaybackTarget::Playlist => &self.overrides.playlists,
+        }
+    }
+
+    fn entries_mut(&mut self, target: PlaybackTarget) -> &mut HashMap<String, PlaybackHints> {
+        match target {
+            PlaybackTarget::Album => &mut self.overrides.albums,
+            PlaybackTarget::Playlist => &mut self.overrides.playlists,
+        }
+    }
+
+    /// The settings to play the album or playlist `id` with
+    pub fn get(&self, config: &Config, target: PlaybackTarget, id: &str) -> PlaybackPreferences {
+        preferences(config, self.entries(target).get(id))
+    }
+
+    pub fn set(&mut self, target: PlaybackTarget, id: &str, hints: PlaybackHints) {
+        self.entries_mut(target).insert(id.to_string(), hints);
+        self.save();
+    }
+
+    pub fn remove(&mut self, target: PlaybackTarget, id: &str) -> bool {
+        let removed = self.entries_mut(target).remove(id).is_some();
+        if removed {
+            self.save();
+        }
+
+        removed
+    }
+}
+
+/// `hints` completed with the `[player]` configuration
+pub fn preferences(config: &Config, hints: Option<&PlaybackHints>) -> PlaybackPreferences {
+    let player = config.player.clone().unwrap_or_default();
+    let defaults = lorconf::Player::default();
+    let hints = hints.cloned().unwrap_or_default();
+
+    PlaybackPreferences {
+        crossfade: hints
+            .crossfade
+            .or(player.crossfade)
+            .or(defaults.crossfade)
+            .unwrap_or_default()
+            .clamp(0.0, MAX_CROSSFADE),
+        gapless: hints
+            .gapless
+            .or(player.gapless)
+            .or(defaults.gapless)
+            .unwrap_or_default(),
+        normalize: hints
+            .normalize
+            .or(player.normalize)
+            .or(defaults.normalize)
+            .unwrap_or_default(),
+        loudness_target: hints
+            .loudness_target
+            .or(player.loudness_target)
+            .or(defaults.loudness_target)
+            .unwrap_or_default(),
+        trim_silence: hints
+            .trim_silence
+            .or(player.trim_silence)
+            .or(defaults.trim_silence)
+            .unwrap_or_default(),
+    }
+}
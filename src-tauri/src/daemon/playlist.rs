@@ -0,0 +1,465 @@
+//! Playlist files found in the music folders: extended `.m3u8`, plain `.m3u`, `.pls` and
+//! `.xspf`. Entries may be absolute, relative to the playlist or `file://` URIs, with Windows
+//! separators. Playlists are browsed in folders mirroring the ones they sit in.
+
+use crate::daemon::global::TrackCollection;
+use mu_protocol::api::PlaylistFolder;
+use mu_protocol::library::Playlist;
+use percent_encoding::{percent_decode_str, utf8_percent_encode, AsciiSet, CONTROLS};
+use quick_xml::escape::escape;
+use quick_xml::events::Event;
+use quick_xml::Reader;
+use std::path::{Component, Path, PathBuf};
+use tracing::warn;
+
+/// Extensions of the image shown as the cover of a playlist, named like it
+const COVER_EXTENSIONS: [&str; 4] = ["jpg", "jpeg", "png", "webp"];
+
+/// Characters escaped in the `file://` URIs of XSPF playlists
+const URI: &AsciiSet = &CONTROLS
+    .add(b' ')
+    .add(b'"')
+    .add(b'#')
+    .add(b'%')
+    .add(b'<')
+    .add(b'>')
+    .add(b'?')
+    .add(b'`')
+    .add(b'{')
+    .add(b'}');
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PlaylistFormat {
+    /// Extended M3U, UTF-8
+    M3u8,
+    /// M3U, in UTF-8 or Latin-1, with or without `#EXTM3U`
+    M3u,
+    Pls,
+    Xspf,
+}
+
+impl PlaylistFormat {
+    /// The format of the playlist at `path`, `None` if it isn't one
+    pub fn from_path(path: &Path) -> Option<Self> {
+        let ext = path.extension()?.to_str()?.to_lowercase();
+        match ext.as_str() {
+            "m3u8" => Some(Self::M3u8),
+            "m3u" => Some(Self::M3u),
+            "pls" => Some(Self::Pls),
+            "xspf" => Some(Self::Xspf),
+            _ => None,
+        }
+    }
+
+    pub fn extension(self) -> &'static str {
+        match self {
+            Self::M3u8 => "m3u8",
+            Self::M3u => "m3u",
+            Self::Pls => "pls",
+            Self::Xspf => "xspf",
+        }
+    }
+
+    /// Entries of the playlist `text`, as written, and its description
+    fn entries(self, text: &str) -> (Vec<String>, Option<String>) {
+        match self {
+            Self::M3u8 | Self::M3u => {
+                let lines = text.lines().map(|x| x.trim());
+                let description = lines
+                    .clone()
+                    .find_map(|x| x.strip_prefix("#PLAYLIST:"))
+                    .map(|x| x.trim().to_string())
+                    .filter(|x| !x.is_empty());
+                let entries = lines
+                    .filter(|x| !x.is_empty() && !x.starts_with('#'))
+                    .map(|x| x.to_string())
+                    .collect();
+                (entries, description)
+            }
+            Self::Pls => {
+                let mut files: Vec<(usize, String)> = text
+                    .lines()
+                    .filter_map(|x| x.trim().split_once('='))
+                    .filter_map(|(key, value)| {
+                        let n = key.trim().strip_prefix("File")?.parse().ok()?;
+                        Some((n, value.trim().to_string()))
+                    })
+                    .collect();
+                files.sort_by_key(|(n, _)| *n);
+                (files.into_iter().map(|(_, file)| file).collect(), None)
+            }
+            Self::Xspf => {
+                let mut reader = Reader::from_str(text);
+                reader.trim_text(true);
+                let mut entries = vec![];
+                let mut description = None;
+                // Elements open, the text of `<location>` and of the `<annotation>` of the
+                // playlist is kept
+                let mut path: Vec<Vec<u8>> = vec![];
+                loop {
+                    match reader.read_event() {
+                        Ok(Event::Start(e)) => path.push(e.name().as_ref().to_vec()),
+                        Ok(Event::Text(e)) => {
+                            let Ok(value) = e.unescape() else {
+                                warn!("Unreadable XSPF text");
+                                continue;
+                            };
+                            match path.last().map(|x| x.as_slice()) {
+                                Some(b"location") => entries.push(value.to_string()),
+                                Some(b"annotation") if path.len() == 2 => {
+                                    description = Some(value.to_string())
+                                }
+                                _ => {}
+                            }
+                        }
+                        Ok(Event::End(_)) => {
+                            path.pop();
+                        }
+                        Ok(Event::Eof) => break,
+                        Err(e) => {
+                            warn!("Invalid XSPF playlist: {e}");
+                            break;
+                        }
+                        _ => {}
+                    }
+                }
+                (entries, description)
+            }
+        }
+    }
+
+    /// Writes a playlist of `tracks` named `name` in this format, read back the same by
+    /// [`parse`]. PLS playlists have no description.
+    pub fn render(self, name: &str, description: Option<&str>, tracks: &[PathBuf]) -> String {
+        let paths = tracks.iter().map(|x| x.display().to_string());
+        let description = description.filter(|x| !x.is_empty());
+        match self {
+            Self::M3u8 | Self::M3u => {
+                let mut text = match self {
+                    Self::M3u8 => "#EXTM3U\n".to_string(),
+                    _ => String::new(),
+                };
+                if let Some(description) = description {
+                    text.push_str(&format!("#PLAYLIST:{description}\n"));
+                }
+                for path in paths {
+                    text.push_str(&format!("{path}\n"));
+                }
+                text
+            }
+            Self::Pls => {
+                let mut text = "[playlist]\n".to_string();
+                for (i, path) in paths.enumerate() {
+                    text.push_str(&format!("File{}={path}\n", i + 1));
+                }
+                text.push_str(&format!("NumberOfEntries={}\nVersion=2\n", tracks.len()));
+                text
+            }
+            Self::Xspf => {
+                let mut text = format!(
+                    "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n\
+                     <playlist version=\"1\" xmlns=\"http://xspf.org/ns/0/\">\n  \
+                     <title>{}</title>\n",
+                    escape(name)
+                );
+                if let Some(description) = description {
+                    text.push_str(&format!(
+                        "  <annotation>{}</annotation>\n",
+                        escape(description)
+                    ));
+                }
+                text.push_str("  <trackList>\n");
+                for path in paths {
+                    let uri = format!("file://{}", utf8_percent_encode(&path, URI));
+                    text.push_str(&format!(
+                        "    <track><location>{}</location></track>\n",
+                        escape(&uri)
+                    ));
+                }
+                text.push_str("  </trackList>\n</playlist>\n");
+                text
+            }
+        }
+    }
+}
+
+/// Removes the `.` and `..` of `path` without looking at the files, for the paths to match the
+/// ones found by the scan
+fn normalize(path: &Path) -> PathBuf {
+    let mut normalized = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir => {
+                normalized.pop();
+            }
+            component => normalized.push(component),
+        }
+    }
+    normalized
+}
+
+/// Path of the file an `entry` of a playlist in `dir` points to, `None` for streams
+fn resolve(dir: &Path, entry: &str) -> Option<PathBuf> {
+    let entry = match entry.strip_prefix("file://") {
+        // `file:///music/a.flac`, or `file://localhost/music/a.flac`
+        Some(uri) => {
+            let path = uri.strip_prefix("localhost").unwrap_or(uri);
+            percent_decode_str(path).decode_utf8_lossy().to_string()
+        }
+        None if entry.contains("://") => return None,
+        None => entry.to_string(),
+    };
+    let entry = entry.replace('\\', "/");
+
+    Some(normalize(&dir.join(entry)))
+}
+
+/// Text of the playlist file, M3U files may be in Latin-1
+fn read_text(path: &Path, format: PlaylistFormat) -> std::io::Result<String> {
+    let bytes = std::fs::read(path)?;
+    let text = match String::from_utf8(bytes) {
+        Ok(text) => text,
+        Err(e) if format == PlaylistFormat::M3u => {
+            e.into_bytes().iter().map(|&b| b as char).collect()
+        }
+        Err(e) => String::from_utf8_lossy(e.as_bytes()).to_string(),
+    };
+
+    Ok(text.trim_start_matches('\u{feff}').to_string())
+}
+
+/// Reads the playlist at `path`, keeping the entries pointing to existing files
+pub fn parse(path: PathBuf) -> Playlist {
+    let name = path
+        .file_stem()
+        .and_then(|x| x.to_str())
+        .unwrap_or("@UNKNOWN@");
+    let format = PlaylistFormat::from_path(&path).unwrap_or(PlaylistFormat::M3u8);
+    let text = read_text(&path, format).unwrap_or_else(|e| {
+        warn!("Unable to read the playlist `{}`: {e}", path.display());
+        String::new()
+    });
+    let dir = path.parent().unwrap_or(Path::new("/"));
+    let (entries, description) = format.entries(&text);
+
+    let mut playlist = Playlist {
+        name: name.to_string(),
+        path: format!("{}", path.display()),
+        tracks: entries
+            .iter()
+            .filter_map(|x| resolve(dir, x))
+            .filter(|p| p.exists())
+            .collect(),
+        id: String::new(),
+        description,
+        cover_url: None,
+    };
+
+    let data = format!(
+        "{}{}#{}",
+        playlist.name,
+        playlist.path,
+        playlist.tracks.len()
+    );
+
+    let id = md5::compute(data);
+    playlist.id = format!("{id:x}");
+
+    playlist
+}
+
+/// Entries of `playlist` as written pointing to none of `tracks`, read again as the parsed
+/// playlist leaves them out. Streams aren't checked.
+pub fn broken_entries(playlist: &Playlist, tracks: &TrackCollection) -> Vec<String> {
+    let path = Path::new(&playlist.path);
+    let format = PlaylistFormat::from_path(path).unwrap_or(PlaylistFormat::M3u8);
+    let Ok(text) = read_text(path, format) else {
+        return vec![];
+    };
+    let dir = path.parent().unwrap_or(Path::new("/"));
+
+    format
+        .entries(&text)
+        .0
+        .into_iter()
+        .filter(|x| resolve(dir, x).is_some_and(|x| !tracks.contains_key(&x)))
+        .collect()
+}
+
+/// The image next to `playlist` named like it, e.g. `Road trip.jpg`
+pub fn cover(playlist: &Playlist) -> Option<PathBuf> {
+    let path = Path::new(&playlist.path);
+    COVER_EXTENSIONS
+        .iter()
+        .flat_map(|ext| [ext.to_string(), ext.to_uppercase()])
+        .map(|ext| path.with_extension(ext))
+        .find(|x| x.is_file())
+}
+
+/// The playlists in folders mirroring the ones they sit in under `roots`, the folders of the
+/// library. Folders and playlists are sorted by name.
+pub fn tree(playlists: &[Playlist], roots: &[PathBuf]) -> PlaylistFolder {
+    let mut root = PlaylistFolder::default();
+    for playlist in playlists {
+        let path = Path::new(&playlist.path);
+        let relative = roots
+            .iter()
+            .find_map(|x| path.strip_prefix(x).ok())
+            .unwrap_or(path);
+
+        let mut folder = &mut root;
+        for name in relative
+            .parent()
+            .into_iter()
+            .flat_map(|x| x.components())
+            .filter_map(|x| match x {
+                Component::Normal(name) => Some(name.to_string_lossy().to_string()),
+                _ => None,
+            })
+        {
+            let i = match folder.folders.iter().position(|x| x.name == name) {
+                Some(i) => i,
+                None => {
+                    folder.folders.push(PlaylistFolder {
+                        name,
+                        ..Default::default()
+                    });
+                    folder.folders.len() - 1
+                }
+            };
+            folder = &mut folder.folders[i];
+        }
+        folder.playlists.push(playlist.clone());
+    }
+
+    sort(&mut root);
+    root
+}
+
+fn sort(folder: &mut PlaylistFolder) {
+    folder.folders.sort_by_key(|x| x.name.to_lowercase());
+    folder.playlists.sort_by_key(|x| x.name.to_lowercase());
+    for folder in &mut folder.folders {
+        sort(folder);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FORMATS: [PlaylistFormat; 4] = [
+        PlaylistFormat::M3u8,
+        PlaylistFormat::M3u,
+        PlaylistFormat::Pls,
+        PlaylistFormat::Xspf,
+    ];
+
+    /// A temporary folder holding `names`, returned with their paths
+    fn files(names: &[&str]) -> (PathBuf, Vec<PathBuf>) {
+        let root = std::env::temp_dir().join(format!("lorchestre-{}", uuid::Uuid::new_v4()));
+        let paths: Vec<PathBuf> = names.iter().map(|x| root.join(x)).collect();
+        for path in &paths {
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(path, b"").unwrap();
+        }
+        (root, paths)
+    }
+
+    #[test]
+    fn round_trip() {
+        let (root, tracks) = files(&[
+            "Artist/01 - Plain.flac",
+            "Artist/02 - Rock & Roll #1 <live> 100%?.flac",
+            "Été/03 - Déjà vu.mp3",
+        ]);
+        for format in FORMATS {
+            let path = root.join(format!("Mix.{}", format.extension()));
+            let text = format.render("Mix <&> \"quoted\"", Some("Tom & Jerry's <b>"), &tracks);
+            std::fs::write(&path, text).unwrap();
+
+            let playlist = parse(path.clone());
+            assert_eq!(playlist.name, "Mix", "{format:?}");
+            assert_eq!(playlist.tracks, tracks, "{format:?}");
+            let description = match format {
+                PlaylistFormat::Pls => None,
+                _ => Some("Tom & Jerry's <b>".to_string()),
+            };
+            assert_eq!(playlist.description, description, "{format:?}");
+        }
+
+        std::fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    fn xspf_is_escaped() {
+        let text = PlaylistFormat::Xspf.render(
+            "A <b> & c",
+            None,
+            &[PathBuf::from("/music/a & b/#1 <x>.flac")],
+        );
+        assert!(text.contains("<title>A &lt;b&gt; &amp; c</title>"));
+        assert!(
+            text.contains("<location>file:///music/a%20&amp;%20b/%231%20%3Cx%3E.flac</location>")
+        );
+        assert!(!text.contains("<annotation>"));
+    }
+
+    #[test]
+    fn relative_entries() {
+        let dir = Path::new("/music/playlists");
+        assert_eq!(
+            resolve(dir, "../Artist/./a.flac"),
+            Some(PathBuf::from("/music/Artist/a.flac"))
+        );
+        assert_eq!(
+            resolve(dir, "..\\Artist\\b.flac"),
+            Some(PathBuf::from("/music/Artist/b.flac"))
+        );
+        assert_eq!(
+            resolve(dir, "/elsewhere/c.flac"),
+            Some(PathBuf::from("/elsewhere/c.flac"))
+        );
+        assert_eq!(
+            resolve(dir, "file://localhost/music/d%20e.flac"),
+            Some(PathBuf::from("/music/d e.flac"))
+        );
+        assert_eq!(
+            resolve(dir, "file:///music/%C3%A9t%C3%A9.flac"),
+            Some(PathBuf::from("/music/été.flac"))
+        );
+        assert_eq!(resolve(dir, "https://radio.example/stream"), None);
+    }
+
+    #[test]
+    fn entries_as_written() {
+        let m3u = "#EXTM3U\n#PLAYLIST: Road trip \n#EXTINF:123,A - B\n\r\na.flac\n  b.flac  \n";
+        assert_eq!(
+            PlaylistFormat::M3u8.entries(m3u),
+            (
+                vec!["a.flac".to_string(), "b.flac".to_string()],
+                Some("Road trip".to_string())
+            )
+        );
+
+        // PLS entries are ordered by their number, not by their line
+        let pls = "[playlist]\nFile2=b.flac\nTitle2=B\nFile10=c.flac\nFile1 = a.flac\nNumberOfEntries=3\n";
+        assert_eq!(
+            PlaylistFormat::Pls.entries(pls).0,
+            vec!["a.flac", "b.flac", "c.flac"]
+        );
+    }
+
+    #[test]
+    fn missing_files_are_left_out() {
+        let (root, tracks) = files(&["a.flac"]);
+        let path = root.join("list.m3u8");
+        let text = "#EXTM3U\na.flac\ngone.flac\nhttp://radio.example/stream\n";
+        std::fs::write(&path, text).unwrap();
+
+        assert_eq!(parse(path).tracks, tracks);
+
+        std::fs::remove_dir_all(root).unwrap();
+    }
+}
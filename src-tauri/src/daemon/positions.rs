@@ -0,0 +1,68 @@
+use crate::daemon::store;
+use mu_protocol::library::SavedPosition;
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::time::SystemTime;
+
+/// Where listening stopped in tracks, keyed by track id
+#[derive(Debug, Default)]
+pub struct Positions {
+    path: PathBuf,
+    entries: HashMap<String, SavedPosition>,
+}
+
+impl Positions {
+    pub fn load(path: PathBuf) -> Self {
+        let entries = store::load(&path, "positions");
+
+        Self { path, entries }
+    }
+
+    fn save(&self) {
+        store::save(&self.path, "positions", &self.entries);
+    }
+
+    pub fn get(&self, track_id: &str) -> Option<SavedPosition> {
+        self.entries.get(track_id).copied()
+    }
+
+    pub fn set(&mut self, track_id: &str, position: u64) -> SavedPosition {
+        let position = SavedPosition {
+            position,
+            updated_at: SystemTime::now(),
+        };
+        self.entries.insert(track_id.to_string(), position);
+        self.save();
+
+        position
+    }
+
+    pub fn remove(&mut self, track_id: &str) -> bool {
+        let removed = self.entries.remove(track_id).is_some();
+        if removed {
+            self.save();
+        }
+
+        removed
+    }
+
+    /// Moves the positions of the track ids found in `ids` to the ids they map to
+    pub fn remap_tracks(&mut self, ids: &HashMap<String, String>) -> bool {
+        let legacy: Vec<String> = self
+            .entries
+            .keys()
+            .filter(|x| ids.contains_key(*x))
+            .cloned()
+            .collect();
+        for old in &legacy {
+            if let Some(position) = self.entries.remove(old) {
+                self.entries.insert(ids[old].clone(), position);
+            }
+        }
+        if !legacy.is_empty() {
+            self.save();
+        }
+
+        !legacy.is_empty()
+    }
+}
@@ -0,0 +1,191 @@
+//! Serving behind a reverse proxy. `network.base_path` is the path the daemon is reached under,
+//! e.g. `/mu` for nginx passing `location /mu/` on without stripping it. Requests are answered
+//! with or without it, and the URLs handed out, like the covers of the albums, start with it.
+//! Absolute URLs follow the `X-Forwarded-Proto` and `X-Forwarded-Host` headers of the proxy.
+
+use axum::extract::Request;
+use axum::http::header::{FORWARDED, HOST};
+use axum::http::{HeaderMap, HeaderName, Uri};
+use axum::middleware::Next;
+use axum::response::Response;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::OnceLock;
+
+const FORWARDED_PROTO: HeaderName = HeaderName::from_static("x-forwarded-proto");
+const FORWARDED_HOST: HeaderName = HeaderName::from_static("x-forwarded-host");
+const FORWARDED_FOR: HeaderName = HeaderName::from_static("x-forwarded-for");
+const REAL_IP: HeaderName = HeaderName::from_static("x-real-ip");
+
+/// Read once on start, the URLs stored in the cache are made again on the next start
+static BASE_PATH: OnceLock<String> = OnceLock::new();
+
+/// `/mu` for `mu`, `/mu/` or `/mu`, and nothing for `/`
+fn normalize(value: &str) -> String {
+    let value = value.trim().trim_matches('/');
+    match value.is_empty() {
+        true => String::new(),
+        false => format!("/{value}"),
+    }
+}
+
+/// Keeps the `network.base_path` of `config` for the lifetime of the daemon
+pub fn init(config: &lorconf::Config) {
+    let base_path = config
+        .network
+        .as_ref()
+        .and_then(|network| network.base_path.as_deref())
+        .map(normalize)
+        .unwrap_or_default();
+    let _ = BASE_PATH.set(base_path);
+}
+
+/// `network.base_path` without its trailing slash, empty when unset
+pub fn base_path() -> &'static str {
+    BASE_PATH.get().map_or("", |x| x.as_str())
+}
+
+/// The URL of the route `path` as handed out to the clients
+pub fn url(path: &str) -> String {
+    format!("{}{path}", base_path())
+}
+
+/// Whether the request of `headers` was passed on by a proxy, its peer is then the proxy
+pub fn forwarded(headers: &HeaderMap) -> bool {
+    [
+        &FORWARDED,
+        &FORWARDED_FOR,
+        &FORWARDED_HOST,
+        &FORWARDED_PROTO,
+        &REAL_IP,
+    ]
+    .into_iter()
+    .any(|x| headers.contains_key(x))
+}
+
+/// The URL of the daemon as seen by the client of `headers`, e.g. `https://example.org/mu`,
+/// `https` by default when the daemon serves TLS
+pub fn origin(headers: &HeaderMap, tls: bool) -> Option<String> {
+    let header = |name: &HeaderName| {
+        headers
+            .get(name)
+            .and_then(|x| x.to_str().ok())
+            // Proxies in a row append theirs, the first one is the client's
+            .and_then(|x| x.split(',').next())
+            .map(|x| x.trim().to_string())
+            .filter(|x| !x.is_empty())
+    };
+    let scheme = header(&FORWARDED_PROTO).unwrap_or(if tls { "https" } else { "http" }.into());
+    let host = header(&FORWARDED_HOST).or_else(|| header(&HOST))?;
+    Some(format!("{scheme}://{host}{}", base_path()))
+}
+
+/// `1.2.3.4`, `1.2.3.4:80`, `::1` or `[::1]:80`, quoted or not as in `Forwarded`
+fn address(value: &str) -> Option<IpAddr> {
+    let value = value.trim().trim_matches('"');
+    value
+        .parse::<IpAddr>()
+        .ok()
+        .or_else(|| value.parse::<SocketAddr>().ok().map(|x| x.ip()))
+        .or_else(|| {
+            value
+                .trim_start_matches('[')
+                .trim_end_matches(']')
+                .parse()
+                .ok()
+        })
+}
+
+/// The address of the client of the request of `headers` received from `peer`. Proxies on
+/// the machine itself are trusted with the address they pass on, in `X-Real-IP`, or the
+/// last entry of `X-Forwarded-For` or `Forwarded`, the one they added: the client can't
+/// pick it to get a new rate limit. Remote peers could, they are their own clients.
+pub fn client(headers: &HeaderMap, peer: IpAddr) -> IpAddr {
+    if !peer.is_loopback() {
+        return peer;
+    }
+    let last = |name: &HeaderName| {
+        headers
+            .get(name)
+            .and_then(|x| x.to_str().ok())
+            .and_then(|x| x.rsplit(',').next())
+            .map(|x| x.to_string())
+    };
+    let real_ip = last(&REAL_IP).and_then(|x| address(&x));
+    let forwarded_for = || last(&FORWARDED_FOR).and_then(|x| address(&x));
+    let forwarded = || {
+        last(&FORWARDED)?
+            .split(';')
+            .filter_map(|x| x.trim().split_once('='))
+            .find(|(key, _)| key.eq_ignore_ascii_case("for"))
+            .and_then(|(_, value)| address(value))
+    };
+    real_ip
+        .or_else(forwarded_for)
+        .or_else(forwarded)
+        .unwrap_or(peer)
+}
+
+/// Turns a `<base_path>/...` path into `/...`. Applied around the router, before routing.
+pub async fn strip_base(mut request: Request, next: Next) -> Response {
+    let base = base_path();
+    let rest = match request.uri().path().strip_prefix(base) {
+        Some(rest) if !base.is_empty() && (rest.is_empty() || rest.starts_with('/')) => rest,
+        _ => return next.run(request).await,
+    };
+    let path = if rest.is_empty() { "/" } else { rest };
+    let path_and_query = match request.uri().query() {
+        Some(query) => format!("{path}?{query}"),
+        None => path.to_string(),
+    };
+    if let Ok(uri) = Uri::builder().path_and_query(path_and_query).build() {
+        *request.uri_mut() = uri;
+    }
+
+    next.run(request).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn headers(values: &[(&'static str, &str)]) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        for (name, value) in values {
+            headers.insert(*name, value.parse().unwrap());
+        }
+        headers
+    }
+
+    #[test]
+    fn clients_behind_a_local_proxy() {
+        let local = IpAddr::from([127, 0, 0, 1]);
+        let client = |values| client(&headers(values), local);
+
+        assert_eq!(client(&[]), local);
+        assert_eq!(
+            client(&[("x-real-ip", "203.0.113.7")]),
+            IpAddr::from([203, 0, 113, 7])
+        );
+        // The proxy appends the address it got the request from
+        assert_eq!(
+            client(&[("x-forwarded-for", "10.0.0.1, 203.0.113.7")]),
+            IpAddr::from([203, 0, 113, 7])
+        );
+        assert_eq!(
+            client(&[(
+                "forwarded",
+                "for=10.0.0.1, for=\"[2001:db8::1]:4711\";proto=https"
+            )]),
+            "2001:db8::1".parse::<IpAddr>().unwrap()
+        );
+        assert_eq!(client(&[("x-forwarded-for", "unknown")]), local);
+    }
+
+    #[test]
+    fn remote_peers_are_their_own_clients() {
+        let peer = IpAddr::from([198, 51, 100, 2]);
+        let headers = headers(&[("x-forwarded-for", "203.0.113.7")]);
+
+        assert_eq!(client(&headers, peer), peer);
+    }
+}
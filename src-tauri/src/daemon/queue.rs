@@ -0,0 +1,188 @@
+//! The shared play queue, saved by the players with `PUT /queue` so that the one playing after
+//! a restart, or on another client, resumes where listening stopped. A radio may keep it topped
+//! up, see [`radio`].
+
+use crate::daemon::global::Media;
+use crate::daemon::radio;
+use crate::daemon::store;
+use mu_protocol::api::{NewQueue, PlayQueue, QualityQuery, RadioSeed};
+use std::collections::{HashMap, HashSet};
+use std::path::PathBuf;
+use std::time::SystemTime;
+
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone)]
+struct SavedQueue {
+    /// Track ids
+    tracks: Vec<String>,
+    current: Option<usize>,
+    /// In milliseconds
+    position: u64,
+    playing: bool,
+    updated_at: SystemTime,
+    #[serde(default)]
+    radio: Option<RadioSeed>,
+}
+
+impl Default for SavedQueue {
+    fn default() -> Self {
+        Self {
+            tracks: vec![],
+            current: None,
+            position: 0,
+            playing: false,
+            updated_at: SystemTime::UNIX_EPOCH,
+            radio: None,
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct Queue {
+    path: PathBuf,
+    saved: SavedQueue,
+}
+
+impl Queue {
+    pub fn load(path: PathBuf) -> Self {
+        let saved = store::load(&path, "queue");
+
+        Self { path, saved }
+    }
+
+    fn save(&self) {
+        store::save(&self.path, "queue", &self.saved);
+    }
+
+    /// Replaces the queue, its tracks being ids of existing tracks. The radio goes on.
+    pub fn set(&mut self, queue: NewQueue) {
+        let current = queue.current.filter(|x| *x < queue.tracks.len());
+        self.saved = SavedQueue {
+            tracks: queue.tracks,
+            current,
+            position: if current.is_some() { queue.position } else { 0 },
+            playing: queue.playing && current.is_some(),
+            updated_at: SystemTime::now(),
+            radio: self.saved.radio.take(),
+        };
+        self.save();
+    }
+
+    /// Starts a radio like `seed`, on the tracks `first` in place of the queue when given
+    pub fn start_radio(&mut self, seed: RadioSeed, first: Option<Vec<String>>) {
+        if let Some(tracks) = first {
+            self.saved = SavedQueue {
+                current: (!tracks.is_empty()).then_some(0),
+                tracks,
+                updated_at: SystemTime::now(),
+                ..Default::default()
+            };
+        }
+        self.saved.radio = Some(seed);
+        self.save();
+    }
+
+    /// Stops the radio, returns whether there was one
+    pub fn stop_radio(&mut self) -> bool {
+        let stopped = self.saved.radio.take().is_some();
+        if stopped {
+            self.save();
+        }
+        stopped
+    }
+
+    /// Queues tracks of the radio until [`radio::AHEAD`] tracks follow the current one, leaving
+    /// out the last `window` tracks of the queue and the ones `quality` doesn't keep. Returns
+    /// whether any was queued.
+    pub fn top_up(&mut self, media: &Media, window: usize, quality: &QualityQuery) -> bool {
+        let Some(seed) = &self.saved.radio else {
+            return false;
+        };
+        let tracks = &self.saved.tracks;
+        let ahead = tracks.len() - self.saved.current.map_or(0, |x| x + 1);
+        if ahead >= radio::AHEAD {
+            return false;
+        }
+        let excluded: HashSet<String> = tracks
+            .iter()
+            .skip(tracks.len().saturating_sub(window))
+            .cloned()
+            .collect();
+
+        let picks = radio::pick(media, seed, &excluded, radio::AHEAD - ahead, quality);
+        if picks.is_empty() {
+            return false;
+        }
+        // A radio started on an empty queue plays its first pick
+        if self.saved.tracks.is_empty() {
+            self.saved.current = Some(0);
+        }
+        self.saved.tracks.extend(picks);
+        self.save();
+        true
+    }
+
+    /// Queues `ids` at the end, or right after the current track when `next`
+    pub fn add(&mut self, ids: Vec<String>, next: bool) {
+        let at = match self.saved.current {
+            Some(current) if next => current + 1,
+            _ => self.saved.tracks.len(),
+        };
+        self.saved.tracks.splice(at..at, ids);
+        self.save();
+    }
+
+    /// The queue with the tracks of `media`, leaving out the ones gone since. While playing,
+    /// the position moves on with the time elapsed since it was saved, up to the end of the
+    /// current track.
+    pub fn get(&self, media: &Media) -> PlayQueue {
+        let saved = &self.saved;
+        let mut tracks = vec![];
+        let mut current = None;
+        for (i, id) in saved.tracks.iter().enumerate() {
+            let Some(track) = media.get_track(&id.as_str().into()) else {
+                continue;
+            };
+            if saved.current == Some(i) {
+                current = Some(tracks.len());
+            }
+            tracks.push(track);
+        }
+
+        let position = match current {
+            Some(i) if saved.playing => {
+                let elapsed = SystemTime::now()
+                    .duration_since(saved.updated_at)
+                    .unwrap_or_default()
+                    .as_millis() as u64;
+                (saved.position + elapsed).min(tracks[i].duration * 1000)
+            }
+            Some(_) => saved.position,
+            None => 0,
+        };
+
+        PlayQueue {
+            tracks,
+            current,
+            position,
+            playing: saved.playing && current.is_some(),
+            updated_at: saved.updated_at,
+            radio: saved.radio.clone(),
+        }
+    }
+
+    /// Replaces the track ids found in `ids` with the ids they map to
+    pub fn remap_tracks(&mut self, ids: &HashMap<String, String>) -> bool {
+        let mut changed = false;
+        for id in self.saved.tracks.iter_mut() {
+            if let Some(new) = ids.get(id) {
+                *id = new.clone();
+                changed = true;
+            }
+        }
+        if changed {
+            self.save();
+        }
+
+        changed
+    }
+}
@@ -0,0 +1,69 @@
+//! The radio of the shared queue, started with `POST /queue/radio`. Each time the queue is saved,
+//! e.g. as a player moves on to the next track, it is topped up with tracks like the seed so that
+//! a few are always ahead of the current one. The last tracks of the queue, `player.radio_window`
+//! of them, aren't queued again.
+
+use crate::daemon::global::Media;
+use crate::daemon::similar::{self, Profile};
+use mu_protocol::api::{QualityQuery, RadioSeed};
+use rand::seq::SliceRandom;
+use std::collections::HashSet;
+
+/// Tracks kept ahead of the current one
+pub const AHEAD: usize = 10;
+/// The picks are drawn from this many of the most alike tracks, for the radio not to play the
+/// same tracks in the same order each time
+const CANDIDATES: usize = 25;
+
+/// `player.radio_window`
+pub fn window(config: &lorconf::Config) -> usize {
+    config
+        .player
+        .as_ref()
+        .and_then(|player| player.radio_window)
+        .or(lorconf::Player::default().radio_window)
+        .unwrap_or_default()
+}
+
+/// The profile of `seed`, `None` when its track or album is gone or no track is of its genre
+pub fn profile(media: &Media, seed: &RadioSeed) -> Option<Profile> {
+    match seed {
+        RadioSeed::Track { id } => Some(Profile::of([media.track(&id.as_str().into())?])),
+        RadioSeed::Album { id } => {
+            let album = media.album(&id.as_str().into())?;
+            Some(Profile::of(
+                album.tracks.iter().filter_map(|x| media.tracks.get(x)),
+            ))
+        }
+        RadioSeed::Genre { name } => {
+            let name = name.to_lowercase();
+            media
+                .tracks
+                .values()
+                .any(|x| x.genre.as_ref().is_some_and(|x| x.to_lowercase() == name))
+                .then(|| Profile::genre(&name))
+        }
+    }
+}
+
+/// Ids of up to `count` tracks like `seed` kept by `quality`, none of `excluded`
+pub fn pick(
+    media: &Media,
+    seed: &RadioSeed,
+    excluded: &HashSet<String>,
+    count: usize,
+    quality: &QualityQuery,
+) -> Vec<String> {
+    let Some(profile) = profile(media, seed) else {
+        return vec![];
+    };
+    let mut candidates: Vec<String> = similar::similar(media, &profile, quality)
+        .into_iter()
+        .map(|(_, x)| x.id.clone())
+        .filter(|x| !excluded.contains(x))
+        .take(CANDIDATES.max(count))
+        .collect();
+    candidates.shuffle(&mut rand::thread_rng());
+    candidates.truncate(count);
+    candidates
+}
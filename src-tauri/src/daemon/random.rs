@@ -0,0 +1,66 @@
+use crate::daemon::global::Media;
+use mu_protocol::api::{QualityQuery, RandomAlbumQuery, RandomTracks, RandomTracksQuery};
+use mu_protocol::library::{Album, Track};
+use rand::rngs::StdRng;
+use rand::seq::SliceRandom;
+use rand::{Rng, SeedableRng};
+
+fn rng(seed: Option<u64>) -> (u64, StdRng) {
+    let seed = seed.unwrap_or_else(|| rand::thread_rng().gen());
+    (seed, StdRng::seed_from_u64(seed))
+}
+
+pub fn random_tracks(
+    media: &Media,
+    query: RandomTracksQuery,
+    quality: &QualityQuery,
+) -> RandomTracks {
+    let genre = query.genre.map(|x| x.to_lowercase());
+    let mut tracks: Vec<&Track> = media
+        .tracks
+        .values()
+        .filter(|track| match &genre {
+            Some(genre) => track
+                .genre
+                .as_ref()
+                .is_some_and(|x| x.to_lowercase() == *genre),
+            None => true,
+        })
+        .filter(|track| match query.min_year {
+            Some(min_year) => track.album_year.is_some_and(|year| year >= min_year),
+            None => true,
+        })
+        .filter(|track| quality.keeps(track))
+        .collect();
+
+    // The collection is unordered, sort it first so a seed always gives the same shuffle
+    tracks.sort_by(|a, b| a.file_path.cmp(&b.file_path));
+    let (seed, mut rng) = rng(query.seed);
+    tracks.shuffle(&mut rng);
+
+    RandomTracks {
+        seed,
+        total: tracks.len(),
+        tracks: tracks
+            .into_iter()
+            .skip(query.offset.unwrap_or(0))
+            .take(query.limit.unwrap_or(50))
+            .cloned()
+            .collect(),
+    }
+}
+
+pub fn random_album(
+    media: &Media,
+    query: RandomAlbumQuery,
+    quality: &QualityQuery,
+) -> Option<Album> {
+    let mut albums: Vec<&Album> = media
+        .albums
+        .iter()
+        .filter(|x| quality.keeps_album(x))
+        .collect();
+    albums.sort_by(|a, b| a.id.cmp(&b.id));
+    let (_, mut rng) = rng(query.seed);
+    albums.choose(&mut rng).map(|x| (*x).clone())
+}
@@ -0,0 +1,220 @@
+//! Reconciliation of the cache of a library with its files: a file gone from one path and
+//! found at another with the same size and content was moved, its tracks are kept instead of
+//! being read again. A file whose size or modification time changed at the same path was
+//! edited, e.g. retagged, and is read again. Sizes, times and hashes of the files are kept in
+//! `.cache.sums`.
+
+use crate::daemon::playlist::PlaylistFormat;
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+use std::time::UNIX_EPOCH;
+use tracing::warn;
+
+/// Bytes hashed from the start of a file, enough to tell apart files of the same size
+const HASHED: u64 = 64 * 1024;
+
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct Fingerprint {
+    pub size: u64,
+    pub hash: String,
+    /// Modification time in milliseconds since the epoch, missing from the fingerprints taken
+    /// before it was kept
+    #[serde(default)]
+    pub modified: Option<u64>,
+}
+
+/// Modification time of a file in milliseconds since the epoch
+fn modified(metadata: &fs::Metadata) -> Option<u64> {
+    let since = metadata.modified().ok()?.duration_since(UNIX_EPOCH).ok()?;
+    Some(since.as_millis() as u64)
+}
+
+impl Fingerprint {
+    pub fn of(path: &Path) -> std::io::Result<Self> {
+        let f = fs::File::open(path)?;
+        let metadata = f.metadata()?;
+        let mut head = vec![];
+        f.take(HASHED).read_to_end(&mut head)?;
+
+        Ok(Self {
+            size: metadata.len(),
+            hash: format!("{:x}", md5::compute(&head)),
+            modified: modified(&metadata),
+        })
+    }
+
+    /// Fingerprint hashing the whole file instead of its start
+    pub fn whole(path: &Path) -> std::io::Result<Self> {
+        let mut f = fs::File::open(path)?;
+        let metadata = f.metadata()?;
+        let mut context = md5::Context::new();
+        std::io::copy(&mut f, &mut context)?;
+
+        Ok(Self {
+            size: metadata.len(),
+            hash: format!("{:x}", context.compute()),
+            modified: modified(&metadata),
+        })
+    }
+
+    /// Whether the file at `path` has the same size and modification time as when the
+    /// fingerprint was taken
+    pub fn is_current(&self, path: &Path) -> bool {
+        self.modified.is_some()
+            && fs::metadata(path)
+                .is_ok_and(|x| x.len() == self.size && modified(&x) == self.modified)
+    }
+
+    /// Whether both fingerprints are of the same content, wherever and whenever written
+    fn same_content(&self, other: &Self) -> bool {
+        self.size == other.size && self.hash == other.hash
+    }
+}
+
+/// Fingerprints of the files of a library, keyed by path
+#[derive(Debug, Default)]
+pub struct Fingerprints {
+    path: PathBuf,
+    entries: HashMap<PathBuf, Fingerprint>,
+}
+
+impl Fingerprints {
+    pub fn load(cache_dir: &Path) -> Self {
+        let path = cache_dir.join(".cache.sums");
+        let mut entries = HashMap::new();
+        if path.exists() {
+            let mut buf = String::new();
+            if let Ok(mut f) = fs::File::open(&path) {
+                let _ = f.read_to_string(&mut buf);
+            }
+            match serde_json::from_str(&buf) {
+                Ok(parsed) => entries = parsed,
+                Err(e) => warn!("Unable to read fingerprints `{}`: {e}", path.display()),
+            }
+        }
+
+        Self { path, entries }
+    }
+
+    fn save(&self) {
+        let data = serde_json::to_string(&self.entries).unwrap();
+        match fs::File::create(&self.path) {
+            Ok(mut f) => {
+                let _ = f.write_all(data.as_bytes());
+            }
+            Err(e) => warn!("Unable to save fingerprints `{}`: {e}", self.path.display()),
+        }
+    }
+
+    /// Pairs the files of `removed` with the files of `added` they were moved to. Playlists are
+    /// left out, they are cheap to read again.
+    pub fn find_moves(&self, removed: &[PathBuf], added: &[PathBuf]) -> Vec<(PathBuf, PathBuf)> {
+        let mut gone: Vec<(&PathBuf, &Fingerprint)> = removed
+            .iter()
+            .filter(|x| !is_playlist(x))
+            .filter_map(|x| self.entries.get(x).map(|f| (x, f)))
+            .collect();
+
+        let mut moves = vec![];
+        for file in added.iter().filter(|x| !is_playlist(x)) {
+            let Ok(size) = fs::metadata(file).map(|x| x.len()) else {
+                continue;
+            };
+            // Only hashed when a gone file has the same size
+            if !gone.iter().any(|(_, f)| f.size == size) {
+                continue;
+            }
+            let Ok(fingerprint) = Fingerprint::of(file) else {
+                continue;
+            };
+            if let Some(i) = gone.iter().position(|(_, f)| f.same_content(&fingerprint)) {
+                let (from, _) = gone.swap_remove(i);
+                moves.push((from.clone(), file.clone()));
+            }
+        }
+
+        moves
+    }
+
+    /// The files of `files` edited since their fingerprint was taken. Those without one, or
+    /// with one taken without modification time, aren't known to have changed.
+    pub fn edited<'a>(&self, files: impl Iterator<Item = &'a PathBuf>) -> Vec<PathBuf> {
+        files
+            .filter(|file| {
+                self.entries
+                    .get(*file)
+                    .is_some_and(|x| x.modified.is_some() && !x.is_current(file))
+            })
+            .cloned()
+            .collect()
+    }
+
+    /// Digest of the paths, sizes and modification times of the files, which changes with any
+    /// of them
+    pub fn digest(&self) -> String {
+        let mut entries: Vec<_> = self.entries.iter().collect();
+        entries.sort_by(|a, b| a.0.cmp(b.0));
+        let mut context = md5::Context::new();
+        for (path, fingerprint) in entries {
+            context.consume(path.as_os_str().as_encoded_bytes());
+            context.consume(
+                format!("\0{}\0{:?}\n", fingerprint.size, fingerprint.modified).as_bytes(),
+            );
+        }
+        format!("{:x}", context.compute())
+    }
+
+    /// Keeps the fingerprints of `files`, taking the ones missing or out of date
+    pub fn update(&mut self, files: &[PathBuf]) {
+        let count = self.entries.len();
+        let current: HashSet<&PathBuf> = files.iter().collect();
+        self.entries.retain(|path, _| current.contains(path));
+        let mut changed = self.entries.len() != count;
+
+        for file in files {
+            if self.entries.get(file).is_some_and(|x| x.is_current(file)) {
+                continue;
+            }
+            match Fingerprint::of(file) {
+                Ok(fingerprint) => {
+                    self.entries.insert(file.clone(), fingerprint);
+                    changed = true;
+                }
+                Err(e) => warn!("Unable to fingerprint `{}`: {e}", file.display()),
+            }
+        }
+
+        if changed {
+            self.save();
+        }
+    }
+
+    /// Takes the fingerprints of `files` again, e.g. once their tags were edited
+    pub fn refresh(&mut self, files: &[PathBuf]) {
+        for file in files {
+            match Fingerprint::of(file) {
+                Ok(fingerprint) => {
+                    self.entries.insert(file.clone(), fingerprint);
+                }
+                Err(e) => warn!("Unable to fingerprint `{}`: {e}", file.display()),
+            }
+        }
+        self.save();
+    }
+
+    /// Keeps the fingerprints of the files moved by `moves` under their new paths
+    pub fn rename(&mut self, moves: &HashMap<PathBuf, PathBuf>) {
+        for (from, to) in moves {
+            if let Some(fingerprint) = self.entries.remove(from) {
+                self.entries.insert(to.clone(), fingerprint);
+            }
+        }
+        self.save();
+    }
+}
+
+fn is_playlist(path: &Path) -> bool {
+    PlaylistFormat::from_path(path).is_some()
+}
@@ -0,0 +1,615 @@
+//! Audio files kept on the storages of `[[remotes]]` in the configuration, e.g. S3 buckets,
+//! and scanned into a library besides its folders. Their tracks are at
+//! `remote://<name>/<key>`: the scan lists them and reads their tags through byte ranges, see
+//! [`open`], and `/audio/:id` streams them through a cache of segments in `<cache>/remote`, see
+//! [`respond`]. The storages are reached through the [`MediaSource`] trait, S3 being the only
+//! kind for now.
+
+use crate::daemon::utils;
+use axum::body::{Body, Bytes};
+use axum::http::header::{ACCEPT_RANGES, CONTENT_LENGTH, CONTENT_RANGE};
+use axum::http::{HeaderValue, StatusCode};
+use axum::response::{IntoResponse, Response};
+use axum_extra::headers::Range;
+use chrono::Utc;
+use futures::future::BoxFuture;
+use hmac::{Hmac, Mac};
+use mime_guess::mime;
+use quick_xml::events::Event;
+use quick_xml::Reader as XmlReader;
+use sha2::{Digest, Sha256};
+use std::collections::{HashMap, HashSet};
+use std::io::{Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, OnceLock};
+use tracing::{info, warn};
+
+pub const SCHEME: &str = "remote://";
+
+/// Size of the segments of the files cached by `/audio`
+const SEGMENT: u64 = 1 << 20;
+
+/// Size of the blocks read by the scan, the tags being at the start or the end of the files
+const BLOCK: u64 = 256 * 1024;
+
+/// Blocks kept by a [`Reader`], for the tags read at both ends of a file
+const BLOCKS: usize = 4;
+
+/// SHA-256 of the empty body of the requests
+const EMPTY_SHA256: &str = "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855";
+
+/// Read once on start, see [`init`]
+static SOURCES: OnceLock<HashMap<String, Arc<dyn MediaSource>>> = OnceLock::new();
+
+/// Access to the files of a storage. A missing file fails with
+/// [`std::io::ErrorKind::NotFound`].
+pub trait MediaSource: Send + Sync {
+    /// The keys of the files of the storage
+    fn list(&self) -> BoxFuture<'_, std::io::Result<Vec<String>>>;
+
+    /// The size of the file `key`
+    fn size<'a>(&'a self, key: &'a str) -> BoxFuture<'a, std::io::Result<u64>>;
+
+    /// The bytes `start..end` of the file `key`
+    fn read<'a>(
+        &'a self,
+        key: &'a str,
+        start: u64,
+        end: u64,
+    ) -> BoxFuture<'a, std::io::Result<Vec<u8>>>;
+}
+
+/// Names are the first segment of the paths of the tracks
+fn valid(name: &str) -> bool {
+    !name.is_empty() && !name.contains(['/', '\\'])
+}
+
+/// Keeps the storages of the `remotes` of `config` for the lifetime of the daemon
+pub fn init(config: &lorconf::Config) {
+    let mut sources: HashMap<String, Arc<dyn MediaSource>> = HashMap::new();
+    for remote in config.remotes.iter().flatten() {
+        let name = remote.name.trim();
+        if !valid(name) {
+            warn!("Ignoring the remote with the invalid name `{name}`");
+            continue;
+        }
+        match remote.kind.as_deref().unwrap_or("s3") {
+            "s3" => match S3::new(remote) {
+                Ok(source) => {
+                    sources.insert(name.to_string(), Arc::new(source));
+                }
+                Err(e) => warn!("Ignoring the remote `{name}`: {e}"),
+            },
+            kind => warn!("Ignoring the remote `{name}` of the unknown kind `{kind}`"),
+        }
+    }
+    let _ = SOURCES.set(sources);
+}
+
+/// Roots of the remotes scanned into the library `name`, the remotes naming no library going
+/// to the `first` one
+pub fn roots(config: &lorconf::Config, name: &str, first: bool) -> Vec<PathBuf> {
+    config
+        .remotes
+        .iter()
+        .flatten()
+        .filter(|x| valid(x.name.trim()))
+        .filter(|x| match &x.library {
+            Some(library) => library == name,
+            None => first,
+        })
+        .map(|x| PathBuf::from(format!("{SCHEME}{}", x.name.trim())))
+        .collect()
+}
+
+/// Whether `path` is a file or a root of a remote
+pub fn is_remote(path: &Path) -> bool {
+    path.to_str().is_some_and(|x| x.starts_with(SCHEME))
+}
+
+/// The storage of the remote file `path` and its key there
+fn locate(path: &Path) -> Option<(Arc<dyn MediaSource>, String)> {
+    let (name, key) = path.to_str()?.strip_prefix(SCHEME)?.split_once('/')?;
+    let source = SOURCES.get()?.get(name)?;
+    Some((Arc::clone(source), key.to_string()))
+}
+
+/// The audio files of the remotes among `roots`. A remote that can't be listed keeps its files
+/// of `previous`, for its tracks not to be dropped on a network failure.
+pub async fn files(roots: &[PathBuf], previous: &[PathBuf]) -> Vec<PathBuf> {
+    let mut files = vec![];
+    for root in roots.iter().filter(|x| is_remote(x)) {
+        let root = root.to_string_lossy();
+        let name = root.trim_start_matches(SCHEME).trim_end_matches('/');
+        let Some(source) = SOURCES.get().and_then(|x| x.get(name)) else {
+            warn!("remote: no storage named `{name}`");
+            continue;
+        };
+        let prefix = format!("{SCHEME}{name}/");
+        match source.list().await {
+            Ok(listed) => {
+                info!("remote: {} files in `{name}`", listed.len());
+                files.extend(
+                    listed
+                        .into_iter()
+                        .map(|key| PathBuf::from(format!("{prefix}{key}")))
+                        .filter(|x| {
+                            mime_guess::from_path(x)
+                                .first()
+                                .is_some_and(|x| x.type_() == mime::AUDIO)
+                        }),
+                );
+            }
+            Err(e) => {
+                warn!("remote: unable to list `{name}`, keeping its files: {e}");
+                files.extend(
+                    previous
+                        .iter()
+                        .filter(|x| x.to_str().is_some_and(|x| x.starts_with(&prefix)))
+                        .cloned(),
+                );
+            }
+        }
+    }
+    files
+}
+
+/// Runs `future` from the synchronous code of the scan, which runs on the runtime
+fn wait<T>(future: BoxFuture<'_, T>) -> T {
+    tokio::task::block_in_place(|| tokio::runtime::Handle::current().block_on(future))
+}
+
+/// A remote file read by blocks, for lofty to read its tags without downloading it
+pub struct Reader {
+    source: Arc<dyn MediaSource>,
+    key: String,
+    size: u64,
+    position: u64,
+    /// Blocks read, by offset, the last one read last
+    blocks: Vec<(u64, Vec<u8>)>,
+}
+
+impl Read for Reader {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        if self.position >= self.size || buf.is_empty() {
+            return Ok(0);
+        }
+        let offset = self.position - self.position % BLOCK;
+        let i = match self.blocks.iter().position(|(x, _)| *x == offset) {
+            Some(i) => i,
+            None => {
+                let end = (offset + BLOCK).min(self.size);
+                let data = wait(self.source.read(&self.key, offset, end))?;
+                if self.blocks.len() == BLOCKS {
+                    self.blocks.remove(0);
+                }
+                self.blocks.push((offset, data));
+                self.blocks.len() - 1
+            }
+        };
+        let block = &self.blocks[i].1;
+        let start = (self.position - offset) as usize;
+        let count = buf.len().min(block.len().saturating_sub(start));
+        buf[..count].copy_from_slice(&block[start..start + count]);
+        self.position += count as u64;
+        Ok(count)
+    }
+}
+
+impl Seek for Reader {
+    fn seek(&mut self, pos: SeekFrom) -> std::io::Result<u64> {
+        let position = match pos {
+            SeekFrom::Start(x) => Some(x),
+            SeekFrom::End(x) => self.size.checked_add_signed(x),
+            SeekFrom::Current(x) => self.position.checked_add_signed(x),
+        };
+        match position {
+            Some(position) => {
+                self.position = position;
+                Ok(position)
+            }
+            None => Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                "seek before the start of the file",
+            )),
+        }
+    }
+}
+
+/// A reader of the remote file `path`, `None` for a local one
+pub fn open(path: &Path) -> Option<std::io::Result<Reader>> {
+    if !is_remote(path) {
+        return None;
+    }
+    let Some((source, key)) = locate(path) else {
+        return Some(Err(std::io::Error::new(
+            std::io::ErrorKind::NotFound,
+            format!("no remote storage for `{}`", path.display()),
+        )));
+    };
+    Some(wait(source.size(&key)).map(|size| Reader {
+        source,
+        key,
+        size,
+        position: 0,
+        blocks: vec![],
+    }))
+}
+
+/// The segment `index` of the file `key` of `size` bytes, read from the cache `dir` or fetched
+/// and kept there, and whether it was fetched
+async fn segment(
+    dir: &Path,
+    source: &dyn MediaSource,
+    key: &str,
+    size: u64,
+    index: u64,
+) -> std::io::Result<(Vec<u8>, bool)> {
+    let path = dir.join(index.to_string());
+    if let Ok(data) = tokio::fs::read(&path).await {
+        return Ok((data, false));
+    }
+
+    let start = index * SEGMENT;
+    let data = source.read(key, start, (start + SEGMENT).min(size)).await?;
+    tokio::fs::create_dir_all(dir).await?;
+    // Written aside and renamed, as a concurrent request may be fetching the same segment
+    let tmp = dir.join(format!("{}.tmp", uuid::Uuid::new_v4()));
+    tokio::fs::write(&tmp, &data).await?;
+    tokio::fs::rename(&tmp, &path).await?;
+    Ok((data, true))
+}
+
+/// Removes the oldest segments of `root` until they take at most `max` bytes
+fn trim(root: &Path, max: u64) {
+    let mut segments: Vec<_> = std::fs::read_dir(root)
+        .into_iter()
+        .flatten()
+        .flatten()
+        .flat_map(|x| std::fs::read_dir(x.path()).into_iter().flatten().flatten())
+        .filter_map(|x| {
+            let metadata = x.metadata().ok()?;
+            Some((metadata.modified().ok()?, metadata.len(), x.path()))
+        })
+        .collect();
+    let mut total: u64 = segments.iter().map(|(_, size, _)| size).sum();
+    if total <= max {
+        return;
+    }
+
+    segments.sort();
+    let mut dirs = HashSet::new();
+    for (_, size, path) in segments {
+        if total <= max {
+            break;
+        }
+        if std::fs::remove_file(&path).is_ok() {
+            total -= size;
+            dirs.extend(path.parent().map(Path::to_path_buf));
+        }
+    }
+    for dir in dirs {
+        // Left when it still has segments
+        let _ = std::fs::remove_dir(dir);
+    }
+}
+
+/// Streams the remote file `path` from the segments cached in `<cache_dir>/remote`, fetching
+/// the missing ones, honoring `range` and answering `head` without a body. The segments past
+/// `cache_size` bytes are removed afterwards, the oldest first.
+pub async fn respond(
+    cache_dir: &Path,
+    path: &Path,
+    range: Option<Range>,
+    head: bool,
+    cache_size: u64,
+) -> std::io::Result<Response> {
+    let (source, key) = locate(path).ok_or_else(|| {
+        std::io::Error::new(
+            std::io::ErrorKind::NotFound,
+            format!("no remote storage for `{}`", path.display()),
+        )
+    })?;
+    let size = source.size(&key).await?;
+
+    if head {
+        let mut response = ().into_response();
+        let headers = response.headers_mut();
+        headers.insert(CONTENT_LENGTH, HeaderValue::from(size));
+        headers.insert(ACCEPT_RANGES, HeaderValue::from_static("bytes"));
+        return Ok(response);
+    }
+
+    let bounds = match utils::byte_range(range.as_ref(), size) {
+        Ok(bounds) => bounds,
+        Err(not_satisfiable) => return Ok(not_satisfiable.into_response()),
+    };
+    let (start, end) = bounds.unwrap_or((0, size.saturating_sub(1)));
+
+    // The size is part of the folder, for a file replaced on the storage not to be served from
+    // the segments of the previous one
+    let root = cache_dir.join("remote");
+    let dir = root.join(format!(
+        "{:x}",
+        md5::compute(format!("{}\0{size}", path.display()))
+    ));
+    let stream = futures::stream::try_unfold(start, move |position| {
+        let (source, key, dir, root) =
+            (Arc::clone(&source), key.clone(), dir.clone(), root.clone());
+        async move {
+            if size == 0 || position > end {
+                return Ok(None);
+            }
+            let index = position / SEGMENT;
+            let (data, fetched) = segment(&dir, source.as_ref(), &key, size, index).await?;
+            if fetched {
+                tokio::task::spawn_blocking(move || trim(&root, cache_size));
+            }
+            let offset = index * SEGMENT;
+            let from = (position - offset) as usize;
+            let to = ((end + 1).min(offset + SEGMENT) - offset) as usize;
+            if to > data.len() || from >= to {
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::UnexpectedEof,
+                    format!("the segment {index} of `{key}` is truncated"),
+                ));
+            }
+            Ok::<_, std::io::Error>(Some((
+                Bytes::copy_from_slice(&data[from..to]),
+                offset + to as u64,
+            )))
+        }
+    });
+
+    let mut response = Body::from_stream(stream).into_response();
+    let headers = response.headers_mut();
+    headers.insert(ACCEPT_RANGES, HeaderValue::from_static("bytes"));
+    headers.insert(CONTENT_LENGTH, HeaderValue::from(end + 1 - start));
+    if bounds.is_some() {
+        if let Ok(value) = HeaderValue::from_str(&format!("bytes {start}-{end}/{size}")) {
+            headers.insert(CONTENT_RANGE, value);
+        }
+        *response.status_mut() = StatusCode::PARTIAL_CONTENT;
+    }
+    Ok(response)
+}
+
+/// An S3 bucket, or a bucket of a service speaking its API, addressed by path, e.g.
+/// `http://nas:9000/<bucket>/<key>`. The requests are signed with AWS Signature Version 4,
+/// anonymous without keys.
+struct S3 {
+    client: reqwest::Client,
+    /// Scheme and authority of the endpoint, e.g. `https://s3.eu-west-3.amazonaws.com`
+    origin: String,
+    host: String,
+    /// Path of the bucket on the endpoint, e.g. `/my-music`
+    base: String,
+    region: String,
+    prefix: String,
+    credentials: Option<(String, String)>,
+}
+
+/// `value` encoded as the URIs signed by AWS want it, keeping the `/` of the keys
+fn aws_encode(value: &str, keep_slash: bool) -> String {
+    value
+        .bytes()
+        .map(|b| match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => {
+                (b as char).to_string()
+            }
+            b'/' if keep_slash => "/".to_string(),
+            b => format!("%{b:02X}"),
+        })
+        .collect()
+}
+
+fn hmac(key: &[u8], data: &str) -> Vec<u8> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(key).expect("HMAC takes keys of any size");
+    mac.update(data.as_bytes());
+    mac.finalize().into_bytes().to_vec()
+}
+
+impl S3 {
+    fn new(remote: &lorconf::Remote) -> Result<Self, String> {
+        let endpoint = reqwest::Url::parse(&remote.endpoint)
+            .map_err(|e| format!("invalid endpoint `{}`: {e}", remote.endpoint))?;
+        let host = endpoint
+            .host_str()
+            .ok_or(format!("the endpoint `{}` has no host", remote.endpoint))?;
+        let host = match endpoint.port() {
+            Some(port) => format!("{host}:{port}"),
+            None => host.to_string(),
+        };
+        Ok(Self {
+            client: reqwest::Client::new(),
+            origin: format!("{}://{host}", endpoint.scheme()),
+            base: format!(
+                "{}/{}",
+                endpoint.path().trim_end_matches('/'),
+                aws_encode(&remote.bucket, false)
+            ),
+            host,
+            region: remote.region.clone().unwrap_or("us-east-1".to_string()),
+            prefix: remote.prefix.clone().unwrap_or_default(),
+            credentials: remote.access_key.clone().zip(remote.secret_key.clone()),
+        })
+    }
+
+    /// Sends the request `method` of the object `key`, the bucket itself when empty
+    async fn send(
+        &self,
+        method: reqwest::Method,
+        key: &str,
+        query: &[(&str, &str)],
+        range: Option<(u64, u64)>,
+    ) -> std::io::Result<reqwest::Response> {
+        let uri = match key {
+            "" => self.base.clone(),
+            key => format!("{}/{}", self.base, aws_encode(key, true)),
+        };
+        let mut query: Vec<String> = query
+            .iter()
+            .map(|(k, v)| format!("{}={}", aws_encode(k, false), aws_encode(v, false)))
+            .collect();
+        query.sort();
+        let query = query.join("&");
+        let url = match query.as_str() {
+            "" => format!("{}{uri}", self.origin),
+            query => format!("{}{uri}?{query}", self.origin),
+        };
+
+        let mut request = self.client.request(method.clone(), &url);
+        if let Some((start, end)) = range {
+            request = request.header("Range", format!("bytes={start}-{}", end - 1));
+        }
+        if let Some((access_key, secret_key)) = &self.credentials {
+            let now = Utc::now();
+            let amz_date = now.format("%Y%m%dT%H%M%SZ").to_string();
+            let date = now.format("%Y%m%d").to_string();
+            let signed_headers = "host;x-amz-content-sha256;x-amz-date";
+            let canonical = format!(
+                "{method}\n{uri}\n{query}\nhost:{}\nx-amz-content-sha256:{EMPTY_SHA256}\nx-amz-date:{amz_date}\n\n{signed_headers}\n{EMPTY_SHA256}",
+                self.host
+            );
+            let scope = format!("{date}/{}/s3/aws4_request", self.region);
+            let to_sign = format!(
+                "AWS4-HMAC-SHA256\n{amz_date}\n{scope}\n{:x}",
+                Sha256::digest(canonical.as_bytes())
+            );
+            let key = [self.region.as_str(), "s3", "aws4_request"].iter().fold(
+                hmac(format!("AWS4{secret_key}").as_bytes(), &date),
+                |key, x| hmac(&key, x),
+            );
+            let signature: String = hmac(&key, &to_sign)
+                .iter()
+                .map(|x| format!("{x:02x}"))
+                .collect();
+            request = request
+                .header("x-amz-date", amz_date)
+                .header("x-amz-content-sha256", EMPTY_SHA256)
+                .header(
+                    "Authorization",
+                    format!(
+                        "AWS4-HMAC-SHA256 Credential={access_key}/{scope}, SignedHeaders={signed_headers}, Signature={signature}"
+                    ),
+                );
+        }
+
+        let response = request.send().await.map_err(std::io::Error::other)?;
+        match response.status() {
+            status if status.is_success() => Ok(response),
+            reqwest::StatusCode::NOT_FOUND => Err(std::io::Error::new(
+                std::io::ErrorKind::NotFound,
+                format!("no object `{key}` in the bucket"),
+            )),
+            status => Err(std::io::Error::other(format!(
+                "the storage answered {status} to {method} `{url}`"
+            ))),
+        }
+    }
+
+    /// A page of the keys of the bucket, with the token of the next one
+    async fn list_page(
+        &self,
+        token: Option<&str>,
+    ) -> std::io::Result<(Vec<String>, Option<String>)> {
+        let mut query = vec![("list-type", "2"), ("prefix", self.prefix.as_str())];
+        if let Some(token) = token {
+            query.push(("continuation-token", token));
+        }
+        let text = self
+            .send(reqwest::Method::GET, "", &query, None)
+            .await?
+            .text()
+            .await
+            .map_err(std::io::Error::other)?;
+
+        let mut reader = XmlReader::from_str(&text);
+        reader.trim_text(true);
+        let mut keys = vec![];
+        let mut element = vec![];
+        let (mut key, mut truncated, mut next) = (None, false, None);
+        loop {
+            match reader.read_event() {
+                Ok(Event::Start(e)) => element = e.local_name().as_ref().to_vec(),
+                Ok(Event::Text(e)) => {
+                    let value = e.unescape().map_err(std::io::Error::other)?.to_string();
+                    match element.as_slice() {
+                        b"Key" => key = Some(value),
+                        b"IsTruncated" => truncated = value == "true",
+                        b"NextContinuationToken" => next = Some(value),
+                        _ => {}
+                    }
+                }
+                Ok(Event::End(e)) => {
+                    if e.local_name().as_ref() == b"Contents" {
+                        // Keys ending with a `/` stand for folders
+                        keys.extend(key.take().filter(|x| !x.ends_with('/')));
+                    }
+                    element.clear();
+                }
+                Ok(Event::Eof) => break,
+                Err(e) => return Err(std::io::Error::other(e)),
+                _ => {}
+            }
+        }
+        Ok((keys, next.filter(|_| truncated)))
+    }
+}
+
+impl MediaSource for S3 {
+    fn list(&self) -> BoxFuture<'_, std::io::Result<Vec<String>>> {
+        Box::pin(async move {
+            let mut keys = vec![];
+            let mut token = None;
+            loop {
+                let (page, next) = self.list_page(token.as_deref()).await?;
+                keys.extend(page);
+                match next {
+                    Some(next) => token = Some(next),
+                    None => return Ok(keys),
+                }
+            }
+        })
+    }
+
+    fn size<'a>(&'a self, key: &'a str) -> BoxFuture<'a, std::io::Result<u64>> {
+        Box::pin(async move {
+            let response = self.send(reqwest::Method::HEAD, key, &[], None).await?;
+            response
+                .headers()
+                .get(reqwest::header::CONTENT_LENGTH)
+                .and_then(|x| x.to_str().ok())
+                .and_then(|x| x.parse().ok())
+                .ok_or(std::io::Error::other(format!(
+                    "the storage gave no size for `{key}`"
+                )))
+        })
+    }
+
+    fn read<'a>(
+        &'a self,
+        key: &'a str,
+        start: u64,
+        end: u64,
+    ) -> BoxFuture<'a, std::io::Result<Vec<u8>>> {
+        Box::pin(async move {
+            if start >= end {
+                return Ok(vec![]);
+            }
+            let response = self
+                .send(reqwest::Method::GET, key, &[], Some((start, end)))
+                .await?;
+            let partial = response.status() == reqwest::StatusCode::PARTIAL_CONTENT;
+            let data = response.bytes().await.map_err(std::io::Error::other)?;
+            // Storages ignoring `Range` send the whole file
+            Ok(match partial {
+                true => data.to_vec(),
+                false => data
+                    .get(start as usize..(end as usize).min(data.len()))
+                    .unwrap_or_default()
+                    .to_vec(),
+            })
+        })
+    }
+}
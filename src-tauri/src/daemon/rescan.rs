@@ -0,0 +1,116 @@
+//! Periodic scans of a library, `scan.interval` after the last one, looking for the files added,
+//! changed or removed since. The ones due during `scan.quiet_hours` wait for their end.
+
+use crate::daemon::config::SharedConfig;
+use crate::daemon::libraries::Library;
+use chrono::{DateTime, Local, NaiveTime};
+use mu_protocol::api::Job;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+use tokio::sync::oneshot;
+use tracing::warn;
+
+/// Longest sleep of the scheduler, for a change of the configuration or of the clock, or a
+/// suspend, to be picked up
+const MAX_WAIT: Duration = Duration::from_secs(60);
+
+/// `6h`, `90m` or `1h30m`: amounts of seconds (`s`), minutes (`m`), hours (`h`) or days (`d`)
+fn parse_interval(interval: &str) -> Option<Duration> {
+    let mut seconds = 0;
+    let mut amount = String::new();
+    for c in interval.trim().chars() {
+        if c.is_ascii_digit() {
+            amount.push(c);
+            continue;
+        }
+        let unit = match c {
+            's' => 1,
+            'm' => 60,
+            'h' => 3600,
+            'd' => 86400,
+            _ => return None,
+        };
+        seconds = amount
+            .parse::<u64>()
+            .ok()?
+            .checked_mul(unit)
+            .and_then(|x| x.checked_add(seconds))?;
+        amount.clear();
+    }
+    (amount.is_empty() && seconds > 0).then(|| Duration::from_secs(seconds))
+}
+
+/// `23:00-07:00` as its start and end
+fn parse_quiet_hours(hours: &str) -> Option<(NaiveTime, NaiveTime)> {
+    let (start, end) = hours.split_once('-')?;
+    let time = |x: &str| NaiveTime::parse_from_str(x.trim(), "%H:%M").ok();
+    Some((time(start)?, time(end)?))
+}
+
+/// How long from `now` until the end of the quiet hours, `None` outside of them
+fn quiet_for((start, end): (NaiveTime, NaiveTime), now: DateTime<Local>) -> Option<Duration> {
+    let time = now.time();
+    let quiet = match start <= end {
+        true => start <= time && time < end,
+        // Over midnight
+        false => time >= start || time < end,
+    };
+    if !quiet {
+        return None;
+    }
+    let left = (end - time).num_seconds().rem_euclid(86400) as u64;
+    Some(Duration::from_secs(left.max(1)))
+}
+
+/// Queues a scan of `library` with `scan` every `scan.interval` after the last one ended, outside
+/// of `scan.quiet_hours`. The scan running already is waited for rather than doubled.
+pub async fn schedule<F>(library: Arc<Library>, config: SharedConfig, scan: F)
+where
+    F: Fn() -> oneshot::Receiver<Job>,
+{
+    // Warned about once, not on every look
+    let (mut invalid_interval, mut invalid_hours) = (None, None);
+    loop {
+        let (interval, quiet_hours) = {
+            let config = config.read().await;
+            let scan = config.scan.clone().unwrap_or_default();
+            (scan.interval, scan.quiet_hours)
+        };
+        let every = interval.as_deref().and_then(|x| {
+            let every = parse_interval(x);
+            if every.is_none() && invalid_interval.as_deref() != Some(x) {
+                warn!("scan.interval: invalid interval `{x}`, expected e.g. 6h or 1h30m");
+                invalid_interval = Some(x.to_string());
+            }
+            every
+        });
+        let quiet = quiet_hours.as_deref().and_then(|x| {
+            let quiet = parse_quiet_hours(x);
+            if quiet.is_none() && invalid_hours.as_deref() != Some(x) {
+                warn!("scan.quiet_hours: invalid hours `{x}`, expected e.g. 23:00-07:00");
+                invalid_hours = Some(x.to_string());
+            }
+            quiet
+        });
+        let Some(every) = every else {
+            tokio::time::sleep(MAX_WAIT).await;
+            continue;
+        };
+
+        // The scan on start is still going without a last one
+        let now = SystemTime::now();
+        let last = library.scan.last().map_or(now, |x| x.finished_at);
+        let due = last.checked_add(every).unwrap_or(now + MAX_WAIT);
+        if let Ok(wait) = due.duration_since(now) {
+            tokio::time::sleep(wait.min(MAX_WAIT)).await;
+            continue;
+        }
+        if let Some(wait) = quiet.and_then(|x| quiet_for(x, Local::now())) {
+            tokio::time::sleep(wait.min(MAX_WAIT)).await;
+            continue;
+        }
+
+        // One scan at a time
+        let _ = scan().await;
+    }
+}
@@ -0,0 +1,200 @@
+//! State of the scans of a library, reported by `GET /scan/status`, `/scan/last` and `/readyz`.
+
+use crate::daemon::global::Media;
+use mu_protocol::api::{LastScan, Reconciliation, ScanError, ScanProgress, ScanStatus};
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Instant, SystemTime};
+use tokio::sync::Notify;
+
+/// Files listed with the reason they couldn't be read by a scan, the others are only counted
+const MAX_FAILED: usize = 100;
+
+/// Progress of a scan, updated by `utils::cache_resolve`
+#[derive(Debug)]
+pub struct Progress {
+    started: Instant,
+    done: AtomicUsize,
+    total: AtomicUsize,
+    errors: AtomicUsize,
+    failed: Mutex<Vec<ScanError>>,
+    reconciliation: Mutex<Option<Reconciliation>>,
+    fingerprint: Mutex<Option<String>>,
+    /// Whether the media read so far is wanted, see [`Progress::stream`]
+    streaming: AtomicBool,
+    /// Media read so far, not taken yet
+    partial: Mutex<Option<Media>>,
+    streamed: Notify,
+}
+
+impl Default for Progress {
+    fn default() -> Self {
+        Self {
+            started: Instant::now(),
+            done: AtomicUsize::new(0),
+            total: AtomicUsize::new(0),
+            errors: AtomicUsize::new(0),
+            failed: Mutex::new(vec![]),
+            reconciliation: Mutex::new(None),
+            fingerprint: Mutex::new(None),
+            streaming: AtomicBool::new(false),
+            partial: Mutex::new(None),
+            streamed: Notify::new(),
+        }
+    }
+}
+
+impl Progress {
+    /// Adds `files` to the files to read
+    pub fn expect(&self, files: usize) {
+        self.total.fetch_add(files, Ordering::Relaxed);
+    }
+
+    pub fn advance(&self) {
+        self.done.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Counts the file `path` that could not be read because of `error`
+    pub fn error(&self, path: &Path, error: String) {
+        self.errors.fetch_add(1, Ordering::Relaxed);
+        let mut failed = self.failed.lock().unwrap();
+        if failed.len() < MAX_FAILED {
+            failed.push(ScanError {
+                path: path.display().to_string(),
+                error,
+            });
+        }
+    }
+
+    /// Records what the scan found against the cache and the fingerprint of the files, once
+    /// gone through
+    pub fn reconciled(&self, reconciliation: Reconciliation, fingerprint: String) {
+        *self.reconciliation.lock().unwrap() = Some(reconciliation);
+        *self.fingerprint.lock().unwrap() = Some(fingerprint);
+    }
+
+    /// Asks the scan for the media it read so far, taken with [`Progress::partial`] until
+    /// [`Progress::end_stream`]
+    pub fn stream(&self) {
+        self.streaming.store(true, Ordering::SeqCst);
+    }
+
+    pub fn end_stream(&self) {
+        self.streaming.store(false, Ordering::SeqCst);
+        self.partial.lock().unwrap().take();
+        self.streamed.notify_one();
+    }
+
+    /// Whether the media read so far is wanted
+    pub fn streaming(&self) -> bool {
+        self.streaming.load(Ordering::SeqCst)
+    }
+
+    /// Hands `media`, read so far, over to [`Progress::partial`], replacing the one not taken
+    /// yet
+    pub fn read_so_far(&self, media: &Media) {
+        if self.streaming() {
+            *self.partial.lock().unwrap() = Some(media.clone());
+            self.streamed.notify_one();
+        }
+    }
+
+    /// Waits for the next media read so far, `None` once the stream ended
+    pub async fn partial(&self) -> Option<Media> {
+        loop {
+            if let Some(media) = self.partial.lock().unwrap().take() {
+                return Some(media);
+            }
+            if !self.streaming() {
+                return None;
+            }
+            self.streamed.notified().await;
+        }
+    }
+
+    pub fn snapshot(&self) -> ScanProgress {
+        ScanProgress {
+            done: self.done.load(Ordering::Relaxed),
+            total: self.total.load(Ordering::Relaxed),
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct ScanState {
+    running: Mutex<Option<Arc<Progress>>>,
+    last: Mutex<Option<LastScan>>,
+}
+
+/// A running scan, recorded as the last one once dropped
+pub struct Scan<'a> {
+    state: &'a ScanState,
+    pub progress: Arc<Progress>,
+    completed: bool,
+}
+
+impl Scan<'_> {
+    /// Marks the scan as gone through, it counts as cancelled otherwise
+    pub fn complete(mut self) {
+        self.completed = true;
+    }
+}
+
+impl Drop for Scan<'_> {
+    fn drop(&mut self) {
+        let mut running = self.state.running.lock().unwrap();
+        // Unless another scan started since
+        if running
+            .as_ref()
+            .is_some_and(|x| Arc::ptr_eq(x, &self.progress))
+        {
+            *running = None;
+        }
+
+        *self.state.last.lock().unwrap() = Some(LastScan {
+            finished_at: SystemTime::now(),
+            duration: self.progress.started.elapsed().as_millis() as u64,
+            errors: self.progress.errors.load(Ordering::Relaxed),
+            failed: self.progress.failed.lock().unwrap().clone(),
+            completed: self.completed,
+            reconciliation: *self.progress.reconciliation.lock().unwrap(),
+            fingerprint: self.progress.fingerprint.lock().unwrap().clone(),
+        });
+    }
+}
+
+impl ScanState {
+    pub fn begin(&self) -> Scan<'_> {
+        let progress = Arc::new(Progress::default());
+        *self.running.lock().unwrap() = Some(Arc::clone(&progress));
+
+        Scan {
+            state: self,
+            progress,
+            completed: false,
+        }
+    }
+
+    pub fn status(&self) -> ScanStatus {
+        let progress = self.running.lock().unwrap().as_ref().map(|x| x.snapshot());
+        ScanStatus {
+            running: progress.is_some(),
+            progress,
+            last: self.last(),
+        }
+    }
+
+    pub fn last(&self) -> Option<LastScan> {
+        self.last.lock().unwrap().clone()
+    }
+
+    /// Whether a scan of the library went through
+    pub fn scanned(&self) -> bool {
+        self.last
+            .lock()
+            .unwrap()
+            .as_ref()
+            .is_some_and(|x| x.completed)
+    }
+}
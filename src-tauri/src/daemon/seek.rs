@@ -0,0 +1,346 @@
+use mu_protocol::api::SeekTable;
+use mu_protocol::library::Track;
+use std::fs::File;
+use std::io::{BufReader, Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+use tracing::warn;
+
+pub const DEFAULT_INTERVAL: u64 = 5;
+
+/// A seek table saved along the state of the file it was built from
+#[derive(serde::Serialize, serde::Deserialize, Debug)]
+struct CachedSeekTable {
+    size: u64,
+    modified: SystemTime,
+    table: SeekTable,
+}
+
+/// Start of the audio after an id3v2 tag, if any
+pub fn skip_id3v2<R: Read + Seek>(reader: &mut R) -> std::io::Result<u64> {
+    let mut header = [0u8; 10];
+    reader.seek(SeekFrom::Start(0))?;
+    if reader.read_exact(&mut header).is_err() || &header[0..3] != b"ID3" {
+        return Ok(0);
+    }
+
+    let size = header[6..10]
+        .iter()
+        .fold(0u64, |acc, b| (acc << 7) | (*b & 0x7f) as u64);
+    let footer = if header[5] & 0x10 != 0 { 10 } else { 0 };
+    Ok(10 + size + footer)
+}
+
+/// Start of the audio of the file at `path`, after what tag editors rewrite before it: an
+/// id3v2 tag, the metadata blocks of a FLAC file, the chunks before the `data` of a wave
+/// file, the atoms before the `mdat` of an MP4 file and the header pages of an Ogg stream.
+/// The start of the file, or of what follows the id3v2 tag, for the other formats.
+pub fn audio_start(path: &Path) -> std::io::Result<u64> {
+    let mut reader = BufReader::new(File::open(path)?);
+    let start = skip_id3v2(&mut reader)?;
+    reader.seek(SeekFrom::Start(start))?;
+    let mut magic = [0u8; 8];
+    if reader.read_exact(&mut magic).is_err() {
+        return Ok(start);
+    }
+
+    let audio = match (&magic[0..4], &magic[4..8]) {
+        (b"fLaC", _) => flac_frames(&mut reader, start + 4)?,
+        (b"RIFF", _) => wav_data(&mut reader, start)?,
+        (b"OggS", _) => ogg_audio(&mut reader, start)?,
+        (_, b"ftyp") => mp4_mdat(&mut reader, start)?,
+        _ => None,
+    };
+    Ok(audio.unwrap_or(start))
+}
+
+/// Start of the frames of a FLAC stream whose metadata blocks start at `blocks`
+fn flac_frames<R: Read + Seek>(reader: &mut R, blocks: u64) -> std::io::Result<Option<u64>> {
+    reader.seek(SeekFrom::Start(blocks))?;
+    let mut header = [0u8; 4];
+    loop {
+        if reader.read_exact(&mut header).is_err() {
+            return Ok(None);
+        }
+        let length = u32::from_be_bytes([0, header[1], header[2], header[3]]);
+        reader.seek_relative(length as i64)?;
+        // Last metadata block
+        if header[0] & 0x80 != 0 {
+            return reader.stream_position().map(Some);
+        }
+    }
+}
+
+/// Start of the samples of the wave file starting at `riff`
+fn wav_data<R: Read + Seek>(reader: &mut R, riff: u64) -> std::io::Result<Option<u64>> {
+    reader.seek(SeekFrom::Start(riff))?;
+    let mut header = [0u8; 12];
+    reader.read_exact(&mut header)?;
+    if &header[8..12] != b"WAVE" {
+        return Ok(None);
+    }
+
+    let mut chunk = [0u8; 8];
+    while reader.read_exact(&mut chunk).is_ok() {
+        if &chunk[0..4] == b"data" {
+            return reader.stream_position().map(Some);
+        }
+        let size = u32::from_le_bytes(chunk[4..8].try_into().unwrap()) as u64;
+        reader.seek_relative((size + size % 2) as i64)?;
+    }
+    Ok(None)
+}
+
+/// Start of the first Ogg page holding audio, the header packets ending on pages of granule
+/// position 0, or -1 for a page ending no packet
+fn ogg_audio<R: Read + Seek>(reader: &mut R, first: u64) -> std::io::Result<Option<u64>> {
+    let mut page = first;
+    let mut header = [0u8; 27];
+    loop {
+        reader.seek(SeekFrom::Start(page))?;
+        if reader.read_exact(&mut header).is_err() || &header[0..4] != b"OggS" {
+            return Ok(None);
+        }
+        let granule = u64::from_le_bytes(header[6..14].try_into().unwrap());
+        if granule != 0 && granule != u64::MAX {
+            return Ok(Some(page));
+        }
+        let mut segments = vec![0u8; header[26] as usize];
+        reader.read_exact(&mut segments)?;
+        let body: u64 = segments.iter().map(|x| *x as u64).sum();
+        page += 27 + segments.len() as u64 + body;
+    }
+}
+
+/// Start of the `mdat` atom, holding the samples, of the MP4 file starting at `first`
+fn mp4_mdat<R: Read + Seek>(reader: &mut R, first: u64) -> std::io::Result<Option<u64>> {
+    let mut atom = first;
+    let mut header = [0u8; 8];
+    loop {
+        reader.seek(SeekFrom::Start(atom))?;
+        if reader.read_exact(&mut header).is_err() {
+            return Ok(None);
+        }
+        let mut size = u32::from_be_bytes(header[0..4].try_into().unwrap()) as u64;
+        let mut start = atom + 8;
+        if size == 1 {
+            let mut large = [0u8; 8];
+            reader.read_exact(&mut large)?;
+            size = u64::from_be_bytes(large);
+            start += 8;
+        }
+        if &header[4..8] == b"mdat" {
+            return Ok(Some(start));
+        }
+        // Atoms running to the end of the file, or broken
+        if size < start - atom {
+            return Ok(None);
+        }
+        atom += size;
+    }
+}
+
+/// Length in bytes, number of samples and sample rate of the mpeg audio frame starting with
+/// `header`
+pub fn mpeg_frame(header: [u8; 4]) -> Option<(u64, u64, u64)> {
+    if header[0] != 0xff || header[1] & 0xe0 != 0xe0 {
+        return None;
+    }
+
+    // 0: MPEG 2.5, 2: MPEG 2, 3: MPEG 1
+    let version = (header[1] >> 3) & 0x3;
+    // 1: Layer III, 2: Layer II, 3: Layer I
+    let layer = (header[1] >> 1) & 0x3;
+    let bitrate_index = (header[2] >> 4) as usize;
+    let sample_rate_index = ((header[2] >> 2) & 0x3) as usize;
+    let padding = ((header[2] >> 1) & 0x1) as u64;
+    if version == 1 || layer == 0 || bitrate_index == 0 || bitrate_index == 15 {
+        return None;
+    }
+
+    const BITRATES_V1: [[u64; 15]; 3] = [
+        [
+            0, 32, 40, 48, 56, 64, 80, 96, 112, 128, 160, 192, 224, 256, 320,
+        ],
+        [
+            0, 32, 48, 56, 64, 80, 96, 112, 128, 160, 192, 224, 256, 320, 384,
+        ],
+        [
+            0, 32, 64, 96, 128, 160, 192, 224, 256, 288, 320, 352, 384, 416, 448,
+        ],
+    ];
+    const BITRATES_V2: [[u64; 15]; 3] = [
+        [0, 8, 16, 24, 32, 40, 48, 56, 64, 80, 96, 112, 128, 144, 160],
+        [0, 8, 16, 24, 32, 40, 48, 56, 64, 80, 96, 112, 128, 144, 160],
+        [
+            0, 32, 48, 56, 64, 80, 96, 112, 128, 144, 160, 176, 192, 224, 256,
+        ],
+    ];
+    const SAMPLE_RATES: [u64; 3] = [44100, 48000, 32000];
+
+    let sample_rate = SAMPLE_RATES.get(sample_rate_index)?
+        >> match version {
+            3 => 0,
+            2 => 1,
+            _ => 2,
+        };
+    let bitrate = match version {
+        3 => BITRATES_V1[layer as usize - 1][bitrate_index],
+        _ => BITRATES_V2[layer as usize - 1][bitrate_index],
+    } * 1000;
+
+    let (length, samples) = match (layer, version) {
+        (3, _) => ((12 * bitrate / sample_rate + padding) * 4, 384),
+        (2, _) | (1, 3) => (144 * bitrate / sample_rate + padding, 1152),
+        _ => (72 * bitrate / sample_rate + padding, 576),
+    };
+
+    Some((length, samples, sample_rate))
+}
+
+/// Walks the frames of an mpeg audio file, keeping the offset of the frame playing at every
+/// `interval` seconds
+fn mpeg_offsets(path: &Path, interval: u64) -> std::io::Result<Vec<u64>> {
+    let mut reader = BufReader::new(File::open(path)?);
+    let mut offset = skip_id3v2(&mut reader)?;
+    reader.seek(SeekFrom::Start(offset))?;
+
+    let mut offsets = vec![];
+    // Position in seconds, kept as a fraction to not accumulate rounding errors
+    let (mut samples, mut sample_rate) = (0u64, 1u64);
+    let mut header = [0u8; 4];
+    while reader.read_exact(&mut header).is_ok() {
+        let Some((length, frame_samples, rate)) = mpeg_frame(header) else {
+            // Not on a frame boundary, look for the next sync word
+            offset += 1;
+            reader.seek_relative(-3)?;
+            continue;
+        };
+
+        if rate != sample_rate {
+            samples = samples * rate / sample_rate;
+            sample_rate = rate;
+        }
+        while (offsets.len() as u64)
+            .saturating_mul(interval)
+            .saturating_mul(sample_rate)
+            < samples + frame_samples
+        {
+            offsets.push(offset);
+        }
+
+        samples += frame_samples;
+        offset += length;
+        reader.seek_relative(length as i64 - 4)?;
+    }
+
+    Ok(offsets)
+}
+
+/// Wave files are constant bitrate: offsets are computed from the `fmt ` and `data` chunks
+fn wav_offsets(path: &Path, interval: u64, duration: u64) -> std::io::Result<Vec<u64>> {
+    let mut reader = BufReader::new(File::open(path)?);
+    let mut riff = [0u8; 12];
+    reader.read_exact(&mut riff)?;
+    if &riff[0..4] != b"RIFF" || &riff[8..12] != b"WAVE" {
+        return Ok(vec![]);
+    }
+
+    let mut byte_rate = None;
+    let mut block_align = 1;
+    let mut chunk = [0u8; 8];
+    while reader.read_exact(&mut chunk).is_ok() {
+        let size = u32::from_le_bytes(chunk[4..8].try_into().unwrap()) as u64;
+        match &chunk[0..4] {
+            b"fmt " => {
+                let mut fmt = [0u8; 16];
+                reader.read_exact(&mut fmt)?;
+                byte_rate = Some(u32::from_le_bytes(fmt[8..12].try_into().unwrap()) as u64);
+                block_align = u16::from_le_bytes(fmt[12..14].try_into().unwrap()).max(1) as u64;
+                reader.seek_relative((size + size % 2) as i64 - 16)?;
+            }
+            b"data" => {
+                let Some(byte_rate) = byte_rate else {
+                    break;
+                };
+                let start = reader.stream_position()?;
+                return Ok((0..=duration / interval)
+                    .map(|n| n.saturating_mul(interval).saturating_mul(byte_rate))
+                    .take_while(|x| *x < size)
+                    .map(|x| start + x - x % block_align)
+                    .collect());
+            }
+            _ => reader.seek_relative((size + size % 2) as i64)?,
+        }
+    }
+
+    Ok(vec![])
+}
+
+fn build(track: &Track, interval: u64, size: u64) -> SeekTable {
+    let path = Path::new(&track.file_path);
+    let offsets = match track.mime.as_str() {
+        "audio/mpeg" => mpeg_offsets(path, interval),
+        "audio/wav" => wav_offsets(path, interval, track.duration),
+        _ => Ok(vec![]),
+    };
+
+    match offsets {
+        Ok(offsets) if !offsets.is_empty() => SeekTable {
+            interval,
+            exact: true,
+            offsets,
+        },
+        result => {
+            if let Err(e) = result {
+                warn!("Unable to read the frames of `{}`: {e}", track.file_path);
+            }
+            // Spread evenly over the file, good enough for constant bitrate streams
+            let duration = track.duration.max(1);
+            SeekTable {
+                interval,
+                exact: false,
+                offsets: (0..=track.duration / interval)
+                    .map(|n| size * (n * interval).min(duration) / duration)
+                    .collect(),
+            }
+        }
+    }
+}
+
+fn cache_path(cache_dir: &Path, track: &Track, interval: u64) -> PathBuf {
+    cache_dir.join("seek").join(format!(
+        "{:x}-{interval}.json",
+        md5::compute(&track.file_path)
+    ))
+}
+
+/// The seek table of `track`, built once and cached until the file changes
+pub fn seek_table(cache_dir: &Path, track: &Track, interval: u64) -> std::io::Result<SeekTable> {
+    let meta = std::fs::metadata(&track.file_path)?;
+    let (size, modified) = (meta.len(), meta.modified()?);
+
+    let path = cache_path(cache_dir, track, interval);
+    if let Ok(data) = std::fs::read_to_string(&path) {
+        if let Ok(cached) = serde_json::from_str::<CachedSeekTable>(&data) {
+            if cached.size == size && cached.modified == modified {
+                return Ok(cached.table);
+            }
+        }
+    }
+
+    let table = build(track, interval, size);
+    let cached = CachedSeekTable {
+        size,
+        modified,
+        table,
+    };
+    if let Some(dir) = path.parent() {
+        std::fs::DirBuilder::new().recursive(true).create(dir)?;
+    }
+    if let Err(e) = std::fs::write(&path, serde_json::to_string(&cached).unwrap()) {
+        warn!("Unable to cache the seek table `{}`: {e}", path.display());
+    }
+
+    Ok(cached.table)
+}
@@ -0,0 +1,329 @@
+//! Shared listening sessions, or party mode: a host creates one with `POST /sessions` and
+//! reports what it plays, the listeners join its socket.io room with the `session:join` message
+//! to hear the same thing. Anyone may suggest tracks and vote for them, one vote per device, the
+//! host moving on to the most voted with `POST /sessions/{id}/next`. Sessions only live in
+//! memory.
+
+use crate::daemon::changes;
+use crate::daemon::events;
+use axum::http::{HeaderMap, HeaderName};
+use mu_protocol::api::{HostedSession, Session, SessionEntry, SessionSync};
+use mu_protocol::events::Event;
+use mu_protocol::library::Track;
+use socketioxide::extract::{Data, SocketRef, State};
+use socketioxide::SocketIo;
+use std::cmp::Reverse;
+use std::collections::{HashMap, HashSet};
+use std::net::IpAddr;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime};
+use tracing::{info, warn};
+
+/// Header of the requests of the host, with the token given by `POST /sessions`
+pub const HOST_HEADER: HeaderName = HeaderName::from_static("x-session-token");
+
+const DEFAULT_NAME: &str = "Party";
+
+/// Interval at which playing sessions send their position to their room
+const SYNC_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Sessions without any request for this long are ended
+const IDLE_TIMEOUT: Duration = Duration::from_secs(3 * 60 * 60);
+
+#[derive(Debug)]
+struct Suggestion {
+    track: Track,
+    voters: HashSet<IpAddr>,
+}
+
+#[derive(Debug)]
+struct PartySession {
+    name: String,
+    /// MD5 of the token of the host
+    host_hash: String,
+    current: Option<Track>,
+    /// In milliseconds, at `updated_at`
+    position: u64,
+    playing: bool,
+    updated_at: SystemTime,
+    /// In the order they were suggested, sorted by votes when listed
+    queue: Vec<Suggestion>,
+    last_activity: SystemTime,
+}
+
+impl PartySession {
+    /// Position of the host, counting the time since it reported it while playing
+    fn position(&self) -> u64 {
+        match &self.current {
+            Some(track) if self.playing => {
+                let elapsed = SystemTime::now()
+                    .duration_since(self.updated_at)
+                    .unwrap_or_default()
+                    .as_millis() as u64;
+                (self.position + elapsed).min(track.duration * 1000)
+            }
+            Some(_) => self.position,
+            None => 0,
+        }
+    }
+
+    fn to_session(&self, id: &str) -> Session {
+        let mut queue: Vec<SessionEntry> = self
+            .queue
+            .iter()
+            .map(|x| SessionEntry {
+                track: x.track.clone(),
+                votes: x.voters.len(),
+            })
+            .collect();
+        // Stable: ties keep the order of the suggestions
+        queue.sort_by_key(|x| Reverse(x.votes));
+
+        Session {
+            id: id.to_string(),
+            name: self.name.clone(),
+            current: self.current.clone(),
+            position: self.position(),
+            playing: self.playing && self.current.is_some(),
+            queue,
+            updated_at: self.updated_at,
+        }
+    }
+
+    fn touch(&mut self) {
+        self.last_activity = SystemTime::now();
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct Sessions {
+    sessions: Mutex<HashMap<String, PartySession>>,
+}
+
+fn hash(token: &str) -> String {
+    format!("{:x}", md5::compute(token))
+}
+
+/// Socket.io room of the session `id`
+pub fn room(id: &str) -> String {
+    format!("session:{id}")
+}
+
+impl Sessions {
+    pub fn create(&self, name: Option<String>) -> HostedSession {
+        let id = uuid::Uuid::new_v4().simple().to_string();
+        let host_token = uuid::Uuid::new_v4().simple().to_string();
+        let name = name
+            .map(|x| x.trim().to_string())
+            .filter(|x| !x.is_empty())
+            .unwrap_or(DEFAULT_NAME.to_string());
+        let now = SystemTime::now();
+        let party = PartySession {
+            name,
+            host_hash: hash(&host_token),
+            current: None,
+            position: 0,
+            playing: false,
+            updated_at: now,
+            queue: vec![],
+            last_activity: now,
+        };
+        let session = party.to_session(&id);
+        self.sessions.lock().unwrap().insert(id.clone(), party);
+        info!("session {id} started");
+
+        HostedSession {
+            session,
+            host_token,
+        }
+    }
+
+    pub fn get(&self, id: &str) -> Option<Session> {
+        self.sessions
+            .lock()
+            .unwrap()
+            .get(id)
+            .map(|x| x.to_session(id))
+    }
+
+    pub fn list(&self) -> Vec<Session> {
+        let mut sessions: Vec<Session> = self
+            .sessions
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(id, x)| x.to_session(id))
+            .collect();
+        sessions.sort_by_key(|x| x.name.to_lowercase());
+        sessions
+    }
+
+    /// Whether `headers` carry the token of the host of the session `id`, `None` if there is
+    /// no such session
+    pub fn is_host(&self, id: &str, headers: &HeaderMap) -> Option<bool> {
+        let sessions = self.sessions.lock().unwrap();
+        let party = sessions.get(id)?;
+        let token = headers.get(HOST_HEADER).and_then(|x| x.to_str().ok());
+
+        Some(token.is_some_and(|x| hash(x) == party.host_hash))
+    }
+
+    pub fn end(&self, id: &str) -> bool {
+        let ended = self.sessions.lock().unwrap().remove(id).is_some();
+        if ended {
+            info!("session {id} ended");
+        }
+
+        ended
+    }
+
+    /// Adds the vote of `voter` for `track`, suggesting it if it isn't queued yet
+    pub fn vote(&self, id: &str, track: Track, voter: IpAddr) -> Option<Session> {
+        let mut sessions = self.sessions.lock().unwrap();
+        let party = sessions.get_mut(id)?;
+        match party.queue.iter_mut().find(|x| x.track.id == track.id) {
+            Some(suggestion) => {
+                suggestion.voters.insert(voter);
+            }
+            None => party.queue.push(Suggestion {
+                track,
+                voters: HashSet::from([voter]),
+            }),
+        }
+        party.touch();
+
+        Some(party.to_session(id))
+    }
+
+    /// Withdraws the vote of `voter` for the track `track_id`, which leaves the queue without
+    /// votes
+    pub fn unvote(&self, id: &str, track_id: &str, voter: IpAddr) -> Option<Session> {
+        let mut sessions = self.sessions.lock().unwrap();
+        let party = sessions.get_mut(id)?;
+        for suggestion in party.queue.iter_mut().filter(|x| x.track.id == track_id) {
+            suggestion.voters.remove(&voter);
+        }
+        party.queue.retain(|x| !x.voters.is_empty());
+        party.touch();
+
+        Some(party.to_session(id))
+    }
+
+    /// Sets what the host plays, taking the track out of the queue
+    pub fn play(
+        &self,
+        id: &str,
+        track: Option<Track>,
+        position: u64,
+        playing: bool,
+    ) -> Option<Session> {
+        let mut sessions = self.sessions.lock().unwrap();
+        let party = sessions.get_mut(id)?;
+        if let Some(track) = &track {
+            party.queue.retain(|x| x.track.id != track.id);
+        }
+        party.position = match &track {
+            Some(track) => position.min(track.duration * 1000),
+            None => 0,
+        };
+        party.playing = playing && track.is_some();
+        party.current = track;
+        party.updated_at = SystemTime::now();
+        party.touch();
+
+        Some(party.to_session(id))
+    }
+
+    /// Plays the most voted track from its start, nothing once the queue is empty
+    pub fn next(&self, id: &str) -> Option<Session> {
+        let mut sessions = self.sessions.lock().unwrap();
+        let party = sessions.get_mut(id)?;
+        // The first of the most voted, as listed
+        let mut best: Option<usize> = None;
+        for (i, suggestion) in party.queue.iter().enumerate() {
+            if best.is_none_or(|b| suggestion.voters.len() > party.queue[b].voters.len()) {
+                best = Some(i);
+            }
+        }
+        party.current = best.map(|i| party.queue.remove(i).track);
+        party.position = 0;
+        party.playing = party.current.is_some();
+        party.updated_at = SystemTime::now();
+        party.touch();
+
+        Some(party.to_session(id))
+    }
+
+    /// Positions of the playing sessions
+    fn syncs(&self) -> Vec<SessionSync> {
+        self.sessions
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|(_, x)| x.playing && x.current.is_some())
+            .map(|(id, x)| SessionSync {
+                id: id.clone(),
+                track: x.current.as_ref().map(|x| x.id.clone()),
+                position: x.position(),
+                playing: x.playing,
+            })
+            .collect()
+    }
+
+    /// Ends the sessions idle for `IDLE_TIMEOUT`, returning their ids
+    fn reap_idle(&self) -> Vec<String> {
+        let mut sessions = self.sessions.lock().unwrap();
+        let idle: Vec<String> = sessions
+            .iter()
+            .filter(|(_, x)| {
+                x.last_activity
+                    .elapsed()
+                    .is_ok_and(|elapsed| elapsed >= IDLE_TIMEOUT)
+            })
+            .map(|(id, _)| id.clone())
+            .collect();
+        for id in &idle {
+            sessions.remove(id);
+            info!("session {id} ended after being idle");
+        }
+
+        idle
+    }
+}
+
+/// Sends the position of the playing sessions to their room and ends the idle ones
+pub async fn sync(sessions: Arc<Sessions>, io: SocketIo) {
+    loop {
+        tokio::time::sleep(SYNC_INTERVAL).await;
+        for sync in sessions.syncs() {
+            events::emit_to(&io, room(&sync.id), Event::SessionSync, &sync);
+        }
+        for id in sessions.reap_idle() {
+            events::emit_to(&io, room(&id), Event::SessionEnded, &id);
+        }
+    }
+}
+
+/// Handles the `session:join` and `session:leave` messages of a socket, carrying the id of a
+/// session. Joining sends the session to the socket as `session:updated`.
+pub fn listen(socket: &SocketRef) {
+    socket.on(
+        "session:join",
+        |socket: SocketRef, Data::<String>(id), sessions: State<Arc<Sessions>>| {
+            let Some(session) = sessions.get(&id) else {
+                return;
+            };
+            if let Err(e) = socket.join(room(&id)) {
+                warn!("Unable to join the session {id}: {e}");
+                return;
+            }
+            let _ = socket.emit(
+                Event::SessionUpdated.name(),
+                (&session, changes::generation(), events::sequence()),
+            );
+        },
+    );
+    socket.on("session:leave", |socket: SocketRef, Data::<String>(id)| {
+        let _ = socket.leave(room(&id));
+    });
+}
@@ -0,0 +1,72 @@
+use crate::daemon::proxy;
+use crate::daemon::users;
+use axum::http::HeaderMap;
+use lorconf::Config;
+use std::net::IpAddr;
+use std::time::Duration;
+use tokio_util::sync::CancellationToken;
+use tracing::info;
+
+/// How long in-flight requests (mostly audio streams) are given to end once shutting down
+pub const GRACE_PERIOD: Duration = Duration::from_secs(10);
+
+/// Cancels `token` on SIGINT or SIGTERM
+pub async fn watch_signals(token: CancellationToken) {
+    let ctrl_c = async {
+        let _ = tokio::signal::ctrl_c().await;
+    };
+
+    #[cfg(unix)]
+    let terminate = async {
+        match tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate()) {
+            Ok(mut signal) => {
+                signal.recv().await;
+            }
+            Err(_) => std::future::pending::<()>().await,
+        }
+    };
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    tokio::select! {
+        _ = ctrl_c => info!("received SIGINT"),
+        _ = terminate => info!("received SIGTERM"),
+        _ = token.cancelled() => return,
+    }
+
+    token.cancel();
+}
+
+/// Remote management requests must carry `Authorization: Bearer <network.admin_token>`.
+/// Without a configured token, only requests coming from the machine itself are accepted.
+/// Behind a reverse proxy every request comes from it, so a token is needed there: when
+/// `network.base_path` is set or the request has forwarding headers, see [`proxy`].
+pub fn is_authorized(config: &Config, peer: IpAddr, headers: &HeaderMap) -> bool {
+    let token = config
+        .network
+        .as_ref()
+        .and_then(|network| network.admin_token.as_deref());
+
+    match token {
+        Some(token) => users::bearer(headers).is_some_and(|x| same_token(x, token)),
+        None => peer.is_loopback() && proxy::base_path().is_empty() && !proxy::forwarded(headers),
+    }
+}
+
+/// Whether `token` is the `network.admin_token`
+pub fn is_admin_token(config: &Config, token: &str) -> bool {
+    config
+        .network
+        .as_ref()
+        .and_then(|network| network.admin_token.as_deref())
+        .is_some_and(|x| same_token(x, token))
+}
+
+/// Compares two tokens in a time depending on their length only, not on where they differ
+pub(crate) fn same_token(a: &str, b: &str) -> bool {
+    a.len() == b.len()
+        && a.bytes()
+            .zip(b.bytes())
+            .fold(0, |acc, (x, y)| acc | (x ^ y))
+            == 0
+}
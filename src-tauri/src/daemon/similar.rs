@@ -0,0 +1,144 @@
+//! Tracks like others, for "play something like this" and the radio. Tracks are scored on their
+//! metadata against a [`Profile`]: the genre, the artists and credits they share, how far apart
+//! their years are and how close their durations are. The duration only breaks ties between
+//! tracks sharing something else.
+
+use crate::daemon::global::Media;
+use mu_protocol::api::QualityQuery;
+use mu_protocol::library::Track;
+use std::collections::HashSet;
+
+const GENRE: f32 = 3.0;
+const ARTISTS: f32 = 3.0;
+const CREDITS: f32 = 1.0;
+const YEAR: f32 = 1.5;
+const DURATION: f32 = 0.5;
+/// Years apart past which the year doesn't count
+const YEARS: f32 = 10.0;
+
+/// Year of `track`, of its album or else of its release date
+fn year(track: &Track) -> Option<u32> {
+    track.album_year.or_else(|| {
+        track
+            .release_date
+            .as_deref()
+            .and_then(|x| x.get(..4))
+            .and_then(|x| x.parse().ok())
+    })
+}
+
+fn artists(track: &Track) -> impl Iterator<Item = String> + '_ {
+    track
+        .artists
+        .iter()
+        .chain(&track.album_artists)
+        .map(|x| x.to_lowercase())
+}
+
+/// Part of `names` found in `known`
+fn share(names: HashSet<String>, known: &HashSet<String>) -> f32 {
+    if names.is_empty() {
+        return 0.0;
+    }
+    names.iter().filter(|x| known.contains(*x)).count() as f32 / names.len() as f32
+}
+
+/// What the tracks are compared with: a track, the tracks of an album, or a genre
+#[derive(Debug, Default, Clone)]
+pub struct Profile {
+    genres: HashSet<String>,
+    artists: HashSet<String>,
+    credits: HashSet<String>,
+    year: Option<f32>,
+    /// In seconds
+    duration: Option<f32>,
+    /// Ids of the tracks the profile is made of, never suggested
+    tracks: HashSet<String>,
+}
+
+impl Profile {
+    /// The profile of `tracks`, their genres, artists and credits, and their mean year and
+    /// duration
+    pub fn of<'a>(tracks: impl IntoIterator<Item = &'a Track>) -> Self {
+        let mut profile = Profile::default();
+        let (mut years, mut durations) = (vec![], vec![]);
+        for track in tracks {
+            profile.tracks.insert(track.id.clone());
+            if let Some(genre) = &track.genre {
+                profile.genres.insert(genre.to_lowercase());
+            }
+            profile.artists.extend(artists(track));
+            profile
+                .credits
+                .extend(track.credits.iter().map(|x| x.name.to_lowercase()));
+            years.extend(year(track).map(|x| x as f32));
+            if track.duration > 0 {
+                durations.push(track.duration as f32);
+            }
+        }
+        let mean = |x: Vec<f32>| (!x.is_empty()).then(|| x.iter().sum::<f32>() / x.len() as f32);
+        profile.year = mean(years);
+        profile.duration = mean(durations);
+        profile
+    }
+
+    /// The profile of the tracks of `genre`, whatever its case
+    pub fn genre(genre: &str) -> Self {
+        Profile {
+            genres: HashSet::from([genre.to_lowercase()]),
+            ..Default::default()
+        }
+    }
+
+    /// How much `track` is like the profile, 0 when it shares nothing but its duration
+    pub fn score(&self, track: &Track) -> f32 {
+        let mut score = 0.0;
+        if track
+            .genre
+            .as_ref()
+            .is_some_and(|x| self.genres.contains(&x.to_lowercase()))
+        {
+            score += GENRE;
+        }
+        score += ARTISTS * share(artists(track).collect(), &self.artists);
+        let credits = track.credits.iter().map(|x| x.name.to_lowercase());
+        score += CREDITS * share(credits.collect(), &self.credits);
+        if let (Some(a), Some(b)) = (self.year, year(track)) {
+            score += YEAR * (1.0 - (a - b as f32).abs() / YEARS).max(0.0);
+        }
+
+        if score > 0.0 {
+            if let Some(duration) = self.duration.filter(|_| track.duration > 0) {
+                let other = track.duration as f32;
+                score += DURATION * duration.min(other) / duration.max(other);
+            }
+        }
+        score
+    }
+
+    /// Whether `track` is one of the tracks the profile is made of
+    pub fn contains(&self, track: &Track) -> bool {
+        self.tracks.contains(&track.id)
+    }
+}
+
+/// The tracks of `media` like `profile`, the most alike first, without the tracks it is made
+/// of
+pub fn similar<'a>(
+    media: &'a Media,
+    profile: &Profile,
+    quality: &QualityQuery,
+) -> Vec<(f32, &'a Track)> {
+    let mut tracks: Vec<(f32, &Track)> = media
+        .tracks
+        .values()
+        .filter(|x| !profile.contains(x) && quality.keeps(x))
+        .map(|x| (profile.score(x), x))
+        .filter(|(score, _)| *score > 0.0)
+        .collect();
+    tracks.sort_by(|a, b| {
+        b.0.total_cmp(&a.0)
+            .then_with(|| a.1.file_path.cmp(&b.1.file_path))
+    });
+    tracks
+}
@@ -0,0 +1,90 @@
+//! Values read far more often than written, e.g. the [`Media`](crate::daemon::global::Media) of
+//! a library: a reader takes the current snapshot without waiting, even while a scan writes, and
+//! keeps it as long as it needs. A writer works on a copy of the snapshot, taken once it changes
+//! something, which replaces the snapshot when the writer is dropped. Writers wait for each
+//! other, none of their changes is lost.
+
+use arc_swap::ArcSwap;
+use std::fmt;
+use std::ops::{Deref, DerefMut};
+use std::sync::Arc;
+use tokio::sync::{Mutex, MutexGuard};
+
+pub struct Snapshot<T> {
+    current: ArcSwap<T>,
+    writer: Mutex<()>,
+}
+
+impl<T: Clone> Snapshot<T> {
+    pub fn new(value: T) -> Self {
+        Self {
+            current: ArcSwap::from_pointee(value),
+            writer: Mutex::new(()),
+        }
+    }
+
+    /// The current snapshot, left as is by the writers
+    pub fn load(&self) -> Arc<T> {
+        self.current.load_full()
+    }
+
+    /// Waits for the other writers, the readers go on with the current snapshot until the
+    /// returned writer is dropped. The first change copies the whole value, so changes made
+    /// together are better made through a single writer.
+    pub async fn write(&self) -> Writer<'_, T> {
+        let guard = self.writer.lock().await;
+        Writer {
+            snapshot: self,
+            value: self.current.load_full(),
+            changed: false,
+            _guard: guard,
+        }
+    }
+}
+
+impl<T: fmt::Debug> fmt::Debug for Snapshot<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.current.load().fmt(f)
+    }
+}
+
+/// Changes of a [`Snapshot`], seen by the readers once dropped
+pub struct Writer<'a, T: Clone> {
+    snapshot: &'a Snapshot<T>,
+    value: Arc<T>,
+    changed: bool,
+    _guard: MutexGuard<'a, ()>,
+}
+
+impl<T: Clone> Writer<'_, T> {
+    /// Replaces the snapshot right away, returning the new one, e.g. to tell the clients about
+    /// the changes once they can see them
+    pub fn commit(self) -> Arc<T> {
+        let value = Arc::clone(&self.value);
+        drop(self);
+        value
+    }
+}
+
+impl<T: Clone> Deref for Writer<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.value
+    }
+}
+
+impl<T: Clone> DerefMut for Writer<'_, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        self.changed = true;
+        Arc::make_mut(&mut self.value)
+    }
+}
+
+impl<T: Clone> Drop for Writer<'_, T> {
+    fn drop(&mut self) {
+        if self.changed {
+            self.snapshot.current.store(Arc::clone(&self.value));
+        }
+    }
+}
@@ -0,0 +1,15 @@
+use crate::daemon::artists;
+use crate::daemon::global::Media;
+use mu_protocol::api::Stats;
+
+pub fn stats(media: &Media, plays: usize, favorites: usize) -> Stats {
+    Stats {
+        tracks: media.tracks.len(),
+        albums: media.albums.len(),
+        artists: artists::artists(media).len(),
+        playlists: media.playlists.len(),
+        duration: media.tracks.values().map(|x| x.duration).sum(),
+        plays,
+        favorites,
+    }
+}
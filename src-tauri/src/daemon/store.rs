@@ -0,0 +1,84 @@
+//! The state the daemon keeps for its users, apart from `config.toml`: favorites, queue,
+//! history... each in a json file of the data folder, e.g. `favorites.json`. Files are written
+//! to a temporary file first and renamed over the previous one, which is kept as a `.bak`, so
+//! a crash mid-write never leaves a truncated state. A file that can't be read is set aside as
+//! a `.corrupt` and its `.bak` read instead.
+
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use tracing::warn;
+
+/// `path` with `suffix` appended to its file name, e.g. `favorites.json.bak`
+fn sibling(path: &Path, suffix: &str) -> PathBuf {
+    let mut name = path.file_name().unwrap_or_default().to_os_string();
+    name.push(suffix);
+    path.with_file_name(name)
+}
+
+fn read<T: DeserializeOwned>(path: &Path) -> io::Result<T> {
+    let data = fs::read(path)?;
+    Ok(serde_json::from_slice(&data)?)
+}
+
+/// Reads the state at `path`, named `what` in the logs, e.g. "favorites". The default when
+/// neither the file nor its backup can be read.
+pub fn load<T: DeserializeOwned + Default>(path: &Path, what: &str) -> T {
+    let backup = sibling(path, ".bak");
+    let error = match read(path) {
+        Ok(value) => return value,
+        Err(e) if e.kind() == io::ErrorKind::NotFound && !backup.exists() => return T::default(),
+        // A save stopped between its two renames leaves the backup only
+        Err(e) if e.kind() == io::ErrorKind::NotFound => None,
+        Err(e) => Some(e),
+    };
+
+    if let Some(e) = error {
+        let corrupt = sibling(path, ".corrupt");
+        warn!(
+            "Unable to read {what} `{}`: {e}, set aside as `{}`",
+            path.display(),
+            corrupt.display()
+        );
+        if let Err(e) = fs::rename(path, &corrupt) {
+            warn!("Unable to set `{}` aside: {e}", path.display());
+        }
+    }
+    match read(&backup) {
+        Ok(value) => {
+            warn!("{what} restored from `{}`", backup.display());
+            value
+        }
+        Err(e) => {
+            if e.kind() != io::ErrorKind::NotFound {
+                warn!("Unable to read {what} `{}`: {e}", backup.display());
+            }
+            T::default()
+        }
+    }
+}
+
+/// Writes `data` to `path` through a temporary file, keeping the previous file as a backup
+fn write(path: &Path, data: &[u8]) -> io::Result<()> {
+    let temporary = sibling(path, ".tmp");
+    let mut f = fs::File::create(&temporary)?;
+    f.write_all(data)?;
+    f.sync_all()?;
+    drop(f);
+
+    match fs::rename(path, sibling(path, ".bak")) {
+        Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e),
+        _ => {}
+    }
+    fs::rename(&temporary, path)
+}
+
+/// Saves `value` as the state at `path`, named `what` in the logs
+pub fn save<T: Serialize>(path: &Path, what: &str, value: &T) {
+    let data = serde_json::to_vec(value).unwrap();
+    if let Err(e) = write(path, &data) {
+        warn!("Unable to save {what} `{}`: {e}", path.display());
+    }
+}
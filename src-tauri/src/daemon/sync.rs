@@ -0,0 +1,182 @@
+//! Manifests of the playlists a client keeps offline, for `GET /sync/manifest`: the audio of
+//! their tracks, the covers of their albums and their lyrics, each with a hash of its content.
+//! Comparing the hashes with the ones of its last sync, a client fetches only the files changed,
+//! alone or in an archive from `POST /sync/download`. The whole audio files are hashed, the
+//! hashes are kept in `.sync.sums` until the size or modification time of a file changes.
+
+use crate::daemon::cue;
+use crate::daemon::lrc;
+use crate::daemon::proxy;
+use crate::daemon::reconcile::Fingerprint;
+use crate::daemon::remote;
+use mu_protocol::api::{SyncFile, SyncKind, SyncManifest, SyncPlaylist};
+use mu_protocol::library::{Playlist, Track};
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+use tracing::warn;
+
+/// Hashes of the whole audio files, keyed by path
+struct Sums {
+    path: PathBuf,
+    entries: HashMap<PathBuf, Fingerprint>,
+    changed: bool,
+}
+
+impl Sums {
+    fn load(cache_dir: &Path) -> Self {
+        let path = cache_dir.join(".sync.sums");
+        let entries = match fs::read_to_string(&path) {
+            Ok(data) => serde_json::from_str(&data).unwrap_or_else(|e| {
+                warn!("sync: unable to read `{}`: {e}", path.display());
+                HashMap::new()
+            }),
+            Err(_) => HashMap::new(),
+        };
+
+        Self {
+            path,
+            entries,
+            changed: false,
+        }
+    }
+
+    fn save(&self) {
+        if !self.changed {
+            return;
+        }
+        let data = serde_json::to_string(&self.entries).unwrap();
+        if let Err(e) = fs::write(&self.path, data) {
+            warn!("sync: unable to save `{}`: {e}", self.path.display());
+        }
+    }
+
+    /// Fingerprint of the whole `file`, hashed again only when it changed
+    fn get(&mut self, file: &Path) -> std::io::Result<Fingerprint> {
+        if let Some(fingerprint) = self.entries.get(file).filter(|x| x.is_current(file)) {
+            return Ok(fingerprint.clone());
+        }
+        let fingerprint = Fingerprint::whole(file)?;
+        self.entries.insert(file.to_path_buf(), fingerprint.clone());
+        self.changed = true;
+        Ok(fingerprint)
+    }
+}
+
+fn hash(data: impl AsRef<[u8]>) -> String {
+    format!("{:x}", md5::compute(data))
+}
+
+/// The audio of `track`. The parts of files split by a cue sheet are hashed after their source,
+/// and the files of remote storages after their path, their edits only being told by it.
+fn audio(cache_dir: &Path, sums: &mut Sums, track: &Track) -> std::io::Result<SyncFile> {
+    let file = Path::new(track.audio_file());
+    let (ext, size, hash) = if remote::is_remote(file) {
+        let ext = file.extension().unwrap_or_default().to_string_lossy();
+        (ext.to_string(), None, hash(&track.file_path))
+    } else if track.source.is_some() {
+        let source = sums.get(file)?;
+        let (cut, _, fresh) = cue::cut(cache_dir, track)?;
+        let ext = cut.extension().unwrap_or_default().to_string_lossy();
+        let size = fresh
+            .then(|| fs::metadata(&cut).ok().map(|x| x.len()))
+            .flatten();
+        (
+            ext.to_string(),
+            size,
+            hash(format!("{}:{}", source.hash, track.id)),
+        )
+    } else {
+        let fingerprint = sums.get(file)?;
+        let ext = file.extension().unwrap_or_default().to_string_lossy();
+        (ext.to_string(), Some(fingerprint.size), fingerprint.hash)
+    };
+
+    Ok(SyncFile {
+        path: format!("audio/{}.{ext}", track.id),
+        kind: SyncKind::Audio,
+        id: track.id.clone(),
+        url: proxy::url(&format!("/audio/{}", track.id)),
+        size,
+        hash,
+    })
+}
+
+/// The cover of the album of `track`, once extracted
+fn cover(covers_dir: &Path, track: &Track) -> Option<SyncFile> {
+    let handle = format!("{}{}", track.album_id, track.cover_ext);
+    let data = fs::read(covers_dir.join(&handle)).ok()?;
+    Some(SyncFile {
+        path: format!("covers/{handle}"),
+        kind: SyncKind::Cover,
+        id: track.album_id.clone(),
+        url: proxy::url(&format!("/cover/{handle}")),
+        size: Some(data.len() as u64),
+        // As the `ETag` of `GET /cover/{handle}`
+        hash: hash(&data),
+    })
+}
+
+/// The body of `GET /lyrics/{id}` for `track`, if it has lyrics
+pub fn lyrics_data(track: &Track) -> Option<Vec<u8>> {
+    let lyrics = lrc::lyrics(track);
+    if lyrics.synced.is_empty() && lyrics.plain.is_none() {
+        return None;
+    }
+    serde_json::to_vec(&lyrics).ok()
+}
+
+fn lyrics(track: &Track) -> Option<SyncFile> {
+    let data = lyrics_data(track)?;
+    Some(SyncFile {
+        path: format!("lyrics/{}.json", track.id),
+        kind: SyncKind::Lyrics,
+        id: track.id.clone(),
+        url: proxy::url(&format!("/lyrics/{}", track.id)),
+        size: Some(data.len() as u64),
+        hash: hash(&data),
+    })
+}
+
+/// The manifest of `playlists`, given with their tracks, blocking while the audio files not
+/// hashed yet are read
+pub fn manifest(cache_dir: &Path, playlists: Vec<(Playlist, Vec<Track>)>) -> SyncManifest {
+    let covers_dir = cache_dir.join("covers");
+    let mut sums = Sums::load(cache_dir);
+    let mut files = vec![];
+    let mut listed = HashSet::new();
+    let mut synced = vec![];
+
+    for (playlist, tracks) in playlists {
+        for track in &tracks {
+            if !listed.insert(track.id.clone()) {
+                continue;
+            }
+            match audio(cache_dir, &mut sums, track) {
+                Ok(file) => files.push(file),
+                Err(e) => warn!("sync: unable to hash `{}`: {e}", track.audio_file()),
+            }
+            if let Some(file) = cover(&covers_dir, track) {
+                if listed.insert(file.path.clone()) {
+                    files.push(file);
+                }
+            }
+            files.extend(lyrics(track));
+        }
+        synced.push(SyncPlaylist {
+            id: playlist.id,
+            name: playlist.name,
+            description: playlist.description,
+            tracks: tracks.into_iter().map(|x| x.id).collect(),
+        });
+    }
+    sums.save();
+
+    let hash = hash(serde_json::to_vec(&(&synced, &files)).unwrap_or_default());
+    SyncManifest {
+        size: files.iter().filter_map(|x| x.size).sum(),
+        playlists: synced,
+        files,
+        hash,
+    }
+}
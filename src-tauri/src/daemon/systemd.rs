@@ -0,0 +1,90 @@
+//! Integration with systemd: socket activation and readiness notifications, see
+//! `sd_listen_fds(3)` and `sd_notify(3)`. Both do nothing when not started by systemd.
+
+use tracing::warn;
+
+/// First descriptor passed by systemd, the next ones follow
+#[cfg(unix)]
+const LISTEN_FDS_START: std::os::fd::RawFd = 3;
+
+/// The listening socket passed by systemd when started by a `.socket` unit
+#[cfg(unix)]
+pub fn activated_listener() -> Option<std::net::TcpListener> {
+    use std::os::fd::FromRawFd;
+
+    let pid: u32 = std::env::var("LISTEN_PID").ok()?.parse().ok()?;
+    let fds: u32 = std::env::var("LISTEN_FDS").ok()?.parse().ok()?;
+    // Meant for another process, e.g. a parent we inherited the environment of
+    if pid != std::process::id() || fds == 0 {
+        return None;
+    }
+    for name in ["LISTEN_PID", "LISTEN_FDS", "LISTEN_FDNAMES"] {
+        std::env::remove_var(name);
+    }
+    if fds > 1 {
+        warn!("systemd: {fds} sockets passed, only the first one is used");
+    }
+
+    // SAFETY: systemd hands the descriptor over to us, nothing else in the process owns it
+    let passed = unsafe { std::net::TcpListener::from_raw_fd(LISTEN_FDS_START) };
+    // The passed descriptor would be inherited by the encoders, its duplicate is close-on-exec
+    let listener = match passed.try_clone() {
+        Ok(listener) => listener,
+        Err(e) => {
+            warn!("systemd: unable to use the passed socket: {e}");
+            return None;
+        }
+    };
+    if let Err(e) = listener.set_nonblocking(true) {
+        warn!("systemd: unable to use the passed socket: {e}");
+        return None;
+    }
+
+    Some(listener)
+}
+
+#[cfg(not(unix))]
+pub fn activated_listener() -> Option<std::net::TcpListener> {
+    None
+}
+
+#[cfg(target_os = "linux")]
+fn send_abstract(
+    socket: &std::os::unix::net::UnixDatagram,
+    name: &str,
+    state: &str,
+) -> std::io::Result<usize> {
+    use std::os::linux::net::SocketAddrExt;
+    use std::os::unix::net::SocketAddr;
+
+    socket.send_to_addr(state.as_bytes(), &SocketAddr::from_abstract_name(name)?)
+}
+
+#[cfg(all(unix, not(target_os = "linux")))]
+fn send_abstract(
+    _socket: &std::os::unix::net::UnixDatagram,
+    _name: &str,
+    _state: &str,
+) -> std::io::Result<usize> {
+    Err(std::io::ErrorKind::Unsupported.into())
+}
+
+/// Sends `state`, e.g. `READY=1`, to the service manager
+#[cfg(unix)]
+pub fn notify(state: &str) {
+    use std::os::unix::net::UnixDatagram;
+
+    let Some(path) = std::env::var("NOTIFY_SOCKET").ok() else {
+        return;
+    };
+    let sent = UnixDatagram::unbound().and_then(|socket| match path.strip_prefix('@') {
+        Some(name) => send_abstract(&socket, name, state),
+        None => socket.send_to(state.as_bytes(), &path),
+    });
+    if let Err(e) = sent {
+        warn!("systemd: unable to notify `{state}`: {e}");
+    }
+}
+
+#[cfg(not(unix))]
+pub fn notify(_state: &str) {}
@@ -0,0 +1,166 @@
+//! Tag edits applied to many tracks at once by `POST /tracks/batch`, as a job. Each file is
+//! edited in a copy renamed over it once written, so a failed edit leaves the file untouched.
+
+use crate::daemon::config::SharedConfig;
+use crate::daemon::global::{read_track, ScanOptions};
+use crate::daemon::jobs::JobContext;
+use crate::daemon::libraries::Library;
+use crate::daemon::reconcile::Fingerprints;
+use crate::daemon::utils;
+use lofty::config::WriteOptions;
+use lofty::prelude::*;
+use lofty::probe::Probe;
+use lofty::tag::Tag;
+use mu_protocol::api::{BatchFailure, BatchOperation};
+use mu_protocol::library::Track;
+use socketioxide::SocketIo;
+use std::path::{Path, PathBuf};
+use tracing::{info, warn};
+
+/// Applies `operation` to `tag`, the one of `track`, `number` being the position of the track
+/// in the batch
+fn edit(tag: &mut Tag, operation: &BatchOperation, track: &Track, number: u32) {
+    match operation {
+        BatchOperation::SetGenre { genre } => tag.set_genre(genre.clone()),
+        BatchOperation::SetAlbumArtist { album_artist } => {
+            tag.insert_text(ItemKey::AlbumArtist, album_artist.clone());
+        }
+        BatchOperation::Renumber { start } => tag.set_track(start.unwrap_or(1) + number),
+        BatchOperation::MoveToAlbum {
+            album,
+            album_artist,
+        } => {
+            tag.set_album(album.clone());
+            if let Some(album_artist) = album_artist {
+                tag.insert_text(ItemKey::AlbumArtist, album_artist.clone());
+            }
+        }
+        BatchOperation::WriteInferred => {
+            if track.title_inferred {
+                tag.set_title(track.title.clone());
+            }
+            if track.track_inferred {
+                tag.set_track(track.track);
+            }
+        }
+    }
+}
+
+/// The copy of `path` edited before replacing it, hidden next to it with the same extension
+fn staging_path(path: &Path) -> PathBuf {
+    let name = path.file_name().unwrap_or_default().to_string_lossy();
+    path.with_file_name(format!(".mu-{name}"))
+}
+
+/// Edits the tags of the file `path` with `change`, leaving it as it was on failure
+pub fn write(path: &Path, change: impl FnOnce(&mut Tag)) -> Result<(), Box<dyn std::error::Error>> {
+    let staging = staging_path(path);
+    std::fs::copy(path, &staging)?;
+    let result = (|| -> Result<(), Box<dyn std::error::Error>> {
+        let mut tagged_file = Probe::open(&staging)?.read()?;
+        if tagged_file.primary_tag().is_none() {
+            tagged_file.insert_tag(Tag::new(tagged_file.primary_tag_type()));
+        }
+        let tag = tagged_file.primary_tag_mut().unwrap();
+        change(tag);
+        tag.save_to_path(&staging, WriteOptions::default())?;
+        std::fs::rename(&staging, path)?;
+        Ok(())
+    })();
+    if result.is_err() {
+        let _ = std::fs::remove_file(&staging);
+    }
+
+    result
+}
+
+/// Edits the files of `tracks` in order until the job of `context` is cancelled, returning the
+/// tracks read again from the edited files
+fn apply(
+    tracks: Vec<Track>,
+    operation: &BatchOperation,
+    covers_dir: &PathBuf,
+    options: &ScanOptions,
+    context: &JobContext,
+) -> (Vec<(Track, Track)>, Vec<BatchFailure>) {
+    let mut updated = vec![];
+    let mut failed = vec![];
+    let total = tracks.len();
+    for (i, track) in tracks.into_iter().enumerate() {
+        if context.cancel.is_cancelled() {
+            failed.push(BatchFailure {
+                track: track.id,
+                error: "the batch was cancelled".to_string(),
+            });
+            continue;
+        }
+        context.progress(i, total);
+
+        let path = PathBuf::from(&track.file_path);
+        let edited = write(&path, |tag| edit(tag, operation, &track, i as u32))
+            .and_then(|()| Ok(read_track(covers_dir, path, options)?));
+        match edited {
+            Ok(mut edited) => {
+                // The same track as far as the clients are concerned
+                edited.id.clone_from(&track.id);
+                updated.push((track, edited));
+            }
+            Err(e) => {
+                warn!("tags: unable to edit `{}`: {e}", track.file_path);
+                failed.push(BatchFailure {
+                    track: track.id,
+                    error: e.to_string(),
+                });
+            }
+        }
+    }
+
+    (updated, failed)
+}
+
+/// Applies `operation` to `tracks` of `library` and updates its media, as a job. Returns the
+/// ids of the edited tracks and the failures.
+pub async fn run(
+    library: &Library,
+    config: &SharedConfig,
+    io: &SocketIo,
+    tracks: Vec<Track>,
+    operation: BatchOperation,
+    context: JobContext,
+) -> Result<(Vec<String>, Vec<BatchFailure>), String> {
+    let options = ScanOptions::from_config(&*config.read().await);
+    let covers_dir = library.cache_dir.join("covers");
+    let (updated, failed) = tokio::task::spawn_blocking(move || {
+        apply(tracks, &operation, &covers_dir, &options, &context)
+    })
+    .await
+    .map_err(|e| e.to_string())?;
+    info!(
+        "tags: {} tracks edited in {}, {} failed",
+        updated.len(),
+        library.name,
+        failed.len()
+    );
+    if updated.is_empty() {
+        return Ok((vec![], failed));
+    }
+
+    let mut media = library.media.write().await;
+    for (track, edited) in &updated {
+        media.remove_song(PathBuf::from(&track.file_path));
+        media.add_song(edited.clone());
+    }
+    // Tracks moved to another album take its cover
+    media.select_covers(&library.cache_dir.join("covers"));
+    utils::save_cache(&library.cache_dir, &media);
+    library.publish(io, &media.commit());
+    library.colors.notify_one();
+
+    let files: Vec<PathBuf> = updated
+        .iter()
+        .map(|(x, _)| PathBuf::from(&x.file_path))
+        .collect();
+    Fingerprints::load(&library.cache_dir).refresh(&files);
+
+    Ok((updated.into_iter().map(|(x, _)| x.id).collect(), failed))
+}
@@ -0,0 +1,237 @@
+//! Sleep timers and alarms: `POST /timers` schedules stopping the playback or playing an album
+//! or playlist, `after` some seconds or at a time of day, possibly repeating on some days of the
+//! week. Timers are kept in `timers.json` and sent to the players as `timer:fired`.
+
+use crate::daemon::events;
+use crate::daemon::libraries::Libraries;
+use crate::daemon::store;
+use chrono::{DateTime, Datelike, Local, NaiveTime};
+use mu_protocol::api::{FiredTimer, NewTimer, Timer, TimerAction, Weekday};
+use mu_protocol::events::Event;
+use mu_protocol::library::Track;
+use socketioxide::SocketIo;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+use tokio::sync::{Notify, RwLock};
+use tracing::{info, warn};
+
+/// Longest sleep of the scheduler, for the timers to fire on time after a clock change or a
+/// suspend
+const MAX_WAIT: Duration = Duration::from_secs(60);
+
+/// Timers due for longer, e.g. while the daemon was stopped, are skipped rather than fired late
+const GRACE: Duration = Duration::from_secs(60);
+
+#[derive(Debug, Default)]
+pub struct Timers {
+    path: PathBuf,
+    timers: Vec<Timer>,
+    /// Wakes the scheduler up when a timer is added or removed
+    changed: Arc<Notify>,
+}
+
+fn weekday(day: chrono::Weekday) -> Weekday {
+    match day {
+        chrono::Weekday::Mon => Weekday::Monday,
+        chrono::Weekday::Tue => Weekday::Tuesday,
+        chrono::Weekday::Wed => Weekday::Wednesday,
+        chrono::Weekday::Thu => Weekday::Thursday,
+        chrono::Weekday::Fri => Weekday::Friday,
+        chrono::Weekday::Sat => Weekday::Saturday,
+        chrono::Weekday::Sun => Weekday::Sunday,
+    }
+}
+
+/// `HH:MM` as a time of day
+fn parse_time(at: &str) -> Option<NaiveTime> {
+    NaiveTime::parse_from_str(at.trim(), "%H:%M").ok()
+}
+
+/// The first time of day `at` after `after`, on one of `days` unless empty
+fn next_time(at: NaiveTime, days: &[Weekday], after: SystemTime) -> Option<SystemTime> {
+    let after = DateTime::<Local>::from(after);
+    // A week and a day, for the time of today already passed
+    for offset in 0..=7 {
+        let date = after.date_naive() + chrono::Duration::days(offset);
+        if !days.is_empty() && !days.contains(&weekday(date.weekday())) {
+            continue;
+        }
+        // Skipped by a change to daylight saving time
+        let Some(time) = date.and_time(at).and_local_timezone(Local).earliest() else {
+            continue;
+        };
+        if time > after {
+            return Some(time.into());
+        }
+    }
+
+    None
+}
+
+/// When `timer` fires after `after`, `None` once it won't anymore
+fn reschedule(timer: &Timer, after: SystemTime) -> Option<SystemTime> {
+    if timer.days.is_empty() {
+        return None;
+    }
+    next_time(parse_time(timer.at.as_deref()?)?, &timer.days, after)
+}
+
+impl Timers {
+    pub fn load(path: PathBuf) -> Self {
+        let timers = store::load(&path, "timers");
+
+        Self {
+            path,
+            timers,
+            changed: Arc::new(Notify::new()),
+        }
+    }
+
+    /// Reads the timers again, e.g. once restored by `POST /import`
+    pub fn reload(&mut self) {
+        self.timers = Self::load(self.path.clone()).timers;
+        self.changed.notify_one();
+    }
+
+    fn save(&self) {
+        store::save(&self.path, "timers", &self.timers);
+        self.changed.notify_one();
+    }
+
+    /// The timers, the next to fire first
+    pub fn list(&self) -> Vec<Timer> {
+        let mut timers = self.timers.clone();
+        timers.sort_by_key(|x| x.next);
+        timers
+    }
+
+    /// Schedules `new` for the library `library`, failing with the reason it can't be
+    pub fn add(&mut self, new: NewTimer, library: &str) -> Result<Timer, String> {
+        let now = SystemTime::now();
+        let next = match (new.after, &new.at) {
+            (Some(_), Some(_)) => return Err("`after` and `at` can't be both set".to_string()),
+            (Some(_), None) if !new.days.is_empty() => {
+                return Err("`days` only apply to `at`".to_string())
+            }
+            (Some(after), None) => now + Duration::from_secs(after),
+            (None, Some(at)) => {
+                let Some(time) = parse_time(at) else {
+                    return Err(format!("invalid time of day `{at}`, expected HH:MM"));
+                };
+                next_time(time, &new.days, now)
+                    .ok_or(format!("`{at}` doesn't exist in the local time"))?
+            }
+            (None, None) => return Err("either `after` or `at` must be set".to_string()),
+        };
+
+        let timer = Timer {
+            id: uuid::Uuid::new_v4().simple().to_string(),
+            label: new.label,
+            action: new.action,
+            at: new.at.map(|x| x.trim().to_string()),
+            days: new.days,
+            library: library.to_string(),
+            next,
+            created_at: now,
+        };
+        self.timers.push(timer.clone());
+        self.save();
+
+        Ok(timer)
+    }
+
+    pub fn remove(&mut self, id: &str) -> bool {
+        let count = self.timers.len();
+        self.timers.retain(|x| x.id != id);
+        let removed = self.timers.len() != count;
+        if removed {
+            self.save();
+        }
+
+        removed
+    }
+
+    /// Takes the timers due at `now` out, the repeating ones being scheduled again. The ones
+    /// missed by more than `GRACE` aren't returned.
+    fn take_due(&mut self, now: SystemTime) -> Vec<Timer> {
+        let mut due = vec![];
+        let mut changed = false;
+        self.timers.retain_mut(|timer| {
+            if timer.next > now {
+                return true;
+            }
+            changed = true;
+            let late = now.duration_since(timer.next).unwrap_or_default();
+            if late > GRACE {
+                warn!("timer {} missed by {}s, skipped", timer.id, late.as_secs());
+            } else {
+                due.push(timer.clone());
+            }
+            match reschedule(timer, now) {
+                Some(next) => {
+                    timer.next = next;
+                    true
+                }
+                None => false,
+            }
+        });
+        if changed {
+            self.save();
+        }
+
+        due
+    }
+
+    fn next(&self) -> Option<SystemTime> {
+        self.timers.iter().map(|x| x.next).min()
+    }
+}
+
+/// Tracks of the album or playlist of `timer`, none for `stop` or once gone
+async fn tracks(timer: &Timer, libraries: &Libraries) -> Vec<Track> {
+    let Some(library) = libraries.get(Some(&timer.library)) else {
+        return vec![];
+    };
+    let media = library.media.load();
+    let paths = match &timer.action {
+        TimerAction::Stop => return vec![],
+        TimerAction::Album { id } => media.get_album(&id.as_str().into()).map(|x| x.tracks),
+        TimerAction::Playlist { id } => media.get_playlist(&id.as_str().into()).map(|x| x.tracks),
+    };
+
+    paths
+        .unwrap_or_default()
+        .iter()
+        .filter_map(|x| media.tracks.get(x).cloned())
+        .collect()
+}
+
+/// Fires the timers when they are due
+pub async fn run(timers: Arc<RwLock<Timers>>, libraries: Arc<Libraries>, io: SocketIo) {
+    let changed = Arc::clone(&timers.read().await.changed);
+    loop {
+        let wait = timers
+            .read()
+            .await
+            .next()
+            .map(|next| next.duration_since(SystemTime::now()).unwrap_or_default())
+            .unwrap_or(MAX_WAIT)
+            .min(MAX_WAIT);
+        tokio::select! {
+            _ = tokio::time::sleep(wait) => {}
+            _ = changed.notified() => continue,
+        }
+
+        let due = timers.write().await.take_due(SystemTime::now());
+        if due.is_empty() {
+            continue;
+        }
+        for timer in due {
+            info!("timer {} fired", timer.id);
+            let tracks = tracks(&timer, &libraries).await;
+            events::emit(&io, Event::TimerFired, FiredTimer { timer, tracks });
+        }
+        events::emit(&io, Event::Timers, timers.read().await.list());
+    }
+}
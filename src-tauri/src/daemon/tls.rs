@@ -0,0 +1,38 @@
+use crate::daemon::config::WATCH_INTERVAL;
+use axum_server::tls_rustls::RustlsConfig;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+use tracing::{info, warn};
+
+/// Certificate and key of `network.tls`, `None` unless both are set
+pub fn files(config: &lorconf::Config) -> Option<(PathBuf, PathBuf)> {
+    let tls = config.network.as_ref()?.tls.as_ref()?;
+    Some((tls.cert.clone()?, tls.key.clone()?))
+}
+
+fn modified(path: &Path) -> Option<SystemTime> {
+    std::fs::metadata(path).and_then(|x| x.modified()).ok()
+}
+
+/// Reloads the certificate and key whenever one of them is modified, e.g. renewed. The
+/// current ones are kept if the new files can't be loaded, as when only one was written yet.
+pub async fn watch(rustls: RustlsConfig, cert: PathBuf, key: PathBuf) {
+    let mut last_modified = (modified(&cert), modified(&key));
+
+    loop {
+        tokio::time::sleep(WATCH_INTERVAL).await;
+
+        let current = (modified(&cert), modified(&key));
+        if current == last_modified {
+            continue;
+        }
+
+        match rustls.reload_from_pem_file(&cert, &key).await {
+            Ok(()) => {
+                last_modified = current;
+                info!("TLS certificate reloaded");
+            }
+            Err(e) => warn!("Unable to reload the TLS certificate: {e}"),
+        }
+    }
+}
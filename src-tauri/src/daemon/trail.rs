@@ -0,0 +1,124 @@
+//! Request logging and the audit trail. Every request runs in a `request` span carrying its
+//! method, path and client, the account of its token or its remote address, and ends with an
+//! event giving its status and duration. Requests that change something, tag edits, deletions,
+//! playlist or account changes..., are kept in `audit.jsonl`, one entry per line, and listed by
+//! `GET /admin/audit`. Playback reports, like positions and plays, aren't kept.
+
+use axum::http::Method;
+use mu_protocol::api::{AuditEntry, AuditQuery};
+use std::collections::VecDeque;
+use std::io::Write;
+use std::path::PathBuf;
+use std::time::{Duration, SystemTime};
+use tracing::warn;
+
+/// Entries kept, the oldest ones are dropped past it
+const MAX_ENTRIES: usize = 10_000;
+const DEFAULT_LIMIT: usize = 100;
+
+/// Whether a `method` request to `path` is kept in the trail
+pub fn audited(method: &Method, path: &str) -> bool {
+    if !matches!(
+        *method,
+        Method::POST | Method::PUT | Method::PATCH | Method::DELETE
+    ) {
+        return false;
+    }
+    // Reported all along the playback
+    let playback = path.starts_with("/player/")
+        || path == "/queue"
+        || path.ends_with("/position")
+        || path.ends_with("/played")
+        || (path.starts_with("/sessions/")
+            && (path.ends_with("/playback") || path.ends_with("/next")));
+    !playback
+}
+
+/// Mutating requests, oldest first
+#[derive(Debug, Default)]
+pub struct AuditTrail {
+    path: PathBuf,
+    entries: VecDeque<AuditEntry>,
+}
+
+impl AuditTrail {
+    pub fn load(path: PathBuf) -> Self {
+        let mut entries = VecDeque::new();
+        if let Ok(text) = std::fs::read_to_string(&path) {
+            for line in text.lines().filter(|x| !x.trim().is_empty()) {
+                match serde_json::from_str(line) {
+                    Ok(entry) => entries.push_back(entry),
+                    Err(e) => warn!(
+                        "Skipping an entry of the audit trail `{}`: {e}",
+                        path.display()
+                    ),
+                }
+            }
+        }
+        let mut trail = Self { path, entries };
+        if trail.entries.len() > MAX_ENTRIES {
+            trail.entries.drain(..trail.entries.len() - MAX_ENTRIES);
+            trail.save();
+        }
+
+        trail
+    }
+
+    fn save(&self) {
+        let mut data = String::new();
+        for entry in &self.entries {
+            data.push_str(&serde_json::to_string(entry).unwrap());
+            data.push('\n');
+        }
+        if let Err(e) = std::fs::write(&self.path, data) {
+            warn!(
+                "Unable to save the audit trail `{}`: {e}",
+                self.path.display()
+            );
+        }
+    }
+
+    /// Appends `entry` to the file, which is only rewritten once the oldest entries go
+    pub fn record(&mut self, entry: AuditEntry) {
+        let line = serde_json::to_string(&entry).unwrap();
+        self.entries.push_back(entry);
+        if self.entries.len() > MAX_ENTRIES * 11 / 10 {
+            self.entries.drain(..self.entries.len() - MAX_ENTRIES);
+            self.save();
+            return;
+        }
+
+        let appended = std::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)
+            .and_then(|mut f| writeln!(f, "{line}"));
+        if let Err(e) = appended {
+            warn!(
+                "Unable to write to the audit trail `{}`: {e}",
+                self.path.display()
+            );
+        }
+    }
+
+    /// The most recent entries matching `query`, newest first
+    pub fn query(&self, query: &AuditQuery) -> Vec<AuditEntry> {
+        let since = query
+            .since
+            .map(|x| SystemTime::UNIX_EPOCH + Duration::from_secs(x));
+        self.entries
+            .iter()
+            .rev()
+            .take_while(|x| since.is_none_or(|since| x.at >= since))
+            .filter(|x| query.user.is_none() || x.user == query.user)
+            .filter(|x| {
+                query
+                    .path
+                    .as_deref()
+                    .is_none_or(|path| x.path.starts_with(path))
+            })
+            .take(query.limit.unwrap_or(DEFAULT_LIMIT))
+            .cloned()
+            .collect()
+    }
+}
@@ -0,0 +1,342 @@
+//! Accounts of the people sharing the daemon. Requests carrying the token of an account as
+//! `Authorization: Bearer <token>` get its own favorites, history, queue, bookmarks, positions
+//! and listen later list, kept in `users/<name>/`. The other requests share the ones of the
+//! default user, as before accounts existed. Admins manage the accounts with `/admin/users`.
+//!
+//! The media is the same for everyone, the bookmarks and positions are read with
+//! `/track/{id}/bookmarks` and `/track/{id}/position`.
+//!
+//! An account may be limited to some of the libraries, see [`User::libraries`] and
+//! [`crate::daemon::libraries`].
+
+use crate::daemon::bookmarks::Bookmarks;
+use crate::daemon::favorites::Favorites;
+use crate::daemon::history::History;
+use crate::daemon::listen_later::ListenLater;
+use crate::daemon::positions::Positions;
+use crate::daemon::queue::Queue;
+use crate::daemon::shutdown::same_token;
+use crate::daemon::store;
+use axum::http::{header::AUTHORIZATION, HeaderMap};
+use mu_protocol::api::{NewUser, User, UserChanges, UserToken};
+use sha2::{Digest, Sha256};
+use socketioxide::extract::SocketRef;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::SystemTime;
+use tokio::sync::RwLock;
+use tracing::{info, warn};
+
+/// Longest name of an account
+const MAX_NAME: usize = 64;
+
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone)]
+struct Account {
+    #[serde(flatten)]
+    user: User,
+    /// SHA-256 of the token, which isn't kept. The accounts created before are hashed with MD5
+    /// until their next authentication.
+    token_hash: String,
+}
+
+/// The stores of an account
+#[derive(Debug, Clone)]
+pub struct UserData {
+    pub favorites: Arc<RwLock<Favorites>>,
+    pub history: Arc<RwLock<History>>,
+    pub queue: Arc<RwLock<Queue>>,
+    pub bookmarks: Arc<RwLock<Bookmarks>>,
+    pub positions: Arc<RwLock<Positions>>,
+    pub listen_later: Arc<RwLock<ListenLater>>,
+}
+
+impl UserData {
+    fn load(dir: &Path) -> Self {
+        if let Err(e) = std::fs::create_dir_all(dir) {
+            warn!("Unable to create `{}`: {e}", dir.display());
+        }
+        Self {
+            favorites: Arc::new(RwLock::new(Favorites::load(dir.join("favorites.json")))),
+            history: Arc::new(RwLock::new(History::load(dir.join("history.json")))),
+            queue: Arc::new(RwLock::new(Queue::load(dir.join("queue.json")))),
+            bookmarks: Arc::new(RwLock::new(Bookmarks::load(dir.join("bookmarks.json")))),
+            positions: Arc::new(RwLock::new(Positions::load(dir.join("positions.json")))),
+            listen_later: Arc::new(RwLock::new(ListenLater::load(
+                dir.join("listen_later.json"),
+            ))),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct Users {
+    path: PathBuf,
+    /// Folders of the data of the accounts
+    dir: PathBuf,
+    accounts: Mutex<Vec<Account>>,
+    /// Stores of the accounts, loaded on their first request
+    data: Mutex<HashMap<String, UserData>>,
+}
+
+/// `library.hide_explicit`, for the default user
+pub fn hide_explicit(config: &lorconf::Config) -> bool {
+    config
+        .library
+        .as_ref()
+        .and_then(|library| library.hide_explicit)
+        .or(lorconf::Library::default().hide_explicit)
+        .unwrap_or_default()
+}
+
+fn hash(token: &str) -> String {
+    format!("{:x}", Sha256::digest(token))
+}
+
+/// The hash the tokens had before [`hash`]
+fn legacy_hash(token: &str) -> String {
+    format!("{:x}", md5::compute(token))
+}
+
+fn new_token() -> String {
+    uuid::Uuid::new_v4().simple().to_string()
+}
+
+impl Users {
+    pub fn load(app_dir: &Path) -> Self {
+        let path = app_dir.join("users.json");
+        let accounts = store::load(&path, "users");
+
+        Self {
+            path,
+            dir: app_dir.join("users"),
+            accounts: Mutex::new(accounts),
+            data: Mutex::new(HashMap::new()),
+        }
+    }
+
+    fn save(&self) {
+        let accounts = self.accounts.lock().unwrap();
+        store::save(&self.path, "users", &*accounts);
+    }
+
+    /// The account with the token `token`. The hash of an account still hashed with MD5 is
+    /// replaced by its SHA-256.
+    pub fn authenticate(&self, token: &str) -> Option<User> {
+        let hash = hash(token);
+        let legacy = legacy_hash(token);
+        let (user, migrated) = {
+            let mut accounts = self.accounts.lock().unwrap();
+            let account = accounts
+                .iter_mut()
+                .find(|x| same_token(&x.token_hash, &hash) || same_token(&x.token_hash, &legacy))?;
+            let migrated = account.token_hash.len() != hash.len();
+            if migrated {
+                account.token_hash = hash;
+            }
+            (account.user.clone(), migrated)
+        };
+        if migrated {
+            info!("user {}: token hashed again", user.name);
+            self.save();
+        }
+
+        Some(user)
+    }
+
+    /// The stores of the account `name`
+    pub fn data(&self, name: &str) -> UserData {
+        self.data
+            .lock()
+            .unwrap()
+            .entry(name.to_string())
+            .or_insert_with(|| UserData::load(&self.dir.join(name)))
+            .clone()
+    }
+
+    pub fn list(&self) -> Vec<User> {
+        let mut users: Vec<User> = self
+            .accounts
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|x| x.user.clone())
+            .collect();
+        users.sort_by_key(|x| x.name.to_lowercase());
+        users
+    }
+
+    /// Creates the account, failing with the reason it can't be
+    pub fn create(&self, new: NewUser) -> Result<UserToken, String> {
+        // Names are directories of the data
+        let name = new.name.trim();
+        if name.is_empty()
+            || name.len() > MAX_NAME
+            || name.starts_with('.')
+            || name.contains(['/', '\\'])
+        {
+            return Err(format!("invalid user name `{name}`"));
+        }
+
+        let mut accounts = self.accounts.lock().unwrap();
+        if accounts.iter().any(|x| x.user.name == name) {
+            return Err(format!("a user named {name} already exists"));
+        }
+        let token = new_token();
+        let user = User {
+            name: name.to_string(),
+            role: new.role,
+            created_at: SystemTime::now(),
+            hide_explicit: new.hide_explicit,
+            libraries: new.libraries,
+        };
+        accounts.push(Account {
+            user: user.clone(),
+            token_hash: hash(&token),
+        });
+        drop(accounts);
+        self.save();
+        info!("user {} created", user.name);
+
+        Ok(UserToken { user, token })
+    }
+
+    /// Gives the account `name` a new token, the former one no longer works
+    pub fn renew(&self, name: &str) -> Option<UserToken> {
+        let mut accounts = self.accounts.lock().unwrap();
+        let account = accounts.iter_mut().find(|x| x.user.name == name)?;
+        let token = new_token();
+        account.token_hash = hash(&token);
+        let user = account.user.clone();
+        drop(accounts);
+        self.save();
+
+        Some(UserToken { user, token })
+    }
+
+    /// Applies `changes` to the account `name`, `None` if there is no such account
+    pub fn change(&self, name: &str, changes: UserChanges) -> Option<User> {
+        let mut accounts = self.accounts.lock().unwrap();
+        let account = accounts.iter_mut().find(|x| x.user.name == name)?;
+        if let Some(hide_explicit) = changes.hide_explicit {
+            account.user.hide_explicit = hide_explicit;
+        }
+        if let Some(libraries) = changes.libraries {
+            account.user.libraries = libraries;
+        }
+        let user = account.user.clone();
+        drop(accounts);
+        self.save();
+
+        Some(user)
+    }
+
+    /// Removes the account `name` along with its data
+    pub fn remove(&self, name: &str) -> bool {
+        let mut accounts = self.accounts.lock().unwrap();
+        let count = accounts.len();
+        accounts.retain(|x| x.user.name != name);
+        if accounts.len() == count {
+            return false;
+        }
+        drop(accounts);
+        self.save();
+
+        self.data.lock().unwrap().remove(name);
+        let dir = self.dir.join(name);
+        if dir.exists() {
+            if let Err(e) = std::fs::remove_dir_all(&dir) {
+                warn!("Unable to remove `{}`: {e}", dir.display());
+            }
+        }
+        info!("user {name} removed");
+        true
+    }
+
+    /// Puts a socket connecting with the token of an account as `auth.token` in the room of
+    /// the account, where the events about its data are sent. Returns the account.
+    pub fn join_room(&self, socket: &SocketRef, auth: &serde_json::Value) -> Option<User> {
+        let user = auth
+            .get("token")
+            .and_then(|x| x.as_str())
+            .and_then(|x| self.authenticate(x))?;
+        if let Err(e) = socket.join(room(&user.name)) {
+            warn!("Unable to join the room of {}: {e}", user.name);
+        }
+        Some(user)
+    }
+}
+
+/// Whether `user` sees the library named `library`, see [`User::libraries`]
+pub fn sees(user: &User, library: &str) -> bool {
+    user.libraries.is_empty() || user.libraries.iter().any(|x| x == library)
+}
+
+/// Token of `Authorization: Bearer <token>`
+pub fn bearer(headers: &HeaderMap) -> Option<&str> {
+    headers
+        .get(AUTHORIZATION)
+        .and_then(|x| x.to_str().ok())
+        .and_then(|x| x.strip_prefix("Bearer "))
+}
+
+/// Socket.io room of the account `name`
+pub fn room(name: &str) -> String {
+    format!("user:{name}")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use mu_protocol::api::Role;
+
+    fn users() -> (PathBuf, Users) {
+        let root = std::env::temp_dir().join(format!("lorchestre-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&root).unwrap();
+        let users = Users::load(&root);
+        (root, users)
+    }
+
+    fn new_user(name: &str) -> NewUser {
+        NewUser {
+            name: name.to_string(),
+            role: Role::Listener,
+            hide_explicit: false,
+            libraries: vec![],
+        }
+    }
+
+    #[test]
+    fn tokens_authenticate_their_account() {
+        let (root, users) = users();
+        let alice = users.create(new_user("alice")).unwrap();
+        let bob = users.create(new_user("bob")).unwrap();
+
+        assert_eq!(users.authenticate(&alice.token).unwrap().name, "alice");
+        assert_eq!(users.authenticate(&bob.token).unwrap().name, "bob");
+        assert!(users.authenticate("").is_none());
+        assert!(users.authenticate(&hash(&alice.token)).is_none());
+
+        let renewed = users.renew("alice").unwrap();
+        assert!(users.authenticate(&alice.token).is_none());
+        assert_eq!(users.authenticate(&renewed.token).unwrap().name, "alice");
+        std::fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    fn md5_hashes_are_replaced() {
+        let (root, users) = users();
+        let token = users.create(new_user("alice")).unwrap().token;
+        users.accounts.lock().unwrap()[0].token_hash = legacy_hash(&token);
+        users.save();
+
+        let users = Users::load(&root);
+        assert_eq!(users.authenticate(&token).unwrap().name, "alice");
+        assert_eq!(users.accounts.lock().unwrap()[0].token_hash, hash(&token));
+        // Saved once hashed again
+        let users = Users::load(&root);
+        assert_eq!(users.accounts.lock().unwrap()[0].token_hash, hash(&token));
+        assert_eq!(users.authenticate(&token).unwrap().name, "alice");
+        std::fs::remove_dir_all(root).unwrap();
+    }
+}
@@ -1,92 +1,355 @@
 use std::{
     fs,
     io::{Read, Write},
+    panic::AssertUnwindSafe,
     path::{Path, PathBuf},
+    time::{Duration, Instant},
 };
 
+use crate::daemon::audiobooks;
 use crate::daemon::global::utils::cache_audio_files;
 use crate::daemon::global::utils::get_audio_files;
 use crate::daemon::global::utils::read_cache_audio_files;
-use crate::daemon::global::Media;
+use crate::daemon::global::{check_dir, Media, ScanOptions, ALBUM_ID_SCHEME};
+use crate::daemon::reconcile::Fingerprints;
+use crate::daemon::remote;
+use crate::daemon::scan::Progress;
+use axum::http::HeaderValue;
+use axum_extra::headers::{ContentRange, Header, Range};
+use axum_range::RangeNotSatisfiable;
+use mu_protocol::api::Reconciliation;
+use mu_protocol::library::Track;
+use tokio_util::sync::CancellationToken;
 use tracing::{info, warn};
 
+/// `Content-Disposition` header value for `file_name`, with an ASCII fallback
+/// and the RFC 5987 encoded name for clients supporting it
+pub fn content_disposition(disposition: &str, file_name: &str) -> String {
+    let fallback: String = file_name
+        .chars()
+        .map(|c| match c {
+            '"' | '\\' | '/' => '_',
+            c if !c.is_ascii() || c.is_ascii_control() => '_',
+            c => c,
+        })
+        .collect();
+
+    let encoded: String = file_name
+        .bytes()
+        .map(|b| match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => {
+                (b as char).to_string()
+            }
+            b => format!("%{b:02X}"),
+        })
+        .collect();
+
+    format!("{disposition}; filename=\"{fallback}\"; filename*=UTF-8''{encoded}")
+}
+
+/// The bytes of a file of `size` bytes to serve for `range`, as inclusive bounds: the first of
+/// its ranges overlapping the file, `None` for the whole file. A suffix longer than the file
+/// stands for all of it. Fails when none of the ranges overlaps the file.
+pub fn byte_range(
+    range: Option<&Range>,
+    size: u64,
+) -> Result<Option<(u64, u64)>, RangeNotSatisfiable> {
+    let Some(range) = range else {
+        return Ok(None);
+    };
+    let mut values = Vec::<HeaderValue>::new();
+    range.encode(&mut values);
+    let last = size.checked_sub(1);
+
+    let bounds = values
+        .first()
+        .and_then(|x| x.to_str().ok())
+        .and_then(|x| x.strip_prefix("bytes="))
+        .into_iter()
+        .flat_map(|x| x.split(','))
+        .find_map(|spec| {
+            let (start, end) = spec.trim().split_once('-')?;
+            let last = last?;
+            if start.is_empty() {
+                // The last `end` bytes
+                let suffix = end.parse::<u64>().ok().filter(|x| *x > 0)?;
+                return Some((size.saturating_sub(suffix), last));
+            }
+            let start = start.parse::<u64>().ok()?;
+            let end = match end {
+                "" => last,
+                end => end.parse::<u64>().ok()?.min(last),
+            };
+            (start <= end).then_some((start, end))
+        });
+
+    match bounds {
+        Some(bounds) => Ok(Some(bounds)),
+        None => Err(RangeNotSatisfiable(ContentRange::unsatisfied_bytes(size))),
+    }
+}
+
 pub enum CacheCompareDiff {
     ToAdd { files: Vec<PathBuf> },
     ToRemove { files: Vec<PathBuf> },
     NoDiff,
 }
 
-pub async fn cache_resolve(cache_dir: &PathBuf) -> Media {
+/// How often the media read so far is handed over while a scan reads files
+const STREAM_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Reads `files` into `media`, counting them in `progress`, which is handed the media read so
+/// far when streaming. A file already in `media` is replaced, its tracks read again get back
+/// what they had in `previous`, see [`Media::restore_tracks`]. Files that can't be opened are
+/// left out of the library, they're returned for the scan to try them again next time. Returns
+/// `None` if the scan was cancelled.
+fn add_files(
+    media: &mut Media,
+    files: Vec<PathBuf>,
+    previous: &[Track],
+    covers_dir: &PathBuf,
+    options: &ScanOptions,
+    cancel: &CancellationToken,
+    progress: &Progress,
+) -> Option<Vec<PathBuf>> {
+    let mut skipped = vec![];
+    let mut streamed = Instant::now();
+    progress.expect(files.len());
+    for file in files {
+        if cancel.is_cancelled() {
+            info!("cache process cancelled");
+            return None;
+        }
+        // A file lofty chokes on is skipped rather than taking the daemon down. Remote files
+        // are opened by the reader.
+        let opened = match remote::is_remote(&file) {
+            true => Ok(()),
+            false => fs::File::open(&file).map(|_| ()),
+        };
+        media.remove_media(file.clone());
+        let added = opened.map_err(|e| e.to_string()).and_then(|_| {
+            std::panic::catch_unwind(AssertUnwindSafe(|| {
+                media.add_media(file.clone(), covers_dir, options)
+            }))
+            .map_err(|_| "the file made the reader panic".to_string())?
+            .map_err(|e| e.to_string())
+        });
+        match added {
+            Ok(()) => info!("+ {}", file.display().to_string()),
+            Err(e) => {
+                warn!("Skipping `{}`: {e}", file.display());
+                progress.error(&file, e);
+                skipped.push(file);
+            }
+        }
+        progress.advance();
+        if progress.streaming() && streamed.elapsed() >= STREAM_INTERVAL {
+            media.restore_tracks(previous);
+            progress.read_so_far(media);
+            streamed = Instant::now();
+        }
+    }
+    media.restore_tracks(previous);
+
+    Some(skipped)
+}
+
+/// Reads the metadata cache of `cache_dir`, `None` without one or when it was written by a
+/// scan giving other album ids
+pub fn read_cache(cache_dir: &Path) -> Option<Media> {
+    let cache_file = cache_dir.join(".cache.json");
+    if !cache_file.exists() {
+        return None;
+    }
+    let mut f = fs::File::open(cache_file).ok()?;
+    let mut buf = String::new();
+    let _ = f.read_to_string(&mut buf);
+    let cache_data = serde_json::from_str::<Media>(&buf)
+        .ok()
+        .filter(|x| x.album_id_scheme == ALBUM_ID_SCHEME);
+    let Some(mut cache_data) = cache_data else {
+        warn!("[WARN] Unmatched Media cache verison");
+        return None;
+    };
+    cache_data.reindex();
+
+    Some(cache_data)
+}
+
+/// Scans the folders `paths` of a library, reusing the cached metadata of unchanged and moved
+/// files, see `reconcile`. Returns `None` if the scan was cancelled, in which case the cache is
+/// left untouched.
+pub async fn cache_resolve(
+    cache_dir: &PathBuf,
+    paths: &[PathBuf],
+    options: &ScanOptions,
+    cancel: &CancellationToken,
+    progress: &Progress,
+) -> Option<Media> {
     info!("Starting cache process...");
-    let p_string = cache_dir.join(".cache.json");
     let covers_dir = cache_dir.join("covers");
     let ac_string = cache_dir.join(".cache.list");
     let ac_path = Path::new(&ac_string);
 
     let prev_audio_files = read_cache_audio_files(ac_path);
-    let curr_audio_files = get_audio_files();
-    cache_audio_files(ac_path);
+    check_dir(cache_dir);
+    let (mut curr_audio_files, aliases) = get_audio_files(paths, &options.walk);
+    curr_audio_files.extend(remote::files(paths, &prev_audio_files).await);
 
     let (diff, _, _) = compare_caches(prev_audio_files, curr_audio_files.clone());
+    let mut fingerprints = Fingerprints::load(cache_dir);
 
     let mut cache = Media::default();
-    let cache_file = Path::new(&p_string);
     let mut needs_update = true;
+    let mut skipped = vec![];
+    let mut reconciliation = Reconciliation::default();
+    let mut edited = vec![];
 
-    if cache_file.exists() {
-        let mut f = fs::File::open(cache_file).unwrap();
-        let mut buf = String::new();
-        let _ = f.read_to_string(&mut buf);
-        if let Ok(mut cache_data) = serde_json::from_str::<Media>(&buf) {
-            'f: for d in diff {
-                match d {
-                    CacheCompareDiff::ToAdd { files } => {
-                        for file in files {
-                            info!("+ {}", file.display().to_string());
-                            cache_data.add_media(file, &covers_dir);
-                        }
-                    }
-                    CacheCompareDiff::ToRemove { files } => {
-                        for file in files {
-                            info!("- {}", file.display().to_string());
-                            cache_data.remove_media(file);
-                        }
-                    }
-                    CacheCompareDiff::NoDiff => {
-                        needs_update = false;
-                        info!("~ No cache change");
-                        break 'f;
-                    }
-                }
+    if let Some(mut cache_data) = read_cache(cache_dir) {
+        let (mut to_add, mut to_remove) = (vec![], vec![]);
+        for d in diff {
+            match d {
+                CacheCompareDiff::ToAdd { files } => to_add = files,
+                CacheCompareDiff::ToRemove { files } => to_remove = files,
+                CacheCompareDiff::NoDiff => {}
             }
+        }
+        // The cache may remember files the list doesn't, when it was written apart
+        for file in cache_data.missing_files() {
+            if !to_remove.contains(&file) {
+                to_remove.push(file);
+            }
+        }
 
-            cache = cache_data;
+        let mut moved = vec![];
+        for (from, to) in fingerprints.find_moves(&to_remove, &to_add) {
+            info!("> {} -> {}", from.display(), to.display());
+            cache_data.move_song(&from, &to);
+            to_remove.retain(|x| *x != from);
+            to_add.retain(|x| *x != to);
+            moved.push(to);
+        }
+        reconciliation.moved = moved.len();
+        for file in &to_remove {
+            info!("- {}", file.display().to_string());
+            cache_data.remove_media(file.clone());
+        }
+        reconciliation.removed = to_remove.len();
+        reconciliation.added = to_add.len();
+
+        // Files at the same path whose size or modification time changed are read again, all
+        // of them by a full scan. The moved ones were just moved as they were.
+        let unchanged = curr_audio_files
+            .iter()
+            .filter(|x| !to_add.contains(x) && !moved.contains(x));
+        edited = match options.full {
+            true => unchanged.cloned().collect(),
+            false => fingerprints.edited(unchanged),
+        };
+        let mut previous = vec![];
+        for file in &edited {
+            info!("* {}", file.display());
+            previous.extend(cache_data.tracks_of(file));
+            to_add.push(file.clone());
+        }
+        reconciliation.updated = edited.len();
+
+        if to_add.is_empty() && to_remove.is_empty() && reconciliation.moved == 0 {
+            needs_update = false;
+            info!("~ No cache change");
         } else {
-            warn!("[WARN] Unmatched Media cache verison");
-            for file in curr_audio_files {
-                info!("+ {}", file.display().to_string());
-                cache.add_media(file, &covers_dir);
-            }
-            needs_update = true;
+            skipped = add_files(
+                &mut cache_data,
+                to_add,
+                &previous,
+                &covers_dir,
+                options,
+                cancel,
+                progress,
+            )?;
         }
+
+        cache = cache_data;
     } else {
-        for file in curr_audio_files {
-            info!("+ {}", file.display().to_string());
-            cache.add_media(file, &covers_dir);
-        }
+        skipped = add_files(
+            &mut cache,
+            curr_audio_files.clone(),
+            &[],
+            &covers_dir,
+            options,
+            cancel,
+            progress,
+        )?;
+        reconciliation.added = curr_audio_files.len();
+        needs_update = true;
+    }
+    curr_audio_files.retain(|x| !skipped.contains(x));
+    // Edited files that can no longer be read are gone from the library
+    let skipped_updates = skipped.iter().filter(|x| edited.contains(x)).count();
+    reconciliation.added -= skipped.len() - skipped_updates;
+    reconciliation.updated -= skipped_updates;
+    reconciliation.removed += skipped_updates;
+    reconciliation.unchanged = curr_audio_files.len()
+        - reconciliation.added
+        - reconciliation.moved
+        - reconciliation.updated;
+    info!(
+        "reconciled: {} added, {} removed, {} moved, {} updated, {} unchanged",
+        reconciliation.added,
+        reconciliation.removed,
+        reconciliation.moved,
+        reconciliation.updated,
+        reconciliation.unchanged
+    );
+    // The remote files are only told apart by their paths
+    let local_files: Vec<PathBuf> = curr_audio_files
+        .iter()
+        .filter(|x| !remote::is_remote(x))
+        .cloned()
+        .collect();
+    fingerprints.update(&local_files);
+    progress.reconciled(reconciliation, fingerprints.digest());
+
+    if cache.aliases != aliases {
+        cache.aliases = aliases;
+        needs_update = true;
+    }
+    // Caches written before the tracks had ids
+    if cache.assign_track_ids() {
+        needs_update = true;
+    }
+    if cache.select_covers(&covers_dir) {
+        needs_update = true;
+    }
+    if cache.select_playlist_covers() {
+        needs_update = true;
+    }
+    if audiobooks::classify(&mut cache, options.audiobook_min_duration) {
+        needs_update = true;
+    }
+    if cache.summarize_albums() {
         needs_update = true;
     }
 
+    // Only written once the scan went through, a cancelled scan is picked up again next time
+    cache_audio_files(ac_path, &curr_audio_files);
+
     if needs_update {
+        cache.album_id_scheme = ALBUM_ID_SCHEME;
         info!("* cache updated");
-        let jason = serde_json::to_string(&cache).unwrap();
-        let mut f = fs::File::create(&p_string).unwrap();
-        let _ = f.write_all(jason.as_bytes());
+        save_cache(cache_dir, &cache);
     }
 
     info!("cache process ended");
 
-    cache
+    Some(cache)
+}
+
+/// Writes `media` to the metadata cache of `cache_dir`
+pub fn save_cache(cache_dir: &Path, media: &Media) {
+    let jason = serde_json::to_string(media).unwrap();
+    let mut f = fs::File::create(cache_dir.join(".cache.json")).unwrap();
+    let _ = f.write_all(jason.as_bytes());
 }
 
 pub fn compare_caches(
@@ -128,3 +391,152 @@ pub fn compare_caches(
         to_remove_len,
     )
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::http::{header::CONTENT_RANGE, StatusCode};
+    use axum::response::IntoResponse;
+
+    fn range(value: &'static str) -> Range {
+        let value = HeaderValue::from_static(value);
+        Range::decode(&mut std::iter::once(&value)).unwrap()
+    }
+
+    #[test]
+    fn byte_range_without_range() {
+        assert_eq!(byte_range(None, 1000).unwrap(), None);
+    }
+
+    #[test]
+    fn byte_range_bounded() {
+        assert_eq!(
+            byte_range(Some(&range("bytes=0-99")), 1000).unwrap(),
+            Some((0, 99))
+        );
+        // An end past the file stops at its last byte
+        assert_eq!(
+            byte_range(Some(&range("bytes=900-4000")), 1000).unwrap(),
+            Some((900, 999))
+        );
+    }
+
+    #[test]
+    fn byte_range_open_ended() {
+        assert_eq!(
+            byte_range(Some(&range("bytes=500-")), 1000).unwrap(),
+            Some((500, 999))
+        );
+        assert_eq!(
+            byte_range(Some(&range("bytes=999-")), 1000).unwrap(),
+            Some((999, 999))
+        );
+    }
+
+    #[test]
+    fn byte_range_suffix() {
+        assert_eq!(
+            byte_range(Some(&range("bytes=-100")), 1000).unwrap(),
+            Some((900, 999))
+        );
+        assert_eq!(
+            byte_range(Some(&range("bytes=-5000")), 1000).unwrap(),
+            Some((0, 999))
+        );
+    }
+
+    #[test]
+    fn byte_range_unsatisfiable() {
+        for value in ["bytes=1000-", "bytes=4000-5000", "bytes=-0", "bytes=5-3"] {
+            let response = byte_range(Some(&range(value)), 1000)
+                .unwrap_err()
+                .into_response();
+            assert_eq!(
+                response.status(),
+                StatusCode::RANGE_NOT_SATISFIABLE,
+                "{value}"
+            );
+            assert_eq!(response.headers()[CONTENT_RANGE], "bytes */1000", "{value}");
+        }
+        // Nothing can be served from an empty file
+        assert!(byte_range(Some(&range("bytes=0-")), 0).is_err());
+        assert!(byte_range(Some(&range("bytes=-10")), 0).is_err());
+    }
+
+    #[test]
+    fn byte_range_multiple() {
+        // Only the first range is served
+        assert_eq!(
+            byte_range(Some(&range("bytes=0-9, 20-29")), 1000).unwrap(),
+            Some((0, 9))
+        );
+        // Skipping the ones out of the file
+        assert_eq!(
+            byte_range(Some(&range("bytes=2000-2100,-10")), 1000).unwrap(),
+            Some((990, 999))
+        );
+        assert!(byte_range(Some(&range("bytes=2000-2100,3000-")), 1000).is_err());
+    }
+
+    /// A mono 16 bits wav file of `samples` samples, all of `value`
+    fn wav(samples: u32, value: u8) -> Vec<u8> {
+        let size = samples * 2;
+        let mut data = vec![];
+        data.extend(b"RIFF");
+        data.extend((36 + size).to_le_bytes());
+        data.extend(b"WAVEfmt ");
+        data.extend(16u32.to_le_bytes());
+        data.extend(1u16.to_le_bytes()); // PCM
+        data.extend(1u16.to_le_bytes()); // Mono
+        data.extend(8000u32.to_le_bytes());
+        data.extend(16000u32.to_le_bytes());
+        data.extend(2u16.to_le_bytes());
+        data.extend(16u16.to_le_bytes());
+        data.extend(b"data");
+        data.extend(size.to_le_bytes());
+        data.extend(std::iter::repeat_n(value, size as usize));
+        data
+    }
+
+    /// Scans `music` into `cache`, returning how the files were reconciled
+    async fn scan(music: &Path, cache: &PathBuf, full: bool) -> Reconciliation {
+        let options = ScanOptions {
+            full,
+            ..ScanOptions::from_config(&lorconf::Config::default())
+        };
+        let state = crate::daemon::scan::ScanState::default();
+        let scan = state.begin();
+        let media = cache_resolve(
+            cache,
+            &[music.to_path_buf()],
+            &options,
+            &CancellationToken::new(),
+            &scan.progress,
+        )
+        .await;
+        assert!(media.is_some());
+        scan.complete();
+        state.last().unwrap().reconciliation.unwrap()
+    }
+
+    #[tokio::test]
+    async fn full_scan_after_a_move() {
+        let root = std::env::temp_dir().join(format!("lorchestre-{}", uuid::Uuid::new_v4()));
+        let (music, cache) = (root.join("music"), root.join("cache"));
+        std::fs::create_dir_all(&music).unwrap();
+        std::fs::write(music.join("a.wav"), wav(8000, 1)).unwrap();
+        std::fs::write(music.join("b.wav"), wav(8000, 2)).unwrap();
+        assert_eq!(scan(&music, &cache, false).await.added, 2);
+
+        std::fs::rename(music.join("a.wav"), music.join("c.wav")).unwrap();
+        let reconciliation = scan(&music, &cache, true).await;
+        // The moved file isn't read again like the one left in place
+        assert_eq!(reconciliation.moved, 1);
+        assert_eq!(reconciliation.updated, 1);
+        assert_eq!(reconciliation.added, 0);
+        assert_eq!(reconciliation.removed, 0);
+        assert_eq!(reconciliation.unchanged, 0);
+
+        std::fs::remove_dir_all(root).unwrap();
+    }
+}
@@ -0,0 +1,234 @@
+//! Walk of the library folders. Hidden files and folders are skipped, e.g. `.stversions`, as
+//! are the ones matched by a glob of `library.exclude` or of a `.muignore` file. A `.muignore`
+//! holds one glob per line and applies to its folder and below: a glob without `/` matches
+//! names at any depth, one with a `/` matches paths from the folder, and a trailing `/` only
+//! matches folders. Folders are read in parallel.
+//!
+//! A file reached through several paths, by symlinks or hard links, is listed once. The other
+//! paths are its aliases.
+
+use glob::{MatchOptions, Pattern};
+use rayon::prelude::*;
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use tracing::warn;
+
+pub const IGNORE_FILE: &str = ".muignore";
+
+/// Other paths of the files listed, keyed by the path listed
+pub type Aliases = HashMap<PathBuf, Vec<PathBuf>>;
+
+const MATCH: MatchOptions = MatchOptions {
+    case_sensitive: true,
+    require_literal_separator: true,
+    require_literal_leading_dot: false,
+};
+
+/// Which files and folders are left out of a walk
+#[derive(Debug, Clone)]
+pub struct WalkOptions {
+    /// Globs applying to each of the folders walked, like a `.muignore` file in them
+    pub exclude: Vec<String>,
+    /// Whether symlinked folders are walked. A folder reached twice, through a link to a
+    /// folder of the walk or to one already walked, is walked once.
+    pub follow_symlinks: bool,
+}
+
+#[derive(Debug, Clone)]
+struct Rule {
+    pattern: Pattern,
+    /// Folder the rule applies from
+    base: PathBuf,
+    /// Matched against the path from `base` rather than against the name
+    anchored: bool,
+    dir_only: bool,
+}
+
+impl Rule {
+    fn parse(line: &str, base: &Path) -> Option<Self> {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            return None;
+        }
+        let (line, dir_only) = match line.strip_suffix('/') {
+            Some(line) => (line, true),
+            None => (line, false),
+        };
+        let anchored = line.contains('/');
+        let line = line.trim_start_matches('/');
+        match Pattern::new(line) {
+            Ok(pattern) => Some(Self {
+                pattern,
+                base: base.to_path_buf(),
+                anchored,
+                dir_only,
+            }),
+            Err(e) => {
+                warn!("walk: invalid glob `{line}` for `{}`: {e}", base.display());
+                None
+            }
+        }
+    }
+
+    fn matches(&self, path: &Path, is_dir: bool) -> bool {
+        if self.dir_only && !is_dir {
+            return false;
+        }
+        if self.anchored {
+            path.strip_prefix(&self.base)
+                .is_ok_and(|x| self.pattern.matches_path_with(x, MATCH))
+        } else {
+            path.file_name()
+                .is_some_and(|x| self.pattern.matches_with(&x.to_string_lossy(), MATCH))
+        }
+    }
+}
+
+/// State shared by the folders of a walk
+struct Walk<'a> {
+    options: &'a WalkOptions,
+    /// Canonical paths of the folders of the walk
+    roots: Vec<PathBuf>,
+    /// Canonical paths of the symlinked folders walked
+    linked: Mutex<HashSet<PathBuf>>,
+}
+
+impl Walk<'_> {
+    /// Whether the symlinked folder `path` is walked, once per target outside of the roots
+    fn follows(&self, path: &Path) -> bool {
+        if !self.options.follow_symlinks {
+            return false;
+        }
+        let Ok(target) = path.canonicalize() else {
+            return false;
+        };
+        !self.roots.iter().any(|x| target.starts_with(x))
+            && self.linked.lock().unwrap().insert(target)
+    }
+
+    /// Files under `dir`, with the `rules` of the folders above it
+    fn dir(&self, dir: &Path, rules: &[Rule]) -> Vec<PathBuf> {
+        let mut rules = rules.to_vec();
+        let ignore = dir.join(IGNORE_FILE);
+        if ignore.is_file() {
+            match std::fs::read_to_string(&ignore) {
+                Ok(text) => rules.extend(text.lines().filter_map(|x| Rule::parse(x, dir))),
+                Err(e) => warn!("walk: unable to read `{}`: {e}", ignore.display()),
+            }
+        }
+
+        let entries = match std::fs::read_dir(dir) {
+            Ok(entries) => entries,
+            Err(e) => {
+                warn!("walk: unable to read `{}`: {e}", dir.display());
+                return vec![];
+            }
+        };
+        let (mut files, mut dirs) = (vec![], vec![]);
+        for entry in entries.flatten() {
+            if entry.file_name().to_string_lossy().starts_with('.') {
+                continue;
+            }
+            let path = entry.path();
+            let Ok(file_type) = entry.file_type() else {
+                continue;
+            };
+            // Links are followed to what they point to, broken ones are skipped
+            let is_dir = match file_type.is_symlink() {
+                true => match std::fs::metadata(&path) {
+                    Ok(metadata) => metadata.is_dir(),
+                    Err(_) => continue,
+                },
+                false => file_type.is_dir(),
+            };
+            if rules.iter().any(|x| x.matches(&path, is_dir)) {
+                continue;
+            }
+
+            if !is_dir {
+                files.push(path);
+            } else if !file_type.is_symlink() || self.follows(&path) {
+                dirs.push(path);
+            }
+        }
+
+        let nested: Vec<Vec<PathBuf>> = dirs.par_iter().map(|x| self.dir(x, &rules)).collect();
+        files.extend(nested.into_iter().flatten());
+        files
+    }
+}
+
+/// What tells a file apart however it is reached: its device and inode
+#[cfg(unix)]
+type Identity = (u64, u64);
+/// What tells a file apart however it is reached: its canonical path, there are no inodes
+#[cfg(not(unix))]
+type Identity = PathBuf;
+
+#[cfg(unix)]
+fn identity(path: &Path) -> Option<Identity> {
+    use std::os::unix::fs::MetadataExt;
+    let metadata = std::fs::metadata(path).ok()?;
+    Some((metadata.dev(), metadata.ino()))
+}
+
+#[cfg(not(unix))]
+fn identity(path: &Path) -> Option<Identity> {
+    path.canonicalize().ok()
+}
+
+/// `files` once per file with the aliases of the ones reached through several paths. The path
+/// kept is the first that goes through no symlink, or the first one.
+pub fn dedupe(files: Vec<PathBuf>) -> (Vec<PathBuf>, Aliases) {
+    let mut groups: HashMap<Identity, Vec<PathBuf>> = HashMap::new();
+    let mut kept = vec![];
+    for file in files {
+        match identity(&file) {
+            Some(identity) => groups.entry(identity).or_default().push(file),
+            None => kept.push(file),
+        }
+    }
+
+    let mut aliases = Aliases::new();
+    for (_, mut paths) in groups {
+        if paths.len() > 1 {
+            paths.sort_by_cached_key(|x| (x.canonicalize().ok().as_ref() != Some(x), x.clone()));
+        }
+        let mut paths = paths.into_iter();
+        let Some(first) = paths.next() else {
+            continue;
+        };
+        let others: Vec<PathBuf> = paths.collect();
+        if !others.is_empty() {
+            aliases.insert(first.clone(), others);
+        }
+        kept.push(first);
+    }
+    kept.sort();
+    (kept, aliases)
+}
+
+/// Files under `roots`, sorted
+pub fn files(roots: &[PathBuf], options: &WalkOptions) -> Vec<PathBuf> {
+    let walk = Walk {
+        options,
+        roots: roots.iter().filter_map(|x| x.canonicalize().ok()).collect(),
+        linked: Mutex::new(HashSet::new()),
+    };
+
+    let mut files: Vec<PathBuf> = roots
+        .par_iter()
+        .flat_map_iter(|root| {
+            let rules: Vec<Rule> = options
+                .exclude
+                .iter()
+                .filter_map(|x| Rule::parse(x, root))
+                .collect();
+            walk.dir(root, &rules)
+        })
+        .collect();
+    files.sort();
+    files.dedup();
+    files
+}
@@ -0,0 +1,78 @@
+//! Web client served at `/app` from the folder of `network.web_ui`, e.g. the build of the
+//! desktop frontend, so that a browser needs nothing but the daemon. Routes of the client
+//! without a file of their own get its `index.html`, for it to route them.
+
+use axum::http::header::{CACHE_CONTROL, CONTENT_TYPE};
+use axum::http::{HeaderValue, StatusCode};
+use axum::response::{IntoResponse, Response};
+use std::path::{Component, Path, PathBuf};
+use tracing::warn;
+
+const INDEX: &str = "index.html";
+
+/// `network.web_ui`, `None` when no web client is served
+pub fn root(config: &lorconf::Config) -> Option<PathBuf> {
+    config.network.as_ref()?.web_ui.clone()
+}
+
+/// `path` under `root`, `None` when it would leave it
+fn resolve(root: &Path, path: &str) -> Option<PathBuf> {
+    let relative = Path::new(path.trim_start_matches('/'));
+    relative
+        .components()
+        .all(|x| matches!(x, Component::Normal(_)))
+        .then(|| root.join(relative))
+}
+
+fn not_found() -> Response {
+    let mut response = "no such file".into_response();
+    *response.status_mut() = StatusCode::NOT_FOUND;
+    response
+}
+
+/// The file `path` of the web client in `root`, or its `index.html` for a path without an
+/// extension, which is one of its routes
+pub async fn serve(root: &Path, path: &str) -> Response {
+    let Some(mut file) = resolve(root, path) else {
+        return not_found();
+    };
+    if file.is_dir() {
+        file = file.join(INDEX);
+    }
+    if !file.is_file() {
+        // A missing asset isn't a page
+        if Path::new(path).extension().is_some() {
+            return not_found();
+        }
+        file = root.join(INDEX);
+    }
+
+    let data = match tokio::fs::read(&file).await {
+        Ok(data) => data,
+        Err(e) => {
+            warn!("webapp: unable to read `{}`: {e}", file.display());
+            return not_found();
+        }
+    };
+    let mime = mime_guess::from_path(&file).first_or_octet_stream();
+    // Bundlers put the files named after their content under `immutable`
+    let cache = if mime.subtype() == mime_guess::mime::HTML {
+        "no-cache"
+    } else if Path::new(path)
+        .components()
+        .any(|x| x.as_os_str() == "immutable")
+    {
+        "public, max-age=31536000, immutable"
+    } else {
+        "public, max-age=3600"
+    };
+
+    let mut response = data.into_response();
+    if let Ok(value) = HeaderValue::from_str(mime.as_ref()) {
+        response.headers_mut().insert(CONTENT_TYPE, value);
+    }
+    response
+        .headers_mut()
+        .insert(CACHE_CONTROL, HeaderValue::from_static(cache));
+    response
+}
@@ -0,0 +1,135 @@
+//! Webhooks, the `[[webhooks]]` of the configuration: each event emitted to the sockets is
+//! also posted as JSON to the URLs whose filters it passes, for the automations that don't
+//! speak socket.io, e.g. Home Assistant. The posts are made in the background and not retried,
+//! the `sequence` of an event tells a receiver which ones it missed.
+
+use chrono::{SecondsFormat, Utc};
+use hmac::{Hmac, Mac};
+use mu_protocol::events::Event;
+use sha2::Sha256;
+use std::sync::{OnceLock, RwLock};
+use std::time::Duration;
+use tokio::runtime::Handle;
+use tracing::warn;
+
+/// Time given to a receiver to answer
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+/// The webhooks of the running configuration
+static WEBHOOKS: RwLock<Vec<lorconf::Webhook>> = RwLock::new(Vec::new());
+/// The runtime posting, events are also emitted from the blocking threads
+static RUNTIME: OnceLock<(Handle, reqwest::Client)> = OnceLock::new();
+
+/// Body of a post
+#[derive(serde::Serialize)]
+struct Call<'a, T> {
+    event: &'static str,
+    data: &'a T,
+    sequence: u64,
+    generation: u64,
+    /// Room the event was emitted to, e.g. `user:<name>`, every socket got it otherwise
+    room: Option<&'a str>,
+    /// RFC 3339 time of the post
+    at: String,
+}
+
+/// Starts posting the events, called once on the runtime of the daemon
+pub fn init(config: &lorconf::Config) {
+    let client = reqwest::Client::builder()
+        .timeout(TIMEOUT)
+        .build()
+        .unwrap_or_default();
+    let _ = RUNTIME.set((Handle::current(), client));
+    configure(config);
+}
+
+/// Takes the webhooks of `config`, once reloaded
+pub fn configure(config: &lorconf::Config) {
+    *WEBHOOKS.write().unwrap() = config.webhooks.clone().unwrap_or_default();
+}
+
+/// Whether `name` passes the filters of `webhook`
+fn wanted(webhook: &lorconf::Webhook, name: &str) -> bool {
+    let Some(events) = &webhook.events else {
+        return true;
+    };
+    events.iter().any(|x| match x.strip_suffix('*') {
+        Some(prefix) => name.starts_with(prefix),
+        None => x == name,
+    })
+}
+
+/// Posts `event` to the webhooks wanting it
+pub fn post<T: serde::Serialize>(
+    event: Event,
+    room: Option<&str>,
+    data: &T,
+    generation: u64,
+    sequence: u64,
+) {
+    let Some((runtime, client)) = RUNTIME.get() else {
+        return;
+    };
+    let webhooks: Vec<lorconf::Webhook> = WEBHOOKS
+        .read()
+        .unwrap()
+        .iter()
+        .filter(|x| wanted(x, event.name()))
+        .cloned()
+        .collect();
+    if webhooks.is_empty() {
+        return;
+    }
+
+    let call = Call {
+        event: event.name(),
+        data,
+        sequence,
+        generation,
+        room,
+        at: Utc::now().to_rfc3339_opts(SecondsFormat::Secs, true),
+    };
+    let body = match serde_json::to_vec(&call) {
+        Ok(body) => body,
+        Err(e) => {
+            warn!("webhooks: unable to serialize {}: {e}", event.name());
+            return;
+        }
+    };
+
+    for webhook in webhooks {
+        let mut request = client
+            .post(&webhook.url)
+            .header("content-type", "application/json")
+            .header("x-mu-event", event.name());
+        if let Some(secret) = &webhook.secret {
+            let mut mac = Hmac::<Sha256>::new_from_slice(secret.as_bytes())
+                .expect("HMAC takes keys of any size");
+            mac.update(&body);
+            let signature: String = mac
+                .finalize()
+                .into_bytes()
+                .iter()
+                .map(|x| format!("{x:02x}"))
+                .collect();
+            request = request.header("x-mu-signature", format!("sha256={signature}"));
+        }
+        let request = request.body(body.clone());
+        runtime.spawn(async move {
+            match request.send().await {
+                Ok(response) if !response.status().is_success() => warn!(
+                    "webhooks: `{}` answered {} to {}",
+                    webhook.url,
+                    response.status(),
+                    event.name()
+                ),
+                Ok(_) => {}
+                Err(e) => warn!(
+                    "webhooks: unable to post {} to `{}`: {e}",
+                    event.name(),
+                    webhook.url
+                ),
+            }
+        });
+    }
+}
@@ -14,6 +14,8 @@ use tracing_subscriber::FmtSubscriber;
 const VERSION: &str = env!("CARGO_PKG_VERSION");
 #[cfg(target_os = "linux")]
 const LINUX_SYSTEMD: &str = include_str!("./systemd/lorchestre.service");
+#[cfg(target_os = "linux")]
+const LINUX_SYSTEMD_SOCKET: &str = include_str!("./systemd/lorchestre.socket");
 #[cfg(target_os = "macos")]
 const MACOS_LAUNCHD: &str = include_str!("./launchd/dev.luxluth.lorchestre.plist");
 
@@ -37,6 +39,15 @@ fn init_service(bin_path: String) -> std::io::Result<()> {
         service_path.display()
     );
 
+    // Installed but not enabled, socket activation is opt-in
+    let socket_path = service_dir.join("lorchestre.socket");
+    let mut file = std::fs::File::create(&socket_path)?;
+    let address = daemon_(dirs::config_dir().unwrap().join("lorchestre/config.toml"));
+    // systemd only listens on addresses, not host names
+    let address = address.replacen("localhost:", "127.0.0.1:", 1);
+    let socket_content = LINUX_SYSTEMD_SOCKET.replace("{{ADDRESS}}", &address);
+    file.write_all(socket_content.as_bytes())?;
+
     // Reload systemd and start the service
     std::process::Command::new("systemctl")
         .arg("--user")
@@ -50,6 +61,8 @@ fn init_service(bin_path: String) -> std::io::Result<()> {
     std::process::Command::new("systemctl")
         .arg("--user")
         .arg("start")
+        // The service is only started once the initial scan is over
+        .arg("--no-block")
         .arg("lorchestre.service")
         .output()?;
     info!("Systemd service enabled and started.");
@@ -136,12 +149,29 @@ fn daemon_(path: std::path::PathBuf) -> String {
     format!("{host}:{port}")
 }
 
+/// `https://host:port` when the daemon serves TLS, `http://host:port` otherwise
+fn daemon_url_(path: std::path::PathBuf) -> String {
+    let tls = Config::get(&path)
+        .network
+        .and_then(|net| net.tls)
+        .is_some_and(|tls| tls.cert.is_some() && tls.key.is_some());
+    let scheme = if tls { "https" } else { "http" };
+
+    format!("{scheme}://{}", daemon_(path))
+}
+
 #[tauri::command]
 fn daemon_endpoint(app: tauri::AppHandle) -> String {
     let path = app.path().app_config_dir().unwrap().join("config.toml");
     daemon_(path)
 }
 
+#[tauri::command]
+fn daemon_url(app: tauri::AppHandle) -> String {
+    let path = app.path().app_config_dir().unwrap().join("config.toml");
+    daemon_url_(path)
+}
+
 #[tauri::command]
 fn version() -> String {
     VERSION.to_string()
@@ -150,7 +180,7 @@ fn version() -> String {
 #[tauri::command]
 async fn sync_music(app: tauri::AppHandle, window: tauri::Window) {
     let path = app.path().app_config_dir().unwrap().join("config.toml");
-    let endpoint = format!("http://{}/updatemusic", daemon_(path));
+    let endpoint = format!("{}/updatemusic", daemon_url_(path));
     let _ = window.emit("startsync", "");
     let client = reqwest::Client::new();
     let _ = client.put(endpoint).send().await;
@@ -247,8 +277,17 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     let args_vec: Vec<String> = std::env::args().collect();
     let args = args::LorArgs::parse();
     info!("args: {:?}", args_vec);
-    if args.entity.is_some() {
-        start().await?;
+    if let Some(entity) = args.entity {
+        match entity {
+            args::LorSubcommand::Daemon => start().await?,
+            args::LorSubcommand::Import {
+                source,
+                path,
+                remap,
+                user,
+                playlists,
+            } => daemon::import::run(source, path, remap, user, playlists).await?,
+        }
         Ok(())
     } else {
         tauri::Builder::default()
@@ -265,6 +304,7 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
                 config,
                 default_config,
                 daemon_endpoint,
+                daemon_url,
                 sync_music,
                 version,
                 app_info,
@@ -27,11 +27,19 @@ export default class AppConfig {
 	}
 
 	getDaemonEndpoint() {
-		return (
-			`${this.config.network?.host ?? this.defaults.network.host}` +
-			':' +
-			`${this.config.network?.port ?? this.defaults.network.port}`
-		);
+		const host = this.config.network?.host ?? this.defaults.network.host;
+		// IPv6 literals are bracketed in urls
+		const bracketed = host.includes(':') && !host.startsWith('[') ? `[${host}]` : host;
+		return bracketed + ':' + `${this.config.network?.port ?? this.defaults.network.port}`;
+	}
+
+	/**
+	 * Base url of the daemon, `https`/`wss` when it serves TLS
+	 */
+	getDaemonUrl(protocol: 'http' | 'ws' = 'http') {
+		const tls = this.config.network?.tls;
+		const secure = tls?.cert && tls?.key ? 's' : '';
+		return `${protocol}${secure}://${this.getDaemonEndpoint()}`;
 	}
 }
 
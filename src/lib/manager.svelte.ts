@@ -111,7 +111,7 @@ export default class Manager {
 						artists: t.artists,
 						track: t.track,
 						album: t.album,
-						album_artist: t.album_artist,
+						album_artists: t.album_artists,
 						album_id: t.album_id,
 						album_year: t.album_year,
 						lyrics: t.lyrics,
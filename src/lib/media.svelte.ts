@@ -1,5 +1,5 @@
-import type { Album, Media, Playlist, Track } from './type';
-import { io } from 'socket.io-client';
+import type { Album, ColoredTracks, Media, Playlist, ResumeReply, Track } from './type';
+import { io, type Socket } from 'socket.io-client';
 import { getContext, setContext } from 'svelte';
 import { listen } from '@tauri-apps/api/event';
 import { recordToMap } from './utils';
@@ -25,11 +25,12 @@ export default class MediaState {
 
 	async load() {
 		let config = getAppConfig();
-		const endpoint = config.getDaemonEndpoint();
+		const url = config.getDaemonUrl();
+		const socketUrl = config.getDaemonUrl('ws');
 
 		let response = null;
 		try {
-			response = await fetch(`http://${endpoint}/media`);
+			response = await fetch(`${url}/media`);
 		} catch (e) {}
 		if (response) {
 			console.log('First response');
@@ -48,15 +49,11 @@ export default class MediaState {
 			});
 
 			try {
-				const socket = io(`ws://${endpoint}`);
+				const socket = io(socketUrl);
 				if (!this.search.initialized) {
 					this.search.init(socket);
 				}
-				socket.on('newmedia', (media: Media) => {
-					this.albums = media.albums;
-					this.playlists = media.playlists;
-					this.tracks = recordToMap(media.tracks);
-				});
+				this.watch(socket);
 			} catch (e) {
 				console.warn(e);
 			}
@@ -64,7 +61,7 @@ export default class MediaState {
 			this.loadIntervalPingId = window.setInterval(() => {
 				(async () => {
 					try {
-						let response = await fetch(`http://${endpoint}/media`);
+						let response = await fetch(`${url}/media`);
 						if (response.status === 200) {
 							let media = (await response.json()) as Media;
 							this.albums = media.albums;
@@ -81,15 +78,11 @@ export default class MediaState {
 							});
 
 							try {
-								const socket = io(`ws://${endpoint}`);
+								const socket = io(socketUrl);
 								if (!this.search.initialized) {
 									this.search.init(socket);
 								}
-								socket.on('newmedia', (media: Media) => {
-									this.albums = media.albums;
-									this.playlists = media.playlists;
-									this.tracks = recordToMap(media.tracks);
-								});
+								this.watch(socket);
 							} catch (e) {
 								console.warn(e);
 							}
@@ -102,6 +95,103 @@ export default class MediaState {
 		}
 	}
 
+	/** Fetches the whole media again, when the events missed while disconnected are gone */
+	async reload() {
+		const url = getAppConfig().getDaemonUrl();
+		try {
+			const response = await fetch(`${url}/media`);
+			if (response.status === 200) {
+				const media = (await response.json()) as Media;
+				this.albums = media.albums;
+				this.playlists = media.playlists;
+				this.tracks = recordToMap(media.tracks);
+			}
+		} catch (e) {
+			console.warn(e);
+		}
+	}
+
+	watch(socket: Socket) {
+		// Events carry their sequence as third argument, a reconnected socket asks for the
+		// ones it missed from the last one it got
+		let sequence: number | null = null;
+		socket.onAny((_event: string, _data: unknown, _generation: number, received?: number) => {
+			if (typeof received === 'number') {
+				sequence = received;
+			}
+		});
+		socket.on('connect', () => {
+			socket.emit('resume', { sequence }, (reply: ResumeReply) => {
+				sequence = reply.sequence;
+				if (reply.resync) {
+					this.reload();
+				}
+			});
+		});
+
+		// The library is sent item by item as it changes, e.g. after a scan
+		socket.on('track:added', (tracks: Track[]) => this.putTracks(tracks));
+		socket.on('track:updated', (tracks: Track[]) => this.putTracks(tracks));
+		socket.on('track:removed', (ids: string[]) => {
+			const removed = new Set(ids);
+			const tracks = new Map(this.tracks);
+			for (const [path, track] of this.tracks) {
+				if (removed.has(track.id)) {
+					tracks.delete(path);
+				}
+			}
+			this.tracks = tracks;
+		});
+		socket.on('album:added', (albums: Album[]) => {
+			this.albums = upsert(this.albums, albums);
+		});
+		socket.on('album:updated', (albums: Album[]) => {
+			this.albums = upsert(this.albums, albums);
+		});
+		socket.on('album:removed', (ids: string[]) => {
+			this.albums = this.albums.filter((album) => !ids.includes(album.id));
+		});
+		socket.on('playlist:added', (playlists: Playlist[]) => {
+			this.playlists = upsert(this.playlists, playlists);
+		});
+		socket.on('playlist:updated', (playlists: Playlist[]) => {
+			this.playlists = upsert(this.playlists, playlists);
+		});
+		socket.on('playlist:removed', (ids: string[]) => {
+			this.playlists = this.playlists.filter((playlist) => !ids.includes(playlist.id));
+		});
+
+		// Palettes are computed after the scan
+		socket.on('track:colored', ({ ids, album_id, palette }: ColoredTracks) => {
+			const colored = new Set(ids);
+			for (const track of this.tracks.values()) {
+				if (colored.has(track.id)) {
+					track.palette = palette;
+					track.color = palette.primary;
+				}
+			}
+			const album = this.getAlbum(album_id);
+			if (album && !album.palette) {
+				album.palette = palette;
+			}
+		});
+	}
+
+	/** Adds `changed` to the tracks, in place of the tracks of the same id whose file moved */
+	putTracks(changed: Track[]) {
+		const ids = new Set(changed.map((track) => track.id));
+		const tracks = new Map(this.tracks);
+		for (const [path, track] of this.tracks) {
+			if (ids.has(track.id)) {
+				tracks.delete(path);
+			}
+		}
+		for (const track of changed) {
+			tracks.set(track.file_path, track);
+		}
+		this.tracks = tracks;
+	}
+
 	getSongsCount() {
 		let count = 0;
 		this.albums.forEach((album) => {
@@ -131,6 +221,15 @@ export default class MediaState {
 	}
 }
 
+/** `items` with `changed` in place of the items of the same id, the new ones last */
+function upsert<T extends { id: string }>(items: T[], changed: T[]): T[] {
+	const byId = new Map(changed.map((item) => [item.id, item]));
+	const known = new Set(items.map((item) => item.id));
+	return items
+		.map((item) => byId.get(item.id) ?? item)
+		.concat(changed.filter((item) => !known.has(item.id)));
+}
+
 export const MEDIA_SYMBOL = Symbol('MEDIA');
 
 export function setMedia(s: SearchSupervisor) {
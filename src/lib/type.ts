@@ -16,9 +16,15 @@ export enum ToastKind {
 	Close
 }
 
+export type LyricWord = {
+	t: number;
+	text: string;
+};
+
 export type LyricLine = {
 	start_time: number;
 	text: string;
+	words: LyricWord[];
 };
 
 type u8 = number;
@@ -37,12 +43,49 @@ export type Cover = {
 type u32 = number;
 type u64 = number;
 
+export type Credit = {
+	role: string;
+	name: string;
+	instrument?: string;
+};
+
+export type Palette = {
+	primary: Color;
+	secondary: Color;
+	accent: Color;
+	text: Color;
+};
+
 export type Album = {
 	name: string;
-	artist: string;
+	artists: string[];
 	tracks: string[];
 	year?: u32;
 	id: string;
+	credits: Credit[];
+	palette?: Palette;
+	cover_url?: string;
+	media_kind: MediaKind;
+	quality: QualitySummary;
+	guessed: boolean;
+	explicit: boolean;
+};
+
+export type Quality = 'lossless' | 'hires' | 'lossy';
+
+export type QualitySummary = {
+	lossless: number;
+	hires: number;
+	lossy: number;
+	sample_rate?: u32;
+	bit_depth?: number;
+};
+
+export type MediaKind = 'music' | 'audiobook';
+
+export type SavedPosition = {
+	position: number;
+	updated_at: SystemTime;
 };
 
 export type SystemTime = {
@@ -50,24 +93,63 @@ export type SystemTime = {
 	secs_since_epoch: number;
 };
 
+export type Gapless = {
+	encoder_delay: u32;
+	padding: u32;
+	sample_count?: number;
+	sample_rate: u32;
+};
+
 export type Track = {
 	title: string;
+	title_inferred: boolean;
 	artists: string[];
 	track: u32;
+	track_inferred: boolean;
 	album: string;
-	album_artist?: string;
+	album_guessed: boolean;
+	album_artists: string[];
 	album_id: string;
 	album_year?: u32;
+	release_date?: string;
+	original_date?: string;
+	composers: string[];
+	conductor?: string;
+	work?: string;
+	movement?: string;
+	movement_number?: u32;
+	credits: Credit[];
+	explicit?: boolean;
 	lyrics: LyricLine[];
 	cover_ext: string;
 	mime: string;
 	color?: Color;
 	created_at: SystemTime;
 	is_light?: boolean;
+	palette?: Palette;
 	file_path: string;
 	path_base64: string;
+	id: string;
 	duration: u64;
 	bitrate: u32;
+	is_lossless: boolean;
+	sample_rate?: u32;
+	bit_depth?: number;
+	gapless?: Gapless;
+	bpm?: number;
+	key?: string;
+	trim_start_ms?: number;
+	trim_end_ms?: number;
+	media_kind: MediaKind;
+	source?: string;
+	start?: number;
+	end?: number;
+};
+
+export type ColoredTracks = {
+	ids: string[];
+	album_id: string;
+	palette: Palette;
 };
 
 export type QueueTrack = Track & {
@@ -79,6 +161,8 @@ export type Playlist = {
 	tracks: string[];
 	path: string;
 	id: string;
+	description?: string;
+	cover_url?: string;
 };
 
 export type Media = {
@@ -87,10 +171,24 @@ export type Media = {
 	playlists: Playlist[];
 };
 
+/** Acknowledgement of the `resume` message sent once reconnected */
+export type ResumeReply = {
+	sequence: number;
+	replayed: number;
+	resync: boolean;
+};
+
 export type SearchResults = {
 	albums: Array<Album>;
 	playlists: Array<Playlist>;
 	tracks: Array<Track>;
+	/** Lines holding the phrase of a `scope=lyrics` search */
+	lyrics: Array<LyricsMatch>;
+};
+
+export type LyricsMatch = {
+	track: string;
+	lines: LyricLine[];
 };
 
 export type Line = {
@@ -145,6 +243,14 @@ export enum FilterOrder {
 export type Network = {
 	port?: u32;
 	host?: string;
+	tls?: {
+		cert?: string;
+		key?: string;
+	};
+	listen?: string[];
+	unix_socket?: string;
+	base_path?: string;
+	web_ui?: string;
 };
 
 export type Theme = 'auto' | 'dark' | 'light';
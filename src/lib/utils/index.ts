@@ -52,17 +52,16 @@ export function formatTime(seconds: number) {
 	}
 }
 export function getCoverUri(album_id: string, ext: String, config: AppConfig, size = -1) {
-	const endpoint = config.getDaemonEndpoint();
+	const url = config.getDaemonUrl();
 	if (size > 0) {
-		return `http://${endpoint}/cover/${album_id}${ext}?size=${size}x${size}`;
+		return `${url}/cover/${album_id}${ext}?size=${size}x${size}`;
 	} else {
-		return `http://${endpoint}/cover/${album_id}${ext}`;
+		return `${url}/cover/${album_id}${ext}`;
 	}
 }
 
 export function getAudioUri(path: string, config: AppConfig) {
-	const endpoint = config.getDaemonEndpoint();
-	return `http://${endpoint}/audio?path=${path}`;
+	return `${config.getDaemonUrl()}/audio/${path}`;
 }
 
 export function toQueueTrack(track: Track): QueueTrack {
@@ -6,8 +6,8 @@ export const csr = true;
 export const prerender = false;
 
 export const load: PageLoad = async ({ params, fetch }) => {
-	let endpoint = await invoke('daemon_endpoint');
-	let req = await fetch(`http://${endpoint}/album/${params.id}`);
+	let url = await invoke('daemon_url');
+	let req = await fetch(`${url}/album/${params.id}`);
 	if (req.ok) {
 		return {
 			album: (await req.json()) as Album